@@ -0,0 +1,39 @@
+//! Pipeline phase benchmarks
+//!
+//! Times parse, typecheck, evaluate and serialize separately on a small
+//! representative corpus. Use `bunsenite bench` for ad-hoc runs on your own
+//! files with baseline comparison.
+//!
+//! Run with `cargo bench --bench phases`.
+
+use bunsenite::bench::{self, Phase};
+use bunsenite::NickelLoader;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::time::Duration;
+
+const CORPUS: &[(&str, &str)] = &[
+    ("config", include_str!("../examples/config.ncl")),
+    ("records", include_str!("corpus/records.ncl")),
+    ("contracts", include_str!("corpus/contracts.ncl")),
+];
+
+fn bench_phases(c: &mut Criterion) {
+    let loader = NickelLoader::new();
+
+    for phase in Phase::ALL {
+        let mut group = c.benchmark_group(phase.name());
+        for (name, source) in CORPUS {
+            group.bench_with_input(BenchmarkId::from_parameter(name), source, |b, source| {
+                b.iter_custom(|iters| {
+                    (0..iters)
+                        .map(|_| bench::measure(&loader, phase, source, name).unwrap())
+                        .sum::<Duration>()
+                })
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_phases);
+criterion_main!(benches);
@@ -0,0 +1,59 @@
+//! Serialization benchmarks
+//!
+//! Compares the `serde_json::Value` path against arena documents with interned
+//! keys on configs made of many records sharing the same field names.
+//!
+//! Run with `cargo bench --bench serialize`.
+
+use bunsenite::{json, NickelLoader};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+/// A config with `records` service entries sharing the same keys
+fn services(records: usize) -> String {
+    format!(
+        r#"{{
+  services = std.array.generate
+    (fun i => {{
+      name = "svc-%{{std.string.from_number i}}",
+      region = "eu-west-1",
+      replicas = i,
+      enabled = true,
+      ports = [80, 443],
+    }})
+    {records},
+}}"#
+    )
+}
+
+fn bench_serialize(c: &mut Criterion) {
+    let loader = NickelLoader::new();
+    let mut group = c.benchmark_group("serialize");
+
+    for records in [100, 1_000, 10_000] {
+        let source = services(records);
+        group.throughput(Throughput::Elements(records as u64));
+
+        group.bench_with_input(BenchmarkId::new("value", records), &source, |b, source| {
+            b.iter(|| {
+                let value = loader.parse_string(source, "services.ncl").unwrap();
+                json::to_string(&value, false)
+            })
+        });
+
+        group.bench_with_input(
+            BenchmarkId::new("document", records),
+            &source,
+            |b, source| {
+                b.iter(|| {
+                    let doc = loader.parse_document(source, "services.ncl").unwrap();
+                    doc.to_json_string(false)
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_serialize);
+criterion_main!(benches);
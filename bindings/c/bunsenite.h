@@ -0,0 +1,51 @@
+/*
+ * Bunsenite C ABI
+ *
+ * Exported by the Zig FFI layer on top of the Rust core (src/ffi.rs holds
+ * the marshalling logic). All strings are NUL-terminated UTF-8. Strings
+ * returned by the library must be released with free_string(), except for
+ * the static strings returned by version() and rsr_tier().
+ */
+
+#ifndef BUNSENITE_H
+#define BUNSENITE_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Status codes returned by validate_nickel() */
+#define BUNSENITE_STATUS_OK 0
+#define BUNSENITE_STATUS_INVALID 1
+#define BUNSENITE_STATUS_INTERNAL 2
+
+/* Parse and evaluate a Nickel program; returns JSON, or NULL on failure */
+char *parse_nickel(const char *source, const char *name);
+
+/* Validate a Nickel program without evaluating it; returns a status code */
+int validate_nickel(const char *source, const char *name);
+
+/* Release a string returned by the library */
+void free_string(char *ptr);
+
+/* Library version, e.g. "0.1.0" (static, do not free) */
+const char *version(void);
+
+/* Embedded Nickel versions and supported language features as JSON:
+ * {"bunsenite": ..., "nickel_core": ..., "nickel_language": ..., "features": [...]}
+ * Release with free_string(). */
+char *version_info(void);
+
+/* RSR compliance tier (static, do not free) */
+const char *rsr_tier(void);
+
+/* TPCF perimeter */
+uint8_t tpcf_perimeter(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* BUNSENITE_H */
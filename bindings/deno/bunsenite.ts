@@ -68,6 +68,13 @@ const symbols = {
     result: "pointer",
   },
 
+  // Get embedded Nickel version information as JSON (free with free_string)
+  // char* version_info()
+  version_info: {
+    parameters: [],
+    result: "pointer",
+  },
+
   // Get RSR tier
   // const char* rsr_tier()
   rsr_tier: {
@@ -200,6 +207,36 @@ export function getVersion(): string {
   return fromCString(ptr);
 }
 
+/**
+ * Embedded Nickel version information
+ */
+export interface VersionInfo {
+  bunsenite: string;
+  nickel_core: string;
+  nickel_language: string;
+  features: string[];
+}
+
+/**
+ * Get the embedded Nickel version and supported language features
+ *
+ * @returns Version information reported by the native library
+ *
+ * @example
+ * ```typescript
+ * console.log("Nickel language:", getVersionInfo().nickel_language);
+ * ```
+ */
+export function getVersionInfo(): VersionInfo {
+  const library = getLib();
+  const ptr = library.symbols.version_info() as Deno.UnsafePointer;
+  try {
+    return JSON.parse(fromCString(ptr));
+  } finally {
+    library.symbols.free_string(ptr);
+  }
+}
+
 /**
  * Get RSR compliance tier
  *
@@ -0,0 +1,48 @@
+// Bunsenite Deno conformance runner
+//
+// Speaks the line-delimited JSON protocol used by
+// `bunsenite conformance --against deno`: one request per line on stdin
+// ({"file": ..., "source": ...}), answered in order on stdout with
+// {"ok": value} or {"error": message}.
+//
+// Usage (from the repository root, after `cargo build --release`):
+//   bunsenite conformance --against deno
+
+import { parseNickel } from "./bunsenite.ts";
+
+const decoder = new TextDecoder();
+const encoder = new TextEncoder();
+
+function writeAll(bytes: Uint8Array): void {
+  let written = 0;
+  while (written < bytes.length) {
+    written += Deno.stdout.writeSync(bytes.subarray(written));
+  }
+}
+
+function respond(line: string): void {
+  const { file, source } = JSON.parse(line);
+  let response: { ok: unknown } | { error: string };
+  try {
+    response = { ok: parseNickel(source, file) };
+  } catch (e) {
+    response = { error: e instanceof Error ? e.message : String(e) };
+  }
+  writeAll(encoder.encode(JSON.stringify(response) + "\n"));
+}
+
+let buffered = "";
+for await (const chunk of Deno.stdin.readable) {
+  buffered += decoder.decode(chunk, { stream: true });
+  let newline: number;
+  while ((newline = buffered.indexOf("\n")) >= 0) {
+    const line = buffered.slice(0, newline);
+    buffered = buffered.slice(newline + 1);
+    if (line.trim() !== "") {
+      respond(line);
+    }
+  }
+}
+if (buffered.trim() !== "") {
+  respond(buffered);
+}
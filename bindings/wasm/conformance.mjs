@@ -0,0 +1,31 @@
+// Bunsenite WASM conformance runner (Node.js)
+//
+// Speaks the line-delimited JSON protocol used by
+// `bunsenite conformance --against wasm`: one request per line on stdin
+// ({"file": ..., "source": ...}), answered in order on stdout with
+// {"ok": value} or {"error": message}.
+//
+// Usage (from the repository root):
+//   just wasm-node
+//   bunsenite conformance --against wasm
+
+import { createInterface } from "node:readline";
+import { createRequire } from "node:module";
+
+// wasm-pack's nodejs target emits a CommonJS module
+const require = createRequire(import.meta.url);
+const { parse_nickel } = require("../../pkg-node/bunsenite.js");
+
+const lines = createInterface({ input: process.stdin, crlfDelay: Infinity });
+
+for await (const line of lines) {
+  if (line.trim() === "") continue;
+  const { file, source } = JSON.parse(line);
+  let response;
+  try {
+    response = { ok: JSON.parse(parse_nickel(source, file)) };
+  } catch (e) {
+    response = { error: e instanceof Error ? e.message : String(e) };
+  }
+  process.stdout.write(JSON.stringify(response) + "\n");
+}
@@ -0,0 +1,28 @@
+//! Records the target triple and cargo profile of the build, reported by
+//! `bunsenite info` (see `BUILD_INFO` in src/version.rs)
+
+use std::env;
+use std::path::Path;
+
+fn main() {
+    let target = env::var("TARGET").unwrap_or_else(|_| "unknown".to_string());
+    println!("cargo:rustc-env=BUNSENITE_TARGET={}", target);
+
+    // Cargo does not pass custom profile names to build scripts; OUT_DIR is
+    // <target-dir>/[<triple>/]<profile>/build/<package>-<hash>/out
+    let out_dir = env::var("OUT_DIR").unwrap_or_default();
+    let profile = Path::new(&out_dir)
+        .ancestors()
+        .nth(3)
+        .and_then(|dir| dir.file_name())
+        .and_then(|name| name.to_str())
+        .map_or_else(
+            || env::var("PROFILE").unwrap_or_else(|_| "unknown".to_string()),
+            |name| match name {
+                "debug" => "dev".to_string(),
+                name => name.to_string(),
+            },
+        );
+    println!("cargo:rustc-env=BUNSENITE_PROFILE={}", profile);
+    println!("cargo:rerun-if-changed=build.rs");
+}
@@ -0,0 +1,348 @@
+//! Config trees shipped as archives
+//!
+//! Backs `bunsenite parse bundle.zip::main.ncl`: a deployment system can ship
+//! one `.zip`, `.tar` or `.tar.zst` artifact holding a whole config tree,
+//! and name the entrypoint inside it after `::`. Imports resolve within the
+//! archive, as they would in the exploded tree.
+//!
+//! Nickel reads imports from the file system, so a [`Bundle`] unpacks the
+//! archive into a private temporary directory, removed when the bundle is
+//! dropped. Entries that are not plain files or directories, and paths that
+//! are absolute or climb out with `..`, are refused rather than unpacked.
+//! Diagnostics name files as `bundle.zip::lib/base.ncl`, not by their
+//! temporary location.
+//!
+//! Requires the `archives` feature; without it, [`Bundle::open`] fails.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::archive;
+//!
+//! assert_eq!(archive::split("bundle.zip::env/prod.ncl"), Some(("bundle.zip", "env/prod.ncl")));
+//! assert_eq!(archive::split("config.ncl"), None);
+//! ```
+
+use crate::arena::Document;
+use crate::error::{Error, Result};
+use crate::loader::NickelLoader;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+/// Separates the archive from the entrypoint in `ARCHIVE::ENTRY`
+pub const SEPARATOR: &str = "::";
+
+/// Archive formats a bundle can be read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// `.zip`
+    Zip,
+    /// `.tar`
+    Tar,
+    /// `.tar.zst` or `.tzst`
+    TarZstd,
+}
+
+impl Format {
+    /// The format a path's extension names
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+        if name.ends_with(".zip") {
+            Some(Format::Zip)
+        } else if name.ends_with(".tar") {
+            Some(Format::Tar)
+        } else if name.ends_with(".tar.zst") || name.ends_with(".tzst") {
+            Some(Format::TarZstd)
+        } else {
+            None
+        }
+    }
+}
+
+/// Split `ARCHIVE::ENTRY` into the archive path and the entry
+///
+/// Returns `None` for anything else, including `::` after a path that is
+/// not an archive.
+pub fn split(spec: &str) -> Option<(&str, &str)> {
+    let (archive, entry) = spec.rsplit_once(SEPARATOR)?;
+    Format::from_path(Path::new(archive))?;
+    Some((archive, entry))
+}
+
+/// An archive unpacked for evaluation
+#[derive(Debug)]
+pub struct Bundle {
+    archive: PathBuf,
+    entry: String,
+    root: PathBuf,
+    #[cfg(feature = "archives")]
+    _dir: tempfile::TempDir,
+}
+
+impl Bundle {
+    /// Unpack `archive` and check that it contains `entry`
+    ///
+    /// # Errors
+    ///
+    /// Returns an invalid-input error for unknown formats, unsafe entries or
+    /// a missing entrypoint, or an I/O error
+    pub fn open(archive: &Path, entry: &str) -> Result<Self> {
+        let format = Format::from_path(archive).ok_or_else(|| {
+            Error::invalid_input(format!(
+                "{} is not a .zip, .tar or .tar.zst archive",
+                archive.display()
+            ))
+        })?;
+        let bundle = Self::unpack(archive, entry, format)?;
+        if !bundle.path().is_file() {
+            return Err(Error::invalid_input(format!(
+                "{} has no entry '{}'",
+                archive.display(),
+                entry
+            )));
+        }
+        Ok(bundle)
+    }
+
+    /// Open `ARCHIVE::ENTRY`, or return `None` if `spec` is not one
+    ///
+    /// # Errors
+    ///
+    /// As [`open`](Self::open)
+    pub fn open_spec(spec: &str) -> Result<Option<Self>> {
+        match split(spec) {
+            Some((archive, entry)) => Self::open(Path::new(archive), entry).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    #[cfg(feature = "archives")]
+    fn unpack(archive: &Path, entry: &str, format: Format) -> Result<Self> {
+        let dir = tempfile::tempdir()?;
+        let file = std::fs::File::open(archive)?;
+        match format {
+            Format::Zip => unpack_zip(archive, file, dir.path())?,
+            Format::Tar => unpack_tar(archive, file, dir.path())?,
+            Format::TarZstd => unpack_tar(archive, zstd::Decoder::new(file)?, dir.path())?,
+        }
+        Ok(Self {
+            archive: archive.to_path_buf(),
+            entry: entry.to_string(),
+            root: dir.path().to_path_buf(),
+            _dir: dir,
+        })
+    }
+
+    #[cfg(not(feature = "archives"))]
+    fn unpack(archive: &Path, _entry: &str, _format: Format) -> Result<Self> {
+        Err(Error::invalid_input(format!(
+            "Cannot read {}: bunsenite was built without archive support (the `archives` feature)",
+            archive.display()
+        )))
+    }
+
+    /// The unpacked entrypoint
+    pub fn path(&self) -> PathBuf {
+        self.root.join(&self.entry)
+    }
+
+    /// The entrypoint as `ARCHIVE::ENTRY`, for messages
+    pub fn name(&self) -> String {
+        format!("{}{}{}", self.archive.display(), SEPARATOR, self.entry)
+    }
+
+    /// Evaluate the entrypoint to JSON
+    ///
+    /// # Errors
+    ///
+    /// Returns the evaluation error, with files named inside the archive
+    pub fn parse(&self, loader: &NickelLoader) -> Result<Value> {
+        let (source, name) = self.source()?;
+        loader
+            .parse_string(&source, &name)
+            .map_err(|e| self.relabel(e))
+    }
+
+    /// Evaluate the entrypoint into an arena [`Document`]
+    ///
+    /// # Errors
+    ///
+    /// Returns the evaluation error, with files named inside the archive
+    pub fn parse_document(&self, loader: &NickelLoader) -> Result<Document> {
+        let (source, name) = self.source()?;
+        loader
+            .parse_document(&source, &name)
+            .map_err(|e| self.relabel(e))
+    }
+
+    /// The entrypoint's source, with the name to evaluate it under
+    ///
+    /// The name is the full unpacked path, so that relative imports resolve
+    /// inside the bundle.
+    ///
+    /// # Errors
+    ///
+    /// Returns an I/O error if the entrypoint cannot be read
+    pub fn source(&self) -> Result<(String, String)> {
+        let path = self.path();
+        Ok((std::fs::read_to_string(&path)?, path.display().to_string()))
+    }
+
+    /// Name files in an error by their place in the archive
+    fn relabel(&self, error: Error) -> Error {
+        let unpacked = format!("{}{}", self.root.display(), std::path::MAIN_SEPARATOR);
+        let packed = format!("{}{}", self.archive.display(), SEPARATOR);
+        error.relabel(|text| text.replace(&unpacked, &packed))
+    }
+}
+
+/// Where an archive entry at `name` goes below `dir`, if it is safe to
+/// unpack
+#[cfg(feature = "archives")]
+fn destination(archive: &Path, dir: &Path, name: &Path) -> Result<PathBuf> {
+    let safe = name.components().all(|c| {
+        matches!(
+            c,
+            std::path::Component::Normal(_) | std::path::Component::CurDir
+        )
+    });
+    if !safe {
+        return Err(Error::invalid_input(format!(
+            "{} contains an unsafe path '{}'",
+            archive.display(),
+            name.display()
+        )));
+    }
+    Ok(dir.join(name))
+}
+
+#[cfg(feature = "archives")]
+fn unpack_zip(archive: &Path, file: std::fs::File, dir: &Path) -> Result<()> {
+    let invalid = |e: zip::result::ZipError| {
+        Error::invalid_input(format!("Cannot read {}: {}", archive.display(), e))
+    };
+    let mut zip = zip::ZipArchive::new(file).map_err(invalid)?;
+    for index in 0..zip.len() {
+        let mut entry = zip.by_index(index).map_err(invalid)?;
+        let target = destination(archive, dir, Path::new(entry.name()))?;
+        // Unix file type bits: only directories and regular files
+        let is_symlink = entry
+            .unix_mode()
+            .is_some_and(|mode| mode & 0o170000 == 0o120000);
+        if is_symlink {
+            return Err(Error::invalid_input(format!(
+                "{} contains a symlink '{}'",
+                archive.display(),
+                entry.name()
+            )));
+        }
+        if entry.is_dir() {
+            std::fs::create_dir_all(&target)?;
+            continue;
+        }
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::io::copy(&mut entry, &mut std::fs::File::create(&target)?)?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "archives")]
+fn unpack_tar(archive: &Path, reader: impl std::io::Read, dir: &Path) -> Result<()> {
+    let mut tar = tar::Archive::new(reader);
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.into_owned();
+        let target = destination(archive, dir, &name)?;
+        let kind = entry.header().entry_type();
+        if kind.is_dir() {
+            std::fs::create_dir_all(&target)?;
+        } else if kind.is_file() {
+            if let Some(parent) = target.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::io::copy(&mut entry, &mut std::fs::File::create(&target)?)?;
+        } else {
+            return Err(Error::invalid_input(format!(
+                "{} contains '{}', which is not a file or directory",
+                archive.display(),
+                name.display()
+            )));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_spec() {
+        assert_eq!(split("a/b.tar.zst::x.ncl"), Some(("a/b.tar.zst", "x.ncl")));
+        assert_eq!(split("B.ZIP::x.ncl"), Some(("B.ZIP", "x.ncl")));
+        assert_eq!(split("config.ncl::x"), None);
+        assert_eq!(split("bundle.zip"), None);
+    }
+
+    #[cfg(feature = "archives")]
+    #[test]
+    fn test_tar_zst_bundle_resolves_imports() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bundle.tar.zst");
+        let encoder = zstd::Encoder::new(std::fs::File::create(&path).unwrap(), 0).unwrap();
+        let mut builder = tar::Builder::new(encoder);
+        for (name, contents) in [
+            (
+                "env/prod.ncl",
+                r#"(import "../lib/base.ncl") & { env = "prod" }"#,
+            ),
+            ("lib/base.ncl", "{ replicas = 3, env | default = \"dev\" }"),
+            ("lib/broken.ncl", "{ a = 1 + \"x\" }"),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, name, contents.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+
+        let loader = NickelLoader::new();
+        let bundle = Bundle::open(&path, "env/prod.ncl").unwrap();
+        assert_eq!(
+            bundle.parse(&loader).unwrap(),
+            serde_json::json!({ "replicas": 3, "env": "prod" })
+        );
+
+        let broken = Bundle::open(&path, "lib/broken.ncl").unwrap();
+        let error = broken.parse(&loader).unwrap_err().to_string();
+        assert!(
+            error.contains("bundle.tar.zst::lib/broken.ncl"),
+            "{}",
+            error
+        );
+        assert!(Bundle::open(&path, "missing.ncl").is_err());
+    }
+
+    #[cfg(feature = "archives")]
+    #[test]
+    fn test_zip_refuses_unsafe_paths() {
+        use std::io::Write;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bundle.zip");
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&path).unwrap());
+        zip.start_file("../escape.ncl", zip::write::FileOptions::default())
+            .unwrap();
+        zip.write_all(b"{}").unwrap();
+        zip.finish().unwrap();
+
+        let error = Bundle::open(&path, "main.ncl").unwrap_err().to_string();
+        assert!(error.contains("unsafe path"), "{}", error);
+        assert!(!dir.path().join("escape.ncl").exists());
+    }
+}
@@ -0,0 +1,919 @@
+//! Arena-allocated documents with interned strings
+//!
+//! Converting an evaluated program to a `serde_json::Value` allocates one heap
+//! node per value and one `String` per key, so a large config with thousands
+//! of records sharing the same field names pays for every copy of every name.
+//! A [`Document`] instead stores all nodes in a single flat arena and every
+//! distinct string exactly once in an [`Interner`], and is built straight from
+//! the evaluated term through `serde` without an intermediate `Value` tree.
+//!
+//! Because nodes live in flat vectors, documents are also dropped and
+//! serialized without recursion, like the helpers in [`crate::json`].
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::NickelLoader;
+//!
+//! let loader = NickelLoader::new();
+//! let doc = loader
+//!     .parse_document("{ servers = [{ name = \"a\" }, { name = \"b\" }] }", "config.ncl")
+//!     .unwrap();
+//!
+//! assert_eq!(doc.to_json_string(false), r#"{"servers":[{"name":"a"},{"name":"b"}]}"#);
+//! ```
+
+use crate::error::{Error, Result};
+use crate::json::{self, Layout};
+use crate::order::FieldOrder;
+use serde::ser::{self, Serialize};
+use serde_json::{Number, Value};
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::sync::Arc;
+
+/// Handle to a string stored in an [`Interner`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+/// String interner handing out compact [`Symbol`]s
+///
+/// Each distinct string is stored once, no matter how often it is interned.
+#[derive(Debug, Clone, Default)]
+pub struct Interner {
+    lookup: HashMap<Arc<str>, Symbol>,
+    strings: Vec<Arc<str>>,
+}
+
+impl Interner {
+    /// Create an empty interner
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Intern a string, returning the existing symbol if it was seen before
+    pub fn intern(&mut self, string: &str) -> Symbol {
+        if let Some(&symbol) = self.lookup.get(string) {
+            return symbol;
+        }
+
+        let symbol =
+            Symbol(u32::try_from(self.strings.len()).expect("more than u32::MAX distinct strings"));
+        let string: Arc<str> = Arc::from(string);
+        self.strings.push(Arc::clone(&string));
+        self.lookup.insert(string, symbol);
+        symbol
+    }
+
+    /// Look up the string behind a symbol
+    ///
+    /// # Panics
+    ///
+    /// Panics if `symbol` was produced by a different interner
+    pub fn resolve(&self, symbol: Symbol) -> &str {
+        &self.strings[symbol.0 as usize]
+    }
+
+    /// Number of distinct strings stored
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    /// Whether no strings have been interned yet
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+}
+
+/// Index of a node in a [`Document`]'s arena
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct NodeId(u32);
+
+impl NodeId {
+    fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// A single value in the arena
+///
+/// Containers refer to a contiguous run of `Document::members`.
+#[derive(Debug, Clone)]
+enum Node {
+    Null,
+    Bool(bool),
+    Number(Number),
+    String(Symbol),
+    Array { start: usize, len: usize },
+    Object { start: usize, len: usize },
+}
+
+/// A JSON-compatible document stored in a flat arena
+///
+/// Nodes are appended children-first, so every container's members have
+/// smaller ids than the container itself and the root is always the last node.
+#[derive(Debug, Clone)]
+pub struct Document {
+    nodes: Vec<Node>,
+    /// Members of all containers; object members carry their key
+    members: Vec<(Option<Symbol>, NodeId)>,
+    strings: Interner,
+}
+
+impl Document {
+    /// Build a document from any serializable value
+    ///
+    /// Serialization itself follows `serde`'s recursive model, so deep inputs
+    /// should be converted on a large stack (as [`crate::NickelLoader`] does).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value cannot be represented as JSON (for
+    /// example, a map with non-string keys)
+    pub fn from_serialize<T: Serialize + ?Sized>(value: &T) -> Result<Self> {
+        let mut builder = Builder {
+            doc: Document {
+                nodes: Vec::new(),
+                members: Vec::new(),
+                strings: Interner::new(),
+            },
+            pending: Vec::new(),
+        };
+        value.serialize(&mut builder)?;
+        Ok(builder.doc)
+    }
+
+    /// Number of values (scalars and containers) in the document
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// The interner holding every distinct key and string value
+    pub fn strings(&self) -> &Interner {
+        &self.strings
+    }
+
+    /// Convert the document into a `serde_json::Value` without recursion
+    pub fn to_value(&self) -> Value {
+        let mut built: Vec<Option<Value>> = Vec::with_capacity(self.nodes.len());
+
+        for node in &self.nodes {
+            let value = match node {
+                Node::Null => Value::Null,
+                Node::Bool(b) => Value::Bool(*b),
+                Node::Number(n) => Value::Number(n.clone()),
+                Node::String(s) => Value::String(self.strings.resolve(*s).to_string()),
+                Node::Array { start, len } => Value::Array(
+                    self.members[*start..*start + *len]
+                        .iter()
+                        .map(|(_, id)| take_built(&mut built, *id))
+                        .collect(),
+                ),
+                Node::Object { start, len } => Value::Object(
+                    self.members[*start..*start + *len]
+                        .iter()
+                        .map(|(key, id)| {
+                            let key = key.expect("object members are keyed");
+                            (
+                                self.strings.resolve(key).to_string(),
+                                take_built(&mut built, *id),
+                            )
+                        })
+                        .collect(),
+                ),
+            };
+            built.push(Some(value));
+        }
+
+        built.pop().flatten().unwrap_or(Value::Null)
+    }
+
+    /// Write the document as JSON without recursing on nesting depth
+    ///
+    /// The output matches what `serde_json` would produce for the equivalent
+    /// `Value`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing to `writer` fails
+    pub fn write_json<W: Write>(&self, writer: W, pretty: bool) -> io::Result<()> {
+        self.write(writer, pretty.then(Layout::default).as_ref())
+    }
+
+    /// Write the document as pretty JSON laid out as `layout` says, without
+    /// recursing on nesting depth
+    ///
+    /// The output matches [`json::to_writer_with`] for the equivalent
+    /// `Value`, apart from member order after [`reorder`](Self::reorder).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing to `writer` fails
+    pub fn write_json_with<W: Write>(&self, mut writer: W, layout: &Layout) -> io::Result<()> {
+        self.write(&mut writer, Some(layout))?;
+        layout.finish(&mut writer)
+    }
+
+    /// Write the document, compact without a layout
+    fn write<W: Write>(&self, mut writer: W, layout: Option<&Layout>) -> io::Result<()> {
+        let root = match self.nodes.len() {
+            0 => return writer.write_all(b"null"),
+            n => NodeId(n as u32 - 1),
+        };
+
+        let mut stack: Vec<Open> = Vec::new();
+        self.open_node(&mut writer, root, layout, &mut stack)?;
+
+        loop {
+            let depth = stack.len();
+            let Some(open) = stack.last_mut() else {
+                break;
+            };
+
+            if open.next == open.end {
+                let delimiter: &[u8] = if open.object { b"}" } else { b"]" };
+                stack.pop();
+                if let Some(layout) = layout {
+                    layout.newline(&mut writer, depth - 1)?;
+                }
+                writer.write_all(delimiter)?;
+                continue;
+            }
+
+            let first = open.next == open.start;
+            let (key, id) = self.members[open.next];
+            open.next += 1;
+
+            if !first {
+                writer.write_all(b",")?;
+            }
+            if let Some(layout) = layout {
+                layout.newline(&mut writer, depth)?;
+            }
+            match (key, layout) {
+                (Some(key), Some(layout)) => {
+                    layout.write_key(&mut writer, self.strings.resolve(key))?;
+                }
+                (Some(key), None) => {
+                    serde_json::to_writer(&mut writer, self.strings.resolve(key))?;
+                    writer.write_all(b":")?;
+                }
+                (None, _) => {}
+            }
+            self.open_node(&mut writer, id, layout, &mut stack)?;
+        }
+
+        Ok(())
+    }
+
+    /// Serialize the document to a JSON string
+    pub fn to_json_string(&self, pretty: bool) -> String {
+        let mut out = Vec::new();
+        // Writing into a Vec cannot fail
+        self.write_json(&mut out, pretty)
+            .expect("writing JSON to memory failed");
+        String::from_utf8(out).expect("serde_json emits valid UTF-8")
+    }
+
+    /// Serialize the document to a pretty JSON string laid out as `layout`
+    /// says
+    pub fn to_json_string_with(&self, layout: &Layout) -> String {
+        let mut out = Vec::new();
+        // Writing into a Vec cannot fail
+        self.write_json_with(&mut out, layout)
+            .expect("writing JSON to memory failed");
+        String::from_utf8(out).expect("serde_json emits valid UTF-8")
+    }
+
+    /// Put object members in `order`, without recursion
+    ///
+    /// Members `order` has no position for keep their order, after those
+    /// it has. [`to_value`](Self::to_value) loses the order again, since
+    /// `serde_json` maps are sorted.
+    pub fn reorder(&mut self, order: &FieldOrder) {
+        if order.is_empty() || self.nodes.is_empty() {
+            return;
+        }
+
+        let mut pending = vec![(NodeId(self.nodes.len() as u32 - 1), String::new())];
+        while let Some((id, path)) = pending.pop() {
+            match self.nodes[id.index()] {
+                Node::Array { start, len } => {
+                    for (index, (_, member)) in self.members[start..start + len].iter().enumerate()
+                    {
+                        pending.push((*member, json::index_path(&path, index)));
+                    }
+                }
+                Node::Object { start, len } => {
+                    let strings = &self.strings;
+                    let members = &mut self.members[start..start + len];
+                    let key = move |key: Option<Symbol>| {
+                        strings.resolve(key.expect("object members are keyed"))
+                    };
+                    members.sort_by_key(|(name, _)| {
+                        order
+                            .rank(&path, key(*name))
+                            .map_or((1, 0), |rank| (0, rank))
+                    });
+                    for (name, member) in members.iter() {
+                        pending.push((*member, json::key_path(&path, key(*name))));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// A scalar node as JSON, or `None` for a container
+    fn scalar_json(&self, id: NodeId) -> Option<String> {
+        match &self.nodes[id.index()] {
+            Node::Array { .. } | Node::Object { .. } => None,
+            Node::Null => Some("null".to_string()),
+            Node::Bool(b) => Some(b.to_string()),
+            Node::Number(n) => Some(n.to_string()),
+            Node::String(s) => serde_json::to_string(self.strings.resolve(*s)).ok(),
+        }
+    }
+
+    /// Write a scalar, an empty or inline container, or open a container
+    fn open_node<W: Write>(
+        &self,
+        writer: &mut W,
+        id: NodeId,
+        layout: Option<&Layout>,
+        stack: &mut Vec<Open>,
+    ) -> io::Result<()> {
+        match &self.nodes[id.index()] {
+            Node::Null => writer.write_all(b"null"),
+            Node::Bool(true) => writer.write_all(b"true"),
+            Node::Bool(false) => writer.write_all(b"false"),
+            Node::Number(n) => serde_json::to_writer(writer, n).map_err(io::Error::from),
+            Node::String(s) => {
+                serde_json::to_writer(writer, self.strings.resolve(*s)).map_err(io::Error::from)
+            }
+            Node::Array { len: 0, .. } => writer.write_all(b"[]"),
+            Node::Object { len: 0, .. } => writer.write_all(b"{}"),
+            Node::Array { start, len } => {
+                let scalar = |(_, id): &(Option<Symbol>, NodeId)| self.scalar_json(*id);
+                let items = &self.members[*start..*start + *len];
+                if let Some(line) =
+                    layout.and_then(|layout| layout.inline(items.iter().map(scalar)))
+                {
+                    return writer.write_all(line.as_bytes());
+                }
+                stack.push(Open::new(*start, *len, false));
+                writer.write_all(b"[")
+            }
+            Node::Object { start, len } => {
+                stack.push(Open::new(*start, *len, true));
+                writer.write_all(b"{")
+            }
+        }
+    }
+}
+
+/// A container whose members are being written by [`Document::write_json`]
+/// or [`Document::write_json_with`]
+struct Open {
+    start: usize,
+    end: usize,
+    next: usize,
+    object: bool,
+}
+
+impl Open {
+    fn new(start: usize, len: usize, object: bool) -> Self {
+        Self {
+            start,
+            end: start + len,
+            next: start,
+            object,
+        }
+    }
+}
+
+fn take_built(built: &mut [Option<Value>], id: NodeId) -> Value {
+    built[id.index()]
+        .take()
+        .expect("each node has exactly one parent")
+}
+
+/// `serde` serializer appending values to a [`Document`]
+struct Builder {
+    doc: Document,
+    /// Members of containers that are still being serialized
+    pending: Vec<(Option<Symbol>, NodeId)>,
+}
+
+impl Builder {
+    fn push(&mut self, node: Node) -> NodeId {
+        let id = NodeId(u32::try_from(self.doc.nodes.len()).expect("more than u32::MAX nodes"));
+        self.doc.nodes.push(node);
+        id
+    }
+
+    fn string(&mut self, s: &str) -> NodeId {
+        let symbol = self.doc.strings.intern(s);
+        self.push(Node::String(symbol))
+    }
+
+    fn number(&mut self, n: Number) -> NodeId {
+        self.push(Node::Number(n))
+    }
+
+    /// Serialize a map key, accepting the same key types as `serde_json`
+    fn key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<Symbol> {
+        let key = match serde_json::to_value(key)
+            .map_err(|e| Error::serialization_error(e.to_string()))?
+        {
+            Value::String(s) => s,
+            Value::Number(n) => n.to_string(),
+            Value::Bool(b) => b.to_string(),
+            other => {
+                return Err(Error::serialization_error(format!(
+                    "map key must be a string, got {}",
+                    other
+                )))
+            }
+        };
+        Ok(self.doc.strings.intern(&key))
+    }
+
+    /// Move the members pending since `mark` into a new container
+    ///
+    /// Enum variants are wrapped in a single-member object keyed by the
+    /// variant name, as `serde_json` does.
+    fn close(&mut self, mark: usize, object: bool, variant: Option<Symbol>) -> NodeId {
+        let start = self.doc.members.len();
+        self.doc.members.extend(self.pending.drain(mark..));
+        let len = self.doc.members.len() - start;
+        let id = self.push(if object {
+            Node::Object { start, len }
+        } else {
+            Node::Array { start, len }
+        });
+        self.wrap(variant, id)
+    }
+
+    fn wrap(&mut self, variant: Option<Symbol>, id: NodeId) -> NodeId {
+        match variant {
+            None => id,
+            Some(name) => {
+                let start = self.doc.members.len();
+                self.doc.members.push((Some(name), id));
+                self.push(Node::Object { start, len: 1 })
+            }
+        }
+    }
+}
+
+impl<'b> ser::Serializer for &'b mut Builder {
+    type Ok = NodeId;
+    type Error = Error;
+    type SerializeSeq = SeqBuilder<'b>;
+    type SerializeTuple = SeqBuilder<'b>;
+    type SerializeTupleStruct = SeqBuilder<'b>;
+    type SerializeTupleVariant = SeqBuilder<'b>;
+    type SerializeMap = MapBuilder<'b>;
+    type SerializeStruct = MapBuilder<'b>;
+    type SerializeStructVariant = MapBuilder<'b>;
+
+    fn serialize_bool(self, v: bool) -> Result<NodeId> {
+        Ok(self.push(Node::Bool(v)))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<NodeId> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<NodeId> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<NodeId> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<NodeId> {
+        Ok(self.number(Number::from(v)))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<NodeId> {
+        self.serialize_u64(u64::from(v))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<NodeId> {
+        self.serialize_u64(u64::from(v))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<NodeId> {
+        self.serialize_u64(u64::from(v))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<NodeId> {
+        Ok(self.number(Number::from(v)))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<NodeId> {
+        self.serialize_f64(f64::from(v))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<NodeId> {
+        // Like serde_json, non-finite floats become null
+        Ok(match Number::from_f64(v) {
+            Some(n) => self.number(n),
+            None => self.push(Node::Null),
+        })
+    }
+
+    fn serialize_char(self, v: char) -> Result<NodeId> {
+        Ok(self.string(v.encode_utf8(&mut [0; 4])))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<NodeId> {
+        Ok(self.string(v))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<NodeId> {
+        let mark = self.pending.len();
+        for byte in v {
+            let id = self.number(Number::from(*byte));
+            self.pending.push((None, id));
+        }
+        Ok(self.close(mark, false, None))
+    }
+
+    fn serialize_none(self) -> Result<NodeId> {
+        Ok(self.push(Node::Null))
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<NodeId> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<NodeId> {
+        Ok(self.push(Node::Null))
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<NodeId> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<NodeId> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<NodeId> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<NodeId> {
+        let id = value.serialize(&mut *self)?;
+        let name = self.doc.strings.intern(variant);
+        Ok(self.wrap(Some(name), id))
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<SeqBuilder<'b>> {
+        let mark = self.pending.len();
+        Ok(SeqBuilder {
+            builder: self,
+            mark,
+            variant: None,
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SeqBuilder<'b>> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<SeqBuilder<'b>> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<SeqBuilder<'b>> {
+        let mark = self.pending.len();
+        let variant = Some(self.doc.strings.intern(variant));
+        Ok(SeqBuilder {
+            builder: self,
+            mark,
+            variant,
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<MapBuilder<'b>> {
+        let mark = self.pending.len();
+        Ok(MapBuilder {
+            builder: self,
+            mark,
+            variant: None,
+            key: None,
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<MapBuilder<'b>> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<MapBuilder<'b>> {
+        let mark = self.pending.len();
+        let variant = Some(self.doc.strings.intern(variant));
+        Ok(MapBuilder {
+            builder: self,
+            mark,
+            variant,
+            key: None,
+        })
+    }
+}
+
+/// In-progress array (or tuple) being appended to the arena
+struct SeqBuilder<'b> {
+    builder: &'b mut Builder,
+    mark: usize,
+    variant: Option<Symbol>,
+}
+
+impl SeqBuilder<'_> {
+    fn element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        let id = value.serialize(&mut *self.builder)?;
+        self.builder.pending.push((None, id));
+        Ok(())
+    }
+
+    fn finish(self) -> NodeId {
+        self.builder.close(self.mark, false, self.variant)
+    }
+}
+
+impl ser::SerializeSeq for SeqBuilder<'_> {
+    type Ok = NodeId;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<NodeId> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeTuple for SeqBuilder<'_> {
+    type Ok = NodeId;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<NodeId> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeTupleStruct for SeqBuilder<'_> {
+    type Ok = NodeId;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<NodeId> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeTupleVariant for SeqBuilder<'_> {
+    type Ok = NodeId;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<NodeId> {
+        Ok(self.finish())
+    }
+}
+
+/// In-progress object being appended to the arena
+struct MapBuilder<'b> {
+    builder: &'b mut Builder,
+    mark: usize,
+    variant: Option<Symbol>,
+    /// Key serialized by `serialize_key`, awaiting its value
+    key: Option<Symbol>,
+}
+
+impl MapBuilder<'_> {
+    fn member<T: Serialize + ?Sized>(&mut self, key: Symbol, value: &T) -> Result<()> {
+        let id = value.serialize(&mut *self.builder)?;
+        self.builder.pending.push((Some(key), id));
+        Ok(())
+    }
+
+    fn finish(self) -> NodeId {
+        self.builder.close(self.mark, true, self.variant)
+    }
+}
+
+impl ser::SerializeMap for MapBuilder<'_> {
+    type Ok = NodeId;
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+        self.key = Some(self.builder.key(key)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| Error::internal("map value serialized before its key"))?;
+        self.member(key, value)
+    }
+
+    fn end(self) -> Result<NodeId> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeStruct for MapBuilder<'_> {
+    type Ok = NodeId;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        let key = self.builder.doc.strings.intern(key);
+        self.member(key, value)
+    }
+
+    fn end(self) -> Result<NodeId> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeStructVariant for MapBuilder<'_> {
+    type Ok = NodeId;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        let key = self.builder.doc.strings.intern(key);
+        self.member(key, value)
+    }
+
+    fn end(self) -> Result<NodeId> {
+        Ok(self.finish())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_interner_deduplicates() {
+        let mut interner = Interner::new();
+        let a = interner.intern("name");
+        let b = interner.intern("port");
+        assert_eq!(interner.intern("name"), a);
+        assert_ne!(a, b);
+        assert_eq!(interner.len(), 2);
+        assert_eq!(interner.resolve(b), "port");
+    }
+
+    #[test]
+    fn test_round_trip_matches_value() {
+        let value = json!({
+            "name": "example",
+            "ports": [80, 443, -1, 2.5],
+            "nested": { "empty": {}, "list": [], "flag": true, "none": null }
+        });
+        let doc = Document::from_serialize(&value).unwrap();
+
+        assert_eq!(doc.to_value(), value);
+        assert_eq!(
+            doc.to_json_string(false),
+            serde_json::to_string(&value).unwrap()
+        );
+        assert_eq!(
+            doc.to_json_string(true),
+            serde_json::to_string_pretty(&value).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_layout_matches_value_writer() {
+        let value = json!({
+            "name": "example",
+            "ports": [80, 443, -1, 2.5],
+            "nested": { "list": ["a", null, true], "rules": [{ "x": [] }] }
+        });
+        let doc = Document::from_serialize(&value).unwrap();
+        let layout = Layout::new()
+            .with_indent(3)
+            .with_inline_arrays(24)
+            .with_bare_keys(true)
+            .with_final_newline(true);
+
+        let mut out = Vec::new();
+        doc.write_json_with(&mut out, &layout).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            json::to_string_with(&value, &layout)
+        );
+    }
+
+    #[test]
+    fn test_repeated_keys_are_interned_once() {
+        let records: Vec<Value> = (0..1000)
+            .map(|i| json!({ "name": "svc", "replicas": i }))
+            .collect();
+        let doc = Document::from_serialize(&records).unwrap();
+
+        // "name", "replicas" and "svc"
+        assert_eq!(doc.strings().len(), 3);
+        assert_eq!(doc.node_count(), 1 + 1000 * 3);
+    }
+
+    #[test]
+    fn test_enum_variants_match_serde_json() {
+        #[derive(serde::Serialize)]
+        enum Shape {
+            Unit,
+            Newtype(u8),
+            Tuple(u8, u8),
+            Struct { x: u8 },
+        }
+
+        for shape in [
+            Shape::Unit,
+            Shape::Newtype(1),
+            Shape::Tuple(1, 2),
+            Shape::Struct { x: 3 },
+        ] {
+            let doc = Document::from_serialize(&shape).unwrap();
+            assert_eq!(doc.to_value(), serde_json::to_value(&shape).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_reorder_follows_field_order() {
+        let value = json!({ "b": { "y": 1, "x": [{ "q": 1, "p": 2 }] }, "a": 2, "c": 3 });
+        let mut doc = Document::from_serialize(&value).unwrap();
+        doc.reorder(
+            &FieldOrder::new()
+                .with_record("", ["c", "b"])
+                .with_record("b", ["y", "x"])
+                .with_record("b.x[0]", ["q", "p"]),
+        );
+        assert_eq!(
+            doc.to_json_string(false),
+            r#"{"c":3,"b":{"y":1,"x":[{"q":1,"p":2}]},"a":2}"#
+        );
+        assert_eq!(doc.to_value(), value);
+    }
+
+    #[test]
+    fn test_scalar_root() {
+        let doc = Document::from_serialize(&42).unwrap();
+        assert_eq!(doc.to_json_string(false), "42");
+    }
+}
@@ -0,0 +1,243 @@
+//! Evaluation audit log
+//!
+//! An [`AuditLog`] records what evaluation touched outside the program text,
+//! for reviewing configs from less-trusted contributors. Attach one with
+//! [`NickelLoader::with_audit_log`](crate::NickelLoader::with_audit_log) or
+//! `bunsenite --audit-log FILE`. The log is opened for appending and never
+//! rewritten; each line is one JSON event:
+//!
+//! ```text
+//! {"event":"read","path":"app.ncl","time_ms":1760551200000}
+//! {"event":"import","found":true,"from":"app.ncl","path":"lib/base.ncl","time_ms":1760551200001}
+//! {"event":"env","name":"CI","time_ms":1760551200001}
+//! {"event":"transform","time_ms":1760551200950,"transform":"Exec { program: \"sign\", args: [] }"}
+//! ```
+//!
+//! - `read`: a file bunsenite or Nickel reads as program input
+//! - `import`: an import, resolved to the path Nickel loads it from; `found`
+//!   is false if nothing is there
+//! - `env`: an environment variable read while loading, logged once per name:
+//!   by bunsenite itself, or exposed to the program with `--env-prefix` (see
+//!   [`crate::env`])
+//! - `transform`: host code run on an evaluated value (see
+//!   [`crate::transform`]), such as an `exec:` plugin process
+//!
+//! Imports are found by scanning sources before evaluation (see
+//! [`crate::imports`]), so an import in a branch evaluation never takes is
+//! logged too. The log fails closed: if an event cannot be written, the
+//! evaluation that caused it fails.
+
+use crate::error::Result;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::fmt;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Something evaluation touched
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event<'a> {
+    /// A file read as program input
+    Read {
+        /// Path of the file
+        path: &'a Path,
+    },
+    /// An import resolved to a file
+    Import {
+        /// File (or source name) containing the import
+        from: &'a Path,
+        /// Resolved path of the imported file
+        path: &'a Path,
+        /// Whether the file could be read
+        found: bool,
+    },
+    /// An environment variable read
+    Env {
+        /// Name of the variable
+        name: &'a str,
+    },
+    /// A transform run on an evaluated value
+    Transform {
+        /// Description of the transform
+        transform: &'a str,
+    },
+}
+
+impl Event<'_> {
+    /// The event as a log entry, without its timestamp
+    fn to_json(self) -> Value {
+        match self {
+            Event::Read { path } => json!({ "event": "read", "path": path.display().to_string() }),
+            Event::Import { from, path, found } => json!({
+                "event": "import",
+                "from": from.display().to_string(),
+                "path": path.display().to_string(),
+                "found": found,
+            }),
+            Event::Env { name } => json!({ "event": "env", "name": name }),
+            Event::Transform { transform } => {
+                json!({ "event": "transform", "transform": transform })
+            }
+        }
+    }
+}
+
+struct State {
+    output: Box<dyn Write + Send>,
+    env: HashSet<String>,
+}
+
+/// An append-only log of [`Event`]s
+pub struct AuditLog {
+    state: Mutex<State>,
+}
+
+impl fmt::Debug for AuditLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuditLog").finish_non_exhaustive()
+    }
+}
+
+impl AuditLog {
+    /// Log to `output`
+    pub fn new<W: Write + Send + 'static>(output: W) -> Self {
+        Self {
+            state: Mutex::new(State {
+                output: Box::new(output),
+                env: HashSet::new(),
+            }),
+        }
+    }
+
+    /// Append to the file at `path`, creating it if needed
+    ///
+    /// # Errors
+    ///
+    /// Returns an I/O error if the file cannot be opened
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        Ok(Self::new(file))
+    }
+
+    /// Append an event
+    ///
+    /// # Errors
+    ///
+    /// Returns an I/O error if the event cannot be written, so that nothing
+    /// is evaluated without a record of it
+    pub fn record(&self, event: Event<'_>) -> Result<()> {
+        let mut state = self.state.lock().expect("audit log poisoned");
+        if let Event::Env { name } = event {
+            if !state.env.insert(name.to_string()) {
+                return Ok(());
+            }
+        }
+
+        let mut entry = json!({ "time_ms": unix_millis() });
+        if let (Value::Object(entry), Value::Object(fields)) = (&mut entry, event.to_json()) {
+            entry.extend(fields);
+        }
+        let line = crate::json::to_string(&entry, false);
+        writeln!(state.output, "{}", line)
+            .and_then(|()| state.output.flush())
+            .map_err(|e| {
+                std::io::Error::new(e.kind(), format!("cannot write audit log: {}", e)).into()
+            })
+    }
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_record_appends_json_lines() {
+        let output = Shared::default();
+        let log = AuditLog::new(output.clone());
+        log.record(Event::Read {
+            path: Path::new("app.ncl"),
+        })
+        .unwrap();
+        log.record(Event::Env { name: "CI" }).unwrap();
+        log.record(Event::Env { name: "CI" }).unwrap();
+        log.record(Event::Import {
+            from: Path::new("app.ncl"),
+            path: Path::new("lib.ncl"),
+            found: false,
+        })
+        .unwrap();
+
+        let text = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        let entries: Vec<Value> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0]["event"], "read");
+        assert!(entries[0]["time_ms"].as_u64().unwrap() > 0);
+        assert_eq!(entries[1]["name"], "CI");
+        assert_eq!(entries[2]["found"], false);
+    }
+
+    #[test]
+    fn test_open_appends() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        for _ in 0..2 {
+            AuditLog::open(&path)
+                .unwrap()
+                .record(Event::Env { name: "CI" })
+                .unwrap();
+        }
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 2);
+    }
+
+    #[test]
+    fn test_write_failure_fails_evaluation() {
+        struct Full;
+
+        impl Write for Full {
+            fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+                Err(std::io::ErrorKind::StorageFull.into())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let log = AuditLog::new(Full);
+        assert!(log.record(Event::Env { name: "CI" }).is_err());
+
+        let error = crate::NickelLoader::new()
+            .with_audit_log(AuditLog::new(Full))
+            .parse_string("{ a = 1 }", "app.ncl")
+            .unwrap_err();
+        assert!(error.to_string().contains("audit log"), "{}", error);
+    }
+}
@@ -0,0 +1,113 @@
+//! File globs for batch checks
+//!
+//! Backs `bunsenite validate 'configs/**/*.ncl'`: a FILE argument with glob
+//! metacharacters that does not name an existing file is expanded here,
+//! so the pattern works the same whatever the shell (quoted, on Windows, or
+//! in a CI step without `globstar`). `*`, `?` and `[...]` match within one
+//! path component and `**` matches any number of directories.
+//!
+//! The matches are checked together with
+//! [`NickelLoader::validate_many`](crate::NickelLoader::validate_many).
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::batch;
+//!
+//! let dir = tempfile::tempdir().unwrap();
+//! std::fs::create_dir(dir.path().join("prod")).unwrap();
+//! std::fs::write(dir.path().join("app.ncl"), "{}").unwrap();
+//! std::fs::write(dir.path().join("prod/db.ncl"), "{}").unwrap();
+//!
+//! let pattern = format!("{}/**/*.ncl", dir.path().display());
+//! assert!(batch::is_glob(&pattern));
+//! assert_eq!(batch::expand(&pattern).unwrap().len(), 2);
+//! ```
+
+use crate::error::{Error, Result};
+use std::path::{Path, PathBuf};
+
+/// Whether `spec` is a glob pattern rather than a file name
+///
+/// A file that exists under the name is taken literally, even if the name
+/// contains metacharacters.
+pub fn is_glob(spec: &str) -> bool {
+    spec.contains(['*', '?', '[']) && !Path::new(spec).exists()
+}
+
+/// The files matching `pattern`, in path order
+///
+/// Directories that match are skipped, as are hidden files unless the
+/// pattern names them with a leading `.`.
+///
+/// # Errors
+///
+/// Returns an invalid-input error if the pattern is malformed or matches no
+/// file, or an I/O error if a directory on the way cannot be read
+pub fn expand(pattern: &str) -> Result<Vec<PathBuf>> {
+    let options = glob::MatchOptions {
+        require_literal_leading_dot: true,
+        ..glob::MatchOptions::new()
+    };
+    let matches = glob::glob_with(pattern, options)
+        .map_err(|e| Error::invalid_input(format!("Invalid glob '{}': {}", pattern, e)))?;
+
+    let mut files = Vec::new();
+    for path in matches {
+        let path = path.map_err(std::io::Error::from)?;
+        if path.is_file() {
+            files.push(path);
+        }
+    }
+    if files.is_empty() {
+        return Err(Error::invalid_input(format!(
+            "No files match '{}'",
+            pattern
+        )));
+    }
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["a.ncl", "b.json", "nested/deep/c.ncl", ".hidden/d.ncl"] {
+            let path = dir.path().join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "{}").unwrap();
+        }
+        std::fs::create_dir(dir.path().join("dir.ncl")).unwrap();
+
+        let root = dir.path().display();
+        let names = |pattern: String| -> Vec<PathBuf> {
+            expand(&pattern)
+                .unwrap()
+                .iter()
+                .map(|path| path.strip_prefix(dir.path()).unwrap().to_path_buf())
+                .collect()
+        };
+        assert_eq!(
+            names(format!("{}/**/*.ncl", root)),
+            [PathBuf::from("a.ncl"), PathBuf::from("nested/deep/c.ncl")]
+        );
+        assert_eq!(names(format!("{}/?.json", root)), [PathBuf::from("b.json")]);
+        assert!(expand(&format!("{}/*.yaml", root)).is_err());
+        assert!(expand(&format!("{}/[a.ncl", root)).is_err());
+    }
+
+    #[test]
+    fn test_is_glob() {
+        let dir = tempfile::tempdir().unwrap();
+        let literal = dir.path().join("odd[1].ncl");
+        std::fs::write(&literal, "{}").unwrap();
+
+        assert!(is_glob("configs/*.ncl"));
+        assert!(!is_glob("configs/app.ncl"));
+        assert!(!is_glob(&literal.to_string_lossy()));
+    }
+}
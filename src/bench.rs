@@ -0,0 +1,281 @@
+//! Phase benchmarks and performance regression checks
+//!
+//! Times each phase of the pipeline (parse, typecheck, evaluate, serialize) on
+//! user-provided files and compares the results against a stored baseline.
+//! This backs the `bunsenite bench` command; the criterion suite in `benches/`
+//! uses [`measure`] on a fixed corpus.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::bench::{self, Phase};
+//! use bunsenite::NickelLoader;
+//!
+//! let loader = NickelLoader::new();
+//! let elapsed = bench::measure(&loader, Phase::Evaluate, "{ foo = 1 + 1 }", "bench.ncl").unwrap();
+//! assert!(elapsed.as_nanos() > 0);
+//! ```
+
+use crate::error::{Error, Result};
+use crate::loader::{read_source, NickelLoader};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+use std::time::Duration;
+
+/// A phase of the parse-to-output pipeline
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Phase {
+    /// Parsing the source into a program
+    Parse,
+    /// Static typechecking
+    Typecheck,
+    /// Full evaluation
+    Evaluate,
+    /// Serializing the evaluated result to JSON
+    Serialize,
+}
+
+impl Phase {
+    /// All phases, in pipeline order
+    pub const ALL: [Phase; 4] = [
+        Phase::Parse,
+        Phase::Typecheck,
+        Phase::Evaluate,
+        Phase::Serialize,
+    ];
+
+    /// Lowercase name of the phase
+    pub fn name(self) -> &'static str {
+        match self {
+            Phase::Parse => "parse",
+            Phase::Typecheck => "typecheck",
+            Phase::Evaluate => "evaluate",
+            Phase::Serialize => "serialize",
+        }
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Timing of one phase on one file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Measurement {
+    /// File the phase was run on, as given on the command line
+    pub file: String,
+    /// Phase that was timed
+    pub phase: Phase,
+    /// Number of timed iterations
+    pub iterations: u32,
+    /// Mean time per iteration, in nanoseconds
+    pub mean_ns: u64,
+    /// Fastest iteration, in nanoseconds
+    pub min_ns: u64,
+}
+
+/// Stored measurements to compare later runs against
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Baseline {
+    /// Library version that recorded the baseline
+    pub version: String,
+    /// Recorded measurements
+    pub measurements: Vec<Measurement>,
+}
+
+/// Result of comparing a measurement against its baseline
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    /// File the phase was run on
+    pub file: String,
+    /// Phase that was timed
+    pub phase: Phase,
+    /// Baseline mean, in nanoseconds
+    pub baseline_ns: u64,
+    /// Current mean, in nanoseconds
+    pub current_ns: u64,
+    /// Relative change in percent (positive means slower)
+    pub change_percent: f64,
+    /// Whether the slowdown exceeds the threshold
+    pub regressed: bool,
+}
+
+impl Baseline {
+    /// Create a baseline from a set of measurements
+    pub fn new(measurements: Vec<Measurement>) -> Self {
+        Self {
+            version: crate::VERSION.to_string(),
+            measurements,
+        }
+    }
+
+    /// Load a baseline from a JSON file
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or is not a valid baseline
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let contents = std::fs::read_to_string(path.as_ref())?;
+        serde_json::from_str(&contents).map_err(|e| {
+            Error::invalid_input(format!(
+                "Invalid baseline '{}': {}",
+                path.as_ref().display(),
+                e
+            ))
+        })
+    }
+
+    /// Save the baseline as pretty-printed JSON
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be written
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| Error::serialization_error(e.to_string()))?;
+        std::fs::write(path, json + "\n")?;
+        Ok(())
+    }
+
+    /// Compare current measurements against this baseline
+    ///
+    /// A measurement regresses when its mean is more than `threshold_percent`
+    /// slower than the baseline. Measurements without a baseline entry are
+    /// skipped.
+    pub fn compare(&self, current: &[Measurement], threshold_percent: f64) -> Vec<Comparison> {
+        current
+            .iter()
+            .filter_map(|m| {
+                let base = self
+                    .measurements
+                    .iter()
+                    .find(|b| b.file == m.file && b.phase == m.phase)?;
+                let change_percent = if base.mean_ns == 0 {
+                    0.0
+                } else {
+                    (m.mean_ns as f64 - base.mean_ns as f64) / base.mean_ns as f64 * 100.0
+                };
+                Some(Comparison {
+                    file: m.file.clone(),
+                    phase: m.phase,
+                    baseline_ns: base.mean_ns,
+                    current_ns: m.mean_ns,
+                    change_percent,
+                    regressed: change_percent > threshold_percent,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Time a single run of one phase
+///
+/// Earlier phases needed as input (for example, evaluation before
+/// serialization) run outside the timed region.
+///
+/// # Errors
+///
+/// Returns an error if the source fails to parse, typecheck or evaluate
+pub fn measure(loader: &NickelLoader, phase: Phase, source: &str, name: &str) -> Result<Duration> {
+    loader.time_phase(phase, source, name)
+}
+
+/// Run every phase `iterations` times on a file
+///
+/// Each phase gets one untimed warm-up run first.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or any phase fails
+pub fn run(loader: &NickelLoader, path: &Path, iterations: u32) -> Result<Vec<Measurement>> {
+    if iterations == 0 {
+        return Err(Error::invalid_input("iterations must be at least 1"));
+    }
+
+    let (source, name) = read_source(path)?;
+    let mut measurements = Vec::with_capacity(Phase::ALL.len());
+
+    for phase in Phase::ALL {
+        measure(loader, phase, &source, &name)?;
+
+        let mut total = Duration::ZERO;
+        let mut min = Duration::MAX;
+        for _ in 0..iterations {
+            let elapsed = measure(loader, phase, &source, &name)?;
+            total += elapsed;
+            min = min.min(elapsed);
+        }
+
+        measurements.push(Measurement {
+            file: path.display().to_string(),
+            phase,
+            iterations,
+            mean_ns: (total / iterations).as_nanos() as u64,
+            min_ns: min.as_nanos() as u64,
+        });
+    }
+
+    Ok(measurements)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn measurement(file: &str, phase: Phase, mean_ns: u64) -> Measurement {
+        Measurement {
+            file: file.to_string(),
+            phase,
+            iterations: 1,
+            mean_ns,
+            min_ns: mean_ns,
+        }
+    }
+
+    #[test]
+    fn test_measure_all_phases() {
+        let loader = NickelLoader::new();
+        for phase in Phase::ALL {
+            assert!(measure(&loader, phase, "{ foo = [1, 2, 3] }", "bench.ncl").is_ok());
+        }
+    }
+
+    #[test]
+    fn test_measure_reports_errors() {
+        let loader = NickelLoader::new();
+        assert!(measure(&loader, Phase::Evaluate, "{ foo = }", "bad.ncl").is_err());
+    }
+
+    #[test]
+    fn test_compare_flags_regressions() {
+        let baseline = Baseline::new(vec![
+            measurement("a.ncl", Phase::Parse, 1000),
+            measurement("a.ncl", Phase::Evaluate, 1000),
+        ]);
+        let current = vec![
+            measurement("a.ncl", Phase::Parse, 1050),
+            measurement("a.ncl", Phase::Evaluate, 1500),
+            measurement("b.ncl", Phase::Parse, 10),
+        ];
+
+        let comparisons = baseline.compare(&current, 10.0);
+        assert_eq!(comparisons.len(), 2);
+        assert!(!comparisons[0].regressed);
+        assert!(comparisons[1].regressed);
+        assert!((comparisons[1].change_percent - 50.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn test_baseline_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("baseline.json");
+        let baseline = Baseline::new(vec![measurement("a.ncl", Phase::Serialize, 42)]);
+
+        baseline.save(&path).unwrap();
+        assert_eq!(Baseline::load(&path).unwrap(), baseline);
+    }
+}
@@ -0,0 +1,285 @@
+//! What a config needs in order to evaluate
+//!
+//! Backs `bunsenite inspect-capabilities`: before running a config from a
+//! less-trusted source, list everything evaluating it would touch. The
+//! analysis is static: sources are scanned for imports (see
+//! [`crate::imports`]) without evaluating anything, and imports resolving
+//! outside the project root are flagged. [`Report::evaluate`] optionally adds
+//! the outcome of actually evaluating the config.
+//!
+//! Some capabilities are fixed by the engine and reported for completeness:
+//! nickel-lang-core 0.9.1 gives programs no access to environment variables
+//! or the network, so a config can only reach the outside world through the
+//! files it imports and the transforms the loader runs on its output.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use bunsenite::capabilities::Report;
+//! use bunsenite::NickelLoader;
+//! use std::path::Path;
+//!
+//! let report = Report::inspect(&NickelLoader::new(), Path::new("contrib.ncl"), Path::new(".")).unwrap();
+//! for import in report.outside_root() {
+//!     println!("{} imports {}", import.from.display(), import.path.display());
+//! }
+//! ```
+
+use crate::error::Result;
+use crate::imports;
+use crate::loader::{read_source, NickelLoader};
+use crate::paths;
+use serde_json::{json, Value};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// An import the config would load
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Import {
+    /// File containing the import
+    pub from: PathBuf,
+    /// Resolved path of the imported file
+    pub path: PathBuf,
+    /// Whether the file exists and is readable
+    pub found: bool,
+    /// Whether the file lies outside the project root
+    pub outside_root: bool,
+}
+
+/// Everything a config needs to evaluate
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    /// The inspected config
+    pub file: PathBuf,
+    /// Project root imports are checked against
+    pub root: PathBuf,
+    /// Every import, in discovery order
+    pub imports: Vec<Import>,
+    /// Files read, starting with the config itself
+    pub files: Vec<PathBuf>,
+    /// Total bytes of the files read
+    pub bytes: u64,
+    /// Transforms the loader would run on the output
+    pub transforms: Vec<String>,
+    /// How evaluation went, if it was attempted
+    pub evaluation: Option<std::result::Result<Duration, String>>,
+}
+
+impl Report {
+    /// Statically inspect the config at `path`
+    ///
+    /// Imports resolve as `loader` resolves them; relative paths are taken
+    /// from the current directory, as is a relative `root`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an I/O error if the config cannot be read
+    pub fn inspect(loader: &NickelLoader, path: &Path, root: &Path) -> Result<Self> {
+        let (source, name) = read_source(path)?;
+        let cwd = std::env::current_dir()?;
+        let root = imports::normalize(&cwd.join(root));
+        let graph = imports::resolve_source(&source, &name, loader.threads());
+
+        let imports = graph
+            .imports
+            .iter()
+            .map(|(from, path)| Import {
+                from: from.clone(),
+                path: path.clone(),
+                found: !graph.missing.contains(path),
+                outside_root: !imports::normalize(&cwd.join(path)).starts_with(&root),
+            })
+            .collect();
+        let bytes = source.len() as u64 + graph.bytes;
+        let mut files = vec![path.to_path_buf()];
+        files.extend(graph.files);
+
+        Ok(Self {
+            file: path.to_path_buf(),
+            root,
+            imports,
+            files,
+            bytes,
+            transforms: loader.transform_descriptions(),
+            evaluation: None,
+        })
+    }
+
+    /// Evaluate the config with `loader` and record the outcome
+    ///
+    /// This runs the config, including the loader's transforms; inspect the
+    /// static report first.
+    pub fn evaluate(mut self, loader: &NickelLoader) -> Self {
+        let start = Instant::now();
+        self.evaluation = Some(
+            loader
+                .parse_file(&self.file)
+                .map(|_| start.elapsed())
+                .map_err(|e| e.to_string()),
+        );
+        self
+    }
+
+    /// This report with its paths relative to the project at `root`
+    ///
+    /// See [`paths::relative_to`].
+    pub fn relative_to(mut self, root: &Path) -> Self {
+        let relative = |path: &Path| paths::relative_to(path, root);
+        self.file = relative(&self.file);
+        self.root = relative(&self.root);
+        for import in &mut self.imports {
+            import.from = relative(&import.from);
+            import.path = relative(&import.path);
+        }
+        for file in &mut self.files {
+            *file = relative(file);
+        }
+        if let Some(Err(error)) = &mut self.evaluation {
+            *error = paths::relative_text(error, root);
+        }
+        self
+    }
+
+    /// Imports resolving outside the project root
+    pub fn outside_root(&self) -> impl Iterator<Item = &Import> {
+        self.imports.iter().filter(|import| import.outside_root)
+    }
+
+    /// The report as JSON
+    pub fn to_json(&self) -> Value {
+        let imports: Vec<Value> = self
+            .imports
+            .iter()
+            .map(|import| {
+                json!({
+                    "from": paths::display(&import.from),
+                    "path": paths::display(&import.path),
+                    "found": import.found,
+                    "outside_root": import.outside_root,
+                })
+            })
+            .collect();
+        let files: Vec<String> = self
+            .files
+            .iter()
+            .map(|file| file.display().to_string())
+            .collect();
+        let mut report = json!({
+            "file": self.file.display().to_string(),
+            "root": self.root.display().to_string(),
+            "imports": imports,
+            "files": files,
+            "bytes": self.bytes,
+            "env": [],
+            "network": false,
+            "transforms": self.transforms,
+        });
+        match &self.evaluation {
+            Some(Ok(elapsed)) => {
+                report["evaluation"] = json!({ "ok": true, "ms": elapsed.as_millis() as u64 })
+            }
+            Some(Err(error)) => report["evaluation"] = json!({ "ok": false, "error": error }),
+            None => {}
+        }
+        report
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.file.display())?;
+        writeln!(
+            f,
+            "  Files read: {} ({} bytes)",
+            self.files.len(),
+            self.bytes
+        )?;
+        writeln!(f, "  Imports: {}", self.imports.len())?;
+        for import in &self.imports {
+            let mut notes = Vec::new();
+            if import.outside_root {
+                notes.push("outside root");
+            }
+            if !import.found {
+                notes.push("missing");
+            }
+            write!(
+                f,
+                "    {} -> {}",
+                paths::display(&import.from),
+                paths::display(&import.path)
+            )?;
+            if notes.is_empty() {
+                writeln!(f)?;
+            } else {
+                writeln!(f, " ({})", notes.join(", "))?;
+            }
+        }
+        writeln!(
+            f,
+            "  Environment variables: none (not readable by Nickel programs)"
+        )?;
+        writeln!(f, "  Network: none (not reachable by Nickel programs)")?;
+        if self.transforms.is_empty() {
+            writeln!(f, "  Transforms: none")?;
+        } else {
+            writeln!(f, "  Transforms:")?;
+            for transform in &self.transforms {
+                writeln!(f, "    {}", transform)?;
+            }
+        }
+        match &self.evaluation {
+            Some(Ok(elapsed)) => writeln!(f, "  Evaluation: ok in {} ms", elapsed.as_millis())?,
+            Some(Err(error)) => writeln!(f, "  Evaluation: failed: {}", error)?,
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inspect_flags_imports_outside_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let project = dir.path().join("project");
+        std::fs::create_dir(&project).unwrap();
+        let secret = dir.path().join("secret.ncl");
+        std::fs::write(&secret, "{ token = \"x\" }").unwrap();
+        let inside = project.join("base.ncl");
+        std::fs::write(&inside, "{ a = 1 }").unwrap();
+        let config = project.join("app.ncl");
+        std::fs::write(
+            &config,
+            format!(
+                "(import {}) & (import {}) & (import {})",
+                crate::source::string_literal(inside.to_str().unwrap()),
+                crate::source::string_literal(secret.to_str().unwrap()),
+                crate::source::string_literal(project.join("gone.ncl").to_str().unwrap()),
+            ),
+        )
+        .unwrap();
+
+        let loader = NickelLoader::new().with_threads(1);
+        let report = Report::inspect(&loader, &config, &project).unwrap();
+        assert_eq!(report.imports.len(), 3);
+        assert_eq!(report.files.len(), 3);
+        let outside: Vec<&Path> = report.outside_root().map(|i| i.path.as_path()).collect();
+        assert_eq!(outside, vec![secret.as_path()]);
+        assert!(!report.imports[2].found);
+        assert_eq!(report.to_json()["network"], false);
+
+        let relative = report.clone().relative_to(&project);
+        assert_eq!(relative.root, Path::new("."));
+        assert_eq!(relative.files[0], Path::new("app.ncl"));
+        assert_eq!(relative.imports[0].path, Path::new("base.ncl"));
+        assert_eq!(relative.imports[1].path, secret);
+
+        let report = report.evaluate(&loader);
+        assert!(matches!(report.evaluation, Some(Err(_))));
+        assert!(report.to_string().contains("outside root"));
+    }
+}
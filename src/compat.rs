@@ -0,0 +1,203 @@
+//! Compatibility shim for renamed standard library functions
+//!
+//! When the embedded Nickel version is bumped, configs still calling
+//! deprecated stdlib names stop evaluating. With compatibility enabled
+//! ([`NickelLoader::with_compat`](crate::NickelLoader::with_compat) or
+//! `bunsenite --compat`), old names are rewritten to their current
+//! replacements before evaluation and each rewrite is reported as a
+//! [`CompatWarning`], giving config repositories a grace period to migrate.
+//!
+//! Rewriting is lexical: comments and string literals are left untouched, and
+//! only fully qualified `std.` paths are recognised. Code inside string
+//! interpolation (`%{ ... }`) is not rewritten.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::compat;
+//!
+//! let (source, warnings) = compat::translate("std.string.to_num \"42\"");
+//! assert_eq!(source, "std.string.to_number \"42\"");
+//! assert_eq!(warnings[0].old, "std.string.to_num");
+//! ```
+
+use crate::lexer::{self, Token};
+use std::fmt;
+
+/// A stdlib function that was renamed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rename {
+    /// Deprecated fully qualified name
+    pub old: &'static str,
+    /// Current fully qualified name
+    pub new: &'static str,
+    /// Nickel version that introduced the new name
+    pub since: &'static str,
+}
+
+/// Renamed stdlib functions understood by the shim
+pub const RENAMES: &[Rename] = &[
+    Rename {
+        old: "std.string.to_num",
+        new: "std.string.to_number",
+        since: "1.0",
+    },
+    Rename {
+        old: "std.string.from_num",
+        new: "std.string.from_number",
+        since: "1.0",
+    },
+    Rename {
+        old: "std.array.foldl",
+        new: "std.array.fold_left",
+        since: "1.0",
+    },
+    Rename {
+        old: "std.array.foldr",
+        new: "std.array.fold_right",
+        since: "1.0",
+    },
+    Rename {
+        old: "std.contract.from_pred",
+        new: "std.contract.from_predicate",
+        since: "1.0",
+    },
+    Rename {
+        old: "std.num",
+        new: "std.number",
+        since: "1.0",
+    },
+];
+
+/// A deprecated name that was rewritten
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompatWarning {
+    /// Deprecated name found in the source
+    pub old: &'static str,
+    /// Name it was rewritten to
+    pub new: &'static str,
+    /// Nickel version that introduced the new name
+    pub since: &'static str,
+    /// 1-based line of the occurrence
+    pub line: usize,
+    /// 1-based column of the occurrence, in characters
+    pub column: usize,
+}
+
+impl fmt::Display for CompatWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}: `{}` is deprecated, use `{}` (renamed in Nickel {})",
+            self.line, self.column, self.old, self.new, self.since
+        )
+    }
+}
+
+/// Rewrite deprecated stdlib names to their current replacements
+///
+/// Returns the rewritten source and one warning per rewritten occurrence, in
+/// source order. Sources without deprecated names are returned unchanged.
+pub fn translate(source: &str) -> (String, Vec<CompatWarning>) {
+    let mut out = String::with_capacity(source.len());
+    let mut warnings = Vec::new();
+    let mut line = 1;
+    let mut column = 1;
+
+    for (span, token) in lexer::spanned_lenient(source) {
+        let text = &source[span];
+        match token {
+            Token::Word(word) if word.starts_with(is_ident_start) => {
+                let end = word.find(|c: char| !is_path_char(c)).unwrap_or(word.len());
+                match rename_for(&word[..end]) {
+                    Some(rename) => {
+                        warnings.push(CompatWarning {
+                            old: rename.old,
+                            new: rename.new,
+                            since: rename.since,
+                            line,
+                            column,
+                        });
+                        out.push_str(rename.new);
+                        out.push_str(&text[rename.old.len()..]);
+                    }
+                    None => out.push_str(text),
+                }
+            }
+            _ => out.push_str(text),
+        }
+        for c in text.chars() {
+            advance(c, &mut line, &mut column);
+        }
+    }
+
+    (out, warnings)
+}
+
+/// Find the rename for a dotted path, matching whole path segments
+fn rename_for(token: &str) -> Option<&'static Rename> {
+    RENAMES.iter().find(|r| {
+        token
+            .strip_prefix(r.old)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+    })
+}
+
+fn advance(c: char, line: &mut usize, column: &mut usize) {
+    if c == '\n' {
+        *line += 1;
+        *column = 1;
+    } else {
+        *column += 1;
+    }
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+fn is_path_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '\'' | '-' | '.')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rewrites_deprecated_names() {
+        let (out, warnings) = translate("std.array.foldl (fun acc x => acc + x) 0 [1, 2]");
+        assert_eq!(out, "std.array.fold_left (fun acc x => acc + x) 0 [1, 2]");
+        assert_eq!(warnings.len(), 1);
+        assert_eq!((warnings[0].line, warnings[0].column), (1, 1));
+    }
+
+    #[test]
+    fn test_module_rename_keeps_member() {
+        let (out, _) = translate("{ x = std.num.abs (-1) }");
+        assert_eq!(out, "{ x = std.number.abs (-1) }");
+    }
+
+    #[test]
+    fn test_leaves_current_names_alone() {
+        let source = "{ a = std.string.to_number \"1\", b = std.numbers }";
+        let (out, warnings) = translate(source);
+        assert_eq!(out, source);
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn test_skips_strings_and_comments() {
+        let source = "# std.string.to_num\n{ a = \"std.array.foldr\", b = m%\"std.num\"% }";
+        let (out, warnings) = translate(source);
+        assert_eq!(out, source);
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn test_warning_positions() {
+        let (_, warnings) = translate("{\n  a = std.string.from_num 1,\n}");
+        assert_eq!((warnings[0].line, warnings[0].column), (2, 7));
+        assert!(warnings[0].to_string().contains("std.string.from_number"));
+    }
+}
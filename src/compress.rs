@@ -0,0 +1,253 @@
+//! Compressed artifacts
+//!
+//! Backs the global `--compress gzip|zstd` flag: files bunsenite writes
+//! (`parse -o`, `export -o`, `build`, `expand` and `parse --tenants
+//! --out-dir` outputs) are compressed and get a `.gz` or `.zst` extension,
+//! for large generated outputs kept in object storage. Standard output is
+//! never compressed.
+//!
+//! Reading goes the other way without being asked: artifacts read back, such
+//! as `--diff-against` baselines and `drift --live` state, are decompressed
+//! when their first bytes are a gzip or zstd header, whatever their name.
+//!
+//! Requires the `compression` feature; without it, compressing fails and
+//! compressed artifacts are reported as such instead of misread.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::compress::Compression;
+//! use std::path::Path;
+//!
+//! let zstd: Compression = "zstd".parse().unwrap();
+//! assert_eq!(zstd.path(Path::new("out/app.json")), Path::new("out/app.json.zst"));
+//! assert_eq!(Compression::detect(b"{}"), None);
+//! ```
+
+use crate::error::{Error, Result};
+use crate::output;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// A compression format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// gzip (`.gz`)
+    Gzip,
+    /// Zstandard (`.zst`)
+    Zstd,
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s {
+            "gzip" | "gz" => Ok(Compression::Gzip),
+            "zstd" | "zst" => Ok(Compression::Zstd),
+            other => Err(format!(
+                "unknown compression '{}' (expected gzip or zstd)",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+        })
+    }
+}
+
+impl Compression {
+    /// File extension, without the dot
+    pub fn extension(self) -> &'static str {
+        match self {
+            Compression::Gzip => "gz",
+            Compression::Zstd => "zst",
+        }
+    }
+
+    /// `path` with this format's extension appended, unless it has it
+    pub fn path(self, path: &Path) -> PathBuf {
+        if path.extension().and_then(|e| e.to_str()) == Some(self.extension()) {
+            return path.to_path_buf();
+        }
+        let mut name = path.as_os_str().to_owned();
+        name.push(".");
+        name.push(self.extension());
+        PathBuf::from(name)
+    }
+
+    /// The format `bytes` start with, if any
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(&[0x1f, 0x8b]) {
+            Some(Compression::Gzip)
+        } else if bytes.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+
+    /// Compress `bytes`
+    ///
+    /// # Errors
+    ///
+    /// Returns an invalid-input error if bunsenite was built without the
+    /// `compression` feature, or an I/O error
+    pub fn compress(self, bytes: &[u8]) -> Result<Vec<u8>> {
+        #[cfg(feature = "compression")]
+        {
+            use std::io::Write;
+
+            match self {
+                Compression::Gzip => {
+                    let mut encoder =
+                        flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                    encoder.write_all(bytes)?;
+                    Ok(encoder.finish()?)
+                }
+                Compression::Zstd => Ok(zstd::encode_all(bytes, 0)?),
+            }
+        }
+        #[cfg(not(feature = "compression"))]
+        {
+            let _ = bytes;
+            Err(unsupported(self))
+        }
+    }
+
+    /// Decompress `bytes`, which are in this format
+    ///
+    /// # Errors
+    ///
+    /// As [`compress`](Self::compress), or an I/O error for corrupt input
+    pub fn decompress(self, bytes: &[u8]) -> Result<Vec<u8>> {
+        #[cfg(feature = "compression")]
+        {
+            use std::io::Read;
+
+            match self {
+                Compression::Gzip => {
+                    let mut decompressed = Vec::new();
+                    flate2::read::MultiGzDecoder::new(bytes).read_to_end(&mut decompressed)?;
+                    Ok(decompressed)
+                }
+                Compression::Zstd => Ok(zstd::decode_all(bytes)?),
+            }
+        }
+        #[cfg(not(feature = "compression"))]
+        {
+            let _ = bytes;
+            Err(unsupported(self))
+        }
+    }
+}
+
+#[cfg(not(feature = "compression"))]
+fn unsupported(compression: Compression) -> Error {
+    Error::invalid_input(format!(
+        "Cannot handle {} data: bunsenite was built without compression support (the `compression` feature)",
+        compression
+    ))
+}
+
+/// Write `contents` to `path`, compressed if asked, returning the path
+/// written (with the compression's extension)
+///
+/// The file is replaced atomically ([`output::write_atomic`]).
+///
+/// # Errors
+///
+/// Returns a compression error or an I/O error
+pub fn write(path: &Path, contents: &[u8], compression: Option<Compression>) -> Result<PathBuf> {
+    write_with_mode(path, contents, compression, None)
+}
+
+/// As [`write`], with the file's permissions set to `mode` if given
+///
+/// # Errors
+///
+/// As [`write`] and [`output::write_atomic`]
+pub fn write_with_mode(
+    path: &Path,
+    contents: &[u8],
+    compression: Option<Compression>,
+    mode: Option<u32>,
+) -> Result<PathBuf> {
+    match compression {
+        Some(compression) => {
+            let path = compression.path(path);
+            output::write_atomic(&path, &compression.compress(contents)?, mode)?;
+            Ok(path)
+        }
+        None => {
+            output::write_atomic(path, contents, mode)?;
+            Ok(path.to_path_buf())
+        }
+    }
+}
+
+/// Read `path`, decompressing it if it is gzip or zstd data
+///
+/// # Errors
+///
+/// Returns a decompression error or an I/O error
+pub fn read(path: &Path) -> Result<Vec<u8>> {
+    let bytes = std::fs::read(path)?;
+    match Compression::detect(&bytes) {
+        Some(compression) => compression.decompress(&bytes),
+        None => Ok(bytes),
+    }
+}
+
+/// Read `path` as UTF-8 text, decompressing it if needed
+///
+/// # Errors
+///
+/// As [`read`], or an invalid-input error if the contents are not UTF-8
+pub fn read_to_string(path: &Path) -> Result<String> {
+    String::from_utf8(read(path)?)
+        .map_err(|_| Error::invalid_input(format!("{} is not UTF-8 text", path.display())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paths_and_detection() {
+        assert_eq!(
+            Compression::Gzip.path(Path::new("a.json.gz")),
+            Path::new("a.json.gz")
+        );
+        assert_eq!(Compression::Gzip.path(Path::new("a")), Path::new("a.gz"));
+        assert!("lz4".parse::<Compression>().is_err());
+        assert_eq!(
+            Compression::detect(&[0x1f, 0x8b, 8]),
+            Some(Compression::Gzip)
+        );
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_round_trip_through_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let contents = br#"{"replicas":3}"#.repeat(1000);
+        for compression in [Compression::Gzip, Compression::Zstd] {
+            let path = write(&dir.path().join("out.json"), &contents, Some(compression)).unwrap();
+            assert_eq!(path.extension().unwrap(), compression.extension());
+            let written = std::fs::read(&path).unwrap();
+            assert!(written.len() < contents.len());
+            assert_eq!(Compression::detect(&written), Some(compression));
+            assert_eq!(read(&path).unwrap(), contents);
+        }
+        let plain = write(&dir.path().join("plain.json"), b"{}", None).unwrap();
+        assert_eq!(read_to_string(&plain).unwrap(), "{}");
+    }
+}
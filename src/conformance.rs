@@ -0,0 +1,727 @@
+//! Corpus-based conformance testing
+//!
+//! A conformance corpus is a directory of Nickel inputs, each paired with its
+//! expected outcome:
+//!
+//! - `name.ncl` + `name.json`: evaluation must succeed and produce exactly
+//!   this JSON value
+//! - `name.ncl` + `name.error`: evaluation must fail, and the error message
+//!   must contain every non-empty line of the file (`#` lines are comments)
+//!
+//! The same corpus is run against the native engine by `bunsenite conformance`
+//! and can be run against any binding through [`Corpus::run_with`], so the
+//! Deno, WASM and C bindings can be shown to behave identically to the Rust
+//! core. [`Corpus::manifest`] describes the corpus as JSON for harnesses that
+//! are not written in Rust.
+//!
+//! For differential testing, a [`Runner`] drives a binding in a subprocess
+//! over a line-delimited JSON protocol and [`Corpus::differential`] diffs its
+//! results against the native engine:
+//!
+//! ```text
+//! → {"file": "merge.ncl", "source": "{ a = 1 } & { b = 2 }"}
+//! ← {"ok": {"a": 1, "b": 2}}
+//! → {"file": "bad.ncl", "source": "{ a = }"}
+//! ← {"error": "Failed to parse Nickel file 'bad.ncl': ..."}
+//! ```
+//!
+//! Responses must arrive one per line, in request order.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use bunsenite::conformance::Corpus;
+//! use bunsenite::NickelLoader;
+//!
+//! let corpus = Corpus::load("tests/conformance").unwrap();
+//! let report = corpus.run(&NickelLoader::new());
+//! assert!(report.all_passed(), "{:#?}", report.failures().collect::<Vec<_>>());
+//! ```
+
+use crate::error::{Error, Result};
+use crate::loader::NickelLoader;
+use crate::threads;
+use serde_json::{json, Value};
+use std::fmt;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+/// Expected outcome of a conformance case
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expected {
+    /// Evaluation succeeds with this value
+    Output(Value),
+    /// Evaluation fails with a message containing all of these fragments
+    Error(Vec<String>),
+    /// No expectation recorded yet (run with `--update-expected`)
+    Missing,
+}
+
+/// A single input file and its expected outcome
+#[derive(Debug, Clone, PartialEq)]
+pub struct Case {
+    /// Case name (the input's file stem)
+    pub name: String,
+    /// Path to the `.ncl` input
+    pub input: PathBuf,
+    /// Expected outcome
+    pub expected: Expected,
+}
+
+/// Outcome of running one case
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// The engine behaved as expected
+    Pass,
+    /// The engine diverged from the expectation
+    Fail(String),
+    /// The case has no expectation to check against
+    Missing,
+}
+
+/// Result of running one case
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaseResult {
+    /// Case name
+    pub name: String,
+    /// What happened
+    pub outcome: Outcome,
+}
+
+/// Results of running a whole corpus
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Report {
+    /// Per-case results, in corpus order
+    pub results: Vec<CaseResult>,
+}
+
+impl Report {
+    /// Number of passing cases
+    pub fn passed(&self) -> usize {
+        self.results
+            .iter()
+            .filter(|r| r.outcome == Outcome::Pass)
+            .count()
+    }
+
+    /// Cases that failed or had no expectation
+    pub fn failures(&self) -> impl Iterator<Item = &CaseResult> {
+        self.results.iter().filter(|r| r.outcome != Outcome::Pass)
+    }
+
+    /// Whether every case passed
+    pub fn all_passed(&self) -> bool {
+        self.failures().next().is_none()
+    }
+}
+
+/// A directory of conformance cases
+#[derive(Debug, Clone, PartialEq)]
+pub struct Corpus {
+    root: PathBuf,
+    cases: Vec<Case>,
+}
+
+impl Corpus {
+    /// Load every `.ncl` case in a directory, sorted by name
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory or an expectation file cannot be
+    /// read, or if an expected `.json` file is not valid JSON
+    pub fn load<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let root = dir.as_ref().to_path_buf();
+        let mut inputs = Vec::new();
+        for entry in std::fs::read_dir(&root)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "ncl") {
+                inputs.push(path);
+            }
+        }
+        inputs.sort();
+
+        let cases = inputs
+            .into_iter()
+            .map(|input| {
+                let name = input
+                    .file_stem()
+                    .and_then(|s| s.to_str())
+                    .unwrap_or_default()
+                    .to_string();
+                let expected = read_expected(&input)?;
+                Ok(Case {
+                    name,
+                    input,
+                    expected,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self { root, cases })
+    }
+
+    /// Directory the corpus was loaded from
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// All cases, sorted by name
+    pub fn cases(&self) -> &[Case] {
+        &self.cases
+    }
+
+    /// Run the corpus against the native engine
+    pub fn run(&self, loader: &NickelLoader) -> Report {
+        let results = threads::map(loader.threads(), self.cases.iter().collect(), |case| {
+            run_case(case, |source, name| {
+                loader.parse_string(source, name).map_err(|e| e.to_string())
+            })
+        });
+        Report { results }
+    }
+
+    /// Run the corpus against an arbitrary engine
+    ///
+    /// `engine` receives each case's source and file name and returns either
+    /// the evaluated JSON or the error message, exactly as a binding would
+    /// report them to its host language.
+    pub fn run_with<F>(&self, mut engine: F) -> Report
+    where
+        F: FnMut(&str, &str) -> std::result::Result<Value, String>,
+    {
+        let results = self
+            .cases
+            .iter()
+            .map(|case| run_case(case, &mut engine))
+            .collect();
+
+        Report { results }
+    }
+
+    /// Rewrite every case's expectation from the native engine's behavior
+    ///
+    /// Successful cases get a pretty-printed `.json` file; failing cases get an
+    /// `.error` file holding the error's headline (kind and file name). Stale
+    /// expectation files of the other kind are removed. Returns the number of
+    /// cases whose expectation changed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an input cannot be read or an expectation cannot be
+    /// written
+    pub fn update_expected(&mut self, loader: &NickelLoader) -> Result<usize> {
+        let mut written = 0;
+
+        for case in &mut self.cases {
+            let source = std::fs::read_to_string(&case.input)?;
+            let json_path = case.input.with_extension("json");
+            let error_path = case.input.with_extension("error");
+
+            let expected = match loader.parse_string(&source, &file_name(&case.input)) {
+                Ok(value) => {
+                    std::fs::write(&json_path, crate::json::to_string(&value, true) + "\n")?;
+                    remove_if_exists(&error_path)?;
+                    Expected::Output(value)
+                }
+                Err(e) => {
+                    let headline = headline(&e.to_string());
+                    std::fs::write(&error_path, format!("{}\n", headline))?;
+                    remove_if_exists(&json_path)?;
+                    Expected::Error(vec![headline])
+                }
+            };
+
+            if case.expected != expected {
+                written += 1;
+            }
+            case.expected = expected;
+        }
+
+        Ok(written)
+    }
+
+    /// Run the corpus through `engine` and diff it against the native engine
+    ///
+    /// Stored expectations are ignored: each case must produce the same value
+    /// as `loader` does, or fail where `loader` fails. Error messages are not
+    /// compared, since bindings legitimately reword them for their host
+    /// language.
+    pub fn differential<F>(&self, loader: &NickelLoader, engine: F) -> Report
+    where
+        F: FnMut(&str, &str) -> std::result::Result<Value, String>,
+    {
+        let cases = self
+            .cases
+            .iter()
+            .map(|case| {
+                let expected = match std::fs::read_to_string(&case.input) {
+                    Ok(source) => match loader.parse_string(&source, &file_name(&case.input)) {
+                        Ok(value) => Expected::Output(value),
+                        Err(_) => Expected::Error(Vec::new()),
+                    },
+                    Err(_) => Expected::Missing,
+                };
+                Case {
+                    expected,
+                    ..case.clone()
+                }
+            })
+            .collect();
+
+        Corpus {
+            root: self.root.clone(),
+            cases,
+        }
+        .run_with(engine)
+    }
+
+    /// Describe the corpus as JSON for harnesses written in other languages
+    ///
+    /// Each entry carries the case name, the input path, and either an
+    /// `output` value or a list of `error` fragments.
+    pub fn manifest(&self) -> Value {
+        let cases: Vec<Value> = self
+            .cases
+            .iter()
+            .map(|case| {
+                let mut entry = json!({
+                    "name": case.name,
+                    "input": case.input.display().to_string(),
+                });
+                match &case.expected {
+                    Expected::Output(value) => entry["output"] = value.clone(),
+                    Expected::Error(fragments) => entry["error"] = json!(fragments),
+                    Expected::Missing => {}
+                }
+                entry
+            })
+            .collect();
+
+        json!({ "version": 1, "cases": cases })
+    }
+}
+
+/// A language binding that can be exercised through a [`Runner`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Binding {
+    /// Deno FFI bindings (`bindings/deno`)
+    Deno,
+    /// WebAssembly bindings, run under Node.js (`bindings/wasm`)
+    Wasm,
+    /// The C ABI, through a user-supplied runner executable
+    C,
+}
+
+impl Binding {
+    /// Lowercase name of the binding
+    pub fn name(self) -> &'static str {
+        match self {
+            Binding::Deno => "deno",
+            Binding::Wasm => "wasm",
+            Binding::C => "c",
+        }
+    }
+
+    /// Runner command shipped in the repository, relative to its root
+    ///
+    /// The C ABI has no default runner; one must be supplied explicitly.
+    pub fn default_runner(self) -> Option<Vec<String>> {
+        let command: &[&str] = match self {
+            Binding::Deno => &[
+                "deno",
+                "run",
+                "--allow-ffi",
+                "--allow-read",
+                "bindings/deno/conformance.ts",
+            ],
+            Binding::Wasm => &["node", "bindings/wasm/conformance.mjs"],
+            Binding::C => return None,
+        };
+        Some(command.iter().map(|s| s.to_string()).collect())
+    }
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Binding {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s {
+            "deno" => Ok(Binding::Deno),
+            "wasm" => Ok(Binding::Wasm),
+            "c" => Ok(Binding::C),
+            other => Err(format!(
+                "unknown binding '{}' (expected deno, wasm or c)",
+                other
+            )),
+        }
+    }
+}
+
+/// A subprocess speaking the conformance runner protocol
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Runner {
+    program: String,
+    args: Vec<String>,
+}
+
+impl Runner {
+    /// Create a runner from a command line (program followed by arguments)
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `command` is empty
+    pub fn new(command: Vec<String>) -> Result<Self> {
+        let mut command = command.into_iter();
+        let program = command
+            .next()
+            .ok_or_else(|| Error::invalid_input("conformance runner command is empty"))?;
+        Ok(Self {
+            program,
+            args: command.collect(),
+        })
+    }
+
+    /// The default runner for a binding
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the binding has no default runner
+    pub fn for_binding(binding: Binding) -> Result<Self> {
+        let command = binding.default_runner().ok_or_else(|| {
+            Error::invalid_input(format!(
+                "the {} binding has no default conformance runner; pass one with --runner",
+                binding
+            ))
+        })?;
+        Self::new(command)
+    }
+
+    /// Evaluate every case of a corpus in one runner process
+    ///
+    /// Returns one result per case, in corpus order.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the runner cannot be started, exits unsuccessfully,
+    /// or answers a different number of requests than it was sent
+    pub fn evaluate(&self, corpus: &Corpus) -> Result<Vec<std::result::Result<Value, String>>> {
+        let requests = corpus
+            .cases()
+            .iter()
+            .map(|case| {
+                let source = std::fs::read_to_string(&case.input)?;
+                Ok(json!({ "file": file_name(&case.input), "source": source }).to_string())
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let expected = requests.len();
+
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .map_err(|e| {
+                Error::invalid_input(format!(
+                    "Cannot start conformance runner '{}': {}",
+                    self.program, e
+                ))
+            })?;
+
+        // Feed requests from a separate thread so a runner that answers while
+        // we are still writing cannot deadlock on a full pipe
+        let mut stdin = child.stdin.take().expect("runner stdin is piped");
+        let writer = std::thread::spawn(move || -> std::io::Result<()> {
+            for request in requests {
+                writeln!(stdin, "{}", request)?;
+            }
+            Ok(())
+        });
+
+        let stdout = child.stdout.take().expect("runner stdout is piped");
+        let mut responses = Vec::with_capacity(expected);
+        for line in BufReader::new(stdout).lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                responses.push(parse_response(&line));
+            }
+        }
+
+        let written = writer
+            .join()
+            .map_err(|_| Error::internal("conformance request writer panicked"))?;
+        let status = child.wait()?;
+        if !status.success() {
+            return Err(Error::invalid_input(format!(
+                "Conformance runner '{}' exited with {}",
+                self.program, status
+            )));
+        }
+        written?;
+
+        if responses.len() != expected {
+            return Err(Error::invalid_input(format!(
+                "Conformance runner '{}' answered {} of {} requests",
+                self.program,
+                responses.len(),
+                expected
+            )));
+        }
+
+        Ok(responses)
+    }
+
+    /// Run a corpus through this runner and diff it against the native engine
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the runner fails (see [`Runner::evaluate`])
+    pub fn differential(&self, corpus: &Corpus, loader: &NickelLoader) -> Result<Report> {
+        let mut responses = self.evaluate(corpus)?.into_iter();
+        Ok(corpus.differential(loader, |_, _| {
+            responses
+                .next()
+                .unwrap_or_else(|| Err("runner gave no response".to_string()))
+        }))
+    }
+}
+
+/// Read a case's input, evaluate it and check the result
+fn run_case<F>(case: &Case, engine: F) -> CaseResult
+where
+    F: FnOnce(&str, &str) -> std::result::Result<Value, String>,
+{
+    let outcome = match std::fs::read_to_string(&case.input) {
+        Ok(source) => check(case, engine(&source, &file_name(&case.input))),
+        Err(e) => Outcome::Fail(format!("cannot read input: {}", e)),
+    };
+    CaseResult {
+        name: case.name.clone(),
+        outcome,
+    }
+}
+
+/// Decode one `{"ok": ...}` / `{"error": ...}` runner response
+fn parse_response(line: &str) -> std::result::Result<Value, String> {
+    let mut response: Value = serde_json::from_str(line)
+        .map_err(|e| format!("malformed runner response ({}): {}", e, line))?;
+
+    if let Some(value) = response.get_mut("ok") {
+        return Ok(value.take());
+    }
+    match response.get("error").and_then(Value::as_str) {
+        Some(message) => Err(message.to_string()),
+        None => Err(format!(
+            "runner response has neither ok nor error: {}",
+            line
+        )),
+    }
+}
+
+/// Compare an engine result against a case's expectation
+fn check(case: &Case, result: std::result::Result<Value, String>) -> Outcome {
+    match (&case.expected, result) {
+        (Expected::Missing, _) => Outcome::Missing,
+        (Expected::Output(expected), Ok(actual)) if *expected == actual => Outcome::Pass,
+        (Expected::Output(expected), Ok(actual)) => Outcome::Fail(format!(
+            "output mismatch\n  expected: {}\n  actual:   {}",
+            crate::json::to_string(expected, false),
+            crate::json::to_string(&actual, false)
+        )),
+        (Expected::Output(_), Err(message)) => {
+            Outcome::Fail(format!("expected success, got error: {}", message))
+        }
+        (Expected::Error(_), Ok(actual)) => Outcome::Fail(format!(
+            "expected an error, got: {}",
+            crate::json::to_string(&actual, false)
+        )),
+        (Expected::Error(fragments), Err(message)) => {
+            match fragments.iter().find(|f| !message.contains(f.as_str())) {
+                None => Outcome::Pass,
+                Some(fragment) => {
+                    Outcome::Fail(format!("error message lacks {:?}: {}", fragment, message))
+                }
+            }
+        }
+    }
+}
+
+/// Read the `.json` or `.error` expectation next to an input
+fn read_expected(input: &Path) -> Result<Expected> {
+    let json_path = input.with_extension("json");
+    if json_path.exists() {
+        let contents = std::fs::read_to_string(&json_path)?;
+        let value = serde_json::from_str(&contents).map_err(|e| {
+            Error::invalid_input(format!(
+                "Invalid expectation '{}': {}",
+                json_path.display(),
+                e
+            ))
+        })?;
+        return Ok(Expected::Output(value));
+    }
+
+    let error_path = input.with_extension("error");
+    if error_path.exists() {
+        let fragments = std::fs::read_to_string(&error_path)?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_string)
+            .collect();
+        return Ok(Expected::Error(fragments));
+    }
+
+    Ok(Expected::Missing)
+}
+
+/// The part of an error message before its details, e.g.
+/// `Failed to parse Nickel file 'bad.ncl'`
+fn headline(message: &str) -> String {
+    message
+        .split_once(": ")
+        .map_or(message, |(head, _)| head)
+        .to_string()
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("unknown.ncl")
+        .to_string()
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CORPUS_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/conformance");
+
+    #[test]
+    fn test_native_engine_passes_shipped_corpus() {
+        let corpus = Corpus::load(CORPUS_DIR).unwrap();
+        assert!(!corpus.cases().is_empty());
+
+        let report = corpus.run(&NickelLoader::new());
+        let failures: Vec<_> = report.failures().collect();
+        assert!(failures.is_empty(), "{:#?}", failures);
+    }
+
+    #[test]
+    fn test_run_with_detects_divergence() {
+        let corpus = Corpus::load(CORPUS_DIR).unwrap();
+        let report = corpus.run_with(|_, _| Ok(Value::Null));
+        assert!(!report.all_passed());
+    }
+
+    #[test]
+    fn test_update_expected_writes_files() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("ok.ncl"), "{ a = 1 + 1 }").unwrap();
+        std::fs::write(dir.path().join("bad.ncl"), "{ a = }").unwrap();
+
+        let mut corpus = Corpus::load(dir.path()).unwrap();
+        assert!(corpus
+            .cases()
+            .iter()
+            .all(|c| c.expected == Expected::Missing));
+
+        let loader = NickelLoader::new();
+        assert_eq!(corpus.update_expected(&loader).unwrap(), 2);
+        assert!(dir.path().join("ok.json").exists());
+        assert!(dir.path().join("bad.error").exists());
+
+        let reloaded = Corpus::load(dir.path()).unwrap();
+        assert!(reloaded.run(&loader).all_passed());
+    }
+
+    #[test]
+    fn test_differential_matches_native_engine() {
+        let corpus = Corpus::load(CORPUS_DIR).unwrap();
+        let loader = NickelLoader::new();
+
+        let identical = corpus.differential(&loader, |source, name| {
+            loader
+                .parse_string(source, name)
+                .map_err(|_| "reworded error".to_string())
+        });
+        assert!(identical.all_passed());
+
+        let broken = corpus.differential(&loader, |_, _| Ok(Value::Bool(true)));
+        assert!(!broken.all_passed());
+    }
+
+    #[test]
+    fn test_parse_response() {
+        assert_eq!(parse_response(r#"{"ok": {"a": 1}}"#), Ok(json!({ "a": 1 })));
+        assert_eq!(parse_response(r#"{"ok": null}"#), Ok(Value::Null));
+        assert_eq!(
+            parse_response(r#"{"error": "boom"}"#),
+            Err("boom".to_string())
+        );
+        assert!(parse_response("not json").is_err());
+        assert!(parse_response("{}").is_err());
+    }
+
+    #[test]
+    fn test_binding_from_str() {
+        assert_eq!("deno".parse::<Binding>(), Ok(Binding::Deno));
+        assert_eq!("wasm".parse::<Binding>(), Ok(Binding::Wasm));
+        assert!("python".parse::<Binding>().is_err());
+        assert!(Runner::for_binding(Binding::C).is_err());
+        assert!(Runner::for_binding(Binding::Deno).is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_runner_subprocess_protocol() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("one.ncl"), "{ a = 1 }").unwrap();
+        std::fs::write(dir.path().join("two.ncl"), "{ a = }").unwrap();
+        let corpus = Corpus::load(dir.path()).unwrap();
+
+        // A shell runner that ignores its input and answers each request
+        let script = r#"while read -r line; do echo '{"ok": {"a": 1}}'; done"#;
+        let runner = Runner::new(vec!["sh".into(), "-c".into(), script.into()]).unwrap();
+
+        let report = runner.differential(&corpus, &NickelLoader::new()).unwrap();
+        assert_eq!(report.passed(), 1);
+        assert_eq!(report.results[1].name, "two");
+        assert!(matches!(report.results[1].outcome, Outcome::Fail(_)));
+    }
+
+    #[test]
+    fn test_headline() {
+        assert_eq!(
+            headline("Failed to parse Nickel file 'x.ncl': unexpected token"),
+            "Failed to parse Nickel file 'x.ncl'"
+        );
+        assert_eq!(headline("no details"), "no details");
+    }
+
+    #[test]
+    fn test_manifest_lists_cases() {
+        let corpus = Corpus::load(CORPUS_DIR).unwrap();
+        let manifest = corpus.manifest();
+        assert_eq!(manifest["version"], 1);
+        assert_eq!(
+            manifest["cases"].as_array().unwrap().len(),
+            corpus.cases().len()
+        );
+    }
+}
@@ -0,0 +1,283 @@
+//! Contract coverage of a set of configs
+//!
+//! Measures how much of a contract a test corpus exercises: which enum
+//! variants were produced, whether optional fields were both set and left
+//! out, whether booleans took both values and arrays were both empty and
+//! non-empty. `bunsenite conformance --coverage contract.ncl` reports it for
+//! the outputs of a corpus's passing cases, so a suite that only ever tests
+//! `env = 'prod` shows up as not covering `'dev`.
+//!
+//! Contracts are read with [`Contract::parse`], so the same subset
+//! `bunsenite synth` supports is understood. Values not matching the
+//! contract's structure are skipped rather than counted.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::coverage::Coverage;
+//! use bunsenite::synth::Contract;
+//! use serde_json::json;
+//!
+//! let contract = Contract::parse("{ env | std.enum.TagOrString | [| 'dev, 'prod |] }").unwrap();
+//! let mut coverage = Coverage::new(&contract);
+//! coverage.observe(&json!({ "env": "prod" }));
+//!
+//! let gaps: Vec<String> = coverage.gaps().map(|point| point.to_string()).collect();
+//! assert_eq!(gaps, ["env: variant 'dev never produced"]);
+//! ```
+
+use crate::json;
+use crate::synth::Contract;
+use serde_json::Value;
+use std::fmt;
+
+/// One thing a corpus should exercise
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Point {
+    /// Path of the value in the config, e.g. `services[].port`
+    pub path: String,
+    /// What has to happen there
+    pub case: Case,
+    /// How many observed configs exercised it
+    pub hits: usize,
+}
+
+/// What a [`Point`] requires
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Case {
+    /// An optional field is set
+    Set,
+    /// An optional field is left out
+    Unset,
+    /// A boolean is true
+    True,
+    /// A boolean is false
+    False,
+    /// An enum takes this variant
+    Variant(String),
+    /// An array is empty
+    Empty,
+    /// An array has elements
+    NonEmpty,
+}
+
+impl fmt::Display for Point {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = if self.path.is_empty() {
+            "(root)"
+        } else {
+            self.path.as_str()
+        };
+        match &self.case {
+            Case::Set => write!(f, "{}: optional field never set", path),
+            Case::Unset => write!(f, "{}: optional field never left out", path),
+            Case::True => write!(f, "{}: never true", path),
+            Case::False => write!(f, "{}: never false", path),
+            Case::Variant(tag) => write!(f, "{}: variant '{} never produced", path, tag),
+            Case::Empty => write!(f, "{}: never empty", path),
+            Case::NonEmpty => write!(f, "{}: never has elements", path),
+        }
+    }
+}
+
+/// Coverage of one contract by the configs observed so far
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Coverage {
+    contract: Contract,
+    points: Vec<Point>,
+    configs: usize,
+}
+
+impl Coverage {
+    /// Start measuring coverage of `contract`
+    pub fn new(contract: &Contract) -> Self {
+        let mut points = Vec::new();
+        collect(contract, "", &mut points);
+        Self {
+            contract: contract.clone(),
+            points,
+            configs: 0,
+        }
+    }
+
+    /// Count what `config` exercises
+    pub fn observe(&mut self, config: &Value) {
+        let mut next = 0;
+        let mut hits = vec![false; self.points.len()];
+        visit(&self.contract, Some(config), &mut next, &mut hits);
+        for (point, hit) in self.points.iter_mut().zip(hits) {
+            point.hits += usize::from(hit);
+        }
+        self.configs += 1;
+    }
+
+    /// Every point, in contract order
+    pub fn points(&self) -> &[Point] {
+        &self.points
+    }
+
+    /// Points no observed config exercised
+    pub fn gaps(&self) -> impl Iterator<Item = &Point> {
+        self.points.iter().filter(|point| point.hits == 0)
+    }
+
+    /// Number of configs observed
+    pub fn configs(&self) -> usize {
+        self.configs
+    }
+
+    /// Fraction of points exercised, from 0 to 1 (1 if there are none)
+    pub fn ratio(&self) -> f64 {
+        if self.points.is_empty() {
+            return 1.0;
+        }
+        let covered = self.points.len() - self.gaps().count();
+        covered as f64 / self.points.len() as f64
+    }
+}
+
+impl fmt::Display for Coverage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Contract coverage: {}/{} ({:.0}%) over {} config(s)",
+            self.points.len() - self.gaps().count(),
+            self.points.len(),
+            self.ratio() * 100.0,
+            self.configs
+        )?;
+        for gap in self.gaps() {
+            writeln!(f, "  {}", gap)?;
+        }
+        Ok(())
+    }
+}
+
+fn point(path: &str, case: Case) -> Point {
+    Point {
+        path: path.to_string(),
+        case,
+        hits: 0,
+    }
+}
+
+/// List the points of `contract`, in the order [`visit`] numbers them
+fn collect(contract: &Contract, path: &str, points: &mut Vec<Point>) {
+    match contract {
+        Contract::Bool => {
+            points.push(point(path, Case::True));
+            points.push(point(path, Case::False));
+        }
+        Contract::Enum(tags) => {
+            for tag in tags {
+                points.push(point(path, Case::Variant(tag.clone())));
+            }
+        }
+        Contract::Array(element) => {
+            points.push(point(path, Case::Empty));
+            points.push(point(path, Case::NonEmpty));
+            collect(element, &format!("{}[]", path), points);
+        }
+        Contract::Record(fields) => {
+            for field in fields {
+                let path = json::key_path(path, &field.name);
+                if field.optional {
+                    points.push(point(&path, Case::Set));
+                    points.push(point(&path, Case::Unset));
+                }
+                collect(&field.contract, &path, points);
+            }
+        }
+        Contract::Dyn | Contract::Number | Contract::Integer | Contract::String => {}
+    }
+}
+
+fn mark(hits: &mut [bool], next: &mut usize, exercised: bool) {
+    hits[*next] |= exercised;
+    *next += 1;
+}
+
+/// Mark the points `value` exercises, numbering them as [`collect`] does
+///
+/// `value` is `None` where the config has nothing, so the points below it
+/// still get numbered.
+fn visit(contract: &Contract, value: Option<&Value>, next: &mut usize, hits: &mut [bool]) {
+    match contract {
+        Contract::Bool => {
+            let value = value.and_then(Value::as_bool);
+            mark(hits, next, value == Some(true));
+            mark(hits, next, value == Some(false));
+        }
+        Contract::Enum(tags) => {
+            let value = value.and_then(Value::as_str);
+            for tag in tags {
+                mark(hits, next, value == Some(tag.as_str()));
+            }
+        }
+        Contract::Array(element) => {
+            let elements = value.and_then(Value::as_array);
+            mark(hits, next, elements.is_some_and(Vec::is_empty));
+            mark(hits, next, elements.is_some_and(|e| !e.is_empty()));
+            let start = *next;
+            match elements.filter(|e| !e.is_empty()) {
+                Some(elements) => {
+                    for element_value in elements {
+                        *next = start;
+                        visit(element, Some(element_value), next, hits);
+                    }
+                }
+                None => visit(element, None, next, hits),
+            }
+        }
+        Contract::Record(fields) => {
+            let map = value.and_then(Value::as_object);
+            for field in fields {
+                let field_value = map.and_then(|map| map.get(&field.name));
+                if field.optional {
+                    mark(hits, next, field_value.is_some());
+                    mark(hits, next, map.is_some() && field_value.is_none());
+                }
+                visit(&field.contract, field_value, next, hits);
+            }
+        }
+        Contract::Dyn | Contract::Number | Contract::Integer | Contract::String => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_coverage_gaps() {
+        let contract = Contract::parse(
+            r#"{
+              debug | Bool,
+              hosts | Array { env | std.enum.TagOrString | [| 'dev, 'prod |] },
+              tls | { cert | String } | optional,
+            }"#,
+        )
+        .unwrap();
+        let mut coverage = Coverage::new(&contract);
+        assert_eq!(coverage.points().len(), 8);
+        assert_eq!(coverage.ratio(), 0.0);
+
+        coverage.observe(&json!({ "debug": true, "hosts": [{ "env": "prod" }] }));
+        coverage.observe(&json!({ "debug": true, "hosts": [], "tls": { "cert": "x" } }));
+
+        let gaps: Vec<String> = coverage.gaps().map(Point::to_string).collect();
+        assert_eq!(
+            gaps,
+            [
+                "debug: never false",
+                "hosts[].env: variant 'dev never produced"
+            ]
+        );
+        assert_eq!(coverage.configs(), 2);
+        assert_eq!(coverage.ratio(), 0.75);
+        assert!(coverage
+            .to_string()
+            .starts_with("Contract coverage: 6/8 (75%)"));
+    }
+}
@@ -0,0 +1,1013 @@
+//! CUE definitions as output schemas
+//!
+//! Reads the subset of CUE that teams use to publish the shape of a config,
+//! for [`validation`](crate::validation): definitions (`#Config: { ... }`)
+//! and plain top-level fields, built from
+//!
+//! - the types `_`, `null`, `bool`, `int`, `float`, `number`, `string`,
+//!   `bytes`, and the sized integers `uint`, `int8` to `uint64`
+//! - literals, bounds (`>=1`, `<65536`, `!=""`) and their conjunctions
+//!   (`int & >0`) and disjunctions (`"dev" | *"prod"`)
+//! - structs with regular, optional (`name?:`) and required (`name!:`)
+//!   fields, pattern constraints (`[string]: int`), embeddings (`{ #Base,
+//!   extra: int }`) and `...` to leave them open
+//! - lists, `[...T]`, `[A, B]` and `[A, ...T]`
+//! - references to other top-level definitions, fields and `let`s
+//!
+//! Imports, function calls (`strings.MinRunes(1)`) and comprehensions are
+//! rejected. Regular-expression constraints (`=~`, `!~`) are accepted but
+//! not checked.
+//!
+//! As with `cue vet -c`, output must be complete: a regular field without a
+//! default must be present, as must a required one. Structs declared in a
+//! definition are closed, so fields they do not declare are reported.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::cue::Definitions;
+//! use serde_json::json;
+//!
+//! let definitions = Definitions::parse(r#"
+//!     #Config: {
+//!         env: "dev" | "prod"
+//!         port: int & >0 & <65536 | *8080
+//!     }
+//! "#).unwrap();
+//! let violations = definitions.check("#Config", &json!({ "env": "qa", "debug": true }));
+//! let violations: Vec<String> = violations.iter().map(|v| v.to_string()).collect();
+//! assert_eq!(
+//!     violations,
+//!     vec!["debug: field is not allowed", r#"env: expected "dev" | "prod", found "qa""#]
+//! );
+//! ```
+
+use crate::error::{Error, Result};
+use crate::json;
+use crate::source;
+use crate::validation::{self, Violation};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// References followed without descending into the value before a cycle is
+/// assumed
+const MAX_REF_HOPS: usize = 32;
+
+/// The top-level definitions, fields and `let`s of a CUE file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Definitions {
+    bindings: BTreeMap<String, Expr>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Top,
+    Null,
+    Bool,
+    Int,
+    Float,
+    Number,
+    String,
+    Bytes,
+    Literal(Value),
+    Bound(&'static str, Value),
+    /// A sized integer type such as `uint8`
+    Sized(&'static str),
+    /// A regular-expression constraint, which is not checked
+    Regex(&'static str, String),
+    Struct(Struct),
+    List {
+        prefix: Vec<Expr>,
+        rest: Option<Box<Expr>>,
+    },
+    Ref(String),
+    And(Vec<Expr>),
+    Or {
+        alternatives: Vec<Expr>,
+        default: bool,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+struct Struct {
+    fields: Vec<Field>,
+    patterns: Vec<(Expr, Expr)>,
+    embeds: Vec<Expr>,
+    open: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Field {
+    name: String,
+    presence: Presence,
+    value: Expr,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Presence {
+    /// `name: T`: present unless `T` has a default
+    Regular,
+    /// `name?: T`
+    Optional,
+    /// `name!: T`
+    Required,
+}
+
+impl Definitions {
+    /// Read the top-level bindings of a CUE file
+    ///
+    /// # Errors
+    ///
+    /// Returns an invalid-input error for malformed sources, constructs
+    /// outside the supported subset, and references to unknown names
+    pub fn parse(source: &str) -> Result<Self> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            pos: 0,
+        };
+        let bindings = parser.file()?;
+        let definitions = Self { bindings };
+        for expr in definitions.bindings.values() {
+            definitions.check_refs(expr)?;
+        }
+        Ok(definitions)
+    }
+
+    /// Names of the definitions, with their `#`
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.bindings
+            .keys()
+            .map(String::as_str)
+            .filter(|name| name.starts_with('#'))
+    }
+
+    /// The binding `name` refers to, `#Name` or `Name`, or the only
+    /// definition if `name` is `None`
+    ///
+    /// # Errors
+    ///
+    /// Returns an invalid-input error if there is no such binding, or no
+    /// name is given and there is not exactly one definition
+    pub fn resolve(&self, name: Option<&str>) -> Result<String> {
+        let listed = || self.names().collect::<Vec<_>>().join(", ");
+        match name {
+            Some(name) => {
+                let name = name.trim_start_matches('#');
+                [format!("#{}", name), name.to_string()]
+                    .into_iter()
+                    .find(|candidate| self.bindings.contains_key(candidate))
+                    .ok_or_else(|| {
+                        Error::invalid_input(format!(
+                            "No CUE definition named '{}' (definitions: {})",
+                            name,
+                            listed()
+                        ))
+                    })
+            }
+            None => {
+                let mut names = self.names();
+                match (names.next(), names.next()) {
+                    (Some(name), None) => Ok(name.to_string()),
+                    (None, _) => Err(Error::invalid_input("The CUE file has no definitions")),
+                    _ => Err(Error::invalid_input(format!(
+                        "The CUE file has several definitions; pick one with FILE#NAME ({})",
+                        listed()
+                    ))),
+                }
+            }
+        }
+    }
+
+    /// The ways `value` fails to match the binding `name` (as returned by
+    /// [`resolve`](Self::resolve)); an unknown name matches nothing
+    pub fn check(&self, name: &str, value: &Value) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        match self.bindings.get(name) {
+            Some(expr) => self.check_expr(expr, value, "", 0, &mut violations),
+            None => violations.push(Violation::new("", format!("unknown definition {}", name))),
+        }
+        violations.sort_by(|a, b| a.path.cmp(&b.path));
+        violations
+    }
+
+    fn check_refs(&self, expr: &Expr) -> Result<()> {
+        match expr {
+            Expr::Ref(name) if !self.bindings.contains_key(name) => Err(Error::invalid_input(
+                format!("Unknown reference '{}' in CUE schema", name),
+            )),
+            Expr::Struct(body) => {
+                for field in &body.fields {
+                    self.check_refs(&field.value)?;
+                }
+                for (label, value) in &body.patterns {
+                    self.check_refs(label)?;
+                    self.check_refs(value)?;
+                }
+                body.embeds
+                    .iter()
+                    .try_for_each(|expr| self.check_refs(expr))
+            }
+            Expr::List { prefix, rest } => prefix
+                .iter()
+                .chain(rest.as_deref())
+                .try_for_each(|expr| self.check_refs(expr)),
+            Expr::And(exprs)
+            | Expr::Or {
+                alternatives: exprs,
+                ..
+            } => exprs.iter().try_for_each(|expr| self.check_refs(expr)),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_expr(
+        &self,
+        expr: &Expr,
+        value: &Value,
+        path: &str,
+        hops: usize,
+        out: &mut Vec<Violation>,
+    ) {
+        let mismatch =
+            || Violation::new(path, format!("expected {}, found {}", expr, shown(value)));
+        match expr {
+            Expr::Top | Expr::Regex(..) => {}
+            Expr::Null if value.is_null() => {}
+            Expr::Bool if value.is_boolean() => {}
+            Expr::Int if integer(value) => {}
+            Expr::Float | Expr::Number if value.is_number() => {}
+            Expr::String | Expr::Bytes if value.is_string() => {}
+            Expr::Literal(expected) if validation::same(expected, value) => {}
+            Expr::Bound(op, bound) if within(op, bound, value) => {}
+            Expr::Sized(name) if sized(name, value) => {}
+            Expr::Struct(body) => match value {
+                Value::Object(fields) => self.check_struct(body, fields, path, hops, out),
+                _ => out.push(mismatch()),
+            },
+            Expr::List { prefix, rest } => match value {
+                Value::Array(items) => {
+                    if rest.is_none() && items.len() != prefix.len() {
+                        out.push(Violation::new(
+                            path,
+                            format!("expected {} item(s), found {}", prefix.len(), items.len()),
+                        ));
+                    } else if items.len() < prefix.len() {
+                        out.push(Violation::new(
+                            path,
+                            format!(
+                                "expected at least {} item(s), found {}",
+                                prefix.len(),
+                                items.len()
+                            ),
+                        ));
+                    }
+                    for (index, item) in items.iter().enumerate() {
+                        if let Some(expr) = prefix.get(index).or(rest.as_deref()) {
+                            let item_path = json::index_path(path, index);
+                            self.check_expr(expr, item, &item_path, 0, out);
+                        }
+                    }
+                }
+                _ => out.push(mismatch()),
+            },
+            Expr::Ref(name) => match self.bindings.get(name) {
+                _ if hops >= MAX_REF_HOPS => {
+                    out.push(Violation::new(path, format!("reference cycle at {}", name)))
+                }
+                Some(target) => self.check_expr(target, value, path, hops + 1, out),
+                None => out.push(mismatch()),
+            },
+            Expr::And(exprs) => {
+                let before = out.len();
+                for expr in exprs {
+                    // One type mismatch is enough; later bounds would repeat it
+                    if out.len() > before && !matches!(expr, Expr::Struct(_) | Expr::Ref(_)) {
+                        break;
+                    }
+                    self.check_expr(expr, value, path, hops, out);
+                }
+            }
+            Expr::Or { alternatives, .. } => {
+                let matched = alternatives.iter().any(|expr| {
+                    let mut violations = Vec::new();
+                    self.check_expr(expr, value, path, hops, &mut violations);
+                    violations.is_empty()
+                });
+                if !matched {
+                    out.push(mismatch());
+                }
+            }
+            _ => out.push(mismatch()),
+        }
+    }
+
+    fn check_struct(
+        &self,
+        body: &Struct,
+        fields: &Map<String, Value>,
+        path: &str,
+        hops: usize,
+        out: &mut Vec<Violation>,
+    ) {
+        let mut flat = Struct {
+            open: body.open,
+            ..Struct::default()
+        };
+        let mut conjuncts = Vec::new();
+        self.flatten(body, &mut flat, &mut conjuncts, hops);
+        for expr in conjuncts {
+            self.check_expr(expr, &Value::Object(fields.clone()), path, hops, out);
+        }
+
+        for field in &flat.fields {
+            let field_path = json::key_path(path, &field.name);
+            match fields.get(&field.name) {
+                Some(value) => self.check_expr(&field.value, value, &field_path, 0, out),
+                None if field.presence == Presence::Optional => {}
+                None if field.presence == Presence::Regular && self.complete(&field.value, 0) => {}
+                None => out.push(Violation::new(field_path, "required field is missing")),
+            }
+        }
+
+        for (name, value) in fields {
+            let field_path = json::key_path(path, name);
+            let label = Value::String(name.clone());
+            let mut allowed = flat.open || flat.fields.iter().any(|field| &field.name == name);
+            for (pattern, constraint) in &flat.patterns {
+                let mut violations = Vec::new();
+                self.check_expr(pattern, &label, &field_path, 0, &mut violations);
+                if violations.is_empty() {
+                    allowed = true;
+                    self.check_expr(constraint, value, &field_path, 0, out);
+                }
+            }
+            if !allowed {
+                out.push(Violation::new(field_path, "field is not allowed"));
+            }
+        }
+    }
+
+    /// Collect the fields and patterns of `body` and the structs it embeds
+    /// into `flat`, and embedded non-structs into `conjuncts`
+    fn flatten<'a>(
+        &'a self,
+        body: &'a Struct,
+        flat: &mut Struct,
+        conjuncts: &mut Vec<&'a Expr>,
+        hops: usize,
+    ) {
+        flat.fields.extend(body.fields.iter().cloned());
+        flat.patterns.extend(body.patterns.iter().cloned());
+        for embed in &body.embeds {
+            match self.deref(embed, hops) {
+                Some(Expr::Struct(embedded)) => {
+                    flat.open &= embedded.open;
+                    self.flatten(embedded, flat, conjuncts, hops + 1);
+                }
+                _ => conjuncts.push(embed),
+            }
+        }
+    }
+
+    /// `expr`, with references followed
+    fn deref<'a>(&'a self, expr: &'a Expr, hops: usize) -> Option<&'a Expr> {
+        match expr {
+            _ if hops >= MAX_REF_HOPS => None,
+            Expr::Ref(name) => self.deref(self.bindings.get(name)?, hops + 1),
+            expr => Some(expr),
+        }
+    }
+
+    /// Whether a missing field constrained by `expr` still has a complete
+    /// value: a literal, a default, an empty list or a struct of such fields
+    fn complete(&self, expr: &Expr, hops: usize) -> bool {
+        match self.deref(expr, hops) {
+            Some(Expr::Literal(_) | Expr::Null) => true,
+            Some(Expr::Or { default, .. }) => *default,
+            Some(Expr::List { prefix, .. }) => prefix.is_empty(),
+            Some(Expr::Struct(body)) => body.fields.iter().all(|field| match field.presence {
+                Presence::Optional => true,
+                Presence::Regular => self.complete(&field.value, hops + 1),
+                Presence::Required => false,
+            }),
+            Some(Expr::And(exprs)) => exprs.iter().any(|expr| self.complete(expr, hops + 1)),
+            _ => false,
+        }
+    }
+}
+
+/// Whether `value` is a CUE `int`
+fn integer(value: &Value) -> bool {
+    validation::type_name(value) == "integer"
+        || value.as_f64().is_some_and(|number| number.fract() == 0.0)
+}
+
+/// Whether `value` satisfies the bound `op bound`
+fn within(op: &str, bound: &Value, value: &Value) -> bool {
+    if op == "!=" {
+        return !validation::same(bound, value);
+    }
+    let ordering = match (bound, value) {
+        (Value::Number(bound), Value::Number(value)) => value
+            .as_f64()
+            .and_then(|value| value.partial_cmp(&bound.as_f64()?)),
+        (Value::String(bound), Value::String(value)) => Some(value.as_str().cmp(bound.as_str())),
+        _ => None,
+    };
+    ordering.is_some_and(|ordering| match op {
+        "<" => ordering.is_lt(),
+        "<=" => ordering.is_le(),
+        ">" => ordering.is_gt(),
+        ">=" => ordering.is_ge(),
+        _ => false,
+    })
+}
+
+/// `value` in a message: scalars as JSON, collections by kind
+fn shown(value: &Value) -> String {
+    match value {
+        Value::Array(_) => "a list".to_string(),
+        Value::Object(_) => "a struct".to_string(),
+        scalar => json::to_string(scalar, false),
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let join = |f: &mut fmt::Formatter<'_>, exprs: &[Expr], separator: &str| -> fmt::Result {
+            for (index, expr) in exprs.iter().enumerate() {
+                if index > 0 {
+                    f.write_str(separator)?;
+                }
+                write!(f, "{}", expr)?;
+            }
+            Ok(())
+        };
+        match self {
+            Expr::Top => f.write_str("_"),
+            Expr::Null => f.write_str("null"),
+            Expr::Bool => f.write_str("bool"),
+            Expr::Int => f.write_str("int"),
+            Expr::Float => f.write_str("float"),
+            Expr::Number => f.write_str("number"),
+            Expr::String => f.write_str("string"),
+            Expr::Bytes => f.write_str("bytes"),
+            Expr::Literal(value) => f.write_str(&json::to_string(value, false)),
+            Expr::Bound(op, value) => write!(f, "{}{}", op, json::to_string(value, false)),
+            Expr::Sized(name) => f.write_str(name),
+            Expr::Regex(op, pattern) => write!(f, "{}{}", op, source::string_literal(pattern)),
+            Expr::Struct(_) => f.write_str("a struct"),
+            Expr::List { .. } => f.write_str("a list"),
+            Expr::Ref(name) => f.write_str(name),
+            Expr::And(exprs) => join(f, exprs, " & "),
+            Expr::Or { alternatives, .. } => join(f, alternatives, " | "),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Number(Value),
+    Punct(&'static str),
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>> {
+    const PUNCTS: [&str; 25] = [
+        "...", ">=", "<=", "!=", "=~", "!~", "==", "{", "}", "[", "]", "(", ")", ":", ",", "?",
+        "!", "|", "&", "*", ">", "<", "=", ".", "-",
+    ];
+
+    let mut tokens = Vec::new();
+    let mut rest = source;
+    loop {
+        rest = rest.trim_start();
+        if let Some(comment) = rest.strip_prefix("//") {
+            rest = comment.split_once('\n').map_or("", |(_, after)| after);
+            continue;
+        }
+        if let Some(attribute) = rest.strip_prefix('@') {
+            // Attributes such as @go(Name) carry no constraint
+            rest = attribute.split_once(')').map_or("", |(_, after)| after);
+            continue;
+        }
+        let Some(c) = rest.chars().next() else { break };
+
+        if rest.starts_with("\"\"\"") || rest.starts_with("#\"") {
+            return Err(Error::invalid_input(
+                "Multi-line and raw strings are not supported in CUE schemas",
+            ));
+        } else if c == '"' {
+            let (value, after) = string(&rest[1..])?;
+            tokens.push(Token::Str(value));
+            rest = after;
+        } else if c.is_ascii_digit() {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '.' || c == '_'))
+                .unwrap_or(rest.len());
+            let number = serde_json::from_str(&rest[..end]).map_err(|_| {
+                Error::invalid_input(format!(
+                    "Unsupported number '{}' in CUE schema",
+                    &rest[..end]
+                ))
+            })?;
+            tokens.push(Token::Number(number));
+            rest = &rest[end..];
+        } else if c.is_ascii_alphabetic() || matches!(c, '_' | '#' | '$') {
+            let end = rest[1..]
+                .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '_' | '$')))
+                .map_or(rest.len(), |end| end + 1);
+            tokens.push(Token::Ident(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else if let Some(punct) = PUNCTS.iter().find(|p| rest.starts_with(**p)) {
+            tokens.push(Token::Punct(punct));
+            rest = &rest[punct.len()..];
+        } else {
+            return Err(Error::invalid_input(format!(
+                "Unsupported character '{}' in CUE schema",
+                c
+            )));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Read a string literal after its opening quote
+fn string(source: &str) -> Result<(String, &str)> {
+    let mut value = String::new();
+    let mut chars = source.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((value, &source[i + 1..])),
+            '\\' => match chars.next() {
+                Some((_, 'n')) => value.push('\n'),
+                Some((_, 't')) => value.push('\t'),
+                Some((_, 'r')) => value.push('\r'),
+                Some((_, '(')) => {
+                    return Err(Error::invalid_input(
+                        "String interpolation is not supported in CUE schemas",
+                    ))
+                }
+                Some((_, escaped)) => value.push(escaped),
+                None => break,
+            },
+            c => value.push(c),
+        }
+    }
+    Err(Error::invalid_input("Unterminated string in CUE schema"))
+}
+
+fn unexpected(token: Option<&Token>) -> Error {
+    let shown = match token {
+        Some(Token::Ident(name)) => name.clone(),
+        Some(Token::Str(value)) => source::string_literal(value),
+        Some(Token::Number(number)) => number.to_string(),
+        Some(Token::Punct(punct)) => punct.to_string(),
+        None => return Error::invalid_input("CUE schema ends where a value was expected"),
+    };
+    Error::invalid_input(format!("Unsupported CUE syntax at '{}'", shown))
+}
+
+/// The sized integer types and their ranges
+const SIZED: [(&str, i128, i128); 9] = [
+    ("uint", 0, i128::MAX),
+    ("int8", i8::MIN as i128, i8::MAX as i128),
+    ("int16", i16::MIN as i128, i16::MAX as i128),
+    ("int32", i32::MIN as i128, i32::MAX as i128),
+    ("int64", i64::MIN as i128, i64::MAX as i128),
+    ("uint8", 0, u8::MAX as i128),
+    ("uint16", 0, u16::MAX as i128),
+    ("uint32", 0, u32::MAX as i128),
+    ("uint64", 0, u64::MAX as i128),
+];
+
+/// Whether `value` is an integer in the range of the sized type `name`
+fn sized(name: &str, value: &Value) -> bool {
+    let integer = value
+        .as_i64()
+        .map(i128::from)
+        .or_else(|| value.as_u64().map(i128::from))
+        .or_else(|| {
+            value
+                .as_f64()
+                .filter(|n| n.fract() == 0.0)
+                .map(|n| n as i128)
+        });
+    SIZED
+        .iter()
+        .find(|(sized, ..)| *sized == name)
+        .zip(integer)
+        .is_some_and(|((_, min, max), integer)| (*min..=*max).contains(&integer))
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<&Token> {
+        self.tokens.get(self.pos + offset)
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, punct: &'static str) -> bool {
+        if self.peek() == Some(&Token::Punct(punct)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, punct: &'static str) -> Result<()> {
+        if self.eat(punct) {
+            Ok(())
+        } else {
+            Err(unexpected(self.peek()))
+        }
+    }
+
+    /// Whether a field label (with an optional `?` or `!`) and `:` come next
+    fn at_field(&self) -> bool {
+        let colon = match self.peek_at(1) {
+            Some(Token::Punct("?" | "!")) => self.peek_at(2),
+            next => next,
+        };
+        matches!(self.peek(), Some(Token::Ident(_) | Token::Str(_)))
+            && colon == Some(&Token::Punct(":"))
+    }
+
+    /// Whether a pattern constraint, `[label]:`, comes next
+    fn at_pattern(&self) -> bool {
+        let mut depth = 0;
+        for (offset, token) in self.tokens[self.pos..].iter().enumerate() {
+            match token {
+                Token::Punct("[") => depth += 1,
+                Token::Punct("]") if depth > 1 => depth -= 1,
+                Token::Punct("]") if depth == 1 => {
+                    return self.peek_at(offset + 1) == Some(&Token::Punct(":"))
+                }
+                _ if depth == 0 => return false,
+                _ => {}
+            }
+        }
+        false
+    }
+
+    fn file(&mut self) -> Result<BTreeMap<String, Expr>> {
+        if self.peek() == Some(&Token::Ident("package".to_string())) {
+            self.pos += 2;
+        }
+        let mut bindings = BTreeMap::new();
+        while let Some(token) = self.peek() {
+            match token {
+                Token::Ident(word) if word == "import" => {
+                    return Err(Error::invalid_input(
+                        "Imports are not supported in CUE schemas",
+                    ))
+                }
+                Token::Ident(word) if word == "let" => {
+                    self.pos += 1;
+                    let Some(Token::Ident(name)) = self.advance() else {
+                        return Err(unexpected(self.tokens.get(self.pos - 1)));
+                    };
+                    self.expect("=")?;
+                    let value = self.expr(false)?;
+                    bindings.insert(name, value);
+                }
+                _ if self.at_field() => {
+                    let field = self.field(false)?;
+                    let value = match bindings.remove(&field.name) {
+                        Some(Expr::And(mut exprs)) => {
+                            exprs.push(field.value);
+                            Expr::And(exprs)
+                        }
+                        Some(earlier) => Expr::And(vec![earlier, field.value]),
+                        None => field.value,
+                    };
+                    bindings.insert(field.name, value);
+                }
+                _ => return Err(unexpected(self.peek())),
+            }
+            self.eat(",");
+        }
+        Ok(bindings)
+    }
+
+    /// `label: value`, closing the structs in `value` if the field is a
+    /// definition or `closed` is set
+    fn field(&mut self, closed: bool) -> Result<Field> {
+        let name = match self.advance() {
+            Some(Token::Ident(name) | Token::Str(name)) => name,
+            token => return Err(unexpected(token.as_ref())),
+        };
+        let presence = if self.eat("?") {
+            Presence::Optional
+        } else if self.eat("!") {
+            Presence::Required
+        } else {
+            Presence::Regular
+        };
+        self.expect(":")?;
+
+        let closed = closed || name.starts_with('#');
+        let value = if self.at_field() {
+            // `a: b: T` is shorthand for `a: { b: T }`
+            Expr::Struct(Struct {
+                fields: vec![self.field(closed)?],
+                open: !closed,
+                ..Struct::default()
+            })
+        } else if self.at_pattern() {
+            // And `a: [string]: T` for `a: { [string]: T }`
+            self.pos += 1;
+            Expr::Struct(Struct {
+                patterns: vec![self.pattern(closed)?],
+                open: !closed,
+                ..Struct::default()
+            })
+        } else {
+            self.expr(closed)?
+        };
+        Ok(Field {
+            name,
+            presence,
+            value,
+        })
+    }
+
+    /// Alternatives joined with `|`, the default marked `*`
+    fn expr(&mut self, closed: bool) -> Result<Expr> {
+        let mut alternatives = Vec::new();
+        let mut default = false;
+        loop {
+            default |= self.eat("*");
+            alternatives.push(self.conjunction(closed)?);
+            if !self.eat("|") {
+                break;
+            }
+        }
+        Ok(if alternatives.len() == 1 && !default {
+            alternatives.remove(0)
+        } else {
+            Expr::Or {
+                alternatives,
+                default,
+            }
+        })
+    }
+
+    /// Constraints joined with `&`
+    fn conjunction(&mut self, closed: bool) -> Result<Expr> {
+        let mut exprs = vec![self.unary(closed)?];
+        while self.eat("&") {
+            exprs.push(self.unary(closed)?);
+        }
+        Ok(if exprs.len() == 1 {
+            exprs.remove(0)
+        } else {
+            Expr::And(exprs)
+        })
+    }
+
+    fn unary(&mut self, closed: bool) -> Result<Expr> {
+        for op in ["<=", ">=", "<", ">", "!="] {
+            if self.eat(op) {
+                return match self.unary(closed)? {
+                    Expr::Literal(bound) => Ok(Expr::Bound(op, bound)),
+                    _ => Err(Error::invalid_input(format!(
+                        "Only literals can follow '{}' in CUE schemas",
+                        op
+                    ))),
+                };
+            }
+        }
+        for op in ["=~", "!~"] {
+            if self.eat(op) {
+                return match self.advance() {
+                    Some(Token::Str(pattern)) => Ok(Expr::Regex(op, pattern)),
+                    token => Err(unexpected(token.as_ref())),
+                };
+            }
+        }
+        if self.eat("-") {
+            return match self.advance() {
+                Some(Token::Number(number)) => {
+                    let negated = match number.as_i64() {
+                        Some(integer) => Value::from(-integer),
+                        None => Value::from(-number.as_f64().unwrap_or_default()),
+                    };
+                    Ok(Expr::Literal(negated))
+                }
+                token => Err(unexpected(token.as_ref())),
+            };
+        }
+        self.primary(closed)
+    }
+
+    fn primary(&mut self, closed: bool) -> Result<Expr> {
+        let expr = match self.advance() {
+            Some(Token::Str(value)) => Expr::Literal(Value::String(value)),
+            Some(Token::Number(number)) => Expr::Literal(number),
+            Some(Token::Punct("(")) => {
+                let expr = self.expr(closed)?;
+                self.expect(")")?;
+                expr
+            }
+            Some(Token::Punct("{")) => self.body(closed)?,
+            Some(Token::Punct("[")) => self.list(closed)?,
+            Some(Token::Ident(name)) => match name.as_str() {
+                "_" => Expr::Top,
+                "null" => Expr::Null,
+                "true" => Expr::Literal(Value::Bool(true)),
+                "false" => Expr::Literal(Value::Bool(false)),
+                "bool" => Expr::Bool,
+                "int" => Expr::Int,
+                "float" => Expr::Float,
+                "number" => Expr::Number,
+                "string" => Expr::String,
+                "bytes" => Expr::Bytes,
+                _ => match SIZED.iter().find(|(sized, ..)| *sized == name) {
+                    Some((sized, ..)) => Expr::Sized(sized),
+                    None => Expr::Ref(name),
+                },
+            },
+            token => return Err(unexpected(token.as_ref())),
+        };
+        match self.peek() {
+            Some(Token::Punct(punct @ ("." | "("))) => Err(Error::invalid_input(format!(
+                "Unsupported CUE syntax at '{}{}': selectors and function calls need imports",
+                expr, punct
+            ))),
+            _ => Ok(expr),
+        }
+    }
+
+    /// A struct body after its `{`
+    fn body(&mut self, closed: bool) -> Result<Expr> {
+        let mut body = Struct {
+            open: !closed,
+            ..Struct::default()
+        };
+        while !self.eat("}") {
+            if self.peek().is_none() {
+                return Err(Error::invalid_input("Unclosed struct in CUE schema"));
+            }
+            if self.eat("...") {
+                body.open = true;
+            } else if self.eat("[") {
+                body.patterns.push(self.pattern(closed)?);
+            } else if self.at_field() {
+                let field = self.field(closed)?;
+                // Nested definitions and hidden fields are not output fields
+                if !field.name.starts_with(['#', '_']) {
+                    body.fields.push(field);
+                }
+            } else {
+                body.embeds.push(self.expr(closed)?);
+            }
+            self.eat(",");
+        }
+        Ok(Expr::Struct(body))
+    }
+
+    /// A pattern constraint after its `[`: the label and the value
+    fn pattern(&mut self, closed: bool) -> Result<(Expr, Expr)> {
+        let label = self.expr(false)?;
+        self.expect("]")?;
+        self.expect(":")?;
+        Ok((label, self.expr(closed)?))
+    }
+
+    /// A list after its `[`
+    fn list(&mut self, closed: bool) -> Result<Expr> {
+        let mut prefix = Vec::new();
+        let mut rest = None;
+        while !self.eat("]") {
+            if self.eat("...") {
+                rest = Some(Box::new(if self.peek() == Some(&Token::Punct("]")) {
+                    Expr::Top
+                } else {
+                    self.expr(closed)?
+                }));
+                self.expect("]")?;
+                break;
+            }
+            prefix.push(self.expr(closed)?);
+            if !self.eat(",") {
+                self.expect("]")?;
+                break;
+            }
+        }
+        Ok(Expr::List { prefix, rest })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn violations(source: &str, name: &str, value: Value) -> Vec<String> {
+        let definitions = Definitions::parse(source).unwrap();
+        let name = definitions.resolve(Some(name)).unwrap();
+        definitions
+            .check(&name, &value)
+            .iter()
+            .map(Violation::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_embedding_lists_and_presence() {
+        let source = r#"
+            package deploy
+
+            #Base: {
+                name!: string
+                labels?: [string]: string
+            }
+            #Service: {
+                #Base
+                replicas: uint8 | *1
+                ports: [...{ port: int & >0, protocol: *"TCP" | "UDP" }]
+                image: string
+            }
+        "#;
+        assert_eq!(
+            violations(
+                source,
+                "Service",
+                json!({
+                    "labels": { "team": 7 },
+                    "replicas": 300,
+                    "ports": [{ "port": 0 }, { "port": 80, "protocol": "SCTP" }],
+                    "image": "web:1.2",
+                }),
+            ),
+            vec![
+                "labels.team: expected string, found 7",
+                "name: required field is missing",
+                "ports[0].port: expected >0, found 0",
+                r#"ports[1].protocol: expected "TCP" | "UDP", found "SCTP""#,
+                "replicas: expected uint8 | 1, found 300",
+            ]
+        );
+        assert!(violations(
+            source,
+            "#Service",
+            json!({ "name": "web", "ports": [], "image": "web:1.2" }),
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn test_open_structs_and_tuples() {
+        let source = r#"
+            #Config: {
+                pair: [string, int]
+                extra: { known: bool, ... }
+                "quoted key"?: null
+            }
+        "#;
+        assert_eq!(
+            violations(
+                source,
+                "Config",
+                json!({ "pair": ["a"], "extra": { "known": true, "other": 1 }, "quoted key": 1 }),
+            ),
+            vec![
+                "\"quoted key\": expected null, found 1",
+                "pair: expected 2 item(s), found 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resolve_and_unsupported_syntax() {
+        let definitions = Definitions::parse("#A: int\n#B: string\nplain: #A").unwrap();
+        assert_eq!(definitions.names().collect::<Vec<_>>(), ["#A", "#B"]);
+        assert!(definitions.resolve(None).is_err());
+        assert_eq!(definitions.resolve(Some("plain")).unwrap(), "plain");
+
+        for source in [
+            "import \"strings\"",
+            "#A: strings.MinRunes(1)",
+            "#A: #Missing",
+        ] {
+            assert!(Definitions::parse(source).is_err(), "{}", source);
+        }
+    }
+}
@@ -0,0 +1,247 @@
+//! Dependency files for build systems
+//!
+//! Backs `--emit-depfile FILE` on `parse`, `export` and `build`: next to
+//! the outputs, bunsenite writes which files they were made from, so Make,
+//! Ninja or Bazel rebuild them exactly when one of those changes. The inputs
+//! are the config and every file it transitively imports, found as
+//! [`imports::prefetch`] finds them (an import in a branch evaluation never
+//! takes counts too), plus extra inputs such as schemas.
+//!
+//! The format follows the file's extension. Most files get Make syntax, as
+//! `gcc -MD` writes it and Ninja reads it with `depfile = $out.d`:
+//!
+//! ```text
+//! out/app.json: app.ncl lib/base.ncl
+//! ```
+//!
+//! A `.json` file gets an object with sorted `targets` and `inputs`, for
+//! Bazel rules and other tools that would rather not parse Make:
+//!
+//! ```text
+//! {"inputs":["app.ncl","lib/base.ncl"],"targets":["out/app.json"]}
+//! ```
+//!
+//! Imports that cannot be read are left out: Make fails on a prerequisite
+//! that does not exist and has no rule.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::depfile::Depfile;
+//!
+//! let dir = tempfile::tempdir().unwrap();
+//! let app = dir.path().join("app.ncl");
+//! std::fs::write(&app, r#"import "base.ncl""#).unwrap();
+//! std::fs::write(dir.path().join("base.ncl"), "{ port = 80 }").unwrap();
+//!
+//! let depfile = Depfile::new(vec![dir.path().join("app.json")], &app, 1)
+//!     .relative_to(dir.path());
+//! assert_eq!(depfile.to_make(), "app.json: app.ncl base.ncl\n");
+//! ```
+
+use crate::error::{Error, Result};
+use crate::imports;
+use crate::json;
+use crate::output;
+use crate::paths;
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+
+/// Syntax of a depfile
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// `TARGETS: INPUTS`, as Make and Ninja read
+    Make,
+    /// `{"targets": [..], "inputs": [..]}`
+    Json,
+}
+
+impl Format {
+    /// [`Format::Json`] for a `.json` file, else [`Format::Make`]
+    pub fn for_path(path: &Path) -> Self {
+        match path.extension() {
+            Some(extension) if extension.eq_ignore_ascii_case("json") => Format::Json,
+            _ => Format::Make,
+        }
+    }
+}
+
+/// Outputs and the files they were made from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Depfile {
+    targets: Vec<PathBuf>,
+    inputs: Vec<PathBuf>,
+}
+
+impl Depfile {
+    /// `targets`, made from `file` and every file it transitively imports
+    ///
+    /// The import graph is read on up to `threads` threads. Paths are
+    /// normalized, and stay relative if `file` is.
+    pub fn new(targets: Vec<PathBuf>, file: &Path, threads: usize) -> Self {
+        let graph = imports::prefetch(file, threads);
+        let mut depfile = Self {
+            targets,
+            inputs: Vec::new(),
+        };
+        depfile = depfile.with_input(file);
+        for path in graph.files {
+            depfile = depfile.with_input(path);
+        }
+        depfile
+    }
+
+    /// Also depend on `path`, e.g. a schema the outputs were checked against
+    pub fn with_input(mut self, path: impl Into<PathBuf>) -> Self {
+        let path = imports::normalize(&path.into());
+        if !self.inputs.contains(&path) {
+            self.inputs.push(path);
+        }
+        self
+    }
+
+    /// This depfile with its paths relative to the project at `root`
+    ///
+    /// See [`paths::relative_to`](crate::paths::relative_to).
+    pub fn relative_to(mut self, root: &Path) -> Self {
+        for path in self.targets.iter_mut().chain(&mut self.inputs) {
+            *path = paths::relative_to(path, root);
+        }
+        self
+    }
+
+    /// The outputs
+    pub fn targets(&self) -> &[PathBuf] {
+        &self.targets
+    }
+
+    /// The files the outputs were made from: the config first, then its
+    /// imports and extra inputs
+    pub fn inputs(&self) -> &[PathBuf] {
+        &self.inputs
+    }
+
+    /// The depfile in Make syntax, one rule for all targets
+    ///
+    /// Spaces, `#` and `$` in paths are escaped as Make requires.
+    pub fn to_make(&self) -> String {
+        let targets: Vec<String> = self.targets.iter().map(|path| escape(path)).collect();
+        let inputs: Vec<String> = self.inputs.iter().map(|path| escape(path)).collect();
+        format!("{}: {}\n", targets.join(" "), inputs.join(" "))
+    }
+
+    /// The depfile as JSON, paths sorted
+    pub fn to_json(&self) -> Value {
+        let sorted = |paths: &[PathBuf]| {
+            let mut paths: Vec<String> = paths.iter().map(|path| paths::display(path)).collect();
+            paths.sort();
+            paths
+        };
+        json!({
+            "targets": sorted(&self.targets),
+            "inputs": sorted(&self.inputs),
+        })
+    }
+
+    /// Write the depfile to `path`, atomically, in the [`Format`] its
+    /// extension calls for
+    ///
+    /// # Errors
+    ///
+    /// Returns an invalid-input error if there are no targets, or an I/O
+    /// error if the file cannot be written
+    pub fn write(&self, path: &Path) -> Result<()> {
+        if self.targets.is_empty() {
+            return Err(Error::invalid_input(format!(
+                "Nothing was written, so there is no depfile for {}",
+                path.display()
+            )));
+        }
+        let contents = match Format::for_path(path) {
+            Format::Make => self.to_make(),
+            Format::Json => json::to_string(&self.to_json(), true) + "\n",
+        };
+        output::write_atomic(path, contents.as_bytes(), None)
+    }
+}
+
+/// `path` escaped for a Make rule
+fn escape(path: &Path) -> String {
+    let mut escaped = String::new();
+    for c in paths::display(path).chars() {
+        match c {
+            ' ' | '#' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '$' => escaped.push_str("$$"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_inputs_follow_imports() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("lib")).unwrap();
+        let app = dir.path().join("app.ncl");
+        std::fs::write(
+            &app,
+            r#"(import "lib/base.ncl") & (import "gone.ncl") & { data = import "data.json" }"#,
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("lib/base.ncl"), r#"import "../app.ncl""#).unwrap();
+        std::fs::write(dir.path().join("data.json"), "{}").unwrap();
+
+        let depfile = Depfile::new(vec![dir.path().join("out dir/app.json")], &app, 2)
+            .with_input(dir.path().join("schema.json"))
+            .relative_to(dir.path());
+        let mut inputs = depfile.inputs().to_vec();
+        inputs.sort();
+        assert_eq!(
+            inputs,
+            ["app.ncl", "data.json", "lib/base.ncl", "schema.json"].map(PathBuf::from)
+        );
+        assert_eq!(depfile.inputs()[0], PathBuf::from("app.ncl"));
+        assert!(depfile
+            .to_make()
+            .starts_with("out\\ dir/app.json: app.ncl "));
+        assert_eq!(
+            depfile.to_json()["inputs"],
+            json!(["app.ncl", "data.json", "lib/base.ncl", "schema.json"])
+        );
+    }
+
+    #[test]
+    fn test_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let depfile = Depfile {
+            targets: vec![PathBuf::from("a$b.json")],
+            inputs: vec![PathBuf::from("#1.ncl")],
+        };
+        let make = dir.path().join("out.d");
+        depfile.write(&make).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&make).unwrap(),
+            "a$$b.json: \\#1.ncl\n"
+        );
+
+        let json = dir.path().join("out.deps.json");
+        depfile.write(&json).unwrap();
+        let written: Value =
+            serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+        assert_eq!(written["targets"], json!(["a$b.json"]));
+
+        let empty = Depfile {
+            targets: Vec::new(),
+            inputs: Vec::new(),
+        };
+        assert!(empty.write(&make).is_err());
+    }
+}
@@ -0,0 +1,383 @@
+//! Structured diagnostics
+//!
+//! Backs `--error-format json`: instead of free-form text, each error is
+//! written to stderr as one JSON object on a line of its own, for CI systems
+//! and editors:
+//!
+//! ```text
+//! {"code":"type","file":"app.ncl","message":"...","severity":"error","span":{"column":13,"end":18,"line":2,"start":14},"suggestion":"..."}
+//! ```
+//!
+//! - `severity`: `error` or `warning`
+//! - `code`: the kind of error ([`Error::code`]), e.g. `parse`,
+//!   `evaluation` or `type`
+//! - `file`: the config the error is about, if any
+//! - `span`: byte offsets Nickel blamed ([`Error::span`]), with the 1-based
+//!   `line` and `column` they start at when the source is known, else
+//!   `null`
+//! - `message` and `suggestion`: as in the text output (`suggestion` may be
+//!   `null`)
+//!
+//! For people, [`Diagnostic::render`] shows the blamed source line with the
+//! span underlined, as rustc does (`--color` decides whether it is colored):
+//!
+//! ```text
+//! error[type]: TypeMismatch { .. }
+//!  --> app.ncl:2:13
+//!   |
+//! 2 |   port = 1, name : Number = "x" }
+//!   |             ^^^^ this has the wrong type
+//!   |
+//!   = help: Fix the annotated types, or drop the annotation. ...
+//! ```
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::diagnostic::Diagnostic;
+//! use bunsenite::NickelLoader;
+//!
+//! let source = "{ port : Number = \"80\" }";
+//! let error = NickelLoader::new().typecheck(source, "app.ncl").unwrap_err();
+//! let diagnostic = Diagnostic::from_error(&error).with_source(source);
+//! let json = diagnostic.to_json();
+//! assert_eq!(json["code"], "type");
+//! assert_eq!(json["file"], "app.ncl");
+//! assert_eq!(json["severity"], "error");
+//! ```
+
+use crate::error::{self, Error};
+use serde_json::{json, Value};
+use std::fmt;
+
+/// How serious a diagnostic is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The command failed
+    Error,
+    /// Worth a look, but the command went on
+    Warning,
+}
+
+impl Severity {
+    /// The severity's name, as written in JSON
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// An error or warning, broken into fields
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// File the diagnostic is about, if any
+    pub file: Option<String>,
+    /// Byte offsets of the blamed source span
+    pub span: Option<(usize, usize)>,
+    /// 1-based line and column where the span starts, if the source is known
+    pub location: Option<(usize, usize)>,
+    /// How serious it is
+    pub severity: Severity,
+    /// Kind of diagnostic, e.g. `type` (see [`Error::code`])
+    pub code: &'static str,
+    /// What went wrong
+    pub message: String,
+    /// How to fix it
+    pub suggestion: Option<String>,
+}
+
+impl Diagnostic {
+    /// The diagnostic for `error`
+    ///
+    /// Parse, evaluation and type errors name their file and carry the
+    /// engine's message; other errors have no file and their full text.
+    pub fn from_error(error: &Error) -> Self {
+        let (file, message) = match error {
+            Error::ParseError { file, message, .. }
+            | Error::EvaluationError { file, message, .. }
+            | Error::TypeError { file, message, .. } => (Some(file.clone()), message.clone()),
+            other => (None, other.to_string()),
+        };
+        Self {
+            file,
+            span: error.span(),
+            location: None,
+            severity: Severity::Error,
+            code: error.code(),
+            message,
+            suggestion: error.suggestion().map(str::to_string),
+        }
+    }
+
+    /// A warning about no file in particular
+    pub fn warning(message: impl Into<String>) -> Self {
+        Self {
+            file: None,
+            span: None,
+            location: None,
+            severity: Severity::Warning,
+            code: "warning",
+            message: message.into(),
+            suggestion: None,
+        }
+    }
+
+    /// Locate the span in `source`, the contents of [`file`](Self::file)
+    ///
+    /// A span beyond the end of `source` is left without a location.
+    pub fn with_source(mut self, source: &str) -> Self {
+        self.location = self
+            .span
+            .and_then(|(start, _)| error::line_column(source, start));
+        self
+    }
+
+    /// The diagnostic as a JSON object
+    pub fn to_json(&self) -> Value {
+        let span = self.span.map(|(start, end)| {
+            json!({
+                "start": start,
+                "end": end,
+                "line": self.location.map(|(line, _)| line),
+                "column": self.location.map(|(_, column)| column),
+            })
+        });
+        json!({
+            "severity": self.severity.as_str(),
+            "code": self.code,
+            "file": self.file,
+            "span": span,
+            "message": self.message,
+            "suggestion": self.suggestion,
+        })
+    }
+
+    /// The diagnostic for people: a `severity[code]: message` header, where
+    /// it is, the line of `source` its span starts on with the span
+    /// underlined, and the suggestion
+    ///
+    /// `source` is the contents of [`file`](Self::file); without it, or
+    /// without a span inside it, only the header, file and suggestion are
+    /// shown. A span running over several lines is underlined to the end of
+    /// its first. With `color`, ANSI escapes highlight the header, gutter and
+    /// underline.
+    pub fn render(&self, source: Option<&str>, color: bool) -> String {
+        let paint = |style: &str, text: &str| {
+            if color {
+                format!("\x1b[{}m{}\x1b[0m", style, text)
+            } else {
+                text.to_string()
+            }
+        };
+        let accent = match self.severity {
+            Severity::Error => RED,
+            Severity::Warning => YELLOW,
+        };
+        let mut out = format!(
+            "{}{}",
+            paint(accent, &format!("{}[{}]", self.severity, self.code)),
+            paint(BOLD, &format!(": {}", self.message))
+        );
+
+        let snippet = source
+            .zip(self.span)
+            .and_then(|(source, span)| Snippet::of(source, span));
+        let number = snippet.as_ref().map(|s| s.line.to_string());
+        let pad = " ".repeat(number.as_ref().map_or(1, String::len));
+        let bar = paint(BLUE, "|");
+        if let Some(file) = &self.file {
+            let at = match snippet
+                .as_ref()
+                .map(|s| (s.line, s.column))
+                .or(self.location)
+            {
+                Some((line, column)) => format!("{}:{}:{}", file, line, column),
+                None => file.clone(),
+            };
+            out += &format!("\n{}{} {}", pad, paint(BLUE, "-->"), at);
+        }
+        if let (Some(snippet), Some(number)) = (&snippet, &number) {
+            out += &format!("\n{} {}", pad, bar);
+            out += &format!("\n{} {} {}", paint(BLUE, number), bar, snippet.text);
+            let underline = format!("{} {}", "^".repeat(snippet.width), label(self.code));
+            out += &format!(
+                "\n{} {} {}{}",
+                pad,
+                bar,
+                " ".repeat(snippet.indent),
+                paint(accent, &underline)
+            );
+        }
+        if let Some(suggestion) = &self.suggestion {
+            if snippet.is_some() {
+                out += &format!("\n{} {}", pad, bar);
+            }
+            out += &format!(
+                "\n{} {} {} {}",
+                pad,
+                paint(BLUE, "="),
+                paint(BOLD, "help:"),
+                suggestion
+            );
+        }
+        out
+    }
+}
+
+/// ANSI styles for [`Diagnostic::render`]
+const BOLD: &str = "1";
+const RED: &str = "1;31";
+const YELLOW: &str = "1;33";
+const BLUE: &str = "1;34";
+
+/// What the underline of a span says, by error code
+fn label(code: &str) -> &'static str {
+    match code {
+        "parse" => "cannot parse this",
+        "evaluation" => "evaluation failed here",
+        "type" => "this has the wrong type",
+        _ => "here",
+    }
+}
+
+/// The source line a span starts on, laid out for [`Diagnostic::render`]
+#[derive(Debug)]
+struct Snippet {
+    /// 1-based line number
+    line: usize,
+    /// 1-based column, in characters
+    column: usize,
+    /// The line, tabs expanded
+    text: String,
+    /// Display width before the span
+    indent: usize,
+    /// Display width of the span on this line, at least 1
+    width: usize,
+}
+
+impl Snippet {
+    fn of(source: &str, (start, end): (usize, usize)) -> Option<Self> {
+        let (line, column) = error::line_column(source, start)?;
+        let line_start = source[..start].rfind('\n').map_or(0, |newline| newline + 1);
+        let line_end = source[start..]
+            .find('\n')
+            .map_or(source.len(), |newline| start + newline);
+        let spanned = source.get(start..end.min(line_end)).unwrap_or("");
+        Some(Self {
+            line,
+            column,
+            text: source[line_start..line_end]
+                .trim_end_matches('\r')
+                .replace('\t', TAB),
+            indent: width(&source[line_start..start]),
+            width: width(spanned.trim_end_matches('\r')).max(1),
+        })
+    }
+}
+
+/// Tabs are shown as this
+const TAB: &str = "    ";
+
+/// Display width of `text`, one column per character and [`TAB`] per tab
+fn width(text: &str) -> usize {
+    text.chars()
+        .map(|c| if c == '\t' { TAB.len() } else { 1 })
+        .sum()
+}
+
+impl fmt::Display for Diagnostic {
+    /// `file:line:column: severity[code]: message`, leaving out what is not
+    /// known
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file)?;
+            if let Some((line, column)) = self.location {
+                write!(f, "{}:{}:", line, column)?;
+            }
+            f.write_str(" ")?;
+        }
+        write!(f, "{}[{}]: {}", self.severity, self.code, self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_type_error() {
+        let error = Error::type_error(
+            "app.ncl",
+            "TypeMismatch { span: RawSpan { start: ByteIndex(14), end: ByteIndex(18) } }",
+        );
+        let diagnostic =
+            Diagnostic::from_error(&error).with_source("{\n  port = 1, name : Number = \"x\" }");
+        assert_eq!(diagnostic.location, Some((2, 13)));
+        assert_eq!(
+            diagnostic.to_json(),
+            json!({
+                "severity": "error",
+                "code": "type",
+                "file": "app.ncl",
+                "span": { "start": 14, "end": 18, "line": 2, "column": 13 },
+                "message": "TypeMismatch { span: RawSpan { start: ByteIndex(14), end: ByteIndex(18) } }",
+                "suggestion": error.suggestion(),
+            })
+        );
+        assert!(diagnostic
+            .to_string()
+            .starts_with("app.ncl:2:13: error[type]: TypeMismatch"));
+    }
+
+    #[test]
+    fn test_from_other_errors() {
+        let diagnostic = Diagnostic::from_error(&Error::invalid_input("No files match 'x'"));
+        assert_eq!(diagnostic.file, None);
+        assert_eq!(diagnostic.span, None);
+        assert_eq!(
+            diagnostic.to_string(),
+            "error[invalid_input]: Invalid input: No files match 'x'"
+        );
+        assert_eq!(diagnostic.to_json()["span"], Value::Null);
+
+        // Out-of-range spans are not located
+        let error = Error::parse_error("app.ncl", "start: ByteIndex(90), end: ByteIndex(95)");
+        assert_eq!(
+            Diagnostic::from_error(&error).with_source("{}").location,
+            None
+        );
+    }
+
+    #[test]
+    fn test_render() {
+        let error = Error::type_error(
+            "app.ncl",
+            "TypeMismatch { span: RawSpan { start: ByteIndex(14), end: ByteIndex(18) } }",
+        );
+        let source = "{\n\tport = 1, name : Number = \"x\" }";
+        let rendered = Diagnostic::from_error(&error).render(Some(source), false);
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert!(lines[0].starts_with("error[type]: TypeMismatch"));
+        assert_eq!(lines[1], " --> app.ncl:2:13");
+        assert_eq!(lines[2], "  |");
+        assert_eq!(lines[3], "2 |     port = 1, name : Number = \"x\" }");
+        assert_eq!(lines[4], "  |                ^^^^ this has the wrong type");
+        assert!(lines[6].starts_with("  = help: Fix the annotated types"));
+
+        // Without the source, no snippet; with color, escapes
+        let plain = Diagnostic::from_error(&error).render(None, false);
+        assert_eq!(plain.lines().nth(1), Some(" --> app.ncl"));
+        assert_eq!(plain.lines().count(), 3);
+        let colored = Diagnostic::from_error(&error).render(Some(source), true);
+        assert!(colored.starts_with("\x1b[1;31merror[type]\x1b[0m"));
+        assert!(colored.contains("\x1b[1;31m^^^^ this has the wrong type\x1b[0m"));
+    }
+}
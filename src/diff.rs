@@ -0,0 +1,208 @@
+//! Path-level differences between evaluated configs
+//!
+//! Backs `bunsenite parse --diff-against`: compares an evaluated config with a
+//! previously exported artifact and reports only the paths that changed, so
+//! merge requests can show what a change does to production output.
+//! `bunsenite diff OLD NEW` compares two configs the same way, evaluating
+//! both, with [`to_json`] for automation.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::diff::{self, Change};
+//! use serde_json::json;
+//!
+//! let changes = diff::diff(&json!({ "port": 80 }), &json!({ "port": 443 }));
+//! assert_eq!(changes.len(), 1);
+//! assert_eq!(changes[0].to_string(), "~ port: 80 -> 443");
+//! ```
+
+use crate::json;
+use serde_json::{json, Value};
+use std::fmt;
+
+/// A change at one path
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    /// A path present only in the new value
+    Added {
+        /// Path of the value
+        path: String,
+        /// New value
+        value: Value,
+    },
+    /// A path present only in the old value
+    Removed {
+        /// Path of the value
+        path: String,
+        /// Old value
+        value: Value,
+    },
+    /// A path whose value changed
+    Changed {
+        /// Path of the value
+        path: String,
+        /// Old value
+        old: Value,
+        /// New value
+        new: Value,
+    },
+}
+
+impl Change {
+    /// Path the change applies to (empty for the root)
+    pub fn path(&self) -> &str {
+        match self {
+            Change::Added { path, .. }
+            | Change::Removed { path, .. }
+            | Change::Changed { path, .. } => path,
+        }
+    }
+}
+
+impl Change {
+    /// The change as a JSON record: `kind` (`added`, `removed` or
+    /// `changed`), `path`, and `value` or `old` and `new`
+    pub fn to_json(&self) -> Value {
+        match self {
+            Change::Added { path, value } => {
+                json!({ "kind": "added", "path": path, "value": value })
+            }
+            Change::Removed { path, value } => {
+                json!({ "kind": "removed", "path": path, "value": value })
+            }
+            Change::Changed { path, old, new } => {
+                json!({ "kind": "changed", "path": path, "old": old, "new": new })
+            }
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = |p: &str| if p.is_empty() { "." } else { p }.to_string();
+        match self {
+            Change::Added { path: p, value } => {
+                write!(f, "+ {}: {}", path(p), json::to_string(value, false))
+            }
+            Change::Removed { path: p, value } => {
+                write!(f, "- {}: {}", path(p), json::to_string(value, false))
+            }
+            Change::Changed { path: p, old, new } => write!(
+                f,
+                "~ {}: {} -> {}",
+                path(p),
+                json::to_string(old, false),
+                json::to_string(new, false)
+            ),
+        }
+    }
+}
+
+/// Compute the changes from `old` to `new`, in path order
+///
+/// Records are compared field by field and arrays element by element; a
+/// value whose type changed is reported as a single change. The walk uses an
+/// explicit stack, so deeply nested values are safe.
+pub fn diff(old: &Value, new: &Value) -> Vec<Change> {
+    let mut changes = Vec::new();
+    let mut stack = vec![(String::new(), Some(old), Some(new))];
+
+    while let Some((path, old, new)) = stack.pop() {
+        match (old, new) {
+            (Some(Value::Object(old)), Some(Value::Object(new))) => {
+                let mut keys: Vec<&String> = old.keys().chain(new.keys()).collect();
+                keys.sort();
+                keys.dedup();
+                for key in keys.into_iter().rev() {
+                    stack.push((json::key_path(&path, key), old.get(key), new.get(key)));
+                }
+            }
+            (Some(Value::Array(old)), Some(Value::Array(new))) => {
+                for index in (0..old.len().max(new.len())).rev() {
+                    stack.push((
+                        json::index_path(&path, index),
+                        old.get(index),
+                        new.get(index),
+                    ));
+                }
+            }
+            (Some(old), Some(new)) if old != new => changes.push(Change::Changed {
+                path,
+                old: old.clone(),
+                new: new.clone(),
+            }),
+            (None, Some(new)) => changes.push(Change::Added {
+                path,
+                value: new.clone(),
+            }),
+            (Some(old), None) => changes.push(Change::Removed {
+                path,
+                value: old.clone(),
+            }),
+            _ => {}
+        }
+    }
+
+    changes
+}
+
+/// `changes` as a JSON array of [`Change::to_json`] records
+pub fn to_json(changes: &[Change]) -> Value {
+    Value::Array(changes.iter().map(Change::to_json).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_identical_values_have_no_changes() {
+        let value = json!({ "a": [1, { "b": null }], "c": "x" });
+        assert!(diff(&value, &value).is_empty());
+    }
+
+    #[test]
+    fn test_reports_changes_in_path_order() {
+        let old = json!({ "db": { "host": "a", "port": 5432 }, "old": true, "tags": ["x"] });
+        let new = json!({ "db": { "host": "b", "port": 5432 }, "new": 1, "tags": ["x", "y"] });
+
+        let lines: Vec<String> = diff(&old, &new).iter().map(|c| c.to_string()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                r#"~ db.host: "a" -> "b""#,
+                "+ new: 1",
+                "- old: true",
+                r#"+ tags[1]: "y""#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_type_change_is_one_change() {
+        let changes = diff(&json!({ "a": { "b": 1 } }), &json!({ "a": [1] }));
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].path(), "a");
+    }
+
+    #[test]
+    fn test_changes_as_json() {
+        let changes = diff(&json!({ "a": 1, "b": [1] }), &json!({ "a": 2, "c": null }));
+        assert_eq!(
+            to_json(&changes),
+            json!([
+                { "kind": "changed", "path": "a", "old": 1, "new": 2 },
+                { "kind": "removed", "path": "b", "value": [1] },
+                { "kind": "added", "path": "c", "value": null },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_root_change() {
+        let changes = diff(&json!(1), &json!(2));
+        assert_eq!(changes[0].to_string(), "~ .: 1 -> 2");
+    }
+}
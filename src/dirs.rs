@@ -0,0 +1,163 @@
+//! Where bunsenite keeps its global files
+//!
+//! Two directories, each following the platform's conventions and each
+//! overridable by an environment variable, so that distribution packages,
+//! CI images and sandboxes can put them wherever their policy says:
+//!
+//! | Directory | Override | Linux and other Unix | macOS | Windows |
+//! |-----------|----------|----------------------|-------|---------|
+//! | [`config_dir`] | [`CONFIG_DIR_ENV`] | `$XDG_CONFIG_HOME/bunsenite` or `~/.config/bunsenite` | `~/Library/Application Support/bunsenite` | `%APPDATA%\bunsenite` |
+//! | [`cache_dir`] | [`CACHE_DIR_ENV`] | `$XDG_CACHE_HOME/bunsenite` or `~/.cache/bunsenite` | `~/Library/Caches/bunsenite` | `%LOCALAPPDATA%\bunsenite` |
+//!
+//! Empty variables count as unset, and relative `XDG_*` values are ignored,
+//! as the XDG base directory specification requires. On Windows,
+//! `%USERPROFILE%\AppData\...` stands in for an unset `APPDATA` or
+//! `LOCALAPPDATA`. Nothing here creates the directories; writers do, when
+//! they first need them.
+//!
+//! The config directory holds the global settings file (see
+//! [`crate::settings`]); the cache directory holds the caches (see
+//! [`crate::cache`]).
+
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+/// Overrides [`config_dir`]
+pub const CONFIG_DIR_ENV: &str = "BUNSENITE_CONFIG_DIR";
+
+/// Overrides [`cache_dir`]
+pub const CACHE_DIR_ENV: &str = "BUNSENITE_CACHE_DIR";
+
+/// Directory for bunsenite's global configuration
+///
+/// `$BUNSENITE_CONFIG_DIR` if set, else `bunsenite` in the platform
+/// configuration directory (see the [module docs](self)). `None` if neither
+/// is known, e.g. without `HOME`.
+pub fn config_dir() -> Option<PathBuf> {
+    config_dir_from(env_var)
+}
+
+/// Directory for bunsenite's caches
+///
+/// `$BUNSENITE_CACHE_DIR` if set, else `bunsenite` in the platform cache
+/// directory (see the [module docs](self)). `None` if neither is known.
+pub fn cache_dir() -> Option<PathBuf> {
+    cache_dir_from(env_var)
+}
+
+fn env_var(name: &str) -> Option<OsString> {
+    std::env::var_os(name)
+}
+
+/// [`config_dir`], as `var` reads environment variables
+fn config_dir_from(var: impl Fn(&str) -> Option<OsString>) -> Option<PathBuf> {
+    let var = |name: &str| var(name).filter(|value| !value.is_empty());
+    if let Some(dir) = var(CONFIG_DIR_ENV) {
+        return Some(PathBuf::from(dir));
+    }
+    let base = if cfg!(windows) {
+        var("APPDATA")
+            .map(PathBuf::from)
+            .or_else(|| var("USERPROFILE").map(|home| PathBuf::from(home).join("AppData/Roaming")))
+    } else if cfg!(target_os = "macos") {
+        var("HOME").map(|home| PathBuf::from(home).join("Library/Application Support"))
+    } else {
+        xdg(var("XDG_CONFIG_HOME"))
+            .or_else(|| var("HOME").map(|home| PathBuf::from(home).join(".config")))
+    };
+    base.map(|base| base.join("bunsenite"))
+}
+
+/// [`cache_dir`], as `var` reads environment variables
+fn cache_dir_from(var: impl Fn(&str) -> Option<OsString>) -> Option<PathBuf> {
+    let var = |name: &str| var(name).filter(|value| !value.is_empty());
+    if let Some(dir) = var(CACHE_DIR_ENV) {
+        return Some(PathBuf::from(dir));
+    }
+    let base = if cfg!(windows) {
+        var("LOCALAPPDATA")
+            .map(PathBuf::from)
+            .or_else(|| var("USERPROFILE").map(|home| PathBuf::from(home).join("AppData/Local")))
+    } else if cfg!(target_os = "macos") {
+        var("HOME").map(|home| PathBuf::from(home).join("Library/Caches"))
+    } else {
+        xdg(var("XDG_CACHE_HOME"))
+            .or_else(|| var("HOME").map(|home| PathBuf::from(home).join(".cache")))
+    };
+    base.map(|base| base.join("bunsenite"))
+}
+
+/// An `XDG_*_HOME` value, unless it is relative, which the specification
+/// says to ignore
+fn xdg(value: Option<OsString>) -> Option<PathBuf> {
+    value.map(PathBuf::from).filter(|dir| dir.is_absolute())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars<'a>(pairs: &'a [(&'a str, &'a str)]) -> impl Fn(&str) -> Option<OsString> + 'a {
+        move |name| {
+            pairs
+                .iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| OsString::from(value))
+        }
+    }
+
+    #[test]
+    fn test_overrides() {
+        let env = [
+            ("BUNSENITE_CONFIG_DIR", "/etc/bunsenite"),
+            ("BUNSENITE_CACHE_DIR", "/var/cache/bunsenite"),
+            ("HOME", "/home/ada"),
+        ];
+        assert_eq!(
+            config_dir_from(vars(&env)),
+            Some(PathBuf::from("/etc/bunsenite"))
+        );
+        assert_eq!(
+            cache_dir_from(vars(&env)),
+            Some(PathBuf::from("/var/cache/bunsenite"))
+        );
+
+        // Empty overrides are unset
+        let env = [("BUNSENITE_CONFIG_DIR", ""), ("BUNSENITE_CACHE_DIR", "")];
+        assert_eq!(config_dir_from(vars(&env)), None);
+        assert_eq!(cache_dir_from(vars(&env)), None);
+    }
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    #[test]
+    fn test_xdg() {
+        let env = [
+            ("XDG_CONFIG_HOME", "/xdg/config"),
+            ("XDG_CACHE_HOME", "/xdg/cache"),
+            ("HOME", "/home/ada"),
+        ];
+        assert_eq!(
+            config_dir_from(vars(&env)),
+            Some(PathBuf::from("/xdg/config/bunsenite"))
+        );
+        assert_eq!(
+            cache_dir_from(vars(&env)),
+            Some(PathBuf::from("/xdg/cache/bunsenite"))
+        );
+
+        // Relative XDG directories are ignored, falling back to HOME
+        let env = [
+            ("XDG_CONFIG_HOME", "config"),
+            ("XDG_CACHE_HOME", ""),
+            ("HOME", "/home/ada"),
+        ];
+        assert_eq!(
+            config_dir_from(vars(&env)),
+            Some(PathBuf::from("/home/ada/.config/bunsenite"))
+        );
+        assert_eq!(
+            cache_dir_from(vars(&env)),
+            Some(PathBuf::from("/home/ada/.cache/bunsenite"))
+        );
+    }
+}
@@ -0,0 +1,168 @@
+//! Reference documentation from field metadata
+//!
+//! Backs `bunsenite doc`: the doc strings, contracts, `optional` flags and
+//! defaults a config declares on its fields
+//! ([`NickelLoader::extract_metadata`](crate::NickelLoader::extract_metadata))
+//! rendered as a Markdown reference, or as JSON for other tooling. Since
+//! the metadata is read from the evaluated program, fields brought in by
+//! imported contracts are documented along with those written inline.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::docs::{self, FieldDoc};
+//! use serde_json::json;
+//!
+//! let fields = vec![FieldDoc {
+//!     path: "port".to_string(),
+//!     doc: Some("Listen port".to_string()),
+//!     contracts: vec!["Number".to_string()],
+//!     optional: false,
+//!     default: Some(json!(80)),
+//! }];
+//! let markdown = docs::to_markdown("config.ncl", &fields);
+//! assert!(markdown.contains("## `port`\n\nListen port\n"));
+//! assert!(markdown.contains("- Default: `80`\n"));
+//! ```
+
+use crate::json;
+use serde_json::{json, Value};
+
+/// Documentation metadata of one output field
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldDoc {
+    /// Path of the field, in [`json::key_path`] syntax
+    pub path: String,
+    /// Doc string (`| doc "..."`)
+    pub doc: Option<String>,
+    /// Type and contract annotations, as written
+    pub contracts: Vec<String>,
+    /// Whether the field is `optional`
+    pub optional: bool,
+    /// Value the field falls back to when the config leaves it unset
+    /// (`| default = ...`)
+    pub default: Option<Value>,
+}
+
+impl FieldDoc {
+    /// The field as a JSON record: `path`, `doc`, `contracts`, `optional`
+    /// and `default`, with `null` for a missing doc or default
+    pub fn to_json(&self) -> Value {
+        json!({
+            "path": self.path,
+            "doc": self.doc,
+            "contracts": self.contracts,
+            "optional": self.optional,
+            "default": self.default,
+        })
+    }
+
+    /// The field's doc string followed by a bullet list of its contracts,
+    /// `optional` flag and default, each block preceded by a blank line;
+    /// empty when the field declares none of them
+    pub fn to_markdown(&self) -> String {
+        let mut out = String::new();
+        if let Some(doc) = &self.doc {
+            out.push_str(&format!("\n{}\n", doc.trim_end()));
+        }
+
+        let mut facts = Vec::new();
+        if !self.contracts.is_empty() {
+            let contracts: Vec<String> = self
+                .contracts
+                .iter()
+                .map(|contract| format!("`{}`", contract))
+                .collect();
+            facts.push(format!("Contract: {}", contracts.join(", ")));
+        }
+        if self.optional {
+            facts.push("Optional".to_string());
+        }
+        if let Some(default) = &self.default {
+            facts.push(format!("Default: `{}`", json::to_string(default, false)));
+        }
+        if !facts.is_empty() {
+            out.push('\n');
+            for fact in facts {
+                out.push_str(&format!("- {}\n", fact));
+            }
+        }
+        out
+    }
+}
+
+/// `fields` as a Markdown reference titled `title`, one section per field
+pub fn to_markdown(title: &str, fields: &[FieldDoc]) -> String {
+    let mut out = format!("# {}\n", title);
+    if fields.is_empty() {
+        out.push_str("\nNo fields.\n");
+    }
+
+    for field in fields {
+        out.push_str(&format!("\n## `{}`\n", field.path));
+        out.push_str(&field.to_markdown());
+    }
+    out
+}
+
+/// `fields` as a JSON array of [`FieldDoc::to_json`] records
+pub fn to_json(fields: &[FieldDoc]) -> Value {
+    Value::Array(fields.iter().map(FieldDoc::to_json).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(path: &str) -> FieldDoc {
+        FieldDoc {
+            path: path.to_string(),
+            doc: None,
+            contracts: Vec::new(),
+            optional: false,
+            default: None,
+        }
+    }
+
+    #[test]
+    fn test_markdown_lists_each_fact() {
+        let fields = vec![
+            FieldDoc {
+                doc: Some("Database settings\n".to_string()),
+                ..field("db")
+            },
+            FieldDoc {
+                contracts: vec!["String".to_string(), "Hostname".to_string()],
+                optional: true,
+                ..field("db.host")
+            },
+            field("db.port"),
+        ];
+        assert_eq!(
+            to_markdown("app.ncl", &fields),
+            "# app.ncl\n\
+             \n## `db`\n\nDatabase settings\n\
+             \n## `db.host`\n\n- Contract: `String`, `Hostname`\n- Optional\n\
+             \n## `db.port`\n"
+        );
+        assert_eq!(to_markdown("empty.ncl", &[]), "# empty.ncl\n\nNo fields.\n");
+    }
+
+    #[test]
+    fn test_json_has_every_key() {
+        let fields = vec![FieldDoc {
+            default: Some(json!("info")),
+            ..field("log.level")
+        }];
+        assert_eq!(
+            to_json(&fields),
+            json!([{
+                "path": "log.level",
+                "doc": null,
+                "contracts": [],
+                "optional": false,
+                "default": "info",
+            }])
+        );
+    }
+}
@@ -0,0 +1,380 @@
+//! Installation self-test
+//!
+//! Backs `bunsenite doctor`: a quick check that this build works on this
+//! machine, for attaching to "works on my machine" reports. It checks:
+//!
+//! - `nickel`: the embedded Nickel version
+//! - `stdlib`: the Nickel standard library loads and runs
+//! - `round-trip`: a built-in probe program evaluates to the expected JSON,
+//!   and that JSON, written back as Nickel, evaluates to itself
+//! - `features`: the cargo features this build was compiled with
+//! - `locale`: the locale, which only matters for how terminals show
+//!   non-ASCII output (bunsenite itself reads and writes UTF-8 regardless)
+//! - `cache`: the cache `bunsenite bundle push/pull` keeps registry blobs
+//!   in (see [`crate::oci`]) can be written; nothing else needs it
+//! - `settings`: the global settings file (see [`crate::settings`]), if
+//!   there is one, evaluates to options
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::doctor;
+//! use bunsenite::NickelLoader;
+//!
+//! let report = doctor::run(&NickelLoader::new());
+//! assert!(report.healthy(), "{}", report);
+//! ```
+
+use crate::loader::NickelLoader;
+use crate::oci::Cache;
+use crate::settings;
+use crate::source;
+use serde_json::{json, Value};
+use std::fmt;
+
+/// Program evaluated by the round-trip check
+pub const PROBE: &str = r#"let name = "bunsenite" in
+{
+  greeting = "héllo, %{name} ✓",
+  ratio = 0.25,
+  count = 3 * 7,
+  nested = { flags = [true, false], nothing = null },
+  merged = { a = 1 } & { b = 2 },
+}"#;
+
+/// Result of one check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// Working as expected
+    Ok,
+    /// Working, but may explain odd behaviour
+    Warn,
+    /// Broken
+    Fail,
+}
+
+impl Status {
+    fn symbol(self) -> &'static str {
+        match self {
+            Status::Ok => "✓",
+            Status::Warn => "!",
+            Status::Fail => "✗",
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Status::Ok => "ok",
+            Status::Warn => "warn",
+            Status::Fail => "fail",
+        })
+    }
+}
+
+/// One check and its outcome
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    /// Short name, e.g. `stdlib`
+    pub name: &'static str,
+    /// Outcome
+    pub status: Status,
+    /// What was found
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: &'static str, status: Status, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// Outcome of every check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    /// Checks, in the order they ran
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    /// Whether no check failed (warnings are fine)
+    pub fn healthy(&self) -> bool {
+        self.checks.iter().all(|check| check.status != Status::Fail)
+    }
+
+    /// The report as JSON
+    pub fn to_json(&self) -> Value {
+        let checks: Vec<Value> = self
+            .checks
+            .iter()
+            .map(|check| {
+                json!({
+                    "name": check.name,
+                    "status": check.status.to_string(),
+                    "detail": check.detail,
+                })
+            })
+            .collect();
+        json!({
+            "bunsenite": crate::VERSION,
+            "healthy": self.healthy(),
+            "checks": checks,
+        })
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Bunsenite v{}", crate::VERSION)?;
+        for check in &self.checks {
+            writeln!(
+                f,
+                "{} {}: {}",
+                check.status.symbol(),
+                check.name,
+                check.detail
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Run every check with `loader`
+pub fn run(loader: &NickelLoader) -> Report {
+    Report {
+        checks: vec![
+            nickel(),
+            stdlib(loader),
+            round_trip(loader),
+            features(),
+            locale(|name| std::env::var(name).ok()),
+            cache(),
+            settings(loader),
+        ],
+    }
+}
+
+fn nickel() -> Check {
+    let info = crate::VERSION_INFO;
+    Check::new(
+        "nickel",
+        Status::Ok,
+        format!(
+            "Nickel {} (nickel-lang-core {})",
+            info.nickel_language, info.nickel_core
+        ),
+    )
+}
+
+fn stdlib(loader: &NickelLoader) -> Check {
+    let probe = r#"[std.string.uppercase "ok", std.array.length [1, 2], std.record.fields { b = 1, a = 2 }]"#;
+    match loader.parse_string(probe, "doctor-stdlib.ncl") {
+        Ok(value) if value == json!(["OK", 2, ["a", "b"]]) => {
+            Check::new("stdlib", Status::Ok, "loads and runs")
+        }
+        Ok(value) => Check::new(
+            "stdlib",
+            Status::Fail,
+            format!(
+                "unexpected result {}",
+                crate::json::to_string(&value, false)
+            ),
+        ),
+        Err(e) => Check::new("stdlib", Status::Fail, e.to_string()),
+    }
+}
+
+fn round_trip(loader: &NickelLoader) -> Check {
+    let expected = json!({
+        "greeting": "héllo, bunsenite ✓",
+        "ratio": 0.25,
+        "count": 21,
+        "nested": { "flags": [true, false], "nothing": null },
+        "merged": { "a": 1, "b": 2 },
+    });
+    let fail = |detail: String| Check::new("round-trip", Status::Fail, detail);
+
+    let value = match loader.parse_string(PROBE, "doctor-probe.ncl") {
+        Ok(value) => value,
+        Err(e) => return fail(e.to_string()),
+    };
+    if value != expected {
+        return fail(format!(
+            "probe evaluated to {}",
+            crate::json::to_string(&value, false)
+        ));
+    }
+    match loader.parse_string(&source::value_literal(&value), "doctor-round-trip.ncl") {
+        Ok(again) if again == value => Check::new(
+            "round-trip",
+            Status::Ok,
+            "probe program evaluates as expected",
+        ),
+        Ok(again) => fail(format!(
+            "probe output changed when evaluated again: {}",
+            crate::json::to_string(&again, false)
+        )),
+        Err(e) => fail(format!("probe output does not evaluate again: {}", e)),
+    }
+}
+
+fn features() -> Check {
+    let features = [
+        ("archives", cfg!(feature = "archives")),
+        ("cli", cfg!(feature = "cli")),
+        ("compression", cfg!(feature = "compression")),
+        ("embedded", cfg!(feature = "embedded")),
+        ("oci", cfg!(feature = "oci")),
+        ("otel", cfg!(feature = "otel")),
+        ("remote-cache", cfg!(feature = "remote-cache")),
+        ("wasm", cfg!(feature = "wasm")),
+        ("watch", cfg!(feature = "watch")),
+    ];
+    let enabled: Vec<&str> = features
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(name, _)| *name)
+        .collect();
+    let detail = if enabled.is_empty() {
+        "none".to_string()
+    } else {
+        enabled.join(", ")
+    };
+    Check::new("features", Status::Ok, detail)
+}
+
+fn cache() -> Check {
+    let Some(dir) = Cache::default_dir() else {
+        return Check::new(
+            "cache",
+            Status::Warn,
+            "no cache directory (set BUNSENITE_CACHE_DIR); only `bundle push/pull` and `--incremental` need one",
+        );
+    };
+    let probe = dir.join(".doctor-probe");
+    let writable = std::fs::create_dir_all(&dir)
+        .and_then(|()| std::fs::write(&probe, b""))
+        .and_then(|()| std::fs::remove_file(&probe));
+    match writable {
+        Ok(()) => Check::new(
+            "cache",
+            Status::Ok,
+            format!("{} is writable", dir.display()),
+        ),
+        Err(e) => Check::new(
+            "cache",
+            Status::Warn,
+            format!(
+                "{} is not writable ({}); `bundle push/pull` will fail",
+                dir.display(),
+                e
+            ),
+        ),
+    }
+}
+
+fn settings(loader: &NickelLoader) -> Check {
+    let Some((path, required)) = settings::path() else {
+        return Check::new(
+            "settings",
+            Status::Ok,
+            format!(
+                "none ({} is empty or there is no config directory)",
+                settings::SETTINGS_ENV
+            ),
+        );
+    };
+    if !required && !path.exists() {
+        return Check::new(
+            "settings",
+            Status::Ok,
+            format!("none ({} does not exist)", path.display()),
+        );
+    }
+    match settings::load(loader, &path) {
+        Ok(args) => Check::new(
+            "settings",
+            Status::Ok,
+            format!("{}: {}", path.display(), args.join(" ")),
+        ),
+        Err(e) => Check::new("settings", Status::Fail, e.to_string()),
+    }
+}
+
+/// Check the locale, as `var` reads environment variables
+fn locale(var: impl Fn(&str) -> Option<String>) -> Check {
+    // The first of these that is set and not empty wins, as in POSIX
+    let found = ["LC_ALL", "LC_CTYPE", "LANG"].into_iter().find_map(|name| {
+        var(name)
+            .filter(|value| !value.is_empty())
+            .map(|v| (name, v))
+    });
+    match found {
+        Some((name, value)) => {
+            let lower = value.to_ascii_lowercase();
+            if lower.contains("utf-8") || lower.contains("utf8") {
+                Check::new("locale", Status::Ok, format!("{}={}", name, value))
+            } else {
+                Check::new(
+                    "locale",
+                    Status::Warn,
+                    format!(
+                        "{}={} is not UTF-8; terminals may garble non-ASCII output",
+                        name, value
+                    ),
+                )
+            }
+        }
+        None => Check::new(
+            "locale",
+            Status::Warn,
+            "not set (LC_ALL, LC_CTYPE and LANG are empty); terminals may garble non-ASCII output",
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_doctor_is_healthy() {
+        let report = run(&NickelLoader::new());
+        assert!(report.healthy(), "{}", report);
+        let names: Vec<&str> = report.checks.iter().map(|check| check.name).collect();
+        assert_eq!(
+            names,
+            [
+                "nickel",
+                "stdlib",
+                "round-trip",
+                "features",
+                "locale",
+                "cache",
+                "settings"
+            ]
+        );
+        assert_eq!(report.to_json()["healthy"], true);
+    }
+
+    #[test]
+    fn test_locale_precedence() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(var, _)| *var == name)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+        let check = locale(env(&[("LANG", "C"), ("LC_ALL", "en_US.UTF-8")]));
+        assert_eq!(check.status, Status::Ok);
+        assert_eq!(check.detail, "LC_ALL=en_US.UTF-8");
+        assert_eq!(locale(env(&[("LANG", "C")])).status, Status::Warn);
+        assert_eq!(locale(env(&[("LC_ALL", "")])).status, Status::Warn);
+    }
+}
@@ -0,0 +1,205 @@
+//! Drift between desired and live state
+//!
+//! Backs `bunsenite drift --live live.json config.ncl`: compares the evaluated
+//! config (the desired state) with a document exported from the running
+//! system, such as `kubectl get -o json`, and points each difference at the
+//! field definition in the config that produced it.
+//!
+//! Live documents usually carry fields the system fills in itself (status,
+//! timestamps, generated IDs), so by default only paths the config defines
+//! are compared; [`Options::include_extra`] reports live-only paths too, and
+//! [`Options::ignore`] drops paths that are expected to differ.
+//!
+//! Definition sites are found lexically with the record splitter of
+//! [`crate::merge`]: nested record literals and dotted field names are
+//! followed, but not imports, merges with other expressions or function
+//! calls. A path that cannot be followed all the way is attributed to the
+//! deepest field definition found on the way.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::drift::{self, Options};
+//! use serde_json::json;
+//!
+//! let source = "{\n  web = {\n    replicas = 3,\n  },\n}\n";
+//! let desired = json!({ "web": { "replicas": 3 } });
+//! let live = json!({ "web": { "replicas": 2, "uid": "1f0c" } });
+//!
+//! let drifts = drift::detect(&desired, &live, source, &Options::default());
+//! assert_eq!(drifts.len(), 1);
+//! assert_eq!(drifts[0].to_string(), "~ web.replicas: 3 -> 2 (line 3)");
+//! ```
+
+use crate::diff::{self, Change};
+use crate::json;
+use crate::merge::{self, Record};
+use crate::pattern::{self, PathPattern};
+use serde_json::Value;
+use std::fmt;
+
+/// What to compare
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Also report paths present only in the live state
+    pub include_extra: bool,
+    /// Paths not to compare
+    pub ignore: Vec<PathPattern>,
+}
+
+/// Where an output path is defined in a source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Site {
+    /// Path of the definition found; an ancestor of the path looked up if the
+    /// definition could not be followed all the way
+    pub path: String,
+    /// 1-based line of the definition
+    pub line: usize,
+}
+
+/// One difference between desired and live state
+#[derive(Debug, Clone, PartialEq)]
+pub struct Drift {
+    /// The difference, from desired (old) to live (new): `-` is missing from
+    /// the live state, `+` exists only there
+    pub change: Change,
+    /// Where the desired value is defined, if it could be found
+    pub site: Option<Site>,
+}
+
+impl fmt::Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.change)?;
+        match &self.site {
+            Some(site) if site.path == self.change.path() => write!(f, " (line {})", site.line),
+            Some(site) => write!(f, " (in {}, line {})", site.path, site.line),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Differences between `desired` and `live`, located in `source`
+pub fn detect(desired: &Value, live: &Value, source: &str, options: &Options) -> Vec<Drift> {
+    diff::diff(desired, live)
+        .into_iter()
+        .filter(|change| options.include_extra || !matches!(change, Change::Added { .. }))
+        .filter(|change| !options.ignore.iter().any(|p| p.matches(change.path())))
+        .map(|change| Drift {
+            site: locate(source, change.path()),
+            change,
+        })
+        .collect()
+}
+
+/// Find where the field at `path` is defined in `source`
+///
+/// Returns the deepest definition on the way to `path`, or `None` if not even
+/// its first segment is defined in a record literal.
+pub fn locate(source: &str, path: &str) -> Option<Site> {
+    let segments = pattern::split(path)?;
+    let mut text = source;
+    let mut depth = 0;
+    let mut found = None;
+
+    'records: while depth < segments.len() {
+        let Some(record) = Record::split(text) else {
+            break;
+        };
+        for field in record.fields {
+            let Some(key) = pattern::split(&merge::field_key(field)) else {
+                continue;
+            };
+            if key.is_empty() || !segments[depth..].starts_with(&key) {
+                continue;
+            }
+
+            let code = merge::code_chars(field);
+            let start = offset(source, field) + code.first().map_or(0, |&(pos, _)| pos);
+            depth += key.len();
+            found = Some(Site {
+                path: json::join_path(&segments[..depth]),
+                line: source[..start].matches('\n').count() + 1,
+            });
+
+            // Follow the field's value if it is a record literal
+            match code.iter().find(|&&(_, c)| c == '=') {
+                Some(&(eq, _)) => {
+                    text = &field[eq + 1..];
+                    continue 'records;
+                }
+                None => break 'records,
+            }
+        }
+        break;
+    }
+
+    found
+}
+
+/// Byte offset of `part`, a slice of `source`, within `source`
+fn offset(source: &str, part: &str) -> usize {
+    part.as_ptr() as usize - source.as_ptr() as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_locate_follows_records() {
+        let source = r#"let base = import "base.ncl" in
+{
+  # the web tier
+  web = {
+    replicas | Number = 3,
+    image.tag = "v1",
+  },
+  "db.host" = "db",
+  worker = base.worker & { threads = 4 },
+}
+"#;
+        let site = |path| locate(source, path).map(|s| (s.path, s.line));
+        assert_eq!(site("web"), Some(("web".to_string(), 4)));
+        assert_eq!(site("web.replicas"), Some(("web.replicas".to_string(), 5)));
+        assert_eq!(
+            site("web.image.tag"),
+            Some(("web.image.tag".to_string(), 6))
+        );
+        assert_eq!(site("\"db.host\""), Some(("\"db.host\"".to_string(), 8)));
+        assert_eq!(
+            site("worker.threads"),
+            Some(("worker.threads".to_string(), 9))
+        );
+        assert_eq!(site("worker.queue"), Some(("worker".to_string(), 9)));
+        assert_eq!(site("missing"), None);
+    }
+
+    #[test]
+    fn test_detect_filters_extra_and_ignored() {
+        let desired = json!({ "spec": { "replicas": 3, "image": "web:v1" } });
+        let live = json!({
+            "spec": { "replicas": 2, "image": "web:v2" },
+            "status": { "ready": 2 },
+        });
+        let source = "{ spec = { replicas = 3, image = \"web:v1\" } }";
+
+        let options = Options {
+            include_extra: false,
+            ignore: vec![PathPattern::parse("spec.image").unwrap()],
+        };
+        let drifts = detect(&desired, &live, source, &options);
+        assert_eq!(drifts.len(), 1);
+        assert_eq!(drifts[0].change.path(), "spec.replicas");
+        assert_eq!(drifts[0].site.as_ref().map(|s| s.line), Some(1));
+
+        let options = Options {
+            include_extra: true,
+            ignore: Vec::new(),
+        };
+        let drifts = detect(&desired, &live, source, &options);
+        let paths: Vec<&str> = drifts.iter().map(|d| d.change.path()).collect();
+        assert_eq!(paths, ["spec.image", "spec.replicas", "status"]);
+        assert_eq!(drifts[2].site, None);
+    }
+}
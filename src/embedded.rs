@@ -0,0 +1,232 @@
+//! Config trees embedded in the binary
+//!
+//! A Rust application can ship its whole Nickel config tree inside its
+//! executable, with `include_str!` or (with the `embedded` feature) an
+//! [`include_dir::Dir`], and evaluate it at runtime with imports between the
+//! embedded files resolving as they would on disk.
+//!
+//! Nickel reads imports from the file system, so [`EmbeddedSources`] writes
+//! the tree to a private temporary directory when it is built, and removes
+//! it when dropped. Diagnostics name files by their embedded path
+//! (`lib/base.ncl`), not by their temporary location.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::embedded::EmbeddedSources;
+//! use bunsenite::NickelLoader;
+//!
+//! static CONFIG: &[(&str, &str)] = &[
+//!     ("main.ncl", r#"(import "lib/base.ncl") & { env = "prod" }"#),
+//!     ("lib/base.ncl", r#"{ replicas = 3, env | default = "dev" }"#),
+//! ];
+//!
+//! let sources = EmbeddedSources::new(CONFIG.iter().copied()).unwrap();
+//! let value = sources.parse(&NickelLoader::new(), "main.ncl").unwrap();
+//! assert_eq!(value["replicas"], 3);
+//! assert_eq!(value["env"], "prod");
+//! ```
+//!
+//! With the `embedded` feature, a directory can be embedded whole:
+//!
+//! ```text
+//! static CONFIG: include_dir::Dir = include_dir::include_dir!("$CARGO_MANIFEST_DIR/config");
+//!
+//! let sources = EmbeddedSources::from_dir(&CONFIG)?;
+//! ```
+
+use crate::arena::Document;
+use crate::error::{Error, Result};
+use crate::loader::NickelLoader;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Distinguishes the directories of embedded trees in one process
+static TREES: AtomicU64 = AtomicU64::new(0);
+
+/// An embedded config tree, ready to evaluate
+#[derive(Debug)]
+pub struct EmbeddedSources {
+    dir: PathBuf,
+    files: BTreeSet<String>,
+}
+
+impl EmbeddedSources {
+    /// Embed `files`, given as (relative path, contents) pairs
+    ///
+    /// Paths use `/` as separator. Contents are usually Nickel source, but
+    /// may be anything Nickel imports (JSON, YAML, TOML, text).
+    ///
+    /// # Errors
+    ///
+    /// Returns an invalid-input error for paths that are empty, absolute,
+    /// contain `..` or appear twice, or an I/O error if the tree cannot be
+    /// written
+    pub fn new<P, C>(files: impl IntoIterator<Item = (P, C)>) -> Result<Self>
+    where
+        P: AsRef<str>,
+        C: AsRef<[u8]>,
+    {
+        let mut sources = Self {
+            dir: std::env::temp_dir().join(format!(
+                "bunsenite-embedded-{}-{}",
+                std::process::id(),
+                TREES.fetch_add(1, Ordering::Relaxed)
+            )),
+            files: BTreeSet::new(),
+        };
+        std::fs::create_dir_all(&sources.dir)?;
+        for (path, contents) in files {
+            sources.add(path.as_ref(), contents.as_ref())?;
+        }
+        Ok(sources)
+    }
+
+    /// Embed every file below an [`include_dir::Dir`]
+    ///
+    /// # Errors
+    ///
+    /// As [`new`](Self::new)
+    #[cfg(feature = "embedded")]
+    pub fn from_dir(dir: &include_dir::Dir<'_>) -> Result<Self> {
+        fn collect<'a>(dir: &'a include_dir::Dir<'a>, files: &mut Vec<(String, &'a [u8])>) {
+            for file in dir.files() {
+                let path = file.path().to_string_lossy().replace('\\', "/");
+                files.push((path, file.contents()));
+            }
+            for dir in dir.dirs() {
+                collect(dir, files);
+            }
+        }
+
+        let mut files = Vec::new();
+        collect(dir, &mut files);
+        Self::new(files)
+    }
+
+    fn add(&mut self, path: &str, contents: &[u8]) -> Result<()> {
+        let safe = !path.is_empty()
+            && Path::new(path)
+                .components()
+                .all(|c| matches!(c, Component::Normal(_)));
+        if !safe {
+            return Err(Error::invalid_input(format!(
+                "Embedded path '{}' must be relative and may not contain '..'",
+                path
+            )));
+        }
+        if !self.files.insert(path.to_string()) {
+            return Err(Error::invalid_input(format!(
+                "Embedded path '{}' is given more than once",
+                path
+            )));
+        }
+
+        let target = self.dir.join(path);
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(target, contents)?;
+        Ok(())
+    }
+
+    /// Embedded paths, sorted
+    pub fn files(&self) -> impl Iterator<Item = &str> {
+        self.files.iter().map(String::as_str)
+    }
+
+    /// Evaluate the embedded file at `entry` to JSON
+    ///
+    /// # Errors
+    ///
+    /// Returns an invalid-input error if nothing is embedded at `entry`, or
+    /// the evaluation error, with files named by their embedded paths
+    pub fn parse(&self, loader: &NickelLoader, entry: &str) -> Result<Value> {
+        let (source, name) = self.source(entry)?;
+        loader
+            .parse_string(&source, &name)
+            .map_err(|e| self.relabel(e))
+    }
+
+    /// Evaluate the embedded file at `entry` into an arena [`Document`]
+    ///
+    /// # Errors
+    ///
+    /// As [`parse`](Self::parse)
+    pub fn parse_document(&self, loader: &NickelLoader, entry: &str) -> Result<Document> {
+        let (source, name) = self.source(entry)?;
+        loader
+            .parse_document(&source, &name)
+            .map_err(|e| self.relabel(e))
+    }
+
+    /// The source at `entry`, named by its written path so that relative
+    /// imports resolve inside the tree
+    fn source(&self, entry: &str) -> Result<(String, String)> {
+        if !self.files.contains(entry) {
+            return Err(Error::invalid_input(format!(
+                "Nothing is embedded at '{}'",
+                entry
+            )));
+        }
+        let path = self.dir.join(entry);
+        Ok((std::fs::read_to_string(&path)?, path.display().to_string()))
+    }
+
+    /// Name files in an error by their embedded paths
+    fn relabel(&self, error: Error) -> Error {
+        let written = format!("{}{}", self.dir.display(), std::path::MAIN_SEPARATOR);
+        error.relabel(|text| text.replace(&written, ""))
+    }
+}
+
+impl Drop for EmbeddedSources {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_errors_name_embedded_paths() {
+        let sources = EmbeddedSources::new([
+            ("main.ncl", r#"import "lib/broken.ncl""#),
+            ("lib/broken.ncl", r#"{ a = 1 + "x" }"#),
+        ])
+        .unwrap();
+        assert_eq!(
+            sources.files().collect::<Vec<_>>(),
+            ["lib/broken.ncl", "main.ncl"]
+        );
+
+        let error = sources
+            .parse(&NickelLoader::new(), "main.ncl")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("main.ncl"), "{}", error);
+        assert!(
+            !error.contains(&sources.dir.display().to_string()),
+            "{}",
+            error
+        );
+        assert!(sources.parse(&NickelLoader::new(), "missing.ncl").is_err());
+    }
+
+    #[test]
+    fn test_rejects_unsafe_paths_and_cleans_up() {
+        assert!(EmbeddedSources::new([("../escape.ncl", "{}")]).is_err());
+        assert!(EmbeddedSources::new([("/etc/app.ncl", "{}")]).is_err());
+        assert!(EmbeddedSources::new([("a.ncl", "{}"), ("a.ncl", "{}")]).is_err());
+
+        let sources = EmbeddedSources::new([("a.ncl", "{}")]).unwrap();
+        let dir = sources.dir.clone();
+        assert!(dir.join("a.ncl").exists());
+        drop(sources);
+        assert!(!dir.exists());
+    }
+}
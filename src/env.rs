@@ -0,0 +1,99 @@
+//! Environment variables inside configs
+//!
+//! Nickel programs have no access to the environment. Backs
+//! `--env-prefix BUNSEN_`: the variables whose names start with the prefix
+//! are exposed to the program as an `env` record, with the prefix removed,
+//! so `BUNSEN_PORT=8080` is `env.PORT`. Values are strings; convert them in
+//! the config (`std.string.to_number env.PORT`).
+//!
+//! Variables are read once, when the prefix is registered with
+//! [`NickelLoader::with_env`](crate::NickelLoader::with_env), and the
+//! program is evaluated inside `let env = { .. } in`, which moves columns on
+//! its first line in diagnostics. A program's own `env` binding shadows the
+//! record.
+//!
+//! For hermetic builds, [`NickelLoader::with_hermetic`](crate::NickelLoader::with_hermetic)
+//! (`--no-env`) makes `env` an empty record whatever the prefix, and stops
+//! the loader itself from consulting `CI` (see [`crate::threads`]), so the
+//! output cannot depend on the environment. `exec:` transforms are host
+//! programs and keep theirs.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::NickelLoader;
+//!
+//! std::env::set_var("DOCTEST_PORT", "8080");
+//! let loader = NickelLoader::new().with_env("DOCTEST_");
+//! let config = loader
+//!     .parse_string(r#"{ port = std.string.to_number env.PORT }"#, "app.ncl")
+//!     .unwrap();
+//! assert_eq!(config["port"], 8080);
+//! ```
+
+use crate::source;
+use std::collections::BTreeMap;
+
+/// The environment variables whose names start with `prefix`, by full name
+///
+/// A variable named just `prefix`, and variables whose name or value is not
+/// valid Unicode, are left out.
+pub fn capture(prefix: &str) -> BTreeMap<String, String> {
+    std::env::vars_os()
+        .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+        .filter(|(name, _)| name.len() > prefix.len() && name.starts_with(prefix))
+        .collect()
+}
+
+/// Rewrite a program to evaluate with `vars`, named without `prefix`, bound
+/// to `env`
+pub(crate) fn apply(program: &str, prefix: &str, vars: &BTreeMap<String, String>) -> String {
+    let fields: Vec<String> = vars
+        .iter()
+        .map(|(name, value)| {
+            format!(
+                "{} = {}",
+                source::field_name(&name[prefix.len()..]),
+                source::string_literal(value)
+            )
+        })
+        .collect();
+    if fields.is_empty() {
+        format!("let env = {{}} in {}", program)
+    } else {
+        format!("let env = {{ {} }} in {}", fields.join(", "), program)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NickelLoader;
+
+    #[test]
+    fn test_capture() {
+        std::env::set_var("BUNSENITE_TEST_CAPTURE_HOST", "db");
+        std::env::set_var("BUNSENITE_TEST_CAPTURE_", "bare");
+        let vars = capture("BUNSENITE_TEST_CAPTURE_");
+        assert_eq!(vars.len(), 1);
+        assert_eq!(vars["BUNSENITE_TEST_CAPTURE_HOST"], "db");
+    }
+
+    #[test]
+    fn test_env_record() {
+        std::env::set_var("BUNSENITE_TEST_RECORD_HOST", "db\"1");
+        std::env::set_var("BUNSENITE_TEST_RECORD_app.name", "web");
+        let source = r#"{ host = env.HOST, name = env."app.name" }"#;
+        let loader = NickelLoader::new().with_env("BUNSENITE_TEST_RECORD_");
+        let config = loader.parse_string(source, "app.ncl").unwrap();
+        assert_eq!(config["host"], "db\"1");
+        assert_eq!(config["name"], "web");
+
+        // Hermetic: the record is empty, so reading a variable fails
+        let hermetic = loader.with_hermetic(true);
+        assert!(hermetic.parse_string(source, "app.ncl").is_err());
+        let source = r#"{ has_host = std.record.has_field "HOST" env }"#;
+        let config = hermetic.parse_string(source, "app.ncl").unwrap();
+        assert_eq!(config["has_host"], false);
+    }
+}
@@ -4,6 +4,7 @@
 //! Errors are designed to be informative and actionable for end users.
 
 use std::fmt;
+use std::path::Path;
 
 /// Result type alias for Bunsenite operations
 pub type Result<T> = std::result::Result<T, Error>;
@@ -18,6 +19,8 @@ pub enum Error {
         file: String,
         /// Error message from the parser
         message: String,
+        /// Byte offsets of the source span the engine blamed, if any
+        span: Option<(usize, usize)>,
     },
 
     /// Nickel evaluation error
@@ -27,6 +30,19 @@ pub enum Error {
         file: String,
         /// Error message from the evaluator
         message: String,
+        /// Byte offsets of the source span the engine blamed, if any
+        span: Option<(usize, usize)>,
+    },
+
+    /// Nickel static typechecking error
+    #[error("Type error in '{file}': {message}")]
+    TypeError {
+        /// Name of the file that failed to typecheck
+        file: String,
+        /// Error message from the typechecker
+        message: String,
+        /// Byte offsets of the source span the engine blamed, if any
+        span: Option<(usize, usize)>,
     },
 
     /// Serialization error (converting Nickel values to JSON)
@@ -41,6 +57,42 @@ pub enum Error {
     #[error("Invalid input: {0}")]
     InvalidInput(String),
 
+    /// The embedded Nickel version does not satisfy a required version
+    #[error("Embedded Nickel {embedded} does not satisfy the required version '{required}'")]
+    UnsupportedNickelVersion {
+        /// Version requirement that was not met
+        required: String,
+        /// Nickel language version embedded in this build
+        embedded: String,
+    },
+
+    /// Output contains paths denied by `--restrict` rules
+    #[error("Output for target '{target}' contains restricted paths: {}", .paths.join(", "))]
+    RestrictedOutput {
+        /// Output target that was checked, e.g. `json`
+        target: String,
+        /// Denied paths present in the output
+        paths: Vec<String>,
+    },
+
+    /// The process ran into a resource limit set with `--rlimit-*`
+    /// ([`Limits`](crate::limits::Limits)), or an evaluation into one set
+    /// with `--max-depth`, `--max-memory` or `--timeout`
+    /// ([`NickelLoader::with_max_depth`](crate::NickelLoader::with_max_depth)
+    /// and its siblings)
+    #[error("Resource limit exceeded: {resource} ({limit})")]
+    ResourceLimit {
+        /// Limited resource, e.g. `CPU time` or `nesting depth`
+        resource: String,
+        /// The limit, e.g. `30 s` or `2G`
+        limit: String,
+    },
+
+    /// Work stopped early on SIGINT or SIGTERM
+    /// ([`Shutdown`](crate::interrupt::Shutdown))
+    #[error("Interrupted: {0}")]
+    Interrupted(String),
+
     /// Internal error (should not happen in normal operation)
     #[error("Internal error: {0}")]
     Internal(String),
@@ -48,18 +100,38 @@ pub enum Error {
 
 impl Error {
     /// Create a new parse error
+    ///
+    /// The span Nickel blamed is kept from `message` (see [`Error::span`]).
     pub fn parse_error(file: impl Into<String>, message: impl Into<String>) -> Self {
+        let message = message.into();
         Error::ParseError {
             file: file.into(),
-            message: message.into(),
+            span: span_in(&message),
+            message,
         }
     }
 
     /// Create a new evaluation error
+    ///
+    /// The span Nickel blamed is kept from `message` (see [`Error::span`]).
     pub fn evaluation_error(file: impl Into<String>, message: impl Into<String>) -> Self {
+        let message = message.into();
         Error::EvaluationError {
             file: file.into(),
-            message: message.into(),
+            span: span_in(&message),
+            message,
+        }
+    }
+
+    /// Create a new type error
+    ///
+    /// The span Nickel blamed is kept from `message` (see [`Error::span`]).
+    pub fn type_error(file: impl Into<String>, message: impl Into<String>) -> Self {
+        let message = message.into();
+        Error::TypeError {
+            file: file.into(),
+            span: span_in(&message),
+            message,
         }
     }
 
@@ -73,6 +145,14 @@ impl Error {
         Error::InvalidInput(message.into())
     }
 
+    /// Create a new resource limit error
+    pub fn resource_limit(resource: impl Into<String>, limit: impl Into<String>) -> Self {
+        Error::ResourceLimit {
+            resource: resource.into(),
+            limit: limit.into(),
+        }
+    }
+
     /// Create a new internal error
     pub fn internal(message: impl Into<String>) -> Self {
         Error::Internal(message.into())
@@ -85,7 +165,11 @@ impl Error {
     pub fn is_recoverable(&self) -> bool {
         matches!(
             self,
-            Error::ParseError { .. } | Error::InvalidInput(_) | Error::EvaluationError { .. }
+            Error::ParseError { .. }
+                | Error::InvalidInput(_)
+                | Error::EvaluationError { .. }
+                | Error::TypeError { .. }
+                | Error::RestrictedOutput { .. }
         )
     }
 
@@ -94,12 +178,141 @@ impl Error {
         match self {
             Error::ParseError { .. } => Some("Check your Nickel syntax. Run 'nickel check' for detailed diagnostics."),
             Error::EvaluationError { .. } => Some("Ensure all variables are defined and types match."),
+            Error::TypeError { .. } => Some("Fix the annotated types, or drop the annotation. Run 'bunsenite typecheck' to list every type error."),
             Error::InvalidInput(_) => Some("Check the input format and try again."),
+            Error::UnsupportedNickelVersion { .. } => Some("Use a bunsenite build embedding a matching Nickel version. Run 'bunsenite info' to see the embedded version."),
+            Error::RestrictedOutput { .. } => Some("Remove the restricted values from the config, or write them to a target the restriction does not cover."),
             Error::SerializationError(_) => Some("Ensure the Nickel program produces valid JSON-serializable values."),
             Error::IoError(_) => Some("Check file permissions and path."),
+            Error::ResourceLimit { .. } => Some("Raise the limit (--max-depth, --max-memory, --timeout or --rlimit-*) or look for runaway recursion in the config."),
+            Error::Interrupted(_) => Some("Run the command again to finish; nothing was left half-written."),
             Error::Internal(_) => Some("This is a bug. Please report it at: https://gitlab.com/campaign-for-cooler-coding-and-programming/bunsenite/-/issues"),
         }
     }
+
+    /// A stable, machine-readable name for the kind of error, e.g. `type`
+    ///
+    /// Used as the `code` of [`Diagnostic`](crate::diagnostic::Diagnostic)s.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::ParseError { .. } => "parse",
+            Error::EvaluationError { .. } => "evaluation",
+            Error::TypeError { .. } => "type",
+            Error::SerializationError(_) => "serialization",
+            Error::IoError(_) => "io",
+            Error::InvalidInput(_) => "invalid_input",
+            Error::UnsupportedNickelVersion { .. } => "unsupported_nickel_version",
+            Error::RestrictedOutput { .. } => "restricted_output",
+            Error::ResourceLimit { .. } => "resource_limit",
+            Error::Interrupted(_) => "interrupted",
+            Error::Internal(_) => "internal",
+        }
+    }
+
+    /// This error with paths below the project at `root` written relative
+    /// to it, in file names and messages
+    ///
+    /// For diagnostics that read the same wherever the project is checked
+    /// out; see [`paths::relative_to`](crate::paths::relative_to).
+    pub fn relative_to(self, root: &Path) -> Self {
+        let relabel = |text: &str| crate::paths::relative_text(text, root);
+        match self {
+            Error::InvalidInput(message) => Error::InvalidInput(relabel(&message)),
+            Error::Internal(message) => Error::Internal(relabel(&message)),
+            other => other.relabel(relabel),
+        }
+    }
+
+    /// This error with `relabel` applied to the file name and message of a
+    /// parse, evaluation or type error, keeping its span
+    pub(crate) fn relabel(self, relabel: impl Fn(&str) -> String) -> Self {
+        match self {
+            Error::ParseError {
+                file,
+                message,
+                span,
+            } => Error::ParseError {
+                file: relabel(&file),
+                message: relabel(&message),
+                span,
+            },
+            Error::EvaluationError {
+                file,
+                message,
+                span,
+            } => Error::EvaluationError {
+                file: relabel(&file),
+                message: relabel(&message),
+                span,
+            },
+            Error::TypeError {
+                file,
+                message,
+                span,
+            } => Error::TypeError {
+                file: relabel(&file),
+                message: relabel(&message),
+                span,
+            },
+            other => other,
+        }
+    }
+
+    /// Byte offsets of the source span Nickel blamed for this parse,
+    /// evaluation or type error, if it named one
+    ///
+    /// The span is kept when the error is created, so it survives
+    /// [`relative_to`](Error::relative_to) and other rewrites of the message.
+    pub fn span(&self) -> Option<(usize, usize)> {
+        match self {
+            Error::ParseError { span, .. }
+            | Error::EvaluationError { span, .. }
+            | Error::TypeError { span, .. } => *span,
+            _ => None,
+        }
+    }
+
+    /// 1-based line and column in `source` where [`Error::span`] starts
+    pub fn location(&self, source: &str) -> Option<(usize, usize)> {
+        let (start, _) = self.span()?;
+        line_column(source, start)
+    }
+}
+
+/// The first span in an engine message
+///
+/// Nickel reports spans as `start: ByteIndex(..), end: ByteIndex(..)`.
+fn span_in(message: &str) -> Option<(usize, usize)> {
+    fn index_after(message: &str, marker: &str) -> Option<(usize, usize)> {
+        let at = message.find(marker)? + marker.len();
+        let digits = message[at..]
+            .find(|c: char| !c.is_ascii_digit())
+            .map_or(&message[at..], |i| &message[at..at + i]);
+        Some((digits.parse().ok()?, at + digits.len()))
+    }
+
+    let (start, rest) = index_after(message, "start: ByteIndex(")?;
+    let (end, _) = index_after(&message[rest..], "end: ByteIndex(")?;
+    (start <= end).then_some((start, end))
+}
+
+/// 1-based line and column of byte `offset` in `source`
+pub(crate) fn line_column(source: &str, offset: usize) -> Option<(usize, usize)> {
+    let before = source.get(..offset)?;
+    let line = before.matches('\n').count() + 1;
+    let column = before
+        .rfind('\n')
+        .map_or(before, |newline| &before[newline + 1..])
+        .chars()
+        .count()
+        + 1;
+    Some((line, column))
+}
+
+impl serde::ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error::serialization_error(msg.to_string())
+    }
 }
 
 #[cfg(test)]
@@ -127,4 +340,45 @@ mod tests {
         assert!(Error::invalid_input("msg").is_recoverable());
         assert!(!Error::internal("msg").is_recoverable());
     }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_relative_to() {
+        let err = Error::evaluation_error(
+            "/src/app/configs/app.ncl",
+            "missing field in /src/app/lib/base.ncl, imported from /etc/shared.ncl",
+        )
+        .relative_to(Path::new("/src/app"));
+        assert_eq!(
+            err.to_string(),
+            "Failed to evaluate Nickel program 'configs/app.ncl': missing field in lib/base.ncl, imported from /etc/shared.ncl"
+        );
+    }
+
+    #[test]
+    fn test_span_and_location() {
+        let err = Error::type_error(
+            "app.ncl",
+            "TypecheckError(TypeMismatch { span: RawSpan { src_id: FileId(0), start: ByteIndex(14), end: ByteIndex(18) } })",
+        );
+        assert!(err.is_recoverable());
+        assert_eq!(err.span(), Some((14, 18)));
+        assert_eq!(err.location("{}"), None);
+        assert_eq!(
+            err.location("{\n  port = 1, name : Number = \"x\" }"),
+            Some((2, 13))
+        );
+        assert_eq!(
+            Error::parse_error("app.ncl", "unexpected token").span(),
+            None
+        );
+
+        // Rewriting the message keeps the span
+        let relabeled = err.relabel(|text| text.replace("ByteIndex", "Offset"));
+        assert_eq!(relabeled.span(), Some((14, 18)));
+        assert_eq!(
+            Error::invalid_input("start: ByteIndex(1), end: ByteIndex(2)").span(),
+            None
+        );
+    }
 }
@@ -0,0 +1,590 @@
+//! Walking a failing evaluation back to its cause
+//!
+//! Backs `bunsenite why-error`. A contract failure in a deeply merged record
+//! names the contract and the value, but not which of the merged parts set
+//! the value, or whether it replaced a default. The [`Explainer`] narrows
+//! this down by evaluating pieces of the program:
+//!
+//! - a program of the form `let ... in a & b & c` is split into its parts;
+//!   the first prefix (`a`, `a & b`, ...) that fails points at the part
+//!   that breaks it, either because the part fails on its own or because
+//!   merging it into the earlier parts does, in which case the fields it
+//!   sets over earlier values (and earlier defaults) are listed
+//! - otherwise, imported Nickel files that fail on their own are the cause,
+//!   and if there are none, the error is raised in the program itself
+//!
+//! Each part and import is a [`Node`] that can be explained in turn, so the
+//! chain can be walked interactively ([`Explainer::walk`]) or followed to
+//! the end along the likely causes ([`Explainer::trace`]).
+//!
+//! The split is lexical, like [`crate::lint`]: only a merge at the top of the
+//! program (after any `let` bindings) is split, and a part that refers to
+//! fields defined by other parts fails on its own too.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::explain::Explainer;
+//! use bunsenite::NickelLoader;
+//!
+//! let source = "let base = { port | Number | default = 80 } in\n\
+//!               base & { name = \"app\" } & { port = \"eighty\" }";
+//! let explainer = Explainer::new(NickelLoader::new());
+//! let root = explainer.source(source, "app.ncl");
+//! let explanation = explainer.explain(&root);
+//! assert_eq!(explanation.culprit, Some(2));
+//! assert!(explanation.notes.iter().any(|note| note.contains("`port`")));
+//! ```
+
+use crate::error::Result;
+use crate::imports;
+use crate::json;
+use crate::lexer::{self, Token};
+use crate::loader::NickelLoader;
+use crate::paths;
+use serde_json::Value;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+/// Overlapping fields listed at most for one merge
+const MAX_OVERLAPS: usize = 10;
+
+/// Nodes followed at most by [`Explainer::trace`], against import cycles
+const MAX_TRACE: usize = 64;
+
+/// Characters of a part's source shown in its label
+const EXCERPT_CHARS: usize = 40;
+
+/// Characters of an error shown under a node, before `e` shows all of it
+const SUMMARY_CHARS: usize = 160;
+
+/// A program in the evaluation chain: a file, or one part of a merge
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    /// The file's path, or an excerpt of the part's source
+    pub label: String,
+    /// Why the node fails when evaluated on its own, if it does
+    pub error: Option<String>,
+    source: String,
+    name: String,
+}
+
+/// What was found about one [`Node`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Explanation {
+    /// Findings, one sentence each
+    pub notes: Vec<String>,
+    /// The merge parts or imports the node is made of
+    pub children: Vec<Node>,
+    /// Index of the child most likely responsible for the error
+    pub culprit: Option<usize>,
+}
+
+impl Explanation {
+    /// `node` with its error, the notes and the numbered children, as shown
+    /// by [`Explainer::walk`]
+    pub fn render(&self, node: &Node) -> String {
+        let mut out = match &node.error {
+            Some(error) => format!("\n✗ {}\n  {}\n", node.label, summary(error)),
+            None => format!("\n✓ {}\n", node.label),
+        };
+        for note in &self.notes {
+            out.push_str(&format!("  {}\n", note));
+        }
+        if !self.children.is_empty() {
+            out.push('\n');
+        }
+        for (index, child) in self.children.iter().enumerate() {
+            let mark = if child.error.is_some() { "✗" } else { "✓" };
+            let cause = if self.culprit == Some(index) {
+                "  <- likely cause"
+            } else {
+                ""
+            };
+            out.push_str(&format!(
+                "  {}. {} {}{}\n",
+                index + 1,
+                mark,
+                child.label,
+                cause
+            ));
+        }
+        out
+    }
+}
+
+/// Explains evaluation failures
+#[derive(Debug, Clone)]
+pub struct Explainer {
+    loader: NickelLoader,
+}
+
+impl Explainer {
+    /// Create an explainer evaluating with `loader`
+    pub fn new(loader: NickelLoader) -> Self {
+        Self { loader }
+    }
+
+    /// The node for a file, evaluated
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read
+    pub fn file(&self, path: &Path) -> Result<Node> {
+        let source = std::fs::read_to_string(paths::to_open(path))?;
+        let name = path.display().to_string();
+        Ok(self.node(name.clone(), source, name))
+    }
+
+    /// The node for `source`, evaluated; imports resolve relative to `name`
+    pub fn source(&self, source: &str, name: &str) -> Node {
+        self.node(name.to_string(), source.to_string(), name.to_string())
+    }
+
+    fn node(&self, label: String, source: String, name: String) -> Node {
+        let error = self
+            .loader
+            .parse_string(&source, &name)
+            .err()
+            .map(|e| e.to_string());
+        Node {
+            label,
+            error,
+            source,
+            name,
+        }
+    }
+
+    /// Explain why `node` fails, from its merge parts or its imports
+    pub fn explain(&self, node: &Node) -> Explanation {
+        if node.error.is_none() {
+            return Explanation {
+                notes: vec!["Evaluates without error".to_string()],
+                children: Vec::new(),
+                culprit: None,
+            };
+        }
+        split_merge(&node.source)
+            .filter(|merge| merge.parts.len() > 1)
+            .and_then(|merge| self.explain_merge(node, &merge))
+            .unwrap_or_else(|| self.explain_imports(node))
+    }
+
+    fn explain_merge(&self, node: &Node, merge: &Merge) -> Option<Explanation> {
+        let prefix = |count: usize| {
+            let parts: Vec<String> = merge.parts[..count]
+                .iter()
+                .map(|part| format!("({})", part))
+                .collect();
+            format!("{}{}", merge.preamble, parts.join("\n& "))
+        };
+        let failing = (1..=merge.parts.len()).find(|&count| {
+            self.loader
+                .parse_string(&prefix(count), &node.name)
+                .is_err()
+        })?;
+        let index = failing - 1;
+
+        let children: Vec<Node> = merge
+            .parts
+            .iter()
+            .map(|part| self.part(node, merge, part))
+            .collect();
+        let culprit = &children[index];
+        let mut notes = vec![format!("It merges {} parts", children.len())];
+        if culprit.error.is_some() || index == 0 {
+            notes.push(format!(
+                "Part {} ({}) fails on its own",
+                failing, culprit.label
+            ));
+            return Some(Explanation {
+                notes,
+                children,
+                culprit: Some(index),
+            });
+        }
+
+        let earlier = if index == 1 {
+            "part 1".to_string()
+        } else {
+            format!("parts 1-{}", index)
+        };
+        notes.push(format!(
+            "Part {} evaluates on its own, but merging it into {} fails",
+            failing, earlier
+        ));
+        let before = self.loader.parse_string(&prefix(index), &node.name);
+        let part = self.loader.parse_string(&culprit.source, &culprit.name);
+        if let (Ok(before), Ok(part)) = (before, part) {
+            let defaults = self
+                .loader
+                .default_paths(&prefix(index), &node.name)
+                .unwrap_or_default();
+            let mut leaves = Vec::new();
+            leaf_paths(&part, String::new(), &mut leaves);
+            for path in leaves
+                .into_iter()
+                .filter(|path| json::select(&before, path).is_some())
+                .take(MAX_OVERLAPS)
+            {
+                let is_default = defaults
+                    .iter()
+                    .any(|default| path == *default || path.starts_with(&format!("{}.", default)));
+                notes.push(format!(
+                    "Part {} sets `{}`, already set by {}{}",
+                    failing,
+                    path,
+                    earlier,
+                    if is_default { " (as a default)" } else { "" }
+                ));
+            }
+        }
+        Some(Explanation {
+            notes,
+            children,
+            culprit: Some(index),
+        })
+    }
+
+    /// The node for one merge part: the file for a bare `import "..."`,
+    /// the part with the program's `let` bindings otherwise
+    fn part(&self, node: &Node, merge: &Merge, part: &str) -> Node {
+        let import = part
+            .strip_prefix("import")
+            .map(str::trim)
+            .filter(|rest| rest.starts_with('"') && rest.ends_with('"'))
+            .and_then(|_| imports::scan(part).pop());
+        if let Some(import) = import {
+            if let Ok(file) = self.file(&resolve(&node.name, &import)) {
+                return file;
+            }
+        }
+        self.node(
+            excerpt(part),
+            format!("{}{}", merge.preamble, part),
+            node.name.clone(),
+        )
+    }
+
+    fn explain_imports(&self, node: &Node) -> Explanation {
+        let children: Vec<Node> = imports::scan(&node.source)
+            .iter()
+            .filter(|import| {
+                Path::new(import)
+                    .extension()
+                    .map_or(true, |extension| extension == "ncl")
+            })
+            .filter_map(|import| self.file(&resolve(&node.name, import)).ok())
+            .collect();
+        let culprit = children.iter().position(|child| child.error.is_some());
+        let note = match culprit {
+            Some(index) => format!("Import {} fails on its own", children[index].label),
+            None if children.is_empty() => "The error is raised here".to_string(),
+            None => "Its imports evaluate on their own, so the error is raised here".to_string(),
+        };
+        Explanation {
+            notes: vec![note],
+            children,
+            culprit,
+        }
+    }
+
+    /// Follow the likely causes from `root` down to where the error is
+    /// raised, explaining each node on the way
+    pub fn trace(&self, root: Node) -> Vec<(Node, Explanation)> {
+        let mut steps = Vec::new();
+        let mut next = Some(root);
+        while let Some(node) = next.take() {
+            let explanation = self.explain(&node);
+            next = explanation
+                .culprit
+                .map(|index| explanation.children[index].clone());
+            steps.push((node, explanation));
+            if steps.len() == MAX_TRACE {
+                break;
+            }
+        }
+        steps
+    }
+
+    /// Walk the chain from `root` interactively, reading choices from
+    /// `input` until `q` or the end of the input
+    ///
+    /// A number walks into that part or import, an empty line into the
+    /// likely cause, `u` back up, and `e` prints the node's full error.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading choices or writing to `output` fails
+    pub fn walk<R: BufRead, W: Write>(
+        &self,
+        root: Node,
+        mut input: R,
+        mut output: W,
+    ) -> Result<()> {
+        let explanation = self.explain(&root);
+        let mut stack = vec![(root, explanation)];
+        let mut show = true;
+        loop {
+            let (node, explanation) = stack.last().expect("the root is never popped");
+            if show {
+                write!(output, "{}", explanation.render(node))?;
+            }
+            show = true;
+            if explanation.children.is_empty() {
+                write!(output, "\nu: up, e: full error, q: quit > ")?;
+            } else {
+                write!(
+                    output,
+                    "\n1-{}: walk into, Enter: likely cause, u: up, e: full error, q: quit > ",
+                    explanation.children.len()
+                )?;
+            }
+            output.flush()?;
+
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                writeln!(output)?;
+                return Ok(());
+            }
+            let choice = line.trim();
+            let target = match choice {
+                "q" | "quit" => return Ok(()),
+                "u" | "up" => {
+                    if stack.len() > 1 {
+                        stack.pop();
+                    } else {
+                        writeln!(output, "Already at the top")?;
+                        show = false;
+                    }
+                    continue;
+                }
+                "e" | "error" => {
+                    writeln!(output, "{}", node.error.as_deref().unwrap_or("No error"))?;
+                    show = false;
+                    continue;
+                }
+                "" => explanation.culprit,
+                _ => choice.parse::<usize>().ok().and_then(|n| n.checked_sub(1)),
+            };
+            match target
+                .and_then(|index| explanation.children.get(index))
+                .cloned()
+            {
+                Some(child) => {
+                    let explanation = self.explain(&child);
+                    stack.push((child, explanation));
+                }
+                None if choice.is_empty() => {
+                    writeln!(output, "Nothing more to walk into")?;
+                    show = false;
+                }
+                None => {
+                    writeln!(output, "No such part: '{}'", choice)?;
+                    show = false;
+                }
+            }
+        }
+    }
+}
+
+/// A program split into its leading `let` bindings and the parts of its
+/// top-level merge
+#[derive(Debug)]
+struct Merge {
+    preamble: String,
+    parts: Vec<String>,
+}
+
+/// Split `source` at its top-level `&`s, or `None` if its body is anything
+/// other than a plain merge
+fn split_merge(source: &str) -> Option<Merge> {
+    let tokens = lexer::tokens(source).ok()?;
+
+    // Leading `let ... in`s, each to the `in` matching its `let`
+    let mut start = 0;
+    loop {
+        let next = start
+            + tokens[start..]
+                .iter()
+                .take_while(|token| is_trivia(token))
+                .count();
+        if !tokens.get(next).is_some_and(|token| token.is_word("let")) {
+            break;
+        }
+        let mut depth = 0i32;
+        let mut lets = 0i32;
+        let mut end = None;
+        for (index, token) in tokens.iter().enumerate().skip(next) {
+            match token {
+                Token::Punct('{' | '[' | '(') => depth += 1,
+                Token::Punct('}' | ']' | ')') => depth -= 1,
+                token if depth == 0 && token.is_word("let") => lets += 1,
+                token if depth == 0 && token.is_word("in") => {
+                    lets -= 1;
+                    if lets == 0 {
+                        end = Some(index + 1);
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+        start = end?;
+    }
+
+    let mut parts = vec![String::new()];
+    let mut depth = 0i32;
+    for token in &tokens[start..] {
+        match token {
+            Token::Punct('{' | '[' | '(') => depth += 1,
+            Token::Punct('}' | ']' | ')') => depth -= 1,
+            Token::Op(op) if depth == 0 => {
+                if op != "&" {
+                    return None;
+                }
+                parts.push(String::new());
+                continue;
+            }
+            Token::Word(word)
+                if depth == 0 && matches!(word.as_str(), "let" | "fun" | "if" | "match") =>
+            {
+                return None;
+            }
+            _ => {}
+        }
+        if let Some(part) = parts.last_mut() {
+            part.push_str(&token.text());
+        }
+    }
+
+    let parts: Vec<String> = parts.iter().map(|part| part.trim().to_string()).collect();
+    if parts.iter().any(String::is_empty) {
+        return None;
+    }
+    let mut preamble: String = tokens[..start].iter().map(Token::text).collect();
+    if !preamble.is_empty() {
+        preamble.push('\n');
+    }
+    Some(Merge { preamble, parts })
+}
+
+fn is_trivia(token: &Token) -> bool {
+    matches!(token, Token::Space | Token::Newline | Token::Comment(_))
+}
+
+/// Paths of the non-record values in `value`
+fn leaf_paths(value: &Value, path: String, paths: &mut Vec<String>) {
+    match value.as_object() {
+        Some(record) if !record.is_empty() => {
+            for (key, value) in record {
+                leaf_paths(value, json::key_path(&path, key), paths);
+            }
+        }
+        _ if !path.is_empty() => paths.push(path),
+        _ => {}
+    }
+}
+
+/// `import` resolved against the directory of the file `name`
+fn resolve(name: &str, import: &str) -> PathBuf {
+    Path::new(name)
+        .parent()
+        .map_or_else(|| PathBuf::from(import), |dir| dir.join(import))
+}
+
+/// The first line of a part, shortened
+fn excerpt(part: &str) -> String {
+    let line = part.lines().next().unwrap_or_default().trim();
+    if line.chars().count() > EXCERPT_CHARS {
+        format!("{}…", line.chars().take(EXCERPT_CHARS).collect::<String>())
+    } else if line.len() < part.len() {
+        format!("{} …", line)
+    } else {
+        line.to_string()
+    }
+}
+
+/// The first line of an error, shortened
+fn summary(error: &str) -> String {
+    let line = error.lines().next().unwrap_or_default();
+    if line.chars().count() > SUMMARY_CHARS {
+        format!("{}…", line.chars().take(SUMMARY_CHARS).collect::<String>())
+    } else {
+        line.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_merge() {
+        let merge = split_merge(
+            "let a = { x = 1 } in\nlet b = let c = 2 in { y = c } in\na & b & { z = 1 & 2 }",
+        )
+        .unwrap();
+        assert_eq!(
+            merge.preamble,
+            "let a = { x = 1 } in\nlet b = let c = 2 in { y = c } in\n"
+        );
+        assert_eq!(merge.parts, vec!["a", "b", "{ z = 1 & 2 }"]);
+
+        assert!(split_merge("fun x => x & { a = 1 }").is_none());
+        assert!(split_merge("{ a = 1 } | { a | Number }").is_none());
+        assert_eq!(split_merge("{ a = 1 }").unwrap().parts.len(), 1);
+    }
+
+    #[test]
+    fn test_merge_over_default() {
+        let source = "let base = { port | Number | default = 80 } in\n\
+                      base & { name = \"app\" } & { port = \"eighty\" }";
+        let explainer = Explainer::new(NickelLoader::new());
+        let explanation = explainer.explain(&explainer.source(source, "app.ncl"));
+
+        assert_eq!(explanation.culprit, Some(2));
+        assert_eq!(explanation.children[0].label, "base");
+        assert!(explanation
+            .children
+            .iter()
+            .all(|child| child.error.is_none()));
+        assert_eq!(
+            explanation.notes,
+            vec![
+                "It merges 3 parts".to_string(),
+                "Part 3 evaluates on its own, but merging it into parts 1-2 fails".to_string(),
+                "Part 3 sets `port`, already set by parts 1-2 (as a default)".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_walk_into_failing_import() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("bad.ncl"), "{ port | Number = \"x\" }").unwrap();
+        let app = dir.path().join("app.ncl");
+        std::fs::write(&app, "{ name = \"a\" } & import \"bad.ncl\"").unwrap();
+
+        let explainer = Explainer::new(NickelLoader::new());
+        let root = explainer.file(&app).unwrap();
+        let steps = explainer.trace(root.clone());
+        assert_eq!(steps.len(), 2);
+        assert_eq!(steps[0].1.culprit, Some(1));
+        assert!(steps[1].0.label.ends_with("bad.ncl"));
+        assert_eq!(
+            steps[1].1.notes,
+            vec!["The error is raised here".to_string()]
+        );
+
+        let mut output = Vec::new();
+        explainer
+            .walk(root, "\nu\n7\nq\n".as_bytes(), &mut output)
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("  2. ✗ "));
+        assert!(output.contains("<- likely cause"));
+        assert!(output.contains("The error is raised here"));
+        assert!(output.contains("No such part: '7'"));
+    }
+}
@@ -0,0 +1,594 @@
+//! Evaluated configs written as YAML, TOML or JSON
+//!
+//! Backs `bunsenite export config.ncl --format yaml`: the whole evaluated
+//! config, written in one format, for tools such as Kubernetes and Ansible
+//! that read YAML. For several outputs from one config, see
+//! [`crate::exports`].
+//!
+//! One evaluation can be written in several formats at once
+//! (`--format json,yaml -o out/app` writes `out/app.json` and
+//! `out/app.yaml`), so a repository that needs YAML for Kubernetes and JSON
+//! for its application evaluates its config once, not once per format.
+//!
+//! Fields can choose how they are written (quoted, as a YAML block scalar,
+//! base64-encoded) with `Output` contracts; see [`crate::style`]. Records
+//! are written with their fields in name order, or in the order the config
+//! defines them ([`Options::with_source_order`], see [`crate::order`]).
+//! JSON is laid out as `serde_json` pretty-prints it unless
+//! [`Options::with_json_layout`] says otherwise, so a checked-in file keeps
+//! its repository's indentation and array style.
+//!
+//! TOML output, for Cargo manifests and other tool configs, writes records
+//! as tables (`[server]`) and arrays of records as arrays of tables
+//! (`[[servers]]`), with plain values first as TOML requires. TOML has no
+//! null and no integers beyond 64-bit signed, so such values are rejected
+//! with their path rather than dropped.
+//!
+//! Nix output, for configs built into Nix system images, is an expression of
+//! attribute sets and lists that `import ./app.nix` reads back: strings are
+//! escaped (including `${`, so nothing is interpolated), and attribute
+//! names that are not plain identifiers, or are Nix keywords, are quoted.
+//! Integers beyond 64-bit signed are rejected with their path, as Nix cannot
+//! hold them.
+//!
+//! YAML and TOML output need the `yaml` and `toml` features (on by
+//! default); without them, rendering those formats fails with an error
+//! naming the feature.
+//!
+//! # Examples
+//!
+//! ```
+//! # #[cfg(feature = "yaml")] {
+//! use bunsenite::export::{self, Format};
+//! use serde_json::json;
+//!
+//! let yaml = export::render(&json!({ "replicas": 3, "image": "web:1.2" }), Format::Yaml).unwrap();
+//! assert_eq!(yaml, "image: web:1.2\nreplicas: 3\n");
+//! # }
+//! ```
+
+use crate::arena::Document;
+use crate::error::{Error, Result};
+use crate::json::{self, Layout};
+use crate::loader::NickelLoader;
+use crate::order::FieldOrder;
+use crate::style::Styles;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+pub use crate::exports::Format;
+
+/// What [`export_file_all`] writes besides the values
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Options {
+    comments: bool,
+    source_order: bool,
+    json_layout: Layout,
+}
+
+impl Options {
+    /// Values only, with fields in name order
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Write field documentation as `#` comments in YAML output, which
+    /// costs a second evaluation
+    pub fn with_comments(mut self, comments: bool) -> Self {
+        self.comments = comments;
+        self
+    }
+
+    /// Write record fields in the order the config defines them, which
+    /// costs a second evaluation
+    pub fn with_source_order(mut self, source_order: bool) -> Self {
+        self.source_order = source_order;
+        self
+    }
+
+    /// Lay JSON output out as `layout` says; it always ends with a newline
+    pub fn with_json_layout(mut self, layout: Layout) -> Self {
+        self.json_layout = layout;
+        self
+    }
+}
+
+/// `value` in `format`, ending with a newline where the format has one
+///
+/// # Errors
+///
+/// Returns a serialization error if `value` cannot be written in `format`
+/// (TOML needs a record without nulls; text needs a string)
+pub fn render(value: &Value, format: Format) -> Result<String> {
+    render_styled(value, format, &FieldOrder::new(), &Layout::new())
+}
+
+/// As [`render`], with record fields in `order` and JSON laid out as
+/// `layout` says
+pub(crate) fn render_styled(
+    value: &Value,
+    format: Format,
+    order: &FieldOrder,
+    layout: &Layout,
+) -> Result<String> {
+    serialize_styled(value, format, order, layout).map_err(|e| {
+        Error::serialization_error(format!("Cannot write output as {}: {}", format, e))
+    })
+}
+
+/// Evaluate the config at `path` and render it in `format`
+///
+/// # Errors
+///
+/// Returns the evaluation error, or an error from [`render`]
+pub fn export_file(loader: &NickelLoader, path: &Path, format: Format) -> Result<String> {
+    let mut rendered = export_file_all(loader, path, &[format], &Options::new())?;
+    Ok(rendered.remove(0))
+}
+
+/// Evaluate the config at `path` once and render it in each of `formats`
+///
+/// Renderings are returned in the order of `formats`. Fields annotated with
+/// an `Output` contract are written in its [`Style`](crate::style::Style),
+/// and `options` add comments, source order or a JSON layout.
+///
+/// # Errors
+///
+/// Returns the evaluation error, or the first error from [`render`]
+pub fn export_file_all(
+    loader: &NickelLoader,
+    path: &Path,
+    formats: &[Format],
+    options: &Options,
+) -> Result<Vec<String>> {
+    let (value, contracts) = loader.parse_file_with_contracts(path)?;
+    let mut styles = Styles::from_contracts(&contracts);
+    let comments = options.comments && formats.contains(&Format::Yaml);
+    if comments || options.source_order {
+        let (source, name) = crate::loader::read_source(path)?;
+        if comments {
+            styles = styles.with_comments(&loader.field_docs(&source, &name)?);
+        }
+        if options.source_order {
+            styles = styles.with_order(loader.field_order(&source, &name)?);
+        }
+    }
+    styles = styles.with_json_layout(options.json_layout);
+    let rendered = formats
+        .iter()
+        .map(|format| styles.render(&value, *format))
+        .collect();
+    json::drop_deep(value);
+    rendered
+}
+
+/// The file each of `formats` is written to, for the output path `output`
+///
+/// With one format, `output` is used as given. With several, each gets
+/// `output` with the format's extension, replacing an extension `output`
+/// already has for one of them: `out/app` or `out/app.json` with JSON and
+/// YAML gives `out/app.json` and `out/app.yaml`.
+pub fn output_paths(output: &Path, formats: &[Format]) -> Vec<PathBuf> {
+    if formats.len() == 1 {
+        return vec![output.to_path_buf()];
+    }
+    let stem = match output.extension().and_then(|e| e.to_str()) {
+        Some(extension) if Format::from_name(extension).is_ok() => output.with_extension(""),
+        _ => output.to_path_buf(),
+    };
+    formats
+        .iter()
+        .map(|format| {
+            let mut path = stem.clone().into_os_string();
+            path.push(".");
+            path.push(format.extension());
+            PathBuf::from(path)
+        })
+        .collect()
+}
+
+/// `value` in `format`, or why it cannot be written
+pub(crate) fn serialize(value: &Value, format: Format) -> std::result::Result<String, String> {
+    serialize_styled(value, format, &FieldOrder::new(), &Layout::new())
+}
+
+/// As [`serialize`], with record fields in `order` and JSON laid out as
+/// `layout` says
+fn serialize_styled(
+    value: &Value,
+    format: Format,
+    order: &FieldOrder,
+    layout: &Layout,
+) -> std::result::Result<String, String> {
+    let layout = layout.with_final_newline(true);
+    match format {
+        Format::Json if order.is_empty() => Ok(json::to_string_with(value, &layout)),
+        Format::Json => {
+            let mut document = Document::from_serialize(value).map_err(|e| e.to_string())?;
+            document.reorder(order);
+            Ok(document.to_json_string_with(&layout))
+        }
+        Format::Yaml => to_yaml(&order.apply(value)),
+        Format::Toml => to_toml(value, order),
+        Format::Nix => to_nix(value, order),
+        Format::Text => match value {
+            Value::String(text) => Ok(text.clone()),
+            _ => Err("text output must be a string".to_string()),
+        },
+    }
+}
+
+/// `value` as YAML, or why it cannot be written
+#[cfg(feature = "yaml")]
+pub(crate) fn to_yaml<T: serde::Serialize>(value: &T) -> std::result::Result<String, String> {
+    serde_yaml::to_string(value).map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "yaml"))]
+pub(crate) fn to_yaml<T: serde::Serialize>(_value: &T) -> std::result::Result<String, String> {
+    Err("bunsenite was built without YAML output (the `yaml` feature)".to_string())
+}
+
+/// `value` as a TOML document with tables in `order`, or why it cannot be
+/// written
+#[cfg(feature = "toml")]
+fn to_toml(value: &Value, order: &FieldOrder) -> std::result::Result<String, String> {
+    match toml_value(value, "", order)? {
+        toml::Value::Table(table) => toml::to_string(&table).map_err(|e| e.to_string()),
+        _ => Err("a TOML document must be a record".to_string()),
+    }
+}
+
+#[cfg(not(feature = "toml"))]
+fn to_toml(_value: &Value, _order: &FieldOrder) -> std::result::Result<String, String> {
+    Err("bunsenite was built without TOML output (the `toml` feature)".to_string())
+}
+
+/// `value` as TOML with tables in `order`, or why the value at `path` has
+/// no TOML equivalent
+#[cfg(feature = "toml")]
+fn toml_value(
+    value: &Value,
+    path: &str,
+    order: &FieldOrder,
+) -> std::result::Result<toml::Value, String> {
+    let at = || {
+        if path.is_empty() {
+            "the top level".to_string()
+        } else {
+            path.to_string()
+        }
+    };
+    match value {
+        Value::Null => Err(format!("null at {} (TOML has no null)", at())),
+        Value::Bool(b) => Ok(toml::Value::Boolean(*b)),
+        Value::Number(n) => match (n.as_i64(), n.as_f64()) {
+            (Some(i), _) => Ok(toml::Value::Integer(i)),
+            (None, Some(_)) if n.is_u64() => {
+                Err(format!("{} at {} is too large for a TOML integer", n, at()))
+            }
+            (None, Some(f)) => Ok(toml::Value::Float(f)),
+            (None, None) => Err(format!("{} at {} is not a TOML number", n, at())),
+        },
+        Value::String(s) => Ok(toml::Value::String(s.clone())),
+        Value::Array(items) => items
+            .iter()
+            .enumerate()
+            .map(|(index, item)| toml_value(item, &json::index_path(path, index), order))
+            .collect::<std::result::Result<_, _>>()
+            .map(toml::Value::Array),
+        Value::Object(fields) => order
+            .entries(path, fields)
+            .into_iter()
+            .map(|(key, field)| {
+                let field = toml_value(field, &json::key_path(path, key), order)?;
+                Ok((key.clone(), field))
+            })
+            .collect::<std::result::Result<toml::Table, String>>()
+            .map(toml::Value::Table),
+    }
+}
+
+/// `value` as a Nix expression with attributes in `order`, or why it
+/// cannot be written
+fn to_nix(value: &Value, order: &FieldOrder) -> std::result::Result<String, String> {
+    let mut out = String::new();
+    nix_value(value, "", 0, order, &mut out)?;
+    out.push('\n');
+    Ok(out)
+}
+
+/// Append `value`, found at `path` and nested `depth` deep, to `out`
+fn nix_value(
+    value: &Value,
+    path: &str,
+    depth: usize,
+    order: &FieldOrder,
+    out: &mut String,
+) -> std::result::Result<(), String> {
+    let indent = "  ".repeat(depth + 1);
+    match value {
+        Value::Null => out.push_str("null"),
+        Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        Value::Number(n) => match (n.as_i64(), n.as_f64()) {
+            (Some(i), _) => out.push_str(&i.to_string()),
+            (None, Some(_)) if n.is_u64() => {
+                let at = if path.is_empty() {
+                    "the top level"
+                } else {
+                    path
+                };
+                return Err(format!("{} at {} is too large for a Nix integer", n, at));
+            }
+            (None, Some(f)) => {
+                // A Nix float needs its point
+                let f = f.to_string();
+                out.push_str(&f);
+                if !f.contains('.') {
+                    out.push_str(".0");
+                }
+            }
+            (None, None) => return Err(format!("{} at {} is not a Nix number", n, path)),
+        },
+        Value::String(s) => out.push_str(&nix_string(s)),
+        Value::Array(items) if items.is_empty() => out.push_str("[ ]"),
+        Value::Array(items) => {
+            out.push_str("[\n");
+            for (index, item) in items.iter().enumerate() {
+                out.push_str(&indent);
+                let start = out.len();
+                nix_value(item, &json::index_path(path, index), depth + 1, order, out)?;
+                // `[ -1 ]` would be read as a subtraction
+                if out[start..].starts_with('-') {
+                    out.insert(start, '(');
+                    out.push(')');
+                }
+                out.push('\n');
+            }
+            out.push_str(&"  ".repeat(depth));
+            out.push(']');
+        }
+        Value::Object(fields) if fields.is_empty() => out.push_str("{ }"),
+        Value::Object(fields) => {
+            out.push_str("{\n");
+            for (key, field) in order.entries(path, fields) {
+                out.push_str(&indent);
+                out.push_str(&nix_name(key));
+                out.push_str(" = ");
+                nix_value(field, &json::key_path(path, key), depth + 1, order, out)?;
+                out.push_str(";\n");
+            }
+            out.push_str(&"  ".repeat(depth));
+            out.push('}');
+        }
+    }
+    Ok(())
+}
+
+/// `text` as a double-quoted Nix string
+fn nix_string(text: &str) -> String {
+    let mut out = String::from('"');
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '$' if chars.peek() == Some(&'{') => out.push_str("\\$"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// An attribute name, quoted unless it is a plain identifier
+fn nix_name(key: &str) -> String {
+    const KEYWORDS: [&str; 10] = [
+        "assert", "else", "if", "in", "inherit", "let", "or", "rec", "then", "with",
+    ];
+    let mut chars = key.chars();
+    let plain = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '\'' | '-'))
+        && !KEYWORDS.contains(&key);
+    if plain {
+        key.to_string()
+    } else {
+        nix_string(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn test_export_file_as_yaml() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = dir.path().join("deploy.ncl");
+        std::fs::write(
+            &config,
+            r#"{
+              kind = "Deployment",
+              spec = { replicas = 2, ports = [80, 443], selector = null },
+              notes = "line one\nline two",
+            }"#,
+        )
+        .unwrap();
+
+        let yaml = export_file(&NickelLoader::new(), &config, Format::Yaml).unwrap();
+        let back: Value = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(
+            back,
+            json!({
+                "kind": "Deployment",
+                "spec": { "replicas": 2, "ports": [80, 443], "selector": null },
+                "notes": "line one\nline two",
+            })
+        );
+        assert!(yaml.starts_with("kind: Deployment\n"), "{}", yaml);
+    }
+
+    #[test]
+    fn test_nix() {
+        let value = json!({
+            "name": "web",
+            "ports": [80, -1, 0.5, 2e3],
+            "env": { "HOME": "/root", "greeting": "say \"${hi}\"\n\\ $ok" },
+            "in": null,
+            "my key": [true, [], {}],
+        });
+        assert_eq!(
+            render(&value, Format::Nix).unwrap(),
+            r#"{
+  env = {
+    HOME = "/root";
+    greeting = "say \"\${hi}\"\n\\ $ok";
+  };
+  "in" = null;
+  "my key" = [
+    true
+    [ ]
+    { }
+  ];
+  name = "web";
+  ports = [
+    80
+    (-1)
+    0.5
+    2000.0
+  ];
+}
+"#
+        );
+
+        let error = render(&json!({ "id": u64::MAX }), Format::Nix)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("at id is too large"), "{}", error);
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn test_toml_tables_and_arrays_of_tables() {
+        let value = json!({
+            "name": "app",
+            "server": { "host": "localhost", "port": 80 },
+            "servers": [{ "name": "a" }, { "name": "b", "tags": ["x"] }],
+            "package": { "metadata": { "docs": { "all": true } } },
+        });
+        let toml = render(&value, Format::Toml).unwrap();
+        assert!(toml.starts_with("name = \"app\"\n"), "{}", toml);
+        assert!(
+            toml.contains("[server]\nhost = \"localhost\"\nport = 80\n"),
+            "{}",
+            toml
+        );
+        assert!(
+            toml.contains("[[servers]]\nname = \"a\"\n\n[[servers]]\nname = \"b\"\n"),
+            "{}",
+            toml
+        );
+        let back: toml::Table = toml::from_str(&toml).unwrap();
+        assert_eq!(serde_json::to_value(back).unwrap(), value);
+
+        let error = render(&json!({ "servers": [{ "tls": null }] }), Format::Toml)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("null at servers[0].tls"), "{}", error);
+        let error = render(&json!({ "id": u64::MAX }), Format::Toml)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("at id is too large"), "{}", error);
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn test_export_several_formats() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = dir.path().join("app.ncl");
+        std::fs::write(&config, "{ port = 80 }").unwrap();
+
+        let rendered = export_file_all(
+            &NickelLoader::new(),
+            &config,
+            &[Format::Json, Format::Yaml],
+            &Options::new(),
+        )
+        .unwrap();
+        assert_eq!(rendered, ["{\n  \"port\": 80\n}\n", "port: 80\n"]);
+
+        let options = Options::new().with_json_layout(Layout::new().with_indent(4));
+        let rendered = export_file_all(&NickelLoader::new(), &config, &[Format::Json], &options);
+        assert_eq!(rendered.unwrap(), ["{\n    \"port\": 80\n}\n"]);
+
+        let formats = [Format::Json, Format::Yaml];
+        assert_eq!(
+            output_paths(Path::new("out/app.json"), &formats),
+            [PathBuf::from("out/app.json"), PathBuf::from("out/app.yaml")]
+        );
+        assert_eq!(
+            output_paths(Path::new("out/app.v1"), &formats),
+            [
+                PathBuf::from("out/app.v1.json"),
+                PathBuf::from("out/app.v1.yaml")
+            ]
+        );
+        assert_eq!(
+            output_paths(Path::new("app.conf"), &[Format::Toml]),
+            [PathBuf::from("app.conf")]
+        );
+    }
+
+    #[cfg(all(feature = "yaml", feature = "toml"))]
+    #[test]
+    fn test_export_in_source_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = dir.path().join("app.ncl");
+        std::fs::write(
+            &config,
+            r#"{ name = "web", server = { port = 80, host = "a" }, image = "web:1.2" }"#,
+        )
+        .unwrap();
+
+        let options = Options::new().with_source_order(true);
+        let formats = [Format::Json, Format::Yaml, Format::Toml];
+        let rendered = export_file_all(&NickelLoader::new(), &config, &formats, &options).unwrap();
+        assert_eq!(
+            rendered[0],
+            "{\n  \"name\": \"web\",\n  \"server\": {\n    \"port\": 80,\n    \"host\": \"a\"\n  },\n  \"image\": \"web:1.2\"\n}\n"
+        );
+        assert_eq!(
+            rendered[1],
+            "name: web\nserver:\n  port: 80\n  host: a\nimage: web:1.2\n"
+        );
+        assert_eq!(
+            rendered[2],
+            "name = \"web\"\nimage = \"web:1.2\"\n\n[server]\nport = 80\nhost = \"a\"\n"
+        );
+    }
+
+    #[test]
+    fn test_render_rejects_unrepresentable_values() {
+        let error = render(&json!([1, 2]), Format::Toml)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("as toml"), "{}", error);
+        assert_eq!(render(&json!("raw"), Format::Text).unwrap(), "raw");
+    }
+
+    #[cfg(not(feature = "yaml"))]
+    #[test]
+    fn test_missing_yaml_names_feature() {
+        let error = render(&json!({ "a": 1 }), Format::Yaml)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("the `yaml` feature"), "{}", error);
+    }
+}
@@ -0,0 +1,365 @@
+//! Multi-output export manifests
+//!
+//! Backs `bunsenite build`. Instead of one invocation per output file, a
+//! config lists everything it produces in a top-level `exports` record; each
+//! entry names its output path, its format and the value to write:
+//!
+//! ```nickel
+//! {
+//!   services = { ... },
+//!   exports = {
+//!     web = { path = "k8s/web.yaml", content = services.web },
+//!     ci = { path = "ci/pipeline.json", format = "json", content = pipeline },
+//!     motd = { path = "motd.txt", format = "text", content = "Welcome" },
+//!   },
+//! }
+//! ```
+//!
+//! Formats are `json`, `yaml`, `toml`, `nix` and `text` (a string written
+//! as is).
+//! Without `format`, it is taken from the path's extension. Paths are
+//! relative to the output directory and may not leave it. Only the `exports`
+//! field is evaluated (see [`crate::target`]), so fields no export uses are
+//! never forced.
+//!
+//! # Examples
+//!
+//! ```
+//! # #[cfg(feature = "yaml")] {
+//! use bunsenite::exports::{self, Format};
+//! use serde_json::json;
+//!
+//! let exports = exports::from_value(&json!({
+//!     "web": { "path": "web.yaml", "content": { "port": 80 } },
+//! })).unwrap();
+//! assert_eq!(exports[0].format, Format::Yaml);
+//! assert_eq!(exports[0].render().unwrap(), "port: 80\n");
+//! # }
+//! ```
+
+use crate::compress::{self, Compression};
+use crate::error::{Error, Result};
+use crate::loader::NickelLoader;
+use crate::target::Target;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+
+/// Top-level field holding the export manifest
+pub const FIELD: &str = "exports";
+
+/// Output format of an export
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Pretty-printed JSON
+    Json,
+    /// YAML
+    Yaml,
+    /// TOML (the content must be a record without nulls)
+    Toml,
+    /// A Nix expression of attribute sets and lists
+    Nix,
+    /// A string, written as is
+    Text,
+}
+
+impl Format {
+    /// Every format, in the order they are listed to users
+    pub const ALL: [Format; 5] = [
+        Format::Json,
+        Format::Yaml,
+        Format::Toml,
+        Format::Nix,
+        Format::Text,
+    ];
+
+    /// Look up a format by name or alias, ignoring case
+    ///
+    /// # Errors
+    ///
+    /// Returns an invalid-input error for unknown names
+    pub fn from_name(name: &str) -> Result<Self> {
+        let lower = name.to_ascii_lowercase();
+        Self::ALL
+            .into_iter()
+            .find(|format| format.name() == lower || format.aliases().contains(&lower.as_str()))
+            .ok_or_else(|| {
+                Error::invalid_input(format!(
+                    "Unknown export format '{}' (expected json, yaml, toml, nix or text)",
+                    name
+                ))
+            })
+    }
+
+    /// The format's name, as accepted by `--format`
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Yaml => "yaml",
+            Format::Toml => "toml",
+            Format::Nix => "nix",
+            Format::Text => "text",
+        }
+    }
+
+    /// Other names [`Format::from_name`] accepts for the format
+    pub fn aliases(self) -> &'static [&'static str] {
+        match self {
+            Format::Json | Format::Toml | Format::Nix => &[],
+            Format::Yaml => &["yml"],
+            Format::Text => &["txt", "raw"],
+        }
+    }
+
+    /// Conventional file extension, without the dot
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Yaml => "yaml",
+            Format::Toml => "toml",
+            Format::Nix => "nix",
+            Format::Text => "txt",
+        }
+    }
+
+    /// The format an output path's extension implies
+    pub fn from_path(path: &str) -> Option<Self> {
+        let extension = Path::new(path).extension()?.to_str()?;
+        Self::from_name(extension).ok()
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// One output declared in the manifest
+#[derive(Debug, Clone, PartialEq)]
+pub struct Export {
+    /// Entry name in the manifest
+    pub name: String,
+    /// Output path, relative to the output directory
+    pub path: String,
+    /// Output format
+    pub format: Format,
+    /// Value to write
+    pub content: Value,
+}
+
+impl Export {
+    /// The export's file contents
+    ///
+    /// # Errors
+    ///
+    /// Returns a serialization error if the content cannot be written in the
+    /// export's format
+    pub fn render(&self) -> Result<String> {
+        let failed = |e: &dyn fmt::Display| {
+            Error::serialization_error(format!(
+                "Cannot write export '{}' as {}: {}",
+                self.name, self.format, e
+            ))
+        };
+        crate::export::serialize(&self.content, self.format).map_err(|e| failed(&e))
+    }
+}
+
+/// Read an evaluated `exports` record
+///
+/// Entries are returned sorted by name.
+///
+/// # Errors
+///
+/// Returns an invalid-input error for malformed entries, unknown formats,
+/// paths leaving the output directory and paths used twice
+pub fn from_value(exports: &Value) -> Result<Vec<Export>> {
+    let invalid = |message: String| Error::invalid_input(format!("Invalid exports: {}", message));
+    let entries = exports
+        .as_object()
+        .ok_or_else(|| invalid(format!("`{}` must be a record", FIELD)))?;
+
+    let mut seen = HashSet::new();
+    let mut exports = Vec::with_capacity(entries.len());
+    for (name, entry) in entries {
+        let path = entry
+            .get("path")
+            .and_then(Value::as_str)
+            .ok_or_else(|| invalid(format!("'{}' needs a string `path`", name)))?;
+        let safe = !path.is_empty()
+            && Path::new(path)
+                .components()
+                .all(|c| matches!(c, Component::Normal(_)));
+        if !safe {
+            return Err(invalid(format!(
+                "'{}' path '{}' must be relative and stay inside the output directory",
+                name, path
+            )));
+        }
+        if !seen.insert(path) {
+            return Err(invalid(format!("path '{}' is used more than once", path)));
+        }
+
+        let format = match entry.get("format") {
+            Some(Value::String(format)) => Format::from_name(format)?,
+            Some(_) => return Err(invalid(format!("'{}' `format` must be a string", name))),
+            None => Format::from_path(path).ok_or_else(|| {
+                invalid(format!(
+                    "'{}' needs a `format`; none is implied by '{}'",
+                    name, path
+                ))
+            })?,
+        };
+        let content = entry
+            .get("content")
+            .ok_or_else(|| invalid(format!("'{}' needs a `content`", name)))?;
+
+        exports.push(Export {
+            name: name.clone(),
+            path: path.to_string(),
+            format,
+            content: content.clone(),
+        });
+    }
+    Ok(exports)
+}
+
+/// Evaluate the `exports` record of the config at `path`
+///
+/// # Errors
+///
+/// Returns the evaluation error, or an error from [`from_value`]
+pub fn load(loader: &NickelLoader, path: &Path) -> Result<Vec<Export>> {
+    let loader = loader.clone().with_target(Target::parse(FIELD)?);
+    from_value(&loader.parse_file(path)?)
+}
+
+/// Write every export below `out_dir`, returning the paths written
+///
+/// All exports are rendered before anything is written, so a failing export
+/// leaves the output directory untouched.
+///
+/// # Errors
+///
+/// Returns the first rendering error, or an I/O error
+pub fn write(exports: &[Export], out_dir: &Path) -> Result<Vec<PathBuf>> {
+    write_with(exports, out_dir, None)
+}
+
+/// Like [`write`], compressing each file and adding the compression's
+/// extension to its path
+///
+/// # Errors
+///
+/// As [`write`], or a compression error
+pub fn write_with(
+    exports: &[Export],
+    out_dir: &Path,
+    compression: Option<Compression>,
+) -> Result<Vec<PathBuf>> {
+    let rendered = exports
+        .iter()
+        .map(|export| export.render())
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut written = Vec::with_capacity(exports.len());
+    for (export, contents) in exports.iter().zip(rendered) {
+        let path = out_dir.join(&export.path);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        written.push(compress::write(&path, contents.as_bytes(), compression)?);
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_from_value_rejects_bad_entries() {
+        let check = |exports: Value| from_value(&exports).unwrap_err().to_string();
+        assert!(check(json!([])).contains("must be a record"));
+        assert!(check(json!({ "a": { "content": 1 } })).contains("needs a string `path`"));
+        assert!(check(json!({ "a": { "path": "../a.json", "content": 1 } })).contains("inside"));
+        assert!(check(json!({ "a": { "path": "a.conf", "content": 1 } })).contains("`format`"));
+        assert!(check(json!({ "a": { "path": "a.json" } })).contains("`content`"));
+        assert!(check(json!({
+            "a": { "path": "x.json", "content": 1 },
+            "b": { "path": "x.json", "content": 2 },
+        }))
+        .contains("more than once"));
+    }
+
+    #[test]
+    fn test_format_names() {
+        for format in Format::ALL {
+            assert_eq!(Format::from_name(format.name()).unwrap(), format);
+            for alias in format.aliases() {
+                assert_eq!(Format::from_name(alias).unwrap(), format);
+            }
+        }
+        assert_eq!(Format::from_name("YML").unwrap(), Format::Yaml);
+        assert_eq!(Format::from_path("out/notes.txt"), Some(Format::Text));
+        assert!(Format::from_name("xml").is_err());
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn test_render_formats() {
+        let export = |format, content| Export {
+            name: "e".to_string(),
+            path: "e".to_string(),
+            format,
+            content,
+        };
+        let record = json!({ "server": { "port": 80 } });
+        assert_eq!(
+            export(Format::Toml, record.clone()).render().unwrap(),
+            "[server]\nport = 80\n"
+        );
+        assert_eq!(
+            export(Format::Json, record).render().unwrap(),
+            "{\n  \"server\": {\n    \"port\": 80\n  }\n}\n"
+        );
+        assert_eq!(
+            export(Format::Text, json!("hi\n")).render().unwrap(),
+            "hi\n"
+        );
+        assert!(export(Format::Text, json!(1)).render().is_err());
+        assert!(export(Format::Toml, json!([1])).render().is_err());
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn test_load_and_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = dir.path().join("app.ncl");
+        std::fs::write(
+            &config,
+            r#"{
+              unused = std.fail_with "never evaluated",
+              port = 80,
+              exports = {
+                web = { path = "k8s/web.yaml", content = { listen = port } },
+                motd = { path = "motd", format = "text", content = "hello" },
+              },
+            }"#,
+        )
+        .unwrap();
+
+        let exports = load(&NickelLoader::new(), &config).unwrap();
+        let out = dir.path().join("out");
+        let written = write(&exports, &out).unwrap();
+        assert_eq!(written.len(), 2);
+        assert_eq!(std::fs::read_to_string(out.join("motd")).unwrap(), "hello");
+        assert_eq!(
+            std::fs::read_to_string(out.join("k8s/web.yaml")).unwrap(),
+            "listen: 80\n"
+        );
+    }
+}
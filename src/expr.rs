@@ -0,0 +1,402 @@
+//! Evaluating user-supplied predicates against host data
+//!
+//! Host applications can let their end users write small Nickel
+//! expressions, such as feature flag rules or routing conditions, and
+//! evaluate them against data the host provides. The data is bound as
+//! `context`, and each plain identifier the expression uses is bound to the
+//! field of `context` of that name:
+//!
+//! ```nickel
+//! user.plan == "pro" && std.array.elem region ["eu", "us"]
+//! ```
+//!
+//! A [`Sandbox`] keeps such expressions from reaching beyond the data:
+//!
+//! - `import` is rejected outright, so nothing is read from disk
+//! - the expression sees nothing but `context` and the standard library
+//! - the expression's length and nesting depth are bounded before it is
+//!   parsed, and its evaluation by fuel and a memory cap
+//!
+//! Fuel is wall time and memory is estimated from the values evaluation
+//! allocates. Both are charged as evaluation runs, on the calling thread,
+//! so an evaluation that exceeds either stops there and then, and is
+//! reported as a resource-limit error; nothing is left running.
+//!
+//! [`Sandbox::compile`] admits and parses an expression once, for
+//! evaluating against many contexts, as [`crate::flags`] does with its
+//! rules. Nickel's parsed terms cannot be shared between threads, so each
+//! thread parses an expression again the first time it evaluates it.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::expr::{self, Sandbox};
+//! use serde_json::json;
+//!
+//! let context = json!({ "user": { "plan": "pro" }, "region": "eu" });
+//! let rule = r#"user.plan == "pro" && std.array.elem region ["eu", "us"]"#;
+//! assert!(Sandbox::new().test(rule, &context).unwrap());
+//!
+//! let value = expr::eval_expr_with_context("context.region", &context).unwrap();
+//! assert_eq!(value, json!("eu"));
+//! assert!(expr::eval_expr_with_context("import \"secrets.ncl\"", &context).is_err());
+//! ```
+
+use crate::error::{Error, Result};
+use crate::lexer::{self, Token};
+use crate::loader;
+use crate::meter::Budget;
+use crate::source;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+/// Fuel of a [`Sandbox`] unless another is given
+pub const DEFAULT_FUEL: Duration = Duration::from_millis(250);
+
+/// Memory an evaluation may use, in bytes, unless another cap is given
+pub const DEFAULT_MAX_MEMORY: u64 = 64 << 20;
+
+/// Deepest nesting of brackets an expression may have unless another limit
+/// is given
+pub const DEFAULT_MAX_DEPTH: usize = 64;
+
+/// Longest expression, in bytes, unless another limit is given
+pub const DEFAULT_MAX_LENGTH: usize = 4096;
+
+/// Name expressions are evaluated under, as it appears in errors
+const NAME: &str = "<expr>";
+
+/// Words a context field cannot be bound as, since they mean something else
+const RESERVED: &[&str] = &[
+    "context",
+    "default",
+    "doc",
+    "else",
+    "false",
+    "forall",
+    "force",
+    "fun",
+    "if",
+    "import",
+    "in",
+    "let",
+    "match",
+    "not_exported",
+    "null",
+    "optional",
+    "priority",
+    "rec",
+    "std",
+    "then",
+    "true",
+];
+
+/// Limits on evaluating user-supplied expressions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sandbox {
+    fuel: Duration,
+    max_memory: u64,
+    max_depth: usize,
+    max_length: usize,
+}
+
+impl Default for Sandbox {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sandbox {
+    /// A sandbox with the default limits
+    pub fn new() -> Self {
+        Self {
+            fuel: DEFAULT_FUEL,
+            max_memory: DEFAULT_MAX_MEMORY,
+            max_depth: DEFAULT_MAX_DEPTH,
+            max_length: DEFAULT_MAX_LENGTH,
+        }
+    }
+
+    /// Stop an evaluation after `fuel`
+    pub fn with_fuel(mut self, fuel: Duration) -> Self {
+        self.fuel = fuel;
+        self
+    }
+
+    /// Stop an evaluation once it has used about `bytes` of memory
+    ///
+    /// Memory is estimated, not measured: see the [module
+    /// documentation](self). The context counts too.
+    pub fn with_max_memory(mut self, bytes: u64) -> Self {
+        self.max_memory = bytes;
+        self
+    }
+
+    /// Reject expressions nested deeper than `levels`
+    pub fn with_max_depth(mut self, levels: usize) -> Self {
+        self.max_depth = levels;
+        self
+    }
+
+    /// Reject expressions longer than `bytes`
+    pub fn with_max_length(mut self, bytes: usize) -> Self {
+        self.max_length = bytes;
+        self
+    }
+
+    /// Evaluate `expr` against `context`
+    ///
+    /// # Errors
+    ///
+    /// Returns an invalid-input error if `expr` imports anything, a
+    /// resource-limit error if it is too long or deep or runs out of fuel
+    /// or memory, and the evaluation error if it does not evaluate
+    pub fn eval(&self, expr: &str, context: &Value) -> Result<Value> {
+        self.compile(expr)?.eval(context)
+    }
+
+    /// Evaluate predicate `expr` against `context`
+    ///
+    /// # Errors
+    ///
+    /// As [`eval`](Self::eval), and an invalid-input error if `expr` does
+    /// not evaluate to a `Bool`
+    pub fn test(&self, expr: &str, context: &Value) -> Result<bool> {
+        self.compile(expr)?.test(context)
+    }
+
+    /// Check that `expr` parses and is within the limits, without
+    /// evaluating it, e.g. when rules are loaded
+    ///
+    /// # Errors
+    ///
+    /// As [`compile`](Self::compile)
+    pub fn check(&self, expr: &str) -> Result<()> {
+        self.compile(expr).map(drop)
+    }
+
+    /// Admit and parse `expr` once, to evaluate against any number of
+    /// contexts within this sandbox's limits
+    ///
+    /// # Errors
+    ///
+    /// Returns an invalid-input error if `expr` imports anything, a
+    /// resource-limit error if it is too long or deep, and a parse or type
+    /// error if it does not parse
+    pub fn compile(&self, expr: &str) -> Result<Expression> {
+        self.admit(expr)?;
+        let function = function(expr);
+        loader::prepare_function(&function, NAME)?;
+        Ok(Expression {
+            function,
+            budget: Budget {
+                time: self.fuel,
+                memory: self.max_memory,
+            },
+        })
+    }
+
+    /// Reject `expr` before it is evaluated, if it must be
+    fn admit(&self, expr: &str) -> Result<()> {
+        if expr.len() > self.max_length {
+            return Err(Error::resource_limit(
+                "expression length",
+                format!("{} bytes", self.max_length),
+            ));
+        }
+        if loader::source_depth(expr) > self.max_depth {
+            return Err(Error::resource_limit(
+                "nesting depth",
+                format!("{} levels", self.max_depth),
+            ));
+        }
+        if imports(expr) {
+            return Err(Error::invalid_input("Expressions cannot use `import`"));
+        }
+        Ok(())
+    }
+}
+
+/// An expression admitted to a [`Sandbox`] and parsed, ready to evaluate
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expression {
+    /// Function of `context` evaluating the expression
+    function: String,
+    budget: Budget,
+}
+
+impl Expression {
+    /// Evaluate the expression against `context`
+    ///
+    /// # Errors
+    ///
+    /// Returns a resource-limit error if evaluation runs out of fuel or
+    /// memory, and the evaluation error if it does not evaluate
+    pub fn eval(&self, context: &Value) -> Result<Value> {
+        loader::apply_function(&self.function, NAME, context, self.budget)
+    }
+
+    /// Evaluate the expression, a predicate, against `context`
+    ///
+    /// # Errors
+    ///
+    /// As [`eval`](Self::eval), and an invalid-input error if the
+    /// expression does not evaluate to a `Bool`
+    pub fn test(&self, context: &Value) -> Result<bool> {
+        match self.eval(context)? {
+            Value::Bool(b) => Ok(b),
+            other => Err(Error::invalid_input(format!(
+                "Predicate evaluated to {}, not a Bool",
+                crate::json::to_string(&other, false)
+            ))),
+        }
+    }
+}
+
+/// Evaluate `expr` against `context` in a [`Sandbox`] with the default
+/// limits
+///
+/// # Errors
+///
+/// See [`Sandbox::eval`]
+pub fn eval_expr_with_context(expr: &str, context: &Value) -> Result<Value> {
+    Sandbox::new().eval(expr, context)
+}
+
+/// Whether `expr` may import something
+///
+/// Strings are searched too, since interpolations can hold code; an
+/// expression that does not lex is searched as plain text.
+fn imports(expr: &str) -> bool {
+    match lexer::tokens(expr) {
+        Ok(tokens) => tokens.iter().any(|token| match token {
+            Token::Word(_) => token.is_word("import"),
+            Token::Str(text) => mentions_import(text),
+            _ => false,
+        }),
+        Err(_) => mentions_import(expr),
+    }
+}
+
+/// Whether `text` has `import` as a word
+fn mentions_import(text: &str) -> bool {
+    let is_ident = |c: char| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '\'');
+    text.match_indices("import").any(|(at, word)| {
+        let before = text[..at].chars().next_back();
+        let after = text[at + word.len()..].chars().next();
+        !before.is_some_and(is_ident) && !after.is_some_and(is_ident)
+    })
+}
+
+/// The function of `context` evaluating `expr`, with each identifier in
+/// `expr` bound to the field of `context` of that name
+///
+/// Words in strings and words `expr` binds itself are bound too; bindings
+/// are lazy, so those cost nothing.
+fn function(expr: &str) -> String {
+    let is_word = |c: char| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '\'');
+    let words: BTreeSet<&str> = expr
+        .split(|c: char| !is_word(c))
+        .filter(|word| source::is_identifier(word) && !RESERVED.contains(word))
+        .collect();
+    let mut function = String::from("fun context =>\n");
+    for word in words {
+        function.push_str(&format!("let {} = context.{} in\n", word, word));
+    }
+    // On lines of its own, so a trailing comment cannot swallow the paren
+    function.push_str(&format!("(\n{}\n)", expr));
+    function
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_eval() {
+        let context = json!({ "plan": "pro", "seats": 12, "let": 1, "app/id": "x" });
+        let sandbox = Sandbox::new();
+        assert!(sandbox
+            .test("plan == \"pro\" && seats > 10 # big", &context)
+            .unwrap());
+        assert_eq!(
+            sandbox.eval("context.\"app/id\"", &context).unwrap(),
+            json!("x")
+        );
+        assert_eq!(
+            sandbox.eval("context.\"let\" + 1", &context).unwrap(),
+            json!(2)
+        );
+        assert!(sandbox.test("seats + 1", &context).is_err());
+        assert!(sandbox.eval("seats +", &context).is_err());
+        assert!(sandbox.check("seats > 10").is_ok());
+        assert!(sandbox.check("seats >").is_err());
+    }
+
+    #[test]
+    fn test_limits() {
+        let context = json!({});
+        let sandbox = Sandbox::new().with_max_length(32).with_max_depth(2);
+        for expr in [
+            "import \"/etc/passwd\"",
+            "\"%{import \"a.ncl\"}\"",
+            "let x = import in x",
+            "[[[1]]]",
+            "1 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1",
+        ] {
+            assert!(sandbox.eval(expr, &context).is_err(), "{}", expr);
+        }
+        assert!(!imports("important + reimport"));
+
+        let error = Sandbox::new()
+            .with_fuel(Duration::ZERO)
+            .eval("std.array.map (fun n => n + 1) [1, 2]", &context)
+            .unwrap_err();
+        assert_eq!(error.to_string(), "Resource limit exceeded: time (0 s)");
+    }
+
+    #[test]
+    fn test_runaway_evaluations_stop() {
+        let context = json!({});
+        let sandbox = Sandbox::new().with_fuel(Duration::from_secs(30));
+        let cases = [
+            (
+                sandbox.clone().with_fuel(Duration::from_millis(100)),
+                "let rec f = fun x => f x in f 1",
+                "time (100 ms)",
+            ),
+            (
+                sandbox.clone().with_max_memory(1 << 20),
+                "std.array.length (std.array.generate (fun i => i) 100000000)",
+                "memory (1M)",
+            ),
+            (
+                sandbox.clone().with_max_memory(1 << 20),
+                "let rec f = fun s n => if n == 0 then s else f (s ++ s) (n - 1) in f \"x\" 40",
+                "memory (1M)",
+            ),
+        ];
+        for (sandbox, expr, limit) in cases {
+            // Evaluation runs on the thread that asks, so once that thread
+            // has been joined nothing of the evaluation is left running
+            let context = context.clone();
+            let evaluation = std::thread::spawn(move || sandbox.eval(expr, &context));
+            let error = evaluation.join().unwrap().unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                format!("Resource limit exceeded: {}", limit),
+                "{}",
+                expr
+            );
+        }
+        let sandbox = Sandbox::new().with_max_memory(1 << 20);
+        assert!(sandbox
+            .test(
+                "std.array.length (std.array.generate (fun i => i) 100) == 100",
+                &context
+            )
+            .unwrap());
+    }
+}
@@ -0,0 +1,116 @@
+//! Safe core of the C ABI
+//!
+//! The C ABI used by the Deno and ReScript bindings is declared in
+//! `bindings/c/bunsenite.h` and exported by the Zig layer, which keeps this
+//! crate free of `unsafe`. Everything except raw pointer handling lives here
+//! as ordinary Rust, so the marshalling rules (JSON encoding, status codes)
+//! are defined once and tested alongside the core.
+
+use crate::loader::NickelLoader;
+use crate::version::VERSION_INFO;
+
+/// Status code for success
+pub const STATUS_OK: i32 = 0;
+
+/// Status code for a configuration error (parse, evaluation, validation)
+pub const STATUS_INVALID: i32 = 1;
+
+/// Status code for a failure unrelated to the input
+pub const STATUS_INTERNAL: i32 = 2;
+
+/// Backs `parse_nickel`: evaluate a program to a JSON string
+///
+/// Returns `None` on failure, which the ABI reports as a null pointer.
+pub fn parse_nickel(source: &str, name: &str) -> Option<String> {
+    let value = NickelLoader::new().parse_string(source, name).ok()?;
+    let json = crate::json::to_string(&value, false);
+    crate::json::drop_deep(value);
+    Some(json)
+}
+
+/// An evaluated config kept alive across a binding
+///
+/// Backs the WASM `NickelConfig`: a program is evaluated once and hosts then
+/// look up subtrees with [`query`], instead of marshalling the whole config
+/// for every lookup. The C ABI has no handle API, since nothing in this
+/// crate exports one.
+#[derive(Debug)]
+pub struct Handle {
+    value: serde_json::Value,
+}
+
+impl Handle {
+    /// Wrap an already evaluated config
+    pub fn from_value(value: serde_json::Value) -> Self {
+        Self { value }
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        crate::json::drop_deep(std::mem::take(&mut self.value));
+    }
+}
+
+/// The subtree of a handle at `path` as a JSON string
+///
+/// Paths use [`json::key_path`](crate::json::key_path) syntax, e.g.
+/// `servers[0].port`; the empty path selects the whole config. Returns
+/// `None` if nothing is at the path.
+pub fn query(handle: &Handle, path: &str) -> Option<String> {
+    crate::json::select(&handle.value, path).map(|value| crate::json::to_string(value, false))
+}
+
+/// Backs `validate_nickel`: returns [`STATUS_OK`] or an error status
+pub fn validate_nickel(source: &str, name: &str) -> i32 {
+    match NickelLoader::new().validate(source, name) {
+        Ok(()) => STATUS_OK,
+        Err(e) if e.is_recoverable() => STATUS_INVALID,
+        Err(_) => STATUS_INTERNAL,
+    }
+}
+
+/// Backs `version_info`: embedded version information as a JSON string
+pub fn version_info() -> String {
+    crate::json::to_string(&VERSION_INFO.to_json(), false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_nickel() {
+        assert_eq!(
+            parse_nickel("{ foo = 42 }", "test.ncl").as_deref(),
+            Some(r#"{"foo":42}"#)
+        );
+        assert_eq!(parse_nickel("{ foo = }", "bad.ncl"), None);
+    }
+
+    #[test]
+    fn test_handle_queries() {
+        let value = NickelLoader::new()
+            .parse_string(r#"{ db = { host = "h", port = 5432 } }"#, "test.ncl")
+            .unwrap();
+        let handle = Handle::from_value(value);
+        assert_eq!(query(&handle, "db.port").as_deref(), Some("5432"));
+        assert_eq!(
+            query(&handle, "").as_deref(),
+            Some(r#"{"db":{"host":"h","port":5432}}"#)
+        );
+        assert_eq!(query(&handle, "db.user"), None);
+    }
+
+    #[test]
+    fn test_validate_nickel_status() {
+        assert_eq!(validate_nickel("{ foo = 42 }", "test.ncl"), STATUS_OK);
+        assert_eq!(validate_nickel("{ foo = }", "bad.ncl"), STATUS_INVALID);
+    }
+
+    #[test]
+    fn test_version_info_is_json() {
+        let info: serde_json::Value = serde_json::from_str(&version_info()).unwrap();
+        assert_eq!(info["nickel_language"], VERSION_INFO.nickel_language);
+    }
+}
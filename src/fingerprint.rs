@@ -0,0 +1,323 @@
+//! Import-graph fingerprints for incremental checks
+//!
+//! Backs `validate --incremental` and `build --incremental`: in CI, most
+//! configs of a tree are unchanged between commits, so checking all of them
+//! again repeats work whose outcome is already known. As cargo does for
+//! crates, each config gets a [`Fingerprint`] covering everything its result
+//! depends on, and a [`FreshCache`] remembers the fingerprints of configs
+//! that last succeeded. A config whose fingerprint is unchanged is *fresh*
+//! and skipped.
+//!
+//! A fingerprint covers the bunsenite version, the loader's settings
+//! (transforms, overrides, target, import policies), and the
+//! contents of the config and every file it transitively imports (found with
+//! [`imports::prefetch`]), as well as imports that are missing, so creating
+//! one makes the config stale. `exec:` transforms are assumed to give the
+//! same output for the same input.
+//!
+//! Only successes are recorded: a config that failed is checked again on
+//! every run. For tasks that write files, the cache also records what was
+//! written, and a config is only fresh while those outputs are unchanged.
+//!
+//! Paths below the working directory are fingerprinted and recorded
+//! relative to it, so a [`FreshCache`] shared between CI runners
+//! ([`FreshCache::with_remote`]) holds for every checkout of the project.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::fingerprint::{Fingerprint, FreshCache};
+//! use bunsenite::NickelLoader;
+//!
+//! let dir = tempfile::tempdir().unwrap();
+//! let config = dir.path().join("app.ncl");
+//! std::fs::write(&config, r#"{ port = 80 }"#).unwrap();
+//!
+//! let loader = NickelLoader::new();
+//! let cache = FreshCache::new(dir.path().join("cache"));
+//! let fingerprint = Fingerprint::of(&loader, &config).unwrap();
+//! assert!(!cache.is_fresh("validate", &config, &fingerprint));
+//!
+//! loader.validate_many(&[&config]);
+//! cache.record("validate", &config, &fingerprint, &[]).unwrap();
+//! assert!(cache.is_fresh("validate", &config, &fingerprint));
+//! ```
+
+use crate::cache::{CacheBackend, LocalBackend};
+use crate::error::Result;
+use crate::imports;
+use crate::json;
+use crate::loader::NickelLoader;
+use crate::paths;
+use crate::target::sha256_hex;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// SHA-256 over a config, the files it imports and the settings it is
+/// evaluated with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fingerprint(String);
+
+impl Fingerprint {
+    /// Fingerprint `file` as `loader` would evaluate it
+    ///
+    /// The import graph is read on up to
+    /// [`loader.threads()`](NickelLoader::threads) threads.
+    ///
+    /// # Errors
+    ///
+    /// Returns an I/O error if `file` or one of its imports cannot be read
+    pub fn of(loader: &NickelLoader, file: &Path) -> Result<Self> {
+        let graph = imports::prefetch(file, loader.threads());
+        if graph.files.is_empty() {
+            // The config itself is unreadable; report why
+            std::fs::read(file)?;
+        }
+
+        let mut hasher = Sha256::new();
+        hasher.update(crate::VERSION);
+        hasher.update([0]);
+        hasher.update(loader.settings());
+        for path in &graph.files {
+            let contents = std::fs::read(paths::to_open(path))?;
+            hasher.update([0]);
+            hasher.update(relative(path).to_string_lossy().as_bytes());
+            hasher.update([0]);
+            hasher.update(Sha256::digest(&contents));
+        }
+        for path in &graph.missing {
+            hasher.update([1]);
+            hasher.update(relative(path).to_string_lossy().as_bytes());
+        }
+        Ok(Self(format!("{:x}", hasher.finalize())))
+    }
+
+    /// The fingerprint, hex-encoded
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Fingerprints of configs that last succeeded, one entry per config and
+/// task
+///
+/// A task names what was done with the config and how, e.g. `validate` or
+/// `build out/`; a config is fresh for one task and not another.
+///
+/// Entries live in a local directory and, optionally, a shared
+/// [`CacheBackend`] that local misses read through to.
+#[derive(Debug, Clone)]
+pub struct FreshCache {
+    local: LocalBackend,
+    remote: Option<Arc<dyn CacheBackend>>,
+}
+
+impl FreshCache {
+    /// A cache in `dir`, created when first written
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            local: LocalBackend::new(dir),
+            remote: None,
+        }
+    }
+
+    /// Share entries through `remote`
+    ///
+    /// Configs not fresh locally are looked up in `remote`, and fresh
+    /// entries found there are copied to the local directory; recorded
+    /// entries are written to both. Failing to read `remote` counts as a
+    /// miss.
+    pub fn with_remote(mut self, remote: Arc<dyn CacheBackend>) -> Self {
+        self.remote = Some(remote);
+        self
+    }
+
+    /// The default cache directory
+    ///
+    /// `fresh` in [`cache::home`](crate::cache::home), next to the
+    /// [`oci::Cache`](crate::oci::Cache).
+    pub fn default_dir() -> Option<PathBuf> {
+        crate::cache::home().map(|home| home.join("fresh"))
+    }
+
+    /// Cache directory
+    pub fn dir(&self) -> &Path {
+        self.local.dir()
+    }
+
+    /// Whether `file` last succeeded at `task` with this fingerprint, and
+    /// the outputs it wrote then are unchanged
+    pub fn is_fresh(&self, task: &str, file: &Path, fingerprint: &Fingerprint) -> bool {
+        let key = entry_key(task, file);
+        let local = self.local.get(&key).ok().flatten();
+        if local.is_some_and(|entry| is_current(&entry, fingerprint)) {
+            return true;
+        }
+        let Some(remote) = &self.remote else {
+            return false;
+        };
+        match remote.get(&key) {
+            Ok(Some(entry)) if is_current(&entry, fingerprint) => {
+                // Failing to keep a copy only costs fetching it again
+                let _ = self.local.put(&key, &entry);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The outputs recorded for `file` at `task`, relative to the working
+    /// directory where they lie below it; empty if nothing is recorded
+    pub fn outputs(&self, task: &str, file: &Path) -> Vec<PathBuf> {
+        let entry = self
+            .local
+            .get(&entry_key(task, file))
+            .ok()
+            .flatten()
+            .and_then(|entry| serde_json::from_slice::<Value>(&entry).ok());
+        entry
+            .as_ref()
+            .and_then(|entry| entry["outputs"].as_object())
+            .map(|outputs| outputs.keys().map(PathBuf::from).collect())
+            .unwrap_or_default()
+    }
+
+    /// Record that `file` succeeded at `task` with this fingerprint, writing
+    /// `outputs`
+    ///
+    /// # Errors
+    ///
+    /// Returns an I/O error if an output cannot be read or the cache cannot
+    /// be written, or the remote's error if it cannot be written
+    pub fn record(
+        &self,
+        task: &str,
+        file: &Path,
+        fingerprint: &Fingerprint,
+        outputs: &[PathBuf],
+    ) -> Result<()> {
+        let mut recorded = serde_json::Map::new();
+        for path in outputs {
+            let bytes = std::fs::read(path)?;
+            recorded.insert(
+                relative(path).to_string_lossy().into_owned(),
+                Value::String(sha256_hex(&bytes)),
+            );
+        }
+        let entry = json!({
+            "task": task,
+            "file": relative(file).to_string_lossy(),
+            "fingerprint": fingerprint.as_str(),
+            "outputs": recorded,
+        });
+
+        let key = entry_key(task, file);
+        let entry = json::to_string(&entry, true);
+        self.local.put(&key, entry.as_bytes())?;
+        match &self.remote {
+            Some(remote) => remote.put(&key, entry.as_bytes()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Key of the entry for `file` at `task`
+fn entry_key(task: &str, file: &Path) -> String {
+    let key = format!("{}\0{}", task, relative(file).to_string_lossy());
+    sha256_hex(key.as_bytes())
+}
+
+/// Whether `entry` records this fingerprint, with outputs that are unchanged
+fn is_current(entry: &[u8], fingerprint: &Fingerprint) -> bool {
+    let Ok(entry) = serde_json::from_slice::<Value>(entry) else {
+        return false;
+    };
+    entry["fingerprint"] == fingerprint.as_str()
+        && entry["outputs"].as_object().is_some_and(|outputs| {
+            outputs.iter().all(|(path, digest)| {
+                std::fs::read(path).is_ok_and(|bytes| *digest == sha256_hex(&bytes))
+            })
+        })
+}
+
+/// `path` normalized and, if it lies below the working directory, made
+/// relative to it
+fn relative(path: &Path) -> PathBuf {
+    match std::env::current_dir() {
+        Ok(cwd) => paths::relative_to(&imports::normalize(&cwd.join(path)), &cwd),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fingerprint_follows_imports() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = dir.path().join("app.ncl");
+        let base = dir.path().join("base.ncl");
+        std::fs::write(&app, r#"import "base.ncl" & import "extra.ncl""#).unwrap();
+        std::fs::write(&base, "{ port = 80 }").unwrap();
+
+        let loader = NickelLoader::new().with_threads(1);
+        let first = Fingerprint::of(&loader, &app).unwrap();
+        assert_eq!(Fingerprint::of(&loader, &app).unwrap(), first);
+
+        std::fs::write(&base, "{ port = 81 }").unwrap();
+        let edited = Fingerprint::of(&loader, &app).unwrap();
+        assert_ne!(edited, first);
+
+        // A missing import appearing makes the config stale
+        std::fs::write(dir.path().join("extra.ncl"), "{}").unwrap();
+        assert_ne!(Fingerprint::of(&loader, &app).unwrap(), edited);
+
+        let compat = NickelLoader::new().with_threads(1).with_compat(true);
+        assert_ne!(Fingerprint::of(&compat, &app).unwrap(), edited);
+        assert!(Fingerprint::of(&loader, &dir.path().join("gone.ncl")).is_err());
+    }
+
+    #[test]
+    fn test_fresh_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = dir.path().join("app.ncl");
+        let out = dir.path().join("app.json");
+        std::fs::write(&app, "{ port = 80 }").unwrap();
+        std::fs::write(&out, r#"{"port":80}"#).unwrap();
+
+        let cache = FreshCache::new(dir.path().join("cache"));
+        let fingerprint = Fingerprint::of(&NickelLoader::new(), &app).unwrap();
+        cache
+            .record("build", &app, &fingerprint, std::slice::from_ref(&out))
+            .unwrap();
+        assert!(cache.is_fresh("build", &app, &fingerprint));
+        assert!(!cache.is_fresh("validate", &app, &fingerprint));
+
+        assert_eq!(cache.outputs("build", &app), vec![out.clone()]);
+
+        // Outputs changed since are rebuilt
+        std::fs::write(&out, r#"{"port":1}"#).unwrap();
+        assert!(!cache.is_fresh("build", &app, &fingerprint));
+    }
+
+    #[test]
+    fn test_remote_read_through() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = dir.path().join("app.ncl");
+        std::fs::write(&app, "{ port = 80 }").unwrap();
+        let fingerprint = Fingerprint::of(&NickelLoader::new(), &app).unwrap();
+
+        // One runner records into the shared cache, another finds it there
+        let shared: Arc<dyn CacheBackend> = Arc::new(LocalBackend::new(dir.path().join("shared")));
+        let first = FreshCache::new(dir.path().join("a")).with_remote(shared.clone());
+        first.record("validate", &app, &fingerprint, &[]).unwrap();
+
+        let second = FreshCache::new(dir.path().join("b")).with_remote(shared);
+        assert!(second.is_fresh("validate", &app, &fingerprint));
+        assert_eq!(std::fs::read_dir(second.dir()).unwrap().count(), 1);
+        assert!(!FreshCache::new(dir.path().join("c")).is_fresh("validate", &app, &fingerprint));
+    }
+}
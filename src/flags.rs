@@ -0,0 +1,349 @@
+//! Feature flags decided by Nickel predicates
+//!
+//! A flags file is a Nickel record with a field per flag, each one of
+//!
+//! - a `Bool`: the flag is on or off for everyone
+//! - a `String`: a predicate, evaluated in an [expression
+//!   sandbox](crate::expr) against the caller's context
+//! - a record `{ when, default }`: a predicate, and whether the flag is on
+//!   when the predicate fails to evaluate (`false` unless given)
+//!
+//! ```nickel
+//! {
+//!   new_checkout = true,
+//!   beta_search = "user.plan == \"pro\"",
+//!   eu_pricing = { when = "std.array.elem region [\"de\", \"fr\"]", default = false },
+//! }
+//! ```
+//!
+//! The file is evaluated, and every predicate checked and parsed, once, by
+//! [`Flags::load`] (other threads parse a predicate again the first time
+//! they evaluate it; see [`Sandbox::compile`]). After that,
+//! [`Flags::is_enabled`] answers fixed flags at once and remembers each
+//! predicate's answer per context, so a context seen before costs a
+//! lookup, not an evaluation. A predicate that runs out of fuel or memory
+//! is disabled rather than run again. [`Flags`] is `Send` and `Sync`: share
+//! one behind an `Arc` and ask from any thread.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::flags::Flags;
+//! use serde_json::json;
+//!
+//! let flags = Flags::from_value(&json!({
+//!     "new_checkout": true,
+//!     "beta_search": "user.plan == \"pro\"",
+//! }))
+//! .unwrap();
+//!
+//! let pro = json!({ "user": { "plan": "pro" } });
+//! assert!(flags.is_enabled("beta_search", &pro));
+//! assert!(!flags.is_enabled("beta_search", &json!({ "user": { "plan": "free" } })));
+//! assert!(!flags.is_enabled("no_such_flag", &pro));
+//! ```
+
+use crate::error::{Error, Result};
+use crate::expr::{Expression, Sandbox};
+use crate::json;
+use crate::loader::NickelLoader;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+
+/// Answers remembered per predicate unless another limit is given
+pub const DEFAULT_CACHE_SIZE: usize = 10_000;
+
+/// How one flag is decided
+#[derive(Debug)]
+enum Rule {
+    /// The same for every context
+    Fixed(bool),
+    /// By a predicate, with past answers by context
+    When {
+        expr: Expression,
+        default: bool,
+        answers: Mutex<HashMap<String, bool>>,
+        /// The resource and limit the predicate ran out of, once it has
+        disabled: OnceLock<(String, String)>,
+    },
+}
+
+/// A set of feature flags, loaded once and asked many times
+#[derive(Debug)]
+pub struct Flags {
+    rules: BTreeMap<String, Rule>,
+    cache_size: usize,
+}
+
+impl Flags {
+    /// The flags in Nickel file `path`
+    ///
+    /// # Errors
+    ///
+    /// Returns the evaluation error of the file, or an error for the first
+    /// flag that is malformed or whose predicate does not parse (see
+    /// [`from_value`](Self::from_value))
+    pub fn load(loader: &NickelLoader, path: &Path) -> Result<Self> {
+        Self::from_value(&loader.parse_file(path)?)
+    }
+
+    /// The flags in `value`, a record of flags as in a flags file
+    ///
+    /// Predicates are compiled with the default [`Sandbox`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an invalid-input error if `value` is not a record of flags,
+    /// or the error of the first predicate that does not pass
+    /// [`Sandbox::compile`]
+    pub fn from_value(value: &Value) -> Result<Self> {
+        Self::from_value_in(value, Sandbox::new())
+    }
+
+    /// The flags in `value`, with predicates evaluated in `sandbox`
+    ///
+    /// # Errors
+    ///
+    /// As [`from_value`](Self::from_value)
+    pub fn from_value_in(value: &Value, sandbox: Sandbox) -> Result<Self> {
+        let Value::Object(flags) = value else {
+            return Err(Error::invalid_input(format!(
+                "Flags must be a record, got {}",
+                json::to_string(value, false)
+            )));
+        };
+        let mut rules = BTreeMap::new();
+        for (name, flag) in flags {
+            rules.insert(name.clone(), rule(name, flag, &sandbox)?);
+        }
+        Ok(Self {
+            rules,
+            cache_size: DEFAULT_CACHE_SIZE,
+        })
+    }
+
+    /// Remember at most `entries` answers per predicate; 0 remembers none
+    ///
+    /// A predicate that has answered for `entries` contexts forgets them
+    /// all and starts again.
+    pub fn with_cache_size(mut self, entries: usize) -> Self {
+        self.cache_size = entries;
+        self
+    }
+
+    /// The flags' names, in order
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.rules.keys().map(String::as_str)
+    }
+
+    /// Whether `flag` is on for `context`
+    ///
+    /// Flags that do not exist are off, and predicates that fail to
+    /// evaluate give their flag's default; use [`evaluate`](Self::evaluate)
+    /// to tell these cases apart.
+    pub fn is_enabled(&self, flag: &str, context: &Value) -> bool {
+        match self.rules.get(flag) {
+            Some(Rule::Fixed(on)) => *on,
+            Some(Rule::When { default, .. }) => self.evaluate(flag, context).unwrap_or(*default),
+            None => false,
+        }
+    }
+
+    /// Whether `flag` is on for `context`, or why that cannot be told
+    ///
+    /// A predicate that runs out of fuel or memory is disabled: from then
+    /// on it fails with the same resource-limit error for every context,
+    /// without being evaluated. Other failures are not remembered, since
+    /// they may depend on the context and stop as soon as they happen.
+    ///
+    /// # Errors
+    ///
+    /// Returns an invalid-input error if there is no flag `flag` or its
+    /// predicate does not evaluate to a `Bool`, a resource-limit error if
+    /// the predicate is disabled, or the evaluation error of the predicate
+    pub fn evaluate(&self, flag: &str, context: &Value) -> Result<bool> {
+        let (expr, answers, disabled) = match self.rules.get(flag) {
+            Some(Rule::Fixed(on)) => return Ok(*on),
+            Some(Rule::When {
+                expr,
+                answers,
+                disabled,
+                ..
+            }) => (expr, answers, disabled),
+            None => return Err(Error::invalid_input(format!("No flag `{}`", flag))),
+        };
+        if let Some((resource, limit)) = disabled.get() {
+            return Err(Error::resource_limit(resource.as_str(), limit.as_str()));
+        }
+        let key = json::to_string(context, false);
+        if let Some(on) = answers.lock().expect("flag answers poisoned").get(&key) {
+            return Ok(*on);
+        }
+
+        // Evaluated without the lock, so one slow predicate does not hold up
+        // other contexts
+        let on = match expr.test(context) {
+            Err(Error::ResourceLimit { resource, limit }) => {
+                let _ = disabled.set((resource.clone(), limit.clone()));
+                return Err(Error::ResourceLimit { resource, limit });
+            }
+            result => result?,
+        };
+        if self.cache_size > 0 {
+            let mut answers = answers.lock().expect("flag answers poisoned");
+            if answers.len() >= self.cache_size {
+                answers.clear();
+            }
+            answers.insert(key, on);
+        }
+        Ok(on)
+    }
+}
+
+/// The rule of flag `name`, written as `flag`, with any predicate compiled
+/// in `sandbox`
+fn rule(name: &str, flag: &Value, sandbox: &Sandbox) -> Result<Rule> {
+    let malformed = || {
+        Error::invalid_input(format!(
+            "Flag `{}` must be a Bool, a predicate string or {{ when, default }}, got {}",
+            name,
+            json::to_string(flag, false)
+        ))
+    };
+    let (expr, default) = match flag {
+        Value::Bool(on) => return Ok(Rule::Fixed(*on)),
+        Value::String(expr) => (expr, false),
+        Value::Object(fields) => {
+            if fields.keys().any(|key| key != "when" && key != "default") {
+                return Err(malformed());
+            }
+            let expr = match fields.get("when") {
+                Some(Value::String(expr)) => expr,
+                _ => return Err(malformed()),
+            };
+            let default = match fields.get("default") {
+                Some(Value::Bool(default)) => *default,
+                None => false,
+                Some(_) => return Err(malformed()),
+            };
+            (expr, default)
+        }
+        _ => return Err(malformed()),
+    };
+    let expr = sandbox
+        .compile(expr)
+        .map_err(|e| Error::invalid_input(format!("Flag `{}` has a bad predicate: {}", name, e)))?;
+    Ok(Rule::When {
+        expr,
+        default,
+        answers: Mutex::default(),
+        disabled: OnceLock::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    fn answers(flags: &Flags, flag: &str) -> usize {
+        match &flags.rules[flag] {
+            Rule::When { answers, .. } => answers.lock().expect("flag answers poisoned").len(),
+            Rule::Fixed(_) => 0,
+        }
+    }
+
+    #[test]
+    fn test_flags() {
+        let flags = Flags::from_value(&json!({
+            "on": true,
+            "off": false,
+            "pro": "plan == \"pro\"",
+            "eu": { "when": "std.array.elem region [\"de\", \"fr\"]", "default": true },
+        }))
+        .unwrap();
+        assert_eq!(
+            flags.names().collect::<Vec<_>>(),
+            ["eu", "off", "on", "pro"]
+        );
+
+        let context = json!({ "plan": "pro", "region": "us" });
+        assert!(flags.is_enabled("on", &context));
+        assert!(!flags.is_enabled("off", &context));
+        assert!(flags.is_enabled("pro", &context));
+        assert!(!flags.is_enabled("eu", &context));
+        assert!(flags.evaluate("missing", &context).is_err());
+
+        // No `region`: the predicate fails and the default applies
+        let context = json!({ "plan": "free" });
+        assert!(!flags.is_enabled("pro", &context));
+        assert!(flags.evaluate("eu", &context).is_err());
+        assert!(flags.is_enabled("eu", &context));
+    }
+
+    #[test]
+    fn test_answers_are_cached() {
+        let flags = Arc::new(
+            Flags::from_value(&json!({ "big": "seats > 10" }))
+                .unwrap()
+                .with_cache_size(2),
+        );
+        std::thread::scope(|scope| {
+            for seats in [5, 50] {
+                let flags = Arc::clone(&flags);
+                scope.spawn(move || {
+                    let context = json!({ "seats": seats });
+                    for _ in 0..3 {
+                        assert_eq!(flags.is_enabled("big", &context), seats > 10);
+                    }
+                });
+            }
+        });
+        assert_eq!(answers(&flags, "big"), 2);
+
+        flags.is_enabled("big", &json!({ "seats": 11 }));
+        assert_eq!(answers(&flags, "big"), 1);
+    }
+
+    #[test]
+    fn test_runaway_predicate_is_disabled() {
+        let sandbox = Sandbox::new().with_fuel(Duration::from_millis(50));
+        let flags = Flags::from_value_in(
+            &json!({
+                "spin": { "when": "let rec f = fun x => f x in f seats", "default": true },
+                "big": "seats > 10",
+            }),
+            sandbox,
+        )
+        .unwrap();
+
+        let start = Instant::now();
+        for seats in 0..100 {
+            let context = json!({ "seats": seats });
+            assert!(flags.is_enabled("spin", &context));
+            let error = flags.evaluate("spin", &context).unwrap_err();
+            assert_eq!(error.to_string(), "Resource limit exceeded: time (50 ms)");
+        }
+        // Only the first call ran the predicate; each run would take 50 ms
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert_eq!(answers(&flags, "spin"), 0);
+        assert!(flags.is_enabled("big", &json!({ "seats": 11 })));
+    }
+
+    #[test]
+    fn test_bad_flags() {
+        for flags in [
+            json!([]),
+            json!({ "a": 1 }),
+            json!({ "a": { "when": "true", "unless": "false" } }),
+            json!({ "a": "seats >" }),
+            json!({ "a": "import \"other.ncl\"" }),
+        ] {
+            assert!(Flags::from_value(&flags).is_err(), "{}", flags);
+        }
+    }
+}
@@ -0,0 +1,265 @@
+//! Canonical formatting of Nickel source
+//!
+//! Backs `bunsenite fmt` and [`format_source`]. The formatter only changes
+//! whitespace outside strings and comments, so it can never change what a
+//! program means, and it keeps the author's line breaks. Within that, the
+//! style is fixed:
+//!
+//! - indentation is two spaces per open `{`, `[` or `(`, plus one level for
+//!   the continuation of a line ending in `=`, `=>`, `then` or `else` (until
+//!   the `,`, closing bracket or `in` that ends it)
+//! - one space around `=`, after `,`, and inside non-empty `{ }`; none inside
+//!   `( )` and `[ ]` or before `,`; runs of spaces become one
+//! - no trailing whitespace, at most one blank line in a row, none just
+//!   inside brackets, and one newline at the end of the file
+//!
+//! Strings (including multiline `m%"…"%` strings and their interpolations)
+//! and comments are copied as they are.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::fmt::format_source;
+//!
+//! let source = "{\n      name=\"app\" ,\n  ports = [ 80,443 ],\n\n\n}\n";
+//! assert_eq!(
+//!     format_source(source).unwrap(),
+//!     "{\n  name = \"app\",\n  ports = [80, 443],\n}\n"
+//! );
+//! ```
+
+use crate::error::{Error, Result};
+use crate::lexer::{self, Token};
+
+/// Format Nickel source in the canonical style
+///
+/// Formatting is idempotent: formatted source formats to itself.
+///
+/// # Errors
+///
+/// Returns an invalid-input error for unterminated strings and unbalanced
+/// brackets; source that does not lex is left to Nickel to diagnose
+pub fn format_source(source: &str) -> Result<String> {
+    let tokens = lexer::tokens(source)?;
+    let formatted = layout(&tokens)?;
+
+    // Whitespace is all that may change
+    let significant = |tokens: Vec<Token>| -> Vec<Token> {
+        tokens
+            .into_iter()
+            .filter(|t| !matches!(t, Token::Space | Token::Newline))
+            .collect()
+    };
+    if significant(lexer::tokens(&formatted)?) != significant(tokens) {
+        return Err(Error::internal(
+            "Formatting would change the program's tokens",
+        ));
+    }
+    Ok(formatted)
+}
+
+/// Whether `source` is already formatted
+///
+/// # Errors
+///
+/// As [`format_source`]
+pub fn is_formatted(source: &str) -> Result<bool> {
+    Ok(format_source(source)? == source)
+}
+
+/// Lay out tokens line by line
+fn layout(tokens: &[Token]) -> Result<String> {
+    let mut out = String::new();
+    // Open brackets, each with the continuation levels pending inside it;
+    // the first entry is the top level
+    let mut open: Vec<(char, usize)> = vec![(' ', 0)];
+    let mut blank = false;
+    let mut previous_opened = true;
+
+    for line in lines(tokens) {
+        if line.is_empty() {
+            blank = true;
+            continue;
+        }
+
+        // Closers at the start of the line dedent it
+        let closers = line
+            .iter()
+            .take_while(|(t, _)| matches!(t, Token::Punct('}' | ']' | ')')))
+            .count();
+        let depth = (open.len() - 1).saturating_sub(closers);
+        if line[0].0.is_word("in") || line[0].0.is_word("else") {
+            open[depth].1 = open[depth].1.saturating_sub(1);
+        }
+        let level = depth + open[..=depth].iter().map(|(_, hang)| hang).sum::<usize>();
+
+        if blank && !previous_opened && closers == 0 {
+            out.push('\n');
+        }
+        blank = false;
+        out.push_str(&"  ".repeat(level));
+        for (i, (token, spaced)) in line.iter().enumerate() {
+            if i > 0 && space_between(line[i - 1].0, token, *spaced) {
+                out.push(' ');
+            }
+            out.push_str(&token.text());
+        }
+        out.push('\n');
+
+        for (token, _) in &line {
+            match token {
+                Token::Punct(c @ ('{' | '[' | '(')) => open.push((*c, 0)),
+                Token::Punct(c @ ('}' | ']' | ')')) => {
+                    let expected = match c {
+                        '}' => '{',
+                        ']' => '[',
+                        _ => '(',
+                    };
+                    if open.len() == 1 || open[open.len() - 1].0 != expected {
+                        return Err(Error::invalid_input(format!(
+                            "Unbalanced closing bracket '{}'",
+                            c
+                        )));
+                    }
+                    open.pop();
+                }
+                _ => {}
+            }
+        }
+        let depth = open.len() - 1;
+        let last = line
+            .iter()
+            .rev()
+            .map(|(t, _)| *t)
+            .find(|t| !matches!(t, Token::Comment(_)));
+        let hang = &mut open[depth].1;
+        match last {
+            Some(Token::Op(op)) if op == "=" || op == "=>" => *hang += 1,
+            Some(t) if t.is_word("then") || t.is_word("else") => *hang += 1,
+            Some(Token::Punct(',')) => *hang = 0,
+            Some(t) if t.is_word("in") && !line.iter().any(|(t, _)| t.is_word("let")) => {
+                *hang = hang.saturating_sub(1);
+            }
+            _ => {}
+        }
+        previous_opened = matches!(last, Some(Token::Punct('{' | '[' | '(')));
+    }
+
+    if let Some((c, _)) = open.get(1) {
+        return Err(Error::invalid_input(format!("Unclosed bracket '{}'", c)));
+    }
+    Ok(out)
+}
+
+/// Split tokens into lines of non-space tokens, each with whether
+/// whitespace preceded it
+fn lines(tokens: &[Token]) -> Vec<Vec<(&Token, bool)>> {
+    let mut lines = vec![Vec::new()];
+    let mut spaced = false;
+    for token in tokens {
+        match token {
+            Token::Newline => {
+                lines.push(Vec::new());
+                spaced = false;
+            }
+            Token::Space => spaced = true,
+            token => {
+                lines
+                    .last_mut()
+                    .expect("there is always a line")
+                    .push((token, spaced));
+                spaced = false;
+            }
+        }
+    }
+    lines
+}
+
+/// Whether to put a space between `left` and `right`, which the source
+/// separated by whitespace if `spaced`
+fn space_between(left: &Token, right: &Token, spaced: bool) -> bool {
+    // Removing space next to an operator could merge tokens (`[ |` into `[|`)
+    let removable = !left.is_op() && !right.is_op();
+    match (left, right) {
+        (_, Token::Comment(_)) => true,
+        (Token::Punct('{'), Token::Punct('}')) => false,
+        (Token::Punct('(' | '['), _) | (_, Token::Punct(')' | ']')) if removable => false,
+        (_, Token::Punct(',' | ';')) if removable => false,
+        (Token::Punct(',' | ';'), _) => true,
+        (Token::Punct('{'), _) | (_, Token::Punct('}')) => true,
+        (Token::Op(op), _) | (_, Token::Op(op)) if op == "=" => true,
+        _ => spaced,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_indentation_and_continuations() {
+        let source = r#"
+let  base=import "base.ncl" in
+{
+name="app",
+    replicas =
+  if base.prod then
+3
+ else
+  1,
+      ports=[ 80 , 443 ],
+  handler = fun x =>
+x + 1,
+check = match {
+'A => 1,
+},
+nested={a={b=1}},
+empty = { },
+}
+"#;
+        let expected = r#"let base = import "base.ncl" in
+{
+  name = "app",
+  replicas =
+    if base.prod then
+      3
+    else
+      1,
+  ports = [80, 443],
+  handler = fun x =>
+    x + 1,
+  check = match {
+    'A => 1,
+  },
+  nested = { a = { b = 1 } },
+  empty = {},
+}
+"#;
+        let formatted = format_source(source).unwrap();
+        assert_eq!(formatted, expected);
+        assert!(is_formatted(&formatted).unwrap());
+    }
+
+    #[test]
+    fn test_strings_and_comments_are_kept() {
+        let source = "{\n  # keep   this  \n  a = m%\"\n      two  \n   one %{ \"x\"  ++ \"}\" }\n  \"%,\n  b =\"%{  {c=1}.c  }\" ,   # why\n}\n";
+        let formatted = format_source(source).unwrap();
+        assert_eq!(
+            formatted,
+            "{\n  # keep   this\n  a = m%\"\n      two  \n   one %{ \"x\"  ++ \"}\" }\n  \"%,\n  b = \"%{  {c=1}.c  }\", # why\n}\n"
+        );
+        assert!(is_formatted(&formatted).unwrap());
+    }
+
+    #[test]
+    fn test_blank_lines_and_errors() {
+        assert_eq!(
+            format_source("\n\n{\n\n  a = 1,\n\n\n  b = 2,\n\n}\n\n").unwrap(),
+            "{\n  a = 1,\n\n  b = 2,\n}\n"
+        );
+        assert_eq!(format_source("").unwrap(), "");
+        assert!(format_source("{ a = \"x }").is_err());
+        assert!(format_source("{ a = 1 }}").is_err());
+        assert!(format_source("{ a = [1 }").is_err());
+    }
+}
@@ -0,0 +1,269 @@
+//! Evaluating related configs together
+//!
+//! An [`EvalGroup`] evaluates several files or sources concurrently with one
+//! loader and gathers every member's result in a single [`GroupReport`].
+//! Cancelling the group's [`CancelToken`] (from any thread) cancels every
+//! member that has not started yet; with
+//! [`with_fail_fast`](EvalGroup::with_fail_fast), the first failure does so
+//! too. A member that is already evaluating runs to completion, since Nickel
+//! evaluation cannot be interrupted.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::group::EvalGroup;
+//! use bunsenite::NickelLoader;
+//!
+//! let report = EvalGroup::new(NickelLoader::new())
+//!     .with_source("a.ncl", "{ a = 1 }")
+//!     .with_source("b.ncl", "{ b = }")
+//!     .run();
+//!
+//! assert!(!report.is_success());
+//! assert_eq!(report.failures().count(), 1);
+//! ```
+
+use crate::error::{Error, Result};
+use crate::loader::NickelLoader;
+use crate::threads;
+use serde_json::Value;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// A cancellation flag shared by a group and its controllers
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    /// Create a token that is not cancelled
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel every member that has not started
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether the token was cancelled
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Input {
+    File(PathBuf),
+    Source(String),
+}
+
+/// How one member ended
+#[derive(Debug)]
+pub enum Outcome {
+    /// Evaluation succeeded
+    Evaluated(Value),
+    /// Reading or evaluating the member failed
+    Failed(Error),
+    /// The group was cancelled before the member started
+    Cancelled,
+}
+
+/// One member's result
+#[derive(Debug)]
+pub struct MemberResult {
+    /// Member name (file path or source name)
+    pub name: String,
+    /// How it ended
+    pub outcome: Outcome,
+}
+
+/// Results of every member, in the order members were added
+#[derive(Debug, Default)]
+pub struct GroupReport {
+    /// Per-member results
+    pub members: Vec<MemberResult>,
+}
+
+impl GroupReport {
+    /// Whether every member evaluated successfully
+    pub fn is_success(&self) -> bool {
+        self.members
+            .iter()
+            .all(|m| matches!(m.outcome, Outcome::Evaluated(_)))
+    }
+
+    /// Members that failed, with their errors
+    pub fn failures(&self) -> impl Iterator<Item = (&str, &Error)> {
+        self.members.iter().filter_map(|m| match &m.outcome {
+            Outcome::Failed(e) => Some((m.name.as_str(), e)),
+            _ => None,
+        })
+    }
+
+    /// Names of members that never started
+    pub fn cancelled(&self) -> impl Iterator<Item = &str> {
+        self.members
+            .iter()
+            .filter(|m| matches!(m.outcome, Outcome::Cancelled))
+            .map(|m| m.name.as_str())
+    }
+
+    /// Every member's value, or the first member's error
+    ///
+    /// # Errors
+    ///
+    /// Returns the first failure in member order, or an invalid-input error
+    /// if a member was cancelled
+    pub fn into_values(self) -> Result<Vec<(String, Value)>> {
+        self.members
+            .into_iter()
+            .map(|m| match m.outcome {
+                Outcome::Evaluated(value) => Ok((m.name, value)),
+                Outcome::Failed(e) => Err(e),
+                Outcome::Cancelled => Err(Error::invalid_input(format!(
+                    "Evaluation of '{}' was cancelled",
+                    m.name
+                ))),
+            })
+            .collect()
+    }
+}
+
+/// A set of configs evaluated together
+#[derive(Debug, Clone)]
+pub struct EvalGroup {
+    loader: NickelLoader,
+    members: Vec<(String, Input)>,
+    cancel: CancelToken,
+    fail_fast: bool,
+}
+
+impl EvalGroup {
+    /// Create an empty group evaluating with `loader`, on its
+    /// [`threads`](NickelLoader::threads)
+    pub fn new(loader: NickelLoader) -> Self {
+        Self {
+            loader,
+            members: Vec::new(),
+            cancel: CancelToken::new(),
+            fail_fast: false,
+        }
+    }
+
+    /// Add a file
+    pub fn with_file(mut self, path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        self.members
+            .push((path.display().to_string(), Input::File(path)));
+        self
+    }
+
+    /// Add a source string, named for error messages
+    pub fn with_source(mut self, name: impl Into<String>, source: impl Into<String>) -> Self {
+        self.members
+            .push((name.into(), Input::Source(source.into())));
+        self
+    }
+
+    /// Cancel the members that have not started once one fails
+    pub fn with_fail_fast(mut self, fail_fast: bool) -> Self {
+        self.fail_fast = fail_fast;
+        self
+    }
+
+    /// Use an existing token, so one controller can cancel several groups
+    pub fn with_cancel_token(mut self, token: CancelToken) -> Self {
+        self.cancel = token;
+        self
+    }
+
+    /// The group's cancellation token
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
+
+    /// Evaluate every member and collect the results
+    pub fn run(&self) -> GroupReport {
+        let members = threads::map(
+            self.loader.threads(),
+            self.members.iter().collect(),
+            |(name, input)| {
+                let outcome = if self.cancel.is_cancelled() {
+                    Outcome::Cancelled
+                } else {
+                    let result = match input {
+                        Input::File(path) => self.loader.parse_file(path),
+                        Input::Source(source) => self.loader.parse_string(source, name),
+                    };
+                    match result {
+                        Ok(value) => Outcome::Evaluated(value),
+                        Err(e) => {
+                            if self.fail_fast {
+                                self.cancel.cancel();
+                            }
+                            Outcome::Failed(e)
+                        }
+                    }
+                };
+                MemberResult {
+                    name: name.clone(),
+                    outcome,
+                }
+            },
+        );
+
+        GroupReport { members }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_results_in_member_order() {
+        let report = EvalGroup::new(NickelLoader::new().with_threads(4))
+            .with_source("a.ncl", "{ a = 1 }")
+            .with_source("b.ncl", "{ b = 2 }")
+            .with_file("does/not/exist.ncl")
+            .run();
+
+        let names: Vec<&str> = report.members.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, vec!["a.ncl", "b.ncl", "does/not/exist.ncl"]);
+        assert_eq!(report.failures().count(), 1);
+        assert!(report.into_values().is_err());
+    }
+
+    #[test]
+    fn test_into_values() {
+        let values = EvalGroup::new(NickelLoader::new())
+            .with_source("a.ncl", "{ a = 1 }")
+            .run()
+            .into_values()
+            .unwrap();
+        assert_eq!(values, vec![("a.ncl".to_string(), json!({ "a": 1 }))]);
+    }
+
+    #[test]
+    fn test_cancellation() {
+        let group = EvalGroup::new(NickelLoader::new()).with_source("a.ncl", "{ a = 1 }");
+        group.cancel_token().cancel();
+        let report = group.run();
+        assert_eq!(report.cancelled().collect::<Vec<_>>(), vec!["a.ncl"]);
+        assert!(!report.is_success());
+    }
+
+    #[test]
+    fn test_fail_fast_cancels_the_rest() {
+        // One thread runs members in order, so the failure comes first
+        let report = EvalGroup::new(NickelLoader::new().with_threads(1))
+            .with_fail_fast(true)
+            .with_source("bad.ncl", "{ a = }")
+            .with_source("good.ncl", "{ a = 1 }")
+            .run();
+        assert_eq!(report.failures().count(), 1);
+        assert_eq!(report.cancelled().collect::<Vec<_>>(), vec!["good.ncl"]);
+    }
+}
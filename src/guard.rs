@@ -0,0 +1,300 @@
+//! Symlink and path-escape policies for imports
+//!
+//! Nickel follows any import path it is given, including symlinks and paths
+//! leading out of the project. That can make a build depend on files nobody
+//! reviewed (`import "../../home/ci/.secrets.ncl"`) or on whatever a symlink
+//! currently points at, which a fresh checkout may not reproduce.
+//!
+//! An [`ImportGuard`] checks the import graph before evaluation (statically,
+//! as [`crate::imports`] finds it) and applies an [`Action`] to
+//!
+//! - imports reached through a symlink, and
+//! - imports whose real path, with symlinks resolved, is outside the project
+//!   root.
+//!
+//! `warn` reports each finding and evaluates anyway (the loader passes it to
+//! its [`Notice`](crate::loader::Notice) handler, which the CLI prints as a
+//! warning); `deny` fails before anything is evaluated. Findings report the real path, so a review
+//! shows where a symlinked import actually leads.
+//!
+//! Enable it with
+//! [`NickelLoader::with_import_guard`](crate::NickelLoader::with_import_guard)
+//! or `bunsenite --symlink-imports ACTION --escaping-imports ACTION
+//! [--import-root DIR]`.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::guard::{Action, ImportGuard};
+//!
+//! let dir = tempfile::tempdir().unwrap();
+//! let project = dir.path().join("project");
+//! std::fs::create_dir(&project).unwrap();
+//! std::fs::write(dir.path().join("outside.ncl"), "{}").unwrap();
+//! let main = project.join("main.ncl");
+//!
+//! let guard = ImportGuard::new(&project).unwrap().with_escapes(Action::Deny);
+//! let findings = guard.check(r#"import "../outside.ncl""#, main.to_str().unwrap(), 1);
+//! assert_eq!(findings.len(), 1);
+//! assert!(guard.enforce(&findings).is_err());
+//! ```
+
+use crate::error::{Error, Result};
+use crate::imports;
+use crate::paths::{self, CasePolicy};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// What to do with an import a policy covers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Action {
+    /// Evaluate without comment
+    #[default]
+    Allow,
+    /// Report a warning and evaluate
+    Warn,
+    /// Fail before evaluating
+    Deny,
+}
+
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s {
+            "allow" => Ok(Action::Allow),
+            "warn" => Ok(Action::Warn),
+            "deny" => Ok(Action::Deny),
+            other => Err(format!(
+                "unknown action '{}' (expected allow, warn or deny)",
+                other
+            )),
+        }
+    }
+}
+
+/// Why an import was flagged
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// The import path goes through a symlink
+    Symlink,
+    /// The import's real path is outside the project root
+    Escape,
+}
+
+/// One flagged import
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    /// Why it was flagged
+    pub kind: Kind,
+    /// What the guard does about it
+    pub action: Action,
+    /// File containing the import
+    pub from: PathBuf,
+    /// Imported path, as resolved lexically
+    pub path: PathBuf,
+    /// Imported path with symlinks resolved
+    pub real: PathBuf,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let from = paths::display(&self.from);
+        let path = paths::display(&self.path);
+        let real = paths::display(&self.real);
+        match self.kind {
+            Kind::Symlink => write!(
+                f,
+                "import of {} in {} goes through a symlink (real path {})",
+                path, from, real
+            ),
+            Kind::Escape => write!(
+                f,
+                "import of {} in {} resolves outside the project root (real path {})",
+                path, from, real
+            ),
+        }
+    }
+}
+
+/// Symlink and path-escape policies for a project's imports
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportGuard {
+    root: PathBuf,
+    /// `root` as given, made absolute but with symlinks unresolved
+    given: PathBuf,
+    symlinks: Action,
+    escapes: Action,
+}
+
+impl ImportGuard {
+    /// A guard for the project at `root`, allowing everything
+    ///
+    /// # Errors
+    ///
+    /// Returns an I/O error if `root` does not exist
+    pub fn new(root: &Path) -> Result<Self> {
+        Ok(Self {
+            root: root.canonicalize()?,
+            given: imports::normalize(&std::env::current_dir()?.join(root)),
+            symlinks: Action::Allow,
+            escapes: Action::Allow,
+        })
+    }
+
+    /// What to do with imports reached through a symlink
+    pub fn with_symlinks(mut self, action: Action) -> Self {
+        self.symlinks = action;
+        self
+    }
+
+    /// What to do with imports resolving outside the project root
+    pub fn with_escapes(mut self, action: Action) -> Self {
+        self.escapes = action;
+        self
+    }
+
+    /// Project root, with symlinks resolved
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Flag the imports of `source` (named `name`) that a policy covers
+    ///
+    /// Imports are followed transitively, on up to `threads` threads. Allowed
+    /// findings are not returned.
+    pub fn check(&self, source: &str, name: &str, threads: usize) -> Vec<Finding> {
+        let graph = imports::resolve_source(source, name, threads);
+        let cwd = std::env::current_dir().unwrap_or_default();
+        let root = paths::key(&self.root, CasePolicy::Platform);
+
+        let mut findings = Vec::new();
+        for (from, path) in graph.imports {
+            let absolute = imports::normalize(&cwd.join(&path));
+            // Missing imports are left for evaluation to report
+            let Ok(real) = absolute.canonicalize() else {
+                continue;
+            };
+            let real_key = paths::key(&real, CasePolicy::Platform);
+
+            let mut flag = |kind, action| {
+                if action != Action::Allow {
+                    findings.push(Finding {
+                        kind,
+                        action,
+                        from: from.clone(),
+                        path: path.clone(),
+                        real: real.clone(),
+                    });
+                }
+            };
+            if self.through_symlink(&absolute) {
+                flag(Kind::Symlink, self.symlinks);
+            }
+            if !Path::new(&real_key).starts_with(&root) {
+                flag(Kind::Escape, self.escapes);
+            }
+        }
+        findings
+    }
+
+    /// Whether any component of `path` below the project root is a symlink
+    ///
+    /// Symlinks above the root (such as `/var` on macOS) are not the
+    /// project's doing, so paths inside it are only checked below it.
+    fn through_symlink(&self, path: &Path) -> bool {
+        let (mut at, rest) = [&self.given, &self.root]
+            .into_iter()
+            .find_map(|root| Some((root.clone(), path.strip_prefix(root).ok()?)))
+            .unwrap_or((PathBuf::new(), path));
+        rest.components().any(|component| {
+            at.push(component);
+            at.symlink_metadata()
+                .is_ok_and(|metadata| metadata.file_type().is_symlink())
+        })
+    }
+
+    /// Fail if any finding is denied, and otherwise return the findings to
+    /// warn about
+    ///
+    /// # Errors
+    ///
+    /// Returns an invalid-input error listing every denied import
+    pub fn enforce<'a>(&self, findings: &'a [Finding]) -> Result<Vec<&'a Finding>> {
+        let mut warned = Vec::new();
+        let mut denied = Vec::new();
+        for finding in findings {
+            match finding.action {
+                Action::Allow => {}
+                Action::Warn => warned.push(finding),
+                Action::Deny => denied.push(finding.to_string()),
+            }
+        }
+        if denied.is_empty() {
+            Ok(warned)
+        } else {
+            Err(Error::invalid_input(format!(
+                "Denied imports: {}",
+                denied.join("; ")
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinks_and_escapes() {
+        let dir = tempfile::tempdir().unwrap();
+        let project = dir.path().join("project");
+        std::fs::create_dir_all(project.join("lib")).unwrap();
+        std::fs::write(project.join("lib/base.ncl"), "{}").unwrap();
+        std::fs::write(dir.path().join("shared.ncl"), "{}").unwrap();
+        std::os::unix::fs::symlink(project.join("lib"), project.join("linked")).unwrap();
+        std::os::unix::fs::symlink(dir.path().join("shared.ncl"), project.join("shared.ncl"))
+            .unwrap();
+
+        let main = project.join("main.ncl");
+        let source = r#"[import "lib/base.ncl", import "linked/base.ncl", import "shared.ncl", import "missing.ncl"]"#;
+        let guard = ImportGuard::new(&project)
+            .unwrap()
+            .with_symlinks(Action::Warn)
+            .with_escapes(Action::Deny);
+        let findings = guard.check(source, main.to_str().unwrap(), 1);
+
+        let flagged: Vec<(Kind, &Path)> = findings
+            .iter()
+            .map(|f| (f.kind, f.path.strip_prefix(&project).unwrap()))
+            .collect();
+        assert_eq!(
+            flagged,
+            [
+                (Kind::Symlink, Path::new("linked/base.ncl")),
+                (Kind::Symlink, Path::new("shared.ncl")),
+                (Kind::Escape, Path::new("shared.ncl")),
+            ]
+        );
+        let real = dir.path().canonicalize().unwrap().join("shared.ncl");
+        assert_eq!(findings[2].real, real);
+        assert!(findings[2]
+            .to_string()
+            .contains(&real.display().to_string()));
+
+        let error = guard.enforce(&findings).unwrap_err().to_string();
+        assert!(error.contains("outside the project root"));
+        assert!(!error.contains("symlink"));
+        let lenient = guard.with_escapes(Action::Warn);
+        let findings = lenient.check(source, main.to_str().unwrap(), 1);
+        assert_eq!(lenient.enforce(&findings).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_parse_action() {
+        assert_eq!("deny".parse(), Ok(Action::Deny));
+        assert!("block".parse::<Action>().is_err());
+    }
+}
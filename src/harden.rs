@@ -0,0 +1,243 @@
+//! Sandboxing evaluation of untrusted configs (Linux)
+//!
+//! Backs `bunsenite --harden`: once a config's inputs are known, the process
+//! gives up the rest of the filesystem and the network before evaluation
+//! begins, so a config from an external contributor (or a bug in the
+//! evaluator) cannot read or write anything beyond what the command needs.
+//!
+//! A [`Sandbox`] lists what stays reachable: the config and every file it
+//! transitively imports ([`Sandbox::with_inputs_of`], read with
+//! [`imports::prefetch`]) and other inputs read-only, and the directories
+//! outputs are written to. [`Sandbox::apply`] then
+//!
+//! - restricts filesystem access to those paths with Landlock (ABI v2,
+//!   Linux 5.19+), which also denies executing programs, so `exec:`
+//!   transforms fail
+//! - blocks creating sockets of any kind and `io_uring` with a seccomp
+//!   filter, cutting off network access. Unix sockets are blocked too:
+//!   Landlock does not govern connecting to one, so a config could otherwise
+//!   reach local services such as `/var/run/docker.sock`
+//!
+//! Both apply to the calling thread and every thread and process started
+//! afterwards, and cannot be lifted. Hardening fails, rather than carrying on
+//! unprotected or partly protected, if the kernel does not fully enforce
+//! Landlock ABI v2 or the seccomp filter cannot be installed; on other
+//! platforms it always fails.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::harden::Sandbox;
+//!
+//! let dir = tempfile::tempdir().unwrap();
+//! std::fs::write(dir.path().join("base.ncl"), "{ port = 80 }").unwrap();
+//! std::fs::write(dir.path().join("app.ncl"), r#"(import "base.ncl") & { host = "a" }"#).unwrap();
+//!
+//! let sandbox = Sandbox::new()
+//!     .with_inputs_of(&dir.path().join("app.ncl"), 1)
+//!     .with_writable(dir.path().join("out"));
+//! assert_eq!(sandbox.readable().len(), 2);
+//! // sandbox.apply() would now confine this process
+//! ```
+
+use crate::error::{Error, Result};
+use crate::imports;
+use std::path::{Path, PathBuf};
+
+/// Paths a hardened process keeps access to
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Sandbox {
+    readable: Vec<PathBuf>,
+    writable: Vec<PathBuf>,
+}
+
+impl Sandbox {
+    /// A sandbox with access to nothing
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep read access to `file` and every file it transitively imports,
+    /// reading the import graph on up to `threads` threads
+    ///
+    /// Imports that cannot be read are left out, so evaluation reports them
+    /// as it would without hardening.
+    pub fn with_inputs_of(mut self, file: &Path, threads: usize) -> Self {
+        let graph = imports::prefetch(file, threads);
+        self.readable.extend(graph.files);
+        self
+    }
+
+    /// Keep read access to `path` (a file, or a directory and everything
+    /// beneath it)
+    pub fn with_readable(mut self, path: impl Into<PathBuf>) -> Self {
+        self.readable.push(path.into());
+        self
+    }
+
+    /// Keep full access to the directory `dir` and everything beneath it,
+    /// for outputs
+    pub fn with_writable(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.writable.push(dir.into());
+        self
+    }
+
+    /// Paths that stay readable
+    pub fn readable(&self) -> &[PathBuf] {
+        &self.readable
+    }
+
+    /// Directories that stay writable
+    pub fn writable(&self) -> &[PathBuf] {
+        &self.writable
+    }
+
+    /// Confine this process to the sandbox, for good
+    ///
+    /// # Errors
+    ///
+    /// Returns an invalid-input error if the filesystem or network cannot
+    /// be restricted, which is always the case outside Linux
+    #[cfg(target_os = "linux")]
+    pub fn apply(&self) -> Result<()> {
+        restrict_filesystem(&self.readable, &self.writable)?;
+        restrict_network()
+    }
+
+    /// Confine this process to the sandbox, for good
+    ///
+    /// # Errors
+    ///
+    /// Always returns an invalid-input error: hardening needs Linux
+    #[cfg(not(target_os = "linux"))]
+    pub fn apply(&self) -> Result<()> {
+        Err(Error::invalid_input(
+            "--harden is only supported on Linux (Landlock and seccomp)",
+        ))
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn restrict_filesystem(readable: &[PathBuf], writable: &[PathBuf]) -> Result<()> {
+    use landlock::{
+        path_beneath_rules, Access, AccessFs, Ruleset, RulesetAttr, RulesetCreatedAttr,
+        RulesetError, RulesetStatus, ABI,
+    };
+
+    let failed =
+        |e: RulesetError| Error::invalid_input(format!("Cannot restrict filesystem access: {}", e));
+    let abi = ABI::V2;
+    let status = Ruleset::default()
+        .handle_access(AccessFs::from_all(abi))
+        .map_err(failed)?
+        .create()
+        .map_err(failed)?
+        .add_rules(path_beneath_rules(readable, AccessFs::from_read(abi)))
+        .map_err(failed)?
+        .add_rules(path_beneath_rules(writable, AccessFs::from_all(abi)))
+        .map_err(failed)?
+        .restrict_self()
+        .map_err(failed)?;
+
+    match status.ruleset {
+        RulesetStatus::FullyEnforced => Ok(()),
+        RulesetStatus::PartiallyEnforced => Err(Error::invalid_input(
+            "Cannot restrict filesystem access: this kernel supports only part of Landlock ABI v2",
+        )),
+        RulesetStatus::NotEnforced => Err(Error::invalid_input(
+            "Cannot restrict filesystem access: this kernel does not support Landlock",
+        )),
+    }
+}
+
+#[cfg(all(
+    target_os = "linux",
+    any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        target_arch = "riscv64"
+    )
+))]
+fn restrict_network() -> Result<()> {
+    use seccompiler::{BpfProgram, SeccompAction, SeccompFilter};
+    use std::collections::BTreeMap;
+
+    fn failed(e: impl std::fmt::Display) -> Error {
+        Error::invalid_input(format!("Cannot restrict network access: {}", e))
+    }
+
+    // socket() for every domain, AF_UNIX included, and io_uring, whose
+    // requests could open sockets without the socket syscall
+    let rules = BTreeMap::from([
+        (libc::SYS_socket, Vec::new()),
+        (libc::SYS_io_uring_setup, Vec::new()),
+    ]);
+    let filter = SeccompFilter::new(
+        rules,
+        SeccompAction::Allow,
+        SeccompAction::Errno(libc::EACCES as u32),
+        std::env::consts::ARCH.try_into().map_err(failed)?,
+    )
+    .map_err(failed)?;
+    let program: BpfProgram = filter.try_into().map_err(failed)?;
+    seccompiler::apply_filter_all_threads(&program).map_err(failed)
+}
+
+#[cfg(all(
+    target_os = "linux",
+    not(any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        target_arch = "riscv64"
+    ))
+))]
+fn restrict_network() -> Result<()> {
+    Err(Error::invalid_input(format!(
+        "Cannot restrict network access: no seccomp filter for {}",
+        std::env::consts::ARCH
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inputs_follow_imports() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, contents: &str| {
+            let path = dir.path().join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        };
+        write("app.ncl", r#"import "lib/base.ncl" & import "gone.ncl""#);
+        write("lib/base.ncl", r#"{ data = import "data.json" }"#);
+        write("lib/data.json", "{}");
+        write("unrelated.ncl", "{}");
+
+        let sandbox = Sandbox::new().with_inputs_of(&dir.path().join("app.ncl"), 2);
+        let mut names: Vec<_> = sandbox
+            .readable()
+            .iter()
+            .map(|path| path.strip_prefix(dir.path()).unwrap().to_path_buf())
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                PathBuf::from("app.ncl"),
+                PathBuf::from("lib/base.ncl"),
+                PathBuf::from("lib/data.json")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_collects_outputs() {
+        let sandbox = Sandbox::new()
+            .with_readable("tenants.ncl")
+            .with_writable("out");
+        assert_eq!(sandbox.readable(), [PathBuf::from("tenants.ncl")]);
+        assert_eq!(sandbox.writable(), [PathBuf::from("out")]);
+    }
+}
@@ -0,0 +1,433 @@
+//! Import scanning and prefetching
+//!
+//! Nickel loads imports one at a time, as evaluation reaches them. On a
+//! network mount, a large import tree then spends most of its time waiting on
+//! serial file reads. [`prefetch`] walks the import graph breadth-first ahead
+//! of evaluation and reads each level's files concurrently, so the reads
+//! Nickel performs afterwards are served from the page cache.
+//!
+//! Enable it with
+//! [`NickelLoader::with_prefetch_imports`](crate::NickelLoader::with_prefetch_imports)
+//! or `bunsenite --prefetch-imports`. Prefetching is best effort: unreadable
+//! or missing imports are recorded and left for evaluation to report.
+//!
+//! Import discovery is lexical: `import "path"` outside comments and strings.
+//! Windows paths (drive letters, UNC shares, `\\?\` long paths) are resolved
+//! as described in [`crate::paths`], and files are read once however their
+//! paths are spelled, comparing names by the platform's
+//! [`CasePolicy`](crate::paths::CasePolicy).
+//!
+//! # Dependency graphs
+//!
+//! `bunsenite deps` prints a prefetched [`ImportGraph`] as a tree
+//! ([`ImportGraph::to_tree`]), JSON or Graphviz DOT, without evaluating the
+//! config. As discovery is lexical, an import in a branch evaluation never
+//! takes is listed too, which is what a build system needs to invalidate
+//! outputs when any file they may read changes.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::imports;
+//!
+//! let source = r#"
+//!   ## import "commented.ncl"
+//!   let base = import "base.ncl" in
+//!   base & { data = import "data.json", note = "import \"x.ncl\"" }
+//! "#;
+//! assert_eq!(imports::scan(source), vec!["base.ncl", "data.json"]);
+//! ```
+
+use crate::lexer::{self, Token};
+use crate::paths::{self, CasePolicy};
+use crate::threads;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+
+/// Import paths in a Nickel source, in order of appearance
+pub fn scan(source: &str) -> Vec<String> {
+    scan_literals(source)
+        .into_iter()
+        .map(|(_, path)| path)
+        .collect()
+}
+
+/// Import paths in a Nickel source, with the byte range of the string
+/// literal each one is written as
+pub(crate) fn scan_literals(source: &str) -> Vec<(Range<usize>, String)> {
+    let tokens = lexer::spanned_lenient(source);
+    let mut imports = Vec::new();
+    for (i, (_, token)) in tokens.iter().enumerate() {
+        if !token.is_word("import") {
+            continue;
+        }
+        let literal = tokens[i + 1..]
+            .iter()
+            .find(|(_, token)| !matches!(token, Token::Space | Token::Newline));
+        if let Some((span, Token::Str(text))) = literal {
+            if let Some(path) = lexer::string_value(text) {
+                imports.push((span.clone(), path));
+            }
+        }
+    }
+    imports
+}
+
+/// Files reached by a prefetch
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportGraph {
+    /// Files read, in breadth-first order starting with the root
+    pub files: Vec<PathBuf>,
+    /// Imports that could not be read
+    pub missing: Vec<PathBuf>,
+    /// Every resolved import, as (importing file, imported file)
+    pub imports: Vec<(PathBuf, PathBuf)>,
+    /// Total bytes read
+    pub bytes: u64,
+}
+
+impl ImportGraph {
+    /// This graph with its paths relative to the project at `root`
+    ///
+    /// See [`paths::relative_to`].
+    pub fn relative_to(mut self, root: &Path) -> Self {
+        let edges = self.imports.iter_mut().flat_map(|(from, to)| [from, to]);
+        for path in self.files.iter_mut().chain(&mut self.missing).chain(edges) {
+            *path = paths::relative_to(path, root);
+        }
+        self
+    }
+
+    /// Files `path` imports, in order of appearance, each once
+    pub fn imports_of(&self, path: &Path) -> Vec<&Path> {
+        let mut imported: Vec<&Path> = Vec::new();
+        for (from, to) in &self.imports {
+            if from == path && !imported.contains(&to.as_path()) {
+                imported.push(to);
+            }
+        }
+        imported
+    }
+
+    /// The graph below `root` as a tree, one file per line
+    ///
+    /// A file shown before is marked `(*)` and not expanded again, so shared
+    /// imports and cycles are listed in full once; imports that cannot be
+    /// read are marked `(missing)`.
+    pub fn to_tree(&self, root: &Path) -> String {
+        let mut out = String::new();
+        self.write_tree(&normalize(root), "", "", &mut HashSet::new(), &mut out);
+        out
+    }
+
+    fn write_tree(
+        &self,
+        path: &Path,
+        branch: &str,
+        indent: &str,
+        shown: &mut HashSet<PathBuf>,
+        out: &mut String,
+    ) {
+        out.push_str(branch);
+        out.push_str(&paths::display(path));
+        if self.missing.iter().any(|missing| missing == path) {
+            out.push_str(" (missing)\n");
+            return;
+        }
+        if !shown.insert(path.to_path_buf()) {
+            out.push_str(" (*)\n");
+            return;
+        }
+        out.push('\n');
+        let imported = self.imports_of(path);
+        for (at, import) in imported.iter().enumerate() {
+            let (branch, next) = if at + 1 == imported.len() {
+                ("└── ", "    ")
+            } else {
+                ("├── ", "│   ")
+            };
+            let (branch, next) = (indent.to_string() + branch, indent.to_string() + next);
+            self.write_tree(import, &branch, &next, shown, out);
+        }
+    }
+
+    /// The graph as JSON: the files read, the imports that could not be,
+    /// and each import as `{"from": .., "to": ..}`, once
+    pub fn to_json(&self) -> Value {
+        let list = |paths: &[PathBuf]| -> Vec<String> {
+            paths.iter().map(|path| paths::display(path)).collect()
+        };
+        let imports: Vec<Value> = self
+            .edges()
+            .into_iter()
+            .map(|(from, to)| json!({ "from": paths::display(from), "to": paths::display(to) }))
+            .collect();
+        json!({
+            "files": list(&self.files),
+            "missing": list(&self.missing),
+            "imports": imports,
+        })
+    }
+
+    /// The graph in Graphviz DOT, imports that cannot be read dashed
+    pub fn to_dot(&self) -> String {
+        let quote = |path: &Path| {
+            let escaped = paths::display(path)
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"");
+            format!("\"{}\"", escaped)
+        };
+        let mut out = String::from("digraph imports {\n");
+        for path in &self.files {
+            out += &format!("    {};\n", quote(path));
+        }
+        for path in &self.missing {
+            out += &format!("    {} [style=dashed];\n", quote(path));
+        }
+        for (from, to) in self.edges() {
+            out += &format!("    {} -> {};\n", quote(from), quote(to));
+        }
+        out + "}\n"
+    }
+
+    /// Each import once, in the order they were found
+    fn edges(&self) -> Vec<(&Path, &Path)> {
+        let mut edges: Vec<(&Path, &Path)> = Vec::new();
+        for (from, to) in &self.imports {
+            let edge = (from.as_path(), to.as_path());
+            if !edges.contains(&edge) {
+                edges.push(edge);
+            }
+        }
+        edges
+    }
+}
+
+/// Read a file and every file it transitively imports, level by level
+///
+/// Each level of the import graph is read on up to `threads` threads.
+/// Imports of non-Nickel files (JSON, YAML, TOML, text) are read but not
+/// scanned. Relative imports resolve against the importing file's directory.
+pub fn prefetch(root: &Path, threads: usize) -> ImportGraph {
+    let mut graph = ImportGraph::default();
+    let mut seen = HashSet::new();
+    let root = normalize(root);
+    seen.insert(paths::key(&root, CasePolicy::Platform));
+    walk(&mut graph, &mut seen, vec![root], threads);
+    graph
+}
+
+/// Read every file an in-memory source transitively imports
+///
+/// Like [`prefetch`], but starting from a source that is not on disk. Its
+/// relative imports resolve against the directory part of `name`, as Nickel
+/// resolves them; the source itself is not in [`ImportGraph::files`].
+pub fn resolve_source(source: &str, name: &str, threads: usize) -> ImportGraph {
+    let mut graph = ImportGraph::default();
+    let mut seen = HashSet::new();
+    let from = PathBuf::from(name);
+    let dir = from.parent().unwrap_or(Path::new(""));
+    let mut level = Vec::new();
+    for import in scan(source) {
+        let import = normalize(&dir.join(import));
+        graph.imports.push((from.clone(), import.clone()));
+        if seen.insert(paths::key(&import, CasePolicy::Platform)) {
+            level.push(import);
+        }
+    }
+    walk(&mut graph, &mut seen, level, threads);
+    graph
+}
+
+/// Read `level` and the levels below it into `graph`
+fn walk(
+    graph: &mut ImportGraph,
+    seen: &mut HashSet<String>,
+    mut level: Vec<PathBuf>,
+    threads: usize,
+) {
+    while !level.is_empty() {
+        let loaded = threads::map(threads, level, |path| {
+            let result = std::fs::read(paths::to_open(&path)).map(|bytes| {
+                let imports = if is_nickel(&path) {
+                    scan(&String::from_utf8_lossy(&bytes))
+                } else {
+                    Vec::new()
+                };
+                (bytes.len() as u64, imports)
+            });
+            (path, result)
+        });
+
+        let mut next = Vec::new();
+        for (path, result) in loaded {
+            let Ok((bytes, imports)) = result else {
+                graph.missing.push(path);
+                continue;
+            };
+            let dir = path.parent().unwrap_or(Path::new(""));
+            for import in imports {
+                let import = normalize(&dir.join(import));
+                graph.imports.push((path.clone(), import.clone()));
+                if seen.insert(paths::key(&import, CasePolicy::Platform)) {
+                    next.push(import);
+                }
+            }
+            graph.bytes += bytes;
+            graph.files.push(path);
+        }
+        level = next;
+    }
+}
+
+pub(crate) fn is_nickel(path: &Path) -> bool {
+    path.extension().map_or(true, |ext| ext == "ncl")
+}
+
+/// Resolve `.` and `..` lexically, so one file reached by two spellings is
+/// read once
+///
+/// On Windows, separators are also unified and verbatim `\\?\` prefixes
+/// dropped (see [`paths::WindowsPath`]).
+pub(crate) fn normalize(path: &Path) -> PathBuf {
+    if cfg!(windows) {
+        return PathBuf::from(paths::WindowsPath::parse(&path.to_string_lossy()).display());
+    }
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(out.components().next_back(), Some(Component::Normal(_))) =>
+            {
+                out.pop();
+            }
+            component => out.push(component),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scan_skips_comments_and_strings() {
+        let source = r#"
+            let a = import "a.ncl" in # import "b.ncl"
+            let s = m%"import "c.ncl""% in
+            let reimport = 1 in
+            { x = import   "dir/d.yaml", y = "\"import \"e.ncl\"" }
+        "#;
+        assert_eq!(scan(source), vec!["a.ncl", "dir/d.yaml"]);
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(
+            normalize(Path::new("a/./b/../c.ncl")),
+            PathBuf::from("a/c.ncl")
+        );
+        assert_eq!(normalize(Path::new("../x.ncl")), PathBuf::from("../x.ncl"));
+    }
+
+    #[test]
+    fn test_prefetch_walks_graph() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, contents: &str| {
+            let path = dir.path().join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        };
+        write(
+            "main.ncl",
+            r#"import "lib/a.ncl" & import "b.ncl" & import "gone.ncl""#,
+        );
+        write(
+            "lib/a.ncl",
+            r#"import "../b.ncl" & { d = import "data.json" }"#,
+        );
+        write("b.ncl", "{ b = 1 }");
+        write("lib/data.json", "{}");
+
+        let graph = prefetch(&dir.path().join("main.ncl"), 4);
+        let names: Vec<_> = graph
+            .files
+            .iter()
+            .map(|p| p.strip_prefix(dir.path()).unwrap().to_path_buf())
+            .collect();
+        assert_eq!(
+            names,
+            ["main.ncl", "lib/a.ncl", "b.ncl", "lib/data.json"].map(PathBuf::from)
+        );
+        assert_eq!(graph.missing, vec![dir.path().join("gone.ncl")]);
+        assert_eq!(graph.imports.len(), 5);
+    }
+
+    #[test]
+    fn test_resolve_source() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.ncl"), r#"import "b.json""#).unwrap();
+        std::fs::write(dir.path().join("b.json"), "{}").unwrap();
+
+        let name = dir.path().join("main.ncl");
+        let graph = resolve_source(
+            r#"import "a.ncl" & import "a.ncl""#,
+            name.to_str().unwrap(),
+            1,
+        );
+        assert_eq!(
+            graph.files,
+            vec![dir.path().join("a.ncl"), dir.path().join("b.json")]
+        );
+        assert_eq!(graph.imports.len(), 3);
+        assert_eq!(graph.imports[0], (name, dir.path().join("a.ncl")));
+    }
+
+    #[test]
+    fn test_renderings() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("lib")).unwrap();
+        std::fs::write(
+            dir.path().join("main.ncl"),
+            r#"import "lib/a.ncl" & import "b.ncl" & import "gone.ncl""#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("lib/a.ncl"),
+            r#"import "../b.ncl" & { d = import "data.json", e = import "data.json" }"#,
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("b.ncl"), r#"import "main.ncl""#).unwrap();
+        std::fs::write(dir.path().join("lib/data.json"), "{}").unwrap();
+
+        let graph = prefetch(&dir.path().join("main.ncl"), 2).relative_to(dir.path());
+        assert_eq!(
+            graph.to_tree(Path::new("main.ncl")),
+            "main.ncl
+├── lib/a.ncl
+│   ├── b.ncl
+│   │   └── main.ncl (*)
+│   └── lib/data.json
+├── b.ncl (*)
+└── gone.ncl (missing)
+"
+        );
+
+        let json = graph.to_json();
+        assert_eq!(json["missing"], json!(["gone.ncl"]));
+        assert_eq!(json["imports"].as_array().unwrap().len(), 6);
+        assert_eq!(
+            json["imports"][0],
+            json!({ "from": "main.ncl", "to": "lib/a.ncl" })
+        );
+
+        let dot = graph.to_dot();
+        assert!(dot.starts_with("digraph imports {\n    \"main.ncl\";\n"));
+        assert!(dot.contains("    \"gone.ncl\" [style=dashed];\n"));
+        assert!(dot.contains("    \"lib/a.ncl\" -> \"lib/data.json\";\n"));
+    }
+}
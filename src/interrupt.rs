@@ -0,0 +1,156 @@
+//! Graceful shutdown on SIGINT and SIGTERM
+//!
+//! Long-running modes (batch validation, `serve`, `watch`) stop cleanly
+//! when asked: the CLI's signal handler calls [`Shutdown::request`] on
+//! [`Shutdown::global`], and the work checks [`Shutdown::requested`]
+//! between units (files of a batch, requests, rebuilds), stops starting new
+//! ones, and reports what it got done, recording results in the
+//! `--incremental` cache as usual. Work still queued ends with
+//! [`Error::Interrupted`], and the process exits with [`EXIT_CODE`].
+//!
+//! Nickel cannot stop an evaluation midway, so a unit in progress runs to
+//! its end. The handler gives the work [`GRACE`] to wind down, and less to
+//! work that would otherwise sit waiting for input, such as `serve`: it
+//! holds a [`Busy`] guard while it has a response to write, and
+//! [`Shutdown::wait_idle`] tells when none is held. A second signal exits
+//! at once. Files are only ever replaced whole (see
+//! [`output::write_atomic`](crate::output::write_atomic)), so exiting early
+//! leaves earlier outputs, never truncated ones.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::interrupt::Shutdown;
+//!
+//! let shutdown = Shutdown::new();
+//! let files = ["a.ncl", "b.ncl", "c.ncl"];
+//! let mut done = Vec::new();
+//! for file in files {
+//!     if shutdown.check().is_err() {
+//!         break;
+//!     }
+//!     done.push(file);
+//!     shutdown.request(); // as a signal would
+//! }
+//! assert_eq!(done, ["a.ncl"]);
+//! ```
+
+use crate::error::{Error, Result};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Exit code of a run stopped by a signal, as shells report SIGINT
+pub const EXIT_CODE: i32 = 130;
+
+/// Longest wait for work in progress to finish after a signal
+pub const GRACE: Duration = Duration::from_secs(10);
+
+/// A request to stop, and the work that must finish first
+#[derive(Debug, Default)]
+pub struct Shutdown {
+    requested: AtomicBool,
+    busy: AtomicUsize,
+}
+
+impl Shutdown {
+    /// Not requested, and nothing busy
+    pub const fn new() -> Self {
+        Self {
+            requested: AtomicBool::new(false),
+            busy: AtomicUsize::new(0),
+        }
+    }
+
+    /// The process-wide instance signal handlers request
+    pub fn global() -> &'static Shutdown {
+        static GLOBAL: Shutdown = Shutdown::new();
+        &GLOBAL
+    }
+
+    /// Ask work to stop; `true` if this is the first request
+    pub fn request(&self) -> bool {
+        !self.requested.swap(true, Ordering::SeqCst)
+    }
+
+    /// Whether work was asked to stop
+    pub fn requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    /// Succeed unless work was asked to stop
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Interrupted`] once [`request`](Self::request)ed
+    pub fn check(&self) -> Result<()> {
+        if self.requested() {
+            Err(Error::Interrupted("shutdown requested".to_string()))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Mark work that must finish before the process exits, until the
+    /// guard drops
+    pub fn busy(&self) -> Busy<'_> {
+        self.busy.fetch_add(1, Ordering::SeqCst);
+        Busy { shutdown: self }
+    }
+
+    /// Wait until no [`Busy`] guard is held, for at most `timeout`;
+    /// `true` if none is
+    pub fn wait_idle(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        while self.busy.load(Ordering::SeqCst) > 0 {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        true
+    }
+}
+
+/// Work in progress; see [`Shutdown::busy`]
+#[derive(Debug)]
+#[must_use = "the work counts as busy only while the guard is held"]
+pub struct Busy<'a> {
+    shutdown: &'a Shutdown,
+}
+
+impl Drop for Busy<'_> {
+    fn drop(&mut self) {
+        self.shutdown.busy.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request() {
+        let shutdown = Shutdown::new();
+        assert!(shutdown.check().is_ok());
+        assert!(shutdown.request());
+        assert!(!shutdown.request());
+        assert!(shutdown.requested());
+        assert!(matches!(shutdown.check(), Err(Error::Interrupted(_))));
+    }
+
+    #[test]
+    fn test_wait_idle() {
+        let shutdown = Shutdown::new();
+        assert!(shutdown.wait_idle(Duration::ZERO));
+
+        let busy = shutdown.busy();
+        assert!(!shutdown.wait_idle(Duration::from_millis(50)));
+        std::thread::scope(|scope| {
+            scope.spawn(move || {
+                std::thread::sleep(Duration::from_millis(50));
+                drop(busy);
+            });
+            assert!(shutdown.wait_idle(Duration::from_secs(10)));
+        });
+    }
+}
@@ -0,0 +1,556 @@
+//! Stack-safe JSON handling
+//!
+//! `serde_json` serializes, compares and drops [`Value`]s recursively, so a
+//! legitimately deep document (generated ASTs, nested routing tables) can
+//! overflow the native stack long before it runs out of memory. The functions
+//! in this module walk values with an explicit, heap-allocated work stack, so
+//! nesting depth is bounded only by available memory.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::json;
+//! use serde_json::json;
+//!
+//! let value = json!({ "name": "example", "ports": [80, 443] });
+//! assert_eq!(json::to_string(&value, false), r#"{"name":"example","ports":[80,443]}"#);
+//! assert_eq!(json::depth(&value), 2);
+//! ```
+//!
+//! Pretty output can follow a repository's existing conventions instead of
+//! `serde_json`'s, so adopting bunsenite for a checked-in file does not
+//! reformat all of it: see [`Layout`].
+//!
+//! ```
+//! use bunsenite::json::{self, Layout};
+//! use serde_json::json;
+//!
+//! let layout = Layout::new().with_indent(4).with_inline_arrays(40);
+//! let value = json!({ "name": "example", "ports": [80, 443] });
+//! assert_eq!(
+//!     json::to_string_with(&value, &layout),
+//!     "{\n    \"name\": \"example\",\n    \"ports\": [80, 443]\n}"
+//! );
+//! ```
+
+use serde_json::Value;
+use std::io::{self, Write};
+
+/// How pretty-printed JSON is laid out
+///
+/// The default is `serde_json::to_string_pretty`'s layout: two-space
+/// indentation, every array element on its own line, quoted keys and no
+/// newline after the closing brace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    indent: usize,
+    inline_arrays: usize,
+    bare_keys: bool,
+    final_newline: bool,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self {
+            indent: 2,
+            inline_arrays: 0,
+            bare_keys: false,
+            final_newline: false,
+        }
+    }
+}
+
+impl Layout {
+    /// `serde_json`'s pretty layout
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Indent each level by `spaces` spaces
+    pub fn with_indent(mut self, spaces: usize) -> Self {
+        self.indent = spaces;
+        self
+    }
+
+    /// Write arrays of scalars on one line, as `[80, 443]`, when that line
+    /// is at most `width` characters (`0`, the default, never does)
+    pub fn with_inline_arrays(mut self, width: usize) -> Self {
+        self.inline_arrays = width;
+        self
+    }
+
+    /// Leave keys that are identifiers unquoted (`name: "x"`)
+    ///
+    /// The output is then JSON5 rather than JSON, for repositories whose
+    /// generated files are read as JSON5 or JavaScript.
+    pub fn with_bare_keys(mut self, bare: bool) -> Self {
+        self.bare_keys = bare;
+        self
+    }
+
+    /// End the output with a newline
+    pub fn with_final_newline(mut self, newline: bool) -> Self {
+        self.final_newline = newline;
+        self
+    }
+
+    /// Start a new line at nesting `depth`
+    pub(crate) fn newline<W: Write>(&self, writer: &mut W, depth: usize) -> io::Result<()> {
+        writer.write_all(b"\n")?;
+        for _ in 0..depth * self.indent {
+            writer.write_all(b" ")?;
+        }
+        Ok(())
+    }
+
+    /// Write an object key and its separator
+    pub(crate) fn write_key<W: Write>(&self, writer: &mut W, key: &str) -> io::Result<()> {
+        let identifier = key
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || matches!(c, '_' | '$'))
+            && key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '$'));
+        if self.bare_keys && identifier {
+            writer.write_all(key.as_bytes())?;
+        } else {
+            serde_json::to_writer(&mut *writer, key)?;
+        }
+        writer.write_all(b": ")
+    }
+
+    /// An array's one-line form, from its elements' JSON (`None` for a
+    /// container), if it may be written inline
+    pub(crate) fn inline(&self, items: impl IntoIterator<Item = Option<String>>) -> Option<String> {
+        if self.inline_arrays == 0 {
+            return None;
+        }
+        let mut line = String::from("[");
+        for (index, item) in items.into_iter().enumerate() {
+            if index > 0 {
+                line.push_str(", ");
+            }
+            line.push_str(&item?);
+            if line.len() >= self.inline_arrays {
+                return None;
+            }
+        }
+        line.push(']');
+        (line.len() <= self.inline_arrays).then_some(line)
+    }
+
+    /// Write what follows the value
+    pub(crate) fn finish<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        if self.final_newline {
+            writer.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+}
+
+/// Serialize a value to a JSON string without recursing on nesting depth
+///
+/// The output is byte-for-byte identical to `serde_json::to_string` (or
+/// `serde_json::to_string_pretty` when `pretty` is set).
+pub fn to_string(value: &Value, pretty: bool) -> String {
+    let mut out = Vec::new();
+    // Writing into a Vec cannot fail
+    to_writer(&mut out, value, pretty).expect("writing JSON to memory failed");
+    String::from_utf8(out).expect("serde_json emits valid UTF-8")
+}
+
+/// Serialize a value to a pretty JSON string laid out as `layout` says,
+/// without recursing on nesting depth
+pub fn to_string_with(value: &Value, layout: &Layout) -> String {
+    let mut out = Vec::new();
+    // Writing into a Vec cannot fail
+    to_writer_with(&mut out, value, layout).expect("writing JSON to memory failed");
+    String::from_utf8(out).expect("serde_json emits valid UTF-8")
+}
+
+/// Serialize a value as JSON into a writer without recursing on nesting depth
+///
+/// # Errors
+///
+/// Returns an error if writing to `writer` fails
+pub fn to_writer<W: Write>(writer: W, value: &Value, pretty: bool) -> io::Result<()> {
+    write(writer, value, pretty.then(Layout::default).as_ref())
+}
+
+/// Serialize a value as pretty JSON laid out as `layout` says into a
+/// writer, without recursing on nesting depth
+///
+/// # Errors
+///
+/// Returns an error if writing to `writer` fails
+pub fn to_writer_with<W: Write>(mut writer: W, value: &Value, layout: &Layout) -> io::Result<()> {
+    write(&mut writer, value, Some(layout))?;
+    layout.finish(&mut writer)
+}
+
+/// Serialize a value, compact without a layout
+fn write<W: Write>(mut writer: W, value: &Value, layout: Option<&Layout>) -> io::Result<()> {
+    let mut stack: Vec<Frame<'_>> = Vec::new();
+    open_value(&mut writer, value, layout, &mut stack)?;
+
+    while !stack.is_empty() {
+        let depth = stack.len();
+        let step = match stack.last_mut() {
+            Some(Frame::Array { items, first }) => match items.next() {
+                Some(item) => Step::Item(None, item, std::mem::replace(first, false)),
+                None => Step::Close(b']'),
+            },
+            Some(Frame::Object { entries, first }) => match entries.next() {
+                Some((key, item)) => Step::Item(Some(key), item, std::mem::replace(first, false)),
+                None => Step::Close(b'}'),
+            },
+            None => unreachable!("loop guard ensures the stack is non-empty"),
+        };
+
+        match step {
+            Step::Item(key, item, first) => {
+                if !first {
+                    writer.write_all(b",")?;
+                }
+                if let Some(layout) = layout {
+                    layout.newline(&mut writer, depth)?;
+                }
+                match (key, layout) {
+                    (Some(key), Some(layout)) => layout.write_key(&mut writer, key)?,
+                    (Some(key), None) => {
+                        serde_json::to_writer(&mut writer, key)?;
+                        writer.write_all(b":")?;
+                    }
+                    (None, _) => {}
+                }
+                open_value(&mut writer, item, layout, &mut stack)?;
+            }
+            Step::Close(delimiter) => {
+                stack.pop();
+                if let Some(layout) = layout {
+                    layout.newline(&mut writer, depth - 1)?;
+                }
+                writer.write_all(&[delimiter])?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Maximum nesting depth of a value
+///
+/// Scalars have depth 0; each enclosing array or object adds one level.
+pub fn depth(value: &Value) -> usize {
+    let mut max = 0;
+    let mut stack = vec![(value, 0usize)];
+
+    while let Some((value, level)) = stack.pop() {
+        match value {
+            Value::Array(items) => {
+                max = max.max(level + 1);
+                stack.extend(items.iter().map(|item| (item, level + 1)));
+            }
+            Value::Object(map) => {
+                max = max.max(level + 1);
+                stack.extend(map.values().map(|item| (item, level + 1)));
+            }
+            _ => {}
+        }
+    }
+
+    max
+}
+
+/// Drop a value without recursing on nesting depth
+///
+/// The default `Drop` of a deeply nested [`Value`] recurses once per level and
+/// can overflow the stack; this tears the tree down iteratively instead.
+pub fn drop_deep(value: Value) {
+    let mut stack = vec![value];
+
+    while let Some(mut value) = stack.pop() {
+        match &mut value {
+            Value::Array(items) => stack.append(items),
+            Value::Object(map) => {
+                let map = std::mem::take(map);
+                stack.extend(map.into_iter().map(|(_, item)| item));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Path of a record field below `parent`, e.g. `server.port`
+///
+/// Keys that are not plain identifiers are quoted, e.g. `labels."app.kubernetes.io/name"`.
+/// The root path is the empty string.
+pub fn key_path(parent: &str, key: &str) -> String {
+    let plain = key
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '\''));
+    let key = if plain {
+        key.to_string()
+    } else {
+        serde_json::to_string(key).expect("strings always serialize")
+    };
+
+    if parent.is_empty() {
+        key
+    } else {
+        format!("{}.{}", parent, key)
+    }
+}
+
+/// Path of an array element below `parent`, e.g. `servers[0]`
+pub fn index_path(parent: &str, index: usize) -> String {
+    format!("{}[{}]", parent, index)
+}
+
+/// Join path segments, as split from a path, back into a path
+///
+/// Keys are unquoted and indices kept as `[n]`, as [`crate::pattern`] splits
+/// them.
+pub(crate) fn join_path(segments: &[String]) -> String {
+    segments.iter().fold(String::new(), |path, segment| {
+        if segment.starts_with('[') {
+            path + segment
+        } else {
+            key_path(&path, segment)
+        }
+    })
+}
+
+/// The value at a path, in [`key_path`]/[`index_path`] syntax
+///
+/// The empty path (or `.`) selects the root. Returns `None` if a segment is
+/// missing, indexes into a non-array, or the path is malformed.
+pub fn select<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    let path = path.trim();
+    if path.is_empty() || path == "." {
+        return Some(value);
+    }
+
+    crate::pattern::split(path)?
+        .iter()
+        .try_fold(value, |value, segment| {
+            match segment.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+                Some(index) => value.as_array()?.get(index.parse::<usize>().ok()?),
+                None => value.as_object()?.get(segment),
+            }
+        })
+}
+
+/// The value at a path, as [`select`], for changing in place
+pub(crate) fn select_mut<'a>(value: &'a mut Value, path: &str) -> Option<&'a mut Value> {
+    let path = path.trim();
+    if path.is_empty() || path == "." {
+        return Some(value);
+    }
+
+    crate::pattern::split(path)?
+        .iter()
+        .try_fold(value, |value, segment| {
+            match segment.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+                Some(index) => value.as_array_mut()?.get_mut(index.parse::<usize>().ok()?),
+                None => value.as_object_mut()?.get_mut(segment),
+            }
+        })
+}
+
+/// An open container on the serializer's work stack
+enum Frame<'a> {
+    Array {
+        items: std::slice::Iter<'a, Value>,
+        first: bool,
+    },
+    Object {
+        entries: serde_json::map::Iter<'a>,
+        first: bool,
+    },
+}
+
+/// Next action taken by the serializer loop
+enum Step<'a> {
+    /// Emit an element (with its key, for objects); the flag marks the first element
+    Item(Option<&'a String>, &'a Value, bool),
+    /// Close the innermost container with the given delimiter
+    Close(u8),
+}
+
+/// Write a scalar, an empty or inline container, or the opening delimiter
+/// of a container
+fn open_value<'a, W: Write>(
+    writer: &mut W,
+    value: &'a Value,
+    layout: Option<&Layout>,
+    stack: &mut Vec<Frame<'a>>,
+) -> io::Result<()> {
+    match value {
+        Value::Array(items) if items.is_empty() => writer.write_all(b"[]"),
+        Value::Object(map) if map.is_empty() => writer.write_all(b"{}"),
+        Value::Array(items) => {
+            let scalar = |item: &Value| match item {
+                Value::Array(_) | Value::Object(_) => None,
+                scalar => serde_json::to_string(scalar).ok(),
+            };
+            if let Some(line) = layout.and_then(|layout| layout.inline(items.iter().map(scalar))) {
+                return writer.write_all(line.as_bytes());
+            }
+            stack.push(Frame::Array {
+                items: items.iter(),
+                first: true,
+            });
+            writer.write_all(b"[")
+        }
+        Value::Object(map) => {
+            stack.push(Frame::Object {
+                entries: map.iter(),
+                first: true,
+            });
+            writer.write_all(b"{")
+        }
+        // Scalars never recurse, so serde_json's own formatting is safe here
+        scalar => serde_json::to_writer(writer, scalar).map_err(io::Error::from),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const DEEP: usize = 100_000;
+
+    fn nested_arrays(depth: usize) -> Value {
+        let mut value = Value::Null;
+        for _ in 0..depth {
+            value = Value::Array(vec![value]);
+        }
+        value
+    }
+
+    fn nested_objects(depth: usize) -> Value {
+        let mut value = json!(1);
+        for _ in 0..depth {
+            let mut map = serde_json::Map::new();
+            map.insert("a".to_string(), value);
+            value = Value::Object(map);
+        }
+        value
+    }
+
+    #[test]
+    fn test_matches_serde_json_compact() {
+        let value = json!({ "a": [1, 2.5, "x\"y"], "b": {}, "c": [], "d": null, "e": true });
+        assert_eq!(
+            to_string(&value, false),
+            serde_json::to_string(&value).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_matches_serde_json_pretty() {
+        let value = json!({ "a": [1, { "b": [[]] }], "c": { "d": "e" }, "f": {} });
+        assert_eq!(
+            to_string(&value, true),
+            serde_json::to_string_pretty(&value).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_layout() {
+        let value = json!({
+            "name": "web",
+            "ports": [80, 443],
+            "hosts": ["a.example", "b.example"],
+            "rules": [{ "allow": true }],
+            "app/name": "x",
+        });
+        assert_eq!(
+            to_string_with(&value, &Layout::new()),
+            serde_json::to_string_pretty(&value).unwrap()
+        );
+
+        let layout = Layout::new()
+            .with_indent(4)
+            .with_inline_arrays(12)
+            .with_bare_keys(true)
+            .with_final_newline(true);
+        assert_eq!(
+            to_string_with(&value, &layout),
+            r#"{
+    "app/name": "x",
+    hosts: [
+        "a.example",
+        "b.example"
+    ],
+    name: "web",
+    ports: [80, 443],
+    rules: [
+        {
+            allow: true
+        }
+    ]
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_depth() {
+        assert_eq!(depth(&json!(1)), 0);
+        assert_eq!(depth(&json!([])), 1);
+        assert_eq!(depth(&json!({ "a": [1, [2]] })), 3);
+    }
+
+    #[test]
+    fn test_deep_arrays_serialize_and_drop() {
+        let value = nested_arrays(DEEP);
+        assert_eq!(depth(&value), DEEP);
+
+        let out = to_string(&value, false);
+        assert_eq!(out.len(), DEEP * 2 + "null".len());
+        assert!(out.starts_with("[[[") && out.ends_with("]]]"));
+        assert!(out.contains("[null]"));
+
+        drop_deep(value);
+    }
+
+    #[test]
+    fn test_deep_objects_serialize_and_drop() {
+        let value = nested_objects(DEEP);
+        assert_eq!(depth(&value), DEEP);
+
+        let out = to_string(&value, false);
+        assert!(out.starts_with(r#"{"a":{"a":{"#));
+        assert!(out.ends_with("}}}"));
+        assert!(out.contains(r#"{"a":1}"#));
+
+        drop_deep(value);
+    }
+
+    #[test]
+    fn test_paths() {
+        assert_eq!(key_path("", "server"), "server");
+        assert_eq!(key_path("server", "port"), "server.port");
+        assert_eq!(index_path("servers", 0), "servers[0]");
+        assert_eq!(key_path("labels", "app/name"), r#"labels."app/name""#);
+    }
+
+    #[test]
+    fn test_select() {
+        let value = json!({ "servers": [{ "port": 80 }], "labels": { "app/name": "x" } });
+        assert_eq!(select(&value, ""), Some(&value));
+        assert_eq!(select(&value, "servers[0].port"), Some(&json!(80)));
+        assert_eq!(select(&value, r#"labels."app/name""#), Some(&json!("x")));
+        assert_eq!(select(&value, "servers[1]"), None);
+        assert_eq!(select(&value, "servers.port"), None);
+        assert_eq!(select(&value, "labels..x"), None);
+    }
+}
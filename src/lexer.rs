@@ -0,0 +1,360 @@
+//! The crate's one lexer for Nickel source
+//!
+//! Everything that reads Nickel source without evaluating it (the formatter,
+//! lint, explain, merge, import scanning, the compatibility shim, masking
+//! and `synth`'s contract reader) splits it with this lexer, so they agree on
+//! where strings, interpolations and comments begin and end.
+//!
+//! Tokens are coarse: a string literal is one token including its
+//! delimiters and interpolations, and anything that is not whitespace, a
+//! comment, a string, punctuation or an operator is a [`Token::Word`].
+
+use crate::error::{Error, Result};
+use std::ops::Range;
+
+/// A lexical token
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Token {
+    /// Spaces and tabs
+    Space,
+    Newline,
+    /// `# …`, to the end of the line
+    Comment(String),
+    /// A string literal, with its delimiters and any interpolations
+    Str(String),
+    /// One of `{ } [ ] ( ) , ;`
+    Punct(char),
+    /// A run of operator characters, such as `=`, `=>` or `|>`
+    Op(String),
+    /// Anything else: identifiers, numbers, field paths, enum tags
+    Word(String),
+}
+
+impl Token {
+    pub(crate) fn text(&self) -> String {
+        match self {
+            Token::Space => " ".to_string(),
+            Token::Newline => "\n".to_string(),
+            Token::Punct(c) => c.to_string(),
+            Token::Comment(text) | Token::Str(text) | Token::Op(text) | Token::Word(text) => {
+                text.clone()
+            }
+        }
+    }
+
+    pub(crate) fn is_word(&self, word: &str) -> bool {
+        matches!(self, Token::Word(w) if w == word)
+    }
+
+    pub(crate) fn is_op(&self) -> bool {
+        matches!(self, Token::Op(_))
+    }
+
+    /// Whitespace or a comment
+    pub(crate) fn is_trivia(&self) -> bool {
+        matches!(self, Token::Space | Token::Newline | Token::Comment(_))
+    }
+}
+
+fn is_punct(c: char) -> bool {
+    matches!(c, '{' | '}' | '[' | ']' | '(' | ')' | ',' | ';')
+}
+
+fn is_op_char(c: char) -> bool {
+    matches!(
+        c,
+        '=' | '<' | '>' | '!' | '&' | '|' | '+' | '*' | '/' | '@' | ':' | '^' | '?' | '~' | '$'
+    )
+}
+
+/// Split source into [`Token`]s
+///
+/// # Errors
+///
+/// Returns an invalid-input error for unterminated strings
+pub(crate) fn tokens(source: &str) -> Result<Vec<Token>> {
+    Ok(spanned(source)?
+        .into_iter()
+        .map(|(_, token)| token)
+        .collect())
+}
+
+/// Split source into [`Token`]s, each with the byte range it was read from
+///
+/// # Errors
+///
+/// As [`tokens`]
+pub(crate) fn spanned(source: &str) -> Result<Vec<(Range<usize>, Token)>> {
+    Lexer::new(source).spanned(false)
+}
+
+/// As [`spanned`], but an unterminated string runs to the end of the source
+///
+/// For scanners that must not fail, such as the compatibility shim; Nickel
+/// reports the unterminated string when the source is evaluated.
+pub(crate) fn spanned_lenient(source: &str) -> Vec<(Range<usize>, Token)> {
+    Lexer::new(source).spanned(true).unwrap_or_default()
+}
+
+/// The value of a `"…"` string literal, or `None` for multiline strings and
+/// strings with interpolations
+pub(crate) fn string_value(literal: &str) -> Option<String> {
+    let body = literal.strip_prefix('"')?.strip_suffix('"')?;
+    let mut value = String::new();
+    let mut chars = body.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => value.push(match chars.next()? {
+                'n' => '\n',
+                't' => '\t',
+                'r' => '\r',
+                other => other,
+            }),
+            '%' if chars.peek() == Some(&'{') => return None,
+            c => value.push(c),
+        }
+    }
+    Some(value)
+}
+
+/// Splits source into [`Token`]s
+struct Lexer {
+    /// Characters with their byte offsets
+    chars: Vec<(usize, char)>,
+    /// Byte length of the source
+    len: usize,
+    at: usize,
+}
+
+impl Lexer {
+    fn new(source: &str) -> Self {
+        Self {
+            chars: source.char_indices().collect(),
+            len: source.len(),
+            at: 0,
+        }
+    }
+
+    fn peek(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.at + offset).map(|&(_, c)| c)
+    }
+
+    fn text(&self, start: usize) -> String {
+        self.chars[start..self.at].iter().map(|&(_, c)| c).collect()
+    }
+
+    /// The byte offset of the character at `index`
+    fn offset(&self, index: usize) -> usize {
+        self.chars
+            .get(index)
+            .map_or(self.len, |&(offset, _)| offset)
+    }
+
+    /// The number of `%` at the cursor if they open a multiline string
+    /// (`%…%"`)
+    fn multiline_start(&self) -> Option<usize> {
+        let percents = self.chars[self.at..]
+            .iter()
+            .take_while(|&&(_, c)| c == '%')
+            .count();
+        (percents > 0 && self.peek(percents) == Some('"')).then_some(percents)
+    }
+
+    fn spanned(mut self, lenient: bool) -> Result<Vec<(Range<usize>, Token)>> {
+        let mut tokens = Vec::new();
+        while let Some(c) = self.peek(0) {
+            let start = self.at;
+            let token = match self.token(c) {
+                Ok(token) => token,
+                Err(_) if lenient => {
+                    self.at = self.chars.len();
+                    Token::Str(self.text(start))
+                }
+                Err(e) => return Err(e),
+            };
+            tokens.push((self.offset(start)..self.offset(self.at), token));
+        }
+        Ok(tokens)
+    }
+
+    /// Read the token starting with `c`, at the cursor
+    fn token(&mut self, c: char) -> Result<Token> {
+        let start = self.at;
+        Ok(match c {
+            '\n' => {
+                self.at += 1;
+                Token::Newline
+            }
+            '\r' if self.peek(1) == Some('\n') => {
+                self.at += 2;
+                Token::Newline
+            }
+            c if c.is_whitespace() => {
+                self.at += 1;
+                while self
+                    .peek(0)
+                    .is_some_and(|c| c.is_whitespace() && c != '\n' && c != '\r')
+                {
+                    self.at += 1;
+                }
+                Token::Space
+            }
+            '#' => {
+                while self.peek(0).is_some_and(|c| c != '\n' && c != '\r') {
+                    self.at += 1;
+                }
+                Token::Comment(self.text(start).trim_end().to_string())
+            }
+            '"' => {
+                self.at += 1;
+                self.string(0)?;
+                Token::Str(self.text(start))
+            }
+            c if is_punct(c) => {
+                self.at += 1;
+                Token::Punct(c)
+            }
+            c if is_op_char(c) => {
+                while self.peek(0).is_some_and(is_op_char) {
+                    self.at += 1;
+                }
+                Token::Op(self.text(start))
+            }
+            _ => match self.multiline_start() {
+                // `m%"`, `foo-s%"`: the prefix is its own word, glued on
+                Some(percents) => {
+                    self.at += percents + 1;
+                    self.string(percents)?;
+                    Token::Str(self.text(start))
+                }
+                None => {
+                    self.at += 1;
+                    while let Some(c) = self.peek(0) {
+                        let ends = c.is_whitespace()
+                            || is_punct(c)
+                            || is_op_char(c)
+                            || c == '"'
+                            || c == '#'
+                            || (c == '%' && self.multiline_start().is_some());
+                        if ends {
+                            break;
+                        }
+                        self.at += 1;
+                    }
+                    Token::Word(self.text(start))
+                }
+            },
+        })
+    }
+
+    /// Consume a string after its opening delimiter
+    ///
+    /// `percents` is 0 for `"…"` strings, which have escapes and close at
+    /// `"`, and the delimiter's `%` count for multiline strings, which close
+    /// at `"` followed by as many `%`. Interpolations (`%{…}`, with as many
+    /// `%` as the delimiter) are consumed with the string.
+    fn string(&mut self, percents: usize) -> Result<()> {
+        let interpolation = percents.max(1);
+        loop {
+            let Some(c) = self.peek(0) else {
+                return Err(Error::invalid_input("Unterminated string"));
+            };
+            if percents == 0 && c == '\\' {
+                self.at += 2;
+                continue;
+            }
+            if c == '"' && (1..=percents).all(|i| self.peek(i) == Some('%')) {
+                self.at += 1 + percents;
+                return Ok(());
+            }
+            let opens = (0..interpolation).all(|i| self.peek(i) == Some('%'))
+                && self.peek(interpolation) == Some('{');
+            if opens {
+                self.at += interpolation + 1;
+                self.interpolation()?;
+                continue;
+            }
+            self.at += 1;
+        }
+    }
+
+    /// Consume code up to and including the `}` closing an interpolation
+    fn interpolation(&mut self) -> Result<()> {
+        let mut depth = 0usize;
+        loop {
+            let Some(c) = self.peek(0) else {
+                return Err(Error::invalid_input("Unterminated string interpolation"));
+            };
+            match c {
+                '"' => {
+                    self.at += 1;
+                    self.string(0)?;
+                    continue;
+                }
+                '#' => {
+                    while self.peek(0).is_some_and(|c| c != '\n') {
+                        self.at += 1;
+                    }
+                    continue;
+                }
+                '{' => depth += 1,
+                '}' if depth == 0 => {
+                    self.at += 1;
+                    return Ok(());
+                }
+                '}' => depth -= 1,
+                _ => {
+                    if let Some(percents) = self.multiline_start() {
+                        self.at += percents + 1;
+                        self.string(percents)?;
+                        continue;
+                    }
+                }
+            }
+            self.at += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spans_are_byte_ranges() {
+        let source = "{ é = \"x%{a}\", # note\n  b = m%\"y\"% }";
+        let spanned = spanned(source).unwrap();
+        for (span, token) in &spanned {
+            match token {
+                Token::Space | Token::Newline => {}
+                Token::Comment(text) => assert_eq!(source[span.clone()].trim_end(), text),
+                token => assert_eq!(source[span.clone()], token.text()),
+            }
+        }
+        let strings: Vec<_> = spanned
+            .iter()
+            .filter(|(_, t)| matches!(t, Token::Str(_)))
+            .map(|(span, _)| &source[span.clone()])
+            .collect();
+        assert_eq!(strings, ["\"x%{a}\"", "%\"y\"%"]);
+        assert_eq!(spanned.last().unwrap().0.end, source.len());
+    }
+
+    #[test]
+    fn test_unterminated_strings() {
+        assert!(tokens("{ a = \"x }").is_err());
+        let spanned = spanned_lenient("{ a = \"x }");
+        assert_eq!(
+            spanned.last().unwrap(),
+            &(6..10, Token::Str("\"x }".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_string_value() {
+        assert_eq!(string_value(r#""a\"b\n""#).as_deref(), Some("a\"b\n"));
+        assert_eq!(string_value(r#""100\%{x}""#).as_deref(), Some("100%{x}"));
+        assert_eq!(string_value(r#""%{x}""#), None);
+        assert_eq!(string_value(r#"%"x"%"#), None);
+    }
+}
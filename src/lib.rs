@@ -68,16 +68,112 @@
 )]
 #![cfg_attr(docsrs, feature(doc_cfg))]
 
+pub mod archive;
+pub mod arena;
+pub mod audit;
+pub mod batch;
+pub mod bench;
+pub mod cache;
+pub mod capabilities;
+pub mod compat;
+pub mod compress;
+pub mod conformance;
+pub mod coverage;
+#[cfg(feature = "schemas")]
+#[cfg_attr(docsrs, doc(cfg(feature = "schemas")))]
+pub mod cue;
+pub mod depfile;
+pub mod diagnostic;
+pub mod diff;
+pub mod dirs;
+pub mod docs;
+pub mod doctor;
+pub mod drift;
+pub mod embedded;
+pub mod env;
 pub mod error;
+pub mod explain;
+pub mod export;
+pub mod exports;
+pub mod expr;
+pub mod ffi;
+pub mod fingerprint;
+pub mod flags;
+pub mod fmt;
+pub mod group;
+pub mod guard;
+pub mod harden;
+pub mod imports;
+pub mod interrupt;
+pub mod json;
+mod lexer;
+pub mod library;
+pub mod limits;
+pub mod lint;
 pub mod loader;
+#[cfg(feature = "lsp")]
+#[cfg_attr(docsrs, doc(cfg(feature = "lsp")))]
+pub mod lsp;
+pub mod mask;
+pub mod matrix;
+pub mod memo;
+mod meter;
+pub mod merge;
+#[cfg(feature = "daemon")]
+#[cfg_attr(docsrs, doc(cfg(feature = "daemon")))]
+pub mod metrics;
+pub mod mutate;
+pub mod oci;
+pub mod order;
+pub mod output;
+pub mod overrides;
+pub mod owners;
+pub mod paths;
+pub mod pattern;
+pub mod porcelain;
+pub mod profile;
+#[cfg(feature = "schemas")]
+#[cfg_attr(docsrs, doc(cfg(feature = "schemas")))]
+pub mod protobuf;
+pub mod query;
+pub mod restrict;
+pub mod scaffold;
+pub mod schema;
+#[cfg(feature = "daemon")]
+#[cfg_attr(docsrs, doc(cfg(feature = "daemon")))]
+pub mod serve;
+#[cfg(feature = "daemon")]
+#[cfg_attr(docsrs, doc(cfg(feature = "daemon")))]
+pub mod session;
+pub mod settings;
+pub mod source;
+pub mod style;
+pub mod synth;
+pub mod target;
+pub mod telemetry;
+pub mod tenant;
+pub mod testing;
+pub mod threads;
+pub mod transform;
+#[cfg(feature = "schemas")]
+#[cfg_attr(docsrs, doc(cfg(feature = "schemas")))]
+pub mod validation;
+pub mod vendor;
+pub mod version;
+pub mod watch;
+pub mod worker;
 
 #[cfg(target_arch = "wasm32")]
 #[cfg_attr(docsrs, doc(cfg(target_arch = "wasm32")))]
 pub mod wasm;
 
 // Re-exports for convenience
+pub use arena::Document;
 pub use error::{Error, Result};
+pub use fmt::format_source;
 pub use loader::NickelLoader;
+pub use memo::MemoizedLoader;
+pub use version::{BuildInfo, VersionInfo, BUILD_INFO, VERSION_INFO};
 
 /// Library version, updated automatically from Cargo.toml
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -0,0 +1,263 @@
+//! Nickel libraries registered at runtime
+//!
+//! A long-running process such as `bunsenite serve` can be given helper
+//! libraries (Nickel source, usually a record of functions) under a name, and
+//! replace or remove them without restarting. Every evaluation started after
+//! a change sees the new set; evaluations already running keep the one they
+//! started with.
+//!
+//! Each registered version is written to its own file in a private temporary
+//! directory, and [`bind`](Libraries::bind) imports them by name on the first
+//! line of a program, so line numbers in errors still match the program and
+//! errors inside a library name the library's file. A replaced or removed
+//! version's file is deleted once no [`Bound`] program refers to it.
+//!
+//! Native (Rust) host functions cannot be registered: nickel-lang-core 0.9.1
+//! has no API for adding primitive operations from outside the crate, so
+//! helpers have to be written in Nickel.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::library::Libraries;
+//! use bunsenite::NickelLoader;
+//!
+//! let loader = NickelLoader::new();
+//! let libraries = Libraries::new();
+//! libraries.register(&loader, "helpers", "{ double = fun x => x * 2 }").unwrap();
+//!
+//! let program = libraries.bind("{ a = helpers.double 21 }");
+//! let value = loader.parse_string(program.source(), "app.ncl").unwrap();
+//! assert_eq!(value["a"], 42);
+//! ```
+
+use crate::error::{Error, Result};
+use crate::loader::NickelLoader;
+use crate::source;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use tempfile::TempDir;
+
+/// A set of named libraries
+#[derive(Debug, Default)]
+pub struct Libraries {
+    dir: Mutex<Option<Arc<TempDir>>>,
+    versions: AtomicU64,
+    entries: RwLock<BTreeMap<String, Arc<Version>>>,
+}
+
+/// One registered version of a library, deleted once nothing refers to it
+#[derive(Debug)]
+struct Version {
+    path: PathBuf,
+    // Keeps the directory until its last version is gone
+    _dir: Arc<TempDir>,
+}
+
+impl Drop for Version {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// A program with the registered libraries bound to it
+///
+/// Keeps the library versions it imports on disk until it is dropped, so
+/// hold it until the program has been evaluated.
+#[derive(Debug, Clone, Default)]
+pub struct Bound {
+    source: String,
+    _versions: Vec<Arc<Version>>,
+}
+
+impl Bound {
+    /// The program's source, importing the libraries on its first line
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+}
+
+impl Libraries {
+    /// Create an empty set
+    ///
+    /// Nothing is written to disk until the first library is registered.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a library, or replace the one registered under `name`
+    ///
+    /// The library is typechecked with `loader` first, and rejected if that
+    /// fails.
+    ///
+    /// # Errors
+    ///
+    /// Returns an invalid-input error if `name` is not a Nickel identifier,
+    /// the library's parse or type error, or an I/O error if it cannot be
+    /// written
+    pub fn register(&self, loader: &NickelLoader, name: &str, library: &str) -> Result<()> {
+        if !source::is_identifier(name) {
+            return Err(Error::invalid_input(format!(
+                "Library name '{}' is not a Nickel identifier",
+                name
+            )));
+        }
+        loader.typecheck(library, &format!("{}.ncl", name))?;
+
+        let dir = {
+            let mut dir = self.dir.lock().expect("libraries poisoned");
+            match &*dir {
+                Some(dir) => Arc::clone(dir),
+                None => {
+                    let created = Arc::new(
+                        tempfile::Builder::new()
+                            .prefix("bunsenite-libraries-")
+                            .tempdir()?,
+                    );
+                    *dir = Some(Arc::clone(&created));
+                    created
+                }
+            }
+        };
+        // Running evaluations may still import the previous version, so each
+        // version gets a new file
+        let version = self.versions.fetch_add(1, Ordering::Relaxed);
+        let path = dir.path().join(format!("{}.{}.ncl", name, version));
+        std::fs::write(&path, library)?;
+
+        self.entries
+            .write()
+            .expect("libraries poisoned")
+            .insert(name.to_string(), Arc::new(Version { path, _dir: dir }));
+        Ok(())
+    }
+
+    /// Remove a library
+    ///
+    /// # Errors
+    ///
+    /// Returns an invalid-input error if no library is registered under `name`
+    pub fn unregister(&self, name: &str) -> Result<()> {
+        match self
+            .entries
+            .write()
+            .expect("libraries poisoned")
+            .remove(name)
+        {
+            Some(_) => Ok(()),
+            None => Err(Error::invalid_input(format!(
+                "No library named '{}' is registered",
+                name
+            ))),
+        }
+    }
+
+    /// Names of the registered libraries, sorted
+    pub fn names(&self) -> Vec<String> {
+        self.entries
+            .read()
+            .expect("libraries poisoned")
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    /// Make the registered libraries available to a program under their names
+    ///
+    /// The imports are prepended on the program's first line.
+    pub fn bind(&self, program: &str) -> Bound {
+        let entries = self.entries.read().expect("libraries poisoned");
+        let mut bound = String::new();
+        for (name, version) in entries.iter() {
+            bound.push_str(&format!(
+                "let {} = import {} in ",
+                name,
+                source::string_literal(&version.path.to_string_lossy())
+            ));
+        }
+        bound.push_str(program);
+        Bound {
+            source: bound,
+            _versions: entries.values().cloned().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(libraries: &Libraries, name: &str) -> PathBuf {
+        libraries.entries.read().unwrap()[name].path.clone()
+    }
+
+    #[test]
+    fn test_register_replace_unregister() {
+        let loader = NickelLoader::new();
+        let libraries = Libraries::new();
+        assert_eq!(libraries.bind("1").source(), "1");
+
+        libraries.register(&loader, "lib", "{ value = 1 }").unwrap();
+        let bound = libraries.bind("lib.value");
+        assert_eq!(loader.parse_string(bound.source(), "a.ncl").unwrap(), 1);
+
+        libraries.register(&loader, "lib", "{ value = 2 }").unwrap();
+        assert_eq!(
+            loader
+                .parse_string(libraries.bind("lib.value").source(), "a.ncl")
+                .unwrap(),
+            2
+        );
+        // A program bound before the change still sees the old version
+        assert_eq!(loader.parse_string(bound.source(), "a.ncl").unwrap(), 1);
+
+        assert_eq!(libraries.names(), vec!["lib".to_string()]);
+        libraries.unregister("lib").unwrap();
+        assert!(libraries.unregister("lib").is_err());
+        assert!(libraries.names().is_empty());
+    }
+
+    #[test]
+    fn test_register_rejects_bad_libraries() {
+        let loader = NickelLoader::new();
+        let libraries = Libraries::new();
+        assert!(libraries.register(&loader, "my lib", "{}").is_err());
+        assert!(libraries.register(&loader, "lib", "{ a = }").is_err());
+        assert!(libraries
+            .register(&loader, "lib", r#"{ port : Number = "80" }"#)
+            .is_err());
+        assert!(libraries.names().is_empty());
+    }
+
+    #[test]
+    fn test_superseded_versions_are_deleted_once_unused() {
+        let loader = NickelLoader::new();
+        let libraries = Libraries::new();
+        libraries.register(&loader, "lib", "{}").unwrap();
+        let first = path(&libraries, "lib");
+        let bound = libraries.bind("lib");
+
+        libraries.register(&loader, "lib", "{}").unwrap();
+        let second = path(&libraries, "lib");
+        assert!(first.exists());
+        drop(bound);
+        assert!(!first.exists());
+
+        libraries.unregister("lib").unwrap();
+        assert!(!second.exists());
+    }
+
+    #[test]
+    fn test_drop_removes_files() {
+        let libraries = Libraries::new();
+        libraries
+            .register(&NickelLoader::new(), "lib", "{}")
+            .unwrap();
+        let dir = path(&libraries, "lib").parent().unwrap().to_path_buf();
+        assert!(dir.exists());
+        drop(libraries);
+        assert!(!dir.exists());
+    }
+}
@@ -0,0 +1,444 @@
+//! Process resource limits
+//!
+//! Backs the global `--rlimit-as`, `--rlimit-cpu` and `--rlimit-nofile`
+//! flags: opt-in `setrlimit` limits on address space, CPU time and open
+//! files, so that a runaway evaluation (or a bug in the evaluator) fails on
+//! its own instead of starving a shared CI runner.
+//!
+//! A process that exceeds its address space or CPU time is aborted or
+//! killed before it can report anything, so the CLI runs the evaluation in a
+//! child process that applies the limits to itself ([`Limits::apply`]) and
+//! watches it from the parent ([`Limits::supervise`]). A child that fails
+//! because of a limit and can tell exits with its [`Resource::exit_code`];
+//! the parent turns that, or a death by a limit's signal, into an
+//! [`Error::ResourceLimit`] naming the resource.
+//!
+//! Limits are only available on Unix; elsewhere, applying any fails.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::limits::{self, Limits};
+//!
+//! let limits = Limits::new()
+//!     .with_address_space(limits::parse_size("2G").unwrap())
+//!     .with_cpu_time(30);
+//! assert!(!limits.is_empty());
+//! assert_eq!(limits::parse_size("512M").unwrap(), 512 << 20);
+//! ```
+
+use crate::error::{Error, Result};
+use std::io::{Read, Write};
+use std::process::{Command, ExitStatus, Stdio};
+
+/// How much of a child's stderr is kept to recognise limit failures
+const STDERR_TAIL: usize = 4096;
+
+/// A resource a process can be limited in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resource {
+    /// `RLIMIT_AS`
+    AddressSpace,
+    /// `RLIMIT_CPU`
+    CpuTime,
+    /// `RLIMIT_NOFILE`
+    OpenFiles,
+}
+
+impl Resource {
+    /// Exit code of a limited process that failed for want of this resource
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Resource::AddressSpace => 91,
+            Resource::CpuTime => 92,
+            Resource::OpenFiles => 93,
+        }
+    }
+
+    fn from_exit_code(code: i32) -> Option<Self> {
+        [
+            Resource::AddressSpace,
+            Resource::CpuTime,
+            Resource::OpenFiles,
+        ]
+        .into_iter()
+        .find(|resource| resource.exit_code() == code)
+    }
+
+    /// The resource `error` shows running out, if it is an I/O error that
+    /// says so
+    pub fn of_error(error: &Error) -> Option<Self> {
+        #[cfg(unix)]
+        if let Error::IoError(e) = error {
+            return match e.raw_os_error() {
+                Some(libc::ENOMEM) => Some(Resource::AddressSpace),
+                Some(libc::EMFILE) => Some(Resource::OpenFiles),
+                _ => None,
+            };
+        }
+        let _ = error;
+        None
+    }
+}
+
+/// Resource limits for an evaluation process
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    address_space: Option<u64>,
+    cpu_time: Option<u64>,
+    open_files: Option<u64>,
+}
+
+impl Limits {
+    /// No limits
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limit the address space to `bytes` (`RLIMIT_AS`)
+    pub fn with_address_space(mut self, bytes: u64) -> Self {
+        self.address_space = Some(bytes);
+        self
+    }
+
+    /// Limit CPU time to `seconds` (`RLIMIT_CPU`)
+    pub fn with_cpu_time(mut self, seconds: u64) -> Self {
+        self.cpu_time = Some(seconds);
+        self
+    }
+
+    /// Limit open file descriptors to `count` (`RLIMIT_NOFILE`)
+    pub fn with_open_files(mut self, count: u64) -> Self {
+        self.open_files = Some(count);
+        self
+    }
+
+    /// Whether no limit is set
+    pub fn is_empty(&self) -> bool {
+        self.address_space.is_none() && self.cpu_time.is_none() && self.open_files.is_none()
+    }
+
+    /// Apply the limits to the current process and, through inheritance,
+    /// to every process it starts
+    ///
+    /// Limits only ever go down: one above the process's current hard limit
+    /// is capped to it. The CPU hard limit is one second above the soft one,
+    /// so the process gets `SIGXCPU` before it is killed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an invalid-input error if a limit cannot be set, or if any
+    /// is set on a platform other than Unix
+    #[cfg(unix)]
+    pub fn apply(&self) -> Result<()> {
+        use rlimit::Resource;
+
+        let limits = [
+            (Resource::AS, "address space", self.address_space, 0),
+            (Resource::CPU, "CPU time", self.cpu_time, 1),
+            (Resource::NOFILE, "open files", self.open_files, 0),
+        ];
+        for (resource, name, limit, grace) in limits {
+            let Some(limit) = limit else {
+                continue;
+            };
+            let cannot =
+                |e: std::io::Error| Error::invalid_input(format!("Cannot limit {}: {}", name, e));
+            let (_, current_hard) = rlimit::getrlimit(resource).map_err(cannot)?;
+            let hard = limit.saturating_add(grace).min(current_hard);
+            rlimit::setrlimit(resource, limit.min(hard), hard).map_err(cannot)?;
+        }
+        Ok(())
+    }
+
+    /// Apply the limits to the current process and, through inheritance,
+    /// to every process it starts
+    ///
+    /// # Errors
+    ///
+    /// Returns an invalid-input error if any limit is set, since resource
+    /// limits are only supported on Unix
+    #[cfg(not(unix))]
+    pub fn apply(&self) -> Result<()> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::invalid_input(
+                "Resource limits (--rlimit-*) are only supported on Unix",
+            ))
+        }
+    }
+
+    /// Run `command`, which applies these limits to itself, passing its
+    /// stderr through, and return its exit status
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::ResourceLimit`] if the child died of one of the
+    /// limits (see [`Limits::diagnose`]), or an I/O error if it cannot be
+    /// run
+    pub fn supervise(&self, command: Command) -> Result<ExitStatus> {
+        self.supervise_to(command, &mut std::io::stderr())
+    }
+
+    /// As [`supervise`](Self::supervise), passing the child's stderr to
+    /// `out`
+    fn supervise_to(&self, mut command: Command, out: &mut impl Write) -> Result<ExitStatus> {
+        let mut child = command.stderr(Stdio::piped()).spawn()?;
+        let mut stderr = child.stderr.take().expect("child stderr is piped");
+
+        let mut tail = Vec::new();
+        let mut buffer = [0u8; 8192];
+        loop {
+            let read = stderr.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            out.write_all(&buffer[..read])?;
+            out.flush()?;
+
+            tail.extend_from_slice(&buffer[..read]);
+            if tail.len() > STDERR_TAIL {
+                tail.drain(..tail.len() - STDERR_TAIL);
+            }
+        }
+
+        let status = child.wait()?;
+        match self.diagnose(status, &String::from_utf8_lossy(&tail)) {
+            Some(error) => Err(error),
+            None => Ok(status),
+        }
+    }
+
+    /// The limit a process running under these limits ran into, judging by
+    /// its exit status and, failing that, the end of its stderr
+    ///
+    /// Only limits that are set are reported. In order:
+    ///
+    /// 1. CPU time shows as death by `SIGXCPU` (or `SIGKILL` at the hard
+    ///    limit)
+    /// 2. A child that could tell exits with the [`Resource::exit_code`] of
+    ///    the limit
+    /// 3. Address space shows as an abort after Rust's allocation failure
+    ///    message
+    /// 4. A child that failed otherwise is judged by the last OS error in
+    ///    its stderr, `(os error N)`, for failures the child could not
+    ///    attribute, such as an import Nickel could not open. Messages
+    ///    alone, which a config can produce, do not count.
+    pub fn diagnose(&self, status: ExitStatus, stderr: &str) -> Option<Error> {
+        #[cfg(unix)]
+        let aborted = {
+            use std::os::unix::process::ExitStatusExt;
+            let signal = status.signal();
+            if matches!(signal, Some(libc::SIGXCPU) | Some(libc::SIGKILL)) {
+                return self.exceeded(Resource::CpuTime);
+            }
+            signal == Some(libc::SIGABRT)
+        };
+        #[cfg(not(unix))]
+        let aborted = false;
+
+        if let Some(resource) = status.code().and_then(Resource::from_exit_code) {
+            return self.exceeded(resource);
+        }
+        if aborted && stderr.contains("memory allocation of") {
+            return self.exceeded(Resource::AddressSpace);
+        }
+        if status.code() == Some(1) {
+            return last_os_error(stderr).and_then(|resource| self.exceeded(resource));
+        }
+        None
+    }
+
+    /// The error for running out of `resource`, if it is limited
+    fn exceeded(&self, resource: Resource) -> Option<Error> {
+        match resource {
+            Resource::AddressSpace => self
+                .address_space
+                .map(|bytes| Error::resource_limit("address space", format_size(bytes))),
+            Resource::CpuTime => self
+                .cpu_time
+                .map(|seconds| Error::resource_limit("CPU time", format!("{} s", seconds))),
+            Resource::OpenFiles => self
+                .open_files
+                .map(|count| Error::resource_limit("open files", count.to_string())),
+        }
+    }
+}
+
+/// The resource the last `(os error N)` in `stderr` shows running out, if
+/// any
+fn last_os_error(stderr: &str) -> Option<Resource> {
+    let (_, rest) = stderr.rsplit_once("(os error ")?;
+    let (code, _) = rest.split_once(')')?;
+    let error = std::io::Error::from_raw_os_error(code.parse().ok()?);
+    Resource::of_error(&Error::IoError(error))
+}
+
+/// Parse a size in bytes, with an optional binary suffix: `K`, `M`, `G` or
+/// `T`, optionally followed by `iB` (e.g. `512M`, `2G`, `1GiB`)
+///
+/// # Errors
+///
+/// Returns an invalid-input error for anything else, or a size that does
+/// not fit in 64 bits
+pub fn parse_size(size: &str) -> Result<u64> {
+    let invalid = || {
+        Error::invalid_input(format!(
+            "Invalid size '{}': expected bytes with an optional K, M, G or T suffix",
+            size
+        ))
+    };
+    let trimmed = size.trim();
+    // "iB" only follows a suffix, as in "GiB"
+    let trimmed = match trimmed.strip_suffix("iB") {
+        Some(rest) if rest.ends_with(|c: char| c.is_ascii_alphabetic()) => rest,
+        _ => trimmed,
+    };
+    let (digits, shift) = match trimmed.char_indices().last() {
+        Some((at, suffix)) if suffix.is_ascii_alphabetic() => {
+            let shift = match suffix.to_ascii_uppercase() {
+                'K' => 10,
+                'M' => 20,
+                'G' => 30,
+                'T' => 40,
+                _ => return Err(invalid()),
+            };
+            (&trimmed[..at], shift)
+        }
+        _ => (trimmed, 0),
+    };
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(1 << shift))
+        .ok_or_else(invalid)
+}
+
+/// `bytes` with the largest binary suffix that divides it
+pub(crate) fn format_size(bytes: u64) -> String {
+    for (suffix, shift) in [("T", 40), ("G", 30), ("M", 20), ("K", 10)] {
+        if bytes != 0 && bytes % (1 << shift) == 0 {
+            return format!("{}{}", bytes >> shift, suffix);
+        }
+    }
+    format!("{} bytes", bytes)
+}
+
+/// Resident set size of this process, where the platform reports it
+pub(crate) fn resident_bytes() -> Option<u64> {
+    if !cfg!(target_os = "linux") {
+        return None;
+    }
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kib: u64 = line
+        .trim_start_matches("VmRSS:")
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kib * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sizes() {
+        assert_eq!(parse_size("1048576").unwrap(), 1 << 20);
+        assert_eq!(parse_size("64k").unwrap(), 64 << 10);
+        assert_eq!(parse_size(" 2G ").unwrap(), 2 << 30);
+        assert_eq!(parse_size("1GiB").unwrap(), 1 << 30);
+        assert!(parse_size("1iB").is_err());
+        assert!(parse_size("").is_err());
+        assert!(parse_size("G").is_err());
+        assert!(parse_size("2X").is_err());
+        assert!(parse_size("-1M").is_err());
+        assert!(parse_size("99999999999T").is_err());
+
+        assert_eq!(format_size(3 << 30), "3G");
+        assert_eq!(format_size(1536 << 10), "1536K");
+        assert_eq!(format_size(1000), "1000 bytes");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_diagnose() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let limits = Limits::new()
+            .with_address_space(1 << 30)
+            .with_cpu_time(5)
+            .with_open_files(64);
+        let killed = ExitStatus::from_raw(libc::SIGXCPU);
+        let error = limits.diagnose(killed, "").unwrap();
+        assert_eq!(error.to_string(), "Resource limit exceeded: CPU time (5 s)");
+
+        let aborted = ExitStatus::from_raw(libc::SIGABRT);
+        let error = limits
+            .diagnose(aborted, "memory allocation of 4096 bytes failed\n")
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "Resource limit exceeded: address space (1G)"
+        );
+
+        let exited = |code: i32| ExitStatus::from_raw(code << 8);
+        let error = limits
+            .diagnose(exited(Resource::OpenFiles.exit_code()), "")
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "Resource limit exceeded: open files (64)"
+        );
+        let stderr = format!(
+            "Error: import failed: Too many open files (os error {})\n",
+            libc::EMFILE
+        );
+        assert!(limits.diagnose(exited(1), &stderr).is_some());
+
+        // A config can say anything; without the exit code or an OS error
+        // its message is just a message
+        for message in [
+            "✗ Evaluation failed",
+            "Error: std.fail_with \"Too many open files\"",
+            "Error: memory allocation of 1 bytes failed",
+        ] {
+            assert!(limits.diagnose(exited(1), message).is_none(), "{}", message);
+        }
+        assert!(limits.diagnose(exited(2), &stderr).is_none());
+        assert!(Limits::new().diagnose(killed, "").is_none());
+        assert!(Limits::new()
+            .diagnose(exited(Resource::OpenFiles.exit_code()), "")
+            .is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_supervise_reports_limit() {
+        let limits = Limits::new().with_open_files(16);
+        let mut command = Command::new("sh");
+        command.args([
+            "-c",
+            &format!(
+                "echo 'Too many open files (os error 24)' >&2; exit {}",
+                Resource::OpenFiles.exit_code()
+            ),
+        ]);
+        let mut stderr = Vec::new();
+        let error = limits.supervise_to(command, &mut stderr).unwrap_err();
+        assert!(matches!(error, Error::ResourceLimit { .. }));
+        assert_eq!(stderr, b"Too many open files (os error 24)\n");
+
+        let mut command = Command::new("sh");
+        command.args(["-c", "exit 3"]);
+        let status = limits.supervise_to(command, &mut Vec::new()).unwrap();
+        assert_eq!(status.code(), Some(3));
+    }
+}
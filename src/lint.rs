@@ -0,0 +1,565 @@
+//! Static checks for Nickel source
+//!
+//! Backs `bunsenite lint`. A [`Linter`] reads source with the same lexer as
+//! [`crate::fmt`], without evaluating it, and reports a [`Finding`] for each
+//! place an enabled [`Rule`] matches:
+//!
+//! - `unused-let`: a `let` binding its body never mentions (names starting
+//!   with `_` are exempt)
+//! - `shadowed-field`: a field defined with `=` more than once in the same
+//!   record literal, which Nickel merges rather than overrides
+//! - `missing-contract`: a field of the file's top-level record without a
+//!   `|` contract or `:` type annotation (off by default)
+//! - `deprecated-call`: a standard-library function that has been renamed,
+//!   with its replacement
+//!
+//! The checks are lexical, so they err towards silence: a name mentioned
+//! anywhere later counts as used, and only literal record syntax is seen.
+//!
+//! Rules are switched on and off with [`Linter::with_rule`], or by a
+//! `.bunsenite-lint.ncl` file ([`Linter::from_config`]) such as
+//!
+//! ```text
+//! { enable = ["missing-contract"], disable = ["unused-let"] }
+//! ```
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::lint::{Linter, Rule};
+//!
+//! let source = "let unused = 1 in { port = 80, port = 81 }";
+//! let findings = Linter::new().lint(source).unwrap();
+//! assert_eq!(findings.len(), 2);
+//! assert_eq!(findings[0].rule, Rule::UnusedLet);
+//! assert_eq!(findings[1].rule, Rule::ShadowedField);
+//!
+//! let quiet = Linter::new().with_rule(Rule::UnusedLet, false);
+//! assert_eq!(quiet.lint(source).unwrap().len(), 1);
+//! ```
+
+use crate::error::{Error, Result};
+use crate::lexer::{self, Token};
+use crate::loader::NickelLoader;
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// The file `bunsenite lint` reads its rule settings from, if present
+pub const CONFIG_FILE: &str = ".bunsenite-lint.ncl";
+
+/// Standard-library functions renamed in Nickel 1.x, with their
+/// replacements
+const DEPRECATED: &[(&str, &str)] = &[
+    ("std.array.foldl", "std.array.fold_left"),
+    ("std.array.foldr", "std.array.fold_right"),
+    ("std.string.to_str", "std.to_string"),
+    ("std.contract.blame_with", "std.contract.blame_with_message"),
+];
+
+/// A lint rule
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Rule {
+    /// A `let` binding that is never used
+    UnusedLet,
+    /// A field defined more than once in one record literal
+    ShadowedField,
+    /// A top-level field without a contract or type annotation
+    MissingContract,
+    /// A call to a renamed standard-library function
+    DeprecatedCall,
+}
+
+impl Rule {
+    /// Every rule, in reporting order
+    pub const ALL: [Rule; 4] = [
+        Rule::UnusedLet,
+        Rule::ShadowedField,
+        Rule::MissingContract,
+        Rule::DeprecatedCall,
+    ];
+
+    /// The rule's name, as used on the command line and in config files
+    pub fn name(self) -> &'static str {
+        match self {
+            Rule::UnusedLet => "unused-let",
+            Rule::ShadowedField => "shadowed-field",
+            Rule::MissingContract => "missing-contract",
+            Rule::DeprecatedCall => "deprecated-call",
+        }
+    }
+
+    /// Whether the rule is enabled unless configured otherwise
+    pub fn default_enabled(self) -> bool {
+        self != Rule::MissingContract
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        Rule::ALL
+            .into_iter()
+            .find(|rule| rule.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<_> = Rule::ALL.iter().map(|rule| rule.name()).collect();
+                format!(
+                    "unknown lint rule '{}' (expected one of {})",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// One place a rule matched
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    /// The rule that matched
+    pub rule: Rule,
+    /// 1-based line of the offending token
+    pub line: usize,
+    /// What is wrong
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {} [{}]", self.line, self.message, self.rule)
+    }
+}
+
+/// Checks source against a set of enabled rules
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Linter {
+    enabled: BTreeSet<Rule>,
+}
+
+impl Default for Linter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Linter {
+    /// A linter with each rule's default setting
+    pub fn new() -> Self {
+        Self {
+            enabled: Rule::ALL
+                .into_iter()
+                .filter(|rule| rule.default_enabled())
+                .collect(),
+        }
+    }
+
+    /// Enable or disable `rule`
+    pub fn with_rule(mut self, rule: Rule, enabled: bool) -> Self {
+        if enabled {
+            self.enabled.insert(rule);
+        } else {
+            self.enabled.remove(&rule);
+        }
+        self
+    }
+
+    /// A linter configured by the Nickel file at `path`
+    ///
+    /// The file evaluates to a record with optional `enable` and `disable`
+    /// arrays of rule names, applied over the defaults.
+    ///
+    /// # Errors
+    ///
+    /// Returns the evaluation error, or an invalid-input error for other
+    /// fields or unknown rule names
+    pub fn from_config(loader: &NickelLoader, path: &Path) -> Result<Self> {
+        let invalid = |message: String| {
+            Error::invalid_input(format!("Invalid {}: {}", path.display(), message))
+        };
+        let Value::Object(fields) = loader.parse_file(path)? else {
+            return Err(invalid("expected a record".to_string()));
+        };
+        let mut linter = Self::new();
+        for (key, value) in fields {
+            let enabled = match key.as_str() {
+                "enable" => true,
+                "disable" => false,
+                other => {
+                    return Err(invalid(format!(
+                        "unknown field '{}' (expected enable or disable)",
+                        other
+                    )))
+                }
+            };
+            let Value::Array(names) = value else {
+                return Err(invalid(format!("'{}' must be an array of rule names", key)));
+            };
+            for name in names {
+                let rule = name
+                    .as_str()
+                    .ok_or_else(|| invalid(format!("'{}' must be an array of rule names", key)))?
+                    .parse()
+                    .map_err(invalid)?;
+                linter = linter.with_rule(rule, enabled);
+            }
+        }
+        Ok(linter)
+    }
+
+    /// Whether `rule` is enabled
+    pub fn is_enabled(&self, rule: Rule) -> bool {
+        self.enabled.contains(&rule)
+    }
+
+    /// Check `source`, returning findings in line order
+    ///
+    /// # Errors
+    ///
+    /// Returns an invalid-input error for source that does not lex
+    pub fn lint(&self, source: &str) -> Result<Vec<Finding>> {
+        let tokens = significant(source)?;
+        let mut findings = Vec::new();
+        if self.is_enabled(Rule::UnusedLet) {
+            unused_lets(&tokens, &mut findings);
+        }
+        if self.is_enabled(Rule::ShadowedField) {
+            shadowed_fields(&tokens, &mut findings);
+        }
+        if self.is_enabled(Rule::MissingContract) {
+            missing_contracts(&tokens, &mut findings);
+        }
+        if self.is_enabled(Rule::DeprecatedCall) {
+            deprecated_calls(&tokens, &mut findings);
+        }
+        findings.sort_by_key(|finding| (finding.line, finding.rule));
+        Ok(findings)
+    }
+
+    /// Check the file at `path`
+    ///
+    /// # Errors
+    ///
+    /// Returns an I/O error, or as [`lint`](Self::lint)
+    pub fn lint_file(&self, path: &Path) -> Result<Vec<Finding>> {
+        self.lint(&std::fs::read_to_string(path)?)
+    }
+}
+
+/// Tokens other than whitespace and comments, each with its line
+fn significant(source: &str) -> Result<Vec<(Token, usize)>> {
+    let mut line = 1;
+    let mut tokens = Vec::new();
+    for token in lexer::tokens(source)? {
+        match token {
+            Token::Newline => line += 1,
+            Token::Space | Token::Comment(_) => {}
+            Token::Str(ref text) => {
+                let lines = text.matches('\n').count();
+                tokens.push((token, line));
+                line += lines;
+            }
+            token => tokens.push((token, line)),
+        }
+    }
+    Ok(tokens)
+}
+
+fn is_open(token: &Token) -> bool {
+    matches!(token, Token::Punct('{' | '[' | '('))
+}
+
+fn is_close(token: &Token) -> bool {
+    matches!(token, Token::Punct('}' | ']' | ')'))
+}
+
+/// Indices of the names bound by the `let` at `at`
+fn let_names(tokens: &[(Token, usize)], at: usize) -> Vec<usize> {
+    let mut first = at + 1;
+    if tokens.get(first).is_some_and(|(t, _)| t.is_word("rec")) {
+        first += 1;
+    }
+    // Destructuring patterns are not checked
+    if !matches!(tokens.get(first), Some((Token::Word(_), _))) {
+        return Vec::new();
+    }
+    let mut names = vec![first];
+    let (mut depth, mut lets) = (0usize, 0usize);
+    for (i, (token, _)) in tokens.iter().enumerate().skip(first + 1) {
+        if is_open(token) {
+            depth += 1;
+        } else if is_close(token) {
+            if depth == 0 {
+                break;
+            }
+            depth -= 1;
+        } else if depth > 0 {
+            continue;
+        } else if token.is_word("let") {
+            lets += 1;
+        } else if token.is_word("in") {
+            if lets == 0 {
+                break;
+            }
+            lets -= 1;
+        } else if *token == Token::Punct(',') && lets == 0 {
+            if let Some((Token::Word(_), _)) = tokens.get(i + 1) {
+                names.push(i + 1);
+            }
+        }
+    }
+    names
+}
+
+/// Whether `token` mentions the variable `name`
+fn mentions(token: &Token, name: &str) -> bool {
+    let is_ident = |c: char| c.is_alphanumeric() || matches!(c, '_' | '-' | '\'');
+    match token {
+        Token::Word(word) => word
+            .strip_prefix(name)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('.')),
+        // Only interpolations can mention a variable
+        Token::Str(text) if text.contains("%{") => text.match_indices(name).any(|(at, _)| {
+            let before = text[..at].chars().next_back();
+            let after = text[at + name.len()..].chars().next();
+            !before.is_some_and(is_ident) && !after.is_some_and(is_ident)
+        }),
+        _ => false,
+    }
+}
+
+fn unused_lets(tokens: &[(Token, usize)], findings: &mut Vec<Finding>) {
+    for (at, (token, _)) in tokens.iter().enumerate() {
+        if !token.is_word("let") {
+            continue;
+        }
+        for name_at in let_names(tokens, at) {
+            let (Token::Word(name), line) = &tokens[name_at] else {
+                continue;
+            };
+            if name.starts_with('_') {
+                continue;
+            }
+            let used = tokens
+                .iter()
+                .enumerate()
+                .skip(at + 1)
+                .any(|(i, (t, _))| i != name_at && mentions(t, name));
+            if !used {
+                findings.push(Finding {
+                    rule: Rule::UnusedLet,
+                    line: *line,
+                    message: format!("`{}` is bound but never used", name),
+                });
+            }
+        }
+    }
+}
+
+/// A field the record literal opening at some `{` defines
+struct Field<'a> {
+    name: &'a str,
+    line: usize,
+    /// Whether a `|` or `:` annotation precedes its `=`
+    annotated: bool,
+}
+
+/// Fields defined with `=` in the record opening at `open`, in order
+///
+/// Declarations without a value (`port | Number`) and match arms are not
+/// fields here.
+fn record_fields(tokens: &[(Token, usize)], open: usize) -> Vec<Field<'_>> {
+    let mut fields = Vec::new();
+    let mut current: Option<Field<'_>> = None;
+    let mut defined = false;
+    let mut starts_field = true;
+    let mut depth = 0usize;
+    for (token, line) in &tokens[open + 1..] {
+        let field_start = std::mem::replace(&mut starts_field, false);
+        if is_open(token) {
+            depth += 1;
+            continue;
+        }
+        if is_close(token) {
+            if depth == 0 {
+                break;
+            }
+            depth -= 1;
+            continue;
+        }
+        if depth > 0 {
+            continue;
+        }
+        match token {
+            Token::Punct(',') => {
+                if let Some(field) = current.take().filter(|_| defined) {
+                    fields.push(field);
+                }
+                defined = false;
+                starts_field = true;
+            }
+            Token::Word(name) | Token::Str(name) if field_start => {
+                current = Some(Field {
+                    name,
+                    line: *line,
+                    annotated: false,
+                });
+            }
+            Token::Op(op) if op == "=" => defined = true,
+            Token::Op(op) if (op == "|" || op == ":") && !defined => {
+                if let Some(field) = current.as_mut() {
+                    field.annotated = true;
+                }
+            }
+            _ => {}
+        }
+    }
+    if let Some(field) = current.filter(|_| defined) {
+        fields.push(field);
+    }
+    fields
+}
+
+fn shadowed_fields(tokens: &[(Token, usize)], findings: &mut Vec<Finding>) {
+    for (open, (token, _)) in tokens.iter().enumerate() {
+        if *token != Token::Punct('{') {
+            continue;
+        }
+        let mut first: HashMap<&str, usize> = HashMap::new();
+        for field in record_fields(tokens, open) {
+            match first.get(field.name) {
+                Some(line) => findings.push(Finding {
+                    rule: Rule::ShadowedField,
+                    line: field.line,
+                    message: format!(
+                        "field `{}` is already defined on line {}; the definitions are merged",
+                        field.name, line
+                    ),
+                }),
+                None => {
+                    first.insert(field.name, field.line);
+                }
+            }
+        }
+    }
+}
+
+fn missing_contracts(tokens: &[(Token, usize)], findings: &mut Vec<Finding>) {
+    // The file's value is the last record literal outside any brackets
+    let mut depth = 0usize;
+    let mut top = None;
+    for (i, (token, _)) in tokens.iter().enumerate() {
+        if is_open(token) {
+            if depth == 0 && *token == Token::Punct('{') {
+                top = Some(i);
+            }
+            depth += 1;
+        } else if is_close(token) {
+            depth = depth.saturating_sub(1);
+        }
+    }
+    let Some(open) = top else {
+        return;
+    };
+    for field in record_fields(tokens, open) {
+        if !field.annotated {
+            findings.push(Finding {
+                rule: Rule::MissingContract,
+                line: field.line,
+                message: format!("field `{}` has no contract or type annotation", field.name),
+            });
+        }
+    }
+}
+
+fn deprecated_calls(tokens: &[(Token, usize)], findings: &mut Vec<Finding>) {
+    for (token, line) in tokens {
+        let Token::Word(word) = token else {
+            continue;
+        };
+        if let Some((old, new)) = DEPRECATED.iter().find(|(old, _)| old == word) {
+            findings.push(Finding {
+                rule: Rule::DeprecatedCall,
+                line: *line,
+                message: format!("`{}` is deprecated; use `{}`", old, new),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_rules() {
+        let source = r#"let base = import "base.ncl" in
+let rec _scratch = 1, greeting = "hi", unused = 2 in
+let total = std.array.foldl (+) 0 [1, 2] in
+{
+  name = "%{greeting}, world",
+  count = total,
+  port | Number,
+  port = 80,
+  nested = { a = 1, b.c = 2, b.d = 3, a = 4 },
+  check = match { 'A => 1, 'A => 2 },
+} & base
+"#;
+        let findings = Linter::new().lint(source).unwrap();
+        let found: Vec<_> = findings.iter().map(|f| (f.rule, f.line)).collect();
+        assert_eq!(
+            found,
+            [
+                (Rule::UnusedLet, 2),
+                (Rule::DeprecatedCall, 3),
+                (Rule::ShadowedField, 9),
+            ]
+        );
+        assert_eq!(
+            findings[0].to_string(),
+            "line 2: `unused` is bound but never used [unused-let]"
+        );
+    }
+
+    #[test]
+    fn test_missing_contract_and_rule_selection() {
+        let source = "let x = { a = 1 } in\n{\n  port | Number = x.a,\n  host = \"h\",\n  tags : Array String = [],\n}\n";
+        assert!(Linter::new().lint(source).unwrap().is_empty());
+
+        let strict = Linter::new().with_rule(Rule::MissingContract, true);
+        let findings = strict.lint(source).unwrap();
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].line, 4);
+        assert!(findings[0].message.contains("`host`"));
+
+        assert_eq!("shadowed-field".parse::<Rule>(), Ok(Rule::ShadowedField));
+        assert!("no-such-rule".parse::<Rule>().is_err());
+    }
+
+    #[test]
+    fn test_config_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = dir.path().join(CONFIG_FILE);
+        std::fs::write(
+            &config,
+            r#"{ enable = ["missing-contract"], disable = ["unused-let"] }"#,
+        )
+        .unwrap();
+        let linter = Linter::from_config(&NickelLoader::new(), &config).unwrap();
+        assert!(linter.is_enabled(Rule::MissingContract));
+        assert!(!linter.is_enabled(Rule::UnusedLet));
+        assert!(linter.is_enabled(Rule::ShadowedField));
+
+        std::fs::write(&config, r#"{ enable = ["typo"] }"#).unwrap();
+        assert!(Linter::from_config(&NickelLoader::new(), &config).is_err());
+        std::fs::write(&config, r#"{ rules = [] }"#).unwrap();
+        assert!(Linter::from_config(&NickelLoader::new(), &config).is_err());
+    }
+}
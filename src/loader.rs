@@ -5,17 +5,85 @@
 //!
 //! # API Compatibility Notes (nickel-lang-core 0.9.1)
 //!
-//! - `Program::new_from_source()` requires trace parameter: `std::io::sink()`
+//! - `Program::new_from_source()` reads from any `Read` and requires trace parameter: `std::io::sink()`
 //! - `eval_full()` takes no arguments (changed in 0.9.1)
 //! - Manual error conversion required via `serde_json::to_value()`
 //! - NO `into_diagnostics()` method available (deprecated)
 
+use crate::arena::Document;
+use crate::audit::{AuditLog, Event};
+use crate::bench::Phase;
+use crate::compat;
+use crate::docs::FieldDoc;
+use crate::env;
 use crate::error::{Error, Result};
-use nickel_lang_core::cache::InputFormat;
-use nickel_lang_core::program::Program;
+use crate::guard::{Finding, ImportGuard};
+use crate::imports::{self, ImportGraph};
+use crate::interrupt::Shutdown;
+use crate::json;
+use crate::lexer::Token;
+use crate::limits;
+use crate::mask;
+use crate::meter::{self, Budget, Metered};
+use crate::order::FieldOrder;
+use crate::overrides;
+use crate::source;
+use crate::target::Target;
+use crate::telemetry;
+use crate::threads;
+use crate::transform::{PathFilter, Transform};
+use nickel_lang_core::cache::{Cache as ImportCache, ErrorTolerance, SourcePath};
+use nickel_lang_core::eval::cache::Cache as _;
+use nickel_lang_core::eval::VirtualMachine;
+use nickel_lang_core::identifier::LocIdent;
+use nickel_lang_core::mk_app;
+use nickel_lang_core::program::{FieldPath, Program};
+use nickel_lang_core::term::record::Field;
+use nickel_lang_core::term::{MergePriority, RichTerm, Term};
+use nickel_lang_core::typecheck;
+use serde::Deserialize;
 use serde_json::Value;
-use std::io::Write;
-use std::path::Path;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Default stack reserved for parsing and evaluation (256 MiB)
+///
+/// Nickel's program transformations, evaluator and serializer all recurse on
+/// the nesting depth of the program. Running them on a dedicated stack segment
+/// of this size lets configs nest about 20,000 levels in release builds
+/// (5,000 in unoptimized ones, whose frames are larger) instead of
+/// overflowing the calling thread's stack. Sources nested deeper get a
+/// segment sized for their depth, up to [`MAX_STACK_SIZE`]. Segments are
+/// reserved lazily, so unused stack costs address space rather than memory.
+pub const DEFAULT_STACK_SIZE: usize = 256 * 1024 * 1024;
+
+/// Largest stack segment reserved for a deeply nested source (4 GiB)
+///
+/// Enough for about 250,000 levels of nesting in release builds (85,000 in
+/// unoptimized ones); sources nested deeper fail with
+/// [`Error::ResourceLimit`] rather than overflowing the stack.
+#[cfg(target_pointer_width = "64")]
+pub const MAX_STACK_SIZE: usize = 4 << 30;
+
+/// Largest stack segment reserved for a deeply nested source (1 GiB)
+#[cfg(not(target_pointer_width = "64"))]
+pub const MAX_STACK_SIZE: usize = 1 << 30;
+
+/// Stack one level of nesting may take while a program is parsed,
+/// typechecked and evaluated
+///
+/// Measured at about 10 KiB in release builds and 45 KiB in unoptimized
+/// ones, for nested records and arrays alike.
+const STACK_PER_LEVEL: usize = if cfg!(debug_assertions) {
+    48 << 10
+} else {
+    16 << 10
+};
 
 /// Nickel configuration loader
 ///
@@ -30,10 +98,69 @@ use std::path::Path;
 /// let config = r#"{ name = "example", version = "1.0.0" }"#;
 /// let result = loader.parse_string(config, "test.ncl").unwrap();
 /// ```
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone)]
 pub struct NickelLoader {
     /// Enable verbose error reporting
     verbose: bool,
+    /// Stack size for parsing and evaluation (0 = use the caller's stack)
+    stack_size: usize,
+    /// Rewrite deprecated stdlib names before evaluation
+    compat: bool,
+    /// Replace the source's string and number literals before evaluation
+    mask_values: bool,
+    /// Post-processing applied to evaluated values, in order
+    transforms: Vec<Arc<dyn Transform>>,
+    /// Worker threads for batch operations (0 = default)
+    threads: usize,
+    /// Read a file's import tree concurrently before evaluating it
+    prefetch_imports: bool,
+    /// Where to record files, imports, environment and transforms used
+    audit: Option<Arc<AuditLog>>,
+    /// Field values merged over the program, as (path, value)
+    overrides: Vec<(String, Value)>,
+    /// Prefix and environment variables exposed to the program as `env`
+    env: Option<(String, BTreeMap<String, String>)>,
+    /// Give the program an empty `env` and ignore `CI`
+    hermetic: bool,
+    /// Subtree to evaluate instead of the whole program
+    target: Option<Target>,
+    /// Symlink and path-escape policies checked before evaluation
+    import_guard: Option<ImportGuard>,
+    /// Where warnings and statistics that do not fail evaluation go
+    notices: Option<NoticeHandler>,
+    /// Custom serializers for values annotated with a contract
+    serializers: Vec<ContractSerializer>,
+    /// Deepest nesting allowed in the source and the result
+    max_depth: Option<usize>,
+    /// Resident memory of the process at which evaluation fails
+    max_memory: Option<u64>,
+    /// Longest an evaluation may take
+    timeout: Option<Duration>,
+}
+
+impl Default for NickelLoader {
+    fn default() -> Self {
+        Self {
+            verbose: false,
+            stack_size: DEFAULT_STACK_SIZE,
+            compat: false,
+            mask_values: false,
+            transforms: Vec::new(),
+            threads: 0,
+            prefetch_imports: false,
+            audit: None,
+            overrides: Vec::new(),
+            env: None,
+            hermetic: false,
+            target: None,
+            import_guard: None,
+            notices: None,
+            serializers: Vec::new(),
+            max_depth: None,
+            max_memory: None,
+            timeout: None,
+        }
+    }
 }
 
 impl NickelLoader {
@@ -48,6 +175,379 @@ impl NickelLoader {
         self
     }
 
+    /// Set the stack size used for parsing and evaluation
+    ///
+    /// Defaults to [`DEFAULT_STACK_SIZE`]. Sources nested deeper than it
+    /// holds get a larger segment, up to [`MAX_STACK_SIZE`]; `0` runs on the
+    /// caller's own stack, whatever the nesting.
+    pub fn with_stack_size(mut self, bytes: usize) -> Self {
+        self.stack_size = bytes;
+        self
+    }
+
+    /// Fail programs nested more than `levels` deep
+    ///
+    /// Brackets, braces and parentheses are counted in the source before it
+    /// is parsed, so a pathologically deep program is rejected before the
+    /// parser recurses on it; the evaluated value is checked again, which
+    /// catches depth built up by evaluation. Imported files count through
+    /// the result only. Exceeding the limit fails with
+    /// [`Error::ResourceLimit`].
+    pub fn with_max_depth(mut self, levels: usize) -> Self {
+        self.max_depth = Some(levels);
+        self
+    }
+
+    /// Fail evaluations during which the process's resident memory exceeds
+    /// `bytes`
+    ///
+    /// Memory is sampled while evaluation runs, for the whole process, so
+    /// concurrent evaluations share the limit. Only Linux reports resident
+    /// memory; elsewhere evaluation fails with an invalid-input error. See
+    /// [`with_timeout`](Self::with_timeout) for what happens to an
+    /// evaluation over the limit.
+    pub fn with_max_memory(mut self, bytes: u64) -> Self {
+        self.max_memory = Some(bytes);
+        self
+    }
+
+    /// Fail evaluations that take longer than `timeout`
+    ///
+    /// With a timeout or memory limit, evaluation runs on a thread of its
+    /// own, watched by the caller. Nickel cannot stop an evaluation midway,
+    /// so one over a limit is abandoned rather than stopped: the caller gets
+    /// [`Error::ResourceLimit`] at once, while the thread runs on until the
+    /// evaluation ends or the process exits, as the CLI does after
+    /// reporting the error.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Rewrite deprecated stdlib names to their replacements before evaluation
+    ///
+    /// See [`crate::compat`]. The loader applies the rewrites silently; use
+    /// [`compat::translate`] to report them.
+    pub fn with_compat(mut self, compat: bool) -> Self {
+        self.compat = compat;
+        self
+    }
+
+    /// Mask the string and number literals of evaluated sources
+    ///
+    /// See [`crate::mask`]. Only the source given to the loader is masked,
+    /// not the files it imports; add the
+    /// [`MaskValues`](crate::transform::MaskValues) transform to mask those
+    /// values in the output.
+    pub fn with_mask_values(mut self, mask_values: bool) -> Self {
+        self.mask_values = mask_values;
+        self
+    }
+
+    /// Set the number of worker threads for batch operations
+    ///
+    /// Governs tenant and matrix evaluation and conformance checks. `0`
+    /// selects [`threads::default_threads`]; `1` runs everything in order on
+    /// the calling thread. Results are in input order either way.
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// The number of worker threads for batch operations
+    pub fn threads(&self) -> usize {
+        match self.threads {
+            0 if self.hermetic => threads::available_threads(),
+            0 => threads::default_threads(),
+            n => n,
+        }
+    }
+
+    /// Read the import tree of files concurrently before evaluating them
+    ///
+    /// Applies to [`parse_file`](Self::parse_file) and
+    /// [`parse_file_document`](Self::parse_file_document). See
+    /// [`crate::imports`]; prefetching uses [`threads`](Self::threads)
+    /// workers and is worthwhile on slow or network filesystems. What each
+    /// prefetch read is reported as a [`Notice::Prefetched`].
+    pub fn with_prefetch_imports(mut self, prefetch: bool) -> Self {
+        self.prefetch_imports = prefetch;
+        self
+    }
+
+    /// Record what evaluation touches in an audit log
+    ///
+    /// See [`crate::audit`].
+    pub fn with_audit_log(mut self, log: AuditLog) -> Self {
+        self.audit = Some(Arc::new(log));
+        self
+    }
+
+    /// Set fields of the config, given as (path, value), over what the
+    /// program defines
+    ///
+    /// Paths are in [`json::key_path`] syntax, e.g. `server.port`. Overrides
+    /// are added to those already registered; see [`crate::overrides`].
+    pub fn with_overrides(mut self, overrides: Vec<(String, Value)>) -> Self {
+        self.overrides.extend(overrides);
+        self
+    }
+
+    /// Expose the environment variables whose names start with `prefix` to
+    /// programs as an `env` record, without the prefix
+    ///
+    /// The variables are read now; see [`crate::env`]. A later call replaces
+    /// the prefix and variables.
+    pub fn with_env(mut self, prefix: impl Into<String>) -> Self {
+        let prefix = prefix.into();
+        let vars = env::capture(&prefix);
+        self.env = Some((prefix, vars));
+        self
+    }
+
+    /// Keep the environment out of evaluation: programs see an empty `env`
+    /// record, whatever [`with_env`](Self::with_env) registered, and the
+    /// default thread count ignores `CI`
+    pub fn with_hermetic(mut self, hermetic: bool) -> Self {
+        self.hermetic = hermetic;
+        self
+    }
+
+    /// Evaluate only the subtree at `target` instead of the whole program
+    ///
+    /// See [`crate::target`].
+    pub fn with_target(mut self, target: Target) -> Self {
+        self.target = Some(target);
+        self
+    }
+
+    /// Check imports against symlink and path-escape policies before
+    /// evaluating
+    ///
+    /// See [`crate::guard`]. Denied imports fail evaluation before it starts;
+    /// imports the guard warns about are reported as [`Notice::Import`]s.
+    pub fn with_import_guard(mut self, guard: ImportGuard) -> Self {
+        self.import_guard = Some(guard);
+        self
+    }
+
+    /// Pass each [`Notice`] an evaluation reports to `handler`
+    ///
+    /// Without a handler, notices are dropped: the loader never prints.
+    pub fn with_notices(mut self, handler: impl Fn(&Notice) + Send + Sync + 'static) -> Self {
+        self.notices = Some(NoticeHandler(Arc::new(handler)));
+        self
+    }
+
+    /// Write values annotated with `contract` as the string `serializer`
+    /// makes of them
+    ///
+    /// For logic that would otherwise be repeated in every config, such as
+    /// assembling a connection string from its parts. A field matches if one
+    /// of its contract annotations is `contract` or ends in `.contract`
+    /// (`lib.ConnString` matches `ConnString`); the serializer receives the
+    /// field's evaluated value, before transforms run. Serializers apply to
+    /// every parse. Where fields at several levels match, the outermost
+    /// wins.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bunsenite::NickelLoader;
+    ///
+    /// let loader = NickelLoader::new().with_serializer_for("ConnString", |value| {
+    ///     format!("postgres://{}:{}", value["host"].as_str().unwrap_or(""), value["port"])
+    /// });
+    /// let source = r#"
+    ///     let ConnString = { host | String, port | Number } in
+    ///     { db | ConnString = { host = "db.internal", port = 5432 } }
+    /// "#;
+    /// let value = loader.parse_string(source, "config.ncl").unwrap();
+    /// assert_eq!(value["db"], "postgres://db.internal:5432");
+    /// ```
+    pub fn with_serializer_for(
+        mut self,
+        contract: impl Into<String>,
+        serializer: impl Fn(&Value) -> String + Send + Sync + 'static,
+    ) -> Self {
+        self.serializers.push(ContractSerializer {
+            contract: contract.into(),
+            serialize: Arc::new(serializer),
+        });
+        self
+    }
+
+    /// The serializer registered for a contract annotation, if any
+    fn serializer_for(&self, contract: &str) -> Option<&ContractSerializer> {
+        let contract = contract.trim();
+        self.serializers.iter().find(|serializer| {
+            contract == serializer.contract
+                || contract
+                    .rsplit_once('.')
+                    .is_some_and(|(_, name)| name == serializer.contract)
+        })
+    }
+
+    /// Replace values annotated with a contract that has a serializer
+    fn apply_serializers(&self, value: &mut Value, contracts: &[(String, String)]) {
+        for (path, contract) in contracts {
+            let Some(serializer) = self.serializer_for(contract) else {
+                continue;
+            };
+            // An enclosing value already serialized leaves nothing to select
+            if let Some(slot) = json::select_mut(value, path) {
+                *slot = Value::String((serializer.serialize)(slot));
+            }
+        }
+    }
+
+    /// Apply the import guard, if any, to a source about to be evaluated
+    fn guard_imports(&self, source: &str, name: &str) -> Result<()> {
+        let Some(guard) = &self.import_guard else {
+            return Ok(());
+        };
+        for finding in guard.enforce(&guard.check(source, name, self.threads()))? {
+            self.notify(Notice::Import(finding.clone()));
+        }
+        Ok(())
+    }
+
+    fn notify(&self, notice: Notice) {
+        if let Some(NoticeHandler(handler)) = &self.notices {
+            handler(&notice);
+        }
+    }
+
+    fn audit(&self, event: Event<'_>) -> Result<()> {
+        match &self.audit {
+            Some(log) => log.record(event),
+            None => Ok(()),
+        }
+    }
+
+    /// Read a file for evaluation, prefetching its imports if enabled
+    pub(crate) fn read_file(&self, path: &Path) -> Result<(String, String)> {
+        self.audit(Event::Read { path })?;
+        if self.prefetch_imports {
+            self.notify(Notice::Prefetched(imports::prefetch(path, self.threads())));
+        }
+        read_source(path)
+    }
+
+    /// Add a transform applied to evaluated values before they are returned
+    ///
+    /// Transforms run in the order they were added, on the evaluation stack.
+    /// See [`crate::transform`].
+    pub fn with_transform<T: Transform + 'static>(mut self, transform: T) -> Self {
+        self.transforms.push(Arc::new(transform));
+        self
+    }
+
+    /// Keep or drop output paths by pattern
+    ///
+    /// Shorthand for [`with_transform`](Self::with_transform) with a
+    /// [`PathFilter`]; an empty filter is ignored.
+    pub fn with_path_filter(self, filter: PathFilter) -> Self {
+        if filter.is_empty() {
+            self
+        } else {
+            self.with_transform(filter)
+        }
+    }
+
+    /// Descriptions of the registered transforms, in order
+    pub(crate) fn transform_descriptions(&self) -> Vec<String> {
+        self.transforms
+            .iter()
+            .map(|transform| format!("{:?}", transform))
+            .collect()
+    }
+
+    /// The settings that affect what a config evaluates to, for
+    /// [`Fingerprint`](crate::fingerprint::Fingerprint)s
+    pub(crate) fn settings(&self) -> String {
+        format!(
+            "{:?}",
+            (
+                self.compat,
+                self.mask_values,
+                &self.transforms,
+                &self.overrides,
+                &self.env,
+                self.hermetic,
+                &self.target,
+                &self.import_guard,
+                &self.serializers,
+            )
+        )
+    }
+
+    /// Run the registered transforms over an evaluated value
+    fn post_process(&self, value: Value) -> Result<Value> {
+        if let Some(levels) = self.max_depth {
+            if json::depth(&value) > levels {
+                return Err(depth_exceeded(levels));
+            }
+        }
+        self.transforms.iter().try_fold(value, |value, transform| {
+            self.audit(Event::Transform {
+                transform: &format!("{:?}", transform),
+            })?;
+            transform.apply(value)
+        })
+    }
+
+    /// Audit a source's imports and apply the compatibility shim, masking,
+    /// overrides, target and `env` record, if any
+    ///
+    /// Fails if the audit log cannot be written or the source cannot be
+    /// masked.
+    fn prepare<'a>(&self, source: &'a str, name: &str) -> Result<Cow<'a, str>> {
+        if self.audit.is_some() {
+            if self.threads == 0 && !self.hermetic {
+                // `threads` sizes itself from `CI`
+                self.audit(Event::Env { name: "CI" })?;
+            }
+            let graph = imports::resolve_source(source, name, self.threads());
+            for (from, path) in &graph.imports {
+                let found = !graph.missing.contains(path);
+                self.audit(Event::Import { from, path, found })?;
+            }
+            for path in &graph.files {
+                self.audit(Event::Read { path })?;
+            }
+        }
+        let source = if self.compat {
+            Cow::Owned(compat::translate(source).0)
+        } else {
+            Cow::Borrowed(source)
+        };
+        let source = if self.mask_values {
+            Cow::Owned(mask::mask_source(&source)?)
+        } else {
+            source
+        };
+        let source = if self.overrides.is_empty() {
+            source
+        } else {
+            Cow::Owned(overrides::apply(&source, &self.overrides))
+        };
+        let source = match &self.target {
+            Some(target) => Cow::Owned(target.select(&source)),
+            None => source,
+        };
+        Ok(match (&self.env, self.hermetic) {
+            (_, true) => Cow::Owned(env::apply(&source, "", &BTreeMap::new())),
+            (Some((prefix, vars)), false) => {
+                for name in vars.keys() {
+                    self.audit(Event::Env { name })?;
+                }
+                Cow::Owned(env::apply(&source, prefix, vars))
+            }
+            (None, false) => source,
+        })
+    }
+
     /// Parse and evaluate a Nickel configuration from a string
     ///
     /// # Arguments
@@ -73,35 +573,335 @@ impl NickelLoader {
     /// assert!(result.is_ok());
     /// ```
     pub fn parse_string(&self, source: &str, name: &str) -> Result<Value> {
-        // Create a Program from source
-        // API change in 0.9.1: new_from_source requires trace parameter
-        let mut program = Program::new_from_source(
-            source.to_string(),
-            name.to_string(),
-            InputFormat::Nickel,
-            std::io::sink(), // Trace output (discarded)
-        )
-        .map_err(|e| {
-            let msg = format!("{:?}", e);
-            Error::parse_error(name, msg)
+        self.guard_imports(source, name)?;
+        let source = self.prepare(source, name)?;
+        let source = source.as_ref();
+        let span = telemetry::Evaluation::start(source, name);
+        let value = span.in_scope(|| {
+            self.on_eval_stack(source, name, |loader, source, name| {
+                let value = if loader.serializers.is_empty() {
+                    Self::eval_to_json(source, name)?
+                } else {
+                    loader.eval_with_contracts(source, name)?.0
+                };
+                loader.post_process(value)
+            })
         })?;
+        span.record_output(&value);
+        Ok(value)
+    }
 
-        // Evaluate the program
-        // API change in 0.9.1: eval_full takes no arguments
-        let eval_result = program.eval_full().map_err(|e| {
-            let msg = format!("{:?}", e);
-            Error::evaluation_error(name, msg)
-        })?;
+    /// Parse and evaluate a Nickel configuration into an arena [`Document`]
+    ///
+    /// Unlike [`parse_string`](Self::parse_string), the evaluated program is
+    /// serialized straight into a flat arena with interned keys, without an
+    /// intermediate `serde_json::Value` tree. Prefer this for large configs
+    /// whose output is only written out.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if parsing or evaluation fails
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bunsenite::NickelLoader;
+    ///
+    /// let doc = NickelLoader::new().parse_document("{ foo = 42 }", "config.ncl").unwrap();
+    /// assert_eq!(doc.to_json_string(false), r#"{"foo":42}"#);
+    /// ```
+    pub fn parse_document(&self, source: &str, name: &str) -> Result<Document> {
+        self.guard_imports(source, name)?;
+        let source = self.prepare(source, name)?;
+        let source = source.as_ref();
+        let span = telemetry::Evaluation::start(source, name);
+        span.in_scope(|| {
+            self.on_eval_stack(source, name, |loader, source, name| {
+                // Transforms, serializers and the depth limit work on values,
+                // so they cost the intermediate tree
+                if loader.transforms.is_empty()
+                    && loader.serializers.is_empty()
+                    && loader.max_depth.is_none()
+                {
+                    let term = Self::evaluate(source, name)?;
+                    return telemetry::phase(Phase::Serialize, || Document::from_serialize(&term));
+                }
+                let value = if loader.serializers.is_empty() {
+                    Self::eval_to_json(source, name)?
+                } else {
+                    loader.eval_with_contracts(source, name)?.0
+                };
+                let value = loader.post_process(value)?;
+                let document = Document::from_serialize(&value);
+                json::drop_deep(value);
+                document
+            })
+        })
+    }
+
+    /// Parse and evaluate a Nickel configuration file into an arena [`Document`]
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or if parsing/evaluation fails
+    pub fn parse_file_document<P: AsRef<Path>>(&self, path: P) -> Result<Document> {
+        let (source, name) = self.read_file(path.as_ref())?;
+        self.parse_document(&source, &name)
+    }
+
+    /// Parse, evaluate and convert a program to JSON on the current stack,
+    /// with the contracts annotating its output fields and custom
+    /// serializers applied
+    fn eval_with_contracts(
+        &self,
+        source: &str,
+        name: &str,
+    ) -> Result<(Value, Vec<(String, String)>)> {
+        let term = Self::evaluate(source, name)?;
+        let mut contracts = Vec::new();
+        walk_fields(&term, String::new(), &mut |path, field| {
+            for contract in &field.metadata.annotation.contracts {
+                contracts.push((path.to_string(), contract.typ.to_string()));
+            }
+            true
+        });
+        let mut value = telemetry::phase(Phase::Serialize, || serde_json::to_value(&term))
+            .map_err(|e| Error::serialization_error(format!("Failed to convert to JSON: {}", e)))?;
+        self.apply_serializers(&mut value, &contracts);
+        Ok((value, contracts))
+    }
+
+    /// Parse, evaluate and convert a program to JSON on the current stack
+    fn eval_to_json(source: &str, name: &str) -> Result<Value> {
+        let eval_result = Self::evaluate(source, name)?;
 
         // Convert to JSON
         // API change in 0.9.1: Manual conversion required, no into_diagnostics()
-        let json_value = serde_json::to_value(&eval_result).map_err(|e| {
-            Error::serialization_error(format!("Failed to convert to JSON: {}", e))
-        })?;
+        let json_value = telemetry::phase(Phase::Serialize, || serde_json::to_value(&eval_result))
+            .map_err(|e| Error::serialization_error(format!("Failed to convert to JSON: {}", e)))?;
 
         Ok(json_value)
     }
 
+    /// Parse and fully evaluate a program on the current stack
+    fn evaluate(source: &str, name: &str) -> Result<RichTerm> {
+        let mut program = Self::parse_program(source, name)?;
+        Self::evaluate_program(&mut program, name)
+    }
+
+    /// Fully evaluate a parsed program on the current stack
+    fn evaluate_program(program: &mut Program<Metered>, name: &str) -> Result<RichTerm> {
+        // API change in 0.9.1: eval_full takes no arguments
+        telemetry::phase(Phase::Evaluate, || {
+            program.eval_full().map_err(|e| {
+                let msg = format!("{:?}", e);
+                Error::evaluation_error(name, msg)
+            })
+        })
+    }
+
+    /// Paths of output values that come from contract defaults
+    ///
+    /// A field whose final value has `default` merge priority was never set
+    /// explicitly by the config author. Paths use [`json::key_path`] syntax
+    /// and are returned in output order.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if parsing or evaluation fails
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bunsenite::NickelLoader;
+    ///
+    /// let source = "{ port | default = 80, host = \"example.com\" }";
+    /// let defaults = NickelLoader::new().default_paths(source, "config.ncl").unwrap();
+    /// assert_eq!(defaults, vec!["port".to_string()]);
+    /// ```
+    pub fn default_paths(&self, source: &str, name: &str) -> Result<Vec<String>> {
+        self.inspect_fields(source, name, |path, field, paths| {
+            if field.metadata.priority == MergePriority::Bottom {
+                paths.push(path.to_string());
+                false
+            } else {
+                true
+            }
+        })
+    }
+
+    /// Documentation attached to output fields, as `(path, doc)` pairs
+    ///
+    /// Paths use [`json::key_path`] syntax and are returned in output order,
+    /// parents before their children.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if parsing or evaluation fails
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bunsenite::NickelLoader;
+    ///
+    /// let source = "{ port | doc \"Listen port\" = 80 }";
+    /// let docs = NickelLoader::new().field_docs(source, "config.ncl").unwrap();
+    /// assert_eq!(docs, vec![("port".to_string(), "Listen port".to_string())]);
+    /// ```
+    pub fn field_docs(&self, source: &str, name: &str) -> Result<Vec<(String, String)>> {
+        self.inspect_fields(source, name, |path, field, docs| {
+            if let Some(doc) = &field.metadata.doc {
+                docs.push((path.to_string(), doc.to_string()));
+            }
+            true
+        })
+    }
+
+    /// Documentation metadata of every output field: its doc string, type
+    /// and contract annotations, whether it is `optional` and the default
+    /// it falls back to
+    ///
+    /// Fields are returned in output order, parents before their children,
+    /// including optional fields the config leaves unset. A default is only
+    /// reported for a field whose value still comes from it. This backs
+    /// `bunsenite doc`; see [`docs`](crate::docs) for rendering.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if parsing or evaluation fails
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bunsenite::NickelLoader;
+    ///
+    /// let source = "{ port | Number | doc \"Listen port\" | default = 80 }";
+    /// let fields = NickelLoader::new().extract_metadata(source, "config.ncl").unwrap();
+    /// assert_eq!(fields[0].path, "port");
+    /// assert_eq!(fields[0].doc.as_deref(), Some("Listen port"));
+    /// assert_eq!(fields[0].contracts, vec!["Number".to_string()]);
+    /// assert_eq!(fields[0].default, Some(serde_json::json!(80)));
+    /// ```
+    pub fn extract_metadata(&self, source: &str, name: &str) -> Result<Vec<FieldDoc>> {
+        let source = self.prepare(source, name)?;
+        let source = source.as_ref();
+        self.on_eval_stack(source, name, |_, source, name| {
+            let mut program = Self::parse_program(source, name)?;
+            let term = Self::evaluate_program(&mut program, name)?;
+            let mut fields = Vec::new();
+            walk_metadata(
+                &mut program,
+                &term,
+                String::new(),
+                Some(Vec::new()),
+                &mut fields,
+            );
+            Ok(fields)
+        })
+    }
+
+    /// The order output fields are defined in, for writing records in
+    /// source order rather than name order
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if parsing or evaluation fails
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bunsenite::NickelLoader;
+    ///
+    /// let source = "{ name = \"web\", image = \"web:1.2\" }";
+    /// let order = NickelLoader::new().field_order(source, "config.ncl").unwrap();
+    /// assert_eq!(order.rank("", "name"), Some(0));
+    /// assert_eq!(order.rank("", "image"), Some(1));
+    /// ```
+    pub fn field_order(&self, source: &str, name: &str) -> Result<FieldOrder> {
+        let source = self.prepare(source, name)?;
+        let source = source.as_ref();
+        self.on_eval_stack(source, name, |_, source, name| {
+            let term = Self::evaluate(source, name)?;
+            Ok(walk_records(&term, String::new(), FieldOrder::new()))
+        })
+    }
+
+    /// Evaluate a configuration, also returning the contracts annotating
+    /// its output fields
+    ///
+    /// Contracts are `(path, contract)` pairs, with paths in
+    /// [`json::key_path`] syntax and each contract as written in its
+    /// annotation (`port | Output.Quoted` gives `Output.Quoted`), in output
+    /// order. The program is evaluated once for both.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if parsing or evaluation fails
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bunsenite::NickelLoader;
+    ///
+    /// let source = "{ port | Number = 80 }";
+    /// let (value, contracts) = NickelLoader::new().parse_with_contracts(source, "config.ncl").unwrap();
+    /// assert_eq!(value["port"], 80);
+    /// assert_eq!(contracts, vec![("port".to_string(), "Number".to_string())]);
+    /// ```
+    pub fn parse_with_contracts(
+        &self,
+        source: &str,
+        name: &str,
+    ) -> Result<(Value, Vec<(String, String)>)> {
+        self.guard_imports(source, name)?;
+        let source = self.prepare(source, name)?;
+        let source = source.as_ref();
+        let span = telemetry::Evaluation::start(source, name);
+        let (value, contracts) = span.in_scope(|| {
+            self.on_eval_stack(source, name, |loader, source, name| {
+                let (value, contracts) = loader.eval_with_contracts(source, name)?;
+                Ok((loader.post_process(value)?, contracts))
+            })
+        })?;
+        span.record_output(&value);
+        Ok((value, contracts))
+    }
+
+    /// As [`parse_with_contracts`](Self::parse_with_contracts), for a file
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or if parsing/evaluation fails
+    pub fn parse_file_with_contracts<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> Result<(Value, Vec<(String, String)>)> {
+        let (source, name) = self.read_file(path.as_ref())?;
+        self.parse_with_contracts(&source, &name)
+    }
+
+    /// Evaluate a program and visit every exported field
+    ///
+    /// `visit` receives each field's path and collects results; returning
+    /// `false` skips the field's children.
+    fn inspect_fields<T: Send + 'static>(
+        &self,
+        source: &str,
+        name: &str,
+        mut visit: impl FnMut(&str, &Field, &mut Vec<T>) -> bool + Send + 'static,
+    ) -> Result<Vec<T>> {
+        let source = self.prepare(source, name)?;
+        let source = source.as_ref();
+        self.on_eval_stack(source, name, move |_, source, name| {
+            let term = Self::evaluate(source, name)?;
+            let mut results = Vec::new();
+            walk_fields(&term, String::new(), &mut |path, field| {
+                visit(path, field, &mut results)
+            });
+            Ok(results)
+        })
+    }
+
     /// Parse and evaluate a Nickel configuration from a file
     ///
     /// # Arguments
@@ -125,19 +925,82 @@ impl NickelLoader {
     /// let result = loader.parse_file("config.ncl");
     /// ```
     pub fn parse_file<P: AsRef<Path>>(&self, path: P) -> Result<Value> {
-        let path = path.as_ref();
-        let source = std::fs::read_to_string(path)?;
-        let name = path
-            .file_name()
-            .and_then(|n| n.to_str())
-            .unwrap_or("unknown.ncl");
+        let (source, name) = self.read_file(path.as_ref())?;
+        self.parse_string(&source, &name)
+    }
 
-        self.parse_string(&source, name)
+    /// Evaluate several configuration files merged into one
+    ///
+    /// The files are combined with Nickel's merge operator, in order
+    /// (`base & overlay & ...`), so an overlay such as `prod.ncl` can refine
+    /// a base: fields set in only one file are kept, records are merged
+    /// recursively, and a field set in two files must either agree or be
+    /// overridden through merge priorities (`| default` in the base,
+    /// `| force` in the overlay).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `paths` is empty, a file cannot be read, or
+    /// evaluation (including a merge conflict) fails
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use bunsenite::NickelLoader;
+    ///
+    /// let config = NickelLoader::new().merge_files(&["base.ncl", "prod.ncl"]);
+    /// ```
+    pub fn merge_files<P: AsRef<Path>>(&self, paths: &[P]) -> Result<Value> {
+        if paths.is_empty() {
+            return Err(Error::invalid_input("Nothing to merge: no files given"));
+        }
+
+        let mut imports = Vec::with_capacity(paths.len());
+        let mut names = Vec::with_capacity(paths.len());
+        for path in paths {
+            let path = path.as_ref();
+            let absolute = crate::paths::to_open(&path.canonicalize()?);
+            imports.push(format!(
+                "(import {})",
+                source::string_literal(&absolute.display().to_string())
+            ));
+            names.push(
+                path.file_name()
+                    .and_then(|n| n.to_str())
+                    .unwrap_or("unknown.ncl")
+                    .to_string(),
+            );
+        }
+        self.parse_string(&imports.join(" & "), &names.join(" & "))
+    }
+
+    /// Evaluate only the value at a field path in a configuration file
+    ///
+    /// `path` is as for [`query::query`](crate::query::query), e.g.
+    /// `server.ports[0]`: record fields are selected before evaluation, so
+    /// siblings of the selected field are never forced.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read, the path is malformed or
+    /// selects nothing, or evaluation fails
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use bunsenite::NickelLoader;
+    ///
+    /// let port = NickelLoader::new().query("config.ncl", "server.ports[0]");
+    /// ```
+    pub fn query<P: AsRef<Path>>(&self, path: P, field: &str) -> Result<Value> {
+        let (source, name) = self.read_file(path.as_ref())?;
+        crate::query::query(self, &source, &name, field)
     }
 
     /// Validate a Nickel configuration without evaluating it
     ///
-    /// This performs parsing and type-checking but does not evaluate the program.
+    /// This only parses the program; use [`NickelLoader::typecheck`] to also
+    /// run Nickel's static typechecker.
     ///
     /// # Arguments
     ///
@@ -158,22 +1021,643 @@ impl NickelLoader {
     /// assert!(loader.validate("{ foo = }", "bad.ncl").is_err());
     /// ```
     pub fn validate(&self, source: &str, name: &str) -> Result<()> {
-        // Just try to create a Program - this performs parsing and type-checking
-        Program::new_from_source(
-            source.to_string(),
-            name.to_string(),
-            InputFormat::Nickel,
-            std::io::sink(),
-        )
-        .map_err(|e| {
-            let msg = format!("{:?}", e);
-            Error::parse_error(name, msg)
+        self.guard_imports(source, name)?;
+        let source = self.prepare(source, name)?;
+        let source = source.as_ref();
+        telemetry::Evaluation::start(source, name).in_scope(|| {
+            self.on_eval_stack(source, name, |_, source, name| {
+                Self::check_source(source, name)
+            })
+        })
+    }
+
+    /// Validate several Nickel files, on up to [`threads`](Self::threads)
+    /// threads
+    ///
+    /// Every file is checked, whether or not others fail. Results come back
+    /// in the order of `paths`. Once [`Shutdown::global`] is requested,
+    /// files not yet started fail with [`Error::Interrupted`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use bunsenite::{batch, NickelLoader};
+    ///
+    /// let files = batch::expand("configs/**/*.ncl")?;
+    /// let results = NickelLoader::new().validate_many(&files);
+    /// let failed = results.iter().filter(|(_, result)| result.is_err()).count();
+    /// # Ok::<(), bunsenite::Error>(())
+    /// ```
+    pub fn validate_many<P: AsRef<Path>>(&self, paths: &[P]) -> Vec<(PathBuf, Result<()>)> {
+        let paths = paths
+            .iter()
+            .map(|path| path.as_ref().to_path_buf())
+            .collect();
+        let shutdown = Shutdown::global();
+        threads::map(self.threads(), paths, |path: PathBuf| {
+            let result = shutdown
+                .check()
+                .and_then(|()| self.read_file(&path))
+                .and_then(|(source, name)| self.validate(&source, &name));
+            (path, result)
+        })
+    }
+
+    /// Statically typecheck a Nickel configuration without evaluating it
+    ///
+    /// Runs Nickel's typechecker over the annotated parts of the program
+    /// (`let x : Number = ..`, `(.. : T)`), which `validate` skips.
+    ///
+    /// # Errors
+    ///
+    /// Returns a parse error if the program does not parse, or
+    /// [`Error::TypeError`] for the first type error; [`Error::location`]
+    /// places it in `source`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bunsenite::NickelLoader;
+    ///
+    /// let loader = NickelLoader::new();
+    /// assert!(loader.typecheck("{ port : Number = 80 }", "ok.ncl").is_ok());
+    /// assert!(loader.typecheck(r#"{ port : Number = "80" }"#, "bad.ncl").is_err());
+    /// ```
+    pub fn typecheck(&self, source: &str, name: &str) -> Result<()> {
+        self.guard_imports(source, name)?;
+        let source = self.prepare(source, name)?;
+        let source = source.as_ref();
+        telemetry::Evaluation::start(source, name).in_scope(|| {
+            self.on_eval_stack(source, name, |_, source, name| {
+                Self::typecheck_source(source, name)
+            })
+        })
+    }
+
+    /// Parse a program on the current stack
+    fn check_source(source: &str, name: &str) -> Result<()> {
+        // Typechecking is a separate pass
+        Self::parse_program(source, name).map(drop)
+    }
+
+    /// Create a Program from source and parse it on the current stack
+    ///
+    /// Creating a Program only reads the source, so it is parsed explicitly
+    /// to report syntax errors as parse errors rather than from evaluation.
+    fn parse_program(source: &str, name: &str) -> Result<Program<Metered>> {
+        telemetry::phase(Phase::Parse, || {
+            // API change in 0.9.1: new_from_source requires trace parameter
+            let mut program = Program::new_from_source(
+                source.as_bytes(),
+                name.to_string(),
+                std::io::sink(), // Trace output (discarded)
+            )
+            .map_err(|e| Error::parse_error(name, format!("{:?}", e)))?;
+            program
+                .parse()
+                .map_err(|e| Error::parse_error(name, format!("{:?}", e)))?;
+            Ok(program)
+        })
+    }
+
+    /// Parse and statically typecheck a program on the current stack
+    fn typecheck_source(source: &str, name: &str) -> Result<()> {
+        let mut program = Self::parse_program(source, name)?;
+
+        telemetry::phase(Phase::Typecheck, || {
+            program.typecheck().map_err(|e| {
+                let msg = format!("{:?}", e);
+                Error::type_error(name, msg)
+            })
+        })
+    }
+
+    /// Time a single pipeline phase (see [`crate::bench`])
+    ///
+    /// Inputs to the phase are prepared outside the timed region.
+    pub(crate) fn time_phase(&self, phase: Phase, source: &str, name: &str) -> Result<Duration> {
+        self.on_eval_stack(source, name, move |_, source, name| match phase {
+            Phase::Parse => {
+                let start = Instant::now();
+                Self::check_source(source, name)?;
+                Ok(start.elapsed())
+            }
+            Phase::Typecheck => {
+                let start = Instant::now();
+                Self::typecheck_source(source, name)?;
+                Ok(start.elapsed())
+            }
+            Phase::Evaluate => {
+                let start = Instant::now();
+                let term = Self::evaluate(source, name)?;
+                let elapsed = start.elapsed();
+                drop(term);
+                Ok(elapsed)
+            }
+            Phase::Serialize => {
+                let term = Self::evaluate(source, name)?;
+                let start = Instant::now();
+                let json = Document::from_serialize(&term)?.to_json_string(false);
+                let elapsed = start.elapsed();
+                drop(json);
+                Ok(elapsed)
+            }
+        })
+    }
+
+    /// Run `f` on `source` and `name`, within the configured limits, on a
+    /// dedicated stack segment of the configured size
+    ///
+    /// Everything that recurses on program depth (parsing, evaluation,
+    /// conversion to JSON and dropping the evaluated term) must happen inside
+    /// `f`. The returned JSON is still deep, so callers should serialize it
+    /// with [`crate::json`] rather than `serde_json`. `f` gets the loader to
+    /// use, since with a timeout or memory limit it runs on a thread of its
+    /// own, with copies of the loader and its arguments.
+    fn on_eval_stack<T: Send + 'static>(
+        &self,
+        source: &str,
+        name: &str,
+        f: impl FnOnce(&Self, &str, &str) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        if let Some(levels) = self.max_depth {
+            if source_depth(source) > levels {
+                return Err(depth_exceeded(levels));
+            }
+        }
+        let stack = self.stack_for(source)?;
+        if self.timeout.is_none() && self.max_memory.is_none() {
+            return on_stack(stack, || f(self, source, name));
+        }
+        if self.max_memory.is_some() && limits::resident_bytes().is_none() {
+            return Err(Error::invalid_input(
+                "Memory limits are only supported on Linux",
+            ));
+        }
+
+        let loader = self.clone();
+        let (source, name) = (source.to_string(), name.to_string());
+        let evaluate =
+            telemetry::in_current_span(move || on_stack(stack, || f(&loader, &source, &name)));
+        let (sender, receiver) = mpsc::channel();
+        let evaluation = std::thread::Builder::new()
+            .name("bunsenite-eval".to_string())
+            .spawn(move || {
+                // The receiver is gone if the evaluation was abandoned
+                let _ = sender.send(evaluate());
+            })?;
+        let start = Instant::now();
+        loop {
+            if let Some(bytes) = self.max_memory {
+                if limits::resident_bytes().is_some_and(|resident| resident > bytes) {
+                    return Err(Error::resource_limit("memory", limits::format_size(bytes)));
+                }
+            }
+            let wait = match self.timeout {
+                Some(timeout) => {
+                    let left = timeout.saturating_sub(start.elapsed());
+                    if left.is_zero() {
+                        return Err(Error::resource_limit("time", format_timeout(timeout)));
+                    }
+                    left.min(MEMORY_POLL)
+                }
+                None => MEMORY_POLL,
+            };
+            match receiver.recv_timeout(wait) {
+                Ok(result) => return result,
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => match evaluation.join() {
+                    Err(panic) => std::panic::resume_unwind(panic),
+                    Ok(()) => return Err(Error::internal("Evaluation thread ended early")),
+                },
+            }
+        }
+    }
+
+    /// The stack segment to evaluate `source` on: the configured size, or
+    /// enough for its nesting
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::ResourceLimit`] if `source` is nested deeper than
+    /// [`MAX_STACK_SIZE`] holds
+    fn stack_for(&self, source: &str) -> Result<usize> {
+        // Each level takes two bytes or more, so most sources need no lexing
+        if self.stack_size == 0 || source.len() / 2 <= self.stack_size / STACK_PER_LEVEL {
+            return Ok(self.stack_size);
+        }
+        let needed = source_depth(source).saturating_mul(STACK_PER_LEVEL);
+        if needed > MAX_STACK_SIZE {
+            return Err(depth_exceeded(MAX_STACK_SIZE / STACK_PER_LEVEL));
+        }
+        Ok(self.stack_size.max(needed))
+    }
+}
+
+/// Run `f` on a dedicated stack segment of `size` bytes, or on the caller's
+/// stack if `size` is 0
+fn on_stack<T>(size: usize, f: impl FnOnce() -> T) -> T {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        if size > 0 {
+            return stacker::grow(size, f);
+        }
+    }
+    f()
+}
+
+/// How often resident memory is sampled under a memory limit
+const MEMORY_POLL: Duration = Duration::from_millis(50);
+
+/// The error for a program nested more than `levels` deep
+fn depth_exceeded(levels: usize) -> Error {
+    Error::resource_limit("nesting depth", format!("{} levels", levels))
+}
+
+/// Deepest nesting of brackets, braces and parentheses in `source`
+///
+/// Strings and comments do not count. A source that does not lex counts
+/// as flat, leaving the parser to report it.
+pub(crate) fn source_depth(source: &str) -> usize {
+    let Ok(tokens) = crate::lexer::tokens(source) else {
+        return 0;
+    };
+    let (mut depth, mut max) = (0usize, 0usize);
+    for token in tokens {
+        match token {
+            Token::Punct('{' | '[' | '(') => {
+                depth += 1;
+                max = max.max(depth);
+            }
+            Token::Punct('}' | ']' | ')') => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    max
+}
+
+/// `timeout` as a limit, in whole seconds where it is some
+pub(crate) fn format_timeout(timeout: Duration) -> String {
+    if timeout.subsec_nanos() == 0 {
+        format!("{} s", timeout.as_secs())
+    } else {
+        format!("{} ms", timeout.as_millis())
+    }
+}
+
+/// Functions a thread keeps parsed before it starts afresh
+const MAX_FUNCTIONS: usize = 1024;
+
+thread_local! {
+    static FUNCTIONS: RefCell<Option<Functions>> = const { RefCell::new(None) };
+}
+
+/// A thread's copy of the prepared standard library, with the functions
+/// parsed on the thread
+///
+/// Nickel's terms cannot leave the thread that made them, so each thread
+/// keeps its own.
+struct Functions {
+    cache: ImportCache,
+    types: typecheck::Context,
+    /// Each function's source, parsed, typechecked and transformed
+    parsed: HashMap<String, RichTerm>,
+    /// Sources added, including those that failed to parse
+    added: usize,
+}
+
+impl Functions {
+    fn new() -> Result<Self> {
+        let mut cache = ImportCache::new(ErrorTolerance::Strict);
+        let envs = cache
+            .prepare_stdlib(&mut Metered::new())
+            .map_err(|e| Error::internal(format!("Cannot load the standard library: {:?}", e)))?;
+        Ok(Self {
+            cache,
+            types: envs.type_ctxt,
+            parsed: HashMap::new(),
+            added: 0,
+        })
+    }
+
+    /// Function `source`, parsed, typechecked and transformed the first
+    /// time it is asked for
+    fn prepare(&mut self, source: &str, name: &str) -> Result<RichTerm> {
+        if let Some(function) = self.parsed.get(source) {
+            return Ok(function.clone());
+        }
+        if self.added >= MAX_FUNCTIONS {
+            *self = Self::new()?;
+        }
+        self.added += 1;
+        let id = self
+            .cache
+            .add_string(SourcePath::Path(name.into()), source.to_string());
+        self.cache.prepare(id, &self.types).map_err(|e| match e {
+            nickel_lang_core::error::Error::TypecheckError(e) => {
+                Error::type_error(name, format!("{:?}", e))
+            }
+            e => Error::parse_error(name, format!("{:?}", e)),
         })?;
+        let function = self
+            .cache
+            .get_owned(id)
+            .ok_or_else(|| Error::internal("Prepared function is missing"))?;
+        self.parsed.insert(source.to_string(), function.clone());
+        Ok(function)
+    }
 
-        Ok(())
+    /// Run `f` on this thread's functions, loading the standard library the
+    /// first time
+    fn with<T>(f: impl FnOnce(&mut Functions) -> Result<T>) -> Result<T> {
+        FUNCTIONS.with(|functions| {
+            let mut functions = functions.borrow_mut();
+            let functions = match &mut *functions {
+                Some(functions) => functions,
+                None => functions.insert(Functions::new()?),
+            };
+            f(functions)
+        })
     }
 }
 
+/// Parse and check the Nickel function in `source`, keeping it parsed for
+/// [`apply_function`] on this thread
+///
+/// # Errors
+///
+/// Returns a parse or type error for `source`
+pub(crate) fn prepare_function(source: &str, name: &str) -> Result<()> {
+    on_stack(DEFAULT_STACK_SIZE, || {
+        Functions::with(|functions| functions.prepare(source, name).map(drop))
+    })
+}
+
+/// Apply the Nickel function in `source` to `argument`, evaluating within
+/// `budget`
+///
+/// The function is parsed the first time it is applied on a thread (see
+/// [`prepare_function`]); each application evaluates afresh, sharing
+/// nothing with others but the parsed function and standard library.
+///
+/// # Errors
+///
+/// As [`prepare_function`], a resource-limit error if the evaluation runs
+/// over `budget` (see [`meter::metered`]), and the evaluation error if it
+/// fails
+pub(crate) fn apply_function(
+    source: &str,
+    name: &str,
+    argument: &Value,
+    budget: Budget,
+) -> Result<Value> {
+    on_stack(DEFAULT_STACK_SIZE, || {
+        let (cache, function) = Functions::with(|functions| {
+            let function = functions.prepare(source, name)?;
+            Ok((functions.cache.clone(), function))
+        })?;
+        let argument = RichTerm::deserialize(argument)
+            .map_err(|e| Error::invalid_input(format!("Cannot pass {} to Nickel: {}", name, e)))?;
+        let mut eval_cache = Metered::new();
+        let env = cache
+            .mk_eval_env(&mut eval_cache)
+            .map_err(|_| Error::internal("Standard library is not loaded"))?;
+        let mut vm = VirtualMachine::new_with_cache(cache, eval_cache, std::io::sink())
+            .with_initial_env(env);
+
+        meter::metered(budget, || {
+            let result = vm
+                .eval_full(mk_app!(function, argument))
+                .map_err(|e| Error::evaluation_error(name, format!("{:?}", e)))?;
+            serde_json::to_value(&result).map_err(|e| {
+                Error::serialization_error(format!("Failed to convert to JSON: {}", e))
+            })
+        })
+    })
+}
+
+/// Something an evaluation reports without failing
+///
+/// Passed to the handler set with [`NickelLoader::with_notices`]; the
+/// loader itself prints nothing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Notice {
+    /// An import the [`ImportGuard`] warns about
+    Import(Finding),
+    /// The files an import prefetch read
+    Prefetched(ImportGraph),
+}
+
+impl std::fmt::Display for Notice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Notice::Import(finding) => write!(f, "{}", finding),
+            Notice::Prefetched(graph) => write!(
+                f,
+                "Prefetched {} file(s), {} bytes ({} unreadable)",
+                graph.files.len(),
+                graph.bytes,
+                graph.missing.len()
+            ),
+        }
+    }
+}
+
+/// A handler registered with [`NickelLoader::with_notices`]
+#[derive(Clone)]
+struct NoticeHandler(Arc<dyn Fn(&Notice) + Send + Sync>);
+
+impl std::fmt::Debug for NoticeHandler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NoticeHandler").finish_non_exhaustive()
+    }
+}
+
+/// A serializer registered with [`NickelLoader::with_serializer_for`]
+#[derive(Clone)]
+struct ContractSerializer {
+    contract: String,
+    serialize: Arc<dyn Fn(&Value) -> String + Send + Sync>,
+}
+
+impl std::fmt::Debug for ContractSerializer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ContractSerializer")
+            .field("contract", &self.contract)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Visit exported record fields in output order, parents first
+///
+/// `visit` returns whether to descend into the field's value.
+fn walk_fields(term: &RichTerm, path: String, visit: &mut impl FnMut(&str, &Field) -> bool) {
+    match term.as_ref() {
+        Term::Record(data) => {
+            let mut fields: Vec<_> = data
+                .fields
+                .iter()
+                .filter(|(_, field)| !field.metadata.not_exported)
+                .collect();
+            fields.sort_by(|(a, _), (b, _)| a.label().cmp(b.label()));
+
+            for (id, field) in fields {
+                let Some(value) = &field.value else { continue };
+                let field_path = json::key_path(&path, id.label());
+                if visit(&field_path, field) {
+                    walk_fields(value, field_path, visit);
+                }
+            }
+        }
+        Term::Array(items, _) => {
+            for (index, item) in items.iter().enumerate() {
+                walk_fields(item, json::index_path(&path, index), visit);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Append the documentation metadata of the exported fields below `term`,
+/// parents first, including optional fields without a value
+///
+/// Full evaluation drops optional fields without a value, so the record at
+/// `record_path` is queried from `program` for them. Records in arrays have
+/// no field path, so their unset optional fields are not listed.
+fn walk_metadata(
+    program: &mut Program<Metered>,
+    term: &RichTerm,
+    path: String,
+    record_path: Option<Vec<LocIdent>>,
+    fields: &mut Vec<FieldDoc>,
+) {
+    match term.as_ref() {
+        Term::Record(data) => {
+            let mut entries: Vec<(LocIdent, Field)> = data
+                .fields
+                .iter()
+                .filter(|(_, field)| !field.metadata.not_exported && field.value.is_some())
+                .map(|(id, field)| (*id, field.clone()))
+                .collect();
+            if let Some(record_path) = &record_path {
+                entries.extend(unset_optional_fields(program, record_path));
+            }
+            entries.sort_by(|(a, _), (b, _)| a.label().cmp(b.label()));
+
+            for (id, field) in entries {
+                let metadata = &field.metadata;
+                let field_path = json::key_path(&path, id.label());
+                let default = field
+                    .value
+                    .as_ref()
+                    .filter(|_| metadata.priority == MergePriority::Bottom)
+                    .and_then(|value| serde_json::to_value(value).ok());
+                fields.push(FieldDoc {
+                    path: field_path.clone(),
+                    doc: metadata.doc.clone(),
+                    contracts: metadata
+                        .annotation
+                        .typ
+                        .iter()
+                        .chain(&metadata.annotation.contracts)
+                        .map(|contract| contract.typ.to_string())
+                        .collect(),
+                    optional: metadata.opt,
+                    default,
+                });
+                if let Some(value) = &field.value {
+                    let child = record_path.as_ref().map(|record_path| {
+                        let mut child = record_path.clone();
+                        child.push(id);
+                        child
+                    });
+                    walk_metadata(program, value, field_path, child, fields);
+                }
+            }
+        }
+        Term::Array(items, _) => {
+            for (index, item) in items.iter().enumerate() {
+                walk_metadata(program, item, json::index_path(&path, index), None, fields);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Exported optional fields without a value of the record at `path`
+///
+/// Querying a field evaluates it only to its outermost record, with its
+/// contracts applied, so fields that full evaluation drops are still there.
+fn unset_optional_fields(
+    program: &mut Program<Metered>,
+    path: &[LocIdent],
+) -> Vec<(LocIdent, Field)> {
+    program.field = FieldPath(path.to_vec());
+    let queried = program.query();
+    program.field = FieldPath::new();
+
+    let Ok(Field {
+        value: Some(value), ..
+    }) = queried
+    else {
+        return Vec::new();
+    };
+    match value.as_ref() {
+        Term::Record(data) => data
+            .fields
+            .iter()
+            .filter(|(_, field)| {
+                field.value.is_none() && field.metadata.opt && !field.metadata.not_exported
+            })
+            .map(|(id, field)| (*id, field.clone()))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// `order` with the source order of the fields of every exported record
+/// below `term`
+fn walk_records(term: &RichTerm, path: String, order: FieldOrder) -> FieldOrder {
+    match term.as_ref() {
+        Term::Record(data) => {
+            let mut fields: Vec<_> = data
+                .fields
+                .iter()
+                .filter(|(_, field)| !field.metadata.not_exported)
+                .filter_map(|(id, field)| Some((id, field.value.as_ref()?)))
+                .collect();
+            // By position within the file; fields without one last, by name
+            fields.sort_by_key(|(id, _)| {
+                let position = id.pos.into_opt().map(|span| (span.src_id, span.start.0));
+                (position.is_none(), position, id.label().to_string())
+            });
+
+            let order = order.with_record(path.clone(), fields.iter().map(|(id, _)| id.label()));
+            fields.into_iter().fold(order, |order, (id, value)| {
+                walk_records(value, json::key_path(&path, id.label()), order)
+            })
+        }
+        Term::Array(items, _) => items
+            .iter()
+            .enumerate()
+            .fold(order, |order, (index, item)| {
+                walk_records(item, json::index_path(&path, index), order)
+            }),
+        _ => order,
+    }
+}
+
+/// Read a source file, returning its contents and the name used in diagnostics
+///
+/// The name is the path as given, since Nickel resolves relative imports
+/// against its directory part.
+pub(crate) fn read_source(path: &Path) -> Result<(String, String)> {
+    let source = std::fs::read_to_string(path)?;
+    let name = path.to_string_lossy().into_owned();
+
+    Ok((source, name))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -228,6 +1712,35 @@ mod tests {
         assert!(loader.validate(source, "bad.ncl").is_err());
     }
 
+    #[test]
+    fn test_validate_many() {
+        let dir = tempfile::tempdir().unwrap();
+        let good = dir.path().join("good.ncl");
+        let bad = dir.path().join("bad.ncl");
+        std::fs::write(&good, "{ foo = 42 }").unwrap();
+        std::fs::write(&bad, "{ foo = }").unwrap();
+
+        let loader = NickelLoader::new().with_threads(2);
+        let results = loader.validate_many(&[&bad, &good, &dir.path().join("gone.ncl")]);
+        let paths: Vec<_> = results.iter().map(|(path, _)| path.clone()).collect();
+        assert_eq!(paths, [bad, good, dir.path().join("gone.ncl")]);
+        assert!(results[0].1.is_err());
+        assert!(results[1].1.is_ok());
+        assert!(results[2].1.is_err());
+    }
+
+    #[test]
+    fn test_typecheck() {
+        let loader = NickelLoader::new();
+        let mistyped = r#"{ port : Number = "80" }"#;
+        assert!(loader.validate(mistyped, "app.ncl").is_ok());
+        let error = loader.typecheck(mistyped, "app.ncl").unwrap_err();
+        assert!(matches!(error, Error::TypeError { .. }));
+        assert!(loader
+            .typecheck("{ port : Number = 80 }", "app.ncl")
+            .is_ok());
+    }
+
     #[test]
     fn test_verbose_mode() {
         let loader = NickelLoader::new().with_verbose(true);
@@ -240,6 +1753,308 @@ mod tests {
         assert_eq!(loader.verbose, false);
     }
 
+    #[test]
+    fn test_stack_size_builder() {
+        assert_eq!(NickelLoader::new().stack_size, DEFAULT_STACK_SIZE);
+        assert_eq!(NickelLoader::new().with_stack_size(0).stack_size, 0);
+    }
+
+    #[test]
+    fn test_compat_rewrites_before_evaluation() {
+        let source = r#"{ n = std.string.to_num "42" }"#;
+        let result = NickelLoader::new()
+            .with_compat(true)
+            .parse_string(source, "test.ncl")
+            .unwrap();
+        assert_eq!(result["n"], 42);
+    }
+
+    #[test]
+    fn test_mask_values_keeps_values_out_of_errors() {
+        let source = r#"{ password | Number = "hunter2" }"#;
+        let error = NickelLoader::new()
+            .parse_string(source, "secret.ncl")
+            .unwrap_err();
+        assert!(error.to_string().contains("hunter2"));
+
+        let error = NickelLoader::new()
+            .with_mask_values(true)
+            .parse_string(source, "secret.ncl")
+            .unwrap_err();
+        assert!(!error.to_string().contains("hunter2"));
+        assert!(error.to_string().contains("<string>"));
+    }
+
+    #[test]
+    fn test_default_paths() {
+        let source = r#"
+            let Server = { port | default = 80, host | default = "localhost" } in
+            { server | Server = { host = "example.com" }, replicas | default = 1 }
+        "#;
+        let paths = NickelLoader::new()
+            .default_paths(source, "test.ncl")
+            .unwrap();
+        assert_eq!(paths, vec!["replicas", "server.port"]);
+    }
+
+    #[test]
+    fn test_serializers_replace_annotated_values() {
+        let loader = NickelLoader::new()
+            .with_serializer_for("Dsn", |value| {
+                format!("{}:{}", value["host"].as_str().unwrap(), value["port"])
+            })
+            .with_serializer_for("Upper", |value| value.as_str().unwrap().to_uppercase());
+        let source = r#"
+            let lib = { Dsn = Dyn, Upper = String } in
+            {
+              primary | lib.Dsn = { host | lib.Upper = "a", port = 1 },
+              replicas = [{ db | lib.Dsn = { host = "b", port = 2 } }],
+              name | lib.Upper = "web",
+            }
+        "#;
+        let value = loader.parse_string(source, "test.ncl").unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "primary": "a:1",
+                "replicas": [{ "db": "b:2" }],
+                "name": "WEB",
+            })
+        );
+        let document = loader.parse_document(source, "test.ncl").unwrap();
+        assert_eq!(document.to_value(), value);
+    }
+
+    #[test]
+    fn test_field_order_follows_source() {
+        let source = r#"
+            let Server = { port | default = 80, host | default = "localhost" } in
+            { name = "web", servers = [{ tls = false, host = "a" }], server | Server = {} }
+        "#;
+        let order = NickelLoader::new().field_order(source, "test.ncl").unwrap();
+        let value = NickelLoader::new()
+            .parse_string(source, "test.ncl")
+            .unwrap();
+        assert_eq!(
+            serde_json::to_string(&order.apply(&value)).unwrap(),
+            r#"{"name":"web","servers":[{"tls":false,"host":"a"}],"server":{"port":80,"host":"localhost"}}"#
+        );
+    }
+
+    #[test]
+    fn test_extract_metadata_includes_unset_optional_fields() {
+        let source = r#"
+            let Db = {
+                host | String | doc "Database host",
+                port | Number | default = 5432,
+                replica | String | optional,
+            } in
+            { db | Db = { host = "db.internal" } }
+        "#;
+        let fields = NickelLoader::new()
+            .extract_metadata(source, "test.ncl")
+            .unwrap();
+        let paths: Vec<&str> = fields.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, ["db", "db.host", "db.port", "db.replica"]);
+        assert_eq!(fields[0].contracts, vec!["Db".to_string()]);
+        assert_eq!(fields[1].doc.as_deref(), Some("Database host"));
+        assert_eq!(fields[2].default, Some(serde_json::json!(5432)));
+        assert!(fields[3].optional);
+        assert_eq!(fields[3].default, None);
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let loader = NickelLoader::new().with_max_depth(3);
+        assert!(loader.parse_string("{ a = { b = [1] } }", "ok.ncl").is_ok());
+
+        let error = loader
+            .parse_string("{ a = { b = { c = [1] } } }", "deep.ncl")
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Resource limit exceeded: nesting depth (3 levels)"
+        );
+        // Depth built up by evaluation is caught on the result
+        let source = "let wrap = fun x => { inner = x } in wrap (wrap (wrap (wrap 1)))";
+        assert!(matches!(
+            loader.parse_string(source, "built.ncl"),
+            Err(Error::ResourceLimit { .. })
+        ));
+        // Brackets in strings and comments do not count
+        assert_eq!(source_depth("{ a = \"{{{{\" } # [[[["), 1);
+    }
+
+    #[test]
+    fn test_timeout_and_memory() {
+        let source = "{ ports = std.array.map (fun p => p + 8000) [80, 443] }";
+        let loader = NickelLoader::new().with_timeout(Duration::from_secs(60));
+        assert_eq!(
+            loader.parse_string(source, "ports.ncl").unwrap(),
+            serde_json::json!({ "ports": [8080, 8443] })
+        );
+
+        let error = NickelLoader::new()
+            .with_timeout(Duration::ZERO)
+            .parse_string(source, "ports.ncl")
+            .unwrap_err();
+        assert_eq!(error.to_string(), "Resource limit exceeded: time (0 s)");
+
+        let result = NickelLoader::new()
+            .with_max_memory(1)
+            .parse_string(source, "ports.ncl");
+        if cfg!(target_os = "linux") {
+            assert!(matches!(result, Err(Error::ResourceLimit { .. })));
+        } else {
+            assert!(matches!(result, Err(Error::InvalidInput(_))));
+        }
+    }
+
+    #[test]
+    fn test_parse_nested_record_on_default_stack() {
+        // The depth DEFAULT_STACK_SIZE documents for unoptimized builds
+        const DEPTH: usize = 5_000;
+        let source = format!("{}42{}", "{ a = ".repeat(DEPTH), " }".repeat(DEPTH));
+
+        let result = NickelLoader::new()
+            .parse_string(&source, "deep.ncl")
+            .unwrap();
+        assert_eq!(crate::json::depth(&result), DEPTH);
+        crate::json::drop_deep(result);
+    }
+
+    #[test]
+    fn test_parse_deeply_nested_record() {
+        // Deeper than DEFAULT_STACK_SIZE holds in unoptimized builds
+        const DEPTH: usize = 10_000;
+        let source = format!("{}42{}", "{ a = ".repeat(DEPTH), " }".repeat(DEPTH));
+
+        let result = NickelLoader::new()
+            .parse_string(&source, "deep.ncl")
+            .unwrap();
+        assert_eq!(crate::json::depth(&result), DEPTH);
+        crate::json::drop_deep(result);
+    }
+
+    #[test]
+    fn test_parse_100k_nested_array() {
+        const DEPTH: usize = 100_000;
+        let source = format!("{}42{}", "[".repeat(DEPTH), "]".repeat(DEPTH));
+
+        let result = NickelLoader::new().parse_string(&source, "deep.ncl");
+        if DEPTH * STACK_PER_LEVEL <= MAX_STACK_SIZE {
+            let value = result.unwrap();
+            assert_eq!(crate::json::depth(&value), DEPTH);
+            crate::json::drop_deep(value);
+        } else {
+            // Unoptimized frames need more than MAX_STACK_SIZE
+            assert_eq!(
+                result.unwrap_err().to_string(),
+                format!(
+                    "Resource limit exceeded: nesting depth ({} levels)",
+                    MAX_STACK_SIZE / STACK_PER_LEVEL
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_document_matches_parse_string() {
+        let loader = NickelLoader::new();
+        let source = r#"{ items = std.array.map (fun i => { id = i, tag = "x" }) [1, 2, 3] }"#;
+
+        let value = loader.parse_string(source, "items.ncl").unwrap();
+        let doc = loader.parse_document(source, "items.ncl").unwrap();
+        assert_eq!(doc.to_value(), value);
+        assert_eq!(doc.strings().len(), 4);
+    }
+
+    #[test]
+    fn test_notices_go_to_the_handler() {
+        let dir = tempfile::tempdir().unwrap();
+        let project = dir.path().join("project");
+        std::fs::create_dir(&project).unwrap();
+        std::fs::write(dir.path().join("shared.ncl"), "{ x = 1 }").unwrap();
+        let app = project.join("app.ncl");
+        std::fs::write(&app, r#"(import "../shared.ncl") & { y = 2 }"#).unwrap();
+
+        let notices = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = Arc::clone(&notices);
+        let guard = ImportGuard::new(&project)
+            .unwrap()
+            .with_escapes(crate::guard::Action::Warn);
+        NickelLoader::new()
+            .with_threads(1)
+            .with_prefetch_imports(true)
+            .with_import_guard(guard)
+            .with_notices(move |notice| seen.lock().expect("notices poisoned").push(notice.clone()))
+            .parse_file(&app)
+            .unwrap();
+
+        let notices = notices.lock().expect("notices poisoned");
+        assert!(matches!(&notices[0], Notice::Prefetched(graph) if graph.files.len() == 2));
+        assert!(
+            matches!(&notices[1], Notice::Import(finding) if finding.path.ends_with("shared.ncl"))
+        );
+        assert_eq!(notices.len(), 2);
+    }
+
+    #[test]
+    fn test_audit_log_records_imports_and_transforms() {
+        let dir = tempfile::tempdir().unwrap();
+        let lib = dir.path().join("lib.ncl");
+        std::fs::write(&lib, "{ x = 1 }").unwrap();
+        let log = dir.path().join("audit.jsonl");
+        let source = format!(
+            "(import {}) & {{ y = 2 }}",
+            crate::source::string_literal(lib.to_str().unwrap())
+        );
+
+        NickelLoader::new()
+            .with_threads(1)
+            .with_audit_log(AuditLog::open(&log).unwrap())
+            .with_transform(crate::transform::StripInternal::default())
+            .parse_string(&source, "app.ncl")
+            .unwrap();
+
+        let events: Vec<Value> = std::fs::read_to_string(&log)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let kinds: Vec<&str> = events
+            .iter()
+            .map(|e| e["event"].as_str().unwrap())
+            .collect();
+        assert_eq!(kinds, ["import", "read", "transform"]);
+        assert_eq!(events[0]["found"], true);
+        assert_eq!(events[1]["path"], lib.to_str().unwrap());
+    }
+
+    #[test]
+    fn test_merge_files_applies_overlays_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("base.ncl");
+        let prod = dir.path().join("prod.ncl");
+        std::fs::write(
+            &base,
+            r#"{ replicas | default = 1, db = { host = "localhost", port = 5432 } }"#,
+        )
+        .unwrap();
+        std::fs::write(&prod, r#"{ replicas = 3, db.host | force = "db.prod" }"#).unwrap();
+
+        let loader = NickelLoader::new();
+        let merged = loader.merge_files(&[&base, &prod]).unwrap();
+        assert_eq!(
+            merged,
+            serde_json::json!({ "replicas": 3, "db": { "host": "db.prod", "port": 5432 } })
+        );
+
+        assert!(loader.merge_files::<&Path>(&[]).is_err());
+        let missing = dir.path().join("missing.ncl");
+        assert!(loader.merge_files(&[&base, &missing]).is_err());
+    }
+
     #[test]
     fn test_error_contains_filename() {
         let loader = NickelLoader::new();
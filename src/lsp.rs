@@ -0,0 +1,742 @@
+//! Language server for editors
+//!
+//! Backs `bunsenite lsp`: speaks the Language Server Protocol (JSON-RPC
+//! messages with `Content-Length` headers) on stdin and stdout, so any LSP
+//! client can use it without a plugin of its own.
+//!
+//! - **Diagnostics** when a document is opened or saved: the evaluation
+//!   error, if any, and the findings of the [`Linter`] as warnings. Nickel
+//!   reports errors as text, so an error is placed at the first source span
+//!   it mentions when that span lies within the document, and at its start
+//!   otherwise.
+//! - **Hover** over a field name: its doc string, contracts, `optional`
+//!   flag and default, as read by
+//!   [`NickelLoader::extract_metadata`](crate::NickelLoader::extract_metadata).
+//! - **Go to definition** of the file named by an `import "..."`, and of a
+//!   name: its `let` binding or field definition in the document, or else
+//!   in the files it imports.
+//!
+//! Definitions are found lexically, like [`crate::lint`], so they err
+//! towards what the name looks like rather than what it evaluates to.
+//! Documents are synchronized in full on every change; diagnostics wait for
+//! the save.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::lsp::LanguageServer;
+//! use bunsenite::NickelLoader;
+//!
+//! let body = r#"{"jsonrpc": "2.0", "id": 1, "method": "shutdown"}"#;
+//! let input = format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+//! let mut output = Vec::new();
+//! LanguageServer::new(NickelLoader::new())
+//!     .serve(input.as_bytes(), &mut output)
+//!     .unwrap();
+//! assert!(String::from_utf8(output).unwrap().ends_with(r#""result":null}"#));
+//! ```
+
+use crate::docs::FieldDoc;
+use crate::error::{Error, Result};
+use crate::imports;
+use crate::lint::Linter;
+use crate::loader::NickelLoader;
+use crate::paths;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+/// JSON-RPC error code for a body that is not JSON
+const PARSE_ERROR: i64 = -32700;
+/// JSON-RPC error code for a request after `shutdown`
+const INVALID_REQUEST: i64 = -32600;
+/// JSON-RPC error code for an unsupported method
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// LSP diagnostic severities
+const ERROR: u8 = 1;
+const WARNING: u8 = 2;
+
+/// Fields shown at most in one hover, when a name matches several
+const MAX_HOVER_FIELDS: usize = 5;
+
+/// The language server
+#[derive(Debug, Clone)]
+pub struct LanguageServer {
+    loader: NickelLoader,
+    linter: Linter,
+}
+
+impl LanguageServer {
+    /// Create a server evaluating documents with `loader`, linting with the
+    /// default rules
+    pub fn new(loader: NickelLoader) -> Self {
+        Self {
+            loader,
+            linter: Linter::new(),
+        }
+    }
+
+    /// Lint documents with `linter` instead of the default rules
+    pub fn with_linter(mut self, linter: Linter) -> Self {
+        self.linter = linter;
+        self
+    }
+
+    /// Answer messages from `input` until the client sends `exit` or the
+    /// input ends
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading messages or writing responses fails, or
+    /// if a message's headers are malformed
+    pub fn serve<R: BufRead, W: Write>(&self, mut input: R, mut output: W) -> Result<()> {
+        let mut session = Session {
+            server: self,
+            documents: HashMap::new(),
+            shut_down: false,
+        };
+
+        while let Some(body) = read_message(&mut input)? {
+            let mut replies = Vec::new();
+            let exit = match serde_json::from_str::<Value>(&body) {
+                Ok(message) => session.handle(&message, &mut replies),
+                Err(e) => {
+                    replies.push(error_reply(Value::Null, PARSE_ERROR, &e.to_string()));
+                    false
+                }
+            };
+            for reply in &replies {
+                write_message(&mut output, reply)?;
+            }
+            if exit {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An open document
+#[derive(Debug)]
+struct Document {
+    /// Local path, for `file:` URIs
+    path: Option<PathBuf>,
+    /// Name evaluation reports errors against
+    name: String,
+    text: String,
+    /// Field metadata, read on the first hover after each change
+    metadata: Option<Vec<FieldDoc>>,
+}
+
+impl Document {
+    fn new(uri: &str, text: String) -> Self {
+        let path = uri_to_path(uri);
+        let name = path
+            .as_ref()
+            .map_or_else(|| uri.to_string(), |path| path.display().to_string());
+        Self {
+            path,
+            name,
+            text,
+            metadata: None,
+        }
+    }
+}
+
+/// State of one client connection
+#[derive(Debug)]
+struct Session<'a> {
+    server: &'a LanguageServer,
+    documents: HashMap<String, Document>,
+    shut_down: bool,
+}
+
+impl Session<'_> {
+    /// Handle one message, queueing what to send back in `replies`; returns
+    /// whether the client asked the server to exit
+    fn handle(&mut self, message: &Value, replies: &mut Vec<Value>) -> bool {
+        let Some(method) = message["method"].as_str() else {
+            // A response; the server sends no requests, so none are awaited
+            return false;
+        };
+        let params = &message["params"];
+        let result = match method {
+            "exit" => return true,
+            _ if self.shut_down => Err((INVALID_REQUEST, "Server is shut down".to_string())),
+            "initialize" => Ok(capabilities()),
+            "shutdown" => {
+                self.shut_down = true;
+                Ok(Value::Null)
+            }
+            "textDocument/didOpen"
+            | "textDocument/didChange"
+            | "textDocument/didSave"
+            | "textDocument/didClose" => {
+                self.synchronize(method, params, replies);
+                return false;
+            }
+            "textDocument/hover" => Ok(self.hover(params).unwrap_or(Value::Null)),
+            "textDocument/definition" => Ok(self.definition(params).unwrap_or(Value::Null)),
+            _ => Err((METHOD_NOT_FOUND, format!("Unsupported method: {}", method))),
+        };
+
+        // Notifications (no `id`) get no reply, whatever their method
+        if let Some(id) = message.get("id") {
+            replies.push(match result {
+                Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                Err((code, message)) => error_reply(id.clone(), code, &message),
+            });
+        }
+        false
+    }
+
+    fn synchronize(&mut self, method: &str, params: &Value, replies: &mut Vec<Value>) {
+        let Some(uri) = params["textDocument"]["uri"].as_str() else {
+            return;
+        };
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                let document = Document::new(uri, text.to_string());
+                replies.push(self.diagnostics(uri, &document));
+                self.documents.insert(uri.to_string(), document);
+            }
+            "textDocument/didChange" => {
+                let text = params["contentChanges"]
+                    .as_array()
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change["text"].as_str());
+                if let (Some(document), Some(text)) = (self.documents.get_mut(uri), text) {
+                    document.text = text.to_string();
+                    document.metadata = None;
+                }
+            }
+            "textDocument/didSave" => {
+                let Some(document) = self.documents.get_mut(uri) else {
+                    return;
+                };
+                if let Some(text) = params["text"].as_str() {
+                    document.text = text.to_string();
+                    document.metadata = None;
+                }
+                let document = &self.documents[uri];
+                replies.push(self.diagnostics(uri, document));
+            }
+            _ => {
+                self.documents.remove(uri);
+                replies.push(publish_diagnostics(uri, Vec::new()));
+            }
+        }
+    }
+
+    fn diagnostics(&self, uri: &str, document: &Document) -> Value {
+        let text = &document.text;
+        let mut diagnostics = Vec::new();
+
+        if let Err(e) = self.server.loader.parse_string(text, &document.name) {
+            let message = e.to_string();
+            let (start, end) = e
+                .span()
+                .filter(|&(_, end)| end <= text.len())
+                .unwrap_or((0, 0));
+            diagnostics.push(json!({
+                "range": range(text, start, end),
+                "severity": ERROR,
+                "source": "bunsenite",
+                "message": message,
+            }));
+        }
+        // Lint findings are only advisory, so a source the lexer rejects
+        // is left to the evaluation error above
+        for finding in self.server.linter.lint(text).unwrap_or_default() {
+            let line = finding.line.saturating_sub(1);
+            let start = offset_at(text, line, 0).unwrap_or(text.len());
+            let end = offset_at(text, line, usize::MAX).unwrap_or(text.len());
+            diagnostics.push(json!({
+                "range": range(text, start, end),
+                "severity": WARNING,
+                "source": "bunsenite",
+                "code": finding.rule.name(),
+                "message": finding.message,
+            }));
+        }
+        publish_diagnostics(uri, diagnostics)
+    }
+
+    fn hover(&mut self, params: &Value) -> Option<Value> {
+        let uri = params["textDocument"]["uri"].as_str()?;
+        let document = self.documents.get_mut(uri)?;
+        let offset = position_param(&document.text, params)?;
+        let (start, end) = word_at(&document.text, offset)?;
+
+        if document.metadata.is_none() {
+            let metadata = self
+                .server
+                .loader
+                .extract_metadata(&document.text, &document.name)
+                .unwrap_or_default();
+            document.metadata = Some(metadata);
+        }
+        let fields = document.metadata.as_deref().unwrap_or_default();
+
+        // Prefer fields matching the whole dotted access (`db.port`), then
+        // any field with the name under the cursor
+        let text = &document.text;
+        let chain_start = text[..start]
+            .char_indices()
+            .rev()
+            .take_while(|&(_, c)| is_ident_char(c) || c == '.')
+            .last()
+            .map_or(start, |(i, _)| i);
+        let chain = text[chain_start..end].trim_start_matches('.');
+        let word = &text[start..end];
+        let mut matches: Vec<&FieldDoc> = fields
+            .iter()
+            .filter(|field| path_ends_with(&field.path, chain))
+            .collect();
+        if matches.is_empty() {
+            matches = fields
+                .iter()
+                .filter(|field| path_ends_with(&field.path, word))
+                .collect();
+        }
+
+        let sections: Vec<String> = matches
+            .into_iter()
+            .filter_map(|field| {
+                let body = field.to_markdown();
+                (!body.is_empty()).then(|| format!("`{}`\n{}", field.path, body))
+            })
+            .take(MAX_HOVER_FIELDS)
+            .collect();
+        if sections.is_empty() {
+            return None;
+        }
+        Some(json!({
+            "contents": { "kind": "markdown", "value": sections.join("\n---\n") },
+            "range": range(text, start, end),
+        }))
+    }
+
+    fn definition(&self, params: &Value) -> Option<Value> {
+        let uri = params["textDocument"]["uri"].as_str()?;
+        let document = self.documents.get(uri)?;
+        let text = &document.text;
+        let offset = position_param(text, params)?;
+        let dir = document.path.as_deref().and_then(Path::parent);
+
+        if let Some(import) = import_at(text, offset) {
+            let target = dir?.join(import);
+            return paths::to_open(&target)
+                .is_file()
+                .then(|| location(&target, "", 0, 0));
+        }
+
+        let (start, end) = word_at(text, offset)?;
+        let name = &text[start..end];
+        if let Some((start, end)) = definition_in(text, name) {
+            return Some(json!({ "uri": uri, "range": range(text, start, end) }));
+        }
+        let dir = dir?;
+        imports::scan(text).into_iter().find_map(|import| {
+            let target = dir.join(import);
+            let source = fs::read_to_string(paths::to_open(&target)).ok()?;
+            let (start, end) = definition_in(&source, name)?;
+            Some(location(&target, &source, start, end))
+        })
+    }
+}
+
+/// The server's capabilities, answering `initialize`
+fn capabilities() -> Value {
+    json!({
+        "capabilities": {
+            "textDocumentSync": {
+                "openClose": true,
+                // Full document on every change
+                "change": 1,
+                "save": { "includeText": true },
+            },
+            "hoverProvider": true,
+            "definitionProvider": true,
+        },
+        "serverInfo": { "name": "bunsenite", "version": crate::VERSION },
+    })
+}
+
+fn error_reply(id: Value, code: i64, message: &str) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Value>) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics },
+    })
+}
+
+/// Read one message body, or `None` at the end of the input
+fn read_message<R: BufRead>(input: &mut R) -> Result<Option<String>> {
+    let mut length = None;
+    let mut line = String::new();
+    let length = loop {
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            match length {
+                Some(length) => break length,
+                None => continue,
+            }
+        }
+        if let Some((key, value)) = header.split_once(':') {
+            if key.trim().eq_ignore_ascii_case("content-length") {
+                length = Some(value.trim().parse::<usize>().map_err(|_| {
+                    Error::invalid_input(format!("Invalid LSP header: {}", header))
+                })?);
+            }
+        }
+    };
+
+    let mut body = vec![0u8; length];
+    input.read_exact(&mut body)?;
+    String::from_utf8(body)
+        .map(Some)
+        .map_err(|_| Error::invalid_input("LSP message is not UTF-8"))
+}
+
+fn write_message<W: Write>(output: &mut W, message: &Value) -> Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()?;
+    Ok(())
+}
+
+/// The byte offset of an LSP position (line, UTF-16 column), clamped to
+/// the end of its line; `None` past the last line
+fn offset_at(text: &str, line: usize, character: usize) -> Option<usize> {
+    let mut start = 0;
+    for _ in 0..line {
+        start += text[start..].find('\n')? + 1;
+    }
+    let end = text[start..].find('\n').map_or(text.len(), |i| start + i);
+    let mut units = 0;
+    for (index, c) in text[start..end].char_indices() {
+        if units >= character {
+            return Some(start + index);
+        }
+        units += c.len_utf16();
+    }
+    Some(end)
+}
+
+fn position_param(text: &str, params: &Value) -> Option<usize> {
+    let position = &params["position"];
+    let line = usize::try_from(position["line"].as_u64()?).ok()?;
+    let character = usize::try_from(position["character"].as_u64()?).unwrap_or(usize::MAX);
+    offset_at(text, line, character)
+}
+
+/// The LSP position of a byte offset
+fn position_at(text: &str, offset: usize) -> Value {
+    let mut offset = offset.min(text.len());
+    while !text.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let character: usize = before[line_start..].chars().map(char::len_utf16).sum();
+    json!({ "line": before.matches('\n').count(), "character": character })
+}
+
+fn range(text: &str, start: usize, end: usize) -> Value {
+    json!({ "start": position_at(text, start), "end": position_at(text, end) })
+}
+
+fn location(path: &Path, text: &str, start: usize, end: usize) -> Value {
+    json!({ "uri": path_to_uri(path), "range": range(text, start, end) })
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-' || c == '\''
+}
+
+/// Whether `text[at..at + len]` is a whole identifier
+fn is_word(text: &str, at: usize, len: usize) -> bool {
+    !text[..at].ends_with(is_ident_char) && !text[at + len..].starts_with(is_ident_char)
+}
+
+/// The span of the identifier touching `offset`
+fn word_at(text: &str, offset: usize) -> Option<(usize, usize)> {
+    let start = text[..offset]
+        .char_indices()
+        .rev()
+        .take_while(|&(_, c)| is_ident_char(c))
+        .last()
+        .map_or(offset, |(i, _)| i);
+    let end = text[offset..]
+        .find(|c: char| !is_ident_char(c))
+        .map_or(text.len(), |i| offset + i);
+    let first = text[start..end].chars().next()?;
+    (first.is_alphabetic() || first == '_').then_some((start, end))
+}
+
+/// The path of an `import "..."` on the line at `offset`, if `offset` is
+/// within it
+fn import_at(text: &str, offset: usize) -> Option<&str> {
+    let line_start = text[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = text[offset..].find('\n').map_or(text.len(), |i| offset + i);
+    let line = &text[line_start..line_end];
+    let cursor = offset - line_start;
+
+    for (at, keyword) in line.match_indices("import") {
+        if !is_word(line, at, keyword.len()) {
+            continue;
+        }
+        let Some(path) = line[at + keyword.len()..].trim_start().strip_prefix('"') else {
+            continue;
+        };
+        let Some(close) = path.find('"') else {
+            continue;
+        };
+        let open = line.len() - path.len();
+        if (at..=open + close).contains(&cursor) {
+            return Some(&path[..close]);
+        }
+    }
+    None
+}
+
+/// The span defining `name` in `source`: its `let` binding, or else the
+/// first line-leading field definition (`name =`, `name |`, `name :`)
+fn definition_in(source: &str, name: &str) -> Option<(usize, usize)> {
+    let mut field = None;
+    let mut line_start = 0;
+    for line in source.split_inclusive('\n') {
+        if !line.trim_start().starts_with('#') {
+            for (at, _) in line.match_indices(name) {
+                if !is_word(line, at, name.len()) {
+                    continue;
+                }
+                let span = (line_start + at, line_start + at + name.len());
+                let before = line[..at].trim_end();
+                let after = line[at + name.len()..].trim_start();
+                if binds(before) {
+                    return Some(span);
+                }
+                if field.is_none() && opens_field(before) && defines(after) {
+                    field = Some(span);
+                }
+            }
+        }
+        line_start += line.len();
+    }
+    field
+}
+
+/// Whether a name preceded by `before` is bound by `let` or `let rec`
+fn binds(before: &str) -> bool {
+    let before = before.strip_suffix("rec").map_or(before, str::trim_end);
+    before
+        .strip_suffix("let")
+        .is_some_and(|rest| !rest.ends_with(is_ident_char))
+}
+
+fn opens_field(before: &str) -> bool {
+    before.is_empty() || before.ends_with('{') || before.ends_with(',')
+}
+
+fn defines(after: &str) -> bool {
+    (after.starts_with('=') && !after.starts_with("==") && !after.starts_with("=>"))
+        || (after.starts_with('|') && !after.starts_with("|>") && !after.starts_with("||"))
+        || after.starts_with(':')
+}
+
+fn path_ends_with(path: &str, suffix: &str) -> bool {
+    !suffix.is_empty()
+        && path
+            .strip_suffix(suffix)
+            .is_some_and(|rest| rest.is_empty() || rest.ends_with('.'))
+}
+
+/// The local path of a `file:` URI
+fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?;
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut rest = path.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = match (byte, tail.get(..2)) {
+            (b'%', Some(hex)) => std::str::from_utf8(hex)
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+            _ => None,
+        };
+        match escaped {
+            Some(decoded) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            None => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    let path = String::from_utf8(bytes).ok()?;
+    // `file:///C:/x` names `C:/x` on Windows
+    let path = match path.strip_prefix('/') {
+        Some(drive) if drive.as_bytes().get(1) == Some(&b':') => drive.to_string(),
+        _ => path,
+    };
+    Some(PathBuf::from(path))
+}
+
+/// The `file:` URI of a local path
+fn path_to_uri(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let mut uri = String::from("file://");
+    if !path.starts_with('/') {
+        uri.push('/');
+    }
+    for byte in path.bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-_.~:".contains(&byte) {
+            uri.push(char::from(byte));
+        } else {
+            uri.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    uri
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(message: Value) -> String {
+        let body = message.to_string();
+        format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+    }
+
+    fn exchange(messages: Vec<Value>) -> Vec<Value> {
+        let input: String = messages.into_iter().map(frame).collect();
+        let mut output = Vec::new();
+        LanguageServer::new(NickelLoader::new())
+            .serve(input.as_bytes(), &mut output)
+            .unwrap();
+        let mut output = output.as_slice();
+        let mut replies = Vec::new();
+        while let Some(body) = read_message(&mut output).unwrap() {
+            replies.push(serde_json::from_str(&body).unwrap());
+        }
+        replies
+    }
+
+    fn open(uri: &str, text: &str) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": { "uri": uri, "languageId": "nickel", "version": 1, "text": text } },
+        })
+    }
+
+    fn at(id: u64, method: &str, uri: &str, line: u64, character: u64) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": {
+                "textDocument": { "uri": uri },
+                "position": { "line": line, "character": character },
+            },
+        })
+    }
+
+    #[test]
+    fn test_diagnostics_on_open_and_close() {
+        let uri = "file:///work/app.ncl";
+        let replies = exchange(vec![
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} }),
+            open(uri, "let unused = 1 in\n{ port = }"),
+            json!({ "jsonrpc": "2.0", "method": "textDocument/didClose", "params": { "textDocument": { "uri": uri } } }),
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "nickel/unknown" }),
+            json!({ "jsonrpc": "2.0", "method": "exit" }),
+            json!({ "jsonrpc": "2.0", "id": 3, "method": "shutdown" }),
+        ]);
+
+        assert_eq!(replies.len(), 4);
+        assert_eq!(replies[0]["result"]["capabilities"]["hoverProvider"], true);
+        let diagnostics = replies[1]["params"]["diagnostics"].as_array().unwrap();
+        assert_eq!(diagnostics[0]["severity"], ERROR);
+        assert_eq!(diagnostics[1]["severity"], WARNING);
+        assert_eq!(diagnostics[1]["code"], "unused-let");
+        assert_eq!(
+            diagnostics[1]["range"]["end"],
+            json!({ "line": 0, "character": 17 })
+        );
+        assert_eq!(replies[2]["params"]["diagnostics"], json!([]));
+        assert_eq!(replies[3]["error"]["code"], METHOD_NOT_FOUND);
+    }
+
+    #[test]
+    fn test_definition_follows_imports() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("lib.ncl"), "{\n  helper = 1,\n}").unwrap();
+        let main = dir.path().join("main.ncl");
+        let uri = path_to_uri(&main);
+        assert_eq!(uri_to_path(&uri).unwrap(), main);
+
+        let text = "let lib = import \"lib.ncl\" in\n{ value = lib.helper, again = lib }";
+        let replies = exchange(vec![
+            open(&uri, text),
+            at(1, "textDocument/definition", &uri, 0, 20),
+            at(2, "textDocument/definition", &uri, 1, 16),
+            at(3, "textDocument/definition", &uri, 1, 32),
+            at(4, "textDocument/definition", &uri, 1, 1),
+        ]);
+
+        let lib = path_to_uri(&dir.path().join("lib.ncl"));
+        assert_eq!(replies[1]["result"]["uri"], lib);
+        assert_eq!(
+            replies[1]["result"]["range"]["start"],
+            json!({ "line": 0, "character": 0 })
+        );
+        assert_eq!(replies[2]["result"]["uri"], lib);
+        assert_eq!(
+            replies[2]["result"]["range"]["start"],
+            json!({ "line": 1, "character": 2 })
+        );
+        assert_eq!(replies[3]["result"]["uri"], uri);
+        assert_eq!(
+            replies[3]["result"]["range"]["start"],
+            json!({ "line": 0, "character": 4 })
+        );
+        assert_eq!(replies[4]["result"], Value::Null);
+    }
+
+    #[test]
+    fn test_hover_shows_field_metadata() {
+        let uri = "file:///work/app.ncl";
+        let text = "{\n  port | Number | doc \"Listen port\" | default = 80,\n  name = \"app\",\n}";
+        let replies = exchange(vec![
+            open(uri, text),
+            at(1, "textDocument/hover", uri, 1, 4),
+            at(2, "textDocument/hover", uri, 2, 3),
+        ]);
+
+        let contents = replies[1]["result"]["contents"]["value"].as_str().unwrap();
+        assert!(contents.starts_with("`port`\n\nListen port\n"));
+        assert!(contents.contains("- Contract: `Number`\n"));
+        assert!(contents.contains("- Default: `80`\n"));
+        assert_eq!(
+            replies[1]["result"]["range"]["start"],
+            json!({ "line": 1, "character": 2 })
+        );
+        assert_eq!(replies[2]["result"], Value::Null);
+    }
+}
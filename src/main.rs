@@ -2,10 +2,117 @@
 //!
 //! Command-line interface for parsing and evaluating Nickel configuration files
 
-use bunsenite::{NickelLoader, VERSION};
-use clap::{Parser, Subcommand};
-use std::path::PathBuf;
+use bunsenite::archive::{self, Bundle};
+use bunsenite::audit::AuditLog;
+use bunsenite::batch;
+use bunsenite::bench::{self, Baseline};
+use bunsenite::cache::{self, Policy as CachePolicy};
+use bunsenite::capabilities;
+use bunsenite::compress::{self, Compression};
+use bunsenite::conformance::{Binding, Corpus, Expected, Outcome, Runner};
+use bunsenite::coverage::Coverage;
+use bunsenite::depfile::Depfile;
+use bunsenite::diagnostic::Diagnostic;
+use bunsenite::doctor;
+use bunsenite::drift::{self, Options as DriftOptions};
+use bunsenite::explain::Explainer;
+use bunsenite::export;
+use bunsenite::exports;
+use bunsenite::fingerprint::{Fingerprint, FreshCache};
+use bunsenite::fmt;
+use bunsenite::guard::{Action as ImportAction, ImportGuard};
+use bunsenite::harden::Sandbox;
+use bunsenite::imports;
+use bunsenite::interrupt::{self, Shutdown};
+use bunsenite::limits::{self, Limits};
+use bunsenite::lint::{self, Linter};
+use bunsenite::loader::Notice;
+#[cfg(feature = "lsp")]
+use bunsenite::lsp::LanguageServer;
+use bunsenite::mask;
+use bunsenite::matrix::Matrix;
+use bunsenite::mutate;
+use bunsenite::oci;
+use bunsenite::output;
+use bunsenite::overrides;
+use bunsenite::owners::Owners;
+use bunsenite::porcelain;
+use bunsenite::profile::{self, Profile};
+use bunsenite::query;
+use bunsenite::restrict::Policy;
+use bunsenite::scaffold::{self, Template};
+use bunsenite::schema::{self, Shape};
+#[cfg(feature = "daemon")]
+use bunsenite::serve::Server;
+#[cfg(feature = "daemon")]
+use bunsenite::session::{self, Recorder};
+use bunsenite::settings;
+use bunsenite::style::Styles;
+use bunsenite::synth::{self, Contract};
+use bunsenite::target::{Provenance, Target};
+use bunsenite::tenant;
+use bunsenite::testing;
+use bunsenite::transform::{self, MaskValues, PathFilter};
+#[cfg(feature = "schemas")]
+use bunsenite::validation::Schema;
+use bunsenite::vendor;
+use bunsenite::watch::Watch;
+use bunsenite::worker::{self, Worker};
+use bunsenite::{
+    compat, diff, docs, json, merge, paths, Document, NickelLoader, BUILD_INFO, RSR_TIER,
+    TPCF_PERIMETER, VERSION, VERSION_INFO,
+};
+use clap::builder::{PossibleValue, PossibleValuesParser, TypedValueParser};
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use std::ffi::OsString;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// Report progress or success on stderr, as `✓ ...`
+macro_rules! status {
+    ($($arg:tt)*) => {
+        report(false, porcelain::Kind::Status, format_args!($($arg)*))
+    };
+}
+
+/// Report a failed check on stderr, as `✗ ...`
+macro_rules! failure {
+    ($($arg:tt)*) => {
+        report(false, porcelain::Kind::Failure, format_args!($($arg)*))
+    };
+}
+
+/// Report a warning on stderr, as `Warning: ...`
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        report(false, porcelain::Kind::Diagnostic, format_args!($($arg)*))
+    };
+}
+
+/// Report progress or other information on stderr
+macro_rules! note {
+    ($($arg:tt)*) => {
+        report(false, porcelain::Kind::Note, format_args!($($arg)*))
+    };
+}
+
+/// Report that a check passed on stdout, as `✓ ...`
+macro_rules! passed {
+    ($($arg:tt)*) => {
+        report(true, porcelain::Kind::Status, format_args!($($arg)*))
+    };
+}
+
+/// Report the summary of a check on stdout
+macro_rules! summary {
+    ($($arg:tt)*) => {
+        report(true, porcelain::Kind::Note, format_args!($($arg)*))
+    };
+}
 
 #[derive(Parser)]
 #[command(
@@ -21,110 +128,3797 @@ struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
 
-    /// Enable verbose output
-    #[arg(short, long, global = true)]
-    verbose: bool,
+    /// Enable verbose output
+    #[arg(short, long, global = true)]
+    verbose: bool,
+
+    /// Fail unless the embedded Nickel version satisfies this requirement (e.g. ">=1.5")
+    #[arg(long, global = true, value_name = "REQ")]
+    require_nickel: Option<String>,
+
+    /// Rewrite deprecated stdlib names to their replacements, with warnings
+    #[arg(long, global = true)]
+    compat: bool,
+
+    /// Worker threads for batch work (default: available cores, at most 4 under CI)
+    #[arg(short, long, global = true, value_name = "N")]
+    jobs: Option<usize>,
+
+    /// Read each file's import tree concurrently before evaluating it
+    #[arg(long, global = true)]
+    prefetch_imports: bool,
+
+    /// Post-process evaluated values (strip-internal[=PREFIX], inject:KEY=VALUE, exec:COMMAND)
+    #[arg(long, global = true, value_name = "SPEC")]
+    transform: Vec<String>,
+
+    /// Expose environment variables starting with PREFIX to configs as `env`, without the prefix (e.g. BUNSEN_PORT as env.PORT)
+    #[arg(long, global = true, value_name = "PREFIX")]
+    env_prefix: Option<String>,
+
+    /// Hermetic evaluation: configs get an empty `env` record and the thread count ignores CI
+    #[arg(long, global = true, conflicts_with = "env_prefix")]
+    no_env: bool,
+
+    /// Share --incremental results through URL (https://..., s3://BUCKET/PREFIX or a directory); defaults to $BUNSENITE_REMOTE_CACHE
+    #[arg(long, global = true, value_name = "URL")]
+    remote_cache: Option<String>,
+
+    /// Append every file read, import, environment variable and transform used to this log
+    #[arg(long, global = true, value_name = "FILE")]
+    audit_log: Option<PathBuf>,
+
+    /// What to do with imports reached through a symlink (allow, warn or deny)
+    #[arg(long, global = true, value_name = "ACTION", default_value = "allow")]
+    symlink_imports: ImportAction,
+
+    /// What to do with imports resolving outside the import root (allow, warn or deny)
+    #[arg(long, global = true, value_name = "ACTION", default_value = "allow")]
+    escaping_imports: ImportAction,
+
+    /// Root for --escaping-imports (relative to --project-root, if given)
+    #[arg(long, global = true, value_name = "DIR", default_value = ".")]
+    import_root: PathBuf,
+
+    /// Write errors as human-readable text or as one JSON diagnostic per line, for CI systems and editors
+    #[arg(long, global = true, value_name = "FORMAT", value_enum, default_value_t = ErrorFormat::Human)]
+    error_format: ErrorFormat,
+
+    /// Color human-readable errors: auto (when stderr is a terminal and NO_COLOR is unset), always or never
+    #[arg(long, global = true, value_name = "WHEN", value_enum, default_value_t = Color::Auto)]
+    color: Color,
+
+    /// Keep stdout to the command's output and write everything on stderr as versioned JSON lines, for tools that wrap bunsenite (implies --error-format json)
+    #[arg(long, global = true)]
+    porcelain: bool,
+
+    /// Write paths in errors, provenance records and reports relative to the project root (see --project-root)
+    #[arg(long, global = true)]
+    relative_paths: bool,
+
+    /// Run as if started in DIR: file arguments, outputs, imports and paths in diagnostics are relative to it, not to the working directory
+    #[arg(short = 'C', long, global = true, value_name = "DIR")]
+    project_root: Option<PathBuf>,
+
+    /// Compress files written (not stdout) with gzip or zstd, adding .gz/.zst
+    #[arg(long, global = true, value_name = "ALGO")]
+    compress: Option<Compression>,
+
+    /// Drop filesystem access beyond the config's inputs and outputs, and network access, before evaluating (Linux)
+    #[arg(long, global = true)]
+    harden: bool,
+
+    /// Indent pretty JSON output by N spaces
+    #[arg(long, global = true, value_name = "N", default_value_t = 2)]
+    json_indent: usize,
+
+    /// Keep arrays of scalars on one line in pretty JSON output if at most WIDTH characters
+    #[arg(long, global = true, value_name = "WIDTH", default_value_t = 0)]
+    json_inline_arrays: usize,
+
+    /// Leave identifier keys unquoted in pretty JSON output (JSON5)
+    #[arg(long, global = true)]
+    json_bare_keys: bool,
+
+    /// Export parse/typecheck/eval spans to this OTLP/HTTP collector
+    #[cfg(feature = "otel")]
+    #[arg(long, global = true, value_name = "URL")]
+    otlp_endpoint: Option<String>,
+
+    /// Ignore the global settings file (settings.ncl in the config directory, or $BUNSENITE_SETTINGS)
+    // Read by with_settings(), from the raw arguments before parsing
+    #[allow(dead_code)]
+    #[arg(long, global = true)]
+    no_settings: bool,
+
+    /// Fail configs nested more than N levels deep, in the source or the result
+    #[arg(long, global = true, value_name = "N")]
+    max_depth: Option<usize>,
+
+    /// Fail evaluations once the process's resident memory exceeds SIZE, e.g. 2G (Linux)
+    #[arg(long, global = true, value_name = "SIZE", value_parser = limits::parse_size)]
+    max_memory: Option<u64>,
+
+    /// Fail evaluations that take longer than SECONDS
+    #[arg(long, global = true, value_name = "SECONDS")]
+    timeout: Option<u64>,
+
+    /// Limit the address space of the evaluation, e.g. 2G (Unix)
+    #[arg(long, global = true, value_name = "SIZE", value_parser = limits::parse_size)]
+    rlimit_as: Option<u64>,
+
+    /// Limit the CPU time of the evaluation, in seconds (Unix)
+    #[arg(long, global = true, value_name = "SECONDS")]
+    rlimit_cpu: Option<u64>,
+
+    /// Limit the number of files the evaluation can have open (Unix)
+    #[arg(long, global = true, value_name = "N")]
+    rlimit_nofile: Option<u64>,
+}
+
+impl Cli {
+    /// The `--rlimit-*` limits
+    fn limits(&self) -> Limits {
+        let mut limits = Limits::new();
+        if let Some(bytes) = self.rlimit_as {
+            limits = limits.with_address_space(bytes);
+        }
+        if let Some(seconds) = self.rlimit_cpu {
+            limits = limits.with_cpu_time(seconds);
+        }
+        if let Some(count) = self.rlimit_nofile {
+            limits = limits.with_open_files(count);
+        }
+        limits
+    }
+}
+
+/// Set in the child process that runs a command under `--rlimit-*` limits
+const LIMITED_ENV: &str = "BUNSENITE_LIMITED";
+
+/// Arguments of `parse`
+#[derive(Args, Clone, Debug)]
+struct ParseArgs {
+    /// Path to the Nickel configuration file, ARCHIVE::ENTRY inside a .zip/.tar/.tar.zst bundle, or `-` for stdin
+    #[arg(value_name = "FILE")]
+    file: PathBuf,
+
+    /// File name to report in diagnostics for source read from stdin (default: stdin.ncl)
+    #[arg(long, value_name = "NAME")]
+    name: Option<String>,
+
+    /// Pretty-print the output JSON
+    #[arg(short, long)]
+    pretty: bool,
+
+    /// Write the output JSON to this file, replacing it atomically, instead of stdout
+    #[arg(short, long, value_name = "FILE", conflicts_with_all = ["diff_against", "tenants", "field"])]
+    output: Option<PathBuf>,
+
+    /// Permissions of the output file in octal, e.g. 600 for secrets (Unix)
+    #[arg(long, value_name = "MODE", requires = "output", value_parser = output::parse_mode)]
+    mode: Option<u32>,
+
+    /// List values that came from contract defaults rather than the config (on stderr)
+    #[arg(long)]
+    show_defaults: bool,
+
+    /// Print only the paths that differ from a previous JSON artifact (exit 1 if any)
+    #[arg(long, value_name = "JSON", conflicts_with = "pretty")]
+    diff_against: Option<PathBuf>,
+
+    /// Refuse to write paths matching a rule, e.g. 'secrets.*=deny' (repeatable)
+    #[arg(long, value_name = "RULE")]
+    restrict: Vec<String>,
+
+    /// Only output paths matching this pattern, e.g. 'servers[*].host' (repeatable)
+    #[arg(long, value_name = "PATTERN")]
+    include_path: Vec<String>,
+
+    /// Leave out paths matching this pattern (repeatable)
+    #[arg(long, value_name = "PATTERN")]
+    exclude_path: Vec<String>,
+
+    /// Mask string and number literals before evaluating (see `bunsenite mask`), and
+    /// replace every output value with a placeholder naming its type
+    #[arg(long)]
+    mask_values: bool,
+
+    /// Evaluate FILE as a template once per tenant in this record of parameter sets
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["show_defaults", "diff_against"])]
+    tenants: Option<PathBuf>,
+
+    /// Write one <tenant>.json per tenant into this directory
+    #[arg(long, value_name = "DIR", requires = "tenants")]
+    out_dir: Option<PathBuf>,
+
+    /// Set the field at PATH over the config's value, e.g. 'server.port=8080' (repeatable; VALUE is JSON or a string)
+    #[arg(long = "override", value_name = "PATH=VALUE", value_parser = overrides::parse_spec)]
+    overrides: Vec<(String, serde_json::Value)>,
+
+    /// Evaluate and output only the field at this path, e.g. 'services.web'
+    #[arg(long, value_name = "PATH")]
+    target: Option<String>,
+
+    /// Print only the value at this path, e.g. 'server.ports[0]' (as `query`)
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["diff_against", "tenants", "target", "provenance", "show_defaults", "restrict"])]
+    field: Option<String>,
+
+    /// Write the output's source files, engine and SHA-256 to this JSON file
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["diff_against", "tenants"])]
+    provenance: Option<PathBuf>,
+
+    /// List the files the output was made from in this Make/Ninja depfile (JSON if it ends in .json)
+    #[arg(long, value_name = "FILE", requires = "output", conflicts_with_all = ["diff_against", "tenants", "field"])]
+    emit_depfile: Option<PathBuf>,
+
+    /// Write record fields in the order the config defines them, not by name
+    #[arg(long, conflicts_with_all = ["diff_against", "tenants", "field"])]
+    source_order: bool,
+
+    /// Evaluate again whenever FILE or one of its imports changes (builds with `watch`)
+    #[arg(long, conflicts_with_all = ["diff_against", "tenants"])]
+    watch: bool,
+}
+
+/// Arguments of `validate`
+#[derive(Args, Clone, Debug)]
+struct ValidateArgs {
+    /// Path to the Nickel configuration file, ARCHIVE::ENTRY inside a .zip/.tar/.tar.zst bundle, `-` for stdin, or a quoted glob such as `configs/**/*.ncl`
+    #[arg(value_name = "FILE")]
+    file: PathBuf,
+
+    /// File name to report in diagnostics for source read from stdin (default: stdin.ncl)
+    #[arg(long, value_name = "NAME")]
+    name: Option<String>,
+
+    /// Check the evaluated output against a schema (repeatable)
+    #[arg(long = "schema", value_name = "SCHEMA[#NAME]")]
+    schemas: Vec<String>,
+
+    /// Skip files that passed before and whose imports are unchanged
+    #[arg(long, conflicts_with = "schemas")]
+    incremental: bool,
+}
+
+/// Arguments of `expand`
+#[derive(Args, Clone, Debug)]
+struct ExpandArgs {
+    /// Path to the Nickel entrypoint; the combination is bound to `matrix`
+    #[arg(value_name = "FILE")]
+    file: PathBuf,
+
+    /// Matrix definition with `axes`, and optionally `output` and `exclude`
+    #[arg(long, value_name = "FILE")]
+    matrix: PathBuf,
+
+    /// Directory outputs and manifest.json are written to
+    #[arg(short, long, value_name = "DIR", default_value = "out")]
+    out_dir: PathBuf,
+
+    /// Output path template overriding the definition's, e.g. '{region}/{env}.json'
+    #[arg(long, value_name = "TEMPLATE")]
+    output: Option<String>,
+
+    /// Pretty-print the JSON outputs
+    #[arg(short, long)]
+    pretty: bool,
+}
+
+/// Parser of `--format` export formats, listing them (with their aliases)
+/// so completion scripts offer them
+fn export_formats() -> impl TypedValueParser<Value = export::Format> {
+    PossibleValuesParser::new(
+        export::Format::ALL.map(|format| {
+            PossibleValue::new(format.name()).aliases(format.aliases().iter().copied())
+        }),
+    )
+    .try_map(|name| export::Format::from_name(&name))
+}
+
+/// Output format for `info`, `doctor`, `inspect-capabilities` and `diff`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum InfoFormat {
+    /// Human-readable text
+    Text,
+    /// Machine-readable JSON
+    Json,
+}
+
+/// How errors are written to stderr
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum ErrorFormat {
+    /// Messages with suggestions, for people
+    Human,
+    /// One JSON diagnostic per line (see `bunsenite::diagnostic`)
+    Json,
+}
+
+/// When human-readable errors are colored
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Color {
+    /// When stderr is a terminal and `NO_COLOR` is not set
+    Auto,
+    /// Always
+    Always,
+    /// Never
+    Never,
+}
+
+impl Color {
+    /// Whether to color what is written to stderr
+    fn enabled(self) -> bool {
+        use std::io::IsTerminal;
+
+        match self {
+            Color::Auto => {
+                std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none()
+            }
+            Color::Always => true,
+            Color::Never => false,
+        }
+    }
+}
+
+/// Output format for `deps`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum DepsFormat {
+    /// An indented tree, shared imports shown once
+    Tree,
+    /// Files, missing imports and import edges as JSON
+    Json,
+    /// A Graphviz digraph
+    Dot,
+}
+
+/// Output format for `trace`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum TraceFormat {
+    /// Tables of phases, imports and fields
+    Summary,
+    /// Chrome trace event JSON, for chrome://tracing, Perfetto or speedscope
+    Chrome,
+}
+
+/// Output format for `doc`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum DocFormat {
+    /// A Markdown reference, one section per field
+    Markdown,
+    /// Machine-readable JSON
+    Json,
+}
+
+/// `bundle` actions
+#[derive(Subcommand)]
+enum BundleCommand {
+    /// Push a .zip/.tar/.tar.zst bundle to an OCI registry and print its digest
+    Push {
+        /// Bundle to push
+        #[arg(value_name = "BUNDLE")]
+        bundle: PathBuf,
+
+        /// Destination, oci://REGISTRY/REPOSITORY[:TAG]
+        #[arg(value_name = "REFERENCE")]
+        reference: String,
+    },
+
+    /// Pull a bundle from an OCI registry (or the cache)
+    Pull {
+        /// Source, oci://REGISTRY/REPOSITORY[:TAG][@sha256:DIGEST]
+        #[arg(value_name = "REFERENCE")]
+        reference: String,
+
+        /// Write the bundle to FILE (default: its pushed file name)
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+
+        /// Use only the cache; tags resolve to the digest last seen
+        #[arg(long)]
+        offline: bool,
+    },
+}
+
+/// `cache` actions
+#[derive(Subcommand)]
+enum CacheCommand {
+    /// Remove unused files, least recently used first, and report what is left
+    Gc {
+        /// Remove least recently used files until the cache is at most SIZE, e.g. 1GiB
+        #[arg(long, value_name = "SIZE", value_parser = limits::parse_size)]
+        max_size: Option<u64>,
+
+        /// Remove files unused for longer than AGE, e.g. 30d (s, m, h, d or w)
+        #[arg(long, value_name = "AGE", value_parser = cache::parse_age)]
+        max_age: Option<Duration>,
+    },
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Create a starter project from a template
+    ///
+    /// Writes an entry main.ncl, a contracts.ncl, an overlays directory and a
+    /// bunsenite.toml manifest into DIR (default: the current directory),
+    /// named after DIR unless --name is given. Templates are built in, so
+    /// this works offline. Existing files are only replaced with --force.
+    Init {
+        /// Directory to create the project in
+        #[arg(value_name = "DIR", default_value = ".")]
+        dir: PathBuf,
+
+        /// Starter project: k8s, service or library
+        #[arg(long, value_name = "TEMPLATE", default_value = "service")]
+        template: Template,
+
+        /// Project name, instead of the directory's name
+        #[arg(long, value_name = "NAME")]
+        name: Option<String>,
+
+        /// Replace files that already exist
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Parse and evaluate a Nickel configuration file
+    Parse(Box<ParseArgs>),
+
+    /// Validate a Nickel configuration without evaluating it
+    ///
+    /// With --schema, the config is also evaluated and its output checked
+    /// against each schema: JSON Schema (.json), a CUE definition (.cue) or a
+    /// Protobuf message (.pb/.binpb/.desc/.protoset descriptor set). Name the
+    /// definition or message with FILE#NAME when the file has several.
+    ///
+    /// A glob FILE validates every matching file and prints a pass/fail
+    /// summary, exiting 1 if any file fails.
+    Validate(ValidateArgs),
+
+    /// Statically typecheck a Nickel configuration without evaluating it
+    ///
+    /// Stricter than validate, which only parses: annotated bindings and
+    /// expressions (`let x : Number = ..`) are checked by Nickel's
+    /// typechecker. The first type error is reported with its location.
+    Typecheck {
+        /// Path to the Nickel configuration file, ARCHIVE::ENTRY inside a .zip/.tar/.tar.zst bundle, or `-` for stdin
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+
+        /// File name to report in diagnostics for source read from stdin (default: stdin.ncl)
+        #[arg(long, value_name = "NAME")]
+        name: Option<String>,
+    },
+
+    /// Format Nickel files in the canonical style, in place
+    ///
+    /// Only whitespace outside strings and comments changes; line breaks are
+    /// kept. With --check, nothing is written and the command exits 1 if any
+    /// file is not formatted. FILE `-` formats stdin to stdout.
+    Fmt {
+        /// Nickel files to format
+        #[arg(value_name = "FILE", required = true)]
+        files: Vec<PathBuf>,
+
+        /// List unformatted files and exit 1 instead of rewriting them
+        #[arg(long)]
+        check: bool,
+    },
+
+    /// Print a Nickel file with its values masked, for bug reports
+    ///
+    /// String literals become "<string>", numbers 0 and comments are emptied;
+    /// field names, identifiers, contracts, enum tags and booleans are kept.
+    /// FILE `-` reads stdin.
+    Mask {
+        /// Nickel file to mask
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+    },
+
+    /// Check Nickel files for likely mistakes, without evaluating them
+    ///
+    /// Rules: unused-let, shadowed-field, missing-contract (off by default)
+    /// and deprecated-call. Settings come from .bunsenite-lint.ncl in the
+    /// current directory (or --config), then --enable and --disable. Exits 1
+    /// if anything is found.
+    Lint {
+        /// Nickel files to check
+        #[arg(value_name = "FILE", required = true)]
+        files: Vec<PathBuf>,
+
+        /// Enable these rules (comma-separated)
+        #[arg(long, value_name = "RULE", value_delimiter = ',')]
+        enable: Vec<lint::Rule>,
+
+        /// Disable these rules (comma-separated)
+        #[arg(long, value_name = "RULE", value_delimiter = ',')]
+        disable: Vec<lint::Rule>,
+
+        /// Read rule settings from FILE instead of .bunsenite-lint.ncl
+        #[arg(long, value_name = "FILE")]
+        config: Option<PathBuf>,
+    },
+
+    /// Benchmark parse, typecheck, evaluate and serialize phases
+    Bench {
+        /// Nickel files to benchmark
+        #[arg(value_name = "FILE", required = true)]
+        files: Vec<PathBuf>,
+
+        /// Timed iterations per phase
+        #[arg(short = 'n', long, default_value_t = 10)]
+        iterations: u32,
+
+        /// Baseline to compare against (fails on regression)
+        #[arg(long, value_name = "FILE")]
+        baseline: Option<PathBuf>,
+
+        /// Save the results as a new baseline
+        #[arg(long, value_name = "FILE")]
+        save_baseline: Option<PathBuf>,
+
+        /// Allowed slowdown over the baseline, in percent
+        #[arg(long, default_value_t = 10.0)]
+        threshold: f64,
+    },
+
+    /// Print the import graph of a config, without evaluating it
+    ///
+    /// Follows imports transitively as they are written, so an import in a
+    /// branch evaluation never takes is listed too: every file the config
+    /// may read, as build systems need for invalidation.
+    Deps {
+        /// Path to the Nickel configuration file
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+
+        /// Output format
+        #[arg(short, long, value_enum, default_value_t = DepsFormat::Tree)]
+        format: DepsFormat,
+
+        /// Write to FILE instead of stdout
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+
+    /// Copy a config and every file it imports into one directory
+    ///
+    /// Files keep their places relative to each other, so the copy of FILE
+    /// evaluates on its own, offline; absolute imports are rewritten to
+    /// relative ones. vendor.json in DIR records where each file came from,
+    /// with its SHA-256. Fails if an import cannot be read.
+    Vendor {
+        /// Path to the Nickel configuration file
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+
+        /// Directory to copy the files into
+        #[arg(long, value_name = "DIR", default_value = "vendor")]
+        out: PathBuf,
+    },
+
+    /// Profile where evaluating a config spends its time
+    ///
+    /// Times the parse, evaluate and serialize phases, each file of the
+    /// import tree, and each top-level field, every part on its own.
+    Trace {
+        /// Path to the Nickel configuration file
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+
+        /// Output format
+        #[arg(short, long, value_enum, default_value_t = TraceFormat::Summary)]
+        format: TraceFormat,
+
+        /// Runs per part; the fastest counts
+        #[arg(short = 'n', long, default_value_t = 3)]
+        iterations: u32,
+
+        /// Write to FILE instead of stdout
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+
+    /// Run the tests configs keep next to them, in a `tests` record
+    ///
+    /// Each test is a Bool that must be true, an `{ actual, expected }` pair
+    /// whose sides must be equal, or a record of more tests. Mark the field
+    /// `not_exported` to keep it out of the output. Exits 1 if a test fails
+    /// or does not evaluate.
+    Test {
+        /// Nickel files holding tests
+        #[arg(value_name = "FILE", required = true)]
+        files: Vec<PathBuf>,
+
+        /// Field holding the tests
+        #[arg(long, value_name = "NAME", default_value = testing::DEFAULT_FIELD)]
+        field: String,
+
+        /// Run only the tests whose names contain PATTERN
+        #[arg(long, value_name = "PATTERN")]
+        filter: Option<String>,
+
+        /// Output format
+        #[arg(short, long, value_enum, default_value_t = InfoFormat::Text)]
+        format: InfoFormat,
+    },
+
+    /// Run a conformance corpus (.ncl inputs with expected .json/.error files)
+    Conformance {
+        /// Corpus directory
+        #[arg(value_name = "DIR", default_value = "tests/conformance")]
+        dir: PathBuf,
+
+        /// Rewrite expected outputs from the current engine instead of checking
+        #[arg(long)]
+        update_expected: bool,
+
+        /// Print the corpus manifest as JSON (for non-Rust harnesses)
+        #[arg(long, conflicts_with = "update_expected")]
+        manifest: bool,
+
+        /// Diff a binding (deno, wasm or c) against the native engine
+        #[arg(long, value_name = "BINDING", conflicts_with_all = ["update_expected", "manifest"])]
+        against: Option<Binding>,
+
+        /// Command that runs the binding's conformance runner
+        #[arg(long, value_name = "COMMAND", requires = "against")]
+        runner: Option<String>,
+
+        /// Report which parts of this contract the passing cases' outputs exercise
+        #[arg(long, value_name = "FILE", conflicts_with_all = ["update_expected", "manifest"])]
+        coverage: Option<PathBuf>,
+    },
+
+    /// Infer a Nickel contract from example JSON/YAML/TOML documents
+    InferSchema {
+        /// Example documents
+        #[arg(value_name = "FILE", required = true)]
+        files: Vec<PathBuf>,
+
+        /// Maximum distinct values for a string field to become an enum (0 disables)
+        #[arg(long, value_name = "N", default_value_t = schema::DEFAULT_ENUM_THRESHOLD)]
+        enum_threshold: usize,
+
+        /// Write the contract to a file instead of stdout
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+
+    /// Evaluate a config once per combination of a matrix definition
+    Expand(ExpandArgs),
+
+    /// Report which owners must approve the output changes of a config
+    Owners {
+        /// Path to the Nickel configuration file
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+
+        /// Previous JSON artifact to diff against
+        #[arg(long, value_name = "JSON")]
+        against: PathBuf,
+
+        /// Sidecar file mapping path patterns to owners
+        #[arg(long, value_name = "FILE")]
+        owners_file: Option<PathBuf>,
+    },
+
+    /// Evaluate several configs merged into one (base first, then overlays)
+    ///
+    /// Files are combined with Nickel's `&` in order, so `merge base.ncl
+    /// prod.ncl` applies the prod overlay to the base. Fields set in two
+    /// files must agree unless priorities (`| default`, `| force`) decide.
+    Merge {
+        /// Configs to merge, base first
+        #[arg(value_name = "FILE", required = true)]
+        files: Vec<PathBuf>,
+
+        /// Output format: json, yaml, toml, nix or text
+        #[arg(short, long, value_name = "FORMAT", default_value = "json", ignore_case = true, value_parser = export_formats())]
+        format: export::Format,
+
+        /// Write to FILE instead of stdout
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+
+    /// Three-way merge of Nickel sources, for use as a git merge driver
+    ///
+    /// Configure with:
+    ///   git config merge.nickel.driver 'bunsenite merge-driver %O %A %B --marker-size %L'
+    /// and `*.ncl merge=nickel` in .gitattributes. The result is written to OURS;
+    /// exits 1 if conflicts remain.
+    MergeDriver {
+        /// Common ancestor version
+        #[arg(value_name = "BASE")]
+        base: PathBuf,
+
+        /// Our version; overwritten with the merge result
+        #[arg(value_name = "OURS")]
+        ours: PathBuf,
+
+        /// Their version
+        #[arg(value_name = "THEIRS")]
+        theirs: PathBuf,
+
+        /// Length of conflict markers
+        #[arg(long, value_name = "N", default_value_t = merge::DEFAULT_MARKER_SIZE)]
+        marker_size: usize,
+    },
+
+    /// List what a config would touch when evaluated, without evaluating it
+    InspectCapabilities {
+        /// Path to the Nickel configuration file
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+
+        /// Project root; imports resolving outside it are flagged
+        #[arg(long, value_name = "DIR", default_value = ".")]
+        root: PathBuf,
+
+        /// Also evaluate the config (running any transforms) and report the outcome
+        #[arg(long)]
+        evaluate: bool,
+
+        /// Output format
+        #[arg(long, value_enum, default_value_t = InfoFormat::Text)]
+        format: InfoFormat,
+    },
+
+    /// Generate random configs satisfying a contract, one JSON document per line
+    Synth {
+        /// Contract to satisfy, in the form `infer-schema` writes
+        #[arg(long, value_name = "FILE")]
+        schema: PathBuf,
+
+        /// Number of configs to generate
+        #[arg(long, value_name = "N", default_value_t = 1)]
+        count: usize,
+
+        /// Seed for reproducible output (default: random, printed to stderr)
+        #[arg(long, value_name = "N")]
+        seed: Option<u64>,
+
+        /// Write synth-0001.json, ... to this directory instead of stdout
+        #[arg(short, long, value_name = "DIR")]
+        out_dir: Option<PathBuf>,
+
+        /// Skip checking each config against the contract with Nickel
+        #[arg(long)]
+        no_check: bool,
+    },
+
+    /// Print the value at a field path, evaluating only that field
+    Query {
+        /// Path to the Nickel configuration file
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+
+        /// Field path, e.g. '.services.web' or 'servers[0].host' (default: everything)
+        #[arg(value_name = "PATH", default_value = ".")]
+        path: String,
+
+        /// Pretty-print the output JSON
+        #[arg(short, long)]
+        pretty: bool,
+    },
+
+    /// Print completions of a partial field path, one per line (used by shell completion)
+    #[command(hide = true)]
+    CompletePath {
+        /// Path to the Nickel configuration file
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+
+        /// Partially typed field path
+        #[arg(value_name = "PARTIAL", default_value = "", allow_hyphen_values = true)]
+        partial: String,
+    },
+
+    /// Print a shell completion script for bash, zsh, fish, PowerShell or Elvish
+    ///
+    /// Subcommands, options and `--format` values complete in every shell;
+    /// bash also completes field paths for `query`.
+    Completions {
+        /// Shell to complete for
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+
+    /// Write man pages for bunsenite and each of its subcommands
+    ///
+    /// For packagers: without --out-dir, prints bunsenite(1) to stdout; with
+    /// it, writes bunsenite.1 and one page per subcommand, such as
+    /// bunsenite-parse.1 and bunsenite-bundle-push.1.
+    #[command(hide = true)]
+    Man {
+        /// Directory to write every page to
+        #[arg(long, value_name = "DIR")]
+        out_dir: Option<PathBuf>,
+    },
+
+    /// Evaluate a config and write it as YAML, TOML, JSON or Nix
+    Export {
+        /// Path to the Nickel configuration file
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+
+        /// Output formats: yaml, toml, json, nix or text, comma-separated for several
+        #[arg(short, long, value_name = "FORMAT", default_value = "yaml", value_delimiter = ',', ignore_case = true, value_parser = export_formats())]
+        format: Vec<export::Format>,
+
+        /// Write to FILE instead of stdout (with several formats, FILE.EXT per format),
+        /// replacing it atomically
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+
+        /// Permissions of the output files in octal, e.g. 600 for secrets (Unix)
+        #[arg(long, value_name = "MODE", requires = "output", value_parser = output::parse_mode)]
+        mode: Option<u32>,
+
+        /// Set the field at PATH over the config's value, e.g. 'server.port=8080' (repeatable; VALUE is JSON or a string)
+        #[arg(long = "override", value_name = "PATH=VALUE", value_parser = overrides::parse_spec)]
+        overrides: Vec<(String, serde_json::Value)>,
+
+        /// Write field docs as # comments above their keys in YAML output
+        #[arg(long)]
+        doc_comments: bool,
+
+        /// Write record fields in the order the config defines them, not by name
+        #[arg(long)]
+        source_order: bool,
+
+        /// Export again whenever FILE or one of its imports changes (builds with `watch`)
+        #[arg(long)]
+        watch: bool,
+
+        /// List the files the outputs were made from in this Make/Ninja depfile (JSON if it ends in .json)
+        #[arg(long, value_name = "FILE", requires = "output")]
+        emit_depfile: Option<PathBuf>,
+    },
+
+    /// Write every output declared in a config's `exports` record
+    ///
+    /// Each entry is { path, format? (json, yaml, toml, nix, text), content }; the
+    /// format defaults to the path's extension.
+    Build {
+        /// Path to the Nickel configuration file
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+
+        /// Directory export paths are relative to
+        #[arg(short, long, value_name = "DIR", default_value = ".")]
+        out_dir: PathBuf,
+
+        /// Only write these exports (repeatable)
+        #[arg(long, value_name = "NAME")]
+        only: Vec<String>,
+
+        /// Skip the build if the config and its imports are unchanged since it
+        /// last succeeded and its outputs are untouched
+        #[arg(long)]
+        incremental: bool,
+
+        /// List the files the outputs were made from in this Make/Ninja depfile (JSON if it ends in .json)
+        #[arg(long, value_name = "FILE")]
+        emit_depfile: Option<PathBuf>,
+    },
+
+    /// Compare the evaluated outputs of two configs path by path
+    ///
+    /// Prints `+` added, `-` removed and `~` changed paths, reading from OLD
+    /// to NEW, and exits 1 if anything differs. Unlike a textual diff of the
+    /// Nickel sources, refactors that do not change the output show nothing.
+    Diff {
+        /// The config before the change
+        #[arg(value_name = "OLD")]
+        old: PathBuf,
+
+        /// The config after the change
+        #[arg(value_name = "NEW")]
+        new: PathBuf,
+
+        /// Output format
+        #[arg(long, value_enum, default_value_t = InfoFormat::Text)]
+        format: InfoFormat,
+    },
+
+    /// Generate reference documentation from field metadata
+    ///
+    /// Lists every output field with its doc string, contracts, whether it is
+    /// optional and its default, including optional fields the config leaves
+    /// unset.
+    Doc {
+        /// Path to the Nickel configuration file
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+
+        /// Output format
+        #[arg(short, long, value_enum, default_value_t = DocFormat::Markdown)]
+        format: DocFormat,
+
+        /// Write to FILE instead of stdout
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+
+    /// Export a JSON Schema of a config's fields from their contracts
+    ///
+    /// Contracts with a JSON Schema counterpart (Number, String, Bool, Array,
+    /// `{ _ : T }`, enums, std.number.Nat, ...) become checks; doc strings,
+    /// defaults and `optional` carry over.
+    Schema {
+        /// Path to the Nickel configuration file
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+
+        /// Write to FILE instead of stdout
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+
+    /// Walk a failing evaluation back through its merges and imports
+    ///
+    /// Splits the config's top-level merge into its parts and finds the part
+    /// that breaks it, listing the fields it sets over earlier values and
+    /// defaults; parts and imports can be walked into in turn. Interactive on
+    /// a terminal; otherwise, or with --trace, prints the chain of likely
+    /// causes. Exits 1 if the config fails to evaluate.
+    WhyError {
+        /// Path to the Nickel configuration file
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+
+        /// Print the chain of likely causes instead of prompting
+        #[arg(long)]
+        trace: bool,
+    },
+
+    /// Compare a config's output with exported live state and locate each difference
+    ///
+    /// Differences read from desired to live: `~` changed, `-` missing from the
+    /// live state, `+` only in the live state (with --include-extra). Exits 1 if
+    /// anything differs.
+    Drift {
+        /// Path to the Nickel configuration file
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+
+        /// Live state exported as JSON, e.g. from `kubectl get -o json`
+        #[arg(long, value_name = "JSON")]
+        live: PathBuf,
+
+        /// Also report paths that exist only in the live state
+        #[arg(long)]
+        include_extra: bool,
+
+        /// Path pattern not to compare (repeatable), e.g. 'metadata.annotations'
+        #[arg(long, value_name = "PATTERN")]
+        ignore: Vec<String>,
+    },
+
+    /// Check that a policy rejects small mutations of a config's output
+    Mutate {
+        /// Path to the Nickel configuration file
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+
+        /// Nickel contract the output must satisfy
+        #[arg(long, value_name = "FILE")]
+        policy: PathBuf,
+
+        /// Exit 1 if less than this percentage of mutants is rejected
+        #[arg(long, value_name = "PERCENT")]
+        min_score: Option<f64>,
+    },
+
+    /// Answer JSON-lines evaluation requests on stdin until it closes
+    ///
+    /// Each line is {"id", "method": "parse"|"validate", "source", "file"?,
+    /// "priority": "interactive"|"background"?, "deadline_ms"?, "max_output_bytes"?};
+    /// interactive requests are scheduled ahead of background ones.
+    /// {"id", "method": "status"} reports queue, running and memory state;
+    /// "register"/"unregister" with "name" (and "source") manage helper libraries.
+    #[cfg(feature = "daemon")]
+    Serve {
+        /// Record every request and response to this session file
+        #[arg(long, value_name = "FILE")]
+        record: Option<PathBuf>,
+
+        /// Also report status to every connection on this Unix socket
+        #[arg(long, value_name = "PATH")]
+        status_socket: Option<PathBuf>,
+
+        /// Serve Prometheus metrics over HTTP at this address, e.g. 127.0.0.1:9090
+        #[arg(long, value_name = "ADDR")]
+        metrics_addr: Option<std::net::SocketAddr>,
+    },
+
+    /// Run a language server for editors on stdin and stdout
+    ///
+    /// Publishes evaluation errors and lint findings when a file is opened or
+    /// saved, shows field docs and contracts on hover, and jumps to imported
+    /// files and to let/field definitions. Lint rules come from
+    /// .bunsenite-lint.ncl in the working directory, if present.
+    #[cfg(feature = "lsp")]
+    Lsp,
+
+    /// Answer the requests of a recorded serve session again and report changed responses
+    ///
+    /// Requests are answered one at a time, in the order they were answered
+    /// when recorded; exits 1 if any response differs.
+    #[cfg(feature = "daemon")]
+    Replay {
+        /// Session file written by `serve --record`
+        #[arg(value_name = "FILE")]
+        session: PathBuf,
+    },
+
+    /// Run as a Bazel persistent worker, or do one worker request
+    ///
+    /// With --persistent_worker (Bazel adds it), reads length-prefixed
+    /// WorkRequest messages on stdin and answers each on stdout, keeping one
+    /// process for every action of a build. A request's arguments are
+    /// `export FILE --output FILE [--format FORMAT]` or `validate FILE..`.
+    /// Without the flag, does the request given as arguments once, as Bazel
+    /// runs the action when workers are off; @FILE reads arguments from FILE,
+    /// one per line.
+    Worker {
+        /// Serve WorkRequests on stdin until it closes
+        #[arg(long = "persistent_worker")]
+        persistent_worker: bool,
+
+        /// Request arguments, e.g. `export app.ncl --output app.json`
+        #[arg(
+            value_name = "ARG",
+            trailing_var_arg = true,
+            allow_hyphen_values = true
+        )]
+        args: Vec<String>,
+    },
+
+    /// Push config bundles to, and pull them from, OCI registries
+    ///
+    /// Blobs are cached (in $BUNSENITE_CACHE_DIR/oci or the platform cache
+    /// directory), so digest-pinned pulls work offline. Talking to registries
+    /// needs a build with the `oci` feature.
+    Bundle {
+        #[command(subcommand)]
+        command: BundleCommand,
+    },
+
+    /// Maintain the on-disk cache of OCI blobs and --incremental fingerprints
+    ///
+    /// The cache is in $BUNSENITE_CACHE_DIR or the platform cache directory.
+    /// Set BUNSENITE_CACHE_MAX_SIZE and/or BUNSENITE_CACHE_MAX_AGE to trim it
+    /// the same way after every command that writes to it.
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+
+    /// Check that this installation works and print a report to attach to bug reports
+    ///
+    /// Exits 1 if any check fails.
+    Doctor {
+        /// Output format
+        #[arg(long, value_enum, default_value_t = InfoFormat::Text)]
+        format: InfoFormat,
+    },
+
+    /// Show version and compliance information
+    Info {
+        /// Output format
+        #[arg(long, value_enum, default_value_t = InfoFormat::Text)]
+        format: InfoFormat,
+    },
+}
+
+fn main() {
+    let mut cli = Cli::parse_from(with_settings(std::env::args_os().collect()));
+    if cli.porcelain {
+        PORCELAIN.store(true, Ordering::Relaxed);
+        cli.error_format = ErrorFormat::Json;
+        cli.color = Color::Never;
+    }
+    let limits = cli.limits();
+    let relative_paths = cli.relative_paths;
+    let error_format = cli.error_format;
+    let color = cli.color.enabled();
+    if !limits.is_empty() && std::env::var_os(LIMITED_ENV).is_none() {
+        run_limited(&limits, error_format, color);
+    }
+
+    let result = limits
+        .apply()
+        .and_then(|()| match &cli.require_nickel {
+            Some(requirement) => VERSION_INFO.require(requirement),
+            None => Ok(()),
+        })
+        .and_then(|()| run(cli));
+
+    if let Err(e) = result {
+        // run() has moved to the project root by now
+        let e = match std::env::current_dir() {
+            Ok(root) if relative_paths => e.relative_to(&root),
+            _ => e,
+        };
+        exit_with_error(&e, error_format, color);
+    }
+}
+
+/// `args` with the options of the global settings file inserted after the
+/// program name, so that the ones given win
+fn with_settings(mut args: Vec<OsString>) -> Vec<OsString> {
+    let mut given = args.iter().skip(1).take_while(|arg| *arg != "--");
+    if given.any(|arg| arg == "--no-settings") {
+        return args;
+    }
+    let loader = NickelLoader::new().with_hermetic(true);
+    let settings = settings::args(&loader).and_then(|settings| {
+        let command = Cli::command();
+        let globals: Vec<&str> = command
+            .get_arguments()
+            .filter(|arg| arg.is_global_set())
+            .filter_map(|arg| arg.get_long())
+            .collect();
+        for setting in &settings {
+            let name = setting.trim_start_matches("--");
+            let name = name.split_once('=').map_or(name, |(name, _)| name);
+            if !globals.contains(&name) {
+                return Err(bunsenite::Error::invalid_input(format!(
+                    "Unknown setting '{}' in the settings file; settings are global options: {}",
+                    name.replace('-', "_"),
+                    globals.join(", ")
+                )));
+            }
+        }
+        Ok(settings)
+    });
+    match settings {
+        Ok(settings) => {
+            let at = args.len().min(1);
+            args.splice(at..at, settings.into_iter().map(OsString::from));
+            args
+        }
+        Err(e) => exit_with_error(&e, ErrorFormat::Human, Color::Auto.enabled()),
+    }
+}
+
+/// Run this command again in a child process that applies `limits` to
+/// itself, and exit as it does, reporting a limit it died of as an error
+fn run_limited(limits: &Limits, error_format: ErrorFormat, color: bool) -> ! {
+    let status = std::env::current_exe()
+        .map_err(bunsenite::Error::from)
+        .and_then(|exe| {
+            let mut command = process::Command::new(exe);
+            command
+                .args(std::env::args_os().skip(1))
+                .env(LIMITED_ENV, "1");
+            limits.supervise(command)
+        });
+    match status {
+        Ok(status) => process::exit(status.code().unwrap_or(1)),
+        Err(e) => exit_with_error(&e, error_format, color),
+    }
+}
+
+fn exit_with_error(e: &bunsenite::Error, error_format: ErrorFormat, color: bool) -> ! {
+    match error_format {
+        ErrorFormat::Human => print_error(e, color),
+        ErrorFormat::Json => print_diagnostic(e, None),
+    }
+    let code = match e {
+        bunsenite::Error::Interrupted(_) => interrupt::EXIT_CODE,
+        // Tells the supervising parent which limit this ran into
+        _ if std::env::var_os(LIMITED_ENV).is_some() => {
+            limits::Resource::of_error(e).map_or(1, limits::Resource::exit_code)
+        }
+        _ => 1,
+    };
+    process::exit(code);
+}
+
+/// Stop long-running work cleanly on SIGINT, SIGTERM or Ctrl-C
+///
+/// The first signal asks the work to stop (see [`interrupt`]); the process
+/// exits with [`interrupt::EXIT_CODE`] when it has, or after
+/// [`interrupt::GRACE`], or at once on a second signal. With `idle_exit`,
+/// also as soon as no work is busy, for modes that may be waiting for
+/// input.
+fn handle_interrupts(idle_exit: bool) {
+    let shutdown = Shutdown::global();
+    let installed = ctrlc::set_handler(move || {
+        if !shutdown.request() {
+            process::exit(interrupt::EXIT_CODE);
+        }
+        note!("Interrupted; finishing work in progress (interrupt again to stop now)");
+        std::thread::spawn(move || {
+            let finished = if idle_exit {
+                shutdown.wait_idle(interrupt::GRACE)
+            } else {
+                std::thread::sleep(interrupt::GRACE);
+                false
+            };
+            if !finished {
+                note!("Stopped without waiting for work in progress");
+            }
+            process::exit(interrupt::EXIT_CODE);
+        });
+    });
+    if let Err(e) = installed {
+        warning!("cannot handle interrupts: {}", e);
+    }
+}
+
+/// Write `e` to stderr for people: with the source line it blames
+/// underlined, if the file it names can be read, else as a message
+fn print_error(e: &bunsenite::Error, color: bool) {
+    let diagnostic = Diagnostic::from_error(e);
+    if let Some(source) = source_of(&diagnostic) {
+        if e.location(&source).is_some() {
+            eprintln!("{}", diagnostic.render(Some(&source), color));
+            return;
+        }
+    }
+    eprintln!("Error: {}", e);
+    if let Some(suggestion) = e.suggestion() {
+        eprintln!("\nSuggestion: {}", suggestion);
+    }
+}
+
+/// The file a diagnostic with a span names, as it is now
+fn source_of(diagnostic: &Diagnostic) -> Option<String> {
+    match &diagnostic.file {
+        Some(file) if diagnostic.span.is_some() => std::fs::read_to_string(file).ok(),
+        _ => None,
+    }
+}
+
+/// `result`; with `--error-format json`, an error in it is written located in
+/// `source`, the config checked, and the process exits
+fn located<T>(
+    result: bunsenite::Result<T>,
+    source: &str,
+    error_format: ErrorFormat,
+) -> bunsenite::Result<T> {
+    match result {
+        Err(e) if error_format == ErrorFormat::Json => {
+            print_diagnostic(&e, Some(source));
+            process::exit(1);
+        }
+        result => result,
+    }
+}
+
+/// Write `e` to stderr as a JSON [`Diagnostic`] line, located in `source`
+/// (by default, the file the error names, as it is now)
+fn print_diagnostic(e: &bunsenite::Error, source: Option<&str>) {
+    let mut diagnostic = Diagnostic::from_error(e);
+    let read = match source {
+        Some(_) => None,
+        None => source_of(&diagnostic),
+    };
+    if let Some(source) = source.or(read.as_deref()) {
+        diagnostic = diagnostic.with_source(source);
+    }
+    if porcelain() {
+        eprintln!("{}", porcelain::diagnostic(&diagnostic));
+    } else {
+        eprintln!("{}", json::to_string(&diagnostic.to_json(), false));
+    }
+}
+
+/// Set by `--porcelain` (see [`bunsenite::porcelain`])
+static PORCELAIN: AtomicBool = AtomicBool::new(false);
+
+/// Whether `--porcelain` is on
+fn porcelain() -> bool {
+    PORCELAIN.load(Ordering::Relaxed)
+}
+
+/// Write `message` for people, marked as its kind, to stdout if `stdout`
+/// and stderr otherwise; with `--porcelain`, as a porcelain line on stderr
+fn report(stdout: bool, kind: porcelain::Kind, message: std::fmt::Arguments<'_>) {
+    let message = message.to_string();
+    // Blank lines setting a summary off from what is above are for people
+    let text = message.trim_start_matches('\n');
+    if porcelain() {
+        let line = match kind {
+            porcelain::Kind::Diagnostic => porcelain::diagnostic(&Diagnostic::warning(text.trim())),
+            kind => porcelain::message(kind, text.trim()),
+        };
+        eprintln!("{}", line);
+        return;
+    }
+    let mark = match kind {
+        porcelain::Kind::Status => "✓ ",
+        porcelain::Kind::Failure => "✗ ",
+        porcelain::Kind::Diagnostic => "Warning: ",
+        porcelain::Kind::Note | porcelain::Kind::Result => "",
+    };
+    let blank = &message[..message.len() - text.len()];
+    if stdout {
+        println!("{}{}{}", blank, mark, text);
+    } else {
+        eprintln!("{}{}{}", blank, mark, text);
+    }
+}
+
+/// The outcome `result` of checking `subject` (`pass`, `fail`, ...), as
+/// `row` on stdout, or a porcelain `result` line with `message`
+fn report_result(subject: &str, result: &str, message: Option<&str>, row: impl std::fmt::Display) {
+    if porcelain() {
+        eprintln!("{}", porcelain::result(subject, result, message));
+    } else {
+        println!("{}", row);
+    }
+}
+
+fn run(cli: Cli) -> bunsenite::Result<()> {
+    // First, so that every relative path below resolves against the root
+    if let Some(root) = &cli.project_root {
+        std::env::set_current_dir(root).map_err(|e| {
+            bunsenite::Error::invalid_input(format!(
+                "Cannot use project root {}: {}",
+                root.display(),
+                e
+            ))
+        })?;
+        if cli.verbose {
+            note!("Project root: {}", root.display());
+        }
+    }
+    #[cfg(feature = "otel")]
+    let _otlp = match &cli.otlp_endpoint {
+        Some(endpoint) => Some(bunsenite::telemetry::export_otlp(endpoint)?),
+        None => None,
+    };
+    let mut loader = NickelLoader::new()
+        .with_verbose(cli.verbose)
+        .with_compat(cli.compat)
+        .with_threads(cli.jobs.unwrap_or(0))
+        .with_prefetch_imports(cli.prefetch_imports)
+        .with_hermetic(cli.no_env);
+    if let Some(prefix) = &cli.env_prefix {
+        loader = loader.with_env(prefix);
+    }
+    if let Some(levels) = cli.max_depth {
+        loader = loader.with_max_depth(levels);
+    }
+    if let Some(bytes) = cli.max_memory {
+        loader = loader.with_max_memory(bytes);
+    }
+    if let Some(seconds) = cli.timeout {
+        loader = loader.with_timeout(Duration::from_secs(seconds));
+    }
+    for spec in &cli.transform {
+        loader = loader.with_transform(transform::parse_spec(spec)?);
+    }
+    if let Some(path) = &cli.audit_log {
+        loader = loader.with_audit_log(AuditLog::open(path)?);
+    }
+    if cli.symlink_imports != ImportAction::Allow || cli.escaping_imports != ImportAction::Allow {
+        let guard = ImportGuard::new(&cli.import_root)?
+            .with_symlinks(cli.symlink_imports)
+            .with_escapes(cli.escaping_imports);
+        loader = loader.with_import_guard(guard);
+    }
+    let verbose = cli.verbose;
+    loader = loader.with_notices(move |notice| match notice {
+        Notice::Import(_) => warning!("{}", notice),
+        Notice::Prefetched(_) if verbose => note!("{}", notice),
+        Notice::Prefetched(_) => {}
+    });
+    if cli.harden {
+        sandbox_for(
+            cli.command.as_ref(),
+            cli.remote_cache.as_deref(),
+            loader.threads(),
+        )?
+        .apply()?;
+    }
+
+    let relative_root = if cli.relative_paths {
+        Some(std::env::current_dir()?)
+    } else {
+        None
+    };
+    let relative_root = relative_root.as_deref();
+
+    let layout = json::Layout::new()
+        .with_indent(cli.json_indent)
+        .with_inline_arrays(cli.json_inline_arrays)
+        .with_bare_keys(cli.json_bare_keys);
+
+    match cli.command {
+        Some(Commands::Parse(args)) if args.watch => watch_loop(&loader, &args.file, || {
+            handle_parse(
+                &loader,
+                (*args).clone(),
+                cli.compat,
+                cli.verbose,
+                cli.compress,
+                layout,
+                relative_root,
+            )
+        }),
+        Some(Commands::Parse(args)) => handle_parse(
+            &loader,
+            *args,
+            cli.compat,
+            cli.verbose,
+            cli.compress,
+            layout,
+            relative_root,
+        ),
+        Some(Commands::Validate(args)) => handle_validate(
+            &loader,
+            args,
+            cli.compat,
+            cli.verbose,
+            cli.error_format,
+            cli.remote_cache.as_deref(),
+            relative_root,
+        ),
+        Some(Commands::Typecheck { file, name }) => handle_typecheck(
+            &loader,
+            file,
+            name.as_deref(),
+            cli.compat,
+            cli.verbose,
+            cli.error_format,
+        ),
+        Some(Commands::Fmt { files, check }) => handle_fmt(&files, check),
+        Some(Commands::Mask { file }) => handle_mask(&file),
+        Some(Commands::Init {
+            dir,
+            template,
+            name,
+            force,
+        }) => handle_init(&dir, template, name.as_deref(), force),
+        Some(Commands::Lint {
+            files,
+            enable,
+            disable,
+            config,
+        }) => handle_lint(&loader, &files, &enable, &disable, config.as_deref()),
+        Some(Commands::Bench {
+            files,
+            iterations,
+            baseline,
+            save_baseline,
+            threshold,
+        }) => handle_bench(files, iterations, baseline, save_baseline, threshold),
+        Some(Commands::Deps {
+            file,
+            format,
+            output,
+        }) => handle_deps(&loader, &file, format, output.as_deref(), relative_root),
+        Some(Commands::Vendor { file, out }) => handle_vendor(&loader, &file, &out),
+        Some(Commands::Trace {
+            file,
+            format,
+            iterations,
+            output,
+        }) => handle_trace(&loader, &file, format, iterations, output.as_deref()),
+        Some(Commands::Test {
+            files,
+            field,
+            filter,
+            format,
+        }) => handle_test(&loader, &files, &field, filter.as_deref(), format),
+        Some(Commands::Conformance {
+            dir,
+            update_expected,
+            manifest,
+            against,
+            runner,
+            coverage,
+        }) => handle_conformance(dir, update_expected, manifest, against, runner, coverage),
+        Some(Commands::InferSchema {
+            files,
+            enum_threshold,
+            output,
+        }) => handle_infer_schema(&loader, files, enum_threshold, output),
+        Some(Commands::Expand(args)) => {
+            handle_expand(&loader, args, layout, relative_root, cli.compress)
+        }
+        Some(Commands::Owners {
+            file,
+            against,
+            owners_file,
+        }) => handle_owners(&loader, file, against, owners_file),
+        Some(Commands::Merge {
+            files,
+            format,
+            output,
+        }) => handle_merge(
+            &loader,
+            &files,
+            format,
+            output.as_deref(),
+            layout,
+            cli.compress,
+        ),
+        Some(Commands::MergeDriver {
+            base,
+            ours,
+            theirs,
+            marker_size,
+        }) => handle_merge_driver(base, ours, theirs, marker_size),
+        #[cfg(feature = "daemon")]
+        Some(Commands::Serve {
+            record,
+            status_socket,
+            metrics_addr,
+        }) => {
+            let mut server = Server::new(loader);
+            if let Some(path) = record {
+                server = server.with_recorder(Recorder::create(&path)?);
+            }
+            if let Some(path) = status_socket {
+                server = server.with_status_socket(path);
+            }
+            if let Some(addr) = metrics_addr {
+                server = server.with_metrics_addr(addr);
+            }
+            handle_interrupts(true);
+            server.serve(std::io::stdin().lock(), std::io::stdout())?;
+            Shutdown::global().check()
+        }
+        #[cfg(feature = "lsp")]
+        Some(Commands::Lsp) => {
+            let default_config = Path::new(lint::CONFIG_FILE);
+            let linter = if default_config.is_file() {
+                Linter::from_config(&loader, default_config)?
+            } else {
+                Linter::new()
+            };
+            LanguageServer::new(loader)
+                .with_linter(linter)
+                .serve(std::io::stdin().lock(), std::io::stdout())
+        }
+        #[cfg(feature = "daemon")]
+        Some(Commands::Replay { session }) => handle_replay(loader, &session),
+        Some(Commands::Worker {
+            persistent_worker,
+            args,
+        }) => handle_worker(loader, persistent_worker, &args),
+        Some(Commands::Bundle { command }) => handle_bundle(command),
+        Some(Commands::Cache { command }) => handle_cache(command),
+        Some(Commands::Doctor { format }) => handle_doctor(&loader, format),
+        Some(Commands::InspectCapabilities {
+            file,
+            root,
+            evaluate,
+            format,
+        }) => handle_inspect_capabilities(&loader, &file, &root, evaluate, format, relative_root),
+        Some(Commands::Query { file, path, pretty }) => {
+            let value = loader.query(&file, &path)?;
+            println!("{}", json_string(&value, pretty.then_some(layout)));
+            Ok(())
+        }
+        Some(Commands::CompletePath { file, partial }) => {
+            let candidates = match query::FieldCache::default_dir() {
+                Some(dir) => query::FieldCache::new(dir).complete(&loader, &file, &partial)?,
+                None => {
+                    let (source, name) = read_named_source(&file)?;
+                    query::complete(&loader, &source, &name, &partial)?
+                }
+            };
+            for candidate in candidates {
+                println!("{}", candidate);
+            }
+            Ok(())
+        }
+        Some(Commands::Completions { shell }) => {
+            handle_completions(shell);
+            Ok(())
+        }
+        Some(Commands::Man { out_dir }) => handle_man(out_dir.as_deref()),
+        Some(Commands::Export {
+            file,
+            format,
+            output,
+            mode,
+            overrides,
+            doc_comments,
+            source_order,
+            watch,
+            emit_depfile,
+        }) => {
+            let loader = loader.clone().with_overrides(overrides);
+            let options = export::Options::new()
+                .with_comments(doc_comments)
+                .with_source_order(source_order)
+                .with_json_layout(layout);
+            let run = || {
+                let written = handle_export(
+                    &loader,
+                    &file,
+                    &format,
+                    output.as_deref(),
+                    mode,
+                    &options,
+                    cli.compress,
+                )?;
+                match &emit_depfile {
+                    Some(depfile) => {
+                        write_depfile(depfile, written, &file, loader.threads(), relative_root)
+                    }
+                    None => Ok(()),
+                }
+            };
+            if watch {
+                watch_loop(&loader, &file, run)
+            } else {
+                run()
+            }
+        }
+        Some(Commands::Build {
+            file,
+            out_dir,
+            only,
+            incremental,
+            emit_depfile,
+        }) => {
+            let written = handle_build(
+                &loader,
+                &file,
+                &out_dir,
+                &only,
+                incremental,
+                cli.remote_cache.as_deref(),
+                cli.compress,
+            )?;
+            match emit_depfile {
+                Some(depfile) => {
+                    write_depfile(&depfile, written, &file, loader.threads(), relative_root)
+                }
+                None => Ok(()),
+            }
+        }
+        Some(Commands::Diff { old, new, format }) => {
+            handle_config_diff(&loader, &old, &new, format)
+        }
+        Some(Commands::Doc {
+            file,
+            format,
+            output,
+        }) => handle_doc(&loader, &file, format, output.as_deref()),
+        Some(Commands::Schema { file, output }) => handle_schema(&loader, &file, output.as_deref()),
+        Some(Commands::WhyError { file, trace }) => handle_why_error(loader, &file, trace),
+        Some(Commands::Drift {
+            file,
+            live,
+            include_extra,
+            ignore,
+        }) => handle_drift(&loader, &file, &live, include_extra, &ignore),
+        Some(Commands::Mutate {
+            file,
+            policy,
+            min_score,
+        }) => handle_mutate(&loader, &file, &policy, min_score),
+        Some(Commands::Synth {
+            schema,
+            count,
+            seed,
+            out_dir,
+            no_check,
+        }) => handle_synth(&loader, &schema, count, seed, out_dir.as_deref(), no_check),
+        Some(Commands::Info { format }) => {
+            handle_info(format);
+            Ok(())
+        }
+        None => {
+            // No command specified, show help
+            println!("{}", get_help_text());
+            Ok(())
+        }
+    }
+}
+
+/// What `--harden` leaves reachable for `command`: its config, the files
+/// that imports, other inputs, and the directories of outputs
+fn sandbox_for(
+    command: Option<&Commands>,
+    remote_cache: Option<&str>,
+    threads: usize,
+) -> bunsenite::Result<Sandbox> {
+    fn inputs_of(file: &Path, threads: usize) -> bunsenite::Result<Sandbox> {
+        if is_stdin(file) || archive::split(&file.to_string_lossy()).is_some() {
+            return Err(bunsenite::Error::invalid_input(
+                "--harden does not take stdin or an ARCHIVE::ENTRY file",
+            ));
+        }
+        Ok(Sandbox::new().with_inputs_of(file, threads))
+    }
+    fn directory_of(file: &Path) -> &Path {
+        file.parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."))
+    }
+
+    match command {
+        Some(Commands::Parse(args)) if !args.watch => {
+            let mut sandbox = inputs_of(&args.file, threads)?;
+            if let Some(tenants) = &args.tenants {
+                for file in inputs_of(tenants, threads)?.readable() {
+                    sandbox = sandbox.with_readable(file);
+                }
+            }
+            if let Some(previous) = &args.diff_against {
+                sandbox = sandbox.with_readable(previous);
+            }
+            for output in args
+                .output
+                .iter()
+                .chain(&args.provenance)
+                .chain(&args.emit_depfile)
+            {
+                sandbox = sandbox.with_writable(directory_of(output));
+            }
+            if let Some(dir) = &args.out_dir {
+                sandbox = sandbox.with_writable(dir);
+            }
+            Ok(sandbox)
+        }
+        Some(Commands::Validate(args)) => {
+            let ValidateArgs {
+                file,
+                schemas,
+                incremental,
+                ..
+            } = args;
+            let spec = file.to_string_lossy();
+            let mut sandbox = if batch::is_glob(&spec) {
+                let mut sandbox = Sandbox::new();
+                for matched in batch::expand(&spec)? {
+                    for input in inputs_of(&matched, threads)?.readable() {
+                        sandbox = sandbox.with_readable(input);
+                    }
+                }
+                sandbox
+            } else {
+                inputs_of(file, threads)?
+            };
+            for spec in schemas {
+                let path = spec.split_once('#').map_or(spec.as_str(), |(path, _)| path);
+                sandbox = sandbox.with_readable(path);
+            }
+            if *incremental {
+                let cache = fresh_cache(None)?;
+                std::fs::create_dir_all(cache.dir())?;
+                sandbox = sandbox.with_writable(cache.dir());
+                if let Some(dir) = shared_cache_dir(remote_cache) {
+                    std::fs::create_dir_all(&dir)?;
+                    sandbox = sandbox.with_writable(dir);
+                }
+            }
+            Ok(sandbox)
+        }
+        Some(Commands::Typecheck { file, .. }) => inputs_of(file, threads),
+        Some(Commands::Export {
+            file,
+            output,
+            watch: false,
+            emit_depfile,
+            ..
+        }) => {
+            let mut sandbox = inputs_of(file, threads)?;
+            for output in output.iter().chain(emit_depfile) {
+                sandbox = sandbox.with_writable(directory_of(output));
+            }
+            Ok(sandbox)
+        }
+        _ => Err(bunsenite::Error::invalid_input(
+            "--harden works with parse, validate, typecheck and export, without --watch",
+        )),
+    }
+}
+
+/// Run `evaluate`, then again whenever `file` or one of its imports changes
+///
+/// Evaluation errors are printed and watching goes on; only a failing
+/// watcher ends the loop.
+fn watch_loop(
+    loader: &NickelLoader,
+    file: &Path,
+    mut evaluate: impl FnMut() -> bunsenite::Result<()>,
+) -> bunsenite::Result<()> {
+    if is_stdin(file) || archive::split(&file.to_string_lossy()).is_some() {
+        return Err(bunsenite::Error::invalid_input(
+            "--watch does not take stdin or an ARCHIVE::ENTRY file",
+        ));
+    }
+    let mut watch = Watch::new(file, loader.threads())?;
+    handle_interrupts(false);
+    loop {
+        if let Err(e) = evaluate() {
+            failure!("{}", e);
+        }
+        note!(
+            "Watching {} file(s) for changes (Ctrl-C to stop)",
+            watch.files().len()
+        );
+        let changed = match watch.wait() {
+            Err(bunsenite::Error::Interrupted(_)) => {
+                note!("Stopped watching");
+                process::exit(interrupt::EXIT_CODE);
+            }
+            changed => changed?,
+        };
+        let changed: Vec<String> = changed
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect();
+        note!("\n↻ {} changed", changed.join(", "));
+    }
+}
+
+fn handle_parse(
+    loader: &NickelLoader,
+    args: ParseArgs,
+    compat: bool,
+    verbose: bool,
+    compression: Option<Compression>,
+    layout: json::Layout,
+    relative_root: Option<&Path>,
+) -> bunsenite::Result<()> {
+    let ParseArgs {
+        file,
+        name,
+        pretty,
+        output,
+        mode,
+        show_defaults,
+        diff_against,
+        restrict,
+        include_path,
+        exclude_path,
+        mask_values,
+        tenants,
+        out_dir,
+        overrides,
+        target,
+        field,
+        provenance,
+        emit_depfile,
+        source_order,
+        watch: _,
+    } = args;
+    let policy = Policy::parse(&restrict)?;
+    let mut loader = loader
+        .clone()
+        .with_overrides(overrides)
+        .with_path_filter(PathFilter::parse(&include_path, &exclude_path)?);
+    if mask_values {
+        loader = loader.with_mask_values(true).with_transform(MaskValues);
+    }
+    let target = target.as_deref().map(Target::parse).transpose()?;
+    if let Some(target) = &target {
+        loader = loader.with_target(target.clone());
+    }
+    let loader = &loader;
+
+    if verbose {
+        note!("Parsing file: {}", file.display());
+    }
+
+    let stdin = if is_stdin(&file) {
+        if diff_against.is_some()
+            || tenants.is_some()
+            || provenance.is_some()
+            || emit_depfile.is_some()
+        {
+            return Err(bunsenite::Error::invalid_input(
+                "--diff-against, --tenants, --provenance and --emit-depfile do not take stdin",
+            ));
+        }
+        Some(read_stdin_source(name.as_deref())?)
+    } else {
+        None
+    };
+    let bundle = match &stdin {
+        Some(_) => None,
+        None => Bundle::open_spec(&file.to_string_lossy())?,
+    };
+    if bundle.is_some() && (diff_against.is_some() || tenants.is_some()) {
+        return Err(bunsenite::Error::invalid_input(
+            "--diff-against and --tenants do not take an ARCHIVE::ENTRY file",
+        ));
+    }
+    let config = file.clone();
+    let file = bundle.as_ref().map_or(file, Bundle::path);
+    let named_source = || match (&stdin, &bundle) {
+        (Some(stdin), _) => Ok(stdin.clone()),
+        (None, Some(bundle)) => bundle.source(),
+        (None, None) => read_named_source(&file),
+    };
+
+    if compat {
+        let (source, name) = named_source()?;
+        let shown = if stdin.is_some() {
+            Path::new(&name)
+        } else {
+            file.as_path()
+        };
+        report_compat(shown, &source);
+    }
+    if let Some(previous) = diff_against {
+        return handle_diff(loader, &file, &previous);
+    }
+    if let Some(tenants) = tenants {
+        return handle_tenants(
+            loader,
+            &file,
+            &tenants,
+            out_dir,
+            pretty.then_some(layout),
+            &policy,
+            compression,
+        );
+    }
+
+    if let Some(field) = field {
+        let value = if stdin.is_some() || bundle.is_some() {
+            let (source, name) = named_source()?;
+            query::query(loader, &source, &name, &field)?
+        } else {
+            loader.query(&file, &field)?
+        };
+        println!("{}", json_string(&value, pretty.then_some(layout)));
+        return Ok(());
+    }
+
+    let mut document = match (&stdin, &bundle) {
+        (Some((source, name)), _) => loader.parse_document(source, name)?,
+        (None, Some(bundle)) => bundle.parse_document(loader)?,
+        (None, None) => loader.parse_file_document(&file)?,
+    };
+    if source_order {
+        let (source, name) = named_source()?;
+        document.reorder(&loader.field_order(&source, &name)?);
+    }
+    if !policy.is_empty() {
+        let value = document.to_value();
+        let checked = policy.check(&value, "json");
+        json::drop_deep(value);
+        checked?;
+    }
+
+    match &output {
+        Some(path) => {
+            let mut json = Vec::new();
+            write_document(&document, &mut json, pretty.then_some(&layout))?;
+            let path = compress::write_with_mode(path, &json, compression, mode)?;
+            if verbose {
+                status!("Wrote {}", path.display());
+            }
+            if let Some(depfile) = &emit_depfile {
+                write_depfile(
+                    depfile,
+                    vec![path],
+                    &config,
+                    loader.threads(),
+                    relative_root,
+                )?;
+            }
+        }
+        None => {
+            let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+            write_document(&document, &mut out, pretty.then_some(&layout))?;
+            out.flush()?;
+        }
+    }
+
+    if let Some(path) = provenance {
+        let value = document.to_value();
+        let record = Provenance::new(&file, target.as_ref(), &value, loader.threads())?;
+        let record = match relative_root {
+            Some(root) => record.relative_to(root),
+            None => record,
+        };
+        json::drop_deep(value);
+        std::fs::write(&path, json::to_string(&record.to_json(), true) + "\n")?;
+    }
+
+    if show_defaults {
+        let (source, name) = named_source()?;
+        let defaults = loader.default_paths(&source, &name)?;
+
+        if defaults.is_empty() {
+            note!("No values come from contract defaults");
+        } else {
+            note!("Values from contract defaults:");
+            for path in defaults {
+                note!("  {}", path);
+            }
+        }
+    }
+
+    if verbose {
+        status!("Successfully parsed and evaluated");
+    }
+
+    Ok(())
+}
+
+fn handle_diff(loader: &NickelLoader, file: &Path, previous: &Path) -> bunsenite::Result<()> {
+    let old = read_json_artifact(previous)?;
+    let new = loader.parse_file(file)?;
+
+    let changes = diff::diff(&old, &new);
+    json::drop_deep(new);
+    if changes.is_empty() {
+        status!("No changes against {}", previous.display());
+        return Ok(());
+    }
+
+    let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+    for change in &changes {
+        writeln!(out, "{}", change)?;
+    }
+    out.flush()?;
+
+    failure!(
+        "\n{} path(s) differ from {}",
+        changes.len(),
+        previous.display()
+    );
+    process::exit(1);
+}
+
+fn handle_config_diff(
+    loader: &NickelLoader,
+    old: &Path,
+    new: &Path,
+    format: InfoFormat,
+) -> bunsenite::Result<()> {
+    let old_value = loader.parse_file(old)?;
+    let new_value = loader.parse_file(new)?;
+    let changes = diff::diff(&old_value, &new_value);
+    json::drop_deep(old_value);
+    json::drop_deep(new_value);
+
+    match format {
+        InfoFormat::Json => println!("{}", json::to_string(&diff::to_json(&changes), true)),
+        InfoFormat::Text => {
+            let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+            for change in &changes {
+                writeln!(out, "{}", change)?;
+            }
+            out.flush()?;
+            if changes.is_empty() {
+                status!("{} and {} evaluate the same", old.display(), new.display());
+            } else {
+                failure!("\n{} path(s) differ", changes.len());
+            }
+        }
+    }
+    if !changes.is_empty() {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+fn handle_doc(
+    loader: &NickelLoader,
+    file: &Path,
+    format: DocFormat,
+    output: Option<&Path>,
+) -> bunsenite::Result<()> {
+    let (source, name) = read_named_source(file)?;
+    let fields = loader.extract_metadata(&source, &name)?;
+    let rendered = match format {
+        DocFormat::Markdown => docs::to_markdown(&name, &fields),
+        DocFormat::Json => format!("{}\n", json::to_string(&docs::to_json(&fields), true)),
+    };
+
+    match output {
+        Some(path) => {
+            std::fs::write(path, &rendered)?;
+            status!("{} field(s) documented -> {}", fields.len(), path.display());
+        }
+        None => print!("{}", rendered),
+    }
+    Ok(())
+}
+
+fn handle_init(
+    dir: &Path,
+    template: Template,
+    name: Option<&str>,
+    force: bool,
+) -> bunsenite::Result<()> {
+    let written = scaffold::init(dir, template, name, force)?;
+    for path in &written {
+        status!("Created {}", path.display());
+    }
+    note!("\nNext: bunsenite parse {}", dir.join("main.ncl").display());
+    Ok(())
+}
+
+fn handle_schema(
+    loader: &NickelLoader,
+    file: &Path,
+    output: Option<&Path>,
+) -> bunsenite::Result<()> {
+    let (source, name) = read_named_source(file)?;
+    let fields = loader.extract_metadata(&source, &name)?;
+    let rendered = format!(
+        "{}\n",
+        json::to_string(&schema::to_json_schema(&name, &fields), true)
+    );
+
+    match output {
+        Some(path) => {
+            std::fs::write(path, &rendered)?;
+            status!(
+                "JSON Schema of {} field(s) -> {}",
+                fields.len(),
+                path.display()
+            );
+        }
+        None => print!("{}", rendered),
+    }
+    Ok(())
+}
+
+fn handle_why_error(loader: NickelLoader, file: &Path, trace: bool) -> bunsenite::Result<()> {
+    use std::io::IsTerminal;
+
+    let explainer = Explainer::new(loader);
+    let root = explainer.file(file)?;
+    if root.error.is_none() {
+        passed!("{} evaluates without error", file.display());
+        return Ok(());
+    }
+
+    if trace || !std::io::stdin().is_terminal() {
+        for (node, explanation) in explainer.trace(root) {
+            print!("{}", explanation.render(&node));
+        }
+    } else {
+        explainer.walk(root, std::io::stdin().lock(), std::io::stdout())?;
+    }
+    process::exit(1);
+}
+
+fn handle_tenants(
+    loader: &NickelLoader,
+    file: &Path,
+    tenants: &Path,
+    out_dir: Option<PathBuf>,
+    pretty: Option<json::Layout>,
+    policy: &Policy,
+    compression: Option<Compression>,
+) -> bunsenite::Result<()> {
+    let params = schema::load_sample(loader, tenants)?;
+    let template = std::fs::read_to_string(file)?;
+    let name = file.to_string_lossy();
+
+    let results = tenant::evaluate(loader, &template, &name, &params)?;
+    let mut outputs = serde_json::Map::new();
+    let mut failed = 0;
+    for (tenant, result) in results {
+        match result.and_then(|value| policy.check(&value, "json").map(|()| value)) {
+            Ok(value) => {
+                outputs.insert(tenant, value);
+            }
+            Err(e) => {
+                failure!("{}: {}", tenant, e);
+                failed += 1;
+            }
+        }
+    }
+
+    match out_dir {
+        Some(dir) => {
+            std::fs::create_dir_all(&dir)?;
+            for (tenant, value) in &outputs {
+                let path = dir.join(format!("{}.json", tenant));
+                let contents = json_string(value, pretty) + "\n";
+                compress::write(&path, contents.as_bytes(), compression)?;
+            }
+            status!("{} tenant(s) written to {}", outputs.len(), dir.display());
+        }
+        None => println!("{}", json_string(&outputs.into(), pretty)),
+    }
+
+    if failed > 0 {
+        failure!("\n{} tenant(s) failed", failed);
+        process::exit(1);
+    }
+
+    Ok(())
+}
+
+fn handle_expand(
+    loader: &NickelLoader,
+    args: ExpandArgs,
+    layout: json::Layout,
+    relative_root: Option<&Path>,
+    compression: Option<Compression>,
+) -> bunsenite::Result<()> {
+    let ExpandArgs {
+        file,
+        matrix,
+        out_dir,
+        output,
+        pretty,
+    } = args;
+    let pretty = pretty.then_some(layout);
+    let mut definition = Matrix::load(loader, &matrix)?;
+    if output.is_some() {
+        definition.output = output;
+    }
+    let entries = definition.expand()?;
+    let template = std::fs::read_to_string(&file)?;
+    let name = file.to_string_lossy();
+
+    let results = bunsenite::matrix::evaluate(loader, &template, &name, &entries);
+
+    std::fs::create_dir_all(&out_dir)?;
+    let mut manifest = Vec::with_capacity(entries.len());
+    let mut failed = 0;
+    for (entry, result) in entries.into_iter().zip(results) {
+        let output = match compression {
+            Some(compression) => compression
+                .path(Path::new(&entry.path))
+                .to_string_lossy()
+                .into_owned(),
+            None => entry.path.clone(),
+        };
+        let error = match result {
+            Ok(value) => {
+                let path = out_dir.join(&output);
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                let contents = json_string(&value, pretty) + "\n";
+                compress::write(&path, contents.as_bytes(), compression)?;
+                None
+            }
+            Err(e) => {
+                failure!("{}: {}", entry.path, e);
+                failed += 1;
+                Some(e.to_string())
+            }
+        };
+        manifest.push(serde_json::json!({
+            "combination": entry.combination,
+            "path": output,
+            "status": if error.is_some() { "error" } else { "ok" },
+            "error": error,
+        }));
+    }
+
+    let total = manifest.len();
+    let manifest = serde_json::json!({
+        "entrypoint": shown(&file, relative_root).display().to_string(),
+        "matrix": shown(&matrix, relative_root).display().to_string(),
+        "outputs": manifest,
+    });
+    std::fs::write(
+        out_dir.join("manifest.json"),
+        json::to_string(&manifest, true) + "\n",
+    )?;
+
+    status!(
+        "{} of {} combination(s) written to {}",
+        total - failed,
+        total,
+        out_dir.display()
+    );
+    if failed > 0 {
+        failure!("\n{} combination(s) failed", failed);
+        process::exit(1);
+    }
+
+    Ok(())
+}
+
+/// `value` as JSON, pretty-printed in a layout if given
+fn json_string(value: &serde_json::Value, pretty: Option<json::Layout>) -> String {
+    match pretty {
+        Some(layout) => json::to_string_with(value, &layout),
+        None => json::to_string(value, false),
+    }
+}
+
+/// Write `document` as JSON and a newline, laid out as `pretty` if given
+fn write_document<W: Write>(
+    document: &Document,
+    out: &mut W,
+    pretty: Option<&json::Layout>,
+) -> bunsenite::Result<()> {
+    match pretty {
+        Some(layout) => document.write_json_with(&mut *out, layout)?,
+        None => document.write_json(&mut *out, false)?,
+    }
+    writeln!(out)?;
+    Ok(())
+}
+
+/// Whether `file` is `-`, standing for stdin
+fn is_stdin(file: &Path) -> bool {
+    file.as_os_str() == "-"
+}
+
+/// Read stdin as the source of a config named `name` (`stdin.ncl` if unset)
+fn read_stdin_source(name: Option<&str>) -> bunsenite::Result<(String, String)> {
+    let mut source = String::new();
+    std::io::stdin().read_to_string(&mut source)?;
+    Ok((source, name.unwrap_or("stdin.ncl").to_string()))
+}
+
+/// Read a source file with the name used in diagnostics
+///
+/// The name is the path as given, since Nickel resolves relative imports
+/// against its directory part.
+fn read_named_source(file: &Path) -> bunsenite::Result<(String, String)> {
+    let source = std::fs::read_to_string(file)?;
+    Ok((source, file.to_string_lossy().into_owned()))
+}
+
+/// Read a previously exported JSON artifact, decompressing it if needed
+fn read_json_artifact(path: &Path) -> bunsenite::Result<serde_json::Value> {
+    let contents = compress::read_to_string(path)?;
+    serde_json::from_str(&contents).map_err(|e| {
+        bunsenite::Error::invalid_input(format!(
+            "Invalid JSON artifact '{}': {}",
+            path.display(),
+            e
+        ))
+    })
+}
+
+fn handle_validate(
+    loader: &NickelLoader,
+    args: ValidateArgs,
+    compat: bool,
+    verbose: bool,
+    error_format: ErrorFormat,
+    remote_cache: Option<&str>,
+    relative_root: Option<&Path>,
+) -> bunsenite::Result<()> {
+    let ValidateArgs {
+        file,
+        name: stdin_name,
+        schemas,
+        incremental,
+    } = args;
+    let stdin_name = stdin_name.as_deref();
+    let cache = if incremental {
+        Some(fresh_cache(remote_cache)?)
+    } else {
+        None
+    };
+    let spec = file.to_string_lossy();
+    if batch::is_glob(&spec) {
+        if !schemas.is_empty() || stdin_name.is_some() {
+            return Err(bunsenite::Error::invalid_input(
+                "--schema and --name take a single FILE, not a glob",
+            ));
+        }
+        return validate_glob(
+            loader,
+            &spec,
+            cache.as_ref(),
+            verbose,
+            error_format,
+            relative_root,
+        );
+    }
+    if verbose {
+        note!("Validating file: {}", file.display());
+    }
+
+    let Some(cache) = cache else {
+        let (source, name) = read_checked_source(&file, stdin_name, compat)?;
+        located(loader.validate(&source, &name), &source, error_format)?;
+        if schemas.is_empty() {
+            passed!("Configuration is valid");
+            return Ok(());
+        }
+        return check_schemas(loader, &source, &name, &schemas);
+    };
+
+    if is_stdin(&file) || archive::split(&spec).is_some() {
+        return Err(bunsenite::Error::invalid_input(
+            "--incremental does not take stdin or an ARCHIVE::ENTRY file",
+        ));
+    }
+    let fingerprint = Fingerprint::of(loader, &file)?;
+    if cache.is_fresh(VALIDATE_TASK, &file, &fingerprint) {
+        passed!("Configuration is valid (fresh)");
+        return Ok(());
+    }
+    let (source, name) = read_checked_source(&file, stdin_name, compat)?;
+    located(loader.validate(&source, &name), &source, error_format)?;
+    record_fresh(&cache, VALIDATE_TASK, &file, &fingerprint, &[]);
+    trim_cache();
+    passed!("Configuration is valid");
+    Ok(())
+}
+
+/// Task `validate --incremental` records in the [`FreshCache`]
+const VALIDATE_TASK: &str = "validate";
+
+/// The cache behind `--incremental`, sharing results through
+/// `remote_cache` or `$BUNSENITE_REMOTE_CACHE` if given
+fn fresh_cache(remote_cache: Option<&str>) -> bunsenite::Result<FreshCache> {
+    let fresh = FreshCache::default_dir()
+        .map(FreshCache::new)
+        .ok_or_else(|| {
+            bunsenite::Error::invalid_input(
+                "--incremental needs a cache directory; set BUNSENITE_CACHE_DIR",
+            )
+        })?;
+    match remote_cache_url(remote_cache) {
+        Some(url) => Ok(fresh.with_remote(cache::backend_for(&url)?)),
+        None => Ok(fresh),
+    }
+}
+
+/// `--remote-cache`, else `$BUNSENITE_REMOTE_CACHE`
+fn remote_cache_url(remote_cache: Option<&str>) -> Option<String> {
+    remote_cache
+        .map(str::to_string)
+        .or_else(|| std::env::var(cache::REMOTE_ENV).ok())
+        .filter(|url| !url.is_empty())
+}
+
+/// The directory of a shared cache that is not reached over the network
+fn shared_cache_dir(remote_cache: Option<&str>) -> Option<PathBuf> {
+    remote_cache_url(remote_cache)
+        .filter(|url| !url.contains("://"))
+        .map(PathBuf::from)
+}
+
+/// Record a success in the `--incremental` cache
+///
+/// Failing to record only warns: the command itself succeeded.
+fn record_fresh(
+    cache: &FreshCache,
+    task: &str,
+    file: &Path,
+    fingerprint: &Fingerprint,
+    outputs: &[PathBuf],
+) {
+    if let Err(e) = cache.record(task, file, fingerprint, outputs) {
+        warning!("cannot record {} as fresh: {}", file.display(), e);
+    }
+}
+
+/// Validate every file matching `pattern` and print a pass/fail table,
+/// exiting 1 if any fails
+///
+/// With a `cache`, files that are fresh in it are skipped and files that
+/// pass are recorded.
+fn validate_glob(
+    loader: &NickelLoader,
+    pattern: &str,
+    cache: Option<&FreshCache>,
+    verbose: bool,
+    error_format: ErrorFormat,
+    relative_root: Option<&Path>,
+) -> bunsenite::Result<()> {
+    let files = batch::expand(pattern)?;
+    if verbose {
+        note!("Validating {} file(s) matching {}", files.len(), pattern);
+    }
+    handle_interrupts(false);
+
+    // Files whose fingerprint cannot be computed are validated, which
+    // reports why
+    let fingerprints: Vec<Option<Fingerprint>> = files
+        .iter()
+        .map(|file| cache.and_then(|_| Fingerprint::of(loader, file).ok()))
+        .collect();
+    let fresh: Vec<bool> = files
+        .iter()
+        .zip(&fingerprints)
+        .map(|(file, fingerprint)| match (cache, fingerprint) {
+            (Some(cache), Some(fingerprint)) => cache.is_fresh(VALIDATE_TASK, file, fingerprint),
+            _ => false,
+        })
+        .collect();
+    let stale: Vec<&PathBuf> = files
+        .iter()
+        .zip(&fresh)
+        .filter(|(_, fresh)| !**fresh)
+        .map(|(file, _)| file)
+        .collect();
+    if verbose && cache.is_some() {
+        note!("{} file(s) fresh", files.len() - stale.len());
+    }
+
+    let mut results = loader.validate_many(&stale).into_iter();
+    let mut failed = 0;
+    let mut skipped = 0;
+    let mut unchecked = 0;
+    if !porcelain() {
+        println!("{:<6} FILE", "RESULT");
+    }
+    let total = files.len();
+    for ((file, fingerprint), fresh) in files.iter().zip(&fingerprints).zip(fresh) {
+        let path = shown(file, relative_root);
+        if fresh {
+            skipped += 1;
+            validate_row("fresh", &path);
+            continue;
+        }
+        let (_, result) = results.next().expect("one result per stale file");
+        match result {
+            Ok(()) => {
+                if let (Some(cache), Some(fingerprint)) = (cache, fingerprint) {
+                    record_fresh(cache, VALIDATE_TASK, file, fingerprint, &[]);
+                }
+                validate_row("pass", &path);
+            }
+            Err(bunsenite::Error::Interrupted(_)) => {
+                unchecked += 1;
+                validate_row("unchecked", &path);
+            }
+            Err(e) => {
+                failed += 1;
+                validate_row("fail", &path);
+                let e = match relative_root {
+                    Some(root) => e.relative_to(root),
+                    None => e,
+                };
+                match error_format {
+                    ErrorFormat::Human => eprintln!("       {}", e),
+                    ErrorFormat::Json => {
+                        let source = std::fs::read_to_string(file).ok();
+                        print_diagnostic(&e, source.as_deref());
+                    }
+                }
+            }
+        }
+    }
+
+    let passed = total - failed - unchecked;
+    let passed = match cache {
+        Some(_) => format!("{} passed ({} fresh)", passed, skipped),
+        None => format!("{} passed", passed),
+    };
+    if unchecked > 0 {
+        summary!(
+            "\n{}, {} failed, {} not checked, {} total",
+            passed,
+            failed,
+            unchecked,
+            total
+        );
+        return Err(bunsenite::Error::Interrupted(format!(
+            "{} of {} file(s) not checked",
+            unchecked, total
+        )));
+    }
+    summary!("\n{}, {} failed, {} total", passed, failed, total);
+    if cache.is_some() {
+        trim_cache();
+    }
+    if failed > 0 {
+        failure!("{} of {} file(s) failed validation", failed, total);
+        process::exit(1);
+    }
+    passed!("All configurations are valid");
+    Ok(())
+}
+
+/// A row of the `validate` report: `result` (`pass`, `fail`, `fresh` or
+/// `unchecked`) for `path`
+fn validate_row(result: &str, path: &Path) {
+    let label = match result {
+        "fail" => "FAIL",
+        "unchecked" => "-",
+        result => result,
+    };
+    let subject = path.display().to_string();
+    report_result(
+        &subject,
+        result,
+        None,
+        format_args!("{:<6} {}", label, subject),
+    );
+}
+
+fn handle_typecheck(
+    loader: &NickelLoader,
+    file: PathBuf,
+    stdin_name: Option<&str>,
+    compat: bool,
+    verbose: bool,
+    error_format: ErrorFormat,
+) -> bunsenite::Result<()> {
+    if verbose {
+        note!("Typechecking file: {}", file.display());
+    }
+
+    let (source, name) = read_checked_source(&file, stdin_name, compat)?;
+    if let Err(e) = located(loader.typecheck(&source, &name), &source, error_format) {
+        if let (Some((line, column)), Some((start, end))) = (e.location(&source), e.span()) {
+            let text = source.lines().nth(line - 1).unwrap_or_default();
+            let width = source
+                .get(start..end)
+                .and_then(|span| span.lines().next())
+                .map_or(1, |first| first.chars().count().max(1));
+            eprintln!("{}:{}:{}: type error", name, line, column);
+            eprintln!("    {}", text);
+            eprintln!("    {}{}\n", " ".repeat(column - 1), "^".repeat(width));
+        }
+        return Err(e);
+    }
+    passed!("No type errors");
+    Ok(())
+}
+
+/// `path` as reports show it: relative to the project root with
+/// `--relative-paths`
+fn shown(path: &Path, relative_root: Option<&Path>) -> PathBuf {
+    match relative_root {
+        Some(root) => paths::relative_to(path, root),
+        None => path.to_path_buf(),
+    }
+}
+
+/// Read the config checked by `validate` or `typecheck`: a file, an archive
+/// entry or stdin, reporting compatibility issues with `--compat`
+fn read_checked_source(
+    file: &Path,
+    stdin_name: Option<&str>,
+    compat: bool,
+) -> bunsenite::Result<(String, String)> {
+    let (source, name) = if is_stdin(file) {
+        read_stdin_source(stdin_name)?
+    } else {
+        match Bundle::open_spec(&file.to_string_lossy())? {
+            Some(bundle) => {
+                let (source, _) = bundle.source()?;
+                (source, bundle.name())
+            }
+            None => read_named_source(file)?,
+        }
+    };
+
+    if compat {
+        let shown = if is_stdin(file) {
+            Path::new(&name)
+        } else {
+            file
+        };
+        report_compat(shown, &source);
+    }
+    Ok((source, name))
+}
+
+/// Check the output of a valid config against each `--schema`, exiting 1
+/// on violations
+#[cfg(feature = "schemas")]
+fn check_schemas(
+    loader: &NickelLoader,
+    source: &str,
+    name: &str,
+    schemas: &[String],
+) -> bunsenite::Result<()> {
+    let schemas = schemas
+        .iter()
+        .map(|spec| Ok((spec, Schema::load(spec)?)))
+        .collect::<bunsenite::Result<Vec<_>>>()?;
+    let value = loader.parse_string(source, name)?;
+    let mut failed = 0;
+    for (spec, schema) in &schemas {
+        let violations = schema.check(&value);
+        for violation in &violations {
+            failure!("{}: {}", spec, violation);
+        }
+        failed += usize::from(!violations.is_empty());
+    }
+    json::drop_deep(value);
+
+    if failed > 0 {
+        failure!(
+            "\nOutput does not match {} of {} schema(s)",
+            failed,
+            schemas.len()
+        );
+        process::exit(1);
+    }
+    passed!(
+        "Configuration is valid and matches {} schema(s)",
+        schemas.len()
+    );
+    Ok(())
+}
+
+#[cfg(not(feature = "schemas"))]
+fn check_schemas(
+    _loader: &NickelLoader,
+    _source: &str,
+    _name: &str,
+    _schemas: &[String],
+) -> bunsenite::Result<()> {
+    Err(bunsenite::Error::invalid_input(
+        "Cannot check --schema: bunsenite was built without schema validation (the `schemas` feature)",
+    ))
+}
+
+/// Print a warning for each deprecated stdlib name `--compat` rewrites
+fn report_compat(file: &Path, source: &str) {
+    for warning in compat::translate(source).1 {
+        warning!("{}:{}", file.display(), warning);
+    }
+}
+
+fn handle_fmt(files: &[PathBuf], check: bool) -> bunsenite::Result<()> {
+    let mut unformatted = 0;
+    for file in files {
+        if is_stdin(file) {
+            let mut source = String::new();
+            std::io::stdin().read_to_string(&mut source)?;
+            let formatted = fmt::format_source(&source)?;
+            if check {
+                if formatted != source {
+                    failure!("<stdin> is not formatted");
+                    unformatted += 1;
+                }
+            } else {
+                print!("{}", formatted);
+            }
+            continue;
+        }
+
+        let source = std::fs::read_to_string(file)?;
+        let formatted = fmt::format_source(&source).map_err(|e| {
+            bunsenite::Error::invalid_input(format!("Cannot format {}: {}", file.display(), e))
+        })?;
+        if formatted == source {
+            continue;
+        }
+        if check {
+            failure!("{} is not formatted", file.display());
+            unformatted += 1;
+        } else {
+            std::fs::write(file, formatted)?;
+            status!("Formatted {}", file.display());
+        }
+    }
+
+    if unformatted > 0 {
+        failure!(
+            "\n{} file(s) need formatting (run `bunsenite fmt`)",
+            unformatted
+        );
+        process::exit(1);
+    }
+    Ok(())
+}
+
+fn handle_mask(file: &Path) -> bunsenite::Result<()> {
+    let (source, _) = if is_stdin(file) {
+        read_stdin_source(None)?
+    } else {
+        read_named_source(file)?
+    };
+    print!("{}", mask::mask_source(&source)?);
+    Ok(())
+}
+
+fn handle_lint(
+    loader: &NickelLoader,
+    files: &[PathBuf],
+    enable: &[lint::Rule],
+    disable: &[lint::Rule],
+    config: Option<&Path>,
+) -> bunsenite::Result<()> {
+    let default_config = Path::new(lint::CONFIG_FILE);
+    let mut linter = match config {
+        Some(path) => Linter::from_config(loader, path)?,
+        None if default_config.is_file() => Linter::from_config(loader, default_config)?,
+        None => Linter::new(),
+    };
+    for rule in enable {
+        linter = linter.with_rule(*rule, true);
+    }
+    for rule in disable {
+        linter = linter.with_rule(*rule, false);
+    }
+
+    let mut found = 0;
+    for file in files {
+        let findings = linter.lint_file(file).map_err(|e| {
+            bunsenite::Error::invalid_input(format!("Cannot lint {}: {}", file.display(), e))
+        })?;
+        for finding in &findings {
+            println!(
+                "{}:{}: {} [{}]",
+                file.display(),
+                finding.line,
+                finding.message,
+                finding.rule
+            );
+        }
+        found += findings.len();
+    }
+
+    if found > 0 {
+        failure!("\n{} problem(s) in {} file(s)", found, files.len());
+        process::exit(1);
+    }
+    status!("No problems in {} file(s)", files.len());
+    Ok(())
+}
+
+fn handle_bench(
+    files: Vec<PathBuf>,
+    iterations: u32,
+    baseline: Option<PathBuf>,
+    save_baseline: Option<PathBuf>,
+    threshold: f64,
+) -> bunsenite::Result<()> {
+    let loader = NickelLoader::new();
+    let mut measurements = Vec::new();
+    for file in &files {
+        measurements.extend(bench::run(&loader, file, iterations)?);
+    }
+
+    println!(
+        "{:<40} {:<10} {:>12} {:>12}",
+        "FILE", "PHASE", "MEAN", "MIN"
+    );
+    for m in &measurements {
+        println!(
+            "{:<40} {:<10} {:>12} {:>12}",
+            m.file,
+            m.phase,
+            format_ns(m.mean_ns),
+            format_ns(m.min_ns)
+        );
+    }
+
+    if let Some(path) = save_baseline {
+        Baseline::new(measurements.clone()).save(&path)?;
+        status!("Baseline saved to {}", path.display());
+    }
+
+    if let Some(path) = baseline {
+        let comparisons = Baseline::load(&path)?.compare(&measurements, threshold);
+        let regressions = comparisons.iter().filter(|c| c.regressed).count();
+
+        println!();
+        println!(
+            "{:<40} {:<10} {:>12} {:>12} {:>9}",
+            "FILE", "PHASE", "BASELINE", "CURRENT", "CHANGE"
+        );
+        for c in &comparisons {
+            println!(
+                "{:<40} {:<10} {:>12} {:>12} {:>+8.1}%{}",
+                c.file,
+                c.phase,
+                format_ns(c.baseline_ns),
+                format_ns(c.current_ns),
+                c.change_percent,
+                if c.regressed { "  REGRESSED" } else { "" }
+            );
+        }
+
+        if regressions > 0 {
+            failure!(
+                "\n{} measurement(s) regressed by more than {}%",
+                regressions,
+                threshold
+            );
+            process::exit(1);
+        }
+        status!("\nNo regressions beyond {}%", threshold);
+    }
+
+    Ok(())
+}
+
+fn handle_vendor(loader: &NickelLoader, file: &Path, out: &Path) -> bunsenite::Result<()> {
+    let vendored = vendor::vendor(file, out, loader.threads())?;
+    status!(
+        "Vendored {} file(s) into {} ({} import(s) rewritten)",
+        vendored.files.len(),
+        out.display(),
+        vendored.rewritten
+    );
+    note!(
+        "Evaluate with: bunsenite parse {}",
+        vendored.entry.display()
+    );
+    Ok(())
+}
+
+fn handle_deps(
+    loader: &NickelLoader,
+    file: &Path,
+    format: DepsFormat,
+    output: Option<&Path>,
+    relative_root: Option<&Path>,
+) -> bunsenite::Result<()> {
+    let mut graph = imports::prefetch(file, loader.threads());
+    if graph.files.is_empty() {
+        // The config itself is unreadable; report why
+        std::fs::read(file)?;
+    }
+    if let Some(root) = relative_root {
+        graph = graph.relative_to(root);
+    }
+    let rendered = match format {
+        DepsFormat::Tree => graph.to_tree(&graph.files[0]),
+        DepsFormat::Json => format!("{}\n", json::to_string(&graph.to_json(), true)),
+        DepsFormat::Dot => graph.to_dot(),
+    };
+    if !graph.missing.is_empty() {
+        warning!(
+            "{} import(s) cannot be read: {}",
+            graph.missing.len(),
+            graph
+                .missing
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+
+    match output {
+        Some(path) => {
+            std::fs::write(path, &rendered)?;
+            status!("{} file(s) -> {}", graph.files.len(), path.display());
+        }
+        None => print!("{}", rendered),
+    }
+    Ok(())
+}
+
+fn handle_trace(
+    loader: &NickelLoader,
+    file: &Path,
+    format: TraceFormat,
+    iterations: u32,
+    output: Option<&Path>,
+) -> bunsenite::Result<()> {
+    let profile = profile::run(loader, file, iterations)?;
+    let rendered = match format {
+        TraceFormat::Summary => trace_summary(&profile),
+        TraceFormat::Chrome => format!("{}\n", json::to_string(&profile.to_chrome_trace(), false)),
+    };
+
+    match output {
+        Some(path) => {
+            std::fs::write(path, &rendered)?;
+            status!("Profile of {} -> {}", file.display(), path.display());
+        }
+        None => print!("{}", rendered),
+    }
+    Ok(())
 }
 
-#[derive(Subcommand)]
-enum Commands {
-    /// Parse and evaluate a Nickel configuration file
-    Parse {
-        /// Path to the Nickel configuration file
-        #[arg(value_name = "FILE")]
-        file: PathBuf,
+/// `trace` output for people: phases, the import tree and fields
+fn trace_summary(profile: &Profile) -> String {
+    let ns = |time: Duration| format_ns(time.as_nanos() as u64);
+    let mut out = format!("{:<40} {:>12}\n", "PHASE", "TIME");
+    for (phase, time) in &profile.phases {
+        out += &format!("{:<40} {:>12}\n", phase, ns(*time));
+    }
 
-        /// Pretty-print the output JSON
-        #[arg(short, long)]
-        pretty: bool,
-    },
+    out += &format!("\n{:<40} {:>12} {:>12}\n", "IMPORT", "TOTAL", "SELF");
+    for import in &profile.imports {
+        let name = format!("{}{}", "  ".repeat(import.depth), import.path.display());
+        match &import.error {
+            Some(error) => out += &format!("{:<40} {:>12} {:>12}  {}\n", name, "-", "-", error),
+            None => {
+                out += &format!(
+                    "{:<40} {:>12} {:>12}\n",
+                    name,
+                    ns(import.total),
+                    ns(import.self_time)
+                )
+            }
+        }
+    }
 
-    /// Validate a Nickel configuration without evaluating it
-    Validate {
-        /// Path to the Nickel configuration file
-        #[arg(value_name = "FILE")]
-        file: PathBuf,
-    },
+    if !profile.fields.is_empty() {
+        out += &format!("\n{:<40} {:>12}\n", "FIELD", "TIME");
+        for field in &profile.fields {
+            match &field.error {
+                Some(error) => out += &format!("{:<40} {:>12}  {}\n", field.name, "-", error),
+                None => out += &format!("{:<40} {:>12}\n", field.name, ns(field.time)),
+            }
+        }
+    }
+    out
+}
 
-    /// Show version and compliance information
-    Info,
+/// Format a nanosecond duration with a readable unit
+fn format_ns(ns: u64) -> String {
+    match ns {
+        0..=9_999 => format!("{} ns", ns),
+        10_000..=9_999_999 => format!("{:.1} µs", ns as f64 / 1e3),
+        10_000_000..=999_999_999 => format!("{:.1} ms", ns as f64 / 1e6),
+        _ => format!("{:.2} s", ns as f64 / 1e9),
+    }
 }
 
-fn main() {
-    let cli = Cli::parse();
+fn handle_test(
+    loader: &NickelLoader,
+    files: &[PathBuf],
+    field: &str,
+    filter: Option<&str>,
+    format: InfoFormat,
+) -> bunsenite::Result<()> {
+    let runs: Vec<(&PathBuf, bunsenite::Result<testing::Report>)> = files
+        .iter()
+        .map(|file| (file, testing::run(loader, file, field, filter)))
+        .collect();
+    let passed: usize = runs
+        .iter()
+        .filter_map(|(_, run)| run.as_ref().ok())
+        .map(testing::Report::passed)
+        .sum();
+    let failed: usize = runs
+        .iter()
+        .map(|(_, run)| match run {
+            Ok(report) => report.results.len() - report.passed(),
+            Err(_) => 1,
+        })
+        .sum();
 
-    let result = match cli.command {
-        Some(Commands::Parse { file, pretty }) => {
-            handle_parse(file, pretty, cli.verbose)
+    match format {
+        InfoFormat::Json => {
+            let files: Vec<serde_json::Value> = runs
+                .iter()
+                .map(|(file, run)| {
+                    let (results, error) = match run {
+                        Ok(report) => (report.results.iter().map(test_result_json).collect(), None),
+                        Err(e) => (Vec::new(), Some(e.to_string())),
+                    };
+                    serde_json::json!({
+                        "file": paths::display(file),
+                        "error": error,
+                        "results": results,
+                    })
+                })
+                .collect();
+            let summary = serde_json::json!({
+                "files": files,
+                "passed": passed,
+                "failed": failed,
+            });
+            println!("{}", json::to_string(&summary, true));
         }
-        Some(Commands::Validate { file }) => {
-            handle_validate(file, cli.verbose)
+        InfoFormat::Text => {
+            for (file, run) in &runs {
+                let file = paths::display(file);
+                if files.len() > 1 && !porcelain() {
+                    println!("{}", file);
+                }
+                let report = match run {
+                    Ok(report) => report,
+                    Err(e) => {
+                        let message = e.to_string();
+                        report_result(
+                            &file,
+                            "error",
+                            Some(message.as_str()),
+                            format_args!("✗ {}", e),
+                        );
+                        continue;
+                    }
+                };
+                for result in &report.results {
+                    let subject = format!("{}:{}", file, result.name);
+                    match &result.outcome {
+                        testing::Outcome::Pass => {
+                            report_result(&subject, "pass", None, format_args!("✓ {}", result.name))
+                        }
+                        testing::Outcome::Fail(reason) => report_result(
+                            &subject,
+                            "fail",
+                            Some(reason.as_str()),
+                            format_args!("✗ {}: {}", result.name, reason),
+                        ),
+                        testing::Outcome::Error(message) => {
+                            let mut row = format!("✗ {}: error:", result.name);
+                            for line in message.lines() {
+                                row += &format!("\n    {}", line);
+                            }
+                            report_result(&subject, "error", Some(message.as_str()), row);
+                        }
+                    }
+                }
+            }
+            summary!("\n{} passed, {} failed", passed, failed);
         }
-        Some(Commands::Info) => {
-            handle_info();
-            Ok(())
+    }
+
+    if failed > 0 {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+/// A test's outcome as JSON: `name`, `outcome` (`pass`, `fail` or `error`)
+/// and, unless it passed, `message`
+fn test_result_json(result: &testing::TestResult) -> serde_json::Value {
+    let (outcome, message) = match &result.outcome {
+        testing::Outcome::Pass => ("pass", None),
+        testing::Outcome::Fail(reason) => ("fail", Some(reason)),
+        testing::Outcome::Error(message) => ("error", Some(message)),
+    };
+    serde_json::json!({
+        "name": result.name,
+        "outcome": outcome,
+        "message": message,
+    })
+}
+
+fn handle_conformance(
+    dir: PathBuf,
+    update_expected: bool,
+    manifest: bool,
+    against: Option<Binding>,
+    runner: Option<String>,
+    coverage: Option<PathBuf>,
+) -> bunsenite::Result<()> {
+    let mut corpus = Corpus::load(&dir)?;
+    let loader = NickelLoader::new();
+
+    if manifest {
+        println!("{}", json::to_string(&corpus.manifest(), true));
+        return Ok(());
+    }
+
+    if update_expected {
+        let written = corpus.update_expected(&loader)?;
+        passed!(
+            "Updated {} of {} expectation(s) in {}",
+            written,
+            corpus.cases().len(),
+            dir.display()
+        );
+        return Ok(());
+    }
+
+    let report = match against {
+        Some(binding) => {
+            let runner = match runner {
+                Some(command) => {
+                    Runner::new(command.split_whitespace().map(str::to_string).collect())?
+                }
+                None => Runner::for_binding(binding)?,
+            };
+            summary!("Diffing {} binding against the native engine", binding);
+            runner.differential(&corpus, &loader)?
         }
-        None => {
-            // No command specified, show help
-            println!("{}", get_help_text());
-            Ok(())
+        None => corpus.run(&loader),
+    };
+
+    for result in &report.results {
+        let name = &result.name;
+        match &result.outcome {
+            Outcome::Pass => report_result(name, "pass", None, format_args!("✓ {}", name)),
+            Outcome::Fail(reason) => report_result(
+                name,
+                "fail",
+                Some(reason.as_str()),
+                format_args!("✗ {}: {}", name, reason),
+            ),
+            Outcome::Missing => report_result(
+                name,
+                "missing",
+                Some("no expected output"),
+                format_args!(
+                    "? {}: no expected output (run with --update-expected)",
+                    name
+                ),
+            ),
+        }
+    }
+
+    summary!(
+        "\n{} passed, {} failed",
+        report.passed(),
+        report.results.len() - report.passed()
+    );
+
+    if let Some(contract) = coverage {
+        let mut coverage = Coverage::new(&Contract::parse(&std::fs::read_to_string(contract)?)?);
+        for (case, result) in corpus.cases().iter().zip(&report.results) {
+            if let (Expected::Output(output), Outcome::Pass) = (&case.expected, &result.outcome) {
+                coverage.observe(output);
+            }
+        }
+        println!();
+        print!("{}", coverage);
+    }
+
+    if !report.all_passed() {
+        process::exit(1);
+    }
+
+    Ok(())
+}
+
+fn handle_infer_schema(
+    loader: &NickelLoader,
+    files: Vec<PathBuf>,
+    enum_threshold: usize,
+    output: Option<PathBuf>,
+) -> bunsenite::Result<()> {
+    let samples = files
+        .iter()
+        .map(|file| schema::load_sample(loader, file))
+        .collect::<bunsenite::Result<Vec<_>>>()?;
+
+    let contract = format!(
+        "# Inferred by `bunsenite infer-schema` from {} sample(s)\n{}",
+        samples.len(),
+        Shape::infer(&samples).to_nickel(enum_threshold)
+    );
+
+    match output {
+        Some(path) => {
+            std::fs::write(&path, contract)?;
+            status!("Contract written to {}", path.display());
         }
+        None => print!("{}", contract),
+    }
+
+    Ok(())
+}
+
+fn handle_owners(
+    loader: &NickelLoader,
+    file: PathBuf,
+    against: PathBuf,
+    owners_file: Option<PathBuf>,
+) -> bunsenite::Result<()> {
+    let mut owners = match owners_file {
+        Some(path) => Owners::load(loader, &path)?,
+        None => Owners::default(),
     };
+    let source = std::fs::read_to_string(&file)?;
+    let name = file.to_string_lossy();
+    owners.add_annotations(&loader.field_docs(&source, &name)?)?;
 
-    if let Err(e) = result {
-        eprintln!("Error: {}", e);
-        if let Some(suggestion) = e.suggestion() {
-            eprintln!("\nSuggestion: {}", suggestion);
+    let old = read_json_artifact(&against)?;
+    let changes = diff::diff(&old, &loader.parse_string(&source, &name)?);
+    if changes.is_empty() {
+        println!("No output changes; no approvals needed");
+        return Ok(());
+    }
+
+    let routing = owners.route(changes.iter().map(|c| c.path()));
+    for (owner, paths) in &routing.owners {
+        println!("{}", owner);
+        for path in paths {
+            println!("  {}", path);
+        }
+    }
+    if !routing.unowned.is_empty() {
+        println!("(no owner)");
+        for path in &routing.unowned {
+            println!("  {}", path);
         }
+    }
+
+    Ok(())
+}
+
+fn handle_merge_driver(
+    base: PathBuf,
+    ours: PathBuf,
+    theirs: PathBuf,
+    marker_size: usize,
+) -> bunsenite::Result<()> {
+    let merged = merge::merge_with_markers(
+        &std::fs::read_to_string(&base)?,
+        &std::fs::read_to_string(&ours)?,
+        &std::fs::read_to_string(&theirs)?,
+        marker_size,
+    );
+    std::fs::write(&ours, &merged.text)?;
+
+    if merged.conflicts > 0 {
+        failure!(
+            "{} conflicting field(s) in {}",
+            merged.conflicts,
+            ours.display()
+        );
         process::exit(1);
     }
+
+    Ok(())
+}
+
+fn handle_inspect_capabilities(
+    loader: &NickelLoader,
+    file: &Path,
+    root: &Path,
+    evaluate: bool,
+    format: InfoFormat,
+    relative_root: Option<&Path>,
+) -> bunsenite::Result<()> {
+    let mut report = capabilities::Report::inspect(loader, file, root)?;
+    if evaluate {
+        report = report.evaluate(loader);
+    }
+    if let Some(root) = relative_root {
+        report = report.relative_to(root);
+    }
+
+    match format {
+        InfoFormat::Json => println!("{}", json::to_string(&report.to_json(), true)),
+        InfoFormat::Text => print!("{}", report),
+    }
+    Ok(())
 }
 
-fn handle_parse(file: PathBuf, pretty: bool, verbose: bool) -> bunsenite::Result<()> {
-    if verbose {
-        eprintln!("Parsing file: {}", file.display());
+fn handle_synth(
+    loader: &NickelLoader,
+    schema: &Path,
+    count: usize,
+    seed: Option<u64>,
+    out_dir: Option<&Path>,
+    no_check: bool,
+) -> bunsenite::Result<()> {
+    let source = std::fs::read_to_string(schema)?;
+    let contract = Contract::parse(&source)?;
+    let seed = seed.unwrap_or_else(|| {
+        let seed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        note!("Seed: {}", seed);
+        seed
+    });
+
+    let configs = synth::generate(&contract, count, seed);
+    if !no_check {
+        synth::check(loader, &source, &configs)?;
+    }
+
+    match out_dir {
+        Some(dir) => {
+            std::fs::create_dir_all(dir)?;
+            for (index, config) in configs.iter().enumerate() {
+                let path = dir.join(format!("synth-{:04}.json", index + 1));
+                std::fs::write(&path, json::to_string(config, true) + "\n")?;
+            }
+            status!("{} config(s) written to {}", configs.len(), dir.display());
+        }
+        None => {
+            let mut stdout = std::io::stdout().lock();
+            for config in &configs {
+                writeln!(stdout, "{}", json::to_string(config, false))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn handle_export(
+    loader: &NickelLoader,
+    file: &Path,
+    formats: &[export::Format],
+    output: Option<&Path>,
+    mode: Option<u32>,
+    options: &export::Options,
+    compression: Option<Compression>,
+) -> bunsenite::Result<Vec<PathBuf>> {
+    if output.is_none() && formats.len() > 1 {
+        return Err(bunsenite::Error::invalid_input(
+            "Writing several formats needs -o FILE",
+        ));
+    }
+    let rendered = export::export_file_all(loader, file, formats, options)?;
+    let Some(output) = output else {
+        let mut out = std::io::stdout().lock();
+        out.write_all(rendered[0].as_bytes())?;
+        out.flush()?;
+        return Ok(Vec::new());
+    };
+
+    let paths = export::output_paths(output, formats);
+    let mut written = Vec::new();
+    for ((format, contents), path) in formats.iter().zip(rendered).zip(paths) {
+        let path = compress::write_with_mode(&path, contents.as_bytes(), compression, mode)?;
+        status!("{} -> {} ({})", file.display(), path.display(), format);
+        written.push(path);
     }
+    Ok(written)
+}
 
-    let loader = NickelLoader::new().with_verbose(verbose);
-    let result = loader.parse_file(&file)?;
+fn handle_merge(
+    loader: &NickelLoader,
+    files: &[PathBuf],
+    format: export::Format,
+    output: Option<&Path>,
+    layout: json::Layout,
+    compression: Option<Compression>,
+) -> bunsenite::Result<()> {
+    let value = loader.merge_files(files)?;
+    let rendered = Styles::new()
+        .with_json_layout(layout)
+        .render(&value, format);
+    json::drop_deep(value);
+    let rendered = rendered?;
+
+    match output {
+        Some(path) => {
+            let path = compress::write(path, rendered.as_bytes(), compression)?;
+            status!("{} file(s) merged -> {}", files.len(), path.display());
+        }
+        None => {
+            let mut out = std::io::stdout().lock();
+            out.write_all(rendered.as_bytes())?;
+            out.flush()?;
+        }
+    }
+    Ok(())
+}
 
-    if pretty {
-        println!("{}", serde_json::to_string_pretty(&result).unwrap());
+fn handle_build(
+    loader: &NickelLoader,
+    file: &Path,
+    out_dir: &Path,
+    only: &[String],
+    incremental: bool,
+    remote_cache: Option<&str>,
+    compression: Option<Compression>,
+) -> bunsenite::Result<Vec<PathBuf>> {
+    // A build is fresh for the same output directory, exports and
+    // compression only. The directory is named as given, so runners sharing
+    // a remote cache agree on the task
+    let fresh = if incremental {
+        let mut only = only.to_vec();
+        only.sort();
+        let task = format!(
+            "build\0{}\0{:?}\0{:?}",
+            out_dir.display(),
+            only,
+            compression
+        );
+        let fingerprint = Fingerprint::of(loader, file)?;
+        let cache = fresh_cache(remote_cache)?;
+        if cache.is_fresh(&task, file, &fingerprint) {
+            status!("{}: 0 rebuilt, 1 fresh", file.display());
+            return Ok(cache.outputs(&task, file));
+        }
+        Some((cache, task, fingerprint))
     } else {
-        println!("{}", serde_json::to_string(&result).unwrap());
+        None
+    };
+
+    let mut exports = exports::load(loader, file)?;
+    if let Some(unknown) = only
+        .iter()
+        .find(|name| !exports.iter().any(|e| &e.name == *name))
+    {
+        return Err(bunsenite::Error::invalid_input(format!(
+            "No export named '{}' in {}",
+            unknown,
+            file.display()
+        )));
+    }
+    if !only.is_empty() {
+        exports.retain(|export| only.contains(&export.name));
     }
 
-    if verbose {
-        eprintln!("✓ Successfully parsed and evaluated");
+    let written = exports::write_with(&exports, out_dir, compression)?;
+    for (export, path) in exports.iter().zip(&written) {
+        status!("{} -> {} ({})", export.name, path.display(), export.format);
+    }
+    if let Some((cache, task, fingerprint)) = fresh {
+        record_fresh(&cache, &task, file, &fingerprint, &written);
+        trim_cache();
+        status!("{}: 1 rebuilt, 0 fresh", file.display());
+    }
+    Ok(written)
+}
+
+/// Write `--emit-depfile`: `targets`, made from the config at `file` (for
+/// an `ARCHIVE::ENTRY`, from the archive) and its imports
+fn write_depfile(
+    depfile: &Path,
+    targets: Vec<PathBuf>,
+    file: &Path,
+    threads: usize,
+    relative_root: Option<&Path>,
+) -> bunsenite::Result<()> {
+    let spec = file.to_string_lossy();
+    let config = archive::split(&spec).map_or(file, |(archive, _)| Path::new(archive));
+    let record = Depfile::new(targets, config, threads);
+    let record = match relative_root {
+        Some(root) => record.relative_to(root),
+        None => record,
+    };
+    record.write(depfile)
+}
+
+fn handle_drift(
+    loader: &NickelLoader,
+    file: &Path,
+    live: &Path,
+    include_extra: bool,
+    ignore: &[String],
+) -> bunsenite::Result<()> {
+    let options = DriftOptions {
+        include_extra,
+        ignore: ignore
+            .iter()
+            .map(|pattern| pattern.parse())
+            .collect::<bunsenite::Result<_>>()?,
+    };
+    let desired = loader.parse_file(file)?;
+    let live_state = read_json_artifact(live)?;
+    let source = std::fs::read_to_string(file)?;
+
+    let drifts = drift::detect(&desired, &live_state, &source, &options);
+    if drifts.is_empty() {
+        status!("{} matches {}", live.display(), file.display());
+        return Ok(());
+    }
+
+    let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+    for drift in &drifts {
+        writeln!(out, "{}", drift)?;
     }
+    out.flush()?;
+
+    failure!(
+        "\n{} path(s) of {} drifted from {}",
+        drifts.len(),
+        live.display(),
+        file.display()
+    );
+    process::exit(1);
+}
+
+fn handle_mutate(
+    loader: &NickelLoader,
+    file: &Path,
+    policy: &Path,
+    min_score: Option<f64>,
+) -> bunsenite::Result<()> {
+    let output = loader.parse_file(file)?;
+    let policy = std::fs::read_to_string(policy)?;
+    let report = mutate::run(loader, &output, &policy)?;
+    print!("{}", report);
 
+    if let Some(min_score) = min_score {
+        if report.score() * 100.0 < min_score {
+            failure!(
+                "\nMutation score {:.0}% is below the required {}%",
+                report.score() * 100.0,
+                min_score
+            );
+            process::exit(1);
+        }
+    }
     Ok(())
 }
 
-fn handle_validate(file: PathBuf, verbose: bool) -> bunsenite::Result<()> {
-    if verbose {
-        eprintln!("Validating file: {}", file.display());
+#[cfg(feature = "daemon")]
+fn handle_replay(loader: NickelLoader, path: &Path) -> bunsenite::Result<()> {
+    let exchanges = session::load(path)?;
+    let divergences = session::replay(&Server::new(loader), &exchanges);
+    if divergences.is_empty() {
+        status!("{} request(s) replayed identically", exchanges.len());
+        return Ok(());
     }
 
-    let source = std::fs::read_to_string(&file)?;
-    let name = file
-        .file_name()
-        .and_then(|n| n.to_str())
-        .unwrap_or("unknown.ncl");
+    for divergence in &divergences {
+        println!("{}", divergence);
+    }
+    failure!(
+        "\n{} of {} request(s) answered differently",
+        divergences.len(),
+        exchanges.len()
+    );
+    process::exit(1);
+}
+
+fn handle_worker(loader: NickelLoader, persistent: bool, args: &[String]) -> bunsenite::Result<()> {
+    let worker = Worker::new(loader);
+    if persistent {
+        return worker.serve(std::io::stdin().lock(), std::io::stdout().lock());
+    }
+    let (exit_code, output) = worker.run(&worker::expand_flagfiles(args)?, None);
+    if !output.is_empty() {
+        note!("{}", output.trim_end_matches('\n'));
+    }
+    if exit_code != 0 {
+        process::exit(exit_code);
+    }
+    Ok(())
+}
+
+fn handle_bundle(command: BundleCommand) -> bunsenite::Result<()> {
+    let cache = oci::Cache::default_dir()
+        .map(oci::Cache::new)
+        .ok_or_else(|| {
+            bunsenite::Error::invalid_input("No cache directory; set BUNSENITE_CACHE_DIR")
+        })?;
+    match command {
+        BundleCommand::Push { bundle, reference } => {
+            let reference = oci::Reference::parse(&reference)?;
+            let digest = oci::push(&cache, &bundle, &reference)?;
+            status!("Pushed {} to {}", bundle.display(), reference);
+            println!("{}", digest);
+        }
+        BundleCommand::Pull {
+            reference,
+            output,
+            offline,
+        } => {
+            let reference = oci::Reference::parse(&reference)?;
+            let pulled = oci::pull(&cache, &reference, offline)?;
+            let output = output.unwrap_or_else(|| PathBuf::from(pulled.file_name()));
+            std::fs::copy(&pulled.path, &output)?;
+            status!("Pulled {} to {}", reference, output.display());
+            println!("{}", pulled.manifest);
+        }
+    }
+    trim_cache();
+    Ok(())
+}
+
+fn handle_cache(command: CacheCommand) -> bunsenite::Result<()> {
+    let dir = cache::home().ok_or_else(|| {
+        bunsenite::Error::invalid_input("No cache directory; set BUNSENITE_CACHE_DIR")
+    })?;
+    match command {
+        CacheCommand::Gc { max_size, max_age } => {
+            let mut policy = CachePolicy::new();
+            if let Some(bytes) = max_size {
+                policy = policy.with_max_size(bytes);
+            }
+            if let Some(age) = max_age {
+                policy = policy.with_max_age(age);
+            }
+            if policy.is_empty() {
+                policy = CachePolicy::from_env()?;
+            }
+            if policy.is_empty() {
+                return Err(bunsenite::Error::invalid_input(format!(
+                    "cache gc needs --max-size or --max-age (or {} / {})",
+                    cache::MAX_SIZE_ENV,
+                    cache::MAX_AGE_ENV
+                )));
+            }
+            let report = cache::gc(&dir, &policy)?;
+            status!(
+                "Removed {} file(s), {}; {} file(s), {} left in {}",
+                report.removed,
+                cache::format_size(report.freed),
+                report.kept,
+                cache::format_size(report.size),
+                dir.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Trim the cache to `BUNSENITE_CACHE_MAX_SIZE` and `BUNSENITE_CACHE_MAX_AGE`,
+/// if set, after a command wrote to it
+///
+/// Failing to trim only warns: the command itself succeeded.
+fn trim_cache() {
+    let Some(dir) = cache::home() else {
+        return;
+    };
+    let trimmed = CachePolicy::from_env().and_then(|policy| {
+        if policy.is_empty() {
+            Ok(())
+        } else {
+            cache::gc(&dir, &policy).map(drop)
+        }
+    });
+    if let Err(e) = trimmed {
+        warning!("cannot trim the cache in {}: {}", dir.display(), e);
+    }
+}
 
-    let loader = NickelLoader::new().with_verbose(verbose);
-    loader.validate(&source, name)?;
+fn handle_doctor(loader: &NickelLoader, format: InfoFormat) -> bunsenite::Result<()> {
+    let report = doctor::run(loader);
+    match format {
+        InfoFormat::Json => println!("{}", json::to_string(&report.to_json(), true)),
+        InfoFormat::Text => print!("{}", report),
+    }
+    if !report.healthy() {
+        process::exit(1);
+    }
+    Ok(())
+}
 
-    println!("✓ Configuration is valid");
+fn handle_completions(shell: Shell) {
+    let mut command = Cli::command();
+    let mut script = Vec::new();
+    clap_complete::generate(shell, &mut command, "bunsenite", &mut script);
+    let script = String::from_utf8_lossy(&script);
+    match shell {
+        Shell::Bash => print!("{}", query::bash_completion(&script)),
+        _ => print!("{}", script),
+    }
+}
 
+fn handle_man(out_dir: Option<&Path>) -> bunsenite::Result<()> {
+    let command = Cli::command();
+    let Some(dir) = out_dir else {
+        let mut out = std::io::stdout().lock();
+        clap_mangen::Man::new(command).render(&mut out)?;
+        out.flush()?;
+        return Ok(());
+    };
+    std::fs::create_dir_all(dir)?;
+    let written = write_man_pages(command, dir)?;
+    status!("{} man page(s) -> {}", written, dir.display());
     Ok(())
 }
 
-fn handle_info() {
+/// Write the page of `command` and, named after it, those of its visible
+/// subcommands to `dir`, returning how many were written
+fn write_man_pages(command: clap::Command, dir: &Path) -> bunsenite::Result<usize> {
+    let name = command
+        .get_display_name()
+        .unwrap_or_else(|| command.get_name())
+        .to_string();
+    let bin_name = command
+        .get_bin_name()
+        .unwrap_or_else(|| command.get_name())
+        .to_string();
+    let mut page = Vec::new();
+    clap_mangen::Man::new(command.clone()).render(&mut page)?;
+    std::fs::write(dir.join(format!("{}.1", name)), page)?;
+
+    let mut written = 1;
+    for subcommand in command.get_subcommands().filter(|sub| !sub.is_hide_set()) {
+        let subcommand = subcommand
+            .clone()
+            .display_name(format!("{}-{}", name, subcommand.get_name()))
+            .bin_name(format!("{} {}", bin_name, subcommand.get_name()));
+        written += write_man_pages(subcommand, dir)?;
+    }
+    Ok(written)
+}
+
+fn handle_info(format: InfoFormat) {
+    if format == InfoFormat::Json {
+        let mut info = VERSION_INFO.to_json();
+        info["rsr_tier"] = RSR_TIER.into();
+        info["tpcf_perimeter"] = TPCF_PERIMETER.into();
+        info["build"] = BUILD_INFO.to_json();
+        println!("{}", json::to_string(&info, true));
+        return;
+    }
+
     println!("Bunsenite v{}", VERSION);
     println!();
     println!("A Nickel configuration file parser with multi-language FFI bindings");
     println!();
+    println!("Embedded Nickel:");
+    println!("  • Language: {}", VERSION_INFO.nickel_language);
+    println!("  • nickel-lang-core: {}", VERSION_INFO.nickel_core);
+    println!(
+        "  • Language features: {}",
+        VERSION_INFO.features.join(", ")
+    );
+    println!();
+    println!("Build:");
+    println!("  • Target: {}", BUILD_INFO.target);
+    println!("  • Profile: {}", BUILD_INFO.profile);
+    println!("  • Linkage: {}", BUILD_INFO.linkage());
+    println!();
     println!("Features:");
     println!("  • Type Safety: Compile-time guarantees via Rust's type system");
     println!("  • Memory Safety: Rust ownership model, zero unsafe blocks");
@@ -149,27 +3943,318 @@ USAGE:
     bunsenite <COMMAND>
 
 COMMANDS:
+    init        Create a starter project (--template k8s|service|library)
     parse       Parse and evaluate a Nickel configuration file
     validate    Validate a Nickel configuration without evaluating it
+    typecheck   Statically typecheck a Nickel configuration (stricter than validate)
+    fmt         Format Nickel files in the canonical style (--check for CI)
+    lint        Check Nickel files for unused lets, duplicate fields and more
+    bench       Benchmark pipeline phases against a stored baseline
+    trace       Profile time per phase, import and top-level field
+    deps        Print a config's import graph as a tree, JSON or DOT
+    vendor      Copy a config and its imports into a self-contained directory
+    schema      Export a JSON Schema of a config's fields from their contracts
+    test        Run the tests a config keeps in its `tests` record
+    conformance Run a conformance corpus against the engine
+    infer-schema
+                Infer a Nickel contract from example documents
+    expand      Evaluate a config once per combination of a matrix
+    owners      Report which owners must approve a config's output changes
+    merge-driver
+                Structure-aware three-way merge (git merge driver)
+    inspect-capabilities
+                List the files, imports and transforms a config would use
+    query       Print the value at a field path, evaluating only that field
+    completions Print a bash, zsh, fish, PowerShell or Elvish completion script
+    export      Evaluate a config and write it as YAML, TOML, JSON or Nix
+    build       Write every output declared in a config's `exports` record
+    drift       Compare a config's output with exported live state
+    synth       Generate random configs satisfying a contract
+    mutate      Check that a policy rejects mutations of a config's output
+    serve       Answer JSON-lines evaluation requests on stdin (daemon mode)
+    replay      Re-answer a recorded serve session and report changed responses
+    worker      Run as a Bazel persistent worker (--persistent_worker)
+    bundle      Push config bundles to, or pull them from, OCI registries
+    cache       Trim the on-disk cache (cache gc --max-size 1GiB --max-age 30d)
+    doctor      Check that this installation works (for bug reports)
     info        Show version and compliance information
     help        Print this message or the help of the given subcommand(s)
 
 OPTIONS:
     -v, --verbose    Enable verbose output
+        --require-nickel <REQ>
+                     Fail unless the embedded Nickel version matches REQ
+        --compat     Rewrite deprecated stdlib names, with warnings
+    -j, --jobs <N>   Worker threads for batch work (1 = deterministic order)
+        --prefetch-imports
+                     Read import trees concurrently (slow/network filesystems)
+        --transform <SPEC>
+                     Post-process evaluated values before output
+        --env-prefix <PREFIX>
+                     Expose matching environment variables to configs as `env`
+        --no-env     Hermetic: configs get an empty `env`, CI is ignored
+        --remote-cache <URL>
+                     Share --incremental results (https://, s3:// or a directory)
+        --audit-log <FILE>
+                     Append files, imports, env vars and transforms used
+        --symlink-imports <ACTION>
+                     allow, warn or deny imports through symlinks
+        --escaping-imports <ACTION>
+                     allow, warn or deny imports outside --import-root
+    -C, --project-root <DIR>
+                     Resolve files, imports and diagnostic paths from DIR, not the working directory
+        --compress <ALGO>
+                     gzip or zstd written files (builds with `compression`)
+        --relative-paths
+                     Write paths in errors, provenance and reports relative to the project root
+        --error-format <FORMAT>
+                     human (default) or json: one diagnostic object per line on stderr
+        --color <WHEN>
+                     Color errors and their source snippets: auto (default), always or never
+        --porcelain  stdout carries only the output; stderr only versioned JSON lines, for wrappers
+        --harden     Give up network and unrelated file access before evaluating (Linux)
+        --otlp-endpoint <URL>
+                     Export evaluation spans over OTLP (builds with `otel`)
+        --max-depth <N>, --max-memory <SIZE>, --timeout <SECONDS>
+                     Fail evaluations nested too deep, using too much memory or running too long
+        --rlimit-as <SIZE>, --rlimit-cpu <SECONDS>, --rlimit-nofile <N>
+                     Limit memory, CPU time and open files of the evaluation
+        --no-settings
+                     Ignore the global settings file (settings.ncl in the config directory)
     -h, --help       Print help information
     -V, --version    Print version information
 
 EXAMPLES:
+    # Start a project from the Kubernetes template
+    bunsenite init my-app --template k8s
+
     # Parse and evaluate a config file
     bunsenite parse config.ncl
 
     # Parse with pretty-printed output
     bunsenite parse config.ncl --pretty
 
+    # Override fields for one run; contracts still check the values
+    bunsenite parse config.ncl --override server.port=8080 --override 'server.host="example.com"'
+
+    # Evaluate a template for every tenant in one run
+    bunsenite parse app.ncl --tenants tenants.ncl --out-dir out/
+
+    # Generate region x environment configs with a manifest
+    bunsenite expand app.ncl --matrix regions.ncl --out-dir generated/
+
+    # Read BUNSEN_* variables in the config as env.*, or keep builds hermetic
+    BUNSEN_PORT=8080 bunsenite parse config.ncl --env-prefix BUNSEN_
+    bunsenite build config.ncl --out-dir generated/ --no-env
+
+    # Drop _-prefixed fields and stamp the build
+    bunsenite parse config.ncl --transform strip-internal --transform inject:build=$GIT_SHA
+
+    # Look up one value; with completions installed, field paths complete on <TAB>
+    source <(bunsenite completions bash)
+    bunsenite query config.ncl .services.web.port
+
+    # Completions for other shells (subcommands, options and --format values)
+    bunsenite completions zsh > "${{fpath[1]}}/_bunsenite"
+    bunsenite completions fish > ~/.config/fish/completions/bunsenite.fish
+
+    # Man pages for bunsenite and every subcommand, for packagers
+    bunsenite man --out-dir target/man
+
+    # Defaults for global options, e.g. {{ jobs = 8, color = "always" }}, go in settings.ncl
+    # in $XDG_CONFIG_HOME/bunsenite, ~/Library/Application Support/bunsenite or %APPDATA%\bunsenite
+    # ($BUNSENITE_CONFIG_DIR overrides; caches follow $BUNSENITE_CACHE_DIR); --no-settings skips it
+    bunsenite doctor
+
+    # The same from parse, e.g. in scripts
+    bunsenite parse config.ncl --field 'server.ports[0]'
+
+    # Review what a change does to the output, not to the source
+    bunsenite diff old/config.ncl config.ncl --format json
+
+    # Generate a Markdown reference of a config's fields
+    bunsenite doc config.ncl -o CONFIG.md
+
+    # JSON Schema of the fields' contracts, docs and defaults, for editors and APIs
+    bunsenite schema config.ncl -o config.schema.json
+
+    # Check invariants kept next to the config: tests | not_exported = {{ enough_replicas = replicas >= 2 }}
+    bunsenite test config.ncl --filter network
+
+    # Find which merge, import or default a contract failure comes from
+    bunsenite why-error config.ncl
+
+    # Apply an environment overlay to a base config
+    bunsenite merge base.ncl prod.ncl --format yaml -o prod.yaml
+
+    # Write a config as YAML for kubectl or Ansible
+    bunsenite export deployment.ncl --format yaml | kubectl apply -f -
+
+    # Evaluate once, write out/app.json and out/app.yaml
+    bunsenite export app.ncl --format json,yaml -o out/app
+
+    # An attribute set for a Nix-built image: `import ./host.nix`
+    bunsenite export host.ncl --format nix -o host.nix
+
+    # Write values.yaml with each field's doc as a comment above it
+    bunsenite export values.ncl -o values.yaml --doc-comments
+
+    # Match a repository's JSON style: 4-space indent, short arrays on one line
+    bunsenite export app.ncl --format json -o app.json --json-indent 4 --json-inline-arrays 80
+
+    # Keep fields in the order the config defines them, for reviewed artifacts
+    bunsenite export deployment.ncl -o deployment.yaml --source-order
+
+    # Re-export whenever the config or anything it imports changes
+    bunsenite export app.ncl -o out/app.yaml --watch
+
+    # Write a large output compressed for object storage, then diff against it
+    bunsenite export config.ncl --format json -o release.json --compress zstd
+    bunsenite parse config.ncl --diff-against release.json.zst
+
+    # Write all the YAML/TOML/JSON outputs a config declares in `exports`
+    bunsenite build config.ncl --out-dir generated/
+    bunsenite build config.ncl --out-dir generated/ --incremental
+
+    # Tell Make/Ninja which files the outputs depend on (JSON for Bazel with .json)
+    bunsenite build config.ncl --out-dir generated/ --emit-depfile generated/config.d
+
+    # Export one service's slice of a shared config, with a provenance sidecar
+    bunsenite parse services.ncl --target services.web --provenance web.provenance.json
+
+    # Export a public subset of the config
+    bunsenite parse config.ncl --include-path 'api' --exclude-path 'api.keys'
+
+    # Share a config's structure in a bug report without its values
+    bunsenite parse config.ncl --mask-values
+    bunsenite mask config.ncl > report.ncl
+
+    # Keep secrets out of the exported JSON
+    bunsenite parse config.ncl --restrict 'secrets.*=deny'
+
+    # Show what a change does to a deployed artifact
+    bunsenite parse config.ncl --diff-against deployed.json
+
+    # See which values the author did not set explicitly
+    bunsenite parse config.ncl --show-defaults
+
+    # Run the daemon with metrics for Prometheus to scrape
+    bunsenite serve --metrics-addr 127.0.0.1:9090
+
+    # Language server for editors (diagnostics, hover, go to definition)
+    bunsenite lsp
+
+    # Check an installation before filing a "works on my machine" report
+    bunsenite doctor
+
+    # Reproduce a daemon session recorded with `serve --record`
+    bunsenite replay session.jsonl
+
+    # What a Bazel rule runs with workers off; Bazel adds --persistent_worker
+    bunsenite worker export app.ncl --output app.yaml
+
+    # Refuse imports that leave the repository
+    bunsenite parse config.ncl --escaping-imports deny --import-root .
+
+    # Evaluate an entrypoint inside a shipped bundle (builds with `archives`)
+    bunsenite parse bundle.tar.zst::env/prod.ncl
+
+    # Ship a bundle through a registry, then pull exactly that version
+    bunsenite bundle push configs.tar.zst oci://registry.example/team/configs:v3
+    bunsenite bundle pull oci://registry.example/team/configs@sha256:<digest>
+
+    # Keep the cache bounded on a shared build agent
+    bunsenite cache gc --max-size 1GiB --max-age 30d
+
+    # See a contributed config's imports before running it
+    bunsenite inspect-capabilities contrib.ncl --root .
+
+    # Check which parts of a contract the test corpus exercises
+    bunsenite conformance tests/configs --coverage contract.ncl
+
+    # Generate 100 reproducible configs for load-testing a consumer
+    bunsenite synth --schema contract.ncl --count 100 --seed 7
+
+    # Find where a cluster has drifted from its config
+    kubectl get deployment web -o json > live.json
+    bunsenite drift config.ncl --live live.json --ignore metadata.annotations
+
+    # Find mutations of the output a policy fails to catch
+    bunsenite mutate config.ncl --policy policy.ncl --min-score 80
+
+    # Review what an untrusted config reads
+    bunsenite parse contrib.ncl --audit-log audit.jsonl
+
     # Validate without evaluating
     bunsenite validate config.ncl
 
-    # Show info
+    # Validate a whole tree, with a pass/fail summary
+    bunsenite validate 'configs/**/*.ncl'
+
+    # In CI, only re-check files whose imports changed since they last passed
+    bunsenite validate 'configs/**/*.ncl' --incremental
+
+    # Share those results between CI runners through an S3-compatible bucket
+    bunsenite validate 'configs/**/*.ncl' --incremental --remote-cache s3://ci-cache/bunsenite
+
+    # Also run Nickel's static typechecker, e.g. in CI
+    bunsenite typecheck config.ncl
+
+    # Errors as JSON (file, span, severity, code, message, suggestion) for CI and editors
+    bunsenite --error-format json typecheck config.ncl
+
+    # Errors underline the source line they blame; keep the colors in CI logs
+    bunsenite --color always validate config.ncl
+
+    # Also check the output against what downstream teams publish
+    bunsenite validate config.ncl --schema deploy.schema.json --schema deploy.cue#Service
+    bunsenite validate config.ncl --schema config.pb#app.v1.Config
+
+    # Evaluate a contributor's config with only its own files reachable
+    bunsenite parse contrib.ncl --harden --escaping-imports deny
+
+    # Same output from any directory a build system runs in
+    bunsenite -C /src/app parse configs/app.ncl -o out/app.json
+
+    # Wrap bunsenite in a tool: output on stdout, JSON status lines on stderr
+    bunsenite --porcelain parse config.ncl > config.json 2> events.jsonl
+
+    # Keep a shared CI runner safe from a runaway config
+    bunsenite parse config.ncl --rlimit-as 2G --rlimit-cpu 60
+
+    # Evaluate an untrusted config with per-evaluation limits
+    bunsenite parse untrusted.ncl --max-depth 200 --max-memory 1G --timeout 10
+
+    # Replace a file a service watches in one step, readable by its owner only
+    bunsenite parse secrets.ncl -o /etc/app/secrets.json --mode 600
+
+    # Evaluate generated source from a pipe, naming it in diagnostics
+    render-config | bunsenite parse - --name app.ncl
+
+    # Fail CI if any config is not formatted
+    bunsenite fmt --check config/*.ncl
+
+    # Lint, also requiring contracts on top-level fields
+    bunsenite lint config/*.ncl --enable missing-contract
+
+    # Benchmark and compare against a stored baseline
+    bunsenite bench config.ncl --baseline bench.json
+
+    # The files a config reads, for build-system invalidation
+    bunsenite deps config.ncl
+    bunsenite deps config.ncl --format dot | dot -Tsvg > imports.svg
+
+    # Copy a config and its imports into vendor/ to evaluate offline
+    bunsenite vendor config.ncl --out vendor/
+
+    # Find the slow imports and fields, or open a flame graph in Perfetto
+    bunsenite trace config.ncl
+    bunsenite trace config.ncl --format chrome -o trace.json
+
+    # Generate a contract from existing configs
+    bunsenite infer-schema prod.yaml staging.yaml -o schema.ncl
+
+    # Show info (add --format json for tooling)
     bunsenite info
 
 For more information, visit:
@@ -185,7 +4270,27 @@ mod tests {
     #[test]
     fn test_cli_info_runs() {
         // Just verify info command doesn't panic
-        handle_info();
+        handle_info(InfoFormat::Text);
+        handle_info(InfoFormat::Json);
+    }
+
+    #[test]
+    fn test_format_ns_units() {
+        assert_eq!(format_ns(500), "500 ns");
+        assert_eq!(format_ns(25_000), "25.0 µs");
+        assert_eq!(format_ns(12_000_000), "12.0 ms");
+        assert_eq!(format_ns(3_000_000_000), "3.00 s");
+    }
+
+    #[test]
+    fn test_man_pages() {
+        let dir = tempfile::tempdir().unwrap();
+        let written = write_man_pages(Cli::command(), dir.path()).unwrap();
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), written);
+        let page = std::fs::read_to_string(dir.path().join("bunsenite-bundle-push.1")).unwrap();
+        assert!(page.contains("bunsenite bundle push"));
+        assert!(dir.path().join("bunsenite.1").exists());
+        assert!(!dir.path().join("bunsenite-complete-path.1").exists());
     }
 
     #[test]
@@ -0,0 +1,123 @@
+//! Masking a config's values for bug reports
+//!
+//! [`mask_source`] rewrites Nickel source so it keeps its structure, field
+//! names, identifiers, contracts, enum tags and booleans but none of its
+//! string or number values: string literals become `"<string>"`, numbers `0`
+//! and comments are emptied. The placeholders say nothing about what they
+//! replace, not even its length. Strings naming a field (`"a b" = 1`,
+//! `x."a b"`) are kept.
+//!
+//! `bunsenite mask FILE` prints the masked source for attaching to a report,
+//! and `bunsenite parse --mask-values` evaluates it
+//! ([`NickelLoader::with_mask_values`](crate::NickelLoader::with_mask_values)),
+//! so error messages quote masked source rather than values. A masked config
+//! may fail differently from the original where it depends on its values,
+//! e.g. dividing by a number or matching a string. Imported files are not
+//! rewritten; `parse --mask-values` also masks the output with
+//! [`MaskValues`](crate::transform::MaskValues), so their values do not
+//! reach it either.
+//!
+//! Shrinking a failing config to a minimal reproduction is out of scope (see
+//! the Failing-Config Minimizer in `NEXT_STEPS.md`).
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::mask::mask_source;
+//!
+//! let source = "{ \"db host\" = \"10.0.0.7\", port = 5432, tls = true } # prod";
+//! assert_eq!(
+//!     mask_source(source).unwrap(),
+//!     "{ \"db host\" = \"<string>\", port = 0, tls = true } #"
+//! );
+//! ```
+
+use crate::error::Result;
+use crate::lexer::{self, Token};
+
+/// What a masked string literal is written as
+pub const STRING_PLACEHOLDER: &str = "<string>";
+
+/// Replace the string and number literals and the comments of a Nickel
+/// source with placeholders, keeping everything else
+///
+/// # Errors
+///
+/// Returns an invalid-input error for unterminated strings, rather than
+/// passing their text through
+pub fn mask_source(source: &str) -> Result<String> {
+    let tokens = lexer::spanned(source)?;
+    let code = |token: &&(_, Token)| !token.1.is_trivia();
+
+    let mut out = String::with_capacity(source.len());
+    for (i, (span, token)) in tokens.iter().enumerate() {
+        match token {
+            Token::Comment(_) => out.push('#'),
+            Token::Str(text) => {
+                let before = tokens[..i].iter().rev().find(code).map(|(_, t)| t);
+                let after = tokens[i + 1..].iter().find(code).map(|(_, t)| t);
+                if names_field(before, after) {
+                    out.push_str(text);
+                } else if text.starts_with('%') {
+                    // Keeps any `m` or symbolic-string prefix valid
+                    out.push_str(&format!("%\"{}\"%", STRING_PLACEHOLDER));
+                } else {
+                    out.push_str(&format!("\"{}\"", STRING_PLACEHOLDER));
+                }
+            }
+            Token::Word(word) if is_number(word) => out.push('0'),
+            _ => out.push_str(&source[span.clone()]),
+        }
+    }
+    Ok(out)
+}
+
+/// Whether a string between these tokens is a field name: it is part of a
+/// field path or followed by a definition or annotation
+fn names_field(before: Option<&Token>, after: Option<&Token>) -> bool {
+    let in_path = matches!(before, Some(Token::Word(word)) if word.ends_with('.'))
+        || matches!(after, Some(Token::Word(word)) if word.starts_with('.'));
+    let defined = matches!(after, Some(Token::Op(op)) if op == "=" || op == "|");
+    in_path || defined
+}
+
+fn is_number(word: &str) -> bool {
+    word.trim_start_matches('-')
+        .starts_with(|c: char| c.is_ascii_digit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NickelLoader;
+
+    #[test]
+    fn test_masks_values_and_comments() {
+        let source = "# acme prod\n{\n  name | String = \"acme-prod\",\n  \"the port\" = 8080,\n  ratio = -0.5,\n  mode = 'Strict,\n  cert = m%\"\n    secret\n  \"%,\n  greeting = \"hi %{name}\",\n  both = x.\"the port\",\n}\n";
+        let masked = mask_source(source).unwrap();
+        assert_eq!(
+            masked,
+            "#\n{\n  name | String = \"<string>\",\n  \"the port\" = 0,\n  ratio = 0,\n  mode = 'Strict,\n  cert = m%\"<string>\"%,\n  greeting = \"<string>\",\n  both = x.\"the port\",\n}\n"
+        );
+        for secret in ["acme", "8080", "0.5", "secret", "hi"] {
+            assert!(!masked.contains(secret), "{} leaked", secret);
+        }
+    }
+
+    #[test]
+    fn test_masked_source_evaluates() {
+        let source = "{ host = \"db.internal\", port = 5432, url = \"%{host}:5432\", tls = true }";
+        let value = NickelLoader::new()
+            .parse_string(&mask_source(source).unwrap(), "app.ncl")
+            .unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({ "host": "<string>", "port": 0, "url": "<string>", "tls": true })
+        );
+    }
+
+    #[test]
+    fn test_unterminated_string_is_an_error() {
+        assert!(mask_source("{ password = \"hunter2 }").is_err());
+    }
+}
@@ -0,0 +1,371 @@
+//! Matrix expansion of parameterized configs
+//!
+//! Backs `bunsenite expand`. A matrix definition names axes and their values;
+//! the entrypoint is evaluated once per combination (the cartesian product of
+//! the axes) with the combination bound to a free `matrix` variable, and each
+//! output is written to a path rendered from a template such as
+//! `{region}/{env}.json`.
+//!
+//! A matrix definition is a record (JSON, YAML, TOML or Nickel):
+//!
+//! ```nickel
+//! {
+//!   axes = {
+//!     region = ["eu-west", "us-east"],
+//!     env = ["staging", "prod"],
+//!   },
+//!   output = "{region}/{env}.json",
+//!   exclude = [{ region = "us-east", env = "staging" }],
+//! }
+//! ```
+//!
+//! `output` is optional; by default the axis values are joined with `-`, in
+//! axis name order. `exclude` drops every combination matching all of the
+//! given axis values.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::matrix::Matrix;
+//! use serde_json::json;
+//!
+//! let matrix = Matrix::from_value(&json!({
+//!     "axes": { "region": ["eu", "us"], "env": ["dev", "prod"] },
+//!     "output": "{region}/{env}.json",
+//! }))
+//! .unwrap();
+//!
+//! let entries = matrix.expand().unwrap();
+//! assert_eq!(entries.len(), 4);
+//! assert_eq!(entries[0].path, "eu/dev.json");
+//! ```
+
+use crate::error::{Error, Result};
+use crate::loader::NickelLoader;
+use crate::source;
+use crate::threads;
+use serde_json::{Map, Value};
+use std::collections::BTreeSet;
+use std::path::{Component, Path};
+
+/// Name the combination is bound to in the entrypoint
+pub const BINDING: &str = "matrix";
+
+/// A matrix definition
+#[derive(Debug, Clone, PartialEq)]
+pub struct Matrix {
+    /// Axes and their values, in axis name order
+    pub axes: Vec<(String, Vec<Value>)>,
+    /// Output path template
+    pub output: Option<String>,
+    /// Partial combinations to leave out
+    pub exclude: Vec<Map<String, Value>>,
+}
+
+/// One combination of axis values and where its output goes
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// Axis values, keyed by axis name
+    pub combination: Map<String, Value>,
+    /// Output path, relative to the output directory
+    pub path: String,
+}
+
+impl Matrix {
+    /// Build a matrix from a loaded definition
+    ///
+    /// # Errors
+    ///
+    /// Returns an invalid-input error if the definition has the wrong shape
+    /// or an axis has no values
+    pub fn from_value(value: &Value) -> Result<Self> {
+        let invalid = |message: &str| Error::invalid_input(format!("Invalid matrix: {}", message));
+
+        let record = value
+            .as_object()
+            .ok_or_else(|| invalid("expected a record with an 'axes' field"))?;
+        if let Some(field) = record
+            .keys()
+            .find(|k| !matches!(k.as_str(), "axes" | "output" | "exclude"))
+        {
+            return Err(invalid(&format!("unknown field '{}'", field)));
+        }
+
+        let axes = record
+            .get("axes")
+            .and_then(Value::as_object)
+            .ok_or_else(|| invalid("'axes' must be a record of value lists"))?;
+        if axes.is_empty() {
+            return Err(invalid("'axes' is empty"));
+        }
+        let axes = axes
+            .iter()
+            .map(|(axis, values)| match values.as_array() {
+                Some(values) if !values.is_empty() => Ok((axis.clone(), values.clone())),
+                _ => Err(invalid(&format!(
+                    "axis '{}' must be a non-empty array",
+                    axis
+                ))),
+            })
+            .collect::<Result<_>>()?;
+
+        let output = match record.get("output") {
+            None => None,
+            Some(Value::String(output)) => Some(output.clone()),
+            Some(_) => return Err(invalid("'output' must be a string")),
+        };
+
+        let exclude = match record.get("exclude") {
+            None => Vec::new(),
+            Some(Value::Array(items)) => items
+                .iter()
+                .map(|item| item.as_object().cloned())
+                .collect::<Option<_>>()
+                .ok_or_else(|| invalid("'exclude' must be an array of records"))?,
+            Some(_) => return Err(invalid("'exclude' must be an array of records")),
+        };
+
+        Ok(Self {
+            axes,
+            output,
+            exclude,
+        })
+    }
+
+    /// Load a matrix definition from a file
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be loaded or is not a valid
+    /// definition
+    pub fn load(loader: &NickelLoader, path: &Path) -> Result<Self> {
+        Self::from_value(&crate::schema::load_sample(loader, path)?)
+    }
+
+    /// Every included combination, with its rendered output path
+    ///
+    /// Combinations are in order of the axes' values, the last axis varying
+    /// fastest.
+    ///
+    /// # Errors
+    ///
+    /// Returns an invalid-input error if an output path is unsafe, refers to
+    /// an unknown axis, or is shared by two combinations
+    pub fn expand(&self) -> Result<Vec<Entry>> {
+        let mut combinations = vec![Map::new()];
+        for (axis, values) in &self.axes {
+            combinations = combinations
+                .into_iter()
+                .flat_map(|combination| {
+                    values.iter().map(move |value| {
+                        let mut combination = combination.clone();
+                        combination.insert(axis.clone(), value.clone());
+                        combination
+                    })
+                })
+                .collect();
+        }
+
+        let mut seen = BTreeSet::new();
+        let mut entries = Vec::new();
+        for combination in combinations {
+            if self.is_excluded(&combination) {
+                continue;
+            }
+            let path = self.render(&combination)?;
+            if !seen.insert(path.clone()) {
+                return Err(Error::invalid_input(format!(
+                    "Matrix output path '{}' is shared by several combinations",
+                    path
+                )));
+            }
+            entries.push(Entry { combination, path });
+        }
+        Ok(entries)
+    }
+
+    fn is_excluded(&self, combination: &Map<String, Value>) -> bool {
+        self.exclude.iter().any(|rule| {
+            rule.iter()
+                .all(|(axis, value)| combination.get(axis) == Some(value))
+        })
+    }
+
+    fn render(&self, combination: &Map<String, Value>) -> Result<String> {
+        let path = match &self.output {
+            Some(template) => render_template(template, combination)?,
+            None => {
+                let parts = combination
+                    .values()
+                    .map(placeholder_value)
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| {
+                        Error::invalid_input(
+                            "Matrix axis values must be scalars unless 'output' is set",
+                        )
+                    })?;
+                format!("{}.json", parts.join("-"))
+            }
+        };
+        check_path(&path)?;
+        Ok(path)
+    }
+}
+
+/// Bind a combination in the entrypoint, keeping line numbers intact
+pub fn bind(template: &str, combination: &Map<String, Value>) -> String {
+    format!(
+        "let {} = {} in {}",
+        BINDING,
+        source::value_literal(&Value::Object(combination.clone())),
+        template
+    )
+}
+
+/// Evaluate the entrypoint once per entry, in entry order
+///
+/// A failure for one combination does not stop the others. Combinations are
+/// evaluated on the loader's [`threads`](NickelLoader::threads).
+pub fn evaluate(
+    loader: &NickelLoader,
+    template: &str,
+    name: &str,
+    entries: &[Entry],
+) -> Vec<Result<Value>> {
+    threads::map(loader.threads(), entries.iter().collect(), |entry| {
+        loader.parse_string(&bind(template, &entry.combination), name)
+    })
+}
+
+/// Substitute `{axis}` placeholders; `{{` and `}}` are literal braces
+fn render_template(template: &str, combination: &Map<String, Value>) -> Result<String> {
+    let invalid = |message: String| {
+        Error::invalid_input(format!(
+            "Invalid output template '{}': {}",
+            template, message
+        ))
+    };
+
+    let mut out = String::new();
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.as_str().starts_with('{') => {
+                chars.next();
+                out.push('{');
+            }
+            '}' if chars.as_str().starts_with('}') => {
+                chars.next();
+                out.push('}');
+            }
+            '{' => {
+                let rest = chars.as_str();
+                let end = rest
+                    .find('}')
+                    .ok_or_else(|| invalid("unterminated '{'".to_string()))?;
+                let axis = &rest[..end];
+                let value = combination
+                    .get(axis)
+                    .ok_or_else(|| invalid(format!("unknown axis '{}'", axis)))?;
+                let value = placeholder_value(value)
+                    .ok_or_else(|| invalid(format!("axis '{}' has a non-scalar value", axis)))?;
+                out.push_str(&value);
+                chars = rest[end + 1..].chars();
+            }
+            '}' => return Err(invalid("unmatched '}'".to_string())),
+            c => out.push(c),
+        }
+    }
+    Ok(out)
+}
+
+fn placeholder_value(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+/// Reject output paths that would escape the output directory
+fn check_path(path: &str) -> Result<()> {
+    let safe = !path.is_empty()
+        && Path::new(path)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)));
+    if safe {
+        Ok(())
+    } else {
+        Err(Error::invalid_input(format!(
+            "Matrix output path '{}' must be relative and stay inside the output directory",
+            path
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn matrix(value: Value) -> Matrix {
+        Matrix::from_value(&value).unwrap()
+    }
+
+    #[test]
+    fn test_cartesian_product_with_exclusions() {
+        let m = matrix(json!({
+            "axes": { "env": ["dev", "prod"], "region": ["eu", "us"], "shard": [1] },
+            "exclude": [{ "env": "dev", "region": "us" }],
+        }));
+        let paths: Vec<String> = m.expand().unwrap().into_iter().map(|e| e.path).collect();
+        assert_eq!(
+            paths,
+            vec!["dev-eu-1.json", "prod-eu-1.json", "prod-us-1.json"]
+        );
+    }
+
+    #[test]
+    fn test_output_templates() {
+        let combination = json!({ "region": "eu", "n": 2 });
+        let combination = combination.as_object().unwrap();
+        assert_eq!(
+            render_template("{region}/cluster-{n}.json", combination).unwrap(),
+            "eu/cluster-2.json"
+        );
+        assert_eq!(render_template("{{x}}-{n}", combination).unwrap(), "{x}-2");
+        assert!(render_template("{zone}.json", combination).is_err());
+        assert!(render_template("{region", combination).is_err());
+    }
+
+    #[test]
+    fn test_rejects_unsafe_and_colliding_paths() {
+        let m = matrix(json!({ "axes": { "r": ["..", "x"] }, "output": "{r}/out.json" }));
+        assert!(m.expand().is_err());
+
+        let m = matrix(json!({ "axes": { "r": ["a", "b"] }, "output": "same.json" }));
+        assert!(m.expand().is_err());
+    }
+
+    #[test]
+    fn test_rejects_malformed_definitions() {
+        assert!(Matrix::from_value(&json!({ "axes": {} })).is_err());
+        assert!(Matrix::from_value(&json!({ "axes": { "r": [] } })).is_err());
+        assert!(Matrix::from_value(&json!({ "axes": { "r": [1] }, "typo": 1 })).is_err());
+    }
+
+    #[test]
+    fn test_evaluate_binds_combination() {
+        let m = matrix(json!({ "axes": { "env": ["dev", "prod"] } }));
+        let entries = m.expand().unwrap();
+        let results = evaluate(
+            &NickelLoader::new(),
+            r#"{ replicas = if matrix.env == "prod" then 3 else 1 }"#,
+            "app.ncl",
+            &entries,
+        );
+        assert_eq!(results[0].as_ref().unwrap(), &json!({ "replicas": 1 }));
+        assert_eq!(results[1].as_ref().unwrap(), &json!({ "replicas": 3 }));
+    }
+}
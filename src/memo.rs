@@ -0,0 +1,316 @@
+//! Memoized evaluation for hot paths
+//!
+//! A web service that reads its config on every request should not
+//! evaluate it on every request. [`MemoizedLoader`] remembers each result
+//! by file and overrides, with the config's [`Fingerprint`], and evaluates
+//! again only when the fingerprint changes: when the config, a file it
+//! imports, or the loader's settings do.
+//!
+//! Computing a fingerprint reads the config's import tree, which is cheaper
+//! than evaluating it but not free. [`with_check_interval`] rate-limits
+//! those reads: within the interval after a check, the remembered result is
+//! returned as is, so a change is picked up at most that late.
+//! [`with_ttl`] bounds how long a result is kept at all, and
+//! [`with_max_entries`] how many are kept, forgetting the least recently
+//! used first.
+//!
+//! Only successes are remembered; a config that fails to evaluate is
+//! evaluated again next time.
+//!
+//! [`with_check_interval`]: MemoizedLoader::with_check_interval
+//! [`with_ttl`]: MemoizedLoader::with_ttl
+//! [`with_max_entries`]: MemoizedLoader::with_max_entries
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::{MemoizedLoader, NickelLoader};
+//! use std::time::Duration;
+//!
+//! let dir = tempfile::tempdir().unwrap();
+//! let config = dir.path().join("app.ncl");
+//! std::fs::write(&config, "{ port = 80 }").unwrap();
+//!
+//! let loader = MemoizedLoader::new(NickelLoader::new()).with_check_interval(Duration::ZERO);
+//! assert_eq!(loader.parse_file(&config).unwrap()["port"], 80);
+//! assert_eq!(loader.parse_file(&config).unwrap()["port"], 80); // remembered
+//!
+//! std::fs::write(&config, "{ port = 8080 }").unwrap();
+//! assert_eq!(loader.parse_file(&config).unwrap()["port"], 8080);
+//! ```
+
+use crate::error::Result;
+use crate::fingerprint::Fingerprint;
+use crate::imports;
+use crate::json;
+use crate::loader::NickelLoader;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Results remembered unless another limit is given
+pub const DEFAULT_MAX_ENTRIES: usize = 1024;
+
+/// Shortest time between fingerprint checks of one result unless another
+/// is given
+pub const DEFAULT_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A file and the overrides it is evaluated with
+type Key = (PathBuf, String);
+
+/// A remembered result
+#[derive(Debug)]
+struct Entry {
+    fingerprint: Fingerprint,
+    value: Arc<Value>,
+    evaluated: Instant,
+    checked: Instant,
+    used: Instant,
+}
+
+/// A [`NickelLoader`] that remembers what it evaluated
+///
+/// `Send` and `Sync`: share one between request handlers.
+#[derive(Debug)]
+pub struct MemoizedLoader {
+    loader: NickelLoader,
+    ttl: Option<Duration>,
+    max_entries: usize,
+    check_interval: Duration,
+    entries: Mutex<HashMap<Key, Entry>>,
+}
+
+impl MemoizedLoader {
+    /// Remember what `loader` evaluates
+    pub fn new(loader: NickelLoader) -> Self {
+        Self {
+            loader,
+            ttl: None,
+            max_entries: DEFAULT_MAX_ENTRIES,
+            check_interval: DEFAULT_CHECK_INTERVAL,
+            entries: Mutex::default(),
+        }
+    }
+
+    /// Evaluate again after `ttl`, even if nothing changed
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Remember at most `entries` results; 0 remembers none
+    pub fn with_max_entries(mut self, entries: usize) -> Self {
+        self.max_entries = entries;
+        self
+    }
+
+    /// Check whether a result is out of date at most once per `interval`;
+    /// zero checks on every call
+    pub fn with_check_interval(mut self, interval: Duration) -> Self {
+        self.check_interval = interval;
+        self
+    }
+
+    /// The loader results are evaluated with
+    pub fn loader(&self) -> &NickelLoader {
+        &self.loader
+    }
+
+    /// Evaluate `path`, or return what it evaluated to before if that is
+    /// still current
+    ///
+    /// # Errors
+    ///
+    /// Returns an I/O error if the file or an import cannot be read, or the
+    /// evaluation error
+    pub fn parse_file(&self, path: &Path) -> Result<Arc<Value>> {
+        self.parse_file_with_overrides(path, &[])
+    }
+
+    /// As [`parse_file`](Self::parse_file), with `overrides` applied as by
+    /// [`NickelLoader::with_overrides`]
+    ///
+    /// Results are remembered per set of overrides.
+    ///
+    /// # Errors
+    ///
+    /// As [`parse_file`](Self::parse_file)
+    pub fn parse_file_with_overrides(
+        &self,
+        path: &Path,
+        overrides: &[(String, Value)],
+    ) -> Result<Arc<Value>> {
+        let key = key(path, overrides);
+        let now = Instant::now();
+        if let Some(value) = self.lookup(&key, now, |entry| {
+            now.duration_since(entry.checked) < self.check_interval
+        }) {
+            return Ok(value);
+        }
+
+        let overridden;
+        let loader = if overrides.is_empty() {
+            &self.loader
+        } else {
+            overridden = self.loader.clone().with_overrides(overrides.to_vec());
+            &overridden
+        };
+        let fingerprint = Fingerprint::of(loader, path)?;
+        if let Some(value) = self.lookup(&key, now, |entry| entry.fingerprint == fingerprint) {
+            return Ok(value);
+        }
+
+        // Evaluated without the lock, so other configs are served meanwhile
+        let value = Arc::new(loader.parse_file(path)?);
+        self.remember(
+            key,
+            Entry {
+                fingerprint,
+                value: Arc::clone(&value),
+                evaluated: now,
+                checked: now,
+                used: now,
+            },
+        );
+        Ok(value)
+    }
+
+    /// Forget the results of `path`, with any overrides
+    pub fn invalidate(&self, path: &Path) {
+        let path = imports::normalize(path);
+        self.entries
+            .lock()
+            .expect("memo entries poisoned")
+            .retain(|(file, _), _| *file != path);
+    }
+
+    /// Forget every result
+    pub fn clear(&self) {
+        self.entries.lock().expect("memo entries poisoned").clear();
+    }
+
+    /// Number of results remembered
+    pub fn len(&self) -> usize {
+        self.entries.lock().expect("memo entries poisoned").len()
+    }
+
+    /// Whether no result is remembered
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The remembered result for `key` if it has not expired and is
+    /// `current`, marked as checked and used at `now`
+    fn lookup(
+        &self,
+        key: &Key,
+        now: Instant,
+        current: impl FnOnce(&Entry) -> bool,
+    ) -> Option<Arc<Value>> {
+        let mut entries = self.entries.lock().expect("memo entries poisoned");
+        let entry = entries.get_mut(key)?;
+        if self
+            .ttl
+            .is_some_and(|ttl| now.duration_since(entry.evaluated) >= ttl)
+        {
+            entries.remove(key);
+            return None;
+        }
+        if !current(entry) {
+            return None;
+        }
+        entry.checked = entry.checked.max(now);
+        entry.used = now;
+        Some(Arc::clone(&entry.value))
+    }
+
+    fn remember(&self, key: Key, entry: Entry) {
+        if self.max_entries == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().expect("memo entries poisoned");
+        if !entries.contains_key(&key) && entries.len() >= self.max_entries {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(key, entry);
+    }
+}
+
+fn key(path: &Path, overrides: &[(String, Value)]) -> Key {
+    let overrides: Vec<String> = overrides
+        .iter()
+        .map(|(path, value)| format!("{}={}", path, json::to_string(value, false)))
+        .collect();
+    (imports::normalize(path), overrides.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_picks_up_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = dir.path().join("app.ncl");
+        let base = dir.path().join("base.ncl");
+        std::fs::write(&base, "{ port = 80 }").unwrap();
+        std::fs::write(&config, r#"import "base.ncl""#).unwrap();
+
+        let loader = MemoizedLoader::new(NickelLoader::new()).with_check_interval(Duration::ZERO);
+        let first = loader.parse_file(&config).unwrap();
+        assert!(Arc::ptr_eq(&first, &loader.parse_file(&config).unwrap()));
+
+        std::fs::write(&base, "{ port = 8080 }").unwrap();
+        assert_eq!(
+            *loader.parse_file(&config).unwrap(),
+            json!({ "port": 8080 })
+        );
+
+        let overridden = loader
+            .parse_file_with_overrides(&config, &[("port".to_string(), json!(443))])
+            .unwrap();
+        assert_eq!(overridden["port"], 443);
+        assert_eq!(loader.len(), 2);
+
+        std::fs::write(&base, "{ port = }").unwrap();
+        assert!(loader.parse_file(&config).is_err());
+        loader.invalidate(&config);
+        assert!(loader.is_empty());
+    }
+
+    #[test]
+    fn test_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = dir.path().join("app.ncl");
+        std::fs::write(&config, "{ port = 80 }").unwrap();
+
+        // Within the check interval, a change is not seen yet
+        let loader =
+            MemoizedLoader::new(NickelLoader::new()).with_check_interval(Duration::from_secs(3600));
+        loader.parse_file(&config).unwrap();
+        std::fs::write(&config, "{ port = 8080 }").unwrap();
+        assert_eq!(loader.parse_file(&config).unwrap()["port"], 80);
+
+        // An expired result is evaluated again
+        let loader = loader.with_ttl(Duration::ZERO);
+        assert_eq!(loader.parse_file(&config).unwrap()["port"], 8080);
+
+        let loader = MemoizedLoader::new(NickelLoader::new()).with_max_entries(2);
+        for port in [1, 2, 3] {
+            let overrides = [("port".to_string(), json!(port))];
+            loader
+                .parse_file_with_overrides(&config, &overrides)
+                .unwrap();
+        }
+        assert_eq!(loader.len(), 2);
+    }
+}
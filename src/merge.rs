@@ -0,0 +1,415 @@
+//! Structure-aware three-way merges of Nickel sources
+//!
+//! Backs `bunsenite merge-driver`, a git merge driver for `.ncl` files.
+//! Text-level merges conflict whenever two branches touch neighbouring lines
+//! of a record, and can silently interleave fields. This merge works on record
+//! fields instead: each field is merged as a unit, records nested in fields
+//! are merged recursively, and conflict markers are only emitted around
+//! fields that both sides changed differently.
+//!
+//! The merge is lexical: it understands records, strings and comments, but
+//! not the rest of the Nickel grammar. Anything that is not a record literal
+//! (a `let` preamble, a field value that is a function call) is merged as
+//! opaque text.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::merge;
+//!
+//! let base = "{\n  port = 80,\n  host = \"a\",\n}\n";
+//! let ours = "{\n  port = 8080,\n  host = \"a\",\n}\n";
+//! let theirs = "{\n  port = 80,\n  host = \"b\",\n}\n";
+//!
+//! let merged = merge::merge(base, ours, theirs);
+//! assert_eq!(merged.conflicts, 0);
+//! assert_eq!(merged.text, "{\n  port = 8080,\n  host = \"b\",\n}\n");
+//! ```
+
+use crate::lexer::{self, Token};
+use std::collections::HashMap;
+
+/// Default length of conflict markers (matches git)
+pub const DEFAULT_MARKER_SIZE: usize = 7;
+
+/// Result of a three-way merge
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Merged {
+    /// Merged source, with conflict markers if `conflicts > 0`
+    pub text: String,
+    /// Number of conflicting regions
+    pub conflicts: usize,
+}
+
+/// Merge `ours` and `theirs` against their common ancestor `base`
+///
+/// Uses [`DEFAULT_MARKER_SIZE`] markers; see [`merge_with_markers`].
+pub fn merge(base: &str, ours: &str, theirs: &str) -> Merged {
+    merge_with_markers(base, ours, theirs, DEFAULT_MARKER_SIZE)
+}
+
+/// Merge `ours` and `theirs` against `base` with markers of the given length
+pub fn merge_with_markers(base: &str, ours: &str, theirs: &str, marker_size: usize) -> Merged {
+    let mut merger = Merger {
+        marker_size,
+        conflicts: 0,
+    };
+    let text = merger.merge(base, ours, theirs);
+    Merged {
+        text,
+        conflicts: merger.conflicts,
+    }
+}
+
+struct Merger {
+    marker_size: usize,
+    conflicts: usize,
+}
+
+impl Merger {
+    /// Merge a whole source, or the non-field parts of a record
+    fn merge(&mut self, base: &str, ours: &str, theirs: &str) -> String {
+        if let Some(text) = trivial(base, ours, theirs) {
+            return text.to_string();
+        }
+
+        match self.merge_structured(base, ours, theirs) {
+            Some(text) => text,
+            None => self.conflict(ours, theirs),
+        }
+    }
+
+    /// Merge a field definition
+    ///
+    /// Returns the merged text and whether it is a conflict region, which
+    /// carries its own separating commas.
+    fn merge_field(&mut self, base: &str, ours: &str, theirs: &str) -> (String, bool) {
+        if let Some(text) = trivial(base, ours, theirs) {
+            return (text.to_string(), false);
+        }
+
+        match self.merge_structured(base, ours, theirs) {
+            Some(text) => (text, false),
+            None => (self.field_conflict(ours, theirs), true),
+        }
+    }
+
+    /// Merge three sources ending in record literals field by field
+    fn merge_structured(&mut self, base: &str, ours: &str, theirs: &str) -> Option<String> {
+        let base = Record::split(base)?;
+        let ours = Record::split(ours)?;
+        let theirs = Record::split(theirs)?;
+
+        let base_fields = base.by_key();
+        let our_fields = ours.by_key();
+        let their_fields = theirs.by_key();
+
+        let mut out = self.merge(base.open, ours.open, theirs.open);
+        let mut pieces: Vec<(String, bool)> = Vec::new();
+
+        for (key, ours_text) in ours.keyed() {
+            let base_text = base_fields.get(&key).copied();
+            match (base_text, their_fields.get(&key).copied()) {
+                (_, Some(theirs_text)) => {
+                    pieces.push(self.merge_field(base_text.unwrap_or(""), ours_text, theirs_text));
+                }
+                // Deleted by them
+                (Some(base_text), None) if base_text == ours_text => {}
+                (Some(_), None) => pieces.push((self.field_conflict(ours_text, ""), true)),
+                // Added by us
+                (None, None) => pieces.push((ours_text.to_string(), false)),
+            }
+        }
+
+        for (key, theirs_text) in theirs.keyed() {
+            if our_fields.contains_key(&key) {
+                continue;
+            }
+            match base_fields.get(&key).copied() {
+                // Deleted by us
+                Some(base_text) if base_text == theirs_text => {}
+                Some(_) => pieces.push((self.field_conflict("", theirs_text), true)),
+                // Added by them
+                None => pieces.push((theirs_text.to_string(), false)),
+            }
+        }
+
+        // Fields appended after a comma-less last field need a separator
+        let trailing_comma = ours.trailing_comma || pieces.len() > ours.fields.len();
+        let count = pieces.len();
+        for (i, (text, is_conflict)) in pieces.into_iter().enumerate() {
+            out.push_str(&text);
+            if !is_conflict && (i + 1 < count || trailing_comma) {
+                out.push(',');
+            }
+        }
+
+        out.push_str(&self.merge(base.tail, ours.tail, theirs.tail));
+        out.push_str(&self.merge(base.close, ours.close, theirs.close));
+        Some(out)
+    }
+
+    /// Conflict region standing in for a field, with each side comma-terminated
+    fn field_conflict(&mut self, ours: &str, theirs: &str) -> String {
+        self.conflicts += 1;
+        let mut out = String::new();
+        for (marker, side) in [('<', Some(ours)), ('=', Some(theirs)), ('>', None)] {
+            out.push('\n');
+            out.push_str(&marker.to_string().repeat(self.marker_size));
+            match marker {
+                '<' => out.push_str(" ours"),
+                '>' => out.push_str(" theirs"),
+                _ => {}
+            }
+            if let Some(side) = side.filter(|side| !is_blank(side)) {
+                if !side.starts_with('\n') {
+                    out.push('\n');
+                }
+                out.push_str(side);
+                out.push(',');
+            }
+        }
+        out
+    }
+
+    /// Conflict region over arbitrary text, with markers on their own lines
+    fn conflict(&mut self, ours: &str, theirs: &str) -> String {
+        self.conflicts += 1;
+        let line = |text: &str| {
+            if text.is_empty() || text.ends_with('\n') {
+                text.to_string()
+            } else {
+                format!("{}\n", text)
+            }
+        };
+        format!(
+            "{} ours\n{}{}\n{}{} theirs\n",
+            "<".repeat(self.marker_size),
+            line(ours),
+            "=".repeat(self.marker_size),
+            line(theirs),
+            ">".repeat(self.marker_size),
+        )
+    }
+}
+
+/// Resolve merges where at most one side changed
+fn trivial<'a>(base: &str, ours: &'a str, theirs: &'a str) -> Option<&'a str> {
+    if ours == theirs || theirs == base {
+        Some(ours)
+    } else if ours == base {
+        Some(theirs)
+    } else {
+        None
+    }
+}
+
+/// A source ending in a record literal, split into fields
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Record<'a> {
+    /// Everything up to and including the opening brace
+    open: &'a str,
+    /// Field texts without separating commas, including leading whitespace
+    pub(crate) fields: Vec<&'a str>,
+    /// Whether the last field is followed by a comma
+    trailing_comma: bool,
+    /// Whitespace and comments before the closing brace
+    tail: &'a str,
+    /// The closing brace and anything after it
+    close: &'a str,
+}
+
+impl<'a> Record<'a> {
+    /// Split a source whose last code character closes a record literal
+    pub(crate) fn split(source: &'a str) -> Option<Self> {
+        let code = code_chars(source);
+        let &(close, last) = code.last()?;
+        if last != '}' {
+            return None;
+        }
+
+        // Find the matching opening brace
+        let mut depth = 0usize;
+        let mut open = None;
+        for (i, &(pos, c)) in code.iter().enumerate().rev() {
+            match c {
+                '}' | ']' | ')' => depth += 1,
+                '{' | '[' | '(' => {
+                    depth = depth.checked_sub(1)?;
+                    if depth == 0 {
+                        open = Some((i, pos));
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+        let (open_index, open) = open?;
+        if code[open_index].1 != '{' {
+            return None;
+        }
+
+        let mut fields = Vec::new();
+        let mut start = open + 1;
+        let mut depth = 0usize;
+        for &(pos, c) in &code[open_index + 1..code.len() - 1] {
+            match c {
+                '{' | '[' | '(' => depth += 1,
+                '}' | ']' | ')' => depth = depth.saturating_sub(1),
+                ',' if depth == 0 => {
+                    fields.push(&source[start..pos]);
+                    start = pos + 1;
+                }
+                _ => {}
+            }
+        }
+
+        let rest = &source[start..close];
+        let (trailing_comma, tail) = if is_blank(rest) {
+            (!fields.is_empty(), rest)
+        } else {
+            let field = rest.trim_end();
+            fields.push(field);
+            (false, &rest[field.len()..])
+        };
+
+        Some(Record {
+            open: &source[..=open],
+            fields,
+            trailing_comma,
+            tail,
+            close: &source[close..],
+        })
+    }
+
+    /// Fields paired with unique keys, in source order
+    ///
+    /// Fields defined more than once (which Nickel merges) are told apart by
+    /// occurrence.
+    fn keyed(&self) -> Vec<(String, &'a str)> {
+        let mut seen: HashMap<String, usize> = HashMap::new();
+        self.fields
+            .iter()
+            .map(|&text| {
+                let key = field_key(text);
+                let n = seen.entry(key.clone()).or_default();
+                *n += 1;
+                let key = if *n == 1 {
+                    key
+                } else {
+                    format!("{}#{}", key, n)
+                };
+                (key, text)
+            })
+            .collect()
+    }
+
+    fn by_key(&self) -> HashMap<String, &'a str> {
+        self.keyed().into_iter().collect()
+    }
+}
+
+/// The field path a field definition starts with, e.g. `server.port`
+pub(crate) fn field_key(text: &str) -> String {
+    let code = code_chars(text);
+    let end = code
+        .iter()
+        .find(|&&(_, c)| matches!(c, '=' | '|' | ':' | '{' | '['))
+        .map_or(text.len(), |&(pos, _)| pos);
+    let start = code.first().map_or(0, |&(pos, _)| pos).min(end);
+    text[start..end]
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn is_blank(text: &str) -> bool {
+    code_chars(text).is_empty()
+}
+
+/// Byte positions of characters outside strings and comments
+///
+/// Whitespace is skipped as well. A string, including any interpolated code,
+/// is one `"` at its opening delimiter.
+pub(crate) fn code_chars(source: &str) -> Vec<(usize, char)> {
+    let mut code = Vec::new();
+    for (span, token) in lexer::spanned_lenient(source) {
+        match token {
+            Token::Space | Token::Newline | Token::Comment(_) => {}
+            Token::Str(_) => code.push((span.start, '"')),
+            _ => code.extend(
+                source[span.clone()]
+                    .char_indices()
+                    .map(|(i, c)| (span.start + i, c)),
+            ),
+        }
+    }
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_independent_field_changes_merge_cleanly() {
+        let base = "{\n  a = 1,\n  b = 2,\n  c = 3,\n}\n";
+        let ours = "{\n  a = 10,\n  b = 2,\n  c = 3,\n}\n";
+        let theirs = "{\n  a = 1,\n  b = 20,\n  c = 3,\n}\n";
+
+        let merged = merge(base, ours, theirs);
+        assert_eq!(merged.conflicts, 0);
+        assert_eq!(merged.text, "{\n  a = 10,\n  b = 20,\n  c = 3,\n}\n");
+    }
+
+    #[test]
+    fn test_additions_and_deletions() {
+        let base = "{\n  a = 1,\n  b = 2,\n}";
+        let ours = "{\n  a = 1,\n  b = 2,\n  c = 3,\n}";
+        let theirs = "{\n  b = 2,\n  d = 4\n}";
+
+        let merged = merge(base, ours, theirs);
+        assert_eq!(merged.conflicts, 0);
+        assert_eq!(merged.text, "{\n  b = 2,\n  c = 3,\n  d = 4,\n}");
+    }
+
+    #[test]
+    fn test_nested_records_merge_recursively() {
+        let base = "let x = 1 in {\n  server = {\n    host = \"a\",\n    port = 80,\n  },\n}\n";
+        let ours = "let x = 1 in {\n  server = {\n    host = \"b\",\n    port = 80,\n  },\n}\n";
+        let theirs = "let x = 1 in {\n  server = {\n    host = \"a\",\n    port = 81,\n  },\n}\n";
+
+        let merged = merge(base, ours, theirs);
+        assert_eq!(merged.conflicts, 0);
+        assert!(merged.text.contains("host = \"b\""));
+        assert!(merged.text.contains("port = 81"));
+    }
+
+    #[test]
+    fn test_conflicting_changes_get_markers() {
+        let base = "{\n  a = 1,\n  b = 2,\n}\n";
+        let ours = "{\n  a = 10,\n  b = 2,\n}\n";
+        let theirs = "{\n  a = 11,\n  b = 3,\n}\n";
+
+        let merged = merge(base, ours, theirs);
+        assert_eq!(merged.conflicts, 1);
+        assert_eq!(
+            merged.text,
+            "{\n<<<<<<< ours\n  a = 10,\n=======\n  a = 11,\n>>>>>>> theirs\n  b = 3,\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_strings_and_comments_do_not_split_fields() {
+        let record = Record::split("{ a = \"x, }\", # b, }\n c = m%\"{,\"%, d = [1, 2] }").unwrap();
+        let keys: Vec<String> = record.keyed().into_iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, vec!["a", "c", "d"]);
+    }
+
+    #[test]
+    fn test_non_records_conflict_as_text() {
+        let merged = merge("1", "2", "3");
+        assert_eq!(merged.conflicts, 1);
+        assert_eq!(merged.text, "<<<<<<< ours\n2\n=======\n3\n>>>>>>> theirs\n");
+    }
+}
@@ -0,0 +1,216 @@
+//! Metered evaluation
+//!
+//! Nickel cannot stop an evaluation from outside, but every variable it
+//! enters and every value it allocates goes through its evaluation cache.
+//! [`Metered`] is the loader's evaluation cache: while a [`Budget`] is in
+//! force on the thread ([`metered`]), it charges those steps against the
+//! budget, and once the budget is spent the evaluation fails at its next
+//! step, on the thread that ran it, rather than running on.
+//!
+//! Memory is estimated rather than measured: each value allocated counts as
+//! [`VALUE_SIZE`] bytes, and each string or array an evaluation produces
+//! counts its length. A budget spent in the middle of one operation, such
+//! as `std.array.generate` building a large array, unwinds out of it at
+//! once; where panics abort instead of unwinding, the operation is left to
+//! finish first.
+//!
+//! Without a budget in force, evaluation is not metered.
+
+use crate::error::{Error, Result};
+use crate::limits;
+use nickel_lang_core::eval::cache::lazy::{CBNCache, Thunk};
+use nickel_lang_core::eval::cache::{BlackholedError, Cache, CacheIndex};
+use nickel_lang_core::eval::Closure;
+use nickel_lang_core::identifier::Ident;
+use nickel_lang_core::term::record::FieldDeps;
+use nickel_lang_core::term::{BindingType, RichTerm, Term};
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+/// Bytes charged for each value an evaluation allocates
+pub(crate) const VALUE_SIZE: u64 = 64;
+
+/// Steps between looks at the clock
+const CLOCK_EVERY: u64 = 1024;
+
+/// What an evaluation may spend
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Budget {
+    pub(crate) time: Duration,
+    pub(crate) memory: u64,
+}
+
+/// The resource a budget ran out of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Spent {
+    Time,
+    Memory,
+}
+
+/// What is left of the budget in force on a thread
+#[derive(Debug, Clone, Copy)]
+struct Meter {
+    deadline: Instant,
+    memory: u64,
+    steps: u64,
+    spent: Option<Spent>,
+}
+
+thread_local! {
+    static METER: Cell<Option<Meter>> = const { Cell::new(None) };
+}
+
+/// Run `f`, with every evaluation it runs on this thread charged to
+/// `budget`
+///
+/// # Errors
+///
+/// Returns [`Error::ResourceLimit`] if the budget runs out, else the
+/// result of `f`
+pub(crate) fn metered<T>(budget: Budget, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    let meter = Meter {
+        deadline: Instant::now() + budget.time,
+        memory: budget.memory,
+        steps: 0,
+        spent: None,
+    };
+    let outer = METER.with(|cell| cell.replace(Some(meter)));
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f));
+    let spent = METER
+        .with(|cell| cell.replace(outer))
+        .and_then(|meter| meter.spent);
+
+    let over = |spent| match spent {
+        Spent::Time => Error::resource_limit("time", crate::loader::format_timeout(budget.time)),
+        Spent::Memory => Error::resource_limit("memory", limits::format_size(budget.memory)),
+    };
+    match (result, spent) {
+        (Ok(Ok(value)), _) => Ok(value),
+        (Ok(Err(_)), Some(spent)) => Err(over(spent)),
+        (Ok(Err(e)), None) => Err(e),
+        (Err(payload), spent) => match (payload.downcast_ref::<Spent>(), spent) {
+            (Some(_), Some(spent)) => Err(over(spent)),
+            _ => std::panic::resume_unwind(payload),
+        },
+    }
+}
+
+/// Charge one step and `bytes` of memory to the budget in force, if any,
+/// and return what it has run out of
+fn charge(bytes: u64) -> Option<Spent> {
+    METER.with(|cell| {
+        let mut meter = cell.get()?;
+        if meter.spent.is_none() {
+            if meter.steps % CLOCK_EVERY == 0 && Instant::now() >= meter.deadline {
+                meter.spent = Some(Spent::Time);
+            } else if bytes > meter.memory {
+                meter.spent = Some(Spent::Memory);
+            } else {
+                meter.memory -= bytes;
+            }
+            meter.steps += 1;
+            cell.set(Some(meter));
+        }
+        meter.spent
+    })
+}
+
+/// Leave the operation under way if the budget is spent and panics unwind
+fn unwind_if_spent(spent: Option<Spent>) {
+    if let Some(spent) = spent {
+        if cfg!(panic = "unwind") {
+            // Not a panic: the hook stays quiet, and `metered` catches it
+            std::panic::resume_unwind(Box::new(spent));
+        }
+    }
+}
+
+/// Nickel's call-by-need evaluation cache, charging the budget in force
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Metered(CBNCache);
+
+impl Cache for Metered {
+    type UpdateIndex = <CBNCache as Cache>::UpdateIndex;
+
+    fn get(&self, idx: CacheIndex) -> Closure {
+        self.0.get(idx)
+    }
+
+    fn get_update_index(
+        &mut self,
+        idx: &mut CacheIndex,
+    ) -> std::result::Result<Option<Self::UpdateIndex>, BlackholedError> {
+        if charge(0).is_some() {
+            // Surfaces as an evaluation error, which `metered` replaces
+            return Err(BlackholedError);
+        }
+        self.0.get_update_index(idx)
+    }
+
+    fn add(&mut self, clos: Closure, bty: BindingType) -> CacheIndex {
+        unwind_if_spent(charge(VALUE_SIZE));
+        self.0.add(clos, bty)
+    }
+
+    fn patch<F: Fn(&mut Closure)>(&mut self, idx: CacheIndex, f: F) {
+        self.0.patch(idx, f)
+    }
+
+    fn get_then<T, F: FnOnce(&Closure) -> T>(&self, idx: CacheIndex, f: F) -> T {
+        self.0.get_then(idx, f)
+    }
+
+    fn update(&mut self, clos: Closure, idx: Self::UpdateIndex) {
+        let bytes = match &*clos.body.term {
+            Term::Str(s) => s.len() as u64,
+            Term::Array(array, _) => (array.len() * std::mem::size_of::<RichTerm>()) as u64,
+            _ => 0,
+        };
+        let spent = charge(bytes);
+        self.0.update(clos, idx);
+        unwind_if_spent(spent);
+    }
+
+    fn new() -> Self {
+        Metered(CBNCache::new())
+    }
+
+    fn reset_index_state(&mut self, idx: &mut Self::UpdateIndex) {
+        self.0.reset_index_state(idx)
+    }
+
+    fn map_at_index<F: FnMut(&mut Self, &Closure) -> Closure>(
+        &mut self,
+        idx: &CacheIndex,
+        mut f: F,
+    ) -> CacheIndex {
+        Thunk::map(idx, |closure| f(self, closure))
+    }
+
+    fn build_cached(&mut self, idx: &mut CacheIndex, rec_env: &[(Ident, CacheIndex)]) {
+        self.0.build_cached(idx, rec_env)
+    }
+
+    fn saturate<I: DoubleEndedIterator<Item = Ident> + Clone>(
+        &mut self,
+        idx: CacheIndex,
+        fields: I,
+    ) -> RichTerm {
+        self.0.saturate(idx, fields)
+    }
+
+    fn revert(&mut self, idx: &CacheIndex) -> CacheIndex {
+        self.0.revert(idx)
+    }
+
+    fn deps(&self, idx: &CacheIndex) -> Option<FieldDeps> {
+        self.0.deps(idx)
+    }
+
+    fn make_update_index(
+        &mut self,
+        idx: &mut CacheIndex,
+    ) -> std::result::Result<Self::UpdateIndex, BlackholedError> {
+        self.0.make_update_index(idx)
+    }
+}
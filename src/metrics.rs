@@ -0,0 +1,399 @@
+//! Prometheus metrics for the evaluation daemon
+//!
+//! Backs `bunsenite serve --metrics-addr 127.0.0.1:9090`: while serving, an
+//! HTTP endpoint answers `GET /metrics` in the Prometheus text format with
+//!
+//! - `bunsenite_requests_total{method, outcome}`: answered requests
+//! - `bunsenite_request_duration_seconds{method}`: a latency histogram,
+//!   from a request starting to its response being ready
+//! - `bunsenite_evaluation_errors_total{method, kind}`: failed requests by
+//!   error kind (`parse`, `evaluation`, `invalid_input`, `serialization`,
+//!   `other`)
+//! - gauges for uptime, workers, queued and running requests, registered
+//!   libraries and resident memory, as in a `status` snapshot
+//!
+//! The daemon keeps no caches between requests (every request is evaluated
+//! from scratch), so there are no cache hit rates to export.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::metrics::Metrics;
+//! use std::time::Duration;
+//!
+//! let metrics = Metrics::default();
+//! metrics.observe("parse", Duration::from_millis(20), None);
+//! let text = metrics.render();
+//! assert!(text.contains("bunsenite_requests_total{method=\"parse\",outcome=\"ok\"} 1\n"));
+//! ```
+
+use crate::error::{Error, Result};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Upper bounds of the latency histogram buckets, in seconds
+pub const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Request counters and latencies of a daemon
+#[derive(Debug, Default)]
+pub struct Metrics {
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    requests: BTreeMap<(String, &'static str), u64>,
+    errors: BTreeMap<(String, &'static str), u64>,
+    latency: BTreeMap<String, Histogram>,
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    /// Observations per bucket, not cumulative; the last is `+Inf`
+    buckets: [u64; BUCKETS.len() + 1],
+    sum: f64,
+    count: u64,
+}
+
+impl Metrics {
+    /// Count one answered request of `method`, which took `elapsed` and
+    /// failed with `error`, if any
+    pub fn observe(&self, method: &str, elapsed: Duration, error: Option<&Error>) {
+        let mut state = self.state.lock().expect("metrics poisoned");
+        let outcome = if error.is_some() { "error" } else { "ok" };
+        *state
+            .requests
+            .entry((method.to_string(), outcome))
+            .or_default() += 1;
+        if let Some(error) = error {
+            *state
+                .errors
+                .entry((method.to_string(), error_kind(error)))
+                .or_default() += 1;
+        }
+
+        let seconds = elapsed.as_secs_f64();
+        let histogram = state.latency.entry(method.to_string()).or_default();
+        let bucket = BUCKETS
+            .iter()
+            .position(|&bound| seconds <= bound)
+            .unwrap_or(BUCKETS.len());
+        histogram.buckets[bucket] += 1;
+        histogram.sum += seconds;
+        histogram.count += 1;
+    }
+
+    /// The counters and histograms in the Prometheus text format
+    pub fn render(&self) -> String {
+        let state = self.state.lock().expect("metrics poisoned");
+        let mut text = String::new();
+
+        header(
+            &mut text,
+            "bunsenite_requests_total",
+            "counter",
+            "Answered requests",
+        );
+        for ((method, outcome), count) in &state.requests {
+            let _ = writeln!(
+                text,
+                "bunsenite_requests_total{{method=\"{}\",outcome=\"{}\"}} {}",
+                method, outcome, count
+            );
+        }
+
+        header(
+            &mut text,
+            "bunsenite_request_duration_seconds",
+            "histogram",
+            "Time from a request starting to its response being ready",
+        );
+        for (method, histogram) in &state.latency {
+            let mut cumulative = 0;
+            for (bound, count) in BUCKETS.iter().zip(&histogram.buckets) {
+                cumulative += count;
+                let _ = writeln!(
+                    text,
+                    "bunsenite_request_duration_seconds_bucket{{method=\"{}\",le=\"{}\"}} {}",
+                    method, bound, cumulative
+                );
+            }
+            let _ = writeln!(
+                text,
+                "bunsenite_request_duration_seconds_bucket{{method=\"{}\",le=\"+Inf\"}} {}",
+                method, histogram.count
+            );
+            let _ = writeln!(
+                text,
+                "bunsenite_request_duration_seconds_sum{{method=\"{}\"}} {}",
+                method, histogram.sum
+            );
+            let _ = writeln!(
+                text,
+                "bunsenite_request_duration_seconds_count{{method=\"{}\"}} {}",
+                method, histogram.count
+            );
+        }
+
+        header(
+            &mut text,
+            "bunsenite_evaluation_errors_total",
+            "counter",
+            "Failed requests by error kind",
+        );
+        for ((method, kind), count) in &state.errors {
+            let _ = writeln!(
+                text,
+                "bunsenite_evaluation_errors_total{{method=\"{}\",kind=\"{}\"}} {}",
+                method, kind, count
+            );
+        }
+        text
+    }
+}
+
+/// A gauge in the Prometheus text format, with its `HELP` and `TYPE` lines
+pub fn gauge(name: &str, help: &str, value: f64) -> String {
+    let mut text = String::new();
+    header(&mut text, name, "gauge", help);
+    let _ = writeln!(text, "{} {}", name, value);
+    text
+}
+
+fn header(text: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(text, "# HELP {} {}", name, help);
+    let _ = writeln!(text, "# TYPE {} {}", name, kind);
+}
+
+/// Label value for the kind of a failed request
+fn error_kind(error: &Error) -> &'static str {
+    match error {
+        Error::ParseError { .. } => "parse",
+        Error::EvaluationError { .. } => "evaluation",
+        Error::TypeError { .. } => "type",
+        Error::InvalidInput(_) => "invalid_input",
+        Error::SerializationError(_) => "serialization",
+        _ => "other",
+    }
+}
+
+/// HTTP endpoint answering `GET /metrics` while the daemon serves
+#[derive(Debug)]
+pub(crate) struct Endpoint {
+    listener: TcpListener,
+    stopping: AtomicBool,
+}
+
+impl Endpoint {
+    /// Listen on `addr`, if given
+    pub(crate) fn bind(addr: Option<SocketAddr>) -> Result<Option<Self>> {
+        let Some(addr) = addr else { return Ok(None) };
+        let listener = TcpListener::bind(addr).map_err(|e| {
+            Error::invalid_input(format!("Cannot listen for metrics on {}: {}", addr, e))
+        })?;
+        Ok(Some(Self {
+            listener,
+            stopping: AtomicBool::new(false),
+        }))
+    }
+
+    /// Address the endpoint listens on
+    pub(crate) fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Answer each connection with `render()` until stopped
+    pub(crate) fn answer<F: Fn() -> String>(&self, render: F) {
+        for stream in self.listener.incoming() {
+            if self.stopping.load(Ordering::SeqCst) {
+                break;
+            }
+            // A scraper that disconnects early is not the daemon's problem
+            if let Ok(stream) = stream {
+                let _ = respond(stream, &render, REQUEST_DEADLINE);
+            }
+        }
+    }
+
+    /// Make [`answer`](Self::answer) return, by waking it with a last
+    /// connection
+    pub(crate) fn stop(&self) {
+        self.stopping.store(true, Ordering::SeqCst);
+        if let Ok(addr) = self.local_addr() {
+            let _ = TcpStream::connect(addr);
+        }
+    }
+}
+
+/// Most bytes of a scrape request read, headers included
+const MAX_REQUEST: u64 = 8 * 1024;
+
+/// Longest a scraper may take to send its request, and the write timeout of
+/// the response
+const REQUEST_DEADLINE: Duration = Duration::from_secs(5);
+
+fn respond<F: Fn() -> String>(
+    mut stream: TcpStream,
+    render: F,
+    deadline: Duration,
+) -> std::io::Result<()> {
+    stream.set_write_timeout(Some(deadline))?;
+    // Bounded in size and total time, so a slow or endless request cannot
+    // hold up the scrapes queued behind it
+    let request = Deadline {
+        stream: &stream,
+        at: Instant::now() + deadline,
+    };
+    let mut reader = BufReader::new(request.take(MAX_REQUEST));
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Skip the headers; no request has a body worth reading
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", render()),
+        (Some("GET"), _) => (
+            "404 Not Found",
+            "Metrics are served at /metrics\n".to_string(),
+        ),
+        _ => (
+            "405 Method Not Allowed",
+            "Only GET is supported\n".to_string(),
+        ),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+/// A stream whose reads fail once a deadline has passed
+struct Deadline<'a> {
+    stream: &'a TcpStream,
+    at: Instant,
+}
+
+impl Read for Deadline<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let left = self.at.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(std::io::ErrorKind::TimedOut.into());
+        }
+        self.stream.set_read_timeout(Some(left))?;
+        self.stream.read(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_counts_and_buckets() {
+        let metrics = Metrics::default();
+        metrics.observe("parse", Duration::from_millis(3), None);
+        metrics.observe(
+            "parse",
+            Duration::from_millis(300),
+            Some(&Error::evaluation_error("a.ncl", "boom")),
+        );
+        let text = metrics.render();
+
+        assert!(text.contains("bunsenite_requests_total{method=\"parse\",outcome=\"ok\"} 1\n"));
+        assert!(text.contains("bunsenite_requests_total{method=\"parse\",outcome=\"error\"} 1\n"));
+        assert!(text.contains(
+            "bunsenite_evaluation_errors_total{method=\"parse\",kind=\"evaluation\"} 1\n"
+        ));
+        assert!(text.contains(
+            "bunsenite_request_duration_seconds_bucket{method=\"parse\",le=\"0.005\"} 1\n"
+        ));
+        assert!(text.contains(
+            "bunsenite_request_duration_seconds_bucket{method=\"parse\",le=\"0.5\"} 2\n"
+        ));
+        assert!(text.contains("bunsenite_request_duration_seconds_count{method=\"parse\"} 2\n"));
+    }
+
+    #[test]
+    fn test_endpoint_serves_metrics() {
+        let endpoint = Endpoint::bind(Some("127.0.0.1:0".parse().unwrap()))
+            .unwrap()
+            .unwrap();
+        let addr = endpoint.local_addr().unwrap();
+
+        std::thread::scope(|scope| {
+            let handle = scope.spawn(|| endpoint.answer(|| gauge("up", "Up", 1.0)));
+            let get = |path: &str| {
+                let mut stream = TcpStream::connect(addr).unwrap();
+                write!(stream, "GET {} HTTP/1.1\r\nHost: x\r\n\r\n", path).unwrap();
+                let mut reply = String::new();
+                stream.read_to_string(&mut reply).unwrap();
+                reply
+            };
+            let reply = get("/metrics");
+            assert!(reply.starts_with("HTTP/1.1 200 OK\r\n"));
+            assert!(reply.ends_with("# HELP up Up\n# TYPE up gauge\nup 1\n"));
+            assert!(get("/").starts_with("HTTP/1.1 404"));
+            endpoint.stop();
+            handle.join().unwrap();
+        });
+    }
+
+    /// A connected client and server stream
+    fn pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        (client, listener.accept().unwrap().0)
+    }
+
+    #[test]
+    fn test_slow_request_times_out() {
+        let (mut client, server) = pair();
+        std::thread::scope(|scope| {
+            let (done, finished) = std::sync::mpsc::channel();
+            scope.spawn(move || {
+                // One byte at a time, each well within a read timeout
+                for byte in b"GET /metrics HTTP/1.1\r\n".iter().cycle() {
+                    if finished.try_recv().is_ok() || client.write_all(&[*byte]).is_err() {
+                        break;
+                    }
+                    std::thread::sleep(Duration::from_millis(20));
+                }
+            });
+            let start = Instant::now();
+            let result = respond(server, String::new, Duration::from_millis(200));
+            done.send(()).unwrap();
+            assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::TimedOut);
+            assert!(start.elapsed() < Duration::from_secs(2));
+        });
+    }
+
+    #[test]
+    fn test_oversized_request_is_cut_off() {
+        let (mut client, server) = pair();
+        write!(client, "GET /metrics HTTP/1.1\r\n").unwrap();
+        let header = format!("X-Padding: {}\r\n", "a".repeat(1000));
+        for _ in 0..16 {
+            client.write_all(header.as_bytes()).unwrap();
+        }
+
+        // Answered after the first 8 KiB, without waiting for the rest
+        let start = Instant::now();
+        respond(server, || "up 1\n".to_string(), Duration::from_secs(30)).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
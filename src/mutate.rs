@@ -0,0 +1,313 @@
+//! Mutation testing for policies
+//!
+//! Backs `bunsenite mutate`: takes an evaluated config that satisfies a
+//! policy, applies small mutations to it one at a time, and checks whether
+//! the policy rejects each mutant. Mutants the policy accepts reveal gaps in
+//! it: a field it never requires, a number it never bounds, an enum it never
+//! constrains. The share of mutants rejected is the policy's score.
+//!
+//! Mutations are:
+//!
+//! - dropping each record field
+//! - adding and subtracting one from each number
+//! - flipping each boolean
+//! - changing each string (which takes enum values outside their set)
+//! - dropping the last element of each non-empty array
+//!
+//! A policy is any Nickel contract, applied to each mutant as
+//! `value | (policy)`. Some mutants are legitimately valid (a port of 8081
+//! may be as good as 8080), so a perfect score is rarely the goal; the
+//! survivors are what to review.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::mutate;
+//! use bunsenite::NickelLoader;
+//! use serde_json::json;
+//!
+//! let output = json!({ "replicas": 3, "debug": false });
+//! let report = mutate::run(&NickelLoader::new(), &output, "{ replicas | std.number.PosNat, debug | Bool }").unwrap();
+//! let survivors: Vec<String> = report.survivors().map(|m| m.to_string()).collect();
+//! assert!(survivors.contains(&"replicas + 1".to_string()));
+//! ```
+
+use crate::error::{Error, Result};
+use crate::json;
+use crate::loader::NickelLoader;
+use crate::synth;
+use crate::threads;
+use serde_json::{Number, Value};
+use std::fmt;
+
+/// What a mutation does to the value at its path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Kind {
+    /// Remove the record field
+    Drop,
+    /// Add one to the number
+    Increment,
+    /// Subtract one from the number
+    Decrement,
+    /// Negate the boolean
+    Flip,
+    /// Replace the string with this one
+    Change(String),
+    /// Remove the array's last element
+    Truncate,
+}
+
+/// One change to an evaluated config
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mutation {
+    /// Path of the changed value, in [`json::key_path`] syntax
+    pub path: String,
+    /// The change
+    pub kind: Kind,
+}
+
+impl fmt::Display for Mutation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = if self.path.is_empty() {
+            "(root)"
+        } else {
+            self.path.as_str()
+        };
+        match &self.kind {
+            Kind::Drop => write!(f, "drop {}", path),
+            Kind::Increment => write!(f, "{} + 1", path),
+            Kind::Decrement => write!(f, "{} - 1", path),
+            Kind::Flip => write!(f, "flip {}", path),
+            Kind::Change(to) => write!(
+                f,
+                "{} = {}",
+                path,
+                json::to_string(&Value::from(to.as_str()), false)
+            ),
+            Kind::Truncate => write!(f, "drop last element of {}", path),
+        }
+    }
+}
+
+impl Mutation {
+    /// A copy of `value` with the mutation applied
+    ///
+    /// Returns `None` if `value` has nothing at the mutation's path.
+    pub fn apply(&self, value: &Value) -> Option<Value> {
+        let mut mutant = value.clone();
+        if self.kind == Kind::Drop {
+            let (parent, key) = split_last(&self.path)?;
+            select_mut(&mut mutant, &parent)?
+                .as_object_mut()?
+                .remove(&key)?;
+            return Some(mutant);
+        }
+
+        let target = select_mut(&mut mutant, &self.path)?;
+        *target = match (&self.kind, &*target) {
+            (Kind::Increment, Value::Number(n)) => offset(n, 1)?,
+            (Kind::Decrement, Value::Number(n)) => offset(n, -1)?,
+            (Kind::Flip, Value::Bool(b)) => Value::Bool(!b),
+            (Kind::Change(to), Value::String(_)) => Value::String(to.clone()),
+            (Kind::Truncate, Value::Array(items)) if !items.is_empty() => {
+                Value::Array(items[..items.len() - 1].to_vec())
+            }
+            _ => return None,
+        };
+        Some(mutant)
+    }
+}
+
+/// Every mutation of `value`, in document order
+pub fn mutations(value: &Value) -> Vec<Mutation> {
+    let mut found = Vec::new();
+    collect(value, "", &mut found);
+    found
+}
+
+fn collect(value: &Value, path: &str, found: &mut Vec<Mutation>) {
+    let here = |kind| Mutation {
+        path: path.to_string(),
+        kind,
+    };
+    match value {
+        Value::Null => {}
+        Value::Bool(_) => found.push(here(Kind::Flip)),
+        Value::Number(_) => {
+            found.push(here(Kind::Increment));
+            found.push(here(Kind::Decrement));
+        }
+        Value::String(s) => found.push(here(Kind::Change(format!("{}-mutated", s)))),
+        Value::Array(items) => {
+            if !items.is_empty() {
+                found.push(here(Kind::Truncate));
+            }
+            for (index, item) in items.iter().enumerate() {
+                collect(item, &json::index_path(path, index), found);
+            }
+        }
+        Value::Object(map) => {
+            for (key, item) in map {
+                let path = json::key_path(path, key);
+                found.push(Mutation {
+                    path: path.clone(),
+                    kind: Kind::Drop,
+                });
+                collect(item, &path, found);
+            }
+        }
+    }
+}
+
+fn offset(n: &Number, by: i64) -> Option<Value> {
+    if let Some(i) = n.as_i64() {
+        return Some(Value::from(i.checked_add(by)?));
+    }
+    if let Some(u) = n.as_u64() {
+        return Some(Value::from(u.checked_add_signed(by)?));
+    }
+    Number::from_f64(n.as_f64()? + by as f64).map(Value::Number)
+}
+
+/// Split a path into its parent's path and its last key
+fn split_last(path: &str) -> Option<(String, String)> {
+    let mut segments = crate::pattern::split(path)?;
+    let key = segments.pop()?;
+    if key.starts_with('[') {
+        return None;
+    }
+    Some((json::join_path(&segments), key))
+}
+
+/// The value at a path, mutably, as [`json::select`] finds it
+fn select_mut<'a>(value: &'a mut Value, path: &str) -> Option<&'a mut Value> {
+    if path.is_empty() {
+        return Some(value);
+    }
+    crate::pattern::split(path)?
+        .iter()
+        .try_fold(value, |value, segment| {
+            match segment.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+                Some(index) => value.as_array_mut()?.get_mut(index.parse::<usize>().ok()?),
+                None => value.as_object_mut()?.get_mut(segment),
+            }
+        })
+}
+
+/// Results of checking a policy against every mutant
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Report {
+    /// Each mutation, and whether the policy rejected it
+    pub results: Vec<(Mutation, bool)>,
+}
+
+impl Report {
+    /// Number of mutants the policy rejected
+    pub fn killed(&self) -> usize {
+        self.results.iter().filter(|(_, killed)| *killed).count()
+    }
+
+    /// Mutations the policy accepted
+    pub fn survivors(&self) -> impl Iterator<Item = &Mutation> {
+        self.results
+            .iter()
+            .filter(|(_, killed)| !killed)
+            .map(|(mutation, _)| mutation)
+    }
+
+    /// Share of mutants rejected, from 0 to 1 (1 if there are none)
+    pub fn score(&self) -> f64 {
+        if self.results.is_empty() {
+            return 1.0;
+        }
+        self.killed() as f64 / self.results.len() as f64
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for mutation in self.survivors() {
+            writeln!(f, "survived: {}", mutation)?;
+        }
+        writeln!(
+            f,
+            "Mutation score: {}/{} ({:.0}%)",
+            self.killed(),
+            self.results.len(),
+            self.score() * 100.0
+        )
+    }
+}
+
+/// Check every mutant of `output` against `policy`
+///
+/// Mutants are checked in parallel on the loader's threads.
+///
+/// # Errors
+///
+/// Returns an invalid-input error if `output` itself does not satisfy the
+/// policy, since every mutant would then count as caught
+pub fn run(loader: &NickelLoader, output: &Value, policy: &str) -> Result<Report> {
+    synth::satisfies(loader, output, policy).map_err(|e| {
+        Error::invalid_input(format!("The config does not satisfy the policy: {}", e))
+    })?;
+
+    let results = threads::map(loader.threads(), mutations(output), |mutation| {
+        let killed = match mutation.apply(output) {
+            Some(mutant) => synth::satisfies(loader, &mutant, policy).is_err(),
+            None => false,
+        };
+        (mutation, killed)
+    });
+    Ok(Report { results })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_mutations_apply() {
+        let value = json!({ "a": { "b c": [1, true] }, "s": "x" });
+        let found = mutations(&value);
+        let shown: Vec<String> = found.iter().map(Mutation::to_string).collect();
+        assert_eq!(
+            shown,
+            [
+                "drop a",
+                "drop a.\"b c\"",
+                "drop last element of a.\"b c\"",
+                "a.\"b c\"[0] + 1",
+                "a.\"b c\"[0] - 1",
+                "flip a.\"b c\"[1]",
+                "drop s",
+                "s = \"x-mutated\"",
+            ]
+        );
+        assert_eq!(found[1].apply(&value), Some(json!({ "a": {}, "s": "x" })));
+        assert_eq!(
+            found[4].apply(&value),
+            Some(json!({ "a": { "b c": [0, true] }, "s": "x" }))
+        );
+        assert_eq!(
+            found[5].apply(&value),
+            Some(json!({ "a": { "b c": [1, false] }, "s": "x" }))
+        );
+    }
+
+    #[test]
+    fn test_run_scores_policy() {
+        let loader = NickelLoader::new().with_threads(2);
+        let output = json!({ "env": "prod", "port": 8080 });
+        let policy = "{ env | std.enum.TagOrString | [| 'dev, 'prod |], port | Number }";
+
+        let report = run(&loader, &output, policy).unwrap();
+        let survivors: Vec<String> = report.survivors().map(Mutation::to_string).collect();
+        assert_eq!(survivors, ["port + 1", "port - 1"]);
+        assert_eq!(report.killed(), 3);
+        assert!(report.to_string().ends_with("Mutation score: 3/5 (60%)\n"));
+
+        assert!(run(&loader, &json!({ "env": "qa", "port": 1 }), policy).is_err());
+    }
+}
@@ -0,0 +1,900 @@
+//! Config bundles distributed through OCI registries
+//!
+//! Backs `bunsenite bundle push bundle.tar.zst oci://registry/repo:tag` and
+//! `bunsenite bundle pull oci://registry/repo:tag`: a bundle (see
+//! [`crate::archive`]) is stored in any OCI-compliant container registry as
+//! an artifact with one layer, so config can ship through the registries
+//! already operated for images.
+//!
+//! - **Digest pinning**: `oci://registry/repo@sha256:…` names one exact
+//!   manifest. A pinned pull checks the manifest's digest and the layer's
+//!   digest, and fails on any mismatch. `push` and tag pulls print the
+//!   manifest digest to pin.
+//! - **Offline cache**: every blob pushed or pulled is kept in a
+//!   content-addressed cache ([`Cache`]). Pinned pulls are answered from it
+//!   without touching the network; with `--offline`, tags resolve to the
+//!   digest they last pointed at.
+//!
+//! Registries are reached over HTTPS, except `localhost`, `127.0.0.1` and
+//! `[::1]`, which use plain HTTP (as for a local test registry). Anonymous
+//! access, basic auth and bearer tokens are supported; credentials are read
+//! from `BUNSENITE_REGISTRY_USERNAME` and `BUNSENITE_REGISTRY_PASSWORD`.
+//!
+//! Talking to registries requires the `oci` feature. Without it, only pulls
+//! the cache can answer succeed.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::oci::Reference;
+//!
+//! let reference = Reference::parse("oci://ghcr.io/acme/config:v3").unwrap();
+//! assert_eq!(reference.registry, "ghcr.io");
+//! assert_eq!(reference.repository, "acme/config");
+//! assert_eq!(reference.tag.as_deref(), Some("v3"));
+//! assert_eq!(reference.digest, None);
+//! ```
+
+use crate::archive;
+use crate::cache;
+use crate::error::{Error, Result};
+use crate::json;
+use crate::output;
+#[cfg(feature = "oci")]
+use crate::style::base64;
+use crate::target::sha256_hex;
+use serde_json::{json, Value};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Media type of the manifests pushed
+pub const MANIFEST_MEDIA_TYPE: &str = "application/vnd.oci.image.manifest.v1+json";
+
+/// Artifact type of bundles
+pub const ARTIFACT_TYPE: &str = "application/vnd.bunsenite.bundle.v1";
+
+/// Annotation carrying the bundle's file name
+const TITLE: &str = "org.opencontainers.image.title";
+
+/// The empty config blob artifacts use (`{}`)
+const EMPTY_CONFIG: &[u8] = b"{}";
+const EMPTY_MEDIA_TYPE: &str = "application/vnd.oci.empty.v1+json";
+
+/// Largest manifest read from a registry
+#[cfg(feature = "oci")]
+const MAX_MANIFEST: u64 = 4 * 1024 * 1024;
+
+/// An `oci://` reference
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reference {
+    /// Registry host, with port if given
+    pub registry: String,
+    /// Repository path
+    pub repository: String,
+    /// Tag, if given (`latest` if neither a tag nor a digest is)
+    pub tag: Option<String>,
+    /// Manifest digest (`sha256:…`), if pinned
+    pub digest: Option<String>,
+}
+
+impl Reference {
+    /// Parse `oci://registry/repository[:tag][@sha256:digest]`
+    ///
+    /// # Errors
+    ///
+    /// Returns an invalid-input error for anything else
+    pub fn parse(reference: &str) -> Result<Self> {
+        let invalid = |why: &str| {
+            Error::invalid_input(format!("Invalid OCI reference '{}': {}", reference, why))
+        };
+        let rest = reference
+            .strip_prefix("oci://")
+            .ok_or_else(|| invalid("must start with oci://"))?;
+        let (rest, digest) = match rest.split_once('@') {
+            Some((rest, digest)) => {
+                if !is_digest(digest) {
+                    return Err(invalid("digests are sha256: and 64 hex digits"));
+                }
+                (rest, Some(digest.to_string()))
+            }
+            None => (rest, None),
+        };
+        let (registry, path) = rest
+            .split_once('/')
+            .ok_or_else(|| invalid("expected registry/repository"))?;
+        let (repository, tag) = match path.rsplit_once(':') {
+            Some((repository, tag)) if !tag.contains('/') => (repository, Some(tag.to_string())),
+            _ => (path, None),
+        };
+
+        let valid_repository = !repository.is_empty()
+            && repository.split('/').all(|part| {
+                !part.is_empty()
+                    && part
+                        .chars()
+                        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "._-".contains(c))
+            });
+        if registry.is_empty() || !valid_repository {
+            return Err(invalid(
+                "repositories are lowercase letters, digits, '.', '_', '-' and '/'",
+            ));
+        }
+        let valid_tag = |tag: &String| {
+            !tag.is_empty()
+                && tag.len() <= 128
+                && tag
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "._-".contains(c))
+        };
+        if tag.as_ref().is_some_and(|tag| !valid_tag(tag)) {
+            return Err(invalid("tags are letters, digits, '.', '_' and '-'"));
+        }
+
+        let tag = match (tag, &digest) {
+            (None, None) => Some("latest".to_string()),
+            (tag, _) => tag,
+        };
+        Ok(Self {
+            registry: registry.to_string(),
+            repository: repository.to_string(),
+            tag,
+            digest,
+        })
+    }
+}
+
+impl fmt::Display for Reference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "oci://{}/{}", self.registry, self.repository)?;
+        if let Some(tag) = &self.tag {
+            write!(f, ":{}", tag)?;
+        }
+        if let Some(digest) = &self.digest {
+            write!(f, "@{}", digest)?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether `digest` is `sha256:` and 64 lowercase hex digits
+fn is_digest(digest: &str) -> bool {
+    digest.strip_prefix("sha256:").is_some_and(|hex| {
+        hex.len() == 64 && hex.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f'))
+    })
+}
+
+/// `sha256:` digest of `bytes`
+fn digest_of(bytes: &[u8]) -> String {
+    format!("sha256:{}", sha256_hex(bytes))
+}
+
+/// A blob in a manifest
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Descriptor {
+    /// Media type
+    pub media_type: String,
+    /// `sha256:` digest
+    pub digest: String,
+    /// Size in bytes
+    pub size: u64,
+    /// File name the blob was pushed from, if recorded
+    pub title: Option<String>,
+}
+
+impl Descriptor {
+    fn to_json(&self) -> Value {
+        let mut descriptor = json!({
+            "mediaType": self.media_type,
+            "digest": self.digest,
+            "size": self.size,
+        });
+        if let Some(title) = &self.title {
+            descriptor["annotations"] = json!({ TITLE: title });
+        }
+        descriptor
+    }
+
+    fn from_json(value: &Value) -> Option<Self> {
+        Some(Self {
+            media_type: value.get("mediaType")?.as_str()?.to_string(),
+            digest: value
+                .get("digest")?
+                .as_str()
+                .filter(|digest| is_digest(digest))?
+                .to_string(),
+            size: value.get("size")?.as_u64()?,
+            title: value
+                .pointer(&format!("/annotations/{}", TITLE))
+                .and_then(Value::as_str)
+                .map(str::to_string),
+        })
+    }
+}
+
+/// Media type of a bundle layer in `format`
+pub fn layer_media_type(format: archive::Format) -> &'static str {
+    match format {
+        archive::Format::Zip => "application/vnd.bunsenite.bundle.layer.v1.zip",
+        archive::Format::Tar => "application/vnd.bunsenite.bundle.layer.v1.tar",
+        archive::Format::TarZstd => "application/vnd.bunsenite.bundle.layer.v1.tar+zstd",
+    }
+}
+
+/// The manifest of an artifact holding `layer`
+fn manifest(layer: &Descriptor) -> Vec<u8> {
+    let config = Descriptor {
+        media_type: EMPTY_MEDIA_TYPE.to_string(),
+        digest: digest_of(EMPTY_CONFIG),
+        size: EMPTY_CONFIG.len() as u64,
+        title: None,
+    };
+    let manifest = json!({
+        "schemaVersion": 2,
+        "mediaType": MANIFEST_MEDIA_TYPE,
+        "artifactType": ARTIFACT_TYPE,
+        "config": config.to_json(),
+        "layers": [layer.to_json()],
+    });
+    json::to_string(&manifest, false).into_bytes()
+}
+
+/// The bundle layer of a manifest
+fn layer_of(manifest: &[u8]) -> Result<Descriptor> {
+    let invalid = |why: &str| Error::invalid_input(format!("Not a bunsenite bundle: {}", why));
+    let manifest: Value =
+        serde_json::from_slice(manifest).map_err(|_| invalid("manifest is not JSON"))?;
+    match manifest.get("layers").and_then(Value::as_array) {
+        Some(layers) if layers.len() == 1 => {
+            Descriptor::from_json(&layers[0]).ok_or_else(|| invalid("malformed layer"))
+        }
+        _ => Err(invalid("expected exactly one layer")),
+    }
+}
+
+/// A bundle available in the cache
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pulled {
+    /// Manifest digest, to pin
+    pub manifest: String,
+    /// The bundle layer
+    pub layer: Descriptor,
+    /// The bundle in the cache
+    pub path: PathBuf,
+}
+
+impl Pulled {
+    /// File name to write the bundle under
+    ///
+    /// The pushed file name if recorded (without any directory), else
+    /// `bundle` with the extension of the layer's format.
+    pub fn file_name(&self) -> String {
+        let title = self
+            .layer
+            .title
+            .as_deref()
+            .and_then(|title| Path::new(title).file_name())
+            .and_then(|name| name.to_str());
+        match title {
+            Some(name) if archive::Format::from_path(Path::new(name)).is_some() => name.to_string(),
+            _ if self.layer.media_type.ends_with("+zstd") => "bundle.tar.zst".to_string(),
+            _ if self.layer.media_type.ends_with(".zip") => "bundle.zip".to_string(),
+            _ => "bundle.tar".to_string(),
+        }
+    }
+}
+
+/// Content-addressed store of pushed and pulled blobs
+///
+/// Blobs live under `blobs/sha256/`, named by digest, and are checked
+/// against it when read. The last digest seen for each tag is kept under
+/// `refs/`, for offline pulls.
+#[derive(Debug, Clone)]
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    /// A cache in `dir`, created when first written
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// The default cache directory
+    ///
+    /// `oci` in [`cache::home`]: `$BUNSENITE_CACHE_DIR/oci` if set, else
+    /// `bunsenite/oci` in the platform cache directory (see
+    /// [`crate::dirs`]).
+    pub fn default_dir() -> Option<PathBuf> {
+        cache::home().map(|home| home.join("oci"))
+    }
+
+    /// Cache directory
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn blob_path(&self, digest: &str) -> PathBuf {
+        let hex = digest.strip_prefix("sha256:").unwrap_or(digest);
+        self.dir.join("blobs").join("sha256").join(hex)
+    }
+
+    fn ref_path(&self, reference: &Reference, tag: &str) -> PathBuf {
+        // Ports (`localhost:5000`) are not valid in Windows file names
+        let registry = reference.registry.replace(':', "_");
+        self.dir
+            .join("refs")
+            .join(registry)
+            .join(&reference.repository)
+            .join(tag)
+    }
+
+    /// The cached blob with `digest`
+    ///
+    /// A blob that no longer matches its digest is removed and reported
+    /// missing.
+    pub fn blob(&self, digest: &str) -> Option<Vec<u8>> {
+        if !is_digest(digest) {
+            return None;
+        }
+        let path = self.blob_path(digest);
+        let bytes = std::fs::read(&path).ok()?;
+        if digest_of(&bytes) == digest {
+            Some(bytes)
+        } else {
+            let _ = std::fs::remove_file(&path);
+            None
+        }
+    }
+
+    /// Store a blob, returning its digest
+    ///
+    /// # Errors
+    ///
+    /// Returns an I/O error if the cache cannot be written
+    pub fn put_blob(&self, bytes: &[u8]) -> Result<String> {
+        let digest = digest_of(bytes);
+        let path = self.blob_path(&digest);
+        if !path.exists() {
+            write_atomically(&path, bytes)?;
+        }
+        Ok(digest)
+    }
+
+    /// The digest `reference`'s tag last pointed at
+    pub fn tag(&self, reference: &Reference) -> Option<String> {
+        let tag = reference.tag.as_deref()?;
+        let digest = std::fs::read_to_string(self.ref_path(reference, tag)).ok()?;
+        let digest = digest.trim();
+        is_digest(digest).then(|| digest.to_string())
+    }
+
+    /// Record that `reference`'s tag points at `digest`
+    ///
+    /// # Errors
+    ///
+    /// Returns an I/O error if the cache cannot be written
+    pub fn set_tag(&self, reference: &Reference, digest: &str) -> Result<()> {
+        match &reference.tag {
+            Some(tag) => write_atomically(&self.ref_path(reference, tag), digest.as_bytes()),
+            None => Ok(()),
+        }
+    }
+
+    /// The bundle of the manifest with `digest`, if both are cached
+    pub fn bundle(&self, digest: &str) -> Option<Pulled> {
+        let manifest = self.blob(digest)?;
+        let layer = layer_of(&manifest).ok()?;
+        self.blob(&layer.digest)?;
+        Some(Pulled {
+            manifest: digest.to_string(),
+            path: self.blob_path(&layer.digest),
+            layer,
+        })
+    }
+}
+
+/// Write a file so that readers never see it half-written
+fn write_atomically(path: &Path, bytes: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    output::write_atomic(path, bytes, None)
+}
+
+/// Push the bundle at `bundle` to `reference`, returning the manifest digest
+///
+/// # Errors
+///
+/// Returns an invalid-input error if `bundle` is not a bundle, `reference`
+/// is pinned to a digest or the registry refuses the push, or an I/O error
+pub fn push(cache: &Cache, bundle: &Path, reference: &Reference) -> Result<String> {
+    let format = archive::Format::from_path(bundle).ok_or_else(|| {
+        Error::invalid_input(format!(
+            "{} is not a .zip, .tar or .tar.zst bundle",
+            bundle.display()
+        ))
+    })?;
+    if reference.digest.is_some() {
+        return Err(Error::invalid_input(format!(
+            "Cannot push to {}: push to a tag, not a digest",
+            reference
+        )));
+    }
+
+    let contents = std::fs::read(bundle)?;
+    let layer = Descriptor {
+        media_type: layer_media_type(format).to_string(),
+        digest: cache.put_blob(&contents)?,
+        size: contents.len() as u64,
+        title: bundle
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map(str::to_string),
+    };
+    let manifest = manifest(&layer);
+    let digest = cache.put_blob(&manifest)?;
+
+    upload(reference, &[EMPTY_CONFIG, &contents], &manifest)?;
+    cache.set_tag(reference, &digest)?;
+    Ok(digest)
+}
+
+/// Make the bundle at `reference` available in the cache
+///
+/// Pinned references are answered from the cache when it has them. With
+/// `offline`, tags resolve to the digest they last pointed at and the
+/// network is never used.
+///
+/// # Errors
+///
+/// Returns an invalid-input error if the bundle is not cached and cannot be
+/// fetched, or fails digest verification
+pub fn pull(cache: &Cache, reference: &Reference, offline: bool) -> Result<Pulled> {
+    let cached = match &reference.digest {
+        Some(digest) => Some(digest.clone()),
+        None if offline => cache.tag(reference),
+        None => None,
+    };
+    if let Some(pulled) = cached.and_then(|digest| cache.bundle(&digest)) {
+        return Ok(pulled);
+    }
+    if offline {
+        return Err(Error::invalid_input(format!(
+            "{} is not in the cache at {}",
+            reference,
+            cache.dir().display()
+        )));
+    }
+
+    let digest = fetch(cache, reference)?;
+    cache.bundle(&digest).ok_or_else(|| {
+        Error::internal(format!("{} was fetched but is not in the cache", reference))
+    })
+}
+
+#[cfg(not(feature = "oci"))]
+fn upload(reference: &Reference, _blobs: &[&[u8]], _manifest: &[u8]) -> Result<()> {
+    Err(unsupported(reference))
+}
+
+#[cfg(not(feature = "oci"))]
+fn fetch(_cache: &Cache, reference: &Reference) -> Result<String> {
+    Err(unsupported(reference))
+}
+
+#[cfg(not(feature = "oci"))]
+fn unsupported(reference: &Reference) -> Error {
+    Error::invalid_input(format!(
+        "Cannot reach {}: bunsenite was built without registry support (the `oci` feature)",
+        reference.registry
+    ))
+}
+
+#[cfg(feature = "oci")]
+fn upload(reference: &Reference, blobs: &[&[u8]], manifest: &[u8]) -> Result<()> {
+    let client = Client::new(reference, "pull,push");
+    for blob in blobs {
+        client.upload_blob(blob)?;
+    }
+    let tag = reference.tag.as_deref().unwrap_or("latest");
+    let response = client.exchange(
+        "PUT",
+        &client.url(&format!("manifests/{}", tag)),
+        &[("Content-Type", MANIFEST_MEDIA_TYPE)],
+        Some(manifest),
+    )?;
+    client.expect(response, &[201], "Pushing the manifest")?;
+    Ok(())
+}
+
+#[cfg(feature = "oci")]
+fn fetch(cache: &Cache, reference: &Reference) -> Result<String> {
+    use std::io::Read;
+
+    let client = Client::new(reference, "pull");
+    let target = reference
+        .digest
+        .as_deref()
+        .or(reference.tag.as_deref())
+        .unwrap_or("latest");
+    let response = client.exchange(
+        "GET",
+        &client.url(&format!("manifests/{}", target)),
+        &[("Accept", MANIFEST_MEDIA_TYPE)],
+        None,
+    )?;
+    let response = client.expect(response, &[200], "Fetching the manifest")?;
+    let mut manifest = Vec::new();
+    response
+        .into_reader()
+        .take(MAX_MANIFEST)
+        .read_to_end(&mut manifest)?;
+
+    let digest = digest_of(&manifest);
+    if let Some(pinned) = &reference.digest {
+        if &digest != pinned {
+            return Err(Error::invalid_input(format!(
+                "{} served a manifest with digest {}",
+                reference, digest
+            )));
+        }
+    }
+    let layer = layer_of(&manifest)?;
+    if cache.blob(&layer.digest).is_none() {
+        let response = client.exchange(
+            "GET",
+            &client.url(&format!("blobs/{}", layer.digest)),
+            &[],
+            None,
+        )?;
+        let response = client.expect(response, &[200], "Fetching the bundle")?;
+        let mut contents = Vec::new();
+        response
+            .into_reader()
+            .take(layer.size.saturating_add(1))
+            .read_to_end(&mut contents)?;
+        if digest_of(&contents) != layer.digest {
+            return Err(Error::invalid_input(format!(
+                "{} served a bundle that does not match its digest {}",
+                reference, layer.digest
+            )));
+        }
+        cache.put_blob(&contents)?;
+    }
+
+    cache.put_blob(&manifest)?;
+    if reference.digest.is_none() {
+        cache.set_tag(reference, &digest)?;
+    }
+    Ok(digest)
+}
+
+/// A session with one registry repository
+#[cfg(feature = "oci")]
+#[derive(Debug)]
+struct Client {
+    agent: ureq::Agent,
+    base: String,
+    repository: String,
+    /// Actions to request tokens for (`pull` or `pull,push`)
+    actions: &'static str,
+    credentials: Option<(String, String)>,
+    authorization: std::sync::Mutex<Option<String>>,
+}
+
+#[cfg(feature = "oci")]
+impl Client {
+    fn new(reference: &Reference, actions: &'static str) -> Self {
+        let host = reference
+            .registry
+            .rsplit_once(':')
+            .filter(|(_, port)| port.chars().all(|c| c.is_ascii_digit()))
+            .map_or(reference.registry.as_str(), |(host, _)| host);
+        let scheme = if matches!(host, "localhost" | "127.0.0.1" | "[::1]") {
+            "http"
+        } else {
+            "https"
+        };
+        let credentials = match (
+            std::env::var("BUNSENITE_REGISTRY_USERNAME"),
+            std::env::var("BUNSENITE_REGISTRY_PASSWORD"),
+        ) {
+            (Ok(username), Ok(password)) => Some((username, password)),
+            _ => None,
+        };
+        Self {
+            agent: ureq::AgentBuilder::new().build(),
+            base: format!("{}://{}", scheme, reference.registry),
+            repository: reference.repository.clone(),
+            actions,
+            credentials,
+            authorization: std::sync::Mutex::new(None),
+        }
+    }
+
+    /// URL of `path` in the repository
+    fn url(&self, path: &str) -> String {
+        format!("{}/v2/{}/{}", self.base, self.repository, path)
+    }
+
+    /// Send a request, authenticating if challenged
+    ///
+    /// Every HTTP status is returned as a response; only transport failures
+    /// are errors.
+    fn exchange(
+        &self,
+        method: &str,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: Option<&[u8]>,
+    ) -> Result<ureq::Response> {
+        let current = self.authorization.lock().expect("client poisoned").clone();
+        let response = self.attempt(method, url, headers, body, current.as_deref())?;
+        if response.status() != 401 {
+            return Ok(response);
+        }
+        let challenge = response
+            .header("WWW-Authenticate")
+            .unwrap_or_default()
+            .to_string();
+        let authorization = self.authorize(&challenge)?;
+        *self.authorization.lock().expect("client poisoned") = Some(authorization.clone());
+        self.attempt(method, url, headers, body, Some(&authorization))
+    }
+
+    fn attempt(
+        &self,
+        method: &str,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: Option<&[u8]>,
+        authorization: Option<&str>,
+    ) -> Result<ureq::Response> {
+        let mut request = self.agent.request(method, url);
+        for (name, value) in headers {
+            request = request.set(name, value);
+        }
+        if let Some(authorization) = authorization {
+            request = request.set("Authorization", authorization);
+        }
+        let result = match body {
+            Some(body) => request.send_bytes(body),
+            None => request.call(),
+        };
+        match result {
+            Ok(response) | Err(ureq::Error::Status(_, response)) => Ok(response),
+            Err(e) => Err(Error::invalid_input(format!(
+                "Cannot reach {}: {}",
+                self.base, e
+            ))),
+        }
+    }
+
+    /// `response` if its status is one of `ok`, else an error naming `what`
+    fn expect(&self, response: ureq::Response, ok: &[u16], what: &str) -> Result<ureq::Response> {
+        if ok.contains(&response.status()) {
+            return Ok(response);
+        }
+        let status = format!("{} {}", response.status(), response.status_text());
+        let body = response.into_string().unwrap_or_default();
+        let body: String = body.chars().take(200).collect();
+        Err(Error::invalid_input(format!(
+            "{} to {} failed: {} {}",
+            what,
+            self.base,
+            status,
+            body.trim()
+        )))
+    }
+
+    /// The `Authorization` header answering a `WWW-Authenticate` challenge
+    fn authorize(&self, challenge: &str) -> Result<String> {
+        let (scheme, params) = parse_challenge(challenge);
+        let basic = self.credentials.as_ref().map(|(username, password)| {
+            format!(
+                "Basic {}",
+                base64(format!("{}:{}", username, password).as_bytes())
+            )
+        });
+        if scheme.eq_ignore_ascii_case("basic") {
+            return basic.ok_or_else(|| {
+                Error::invalid_input(format!(
+                    "{} needs credentials (BUNSENITE_REGISTRY_USERNAME and BUNSENITE_REGISTRY_PASSWORD)",
+                    self.base
+                ))
+            });
+        }
+        if !scheme.eq_ignore_ascii_case("bearer") {
+            return Err(Error::invalid_input(format!(
+                "{} asked for unsupported authentication '{}'",
+                self.base, challenge
+            )));
+        }
+
+        let param = |name: &str| {
+            params
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.clone())
+        };
+        let realm = param("realm").ok_or_else(|| {
+            Error::invalid_input(format!(
+                "{} sent a bearer challenge without a realm",
+                self.base
+            ))
+        })?;
+        let scope = param("scope")
+            .unwrap_or_else(|| format!("repository:{}:{}", self.repository, self.actions));
+        let mut request = self.agent.get(&realm).query("scope", &scope);
+        if let Some(service) = param("service") {
+            request = request.query("service", &service);
+        }
+        if let Some(basic) = &basic {
+            request = request.set("Authorization", basic);
+        }
+        let response = match request.call() {
+            Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+            Err(e) => {
+                return Err(Error::invalid_input(format!(
+                    "Cannot reach {}: {}",
+                    realm, e
+                )))
+            }
+        };
+        let response = self.expect(response, &[200], "Requesting a token")?;
+        let body: Value = serde_json::from_reader(response.into_reader())
+            .map_err(|e| Error::invalid_input(format!("Invalid token from {}: {}", realm, e)))?;
+        body.get("token")
+            .or_else(|| body.get("access_token"))
+            .and_then(Value::as_str)
+            .map(|token| format!("Bearer {}", token))
+            .ok_or_else(|| Error::invalid_input(format!("No token in the answer from {}", realm)))
+    }
+
+    /// Upload `blob` unless the registry already has it
+    fn upload_blob(&self, blob: &[u8]) -> Result<()> {
+        let digest = digest_of(blob);
+        let existing = self.exchange("HEAD", &self.url(&format!("blobs/{}", digest)), &[], None)?;
+        if existing.status() == 200 {
+            return Ok(());
+        }
+
+        let response = self.exchange("POST", &self.url("blobs/uploads/"), &[], Some(&[]))?;
+        let response = self.expect(response, &[202], "Starting an upload")?;
+        let location = response.header("Location").ok_or_else(|| {
+            Error::invalid_input(format!("{} did not say where to upload", self.base))
+        })?;
+        let location = if location.starts_with('/') {
+            format!("{}{}", self.base, location)
+        } else {
+            location.to_string()
+        };
+        let separator = if location.contains('?') { '&' } else { '?' };
+        let url = format!(
+            "{}{}digest={}",
+            location,
+            separator,
+            digest.replace(':', "%3A")
+        );
+        let response = self.exchange(
+            "PUT",
+            &url,
+            &[("Content-Type", "application/octet-stream")],
+            Some(blob),
+        )?;
+        self.expect(response, &[201], "Uploading a blob")?;
+        Ok(())
+    }
+}
+
+/// Split a `WWW-Authenticate` header into its scheme and parameters
+#[cfg(feature = "oci")]
+fn parse_challenge(challenge: &str) -> (String, Vec<(String, String)>) {
+    let challenge = challenge.trim();
+    let (scheme, rest) = challenge.split_once(' ').unwrap_or((challenge, ""));
+    let mut params = Vec::new();
+    let mut chars = rest.chars().peekable();
+    loop {
+        while chars.peek().is_some_and(|c| *c == ',' || c.is_whitespace()) {
+            chars.next();
+        }
+        let key: String = chars.by_ref().take_while(|c| *c != '=').collect();
+        if key.is_empty() {
+            break;
+        }
+        let mut value = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => value.extend(chars.next()),
+                    '"' => break,
+                    c => value.push(c),
+                }
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| *c != ',') {
+                value.push(c);
+            }
+        }
+        params.push((key.trim().to_string(), value.trim().to_string()));
+    }
+    (scheme.to_string(), params)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_reference() {
+        let digest = format!("sha256:{}", "a".repeat(64));
+        let pinned =
+            Reference::parse(&format!("oci://localhost:5000/team/app@{}", digest)).unwrap();
+        assert_eq!(pinned.registry, "localhost:5000");
+        assert_eq!(pinned.repository, "team/app");
+        assert_eq!(pinned.tag, None);
+        assert_eq!(pinned.digest, Some(digest.clone()));
+
+        let latest = Reference::parse("oci://registry.example/app").unwrap();
+        assert_eq!(latest.tag.as_deref(), Some("latest"));
+        assert_eq!(latest.to_string(), "oci://registry.example/app:latest");
+
+        assert!(Reference::parse("registry.example/app:v1").is_err());
+        assert!(Reference::parse("oci://registry.example/App:v1").is_err());
+        assert!(Reference::parse("oci://registry.example/app@sha256:abc").is_err());
+    }
+
+    #[test]
+    fn test_offline_pull_from_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::new(dir.path());
+        let reference = Reference::parse("oci://registry.example/app:v1").unwrap();
+        assert!(pull(&cache, &reference, true).is_err());
+
+        let contents = b"not really a tarball";
+        let layer = Descriptor {
+            media_type: layer_media_type(archive::Format::TarZstd).to_string(),
+            digest: cache.put_blob(contents).unwrap(),
+            size: contents.len() as u64,
+            title: Some("../configs.tar.zst".to_string()),
+        };
+        let digest = cache.put_blob(&manifest(&layer)).unwrap();
+        cache.set_tag(&reference, &digest).unwrap();
+
+        let pulled = pull(&cache, &reference, true).unwrap();
+        assert_eq!(pulled.manifest, digest);
+        assert_eq!(pulled.layer, layer);
+        assert_eq!(std::fs::read(&pulled.path).unwrap(), contents);
+        assert_eq!(pulled.file_name(), "configs.tar.zst");
+
+        // A pinned pull needs no network and no tag
+        let pinned = Reference::parse(&format!("oci://registry.example/app@{}", digest)).unwrap();
+        assert_eq!(pull(&cache, &pinned, false).unwrap(), pulled);
+
+        // A corrupted blob is dropped rather than served
+        std::fs::write(&pulled.path, b"tampered").unwrap();
+        assert!(pull(&cache, &reference, true).is_err());
+        assert!(!pulled.path.exists());
+    }
+
+    #[cfg(feature = "oci")]
+    #[test]
+    fn test_auth_helpers() {
+        let (scheme, params) = parse_challenge(
+            r#"Bearer realm="https://auth.example/token",service="registry.example",scope="repository:a/b:pull,push""#,
+        );
+        assert_eq!(scheme, "Bearer");
+        assert_eq!(
+            params,
+            [
+                (
+                    "realm".to_string(),
+                    "https://auth.example/token".to_string()
+                ),
+                ("service".to_string(), "registry.example".to_string()),
+                ("scope".to_string(), "repository:a/b:pull,push".to_string()),
+            ]
+        );
+    }
+}
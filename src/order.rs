@@ -0,0 +1,169 @@
+//! Record fields in source order
+//!
+//! Evaluated records come out with their fields sorted by name, which
+//! scatters the grouping an author chose (`name` and `image` together,
+//! then ports, then resources) across a reviewed artifact. A [`FieldOrder`]
+//! records, for each record in the output, the order its fields were
+//! defined in, and puts them back in that order when the output is written:
+//! `bunsenite parse --source-order` and `bunsenite export --source-order`.
+//!
+//! The order is read from field positions in the evaluated program
+//! ([`NickelLoader::field_order`](crate::NickelLoader::field_order)). A
+//! record merged from several files lists the fields of the file loaded
+//! first (usually the entry file) first. Fields without a position, such as
+//! those computed by a function, follow in name order.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::order::FieldOrder;
+//! use serde_json::json;
+//!
+//! let order = FieldOrder::new().with_record("", ["name", "image", "ports"]);
+//! let value = json!({ "image": "web:1.2", "name": "web", "ports": [80] });
+//! assert_eq!(
+//!     serde_json::to_string(&order.apply(&value)).unwrap(),
+//!     r#"{"name":"web","image":"web:1.2","ports":[80]}"#
+//! );
+//! ```
+
+use crate::json;
+use serde::ser::{Serialize, SerializeMap, SerializeSeq, Serializer};
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap};
+
+/// The source order of fields, for each record path
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FieldOrder {
+    records: BTreeMap<String, HashMap<String, usize>>,
+}
+
+impl FieldOrder {
+    /// No recorded order: every record keeps name order
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Order the fields of the record at `path` ([`json::key_path`] syntax)
+    /// as `keys` lists them
+    pub fn with_record<K: Into<String>>(
+        mut self,
+        path: impl Into<String>,
+        keys: impl IntoIterator<Item = K>,
+    ) -> Self {
+        let ranks = keys
+            .into_iter()
+            .enumerate()
+            .map(|(rank, key)| (key.into(), rank))
+            .collect();
+        self.records.insert(path.into(), ranks);
+        self
+    }
+
+    /// Whether no record has an order
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Where `key` comes in the record at `path`, if its position is known
+    pub fn rank(&self, path: &str, key: &str) -> Option<usize> {
+        self.records.get(path)?.get(key).copied()
+    }
+
+    /// The fields of the record at `path`, in order: fields with a known
+    /// position first, then the rest in their existing order
+    pub fn entries<'a>(
+        &self,
+        path: &str,
+        map: &'a Map<String, Value>,
+    ) -> Vec<(&'a String, &'a Value)> {
+        let mut entries: Vec<_> = map.iter().collect();
+        if let Some(ranks) = self.records.get(path) {
+            entries.sort_by_key(|(key, _)| ranks.get(*key).map_or((1, 0), |rank| (0, *rank)));
+        }
+        entries
+    }
+
+    /// `value`, serializing with its records' fields in order
+    pub fn apply<'a>(&'a self, value: &'a Value) -> impl Serialize + 'a {
+        Ordered {
+            value,
+            path: String::new(),
+            order: self,
+        }
+    }
+}
+
+/// A value serialized with its records' fields in order
+struct Ordered<'a> {
+    value: &'a Value,
+    path: String,
+    order: &'a FieldOrder,
+}
+
+impl Serialize for Ordered<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let nested = |value, path| Ordered {
+            value,
+            path,
+            order: self.order,
+        };
+        match self.value {
+            Value::Object(map) => {
+                let mut out = serializer.serialize_map(Some(map.len()))?;
+                for (key, value) in self.order.entries(&self.path, map) {
+                    out.serialize_entry(key, &nested(value, json::key_path(&self.path, key)))?;
+                }
+                out.end()
+            }
+            Value::Array(items) => {
+                let mut out = serializer.serialize_seq(Some(items.len()))?;
+                for (index, item) in items.iter().enumerate() {
+                    out.serialize_element(&nested(item, json::index_path(&self.path, index)))?;
+                }
+                out.end()
+            }
+            scalar => scalar.serialize(serializer),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_nested_records_are_ordered() {
+        let order = FieldOrder::new()
+            .with_record("", ["spec", "kind"])
+            .with_record("spec", ["replicas", "image"])
+            .with_record("spec.ports[0]", ["port", "name"]);
+        let value = json!({
+            "kind": "Deployment",
+            "spec": {
+                "image": "web",
+                "replicas": 2,
+                "ports": [{ "name": "http", "port": 80 }],
+            },
+        });
+        assert_eq!(
+            serde_json::to_string(&order.apply(&value)).unwrap(),
+            r#"{"spec":{"replicas":2,"image":"web","ports":[{"port":80,"name":"http"}]},"kind":"Deployment"}"#
+        );
+    }
+
+    #[test]
+    fn test_unranked_fields_follow_in_name_order() {
+        let order = FieldOrder::new().with_record("", ["z"]);
+        let map = json!({ "b": 1, "z": 2, "a": 3 });
+        let keys: Vec<&str> = order
+            .entries("", map.as_object().unwrap())
+            .into_iter()
+            .map(|(key, _)| key.as_str())
+            .collect();
+        assert_eq!(keys, ["z", "a", "b"]);
+        assert_eq!(order.rank("", "a"), None);
+        assert!(FieldOrder::new().is_empty());
+    }
+}
@@ -0,0 +1,167 @@
+//! Atomic output files
+//!
+//! Backs `parse -o` and `export -o`: outputs are written to a hidden
+//! temporary file next to the destination, flushed to disk and renamed over
+//! it, so file watchers and config reloaders only ever see the old contents
+//! or the complete new ones. `--mode` sets the permissions of outputs that
+//! carry secrets; the temporary file is created with them, so the contents
+//! are never readable more widely. Without a mode, a replaced file keeps its
+//! permissions.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::output;
+//!
+//! let dir = tempfile::tempdir().unwrap();
+//! let path = dir.path().join("app.json");
+//! output::write_atomic(&path, b"{}", Some(output::parse_mode("600").unwrap())).unwrap();
+//! assert_eq!(std::fs::read(&path).unwrap(), b"{}");
+//! ```
+
+use crate::error::{Error, Result};
+use std::fs::{self, OpenOptions, Permissions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Parse an octal permission mode such as `600`, `0640` or `0o600`
+///
+/// # Errors
+///
+/// Returns an invalid-input error unless `mode` is an octal number no
+/// larger than `7777`
+pub fn parse_mode(mode: &str) -> Result<u32> {
+    let digits = mode.strip_prefix("0o").unwrap_or(mode);
+    u32::from_str_radix(digits, 8)
+        .ok()
+        .filter(|mode| digits.bytes().all(|b| b.is_ascii_digit()) && *mode <= 0o7777)
+        .ok_or_else(|| {
+            Error::invalid_input(format!(
+                "Invalid file mode '{}': expected octal permissions such as 600 or 0640",
+                mode
+            ))
+        })
+}
+
+/// Write `contents` to `path` through a temporary file renamed into place,
+/// with permissions `mode` if given
+///
+/// # Errors
+///
+/// Returns an invalid-input error if `path` has no file name, or a mode is
+/// given on a platform without Unix permissions, or an I/O error
+pub fn write_atomic(path: &Path, contents: &[u8], mode: Option<u32>) -> Result<()> {
+    let partial = partial_path(path)?;
+    let permissions = match mode {
+        Some(mode) => Some(from_mode(mode)?),
+        None => fs::metadata(path).ok().map(|meta| meta.permissions()),
+    };
+
+    let written = write_new(&partial, contents, mode, permissions)
+        .and_then(|()| fs::rename(&partial, path).map_err(Error::from));
+    if written.is_err() {
+        let _ = fs::remove_file(&partial);
+    }
+    written
+}
+
+/// The hidden temporary file `path` is written through
+fn partial_path(path: &Path) -> Result<PathBuf> {
+    let name = path.file_name().ok_or_else(|| {
+        Error::invalid_input(format!("Cannot write {}: not a file path", path.display()))
+    })?;
+    Ok(path.with_file_name(format!(
+        ".{}.partial-{}",
+        name.to_string_lossy(),
+        std::process::id()
+    )))
+}
+
+fn write_new(
+    path: &Path,
+    contents: &[u8],
+    mode: Option<u32>,
+    permissions: Option<Permissions>,
+) -> Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        if let Some(mode) = mode {
+            options.mode(mode);
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+
+    let mut file = options.open(path)?;
+    file.write_all(contents)?;
+    if let Some(permissions) = permissions {
+        // The mode given at creation is narrowed by the umask
+        file.set_permissions(permissions)?;
+    }
+    file.sync_all()?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn from_mode(mode: u32) -> Result<Permissions> {
+    use std::os::unix::fs::PermissionsExt;
+    Ok(Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn from_mode(mode: u32) -> Result<Permissions> {
+    Err(Error::invalid_input(format!(
+        "Cannot set file mode {:o}: permission modes are only supported on Unix",
+        mode
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mode() {
+        assert_eq!(parse_mode("600").unwrap(), 0o600);
+        assert_eq!(parse_mode("0640").unwrap(), 0o640);
+        assert_eq!(parse_mode("0o755").unwrap(), 0o755);
+        assert!(parse_mode("").is_err());
+        assert!(parse_mode("0o").is_err());
+        assert!(parse_mode("rw-").is_err());
+        assert!(parse_mode("800").is_err());
+        assert!(parse_mode("17777").is_err());
+        assert!(parse_mode("+600").is_err());
+    }
+
+    #[test]
+    fn test_replaces_without_leftovers() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.json");
+        fs::write(&path, "old").unwrap();
+        write_atomic(&path, b"new", None).unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+        assert!(write_atomic(&dir.path().join("missing/app.json"), b"{}", None).is_err());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_modes() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("secrets.json");
+        write_atomic(&path, b"{}", Some(0o600)).unwrap();
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o7777;
+        assert_eq!(mode(&path), 0o600);
+
+        // A rewrite without a mode keeps the file's permissions
+        write_atomic(&path, b"{\"token\":1}", None).unwrap();
+        assert_eq!(mode(&path), 0o600);
+    }
+}
@@ -0,0 +1,139 @@
+//! Command-line value overrides
+//!
+//! Backs `parse --override server.port=8080` and `export --override`: each
+//! override sets a field of the config, at any depth, for one invocation
+//! without editing the source. Overrides are merged on top of the program
+//! with `force` priority (`program & { server.port | force = 8080 }`), so
+//! they win over values the config sets, including defaults, while its
+//! contracts still check them.
+//!
+//! Register overrides on a loader with
+//! [`NickelLoader::with_overrides`](crate::NickelLoader::with_overrides).
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::{overrides, NickelLoader};
+//!
+//! let loader = NickelLoader::new().with_overrides(vec![
+//!     overrides::parse_spec("server.port=8080").unwrap(),
+//!     overrides::parse_spec("server.host=example.com").unwrap(),
+//! ]);
+//! let config = loader
+//!     .parse_string(r#"{ server = { port = 80, host = "localhost", tls = true } }"#, "app.ncl")
+//!     .unwrap();
+//! assert_eq!(config["server"]["port"], 8080);
+//! assert_eq!(config["server"]["host"], "example.com");
+//! assert_eq!(config["server"]["tls"], true);
+//! ```
+
+use crate::error::{Error, Result};
+use crate::json;
+use crate::source;
+use serde_json::Value;
+
+/// Parse an override specification, `PATH=VALUE`
+///
+/// `PATH` is a field path in [`json::key_path`] syntax, e.g. `server.port`
+/// or `labels."app/name"`. `VALUE` is parsed as JSON and falls back to a
+/// plain string, so `port=8080` sets a number and `host=example.com` a
+/// string.
+///
+/// # Errors
+///
+/// Returns an invalid-input error if there is no `=`, or the path is
+/// malformed, empty, or has array indices or wildcards (only record fields
+/// can be overridden)
+pub fn parse_spec(spec: &str) -> Result<(String, Value)> {
+    let invalid =
+        |reason: &str| Error::invalid_input(format!("Invalid override '{}': {}", spec, reason));
+    let (path, value) = spec
+        .split_once('=')
+        .ok_or_else(|| invalid("expected PATH=VALUE"))?;
+    let segments = crate::pattern::split(path).ok_or_else(|| invalid("malformed path"))?;
+    if segments.is_empty() {
+        return Err(invalid("empty path"));
+    }
+    if segments
+        .iter()
+        .any(|s| s.starts_with('[') || s == "*" || s == "**")
+    {
+        return Err(invalid("only record fields can be overridden"));
+    }
+    let value = serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()));
+    Ok((json::join_path(&segments), value))
+}
+
+/// Rewrite a program to merge `overrides` on top of it
+///
+/// The program keeps its line numbers, so errors still point into it. When
+/// a path is overridden more than once, the last value is used.
+pub(crate) fn apply(program: &str, overrides: &[(String, Value)]) -> String {
+    let mut fields: Vec<(String, &Value)> = Vec::with_capacity(overrides.len());
+    for (path, value) in overrides {
+        let path = field_path(path);
+        fields.retain(|(seen, _)| *seen != path);
+        fields.push((path, value));
+    }
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|(path, value)| format!("{} | force = {}", path, source::value_literal(value)))
+        .collect();
+    format!("({}\n) & {{ {} }}", program, fields.join(", "))
+}
+
+/// A field path as Nickel source, e.g. `labels."app/name"`
+fn field_path(path: &str) -> String {
+    match crate::pattern::split(path) {
+        Some(segments) if !segments.is_empty() => segments
+            .iter()
+            .map(|segment| source::field_name(segment))
+            .collect::<Vec<_>>()
+            .join("."),
+        _ => source::field_name(path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NickelLoader;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_spec() {
+        assert_eq!(
+            parse_spec("server.port=8080").unwrap(),
+            ("server.port".to_string(), json!(8080))
+        );
+        assert_eq!(
+            parse_spec(r#"labels."app/name"=web"#).unwrap(),
+            (r#"labels."app/name""#.to_string(), json!("web"))
+        );
+        assert_eq!(parse_spec("features=[\"a\"]").unwrap().1, json!(["a"]));
+        assert_eq!(parse_spec("note=a=b").unwrap().1, json!("a=b"));
+        assert!(parse_spec("server.port").is_err());
+        assert!(parse_spec("=1").is_err());
+        assert!(parse_spec("servers[0].port=1").is_err());
+        assert!(parse_spec("servers.*.port=1").is_err());
+    }
+
+    #[test]
+    fn test_overrides_merge_over_program() {
+        let source = r#"{ port | Number | default = 80, name = "app", tags = ["a"] }"#;
+        let loader = NickelLoader::new().with_overrides(vec![
+            ("port".to_string(), json!(1)),
+            ("tags".to_string(), json!(["b"])),
+            ("port".to_string(), json!(8080)),
+        ]);
+        let config = loader.parse_string(source, "app.ncl").unwrap();
+        assert_eq!(
+            config,
+            json!({ "port": 8080, "name": "app", "tags": ["b"] })
+        );
+
+        // Contracts still apply to overridden values
+        let loader = NickelLoader::new().with_overrides(vec![("port".to_string(), json!("x"))]);
+        assert!(loader.parse_string(source, "app.ncl").is_err());
+    }
+}
@@ -0,0 +1,244 @@
+//! Field-level ownership and review routing
+//!
+//! The config equivalent of CODEOWNERS at field granularity. Owners come
+//! from two places:
+//!
+//! - a sidecar file (JSON, YAML, TOML or Nickel) mapping [path patterns] to an
+//!   owner or list of owners, e.g. `{ "secrets" = ["@security"] }`
+//! - `owners:` lines in field documentation, e.g.
+//!   `server | doc "owners: @infra @sre" = { ... }`
+//!
+//! For each changed output path, the most specific matching rule decides who
+//! must approve; on a tie, annotations win over the sidecar.
+//!
+//! [path patterns]: crate::pattern
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::owners::Owners;
+//! use serde_json::json;
+//!
+//! let owners = Owners::from_value(&json!({ "secrets": "@security", "**": ["@platform"] })).unwrap();
+//! assert_eq!(owners.owners_of("secrets.db"), ["@security"]);
+//! assert_eq!(owners.owners_of("server.port"), ["@platform"]);
+//! ```
+
+use crate::error::{Error, Result};
+use crate::loader::NickelLoader;
+use crate::pattern::PathPattern;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Where an ownership rule came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Source {
+    /// The sidecar owners file
+    Sidecar,
+    /// An `owners:` line in field documentation
+    Annotation,
+}
+
+/// An ownership rule
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    /// Paths the rule applies to
+    pub pattern: PathPattern,
+    /// Owners who must approve changes
+    pub owners: Vec<String>,
+    /// Where the rule came from
+    pub source: Source,
+}
+
+/// A set of ownership rules
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Owners {
+    rules: Vec<Rule>,
+}
+
+/// Owners who must approve a set of changed paths
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Routing {
+    /// Changed paths per owner, owners in sorted order
+    pub owners: BTreeMap<String, Vec<String>>,
+    /// Changed paths no rule covers
+    pub unowned: Vec<String>,
+}
+
+impl Owners {
+    /// Load rules from a sidecar file
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be loaded or is not a record of
+    /// patterns to owners
+    pub fn load(loader: &NickelLoader, path: &Path) -> Result<Self> {
+        Self::from_value(&crate::schema::load_sample(loader, path)?)
+    }
+
+    /// Build rules from a record mapping patterns to an owner or owner list
+    ///
+    /// # Errors
+    ///
+    /// Returns an invalid-input error if the value has the wrong shape or a
+    /// pattern is invalid
+    pub fn from_value(value: &Value) -> Result<Self> {
+        let map = value
+            .as_object()
+            .ok_or_else(|| Error::invalid_input("Owners file must be a record"))?;
+
+        let mut rules = Vec::with_capacity(map.len());
+        for (pattern, owners) in map {
+            let owners = match owners {
+                Value::String(owner) => vec![owner.clone()],
+                Value::Array(items) => items
+                    .iter()
+                    .map(|item| item.as_str().map(str::to_string))
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| {
+                        Error::invalid_input(format!("Owners of '{}' must be strings", pattern))
+                    })?,
+                _ => {
+                    return Err(Error::invalid_input(format!(
+                        "Owners of '{}' must be a string or array of strings",
+                        pattern
+                    )))
+                }
+            };
+            rules.push(Rule {
+                pattern: pattern.parse()?,
+                owners,
+                source: Source::Sidecar,
+            });
+        }
+
+        Ok(Self { rules })
+    }
+
+    /// Add rules from `owners:` lines in field documentation
+    ///
+    /// Takes `(path, doc)` pairs as returned by
+    /// [`NickelLoader::field_docs`](crate::NickelLoader::field_docs).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a documented path is not a valid pattern
+    pub fn add_annotations(&mut self, docs: &[(String, String)]) -> Result<()> {
+        for (path, doc) in docs {
+            let owners: Vec<String> = doc
+                .lines()
+                .filter_map(|line| line.trim().strip_prefix("owners:"))
+                .flat_map(|owners| owners.split(|c: char| c.is_whitespace() || c == ','))
+                .filter(|owner| !owner.is_empty())
+                .map(str::to_string)
+                .collect();
+
+            if !owners.is_empty() {
+                self.rules.push(Rule {
+                    pattern: path.parse()?,
+                    owners,
+                    source: Source::Annotation,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// All rules
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// Owners of a path, or an empty slice if no rule covers it
+    pub fn owners_of(&self, path: &str) -> &[String] {
+        self.rules
+            .iter()
+            .filter(|rule| rule.pattern.matches(path))
+            .max_by_key(|rule| (rule.pattern.specificity(), rule.source))
+            .map_or(&[][..], |rule| rule.owners.as_slice())
+    }
+
+    /// Route changed paths to the owners who must approve them
+    pub fn route<'a>(&self, paths: impl IntoIterator<Item = &'a str>) -> Routing {
+        let mut routing = Routing::default();
+        for path in paths {
+            let owners = self.owners_of(path);
+            if owners.is_empty() {
+                routing.unowned.push(path.to_string());
+            }
+            for owner in owners {
+                routing
+                    .owners
+                    .entry(owner.clone())
+                    .or_default()
+                    .push(path.to_string());
+            }
+        }
+        routing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_most_specific_rule_wins() {
+        let owners = Owners::from_value(&json!({
+            "**": "@platform",
+            "server": ["@infra", "@sre"],
+            "server.tls": "@security",
+        }))
+        .unwrap();
+
+        assert_eq!(owners.owners_of("replicas"), ["@platform"]);
+        assert_eq!(owners.owners_of("server.port"), ["@infra", "@sre"]);
+        assert_eq!(owners.owners_of("server.tls.cert"), ["@security"]);
+    }
+
+    #[test]
+    fn test_annotations_override_sidecar_on_ties() {
+        let mut owners = Owners::from_value(&json!({ "db": "@data" })).unwrap();
+        owners
+            .add_annotations(&[
+                (
+                    "db".to_string(),
+                    "Database settings\nowners: @dba, @oncall".to_string(),
+                ),
+                ("cache".to_string(), "No owners here".to_string()),
+            ])
+            .unwrap();
+
+        assert_eq!(owners.owners_of("db.host"), ["@dba", "@oncall"]);
+        assert!(owners.owners_of("cache.size").is_empty());
+    }
+
+    #[test]
+    fn test_route_groups_by_owner() {
+        let owners = Owners::from_value(&json!({ "a": "@x", "b": ["@x", "@y"] })).unwrap();
+        let routing = owners.route(["a.one", "b", "c"]);
+
+        assert_eq!(routing.owners["@x"], vec!["a.one", "b"]);
+        assert_eq!(routing.owners["@y"], vec!["b"]);
+        assert_eq!(routing.unowned, vec!["c"]);
+    }
+
+    #[test]
+    fn test_rejects_malformed_sidecar() {
+        assert!(Owners::from_value(&json!(["@x"])).is_err());
+        assert!(Owners::from_value(&json!({ "a": 1 })).is_err());
+        assert!(Owners::from_value(&json!({ "a": [1] })).is_err());
+    }
+
+    #[test]
+    fn test_annotations_from_field_docs() {
+        let source = r#"{ server | doc "owners: @infra" = { port = 80 }, name = "x" }"#;
+        let docs = NickelLoader::new().field_docs(source, "test.ncl").unwrap();
+
+        let mut owners = Owners::default();
+        owners.add_annotations(&docs).unwrap();
+        assert_eq!(owners.owners_of("server.port"), ["@infra"]);
+    }
+}
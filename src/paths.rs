@@ -0,0 +1,485 @@
+//! Windows paths in import resolution and diagnostics
+//!
+//! Import paths reach bunsenite in every spelling Windows allows: drive
+//! letters (`C:\configs\app.ncl`), drive-relative paths (`C:app.ncl`), UNC
+//! shares (`\\server\share\app.ncl`), forward slashes, and the verbatim
+//! `\\?\` form that lifts the 260-character `MAX_PATH` limit (and that
+//! `std::fs::canonicalize` returns). Verbatim paths are taken literally by
+//! Windows, so joining `lib/base.ncl` onto one, or leaving a `..` in it,
+//! yields a file that does not exist, and they make diagnostics hard to read.
+//!
+//! [`WindowsPath`] parses all of these the same way on every platform (so
+//! the rules are tested everywhere), normalizes separators and `.`/`..`
+//! lexically, and writes paths back in their plain form for display or in
+//! verbatim form for long paths. On Windows, the import walker in
+//! [`crate::imports`] resolves imports with it, reads long paths through
+//! their verbatim form and reports the plain form.
+//!
+//! File names are compared according to a [`CasePolicy`]: by default
+//! case-insensitively on Windows and macOS, whose file systems usually are,
+//! so `Lib/Base.ncl` and `lib/base.ncl` are one file there.
+//!
+//! [`relative_to`] renders paths relative to a project root, for
+//! diagnostics, provenance and reports that should read the same wherever
+//! the project is checked out (`bunsenite --relative-paths`).
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::paths::WindowsPath;
+//!
+//! let base = WindowsPath::parse(r"\\?\C:\configs\app.ncl");
+//! assert_eq!(base.display(), r"C:\configs\app.ncl");
+//! assert_eq!(base.join("shared/base.ncl").display(), r"C:\configs\shared\base.ncl");
+//! assert_eq!(base.join("../../base.ncl").display(), r"C:\base.ncl");
+//! ```
+
+use std::path::{Path, PathBuf};
+
+/// Longest path Windows accepts without the verbatim `\\?\` prefix
+pub const MAX_PATH: usize = 260;
+
+/// Where a Windows path is rooted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Root {
+    /// A drive's root, e.g. `C:\`
+    Drive(char),
+    /// A drive's current directory, e.g. `C:` in `C:app.ncl`
+    DriveRelative(char),
+    /// A network share, e.g. `\\server\share\`
+    Unc {
+        /// Server name
+        server: String,
+        /// Share name
+        share: String,
+    },
+    /// The root of the current drive, `\`
+    CurrentDrive,
+    /// A device or volume, e.g. `\\.\pipe\` or `\\?\Volume{...}\`
+    Device(String),
+}
+
+/// A parsed, lexically normalized Windows path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WindowsPath {
+    root: Option<Root>,
+    segments: Vec<String>,
+}
+
+impl WindowsPath {
+    /// Parse a path in any Windows spelling
+    ///
+    /// Both `\` and `/` separate components (no Windows file name contains
+    /// either), `.` components are dropped and `..` removes the component
+    /// before it, but never goes above a root.
+    pub fn parse(path: &str) -> Self {
+        let (root, rest) = split_root(path);
+        let mut parsed = Self {
+            root,
+            segments: Vec::new(),
+        };
+        parsed.push(rest);
+        parsed
+    }
+
+    fn push(&mut self, rest: &str) {
+        for segment in rest.split(['\\', '/']) {
+            match segment {
+                "" | "." => {}
+                ".." if self.segments.last().is_some_and(|last| last != "..") => {
+                    self.segments.pop();
+                }
+                // `..` at a root stays at the root, as Windows resolves it
+                ".." if self.root.is_some() => {}
+                segment => self.segments.push(segment.to_string()),
+            }
+        }
+    }
+
+    /// Root of the path, if it has one
+    pub fn root(&self) -> Option<&Root> {
+        self.root.as_ref()
+    }
+
+    /// Whether the path names the same file from any current directory
+    pub fn is_absolute(&self) -> bool {
+        matches!(
+            self.root,
+            Some(Root::Drive(_) | Root::Unc { .. } | Root::Device(_))
+        )
+    }
+
+    /// Resolve `import` against this path's directory
+    ///
+    /// `self` is the importing file, so its last component is dropped first.
+    pub fn join(&self, import: &str) -> Self {
+        let mut dir = self.clone();
+        dir.segments.pop();
+        dir.join_dir(import)
+    }
+
+    /// Resolve `path` against this directory
+    pub fn join_dir(&self, path: &str) -> Self {
+        let (root, rest) = split_root(path);
+        let mut joined = match root {
+            None => self.clone(),
+            Some(Root::CurrentDrive) => Self {
+                root: match &self.root {
+                    Some(root @ (Root::Drive(_) | Root::Unc { .. } | Root::Device(_))) => {
+                        Some(root.clone())
+                    }
+                    _ => Some(Root::CurrentDrive),
+                },
+                segments: Vec::new(),
+            },
+            Some(Root::DriveRelative(drive))
+                if matches!(
+                    self.root,
+                    Some(Root::Drive(d) | Root::DriveRelative(d)) if d == drive
+                ) =>
+            {
+                self.clone()
+            }
+            Some(root) => Self {
+                root: Some(root),
+                segments: Vec::new(),
+            },
+        };
+        joined.push(rest);
+        joined
+    }
+
+    /// The path in its plain form, with `\` separators
+    pub fn display(&self) -> String {
+        let root = match &self.root {
+            None => String::new(),
+            Some(Root::Drive(drive)) => format!("{}:\\", drive),
+            Some(Root::DriveRelative(drive)) => format!("{}:", drive),
+            Some(Root::Unc { server, share }) => format!("\\\\{}\\{}\\", server, share),
+            Some(Root::CurrentDrive) => "\\".to_string(),
+            Some(Root::Device(device)) => format!("\\\\.\\{}\\", device),
+        };
+        root + &self.segments.join("\\")
+    }
+
+    /// The path in verbatim `\\?\` form, which Windows accepts beyond
+    /// [`MAX_PATH`]
+    ///
+    /// Paths that are not rooted at a drive or share have no verbatim form
+    /// and are returned plain.
+    pub fn verbatim(&self) -> String {
+        let path = self.segments.join("\\");
+        match &self.root {
+            Some(Root::Drive(drive)) => format!("\\\\?\\{}:\\{}", drive, path),
+            Some(Root::Unc { server, share }) => {
+                format!("\\\\?\\UNC\\{}\\{}\\{}", server, share, path)
+            }
+            _ => self.display(),
+        }
+    }
+
+    /// The form to open the file by: verbatim if the plain form is too long
+    pub fn to_open(&self) -> String {
+        let plain = self.display();
+        if plain.len() >= MAX_PATH {
+            self.verbatim()
+        } else {
+            plain
+        }
+    }
+
+    /// A string equal for two paths exactly when `policy` considers them
+    /// the same file
+    pub fn key(&self, policy: CasePolicy) -> String {
+        fold(self.display(), policy)
+    }
+}
+
+/// Split the root off a path, returning the rest
+fn split_root(path: &str) -> (Option<Root>, &str) {
+    let is_separator = |c: char| c == '\\' || c == '/';
+
+    // Verbatim and NT object paths: `\\?\C:\`, `\\?\UNC\server\share\`, `\??\C:\`
+    let verbatim = path
+        .strip_prefix(r"\\?\")
+        .or_else(|| path.strip_prefix(r"\??\"));
+    if let Some(rest) = verbatim {
+        if rest
+            .get(..4)
+            .is_some_and(|p| p.eq_ignore_ascii_case(r"UNC\"))
+        {
+            return unc(&rest[4..]);
+        }
+        if let Some((drive, rest)) = drive(rest) {
+            return (Some(Root::Drive(drive)), rest);
+        }
+        let (device, rest) = rest.split_once('\\').unwrap_or((rest, ""));
+        return (Some(Root::Device(device.to_string())), rest);
+    }
+
+    let mut chars = path.chars();
+    match (chars.next(), chars.next(), chars.next(), chars.next()) {
+        // Device paths: `\\.\pipe\name`, `//./COM1`
+        (Some(a), Some(b), Some('.' | '?'), Some(d))
+            if is_separator(a) && is_separator(b) && is_separator(d) =>
+        {
+            let rest = &path[4..];
+            let (device, rest) = rest.split_once(is_separator).unwrap_or((rest, ""));
+            (Some(Root::Device(device.to_string())), rest)
+        }
+        (Some(a), Some(b), ..) if is_separator(a) && is_separator(b) => unc(&path[2..]),
+        (Some(a), ..) if is_separator(a) => (Some(Root::CurrentDrive), &path[1..]),
+        _ => match drive(path) {
+            Some((drive, rest)) => (Some(Root::Drive(drive)), rest),
+            None => match path.as_bytes() {
+                [letter, b':', ..] if letter.is_ascii_alphabetic() => (
+                    Some(Root::DriveRelative(letter.to_ascii_uppercase() as char)),
+                    &path[2..],
+                ),
+                _ => (None, path),
+            },
+        },
+    }
+}
+
+/// `C:\rest` or `C:/rest`, as the drive letter and `rest`
+fn drive(path: &str) -> Option<(char, &str)> {
+    match path.as_bytes() {
+        [letter, b':', b'\\' | b'/', ..] if letter.is_ascii_alphabetic() => {
+            Some((letter.to_ascii_uppercase() as char, &path[3..]))
+        }
+        [letter, b':'] if letter.is_ascii_alphabetic() => {
+            Some((letter.to_ascii_uppercase() as char, ""))
+        }
+        _ => None,
+    }
+}
+
+/// `server\share\rest`, as a UNC root and `rest`
+fn unc(path: &str) -> (Option<Root>, &str) {
+    let mut parts = path.splitn(3, ['\\', '/']);
+    let server = parts.next().unwrap_or_default().to_string();
+    let share = parts.next().unwrap_or_default().to_string();
+    let rest = parts.next().unwrap_or_default();
+    (Some(Root::Unc { server, share }), rest)
+}
+
+/// How file names are compared
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CasePolicy {
+    /// Insensitive on Windows and macOS, sensitive elsewhere
+    #[default]
+    Platform,
+    /// `a.ncl` and `A.ncl` are different files
+    Sensitive,
+    /// `a.ncl` and `A.ncl` are the same file
+    Insensitive,
+}
+
+impl CasePolicy {
+    /// Whether names differing only in case are the same file
+    pub fn is_insensitive(self) -> bool {
+        match self {
+            CasePolicy::Platform => cfg!(any(windows, target_os = "macos")),
+            CasePolicy::Sensitive => false,
+            CasePolicy::Insensitive => true,
+        }
+    }
+}
+
+fn fold(path: String, policy: CasePolicy) -> String {
+    if policy.is_insensitive() {
+        path.to_lowercase()
+    } else {
+        path
+    }
+}
+
+/// A string equal for two paths exactly when `policy` considers them the
+/// same file
+///
+/// Paths should already be normalized (see [`crate::imports`]).
+pub fn key(path: &Path, policy: CasePolicy) -> String {
+    if cfg!(windows) {
+        WindowsPath::parse(&path.to_string_lossy()).key(policy)
+    } else {
+        fold(path.to_string_lossy().into_owned(), policy)
+    }
+}
+
+/// `path` as diagnostics should show it
+///
+/// On Windows, verbatim `\\?\` prefixes are dropped and separators are
+/// made `\`; elsewhere the path is shown as is.
+pub fn display(path: &Path) -> String {
+    if cfg!(windows) {
+        WindowsPath::parse(&path.to_string_lossy()).display()
+    } else {
+        path.display().to_string()
+    }
+}
+
+/// `path` relative to the project at `root`, if it lies below it
+///
+/// Relative paths are taken to be relative to `root` already and returned
+/// as they are, as are absolute paths outside `root`. `root` is matched both
+/// as given and with symlinks resolved, so `/var/...` and `/private/var/...`
+/// on macOS are recognized alike.
+pub fn relative_to(path: &Path, root: &Path) -> PathBuf {
+    if !path.is_absolute() {
+        return path.to_path_buf();
+    }
+    let normalized = crate::imports::normalize(path);
+    roots(root)
+        .iter()
+        .find_map(|root| normalized.strip_prefix(root).ok())
+        .map_or_else(
+            || path.to_path_buf(),
+            |rest| {
+                if rest.as_os_str().is_empty() {
+                    PathBuf::from(".")
+                } else {
+                    rest.to_path_buf()
+                }
+            },
+        )
+}
+
+/// `text` with paths below the project at `root` made relative to it, for
+/// messages that quote paths
+pub(crate) fn relative_text(text: &str, root: &Path) -> String {
+    roots(root).iter().fold(text.to_string(), |text, root| {
+        text.replace(
+            &format!("{}{}", root.display(), std::path::MAIN_SEPARATOR),
+            "",
+        )
+    })
+}
+
+/// `root` as given and with symlinks resolved, both made absolute
+pub(crate) fn roots(root: &Path) -> Vec<PathBuf> {
+    let given = std::env::current_dir()
+        .map(|cwd| crate::imports::normalize(&cwd.join(root)))
+        .unwrap_or_else(|_| root.to_path_buf());
+    let mut roots = vec![given];
+    if let Ok(real) = root.canonicalize() {
+        if !roots.contains(&real) {
+            roots.push(real);
+        }
+    }
+    roots
+}
+
+/// `path` in the form to open it by
+///
+/// On Windows, paths longer than [`MAX_PATH`] are given the verbatim
+/// `\\?\` prefix; elsewhere the path is returned as is.
+pub fn to_open(path: &Path) -> PathBuf {
+    if cfg!(windows) {
+        PathBuf::from(WindowsPath::parse(&path.to_string_lossy()).to_open())
+    } else {
+        path.to_path_buf()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shown(path: &str) -> String {
+        WindowsPath::parse(path).display()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_relative_to() {
+        let root = Path::new("/src/app");
+        let relative = |path: &str| relative_to(Path::new(path), root);
+        assert_eq!(
+            relative("/src/app/configs/a.ncl"),
+            Path::new("configs/a.ncl")
+        );
+        assert_eq!(relative("/src/app/./lib/../b.ncl"), Path::new("b.ncl"));
+        assert_eq!(relative("/src/app"), Path::new("."));
+        assert_eq!(
+            relative("/src/application/a.ncl"),
+            Path::new("/src/application/a.ncl")
+        );
+        assert_eq!(relative("/etc/shared.ncl"), Path::new("/etc/shared.ncl"));
+        assert_eq!(relative("configs/a.ncl"), Path::new("configs/a.ncl"));
+    }
+
+    #[test]
+    fn test_parse_roots() {
+        assert_eq!(shown(r"c:/configs\.\app.ncl"), r"C:\configs\app.ncl");
+        assert_eq!(shown(r"\\?\C:\configs\app.ncl"), r"C:\configs\app.ncl");
+        assert_eq!(shown(r"\\?\UNC\fs01\cfg\app.ncl"), r"\\fs01\cfg\app.ncl");
+        assert_eq!(shown("//fs01/cfg/lib/../app.ncl"), r"\\fs01\cfg\app.ncl");
+        assert_eq!(shown(r"\\.\pipe\bunsenite"), r"\\.\pipe\bunsenite");
+        assert_eq!(shown(r"C:app.ncl"), r"C:app.ncl");
+        assert_eq!(shown(r"\app.ncl"), r"\app.ncl");
+        assert_eq!(shown("../lib/a.ncl"), r"..\lib\a.ncl");
+
+        assert!(WindowsPath::parse(r"\\fs01\cfg\a.ncl").is_absolute());
+        assert!(!WindowsPath::parse(r"C:a.ncl").is_absolute());
+        assert!(!WindowsPath::parse(r"\a.ncl").is_absolute());
+    }
+
+    #[test]
+    fn test_dot_dot_stops_at_root() {
+        assert_eq!(shown(r"C:\..\..\a.ncl"), r"C:\a.ncl");
+        assert_eq!(shown(r"\\fs01\cfg\..\a.ncl"), r"\\fs01\cfg\a.ncl");
+        assert_eq!(shown(r"a\..\..\b.ncl"), r"..\b.ncl");
+    }
+
+    #[test]
+    fn test_join_imports() {
+        let file = WindowsPath::parse(r"\\?\D:\repo\env\prod.ncl");
+        assert_eq!(
+            file.join("../lib/base.ncl").display(),
+            r"D:\repo\lib\base.ncl"
+        );
+        assert_eq!(file.join(r"E:\shared\x.ncl").display(), r"E:\shared\x.ncl");
+        assert_eq!(file.join(r"\top.ncl").display(), r"D:\top.ncl");
+        assert_eq!(
+            file.join("D:sibling.ncl").display(),
+            r"D:\repo\env\sibling.ncl"
+        );
+        assert_eq!(file.join("E:other.ncl").display(), r"E:other.ncl");
+
+        let share = WindowsPath::parse(r"\\fs01\cfg\app.ncl");
+        assert_eq!(share.join(r"\top.ncl").display(), r"\\fs01\cfg\top.ncl");
+    }
+
+    #[test]
+    fn test_long_paths_open_verbatim() {
+        let short = WindowsPath::parse(r"C:\a\b.ncl");
+        assert_eq!(short.to_open(), r"C:\a\b.ncl");
+        assert_eq!(short.verbatim(), r"\\?\C:\a\b.ncl");
+
+        let long = format!(r"C:\{}\app.ncl", "d".repeat(MAX_PATH));
+        assert!(WindowsPath::parse(&long)
+            .to_open()
+            .starts_with(r"\\?\C:\dddd"));
+        let share = format!(r"\\fs01\cfg\{}.ncl", "d".repeat(MAX_PATH));
+        assert!(WindowsPath::parse(&share)
+            .to_open()
+            .starts_with(r"\\?\UNC\fs01\cfg\dddd"));
+        assert_eq!(WindowsPath::parse(r"rel\a.ncl").verbatim(), r"rel\a.ncl");
+    }
+
+    #[test]
+    fn test_case_policy() {
+        let a = WindowsPath::parse(r"C:\Lib\Base.ncl");
+        let b = WindowsPath::parse(r"c:/lib/base.ncl");
+        assert_eq!(
+            a.key(CasePolicy::Insensitive),
+            b.key(CasePolicy::Insensitive)
+        );
+        assert_ne!(a.key(CasePolicy::Sensitive), b.key(CasePolicy::Sensitive));
+        assert_eq!(
+            CasePolicy::Platform.is_insensitive(),
+            cfg!(any(windows, target_os = "macos"))
+        );
+    }
+}
@@ -0,0 +1,270 @@
+//! Output path patterns
+//!
+//! Patterns select subtrees of an evaluated config by path, in the syntax
+//! produced by [`json::key_path`](crate::json::key_path) and
+//! [`json::index_path`](crate::json::index_path): dot-separated keys, quoted
+//! when they are not plain identifiers, and `[n]` for array elements.
+//!
+//! In a pattern, `*` (or `[*]`) matches any single key or index, and `**`
+//! matches any number of segments. A pattern selects the whole subtree below
+//! the paths it matches, so `secrets` and `secrets.**` are equivalent.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::pattern::PathPattern;
+//!
+//! let pattern: PathPattern = "servers[*].tls".parse().unwrap();
+//! assert!(pattern.matches("servers[0].tls"));
+//! assert!(pattern.matches("servers[1].tls.cert"));
+//! assert!(!pattern.matches("servers[1].host"));
+//! ```
+
+use crate::error::{Error, Result};
+use std::fmt;
+use std::str::FromStr;
+
+/// A pattern over output paths
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathPattern {
+    source: String,
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    /// A literal key or `[n]` index
+    Literal(String),
+    /// `*`: exactly one segment
+    Any,
+    /// `**`: zero or more segments
+    AnyDeep,
+}
+
+impl PathPattern {
+    /// Parse a pattern
+    ///
+    /// # Errors
+    ///
+    /// Returns an invalid-input error for empty patterns or unterminated
+    /// quotes and brackets
+    pub fn parse(pattern: &str) -> Result<Self> {
+        let segments = split(pattern)
+            .ok_or_else(|| Error::invalid_input(format!("Invalid path pattern '{}'", pattern)))?
+            .into_iter()
+            .map(|segment| match segment.as_str() {
+                "*" | "[*]" => Segment::Any,
+                "**" => Segment::AnyDeep,
+                _ => Segment::Literal(segment),
+            })
+            .collect();
+
+        Ok(Self {
+            source: pattern.to_string(),
+            segments,
+        })
+    }
+
+    /// Whether `path` or one of its ancestors matches the pattern
+    pub fn matches(&self, path: &str) -> bool {
+        match split(path) {
+            Some(path) => matches_prefix(&self.segments, &path),
+            None => false,
+        }
+    }
+
+    /// Whether the pattern may match `path` or one of its descendants
+    ///
+    /// Unlike [`matches`](Self::matches), this is also true when `path` is
+    /// an ancestor of paths the pattern selects.
+    pub fn matches_below(&self, path: &str) -> bool {
+        match split(path) {
+            Some(path) => matches_partial(&self.segments, &path),
+            None => false,
+        }
+    }
+
+    /// How specific the pattern is: the number of segments other than `**`
+    ///
+    /// When several patterns match a path, the most specific one applies.
+    pub fn specificity(&self) -> usize {
+        self.segments
+            .iter()
+            .filter(|s| **s != Segment::AnyDeep)
+            .count()
+    }
+
+    /// The pattern as written
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+}
+
+impl FromStr for PathPattern {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::parse(s)
+    }
+}
+
+impl fmt::Display for PathPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+/// Whether the pattern matches some prefix of `path`
+fn matches_prefix(pattern: &[Segment], path: &[String]) -> bool {
+    match pattern.split_first() {
+        None => true,
+        Some((Segment::AnyDeep, rest)) => {
+            (0..=path.len()).any(|i| matches_prefix(rest, &path[i..]))
+        }
+        Some((segment, rest)) => match path.split_first() {
+            Some((first, path)) => {
+                let head = match segment {
+                    Segment::Literal(literal) => literal == first,
+                    _ => true,
+                };
+                head && matches_prefix(rest, path)
+            }
+            None => false,
+        },
+    }
+}
+
+/// Whether `path` is consistent with a prefix of the pattern, or matched by it
+fn matches_partial(pattern: &[Segment], path: &[String]) -> bool {
+    match (pattern.split_first(), path.split_first()) {
+        (None, _) | (_, None) | (Some((Segment::AnyDeep, _)), _) => true,
+        (Some((segment, rest)), Some((first, path))) => {
+            let head = match segment {
+                Segment::Literal(literal) => literal == first,
+                _ => true,
+            };
+            head && matches_partial(rest, path)
+        }
+    }
+}
+
+/// Split a path into segments; keys are unquoted and indices kept as `[n]`
+pub(crate) fn split(path: &str) -> Option<Vec<String>> {
+    let mut segments = Vec::new();
+    let mut chars = path.trim().chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            '.' => {
+                chars.next();
+                if matches!(chars.peek(), None | Some('.') | Some('[')) {
+                    return None;
+                }
+            }
+            '[' => {
+                let mut segment = String::new();
+                for c in chars.by_ref() {
+                    segment.push(c);
+                    if c == ']' {
+                        break;
+                    }
+                }
+                if !segment.ends_with(']') {
+                    return None;
+                }
+                segments.push(segment);
+            }
+            '"' => {
+                chars.next();
+                let mut segment = String::new();
+                let mut escaped = false;
+                loop {
+                    let c = chars.next()?;
+                    match c {
+                        '\\' if !escaped => escaped = true,
+                        '"' if !escaped => break,
+                        c => {
+                            escaped = false;
+                            segment.push(c);
+                        }
+                    }
+                }
+                segments.push(segment);
+            }
+            _ => {
+                let mut segment = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c == '.' || c == '[' {
+                        break;
+                    }
+                    segment.push(c);
+                    chars.next();
+                }
+                segments.push(segment);
+            }
+        }
+    }
+
+    if segments.is_empty() {
+        None
+    } else {
+        Some(segments)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(s: &str) -> PathPattern {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_literal_patterns_select_subtrees() {
+        assert!(pattern("secrets").matches("secrets"));
+        assert!(pattern("secrets").matches("secrets.db.password"));
+        assert!(!pattern("secrets").matches("secretsx"));
+        assert!(!pattern("secrets.db").matches("secrets"));
+    }
+
+    #[test]
+    fn test_wildcards() {
+        assert!(pattern("secrets.*").matches("secrets.db"));
+        assert!(!pattern("secrets.*").matches("secrets"));
+        assert!(pattern("**.password").matches("a.b.password"));
+        assert!(pattern("**.password").matches("password"));
+        assert!(pattern("items[*].id").matches("items[3].id"));
+        assert!(pattern("items.*").matches("items[3]"));
+    }
+
+    #[test]
+    fn test_quoted_keys() {
+        let p = pattern(r#"labels."app.kubernetes.io/name""#);
+        assert!(p.matches(r#"labels."app.kubernetes.io/name""#));
+        assert!(!p.matches("labels.app"));
+    }
+
+    #[test]
+    fn test_invalid_patterns() {
+        assert!(PathPattern::parse("").is_err());
+        assert!(PathPattern::parse("a..b").is_err());
+        assert!(PathPattern::parse("items[0").is_err());
+        assert!(PathPattern::parse("\"open").is_err());
+    }
+
+    #[test]
+    fn test_matches_below() {
+        assert!(pattern("a.b.c").matches_below("a"));
+        assert!(pattern("a.b.c").matches_below("a.b.c.d"));
+        assert!(pattern("a.*.c").matches_below("a.x"));
+        assert!(!pattern("a.b.c").matches_below("a.x"));
+        assert!(pattern("**.c").matches_below("z"));
+    }
+
+    #[test]
+    fn test_specificity() {
+        assert_eq!(pattern("a.b").specificity(), 2);
+        assert_eq!(pattern("**.b").specificity(), 1);
+    }
+}
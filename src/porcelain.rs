@@ -0,0 +1,139 @@
+//! The `--porcelain` output contract
+//!
+//! With `--porcelain`, the CLI keeps its two output streams apart so that
+//! wrapping tools can depend on their layout:
+//!
+//! - **stdout** carries only what the command was asked to produce: the
+//!   evaluated config, rendered docs or schema, a diff or report, byte for
+//!   byte as without the flag. Commands that only check something
+//!   (`validate`, `typecheck`, `test`, `conformance`) write nothing there.
+//! - **stderr** carries one JSON object per line, never free text. Every
+//!   line has `porcelain`, the contract version ([`VERSION`]), and `kind`
+//!   ([`Kind`]); the other fields depend on the kind:
+//!
+//! ```text
+//! {"kind":"note","message":"Validating 2 file(s) matching configs/*.ncl","porcelain":1}
+//! {"kind":"result","message":null,"porcelain":1,"result":"pass","subject":"configs/app.ncl"}
+//! {"code":"parse","file":"configs/db.ncl","kind":"diagnostic","message":"...","porcelain":1,"severity":"error","span":null,"suggestion":"..."}
+//! {"kind":"failure","message":"1 of 2 file(s) failed validation","porcelain":1}
+//! ```
+//!
+//! Diagnostics have the fields of `--error-format json` (see
+//! [`crate::diagnostic`]). The exit code is 0 on success, 1 on failure and
+//! 130 when interrupted; a command line that does not parse is reported
+//! before porcelain mode starts, as text, with exit code 2.
+//!
+//! A later version may add kinds and fields, which readers should ignore;
+//! it changes [`VERSION`] only to change or remove existing ones.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::porcelain::{self, Kind};
+//!
+//! let line = porcelain::message(Kind::Status, "Wrote out/app.json");
+//! let parsed: serde_json::Value = serde_json::from_str(&line).unwrap();
+//! assert_eq!(parsed["porcelain"], porcelain::VERSION);
+//! assert_eq!(parsed["kind"], "status");
+//! ```
+
+use crate::diagnostic::Diagnostic;
+use crate::json;
+use serde_json::{json, Value};
+
+/// Version of the porcelain output contract
+pub const VERSION: u32 = 1;
+
+/// What a line on stderr reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// Something the command did or found fine, e.g. a file written
+    Status,
+    /// A check that failed, summed up
+    Failure,
+    /// Progress and other information
+    Note,
+    /// The outcome of checking one subject, such as a file or a test
+    Result,
+    /// An error or warning
+    Diagnostic,
+}
+
+impl Kind {
+    /// The kind's name, as written in JSON
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Kind::Status => "status",
+            Kind::Failure => "failure",
+            Kind::Note => "note",
+            Kind::Result => "result",
+            Kind::Diagnostic => "diagnostic",
+        }
+    }
+}
+
+/// A line of kind `kind` with `fields`, an object, besides `porcelain` and
+/// `kind`
+pub fn line(kind: Kind, fields: Value) -> String {
+    let mut line = match fields {
+        Value::Object(fields) => fields,
+        _ => serde_json::Map::new(),
+    };
+    line.insert("porcelain".to_string(), json!(VERSION));
+    line.insert("kind".to_string(), json!(kind.as_str()));
+    json::to_string(&Value::Object(line), false)
+}
+
+/// A line of kind `kind` that is just a message
+pub fn message(kind: Kind, message: &str) -> String {
+    line(kind, json!({ "message": message }))
+}
+
+/// A line with the outcome `result` of checking `subject`, e.g. `pass` or
+/// `fail`, and what the outcome is about, if anything
+pub fn result(subject: &str, result: &str, message: Option<&str>) -> String {
+    line(
+        Kind::Result,
+        json!({ "subject": subject, "result": result, "message": message }),
+    )
+}
+
+/// A line with `diagnostic`
+pub fn diagnostic(diagnostic: &Diagnostic) -> String {
+    line(Kind::Diagnostic, diagnostic.to_json())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+
+    fn parse(line: &str) -> Value {
+        assert!(!line.contains('\n'));
+        serde_json::from_str(line).unwrap()
+    }
+
+    #[test]
+    fn test_lines() {
+        let status = parse(&message(Kind::Failure, "2 file(s) need formatting"));
+        assert_eq!(
+            status,
+            json!({ "porcelain": 1, "kind": "failure", "message": "2 file(s) need formatting" })
+        );
+
+        let pass = parse(&result("app.ncl", "pass", None));
+        assert_eq!(pass["kind"], "result");
+        assert_eq!(pass["subject"], "app.ncl");
+        assert_eq!(pass["message"], Value::Null);
+    }
+
+    #[test]
+    fn test_diagnostic() {
+        let error = Error::invalid_input("no such field");
+        let line = parse(&diagnostic(&Diagnostic::from_error(&error)));
+        assert_eq!(line["porcelain"], VERSION);
+        assert_eq!(line["kind"], "diagnostic");
+        assert_eq!(line["severity"], "error");
+        assert_eq!(line["code"], error.code());
+    }
+}
@@ -0,0 +1,409 @@
+//! Evaluation profiles
+//!
+//! Backs `bunsenite trace`: where does a slow config spend its time? Nickel
+//! evaluates lazily and shares work between imports, so one evaluation
+//! cannot attribute its time exactly. Instead, [`run`] times each part of
+//! the config on its own:
+//!
+//! - phases: parsing, evaluating and serializing the whole config, as
+//!   [`bench`](crate::bench) times them
+//! - imports: each Nickel file of the import tree evaluated on its own. A
+//!   file's *total* includes the files it imports; its *self* time leaves
+//!   out their totals.
+//! - fields: each top-level field of the config evaluated on its own, less
+//!   the time to evaluate the record's field names. A field's time includes
+//!   whatever it depends on, so fields sharing work each count it.
+//!
+//! Each time is the fastest of a number of runs. As for `bench`, the config
+//! is timed as written: overrides and `--env-prefix` are not applied. A part
+//! that fails on its own, such as a file that only evaluates as part of its
+//! importer, is reported with its error rather than failing the profile.
+//!
+//! [`Profile::to_chrome_trace`] lays the profile out as a Chrome trace,
+//! which `chrome://tracing`, Perfetto and speedscope show as a flame graph.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::profile;
+//! use bunsenite::NickelLoader;
+//!
+//! let dir = tempfile::tempdir().unwrap();
+//! let app = dir.path().join("app.ncl");
+//! std::fs::write(&app, r#"{ port = 80, base = import "base.ncl" }"#).unwrap();
+//! std::fs::write(dir.path().join("base.ncl"), "{ name = \"web\" }").unwrap();
+//!
+//! let profile = profile::run(&NickelLoader::new(), &app, 1).unwrap();
+//! assert_eq!(profile.imports.len(), 2);
+//! assert_eq!(profile.fields.len(), 2);
+//! assert!(profile.to_chrome_trace()["traceEvents"].is_array());
+//! ```
+
+use crate::bench::Phase;
+use crate::error::{Error, Result};
+use crate::imports;
+use crate::loader::{read_source, NickelLoader};
+use crate::paths;
+use crate::source;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Where evaluating a config spends its time
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    /// The config profiled
+    pub file: PathBuf,
+    /// Time of each phase on the whole config, in pipeline order
+    pub phases: Vec<(Phase, Duration)>,
+    /// The import tree, depth-first from the config, which comes first
+    pub imports: Vec<ImportTiming>,
+    /// Top-level fields of the config, slowest first
+    pub fields: Vec<FieldTiming>,
+}
+
+/// Time spent evaluating one file of the import tree
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportTiming {
+    /// The file
+    pub path: PathBuf,
+    /// Nesting below the config, which is at 0
+    pub depth: usize,
+    /// Time to evaluate the file, its imports included
+    pub total: Duration,
+    /// [`total`](Self::total) less the totals of the files it imports
+    pub self_time: Duration,
+    /// Why the file could not be evaluated on its own, if it could not
+    pub error: Option<String>,
+}
+
+/// Time spent evaluating one top-level field
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldTiming {
+    /// The field name
+    pub name: String,
+    /// Time to evaluate the field
+    pub time: Duration,
+    /// Why the field could not be evaluated on its own, if it could not
+    pub error: Option<String>,
+}
+
+/// Profile evaluating `file`, timing each part `iterations` times
+///
+/// The import graph is read on up to
+/// [`loader.threads()`](NickelLoader::threads) threads.
+///
+/// # Errors
+///
+/// Returns an error if `iterations` is 0, the file cannot be read, or the
+/// config as a whole fails to parse or evaluate
+pub fn run(loader: &NickelLoader, file: &Path, iterations: u32) -> Result<Profile> {
+    if iterations == 0 {
+        return Err(Error::invalid_input("iterations must be at least 1"));
+    }
+    let (source, name) = read_source(file)?;
+    let fastest = |source: &str, name: &str| -> Result<Duration> {
+        let mut min = Duration::MAX;
+        for _ in 0..iterations {
+            min = min.min(loader.time_phase(Phase::Evaluate, source, name)?);
+        }
+        Ok(min)
+    };
+
+    let mut phases = Vec::new();
+    for phase in [Phase::Parse, Phase::Evaluate, Phase::Serialize] {
+        let mut min = Duration::MAX;
+        for _ in 0..iterations {
+            min = min.min(loader.time_phase(phase, &source, &name)?);
+        }
+        phases.push((phase, min));
+    }
+
+    // Each file once, under the first file found importing it
+    let graph = imports::prefetch(file, loader.threads());
+    let root = imports::normalize(file);
+    let mut tree = Vec::new();
+    let mut seen = HashSet::new();
+    seen.insert(root.clone());
+    let mut stack = vec![(root, 0)];
+    while let Some((path, depth)) = stack.pop() {
+        let children: Vec<PathBuf> = graph
+            .imports
+            .iter()
+            .filter(|(from, to)| *from == path && imports::is_nickel(to) && seen.insert(to.clone()))
+            .map(|(_, to)| to.clone())
+            .collect();
+        stack.extend(children.into_iter().rev().map(|child| (child, depth + 1)));
+        tree.push((path, depth));
+    }
+    let mut timings: Vec<ImportTiming> = tree
+        .into_iter()
+        .map(|(path, depth)| {
+            let timed = std::fs::read_to_string(paths::to_open(&path))
+                .map_err(Error::from)
+                .and_then(|contents| fastest(&contents, &path.to_string_lossy()));
+            ImportTiming {
+                total: *timed.as_ref().unwrap_or(&Duration::ZERO),
+                self_time: Duration::ZERO,
+                error: timed.err().map(|e| first_line(&e)),
+                path,
+                depth,
+            }
+        })
+        .collect();
+    for at in 0..timings.len() {
+        let imported: Duration = children(&timings, at)
+            .map(|child| timings[child].total)
+            .sum();
+        timings[at].self_time = timings[at].total.saturating_sub(imported);
+    }
+
+    // Fields are reached through an import of the config by absolute path,
+    // which resolves wherever the profiling program is
+    let absolute = std::env::current_dir()
+        .map(|cwd| imports::normalize(&cwd.join(file)))
+        .unwrap_or_else(|_| file.to_path_buf());
+    let config = format!(
+        "(import {})",
+        source::string_literal(&absolute.to_string_lossy())
+    );
+    // Not under the config's own name, which Nickel would resolve the import
+    // of the config to, making the program import itself
+    let program_name = format!("{} (fields)", name);
+    let mut fields = Vec::new();
+    if let Value::Object(record) = loader.parse_string(&source, &name)? {
+        let baseline = fastest(&format!("std.record.fields {}", config), &program_name)?;
+        for field in record.keys() {
+            let program = format!("{}.{}", config, source::field_name(field));
+            let timed = fastest(&program, &program_name);
+            fields.push(FieldTiming {
+                name: field.clone(),
+                time: timed
+                    .as_ref()
+                    .map_or(Duration::ZERO, |time| time.saturating_sub(baseline)),
+                error: timed.err().map(|e| first_line(&e)),
+            });
+        }
+        fields.sort_by(|a, b| b.time.cmp(&a.time).then_with(|| a.name.cmp(&b.name)));
+    }
+
+    Ok(Profile {
+        file: file.to_path_buf(),
+        phases,
+        imports: timings,
+        fields,
+    })
+}
+
+impl Profile {
+    /// The profile in Chrome's trace event format
+    ///
+    /// Phases, imports and fields are three threads of one process, each
+    /// starting at 0. Imports nest as the import tree does, a file's imports
+    /// laid out one after the other at its start, scaled down to fit where
+    /// their totals exceed the file's. Fields follow one another, slowest
+    /// first.
+    pub fn to_chrome_trace(&self) -> Value {
+        let mut events = vec![json!({
+            "name": "process_name",
+            "ph": "M",
+            "pid": 1,
+            "args": { "name": format!("bunsenite trace {}", paths::display(&self.file)) },
+        })];
+        for (tid, name) in [(1, "phases"), (2, "imports"), (3, "fields")] {
+            events.push(json!({
+                "name": "thread_name",
+                "ph": "M",
+                "pid": 1,
+                "tid": tid,
+                "args": { "name": name },
+            }));
+        }
+
+        let mut start = 0.0;
+        for (phase, time) in &self.phases {
+            let dur = micros(*time);
+            events.push(event(phase.name(), "phase", 1, start, dur, json!({})));
+            start += dur;
+        }
+        if !self.imports.is_empty() {
+            let root = micros(self.imports[0].total);
+            self.lay_out_import(0, 0.0, root, &mut events);
+        }
+        let mut start = 0.0;
+        for field in &self.fields {
+            let dur = micros(field.time);
+            let args = json!({ "error": field.error });
+            events.push(event(&field.name, "field", 3, start, dur, args));
+            start += dur;
+        }
+
+        json!({ "traceEvents": events, "displayTimeUnit": "ms" })
+    }
+
+    /// Add the event of the import at `at` and, inside it, those of its
+    /// imports
+    fn lay_out_import(&self, at: usize, start: f64, width: f64, events: &mut Vec<Value>) {
+        let import = &self.imports[at];
+        let args = json!({
+            "total_us": micros(import.total),
+            "self_us": micros(import.self_time),
+            "error": import.error,
+        });
+        let name = paths::display(&import.path);
+        events.push(event(&name, "import", 2, start, width, args));
+
+        let children: Vec<usize> = children(&self.imports, at).collect();
+        let sum: f64 = children
+            .iter()
+            .map(|&child| micros(self.imports[child].total))
+            .sum();
+        let scale = if sum > width { width / sum } else { 1.0 };
+        let mut start = start;
+        for child in children {
+            let width = micros(self.imports[child].total) * scale;
+            self.lay_out_import(child, start, width, events);
+            start += width;
+        }
+    }
+}
+
+/// A complete ("X") trace event on thread `tid`, times in microseconds
+fn event(name: &str, category: &str, tid: u32, start: f64, dur: f64, args: Value) -> Value {
+    json!({
+        "name": name,
+        "cat": category,
+        "ph": "X",
+        "pid": 1,
+        "tid": tid,
+        "ts": start,
+        "dur": dur,
+        "args": args,
+    })
+}
+
+/// Indices of the direct imports of the import at `at`, in a depth-first
+/// tree
+fn children(imports: &[ImportTiming], at: usize) -> impl Iterator<Item = usize> + '_ {
+    let depth = imports[at].depth;
+    imports[at + 1..]
+        .iter()
+        .take_while(move |import| import.depth > depth)
+        .enumerate()
+        .filter(move |(_, import)| import.depth == depth + 1)
+        .map(move |(offset, _)| at + 1 + offset)
+}
+
+fn micros(time: Duration) -> f64 {
+    time.as_nanos() as f64 / 1e3
+}
+
+/// The first line of an error, to report it next to a timing
+fn first_line(error: &Error) -> String {
+    let message = error.to_string();
+    message.lines().next().unwrap_or_default().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn import(path: &str, depth: usize, total_us: u64) -> ImportTiming {
+        ImportTiming {
+            path: PathBuf::from(path),
+            depth,
+            total: Duration::from_micros(total_us),
+            self_time: Duration::ZERO,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn test_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = dir.path().join("app.ncl");
+        std::fs::write(
+            &app,
+            r#"let lib = import "lib.ncl" in { port = lib.port, "my name" = "web", data = import "data.json" }"#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("lib.ncl"),
+            r#"(import "base.ncl") & { port = 80 }"#,
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("base.ncl"), "{ port | default = 1 }").unwrap();
+        std::fs::write(dir.path().join("data.json"), "{}").unwrap();
+
+        let profile = run(&NickelLoader::new().with_threads(1), &app, 1).unwrap();
+        let tree: Vec<(PathBuf, usize)> = profile
+            .imports
+            .iter()
+            .map(|import| (paths::relative_to(&import.path, dir.path()), import.depth))
+            .collect();
+        assert_eq!(
+            tree,
+            [("app.ncl", 0), ("lib.ncl", 1), ("base.ncl", 2)].map(|(p, d)| (PathBuf::from(p), d))
+        );
+        assert!(profile.imports.iter().all(|i| i.error.is_none()));
+        assert!(profile.imports.iter().all(|i| i.self_time <= i.total));
+
+        let mut fields: Vec<&str> = profile.fields.iter().map(|f| f.name.as_str()).collect();
+        fields.sort();
+        assert_eq!(fields, ["data", "my name", "port"]);
+        assert!(profile.fields.iter().all(|f| f.error.is_none()));
+        assert!(run(&NickelLoader::new(), &app, 0).is_err());
+    }
+
+    #[test]
+    fn test_chrome_trace() {
+        let profile = Profile {
+            file: PathBuf::from("app.ncl"),
+            phases: vec![
+                (Phase::Parse, Duration::from_micros(10)),
+                (Phase::Evaluate, Duration::from_micros(100)),
+            ],
+            imports: vec![
+                import("app.ncl", 0, 100),
+                import("a.ncl", 1, 80),
+                import("b.ncl", 2, 10),
+                import("c.ncl", 1, 40),
+            ],
+            fields: vec![FieldTiming {
+                name: "port".to_string(),
+                time: Duration::from_micros(5),
+                error: None,
+            }],
+        };
+        let trace = profile.to_chrome_trace();
+        let events = trace["traceEvents"].as_array().unwrap();
+        let spans: Vec<(&str, f64, f64)> = events
+            .iter()
+            .filter(|e| e["ph"] == "X")
+            .map(|e| {
+                (
+                    e["name"].as_str().unwrap(),
+                    e["ts"].as_f64().unwrap(),
+                    e["dur"].as_f64().unwrap(),
+                )
+            })
+            .collect();
+
+        // a.ncl and c.ncl total 120 µs inside app.ncl's 100, so both shrink
+        let scale = 100.0 / 120.0;
+        assert_eq!(
+            spans,
+            [
+                ("parse", 0.0, 10.0),
+                ("evaluate", 10.0, 100.0),
+                ("app.ncl", 0.0, 100.0),
+                ("a.ncl", 0.0, 80.0 * scale),
+                ("b.ncl", 0.0, 10.0),
+                ("c.ncl", 80.0 * scale, 40.0 * scale),
+                ("port", 0.0, 5.0),
+            ]
+        );
+        assert_eq!(events[0]["args"]["name"], "bunsenite trace app.ncl");
+    }
+}
@@ -0,0 +1,794 @@
+//! Protobuf descriptor sets as output schemas
+//!
+//! Teams that consume configs as Protobuf messages publish the message
+//! types as a descriptor set (`protoc --include_imports
+//! --descriptor_set_out=config.pb config.proto`). A [`DescriptorSet`]
+//! decodes one and checks evaluated output against a message's canonical
+//! JSON mapping, for [`validation`](crate::validation):
+//!
+//! - fields are named by their JSON name (`buildDir`) or proto name
+//!   (`build_dir`); unknown fields are reported, `null` means unset
+//! - 64-bit integers may be numbers or decimal strings, floats may be
+//!   `"NaN"` or `"Infinity"`, bytes are base64 strings
+//! - enums are value names or numbers
+//! - repeated fields are arrays, map fields objects
+//! - well-known types such as `google.protobuf.Timestamp` and the wrapper
+//!   types use their special JSON forms
+//! - proto2 `required` fields must be present, and at most one field of a
+//!   `oneof` may be set
+//!
+//! Descriptor sets are decoded from the Protobuf wire format directly, so
+//! no `protoc` is needed at validation time.
+
+use crate::error::{Error, Result};
+use crate::json;
+use crate::validation::{self, Violation};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// Field types, as numbered in `FieldDescriptorProto.Type`
+const DOUBLE: i32 = 1;
+const FLOAT: i32 = 2;
+const INT64: i32 = 3;
+const UINT64: i32 = 4;
+const INT32: i32 = 5;
+const FIXED64: i32 = 6;
+const FIXED32: i32 = 7;
+const BOOL: i32 = 8;
+const STRING: i32 = 9;
+const GROUP: i32 = 10;
+const MESSAGE: i32 = 11;
+const BYTES: i32 = 12;
+const UINT32: i32 = 13;
+const ENUM: i32 = 14;
+const SFIXED32: i32 = 15;
+const SFIXED64: i32 = 16;
+const SINT32: i32 = 17;
+const SINT64: i32 = 18;
+
+/// `FieldDescriptorProto.Label` values
+const LABEL_REQUIRED: i32 = 2;
+const LABEL_REPEATED: i32 = 3;
+
+/// The message and enum types of a decoded `FileDescriptorSet`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DescriptorSet {
+    messages: BTreeMap<String, Message>,
+    enums: BTreeMap<String, Vec<String>>,
+    top_level: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Message {
+    fields: Vec<Field>,
+    oneofs: Vec<String>,
+    map_entry: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Field {
+    name: String,
+    json_name: String,
+    number: u64,
+    label: i32,
+    kind: i32,
+    type_name: String,
+    oneof_index: Option<usize>,
+    proto3_optional: bool,
+}
+
+/// The JSON forms of well-known types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WellKnown {
+    /// A wrapper, timestamp, duration or field mask: a scalar
+    Scalar(i32),
+    /// `Struct`, `Any` and `Empty`: an object
+    Struct,
+    /// `ListValue`: an array
+    List,
+    /// `Value`: anything
+    Value,
+}
+
+impl DescriptorSet {
+    /// Decode an encoded `FileDescriptorSet`
+    ///
+    /// # Errors
+    ///
+    /// Returns an invalid-input error if `bytes` is not a descriptor set, or
+    /// a field refers to a type it does not contain (write it with
+    /// `--include_imports`)
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let mut set = Self::default();
+        decode_fields(bytes, |number, wire| match (number, wire) {
+            (1, Wire::Bytes(file)) => set.decode_file(file),
+            _ => Ok(()),
+        })?;
+
+        for (name, message) in &set.messages {
+            for field in &message.fields {
+                let known = !matches!(field.kind, MESSAGE | GROUP | ENUM)
+                    || set.messages.contains_key(&field.type_name)
+                    || set.enums.contains_key(&field.type_name)
+                    || well_known(&field.type_name).is_some();
+                if !known {
+                    return Err(Error::invalid_input(format!(
+                        "Field {}.{} has type {}, which is not in the descriptor set \
+                         (write it with protoc --include_imports)",
+                        name, field.name, field.type_name
+                    )));
+                }
+            }
+        }
+        Ok(set)
+    }
+
+    /// Fully qualified names of the top-level messages, in file order
+    pub fn messages(&self) -> impl Iterator<Item = &str> {
+        self.top_level.iter().map(String::as_str)
+    }
+
+    /// The fully qualified message `name` (a leading `.` is allowed), or
+    /// the only top-level message outside `google.protobuf` if `name` is
+    /// `None`
+    ///
+    /// # Errors
+    ///
+    /// Returns an invalid-input error if there is no such message, or no
+    /// name is given and there is not exactly one candidate
+    pub fn resolve(&self, name: Option<&str>) -> Result<String> {
+        let listed = || self.top_level.join(", ");
+        match name {
+            Some(name) => {
+                let name = name.trim_start_matches('.');
+                if self.messages.contains_key(name) {
+                    Ok(name.to_string())
+                } else {
+                    Err(Error::invalid_input(format!(
+                        "No message named '{}' in the descriptor set (messages: {})",
+                        name,
+                        listed()
+                    )))
+                }
+            }
+            None => {
+                let mut candidates = self
+                    .messages()
+                    .filter(|name| !name.starts_with("google.protobuf."));
+                match (candidates.next(), candidates.next()) {
+                    (Some(name), None) => Ok(name.to_string()),
+                    (None, _) => Err(Error::invalid_input("The descriptor set has no messages")),
+                    _ => Err(Error::invalid_input(format!(
+                        "The descriptor set has several messages; pick one with FILE#NAME ({})",
+                        listed()
+                    ))),
+                }
+            }
+        }
+    }
+
+    /// The ways `value` fails to match the JSON mapping of the message
+    /// `name` (as returned by [`resolve`](Self::resolve))
+    pub fn check(&self, name: &str, value: &Value) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        self.check_message(name, value, "", &mut violations);
+        violations.sort_by(|a, b| a.path.cmp(&b.path));
+        violations
+    }
+
+    fn decode_file(&mut self, bytes: &[u8]) -> Result<()> {
+        let mut package = String::new();
+        let mut messages = Vec::new();
+        let mut enums = Vec::new();
+        decode_fields(bytes, |number, wire| {
+            match (number, wire) {
+                (2, Wire::Bytes(name)) => package = text(name)?,
+                (4, Wire::Bytes(message)) => messages.push(message),
+                (5, Wire::Bytes(values)) => enums.push(values),
+                _ => {}
+            }
+            Ok(())
+        })?;
+
+        for message in messages {
+            let name = self.decode_message(message, &package)?;
+            self.top_level.push(name);
+        }
+        for values in enums {
+            self.decode_enum(values, &package)?;
+        }
+        Ok(())
+    }
+
+    /// Decode a `DescriptorProto` in `scope`, returning its full name
+    fn decode_message(&mut self, bytes: &[u8], scope: &str) -> Result<String> {
+        let mut name = String::new();
+        let mut message = Message::default();
+        let mut nested = Vec::new();
+        let mut enums = Vec::new();
+        decode_fields(bytes, |number, wire| {
+            match (number, wire) {
+                (1, Wire::Bytes(bytes)) => name = text(bytes)?,
+                (2, Wire::Bytes(bytes)) => message.fields.push(decode_field(bytes)?),
+                (3, Wire::Bytes(bytes)) => nested.push(bytes),
+                (4, Wire::Bytes(bytes)) => enums.push(bytes),
+                (7, Wire::Bytes(options)) => decode_fields(options, |number, wire| {
+                    if let (7, Wire::Varint(flag)) = (number, wire) {
+                        message.map_entry = flag != 0;
+                    }
+                    Ok(())
+                })?,
+                (8, Wire::Bytes(oneof)) => message.oneofs.push(decode_name(oneof)?),
+                _ => {}
+            }
+            Ok(())
+        })?;
+
+        let full_name = qualified(scope, &name);
+        for bytes in nested {
+            self.decode_message(bytes, &full_name)?;
+        }
+        for bytes in enums {
+            self.decode_enum(bytes, &full_name)?;
+        }
+        self.messages.insert(full_name.clone(), message);
+        Ok(full_name)
+    }
+
+    fn decode_enum(&mut self, bytes: &[u8], scope: &str) -> Result<()> {
+        let mut name = String::new();
+        let mut values = Vec::new();
+        decode_fields(bytes, |number, wire| {
+            match (number, wire) {
+                (1, Wire::Bytes(bytes)) => name = text(bytes)?,
+                (2, Wire::Bytes(value)) => values.push(decode_name(value)?),
+                _ => {}
+            }
+            Ok(())
+        })?;
+        self.enums.insert(qualified(scope, &name), values);
+        Ok(())
+    }
+
+    fn check_message(&self, name: &str, value: &Value, path: &str, out: &mut Vec<Violation>) {
+        if let Some(form) = well_known(name) {
+            let matches = match form {
+                WellKnown::Scalar(kind) => return check_scalar(kind, value, path, out),
+                WellKnown::Struct => value.is_object(),
+                WellKnown::List => value.is_array(),
+                WellKnown::Value => true,
+            };
+            if !matches {
+                out.push(mismatch(name, value, path));
+            }
+            return;
+        }
+        let (Some(message), Value::Object(fields)) = (self.messages.get(name), value) else {
+            out.push(mismatch(name, value, path));
+            return;
+        };
+
+        let mut set_oneofs: Vec<Option<&str>> = vec![None; message.oneofs.len()];
+        for (key, value) in fields {
+            let field_path = json::key_path(path, key);
+            let Some(field) = message
+                .fields
+                .iter()
+                .find(|field| &field.json_name == key || &field.name == key)
+            else {
+                out.push(Violation::new(field_path, "field is not allowed"));
+                continue;
+            };
+            if value.is_null() {
+                continue;
+            }
+
+            if let Some(index) = field.oneof_index.filter(|_| !field.proto3_optional) {
+                match set_oneofs.get_mut(index) {
+                    Some(Some(other)) => out.push(Violation::new(
+                        path,
+                        format!(
+                            "sets {} and {}, which are in the same oneof {}",
+                            other, key, message.oneofs[index]
+                        ),
+                    )),
+                    Some(slot) => *slot = Some(key),
+                    None => {}
+                }
+            }
+            self.check_field(field, value, &field_path, out);
+        }
+
+        for field in &message.fields {
+            if field.label == LABEL_REQUIRED
+                && !fields.contains_key(&field.json_name)
+                && !fields.contains_key(&field.name)
+            {
+                out.push(Violation::new(
+                    json::key_path(path, &field.json_name),
+                    "required field is missing",
+                ));
+            }
+        }
+    }
+
+    fn check_field(&self, field: &Field, value: &Value, path: &str, out: &mut Vec<Violation>) {
+        if field.label != LABEL_REPEATED {
+            return self.check_single(field.kind, &field.type_name, value, path, out);
+        }
+
+        let entry = self
+            .messages
+            .get(&field.type_name)
+            .filter(|message| message.map_entry);
+        match (entry, value) {
+            (Some(entry), Value::Object(entries)) => {
+                let part = |number| entry.fields.iter().find(|field| field.number == number);
+                for (key, value) in entries {
+                    let entry_path = json::key_path(path, key);
+                    if let Some(key_field) = part(1) {
+                        check_map_key(key_field.kind, key, &entry_path, out);
+                    }
+                    if let Some(value_field) = part(2) {
+                        if !value.is_null() {
+                            let (kind, type_name) = (value_field.kind, &value_field.type_name);
+                            self.check_single(kind, type_name, value, &entry_path, out);
+                        }
+                    }
+                }
+            }
+            (None, Value::Array(items)) => {
+                for (index, item) in items.iter().enumerate() {
+                    let item_path = json::index_path(path, index);
+                    self.check_single(field.kind, &field.type_name, item, &item_path, out);
+                }
+            }
+            (Some(_), _) => out.push(mismatch("a map", value, path)),
+            (None, _) => out.push(mismatch("a list", value, path)),
+        }
+    }
+
+    fn check_single(
+        &self,
+        kind: i32,
+        type_name: &str,
+        value: &Value,
+        path: &str,
+        out: &mut Vec<Violation>,
+    ) {
+        match kind {
+            MESSAGE | GROUP => self.check_message(type_name, value, path, out),
+            ENUM => {
+                let valid = match value {
+                    Value::Null => type_name == "google.protobuf.NullValue",
+                    Value::String(name) => self
+                        .enums
+                        .get(type_name)
+                        .map_or(true, |values| values.contains(name)),
+                    number => integer(number).is_some_and(|n| in_range(INT32, n)),
+                };
+                if !valid {
+                    out.push(Violation::new(
+                        path,
+                        format!(
+                            "{} is not a value of {}",
+                            json::to_string(value, false),
+                            type_name
+                        ),
+                    ));
+                }
+            }
+            scalar => check_scalar(scalar, value, path, out),
+        }
+    }
+}
+
+/// Check a scalar field value against its JSON mapping
+fn check_scalar(kind: i32, value: &Value, path: &str, out: &mut Vec<Violation>) {
+    let valid = match kind {
+        BOOL => value.is_boolean(),
+        STRING => value.is_string(),
+        BYTES => value.as_str().is_some_and(|text| {
+            text.bytes()
+                .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'+' | b'/' | b'-' | b'_' | b'='))
+        }),
+        DOUBLE | FLOAT => {
+            let number = match value {
+                Value::Number(number) => number.as_f64(),
+                Value::String(text) => text.parse::<f64>().ok(),
+                _ => None,
+            };
+            match number {
+                Some(n) if kind == FLOAT && n.is_finite() && n.abs() > f64::from(f32::MAX) => {
+                    out.push(Violation::new(
+                        path,
+                        format!("{} is out of range for float", n),
+                    ));
+                    return;
+                }
+                Some(_) => true,
+                None => false,
+            }
+        }
+        _ => match integer(value) {
+            Some(n) if !in_range(kind, n) => {
+                out.push(Violation::new(
+                    path,
+                    format!(
+                        "{} is out of range for {}",
+                        json::to_string(value, false),
+                        kind_name(kind)
+                    ),
+                ));
+                return;
+            }
+            Some(_) => true,
+            None => false,
+        },
+    };
+    if !valid {
+        out.push(mismatch(kind_name(kind), value, path));
+    }
+}
+
+/// Check a map key, always a string in JSON, against the key field type
+fn check_map_key(kind: i32, key: &str, path: &str, out: &mut Vec<Violation>) {
+    let valid = match kind {
+        STRING => true,
+        BOOL => key == "true" || key == "false",
+        _ => key.parse::<i128>().is_ok_and(|n| in_range(kind, n)),
+    };
+    if !valid {
+        out.push(Violation::new(
+            path,
+            format!("key is not a valid {}", kind_name(kind)),
+        ));
+    }
+}
+
+/// An integer given as a JSON number or decimal string
+fn integer(value: &Value) -> Option<i128> {
+    match value {
+        Value::Number(number) => number
+            .as_i64()
+            .map(i128::from)
+            .or_else(|| number.as_u64().map(i128::from))
+            .or_else(|| {
+                let float = number.as_f64()?;
+                (float.fract() == 0.0).then_some(float as i128)
+            }),
+        Value::String(text) => text.parse().ok(),
+        _ => None,
+    }
+}
+
+/// Whether `n` fits the integer type `kind`
+fn in_range(kind: i32, n: i128) -> bool {
+    let (min, max) = match kind {
+        INT32 | SFIXED32 | SINT32 => (i32::MIN.into(), i32::MAX.into()),
+        UINT32 | FIXED32 => (0, u32::MAX.into()),
+        INT64 | SFIXED64 | SINT64 => (i64::MIN.into(), i64::MAX.into()),
+        UINT64 | FIXED64 => (0, u64::MAX.into()),
+        _ => return false,
+    };
+    (min..=max).contains(&n)
+}
+
+fn kind_name(kind: i32) -> &'static str {
+    match kind {
+        DOUBLE => "double",
+        FLOAT => "float",
+        INT64 => "int64",
+        UINT64 => "uint64",
+        INT32 => "int32",
+        FIXED64 => "fixed64",
+        FIXED32 => "fixed32",
+        BOOL => "bool",
+        STRING => "string",
+        BYTES => "bytes",
+        UINT32 => "uint32",
+        SFIXED32 => "sfixed32",
+        SFIXED64 => "sfixed64",
+        SINT32 => "sint32",
+        SINT64 => "sint64",
+        _ => "a message",
+    }
+}
+
+fn mismatch(expected: &str, value: &Value, path: &str) -> Violation {
+    let found = match value {
+        Value::Array(_) | Value::Object(_) => validation::type_name(value).to_string(),
+        scalar => json::to_string(scalar, false),
+    };
+    Violation::new(path, format!("expected {}, found {}", expected, found))
+}
+
+/// The JSON form of the well-known type `name`, if it is one
+fn well_known(name: &str) -> Option<WellKnown> {
+    Some(match name.strip_prefix("google.protobuf.")? {
+        "Timestamp" | "Duration" | "FieldMask" | "StringValue" => WellKnown::Scalar(STRING),
+        "BytesValue" => WellKnown::Scalar(BYTES),
+        "BoolValue" => WellKnown::Scalar(BOOL),
+        "DoubleValue" => WellKnown::Scalar(DOUBLE),
+        "FloatValue" => WellKnown::Scalar(FLOAT),
+        "Int64Value" => WellKnown::Scalar(INT64),
+        "UInt64Value" => WellKnown::Scalar(UINT64),
+        "Int32Value" => WellKnown::Scalar(INT32),
+        "UInt32Value" => WellKnown::Scalar(UINT32),
+        "Struct" | "Any" | "Empty" => WellKnown::Struct,
+        "ListValue" => WellKnown::List,
+        "Value" => WellKnown::Value,
+        _ => return None,
+    })
+}
+
+fn qualified(scope: &str, name: &str) -> String {
+    if scope.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", scope, name)
+    }
+}
+
+fn decode_field(bytes: &[u8]) -> Result<Field> {
+    let mut field = Field::default();
+    decode_fields(bytes, |number, wire| {
+        match (number, wire) {
+            (1, Wire::Bytes(bytes)) => field.name = text(bytes)?,
+            (3, Wire::Varint(value)) => field.number = value,
+            (4, Wire::Varint(value)) => field.label = value as i32,
+            (5, Wire::Varint(value)) => field.kind = value as i32,
+            (6, Wire::Bytes(bytes)) => {
+                field.type_name = text(bytes)?.trim_start_matches('.').into()
+            }
+            (9, Wire::Varint(value)) => field.oneof_index = Some(value as usize),
+            (10, Wire::Bytes(bytes)) => field.json_name = text(bytes)?,
+            (17, Wire::Varint(value)) => field.proto3_optional = value != 0,
+            _ => {}
+        }
+        Ok(())
+    })?;
+    if field.json_name.is_empty() {
+        field.json_name = json_name(&field.name);
+    }
+    Ok(field)
+}
+
+/// The `name` field of a message such as `OneofDescriptorProto`
+fn decode_name(bytes: &[u8]) -> Result<String> {
+    let mut name = String::new();
+    decode_fields(bytes, |number, wire| {
+        if let (1, Wire::Bytes(bytes)) = (number, wire) {
+            name = text(bytes)?;
+        }
+        Ok(())
+    })?;
+    Ok(name)
+}
+
+/// The default JSON name of a field: `build_dir` is `buildDir`
+fn json_name(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    let mut upper = false;
+    for c in name.chars() {
+        if c == '_' {
+            upper = true;
+        } else if upper {
+            out.push(c.to_ascii_uppercase());
+            upper = false;
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+fn text(bytes: &[u8]) -> Result<String> {
+    String::from_utf8(bytes.to_vec())
+        .map_err(|_| Error::invalid_input("Descriptor set has a name that is not UTF-8"))
+}
+
+/// A field value in the Protobuf wire format
+#[derive(Debug, Clone, Copy)]
+enum Wire<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+/// Visit each field of an encoded message
+fn decode_fields<'a>(
+    mut bytes: &'a [u8],
+    mut visit: impl FnMut(u64, Wire<'a>) -> Result<()>,
+) -> Result<()> {
+    while !bytes.is_empty() {
+        let key = read_varint(&mut bytes)?;
+        let wire = match key & 7 {
+            0 => Wire::Varint(read_varint(&mut bytes)?),
+            1 => {
+                take(&mut bytes, 8)?;
+                Wire::Fixed
+            }
+            2 => {
+                let len = usize::try_from(read_varint(&mut bytes)?).map_err(|_| truncated())?;
+                Wire::Bytes(take(&mut bytes, len)?)
+            }
+            5 => {
+                take(&mut bytes, 4)?;
+                Wire::Fixed
+            }
+            _ => return Err(truncated()),
+        };
+        visit(key >> 3, wire)?;
+    }
+    Ok(())
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = bytes.split_first().ok_or_else(truncated)?;
+        *bytes = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(truncated())
+}
+
+fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if len > bytes.len() {
+        return Err(truncated());
+    }
+    let (head, rest) = bytes.split_at(len);
+    *bytes = rest;
+    Ok(head)
+}
+
+fn truncated() -> Error {
+    Error::invalid_input("Truncated or malformed descriptor set")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn varint(mut n: u64) -> Vec<u8> {
+        let mut out = Vec::new();
+        loop {
+            let byte = (n & 0x7f) as u8;
+            n >>= 7;
+            if n == 0 {
+                out.push(byte);
+                return out;
+            }
+            out.push(byte | 0x80);
+        }
+    }
+
+    fn number(field: u64, value: u64) -> Vec<u8> {
+        [varint(field << 3), varint(value)].concat()
+    }
+
+    fn bytes(field: u64, value: &[u8]) -> Vec<u8> {
+        [
+            varint(field << 3 | 2),
+            varint(value.len() as u64),
+            value.to_vec(),
+        ]
+        .concat()
+    }
+
+    fn field(name: &str, id: u64, label: u64, kind: u64, type_name: &str) -> Vec<u8> {
+        let mut out = [
+            bytes(1, name.as_bytes()),
+            number(3, id),
+            number(4, label),
+            number(5, kind),
+        ]
+        .concat();
+        if !type_name.is_empty() {
+            out.extend(bytes(6, type_name.as_bytes()));
+        }
+        out
+    }
+
+    /// `app.v1.Config`, with a nested message, enum, map and oneof
+    fn descriptor_set() -> Vec<u8> {
+        let port = [bytes(1, b"Port"), bytes(2, &field("port", 1, 1, 13, ""))].concat();
+        let labels = [
+            bytes(1, b"LabelsEntry"),
+            bytes(2, &field("key", 1, 1, 9, "")),
+            bytes(2, &field("value", 2, 1, 9, "")),
+            bytes(7, &number(7, 1)),
+        ]
+        .concat();
+        let mode = [
+            bytes(1, b"Mode"),
+            bytes(2, &bytes(1, b"DEV")),
+            bytes(2, &bytes(1, b"PROD")),
+        ]
+        .concat();
+        let config = [
+            bytes(1, b"Config"),
+            bytes(2, &field("name", 1, 2, 9, "")),
+            bytes(2, &field("ports", 3, 3, 11, ".app.v1.Config.Port")),
+            bytes(2, &field("labels", 4, 3, 11, ".app.v1.Config.LabelsEntry")),
+            bytes(2, &field("mode", 5, 1, 14, ".app.v1.Config.Mode")),
+            bytes(2, &[field("image", 6, 1, 9, ""), number(9, 0)].concat()),
+            bytes(2, &[field("build_dir", 7, 1, 9, ""), number(9, 0)].concat()),
+            bytes(2, &field("created", 8, 1, 11, ".google.protobuf.Timestamp")),
+            bytes(3, &port),
+            bytes(3, &labels),
+            bytes(4, &mode),
+            bytes(8, &bytes(1, b"source")),
+        ]
+        .concat();
+        let file = [bytes(2, b"app.v1"), bytes(4, &config)].concat();
+        bytes(1, &file)
+    }
+
+    #[test]
+    fn test_checks_json_mapping() {
+        let set = DescriptorSet::decode(&descriptor_set()).unwrap();
+        let name = set.resolve(None).unwrap();
+        assert_eq!(name, "app.v1.Config");
+
+        let violations: Vec<String> = set
+            .check(
+                &name,
+                &json!({
+                    "ports": [{ "port": -1 }, { "port": "443" }],
+                    "labels": { "team": 7 },
+                    "mode": "QA",
+                    "image": "web:1.2",
+                    "buildDir": "web/",
+                    "created": 1700000000,
+                    "extra": true,
+                }),
+            )
+            .iter()
+            .map(Violation::to_string)
+            .collect();
+        assert_eq!(
+            violations,
+            vec![
+                ".: sets buildDir and image, which are in the same oneof source",
+                "created: expected string, found 1700000000",
+                "extra: field is not allowed",
+                "labels.team: expected string, found 7",
+                r#"mode: "QA" is not a value of app.v1.Config.Mode"#,
+                "name: required field is missing",
+                "ports[0].port: -1 is out of range for uint32",
+            ]
+        );
+
+        let valid = json!({ "name": "web", "build_dir": "web/", "mode": 1, "labels": {} });
+        assert!(set.check(&name, &valid).is_empty());
+    }
+
+    #[test]
+    fn test_rejects_malformed_sets() {
+        let set = descriptor_set();
+        assert!(DescriptorSet::decode(&set[..set.len() - 3]).is_err());
+
+        let dangling = bytes(
+            1,
+            &bytes(
+                4,
+                &[bytes(1, b"A"), bytes(2, &field("b", 1, 1, 11, ".B"))].concat(),
+            ),
+        );
+        let err = DescriptorSet::decode(&dangling).unwrap_err();
+        assert!(err.to_string().contains("--include_imports"));
+
+        let set = DescriptorSet::decode(&descriptor_set()).unwrap();
+        assert!(set.resolve(Some("app.v1.Missing")).is_err());
+        assert_eq!(
+            set.resolve(Some(".app.v1.Config.Port")).unwrap(),
+            "app.v1.Config.Port"
+        );
+    }
+}
@@ -0,0 +1,324 @@
+//! Field path queries and their completion
+//!
+//! Backs `bunsenite query FILE PATH` and the field path completion behind
+//! `bunsenite completions bash`, so `bunsenite query config.ncl .ser<TAB>`
+//! offers `.services`.
+//!
+//! Both evaluate as little as they can. A query evaluates only the record
+//! field it selects (see [`crate::target`]), and completion asks Nickel for
+//! the field names of the parent record (`std.record.fields`), which does not
+//! force any field value. Completing a path in a large config therefore costs
+//! little more than parsing it.
+//!
+//! Each TAB press runs `bunsenite complete-path` anew, so the CLI completes
+//! through a [`FieldCache`], which keeps the field names it found on disk
+//! and evaluates again only when the config or a file it imports changes.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::query;
+//! use bunsenite::NickelLoader;
+//!
+//! let source = "{ services = { web = { port = 80 }, worker = {} }, secrets = std.fail_with \"x\" }";
+//! let loader = NickelLoader::new();
+//! assert_eq!(
+//!     query::complete(&loader, source, "app.ncl", ".services.w").unwrap(),
+//!     [".services.web", ".services.worker"]
+//! );
+//! assert_eq!(query::query(&loader, source, "app.ncl", ".services.web.port").unwrap(), 80);
+//! ```
+
+use crate::cache::{CacheBackend, LocalBackend};
+use crate::error::{Error, Result};
+use crate::fingerprint::Fingerprint;
+use crate::json;
+use crate::loader::NickelLoader;
+use crate::target::{sha256_hex, Target};
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+
+/// Evaluate the value at `path` in a program
+///
+/// `path` uses [`json::key_path`] syntax, optionally with a leading `.`;
+/// `.` or an empty path selects the whole program. Only the longest prefix
+/// of record fields is selected during evaluation; array indices after it
+/// are applied to the evaluated value.
+///
+/// # Errors
+///
+/// Returns an invalid-input error for malformed paths and paths selecting
+/// nothing, or the evaluation error
+pub fn query(loader: &NickelLoader, source: &str, name: &str, path: &str) -> Result<Value> {
+    let path = path.trim().trim_start_matches('.');
+    if path.is_empty() {
+        return loader.parse_string(source, name);
+    }
+    let segments = crate::pattern::split(path)
+        .ok_or_else(|| Error::invalid_input(format!("Invalid query path '{}'", path)))?;
+    let fields = segments
+        .iter()
+        .position(|segment| segment.starts_with('['))
+        .unwrap_or(segments.len());
+
+    let value = if fields == 0 {
+        loader.parse_string(source, name)?
+    } else {
+        let target = Target::parse(&json::join_path(&segments[..fields]))?;
+        loader.parse_string(&target.select(source), name)?
+    };
+    json::select(&value, &json::join_path(&segments[fields..]))
+        .cloned()
+        .ok_or_else(|| Error::invalid_input(format!("Nothing at '{}' in {}", path, name)))
+}
+
+/// Complete a partially typed field path
+///
+/// Candidates keep the typed prefix (including a leading `.`), so shells can
+/// offer them as is. Returns no candidates where the parent is not a record.
+///
+/// # Errors
+///
+/// Returns the evaluation error if the parent record cannot be evaluated
+pub fn complete(
+    loader: &NickelLoader,
+    source: &str,
+    name: &str,
+    partial: &str,
+) -> Result<Vec<String>> {
+    let partial = Partial::new(partial);
+    let fields = fields(loader, source, name, partial.parent)?;
+    Ok(partial.candidates(&fields))
+}
+
+/// Field names remembered between completions, one entry per file and
+/// parent record
+///
+/// As [`MemoizedLoader`](crate::MemoizedLoader) does in memory, an entry is
+/// used while the file's [`Fingerprint`] is unchanged, so completing in the
+/// same record again reads the config and its imports but does not evaluate
+/// them.
+#[derive(Debug, Clone)]
+pub struct FieldCache {
+    local: LocalBackend,
+}
+
+impl FieldCache {
+    /// A cache in `dir`, created when first written
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            local: LocalBackend::new(dir),
+        }
+    }
+
+    /// The default cache directory
+    ///
+    /// `fields` in [`cache::home`](crate::cache::home).
+    pub fn default_dir() -> Option<PathBuf> {
+        crate::cache::home().map(|home| home.join("fields"))
+    }
+
+    /// Complete a partially typed field path in `file`, as [`complete`]
+    /// does, evaluating only if the file's fields are not remembered
+    ///
+    /// # Errors
+    ///
+    /// Returns an I/O error if `file` or one of its imports cannot be read,
+    /// or the evaluation error if the parent record cannot be evaluated
+    pub fn complete(
+        &self,
+        loader: &NickelLoader,
+        file: &Path,
+        partial: &str,
+    ) -> Result<Vec<String>> {
+        let partial = Partial::new(partial);
+        let fingerprint = Fingerprint::of(loader, file)?;
+        let key = format!(
+            "{}\0{}",
+            std::fs::canonicalize(file)?.to_string_lossy(),
+            partial.parent
+        );
+        let key = sha256_hex(key.as_bytes());
+
+        let remembered = self
+            .local
+            .get(&key)
+            .ok()
+            .flatten()
+            .and_then(|entry| serde_json::from_slice::<Value>(&entry).ok())
+            .filter(|entry| entry["fingerprint"] == fingerprint.as_str())
+            .and_then(|mut entry| serde_json::from_value(entry["fields"].take()).ok());
+        let fields = match remembered {
+            Some(fields) => fields,
+            None => {
+                let (source, name) = loader.read_file(file)?;
+                let fields = fields(loader, &source, &name, partial.parent)?;
+                let entry = json!({ "fingerprint": fingerprint.as_str(), "fields": fields });
+                // Failing to remember only costs evaluating again
+                let _ = self
+                    .local
+                    .put(&key, json::to_string(&entry, false).as_bytes());
+                fields
+            }
+        };
+        Ok(partial.candidates(&fields))
+    }
+}
+
+/// A partially typed field path, split at its last `.`
+struct Partial<'a> {
+    /// Everything up to and including the last `.`
+    prefix: &'a str,
+    /// What is typed of the last field
+    fragment: &'a str,
+    /// The path of the record being completed in, without dots around it
+    parent: &'a str,
+}
+
+impl<'a> Partial<'a> {
+    fn new(partial: &'a str) -> Self {
+        let (prefix, fragment) = match partial.rfind('.') {
+            Some(dot) => partial.split_at(dot + 1),
+            None => ("", partial),
+        };
+        let parent = prefix.trim_start_matches('.').trim_end_matches('.');
+        Self {
+            prefix,
+            fragment,
+            parent,
+        }
+    }
+
+    /// The paths to `fields` of the parent that match what is typed
+    fn candidates(&self, fields: &[String]) -> Vec<String> {
+        fields
+            .iter()
+            .map(|field| json::key_path("", field))
+            .filter(|field| field.starts_with(self.fragment))
+            .map(|field| format!("{}{}", self.prefix, field))
+            .collect()
+    }
+}
+
+/// The field names of the record at `parent`, or none if it is not a record
+fn fields(loader: &NickelLoader, source: &str, name: &str, parent: &str) -> Result<Vec<String>> {
+    let program = if parent.is_empty() {
+        format!("({}\n)", source)
+    } else {
+        match Target::parse(parent) {
+            Ok(target) => target.select(source),
+            Err(_) => return Ok(Vec::new()),
+        }
+    };
+    let fields = format!(
+        "let bunsenite_parent = {} in if std.is_record bunsenite_parent then std.record.fields bunsenite_parent else []",
+        program
+    );
+    let names = loader.parse_string(&fields, name)?;
+
+    Ok(names
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .map(str::to_string)
+        .collect())
+}
+
+/// A bash completion script for `bunsenite`, from the script clap
+/// generates for its commands and options
+///
+/// Adds completion of field paths for the path argument of `query`, by
+/// calling the hidden `complete-path` command, and leaves every other word
+/// to `generated` (which must define `_bunsenite`).
+pub fn bash_completion(generated: &str) -> String {
+    format!(
+        r#"# bunsenite bash completion
+# Install with: bunsenite completions bash > /etc/bash_completion.d/bunsenite
+{generated}
+_bunsenite_paths() {{
+    if [[ ${{COMP_WORDS[1]}} == query && $COMP_CWORD -eq 3 ]]; then
+        compopt -o nospace
+        COMPREPLY=($(bunsenite complete-path "${{COMP_WORDS[2]}}" "${{COMP_WORDS[COMP_CWORD]}}" 2>/dev/null))
+    else
+        _bunsenite "$@"
+    fi
+}}
+complete -F _bunsenite_paths -o bashdefault -o default bunsenite
+"#,
+        generated = generated.trim_end()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const SOURCE: &str = r#"{
+  services = { web = { ports = [80, 443] }, "my-db" = {}, "a b" = 1 },
+  version = 1,
+  broken = std.fail_with "not needed",
+}"#;
+
+    #[test]
+    fn test_query_paths() {
+        let loader = NickelLoader::new();
+        let query = |path| query(&loader, SOURCE, "app.ncl", path);
+        assert_eq!(query(".services.web.ports[1]").unwrap(), json!(443));
+        assert_eq!(query("version").unwrap(), json!(1));
+        assert!(query(".services.web.ports[5]").is_err());
+        assert!(query(".").is_err());
+    }
+
+    #[test]
+    fn test_bash_completion_wraps_generated() {
+        let script =
+            bash_completion("_bunsenite() {\n    :\n}\ncomplete -F _bunsenite bunsenite\n");
+        let generated = script.find("complete -F _bunsenite bunsenite").unwrap();
+        let wrapper = script.find("complete -F _bunsenite_paths").unwrap();
+        assert!(generated < wrapper);
+        assert!(script.contains("bunsenite complete-path"));
+        assert!(script.contains("        _bunsenite \"$@\""));
+    }
+
+    #[test]
+    fn test_complete_fields() {
+        let loader = NickelLoader::new();
+        let complete = |partial| complete(&loader, SOURCE, "app.ncl", partial).unwrap();
+        assert_eq!(complete(".ser"), [".services"]);
+        assert_eq!(complete(""), ["broken", "services", "version"]);
+        assert_eq!(
+            complete(".services."),
+            [".services.\"a b\"", ".services.my-db", ".services.web"]
+        );
+        assert!(complete(".version.").is_empty());
+        assert!(complete(".services[0].").is_empty());
+    }
+
+    #[test]
+    fn test_field_cache_remembers_until_changed() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = dir.path().join("app.ncl");
+        std::fs::write(&config, SOURCE).unwrap();
+        let loader = NickelLoader::new();
+        let cache = FieldCache::new(dir.path().join("fields"));
+        let complete = |partial| cache.complete(&loader, &config, partial).unwrap();
+        assert_eq!(complete(".services.m"), [".services.my-db"]);
+
+        // A remembered entry is used as is, without evaluating
+        let entries: Vec<_> = std::fs::read_dir(dir.path().join("fields"))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(entries.len(), 1);
+        let mut entry: Value =
+            serde_json::from_slice(&std::fs::read(&entries[0]).unwrap()).unwrap();
+        entry["fields"] = json!(["mine"]);
+        std::fs::write(&entries[0], entry.to_string()).unwrap();
+        assert_eq!(complete(".services.m"), [".services.mine"]);
+
+        std::fs::write(&config, "{ services = { mail = {} } }").unwrap();
+        assert_eq!(complete(".services.m"), [".services.mail"]);
+    }
+}
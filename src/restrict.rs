@@ -0,0 +1,242 @@
+//! Access-control checks on generated outputs
+//!
+//! Backs `bunsenite parse --restrict`. Rules of the form
+//! `PATTERN=deny` or `PATTERN=allow` are checked against the evaluated config
+//! before it is written, so a secret-bearing subtree cannot end up in a
+//! world-readable artifact by accident. A rule can be limited to output
+//! targets with a suffix, e.g. `secrets=deny:json,yaml`.
+//!
+//! Patterns use the [`pattern`](crate::pattern) syntax. For each output
+//! path, the most specific matching rule applies; on a tie the later rule
+//! wins, so `allow` rules can carve exceptions out of broader `deny` rules.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::restrict::Policy;
+//! use serde_json::json;
+//!
+//! let policy = Policy::parse(["secrets.*=deny", "secrets.public_key=allow"]).unwrap();
+//! let output = json!({ "secrets": { "public_key": "ssh-ed25519 ...", "token": "hunter2" } });
+//! assert_eq!(policy.violations(&output, "json"), vec!["secrets.token"]);
+//! ```
+
+use crate::error::{Error, Result};
+use crate::json;
+use crate::pattern::PathPattern;
+use serde_json::Value;
+use std::str::FromStr;
+
+/// What a rule does with matching paths
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Matching paths may be written
+    Allow,
+    /// Matching paths must not be written
+    Deny,
+}
+
+/// A single `PATTERN=ACTION[:TARGETS]` rule
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Restriction {
+    /// Paths the rule applies to
+    pub pattern: PathPattern,
+    /// What to do with them
+    pub action: Action,
+    /// Output targets the rule applies to (empty means all)
+    pub targets: Vec<String>,
+}
+
+impl Restriction {
+    fn applies_to(&self, target: &str) -> bool {
+        self.targets.is_empty() || self.targets.iter().any(|t| t == target)
+    }
+}
+
+impl FromStr for Restriction {
+    type Err = Error;
+
+    fn from_str(rule: &str) -> Result<Self> {
+        let invalid = || {
+            Error::invalid_input(format!(
+                "Invalid restriction '{}': expected PATTERN=allow|deny[:TARGET,...]",
+                rule
+            ))
+        };
+
+        let (pattern, action) = rule.rsplit_once('=').ok_or_else(invalid)?;
+        let (action, targets) = match action.split_once(':') {
+            Some((action, targets)) => (
+                action,
+                targets
+                    .split(',')
+                    .map(|t| t.trim().to_ascii_lowercase())
+                    .filter(|t| !t.is_empty())
+                    .collect(),
+            ),
+            None => (action, Vec::new()),
+        };
+        let action = match action.trim() {
+            "allow" => Action::Allow,
+            "deny" => Action::Deny,
+            _ => return Err(invalid()),
+        };
+
+        Ok(Self {
+            pattern: pattern.parse()?,
+            action,
+            targets,
+        })
+    }
+}
+
+/// An ordered set of restrictions
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Policy {
+    rules: Vec<Restriction>,
+}
+
+impl Policy {
+    /// Parse rules in order
+    ///
+    /// # Errors
+    ///
+    /// Returns an invalid-input error for the first malformed rule
+    pub fn parse<I, S>(rules: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let rules = rules
+            .into_iter()
+            .map(|rule| rule.as_ref().parse())
+            .collect::<Result<_>>()?;
+        Ok(Self { rules })
+    }
+
+    /// Whether the policy has no rules
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Whether a path may be written to a target
+    pub fn allows(&self, path: &str, target: &str) -> bool {
+        let mut best: Option<&Restriction> = None;
+        for rule in &self.rules {
+            if rule.applies_to(target)
+                && rule.pattern.matches(path)
+                && best.map_or(true, |b| {
+                    rule.pattern.specificity() >= b.pattern.specificity()
+                })
+            {
+                best = Some(rule);
+            }
+        }
+        best.map_or(true, |rule| rule.action == Action::Allow)
+    }
+
+    /// Denied paths present in an output, in path order
+    ///
+    /// Only leaves (scalars and empty records or arrays) are reported, so an
+    /// `allow` exception inside a denied subtree is honoured.
+    pub fn violations(&self, output: &Value, target: &str) -> Vec<String> {
+        let mut violations = Vec::new();
+        let mut stack = vec![(String::new(), output)];
+
+        while let Some((path, value)) = stack.pop() {
+            match value {
+                Value::Object(map) if !map.is_empty() => {
+                    for (key, item) in map.iter().rev() {
+                        stack.push((json::key_path(&path, key), item));
+                    }
+                }
+                Value::Array(items) if !items.is_empty() => {
+                    for (index, item) in items.iter().enumerate().rev() {
+                        stack.push((json::index_path(&path, index), item));
+                    }
+                }
+                _ => {
+                    if !path.is_empty() && !self.allows(&path, target) {
+                        violations.push(path);
+                    }
+                }
+            }
+        }
+
+        violations
+    }
+
+    /// Fail if an output contains denied paths
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::RestrictedOutput`] listing the denied paths
+    pub fn check(&self, output: &Value, target: &str) -> Result<()> {
+        let paths = self.violations(output, target);
+        if paths.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::RestrictedOutput {
+                target: target.to_string(),
+                paths,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_rules() {
+        let rule: Restriction = "secrets.*=deny:JSON, yaml".parse().unwrap();
+        assert_eq!(rule.action, Action::Deny);
+        assert_eq!(rule.targets, vec!["json", "yaml"]);
+
+        assert!("secrets".parse::<Restriction>().is_err());
+        assert!("secrets=maybe".parse::<Restriction>().is_err());
+        assert!("=deny".parse::<Restriction>().is_err());
+    }
+
+    #[test]
+    fn test_deny_reports_leaves() {
+        let policy = Policy::parse(["secrets=deny"]).unwrap();
+        let output = json!({ "name": "x", "secrets": { "a": 1, "b": [], "c": [true] } });
+        assert_eq!(
+            policy.violations(&output, "json"),
+            vec!["secrets.a", "secrets.b", "secrets.c[0]"]
+        );
+        assert!(policy.check(&json!({ "name": "x" }), "json").is_ok());
+    }
+
+    #[test]
+    fn test_allow_exceptions_and_targets() {
+        let policy = Policy::parse([
+            "**.password=deny",
+            "dev.password=allow",
+            "internal=deny:yaml",
+        ])
+        .unwrap();
+        let output = json!({
+            "dev": { "password": "dev" },
+            "prod": { "password": "secret" },
+            "internal": { "x": 1 },
+        });
+
+        assert_eq!(policy.violations(&output, "json"), vec!["prod.password"]);
+        assert_eq!(
+            policy.violations(&output, "yaml"),
+            vec!["internal.x", "prod.password"]
+        );
+    }
+
+    #[test]
+    fn test_check_error_lists_paths() {
+        let policy = Policy::parse(["token=deny"]).unwrap();
+        let err = policy.check(&json!({ "token": "t" }), "json").unwrap_err();
+        assert!(err.to_string().contains("token"));
+        assert!(err.is_recoverable());
+    }
+}
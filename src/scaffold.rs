@@ -0,0 +1,268 @@
+//! Starter projects
+//!
+//! Backs `bunsenite init`: a [`Template`] is a small project, embedded in
+//! the binary so it can be created offline, made of
+//!
+//! - `main.ncl`, the entry point
+//! - `contracts.ncl`, the contracts `main.ncl` applies
+//! - `overlays/`, configs to merge over the entry point, e.g. with
+//!   `bunsenite merge main.ncl overlays/prod.ncl`
+//! - `bunsenite.toml`, the project manifest naming the above
+//!
+//! Every `{{name}}` in a template becomes the project's name.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::scaffold::{self, Template};
+//! use bunsenite::NickelLoader;
+//!
+//! let dir = tempfile::tempdir().unwrap();
+//! let written = scaffold::init(dir.path(), Template::Service, Some("billing"), false).unwrap();
+//! assert!(written.contains(&dir.path().join("bunsenite.toml")));
+//!
+//! let config = NickelLoader::new().parse_file(&dir.path().join("main.ncl")).unwrap();
+//! assert_eq!(config["name"], "billing");
+//! ```
+
+use crate::error::{Error, Result};
+use crate::output;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Name of the project manifest
+pub const MANIFEST: &str = "bunsenite.toml";
+
+/// A starter project
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Template {
+    /// Kubernetes manifests: a Deployment and a Service
+    K8s,
+    /// A service's configuration: server, logging and database settings
+    #[default]
+    Service,
+    /// Helpers for other configs to import, with tests
+    Library,
+}
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s {
+            "k8s" | "kubernetes" => Ok(Template::K8s),
+            "service" => Ok(Template::Service),
+            "library" | "lib" => Ok(Template::Library),
+            other => Err(format!(
+                "unknown template '{}' (expected k8s, service or library)",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Template::K8s => "k8s",
+            Template::Service => "service",
+            Template::Library => "library",
+        })
+    }
+}
+
+impl Template {
+    /// The template's files, by path, with `{{name}}` placeholders
+    fn sources(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Template::K8s => &[
+                ("main.ncl", include_str!("../templates/k8s/main.ncl")),
+                (
+                    "contracts.ncl",
+                    include_str!("../templates/k8s/contracts.ncl"),
+                ),
+                (
+                    "overlays/prod.ncl",
+                    include_str!("../templates/k8s/overlays/prod.ncl"),
+                ),
+            ],
+            Template::Service => &[
+                ("main.ncl", include_str!("../templates/service/main.ncl")),
+                (
+                    "contracts.ncl",
+                    include_str!("../templates/service/contracts.ncl"),
+                ),
+                (
+                    "overlays/prod.ncl",
+                    include_str!("../templates/service/overlays/prod.ncl"),
+                ),
+            ],
+            Template::Library => &[
+                ("main.ncl", include_str!("../templates/library/main.ncl")),
+                (
+                    "contracts.ncl",
+                    include_str!("../templates/library/contracts.ncl"),
+                ),
+                (
+                    "overlays/dev.ncl",
+                    include_str!("../templates/library/overlays/dev.ncl"),
+                ),
+            ],
+        }
+    }
+
+    /// The project's files, by path relative to its directory, for a
+    /// project named `name`
+    ///
+    /// # Errors
+    ///
+    /// Returns an invalid-input error unless `name` is made of letters,
+    /// digits, `-`, `_` and `.`
+    pub fn files(self, name: &str) -> Result<Vec<(&'static str, String)>> {
+        if !is_project_name(name) {
+            return Err(Error::invalid_input(format!(
+                "Invalid project name '{}': use letters, digits, '-', '_' and '.'",
+                name
+            )));
+        }
+        let mut files: Vec<(&'static str, String)> = self
+            .sources()
+            .iter()
+            .map(|(path, source)| (*path, source.replace("{{name}}", name)))
+            .collect();
+        files.push((MANIFEST, self.manifest(name)));
+        Ok(files)
+    }
+
+    fn manifest(self, name: &str) -> String {
+        format!(
+            "# bunsenite project manifest\n\
+             [project]\n\
+             name = \"{}\"\n\
+             template = \"{}\"\n\
+             entry = \"main.ncl\"\n\
+             contracts = \"contracts.ncl\"\n\
+             overlays = \"overlays\"\n",
+            name, self
+        )
+    }
+}
+
+/// Create a project from `template` in `dir`, returning the files written
+///
+/// The project is named `name`, or after `dir`. `dir` is created if
+/// needed; existing files are only replaced with `force`.
+///
+/// # Errors
+///
+/// Returns an invalid-input error if the name is not valid (see
+/// [`Template::files`]) or, without `force`, any of the files exists; or
+/// an I/O error
+pub fn init(
+    dir: &Path,
+    template: Template,
+    name: Option<&str>,
+    force: bool,
+) -> Result<Vec<PathBuf>> {
+    let name = match name {
+        Some(name) => name.to_string(),
+        None => default_name(dir)?,
+    };
+    let files = template.files(&name)?;
+
+    let existing: Vec<String> = files
+        .iter()
+        .filter(|(path, _)| dir.join(path).exists())
+        .map(|(path, _)| dir.join(path).display().to_string())
+        .collect();
+    if !existing.is_empty() && !force {
+        return Err(Error::invalid_input(format!(
+            "Not overwriting {} (use --force to replace)",
+            existing.join(", ")
+        )));
+    }
+
+    let mut written = Vec::with_capacity(files.len());
+    for (path, contents) in files {
+        let path = dir.join(path);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        output::write_atomic(&path, contents.as_bytes(), None)?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+/// The name of the project in `dir`: the directory's own name
+fn default_name(dir: &Path) -> Result<String> {
+    let absolute = match dir.canonicalize() {
+        Ok(path) => path,
+        Err(_) => std::env::current_dir()?.join(dir),
+    };
+    absolute
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .ok_or_else(|| {
+            Error::invalid_input(format!(
+                "Cannot name a project after {}; give a name",
+                dir.display()
+            ))
+        })
+}
+
+/// Whether `name` can go into a template unquoted
+fn is_project_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NickelLoader;
+
+    #[test]
+    fn test_templates_evaluate() {
+        let loader = NickelLoader::new();
+        for template in [Template::K8s, Template::Service, Template::Library] {
+            let dir = tempfile::tempdir().unwrap();
+            let written = init(dir.path(), template, Some("acme"), false).unwrap();
+            assert_eq!(written.len(), 4);
+
+            let main = dir.path().join("main.ncl");
+            loader
+                .parse_file(&main)
+                .unwrap_or_else(|e| panic!("{}: {}", template, e));
+            let overlay = written
+                .iter()
+                .find(|path| path.starts_with(dir.path().join("overlays")))
+                .unwrap();
+            loader
+                .merge_files(&[main, overlay.clone()])
+                .unwrap_or_else(|e| panic!("{} overlay: {}", template, e));
+
+            let manifest = std::fs::read_to_string(dir.path().join(MANIFEST)).unwrap();
+            assert!(manifest.contains(&format!("template = \"{}\"", template)));
+            assert!(manifest.contains("name = \"acme\""));
+        }
+    }
+
+    #[test]
+    fn test_init_guards() {
+        let dir = tempfile::tempdir().unwrap();
+        let project = dir.path().join("web-app");
+        init(&project, Template::K8s, None, false).unwrap();
+        let main = std::fs::read_to_string(project.join("main.ncl")).unwrap();
+        assert!(main.contains("name = \"web-app\""));
+
+        assert!(init(&project, Template::K8s, None, false).is_err());
+        assert!(init(&project, Template::Library, None, true).is_ok());
+        assert!(init(dir.path(), Template::K8s, Some("a\"b"), false).is_err());
+        assert_eq!("kubernetes".parse::<Template>(), Ok(Template::K8s));
+        assert!("helm".parse::<Template>().is_err());
+    }
+}
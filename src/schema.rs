@@ -0,0 +1,616 @@
+//! Record contract inference from example documents, and JSON Schema from
+//! contracts
+//!
+//! Backs `bunsenite infer-schema`: given one or more example configs, infers
+//! a Nickel contract describing their shape, to jump-start schema adoption for
+//! legacy configs. Fields missing from some samples become `optional`, and
+//! strings drawn from a small set of repeated values become enums.
+//!
+//! The other way round, [`to_json_schema`] backs `bunsenite schema`: the
+//! contracts, doc strings, `optional` flags and defaults a config declares
+//! ([`NickelLoader::extract_metadata`]) become a JSON Schema (draft 2020-12)
+//! for editors and APIs to validate against. The std contracts with a JSON
+//! Schema counterpart (`Number`, `String`, `Bool`, `Array T`, `{ _ : T }`,
+//! enums, `std.number.Integer`/`Nat`/`PosNat`, `std.string.NonEmpty`) map
+//! to it; other contracts, whose checks are Nickel code, are named in a
+//! `$comment` and left unchecked. Fields are required unless `optional` or
+//! given a default.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::schema::Shape;
+//! use serde_json::json;
+//!
+//! let samples = [json!({ "port": 80, "env": "prod" }), json!({ "port": 443, "env": "prod", "tls": true })];
+//! let contract = Shape::infer(&samples).to_nickel(5);
+//! assert!(contract.contains("port | std.number.Integer"));
+//! assert!(contract.contains("tls | Bool | optional"));
+//! ```
+
+use crate::docs::FieldDoc;
+use crate::error::{Error, Result};
+use crate::loader::NickelLoader;
+use crate::source::{field_name, string_literal};
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+use std::path::Path;
+
+/// JSON Schema dialect [`to_json_schema`] writes
+pub const JSON_SCHEMA_DIALECT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+/// Default maximum number of distinct values for a string enum
+pub const DEFAULT_ENUM_THRESHOLD: usize = 5;
+
+/// Inferred shape of a value across samples
+///
+/// Each kind of JSON value seen is tracked separately, so shapes from
+/// different samples can be merged without losing information.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Shape {
+    /// Number of values this shape was inferred from
+    pub count: usize,
+    /// Whether `null` was seen
+    pub null: bool,
+    /// Whether a boolean was seen
+    pub bool: bool,
+    /// Numbers seen, if any
+    pub number: Option<NumberShape>,
+    /// Strings seen, if any
+    pub string: Option<StringShape>,
+    /// Shape of array elements, if an array was seen
+    pub array: Option<Box<Shape>>,
+    /// Record fields, if a record was seen
+    pub record: Option<RecordShape>,
+}
+
+/// Numbers seen for a value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NumberShape {
+    /// Whether every number was an integer
+    pub integer: bool,
+}
+
+/// Strings seen for a value
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StringShape {
+    /// Number of strings seen
+    pub count: usize,
+    /// Distinct values, up to the tracking limit
+    pub values: BTreeSet<String>,
+    /// Whether more distinct values were seen than are tracked
+    pub overflow: bool,
+}
+
+/// Fields of a record across samples
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RecordShape {
+    /// Number of records seen
+    pub count: usize,
+    /// Shape of each field; a field is optional if `shape.count < count`
+    pub fields: BTreeMap<String, Shape>,
+}
+
+/// Distinct strings tracked per value before giving up on an enum
+const MAX_TRACKED_STRINGS: usize = 64;
+
+impl Shape {
+    /// Infer the shape shared by a set of samples
+    pub fn infer(samples: &[Value]) -> Self {
+        let mut shape = Shape::default();
+        for sample in samples {
+            shape.observe(sample);
+        }
+        shape
+    }
+
+    /// Merge a value into this shape
+    pub fn observe(&mut self, value: &Value) {
+        self.count += 1;
+        match value {
+            Value::Null => self.null = true,
+            Value::Bool(_) => self.bool = true,
+            Value::Number(n) => {
+                let integer = n.is_i64() || n.is_u64();
+                let number = self.number.get_or_insert(NumberShape { integer });
+                number.integer &= integer;
+            }
+            Value::String(s) => {
+                let strings = self.string.get_or_insert_with(StringShape::default);
+                strings.count += 1;
+                if strings.values.len() < MAX_TRACKED_STRINGS {
+                    strings.values.insert(s.clone());
+                } else if !strings.values.contains(s) {
+                    strings.overflow = true;
+                }
+            }
+            Value::Array(items) => {
+                let element = self.array.get_or_insert_with(Box::default);
+                for item in items {
+                    element.observe(item);
+                }
+            }
+            Value::Object(map) => {
+                let record = self.record.get_or_insert_with(RecordShape::default);
+                record.count += 1;
+                for (key, item) in map {
+                    record.fields.entry(key.clone()).or_default().observe(item);
+                }
+            }
+        }
+    }
+
+    /// Render the shape as a Nickel contract file
+    ///
+    /// Record fields holding strings with at most `enum_threshold` distinct
+    /// values, each seen more than once on average, become enums; `0`
+    /// disables enum inference.
+    pub fn to_nickel(&self, enum_threshold: usize) -> String {
+        let mut out = String::new();
+        self.write_contract(&mut out, 0, enum_threshold);
+        out.push('\n');
+        out
+    }
+
+    fn kinds(&self) -> usize {
+        [
+            self.null,
+            self.bool,
+            self.number.is_some(),
+            self.string.is_some(),
+            self.array.is_some(),
+            self.record.is_some(),
+        ]
+        .into_iter()
+        .filter(|&seen| seen)
+        .count()
+    }
+
+    fn write_contract(&self, out: &mut String, indent: usize, enum_threshold: usize) {
+        if self.kinds() != 1 {
+            // Mixed (or no) kinds have no single std contract
+            out.push_str("Dyn");
+            return;
+        }
+
+        if self.null {
+            out.push_str("Dyn");
+        } else if self.bool {
+            out.push_str("Bool");
+        } else if let Some(number) = self.number {
+            out.push_str(if number.integer {
+                "std.number.Integer"
+            } else {
+                "Number"
+            });
+        } else if let Some(strings) = &self.string {
+            if strings.is_enum(enum_threshold) {
+                let tags: Vec<String> = strings.values.iter().map(|v| enum_tag(v)).collect();
+                let _ = write!(out, "std.enum.TagOrString | [| {} |]", tags.join(", "));
+            } else {
+                out.push_str("String");
+            }
+        } else if let Some(element) = &self.array {
+            // Contract composition with `|` is not allowed inside a type, so
+            // array elements never become enums
+            out.push_str("Array ");
+            if element.kinds() == 1 && (element.record.is_some() || element.array.is_some()) {
+                out.push('(');
+                element.write_contract(out, indent, 0);
+                out.push(')');
+            } else {
+                element.write_contract(out, indent, 0);
+            }
+        } else if let Some(record) = &self.record {
+            record.write_contract(out, indent, enum_threshold);
+        }
+    }
+}
+
+impl StringShape {
+    fn is_enum(&self, threshold: usize) -> bool {
+        !self.overflow
+            && !self.values.is_empty()
+            && self.values.len() <= threshold
+            && self.count > self.values.len()
+    }
+}
+
+impl RecordShape {
+    fn write_contract(&self, out: &mut String, indent: usize, enum_threshold: usize) {
+        if self.fields.is_empty() {
+            out.push_str("{}");
+            return;
+        }
+
+        out.push_str("{\n");
+        for (name, field) in &self.fields {
+            out.push_str(&"  ".repeat(indent + 1));
+            out.push_str(&field_name(name));
+            out.push_str(" | ");
+            field.write_contract(out, indent + 1, enum_threshold);
+            if field.count < self.count {
+                out.push_str(" | optional");
+            }
+            out.push_str(",\n");
+        }
+        out.push_str(&"  ".repeat(indent));
+        out.push('}');
+    }
+}
+
+/// Load a data document, such as an example config
+///
+/// JSON is read directly; any other format Nickel can import (YAML, TOML,
+/// Nickel) is evaluated through `loader`.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or parsed
+pub fn load_sample(loader: &NickelLoader, path: &Path) -> Result<Value> {
+    let name = path.display().to_string();
+    if path.extension().is_some_and(|ext| ext == "json") {
+        let contents = std::fs::read_to_string(path)?;
+        return serde_json::from_str(&contents)
+            .map_err(|e| Error::parse_error(name, format!("Invalid JSON: {}", e)));
+    }
+
+    // On Windows, canonical paths are verbatim (`\\?\C:\...`); keep that
+    // form only where the path needs it
+    let absolute = crate::paths::to_open(&path.canonicalize()?);
+    let import = format!("import {}", string_literal(&absolute.display().to_string()));
+    // Not under the sample's own name, which Nickel would resolve the import
+    // to, making the program import itself
+    loader.parse_string(&import, &format!("{} (sample)", name))
+}
+
+/// JSON Schema of a config's fields, titled `title`
+///
+/// `fields` are as [`NickelLoader::extract_metadata`] returns them; nested
+/// paths become nested `properties`, and array elements `items`.
+pub fn to_json_schema(title: &str, fields: &[FieldDoc]) -> Value {
+    let mut root = SchemaNode::default();
+    for field in fields {
+        let Some(segments) = crate::pattern::split(&field.path) else {
+            continue;
+        };
+        let mut node = &mut root;
+        for segment in segments {
+            node = if segment.starts_with('[') {
+                node.items.get_or_insert_with(Box::default).as_mut()
+            } else {
+                node.properties.entry(segment).or_default()
+            };
+        }
+        if node.field.is_none() {
+            node.field = Some(field);
+        }
+    }
+
+    let mut schema = root.to_json_schema();
+    let object = schema.as_object_mut().expect("schemas are objects");
+    object.insert("$schema".to_string(), json!(JSON_SCHEMA_DIALECT));
+    object.insert("title".to_string(), json!(title));
+    object.entry("type").or_insert_with(|| json!("object"));
+    schema
+}
+
+/// A value in a config, with the metadata of the field holding it and the
+/// values below it
+#[derive(Default)]
+struct SchemaNode<'a> {
+    field: Option<&'a FieldDoc>,
+    properties: BTreeMap<String, SchemaNode<'a>>,
+    items: Option<Box<SchemaNode<'a>>>,
+}
+
+impl SchemaNode<'_> {
+    fn required(&self) -> bool {
+        !matches!(self.field, Some(field) if field.optional || field.default.is_some())
+    }
+
+    fn to_json_schema(&self) -> Value {
+        let mut schema = Map::new();
+        if let Some(field) = self.field {
+            if let Some(doc) = &field.doc {
+                schema.insert("description".to_string(), json!(doc));
+            }
+            let mut checks = Vec::new();
+            let mut unchecked = Vec::new();
+            for contract in &field.contracts {
+                match contract_schema(contract) {
+                    Some(check) => checks.push(check),
+                    None => unchecked.push(contract.as_str()),
+                }
+            }
+            schema.extend(merge_checks(checks));
+            if !unchecked.is_empty() {
+                schema.insert(
+                    "$comment".to_string(),
+                    json!(format!("Nickel contracts: {}", unchecked.join(", "))),
+                );
+            }
+            if let Some(default) = &field.default {
+                schema.insert("default".to_string(), default.clone());
+            }
+        }
+
+        if !self.properties.is_empty() {
+            schema.entry("type").or_insert_with(|| json!("object"));
+            let properties: Map<String, Value> = self
+                .properties
+                .iter()
+                .map(|(name, node)| (name.clone(), node.to_json_schema()))
+                .collect();
+            let required: Vec<&String> = self
+                .properties
+                .iter()
+                .filter(|(_, node)| node.required())
+                .map(|(name, _)| name)
+                .collect();
+            schema.insert("properties".to_string(), Value::Object(properties));
+            if !required.is_empty() {
+                schema.insert("required".to_string(), json!(required));
+            }
+        }
+        if let Some(items) = &self.items {
+            schema.entry("type").or_insert_with(|| json!("array"));
+            // Elements' own fields say more than `Array Dyn`
+            let slot = schema.entry("items").or_insert_with(|| json!({}));
+            if slot.as_object().is_some_and(Map::is_empty) {
+                *slot = items.to_json_schema();
+            }
+        }
+        Value::Object(schema)
+    }
+}
+
+/// `checks` as one schema: their keywords together, or an `allOf` if two
+/// of them set the same keyword
+fn merge_checks(checks: Vec<Map<String, Value>>) -> Map<String, Value> {
+    let mut merged = Map::new();
+    for check in &checks {
+        if check.keys().any(|key| merged.contains_key(key)) {
+            let mut all_of = Map::new();
+            all_of.insert("allOf".to_string(), json!(checks));
+            return all_of;
+        }
+        merged.extend(check.clone());
+    }
+    merged
+}
+
+/// The JSON Schema for Nickel contract or type `contract`, as Nickel prints
+/// it; `None` if it has none
+fn contract_schema(contract: &str) -> Option<Map<String, Value>> {
+    let contract = contract.trim();
+    let schema = match contract {
+        "Dyn" => json!({}),
+        "Number" => json!({ "type": "number" }),
+        "Integer" | "std.number.Integer" => json!({ "type": "integer" }),
+        "std.number.Nat" => json!({ "type": "integer", "minimum": 0 }),
+        "std.number.PosNat" => json!({ "type": "integer", "minimum": 1 }),
+        "String" | "std.enum.TagOrString" => json!({ "type": "string" }),
+        "std.string.NonEmpty" => json!({ "type": "string", "minLength": 1 }),
+        "Bool" => json!({ "type": "boolean" }),
+        _ => {
+            if contract.contains("->") {
+                return None;
+            }
+            if let Some(inner) = contract
+                .strip_prefix('(')
+                .and_then(|rest| rest.strip_suffix(')'))
+            {
+                return contract_schema(inner);
+            }
+            if let Some(element) = contract.strip_prefix("Array ") {
+                let items = contract_schema(element).unwrap_or_default();
+                json!({ "type": "array", "items": items })
+            } else if let Some(body) = contract
+                .strip_prefix('{')
+                .and_then(|rest| rest.strip_suffix('}'))
+            {
+                let element = body
+                    .trim()
+                    .strip_prefix('_')?
+                    .trim_start()
+                    .strip_prefix(':')?;
+                let values = contract_schema(element).unwrap_or_default();
+                json!({ "type": "object", "additionalProperties": values })
+            } else if let Some(body) = contract
+                .strip_prefix("[|")
+                .and_then(|rest| rest.strip_suffix("|]"))
+            {
+                let tags = body
+                    .split(',')
+                    .map(|tag| enum_value(tag.trim()))
+                    .collect::<Option<Vec<String>>>()?;
+                json!({ "enum": tags })
+            } else {
+                return None;
+            }
+        }
+    };
+    match schema {
+        Value::Object(schema) => Some(schema),
+        _ => None,
+    }
+}
+
+/// The string an enum tag like `'prod` or `'"eu-west"` exports as
+fn enum_value(tag: &str) -> Option<String> {
+    let name = tag.strip_prefix('\'')?;
+    if name.starts_with('"') {
+        serde_json::from_str(name).ok()
+    } else if !name.is_empty() && !name.contains(char::is_whitespace) {
+        Some(name.to_string())
+    } else {
+        None
+    }
+}
+
+fn enum_tag(value: &str) -> String {
+    format!("'{}", field_name(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_infers_field_types_and_optionality() {
+        let samples = [
+            json!({ "name": "api", "port": 8080, "ratio": 0.5, "tags": ["a"] }),
+            json!({ "name": "web", "port": 80, "ratio": 1 }),
+        ];
+        let contract = Shape::infer(&samples).to_nickel(DEFAULT_ENUM_THRESHOLD);
+
+        assert!(contract.contains("  name | String,\n"));
+        assert!(contract.contains("  port | std.number.Integer,\n"));
+        assert!(contract.contains("  ratio | Number,\n"));
+        assert!(contract.contains("  tags | Array String | optional,\n"));
+    }
+
+    #[test]
+    fn test_low_cardinality_strings_become_enums() {
+        let samples = [
+            json!({ "env": "prod" }),
+            json!({ "env": "dev" }),
+            json!({ "env": "prod" }),
+        ];
+        let contract = Shape::infer(&samples).to_nickel(DEFAULT_ENUM_THRESHOLD);
+        assert!(contract.contains("env | std.enum.TagOrString | [| 'dev, 'prod |]"));
+
+        assert!(Shape::infer(&samples).to_nickel(0).contains("env | String"));
+    }
+
+    #[test]
+    fn test_nested_records_and_quoted_names() {
+        let samples = [json!({ "db": { "host": "localhost" }, "my key": null })];
+        let contract = Shape::infer(&samples).to_nickel(DEFAULT_ENUM_THRESHOLD);
+        assert!(contract.contains("  db | {\n    host | String,\n  },\n"));
+        assert!(contract.contains("  \"my key\" | Dyn,\n"));
+    }
+
+    #[test]
+    fn test_mixed_kinds_fall_back_to_dyn() {
+        let contract = Shape::infer(&[json!({ "x": 1 }), json!({ "x": "one" })]).to_nickel(5);
+        assert!(contract.contains("x | Dyn"));
+    }
+
+    #[test]
+    fn test_inferred_contract_accepts_samples() {
+        let sample = json!({ "name": "api", "port": 8080, "env": ["a", "a"] });
+        let contract = Shape::infer(&[sample]).to_nickel(DEFAULT_ENUM_THRESHOLD);
+        let source = format!(
+            "{{ name = \"api\", port = 8080, env = [\"a\", \"a\"] }} | {}",
+            contract
+        );
+        assert!(NickelLoader::new()
+            .parse_string(&source, "test.ncl")
+            .is_ok());
+    }
+
+    #[test]
+    fn test_load_sample_formats() {
+        let dir = tempfile::tempdir().unwrap();
+        let json_path = dir.path().join("a.json");
+        let yaml_path = dir.path().join("b.yaml");
+        std::fs::write(&json_path, r#"{ "port": 80 }"#).unwrap();
+        std::fs::write(&yaml_path, "port: 443\n").unwrap();
+
+        let loader = NickelLoader::new();
+        assert_eq!(
+            load_sample(&loader, &json_path).unwrap(),
+            json!({ "port": 80 })
+        );
+        assert_eq!(
+            load_sample(&loader, &yaml_path).unwrap(),
+            json!({ "port": 443 })
+        );
+    }
+
+    #[test]
+    fn test_json_schema() {
+        let field = |path: &str, contracts: &[&str]| FieldDoc {
+            path: path.to_string(),
+            doc: None,
+            contracts: contracts.iter().map(|c| c.to_string()).collect(),
+            optional: false,
+            default: None,
+        };
+        let fields = vec![
+            FieldDoc {
+                doc: Some("Listen port".to_string()),
+                default: Some(json!(80)),
+                ..field("port", &["std.number.Nat"])
+            },
+            field("env", &["std.enum.TagOrString", "[| 'dev, 'prod |]"]),
+            FieldDoc {
+                optional: true,
+                ..field("labels", &["{ _ : String }"])
+            },
+            field("servers", &["Array Dyn"]),
+            field("servers[0].host", &["String", "std.string.NonEmpty"]),
+            field("check", &["Checks.Probe"]),
+        ];
+        assert_eq!(
+            to_json_schema("app.ncl", &fields),
+            json!({
+                "$schema": JSON_SCHEMA_DIALECT,
+                "title": "app.ncl",
+                "type": "object",
+                "properties": {
+                    "port": {
+                        "description": "Listen port",
+                        "type": "integer",
+                        "minimum": 0,
+                        "default": 80,
+                    },
+                    "env": { "type": "string", "enum": ["dev", "prod"] },
+                    "labels": {
+                        "type": "object",
+                        "additionalProperties": { "type": "string" },
+                    },
+                    "servers": {
+                        "type": "array",
+                        "items": {
+                            "type": "object",
+                            "properties": {
+                                "host": {
+                                    "allOf": [
+                                        { "type": "string" },
+                                        { "type": "string", "minLength": 1 },
+                                    ],
+                                },
+                            },
+                            "required": ["host"],
+                        },
+                    },
+                    "check": { "$comment": "Nickel contracts: Checks.Probe" },
+                },
+                "required": ["check", "env", "servers"],
+            })
+        );
+    }
+
+    #[test]
+    fn test_json_schema_from_contracts() {
+        let source = r#"
+            let Server = { host | String, port | std.number.Nat | default = 80 } in
+            { server | Server = { host = "web" }, debug | Bool | optional }
+        "#;
+        let fields = NickelLoader::new()
+            .extract_metadata(source, "app.ncl")
+            .unwrap();
+        let schema = to_json_schema("app.ncl", &fields);
+        assert_eq!(schema["required"], json!(["server"]));
+        assert_eq!(schema["properties"]["debug"], json!({ "type": "boolean" }));
+        assert_eq!(
+            schema["properties"]["server"]["properties"]["port"],
+            json!({ "type": "integer", "minimum": 0, "default": 80 })
+        );
+        assert_eq!(schema["properties"]["server"]["required"], json!(["host"]));
+    }
+}
@@ -0,0 +1,997 @@
+//! Long-running evaluation daemon
+//!
+//! Backs `bunsenite serve`: requests arrive as JSON lines on stdin and
+//! responses are written as JSON lines on stdout, as soon as each request
+//! finishes (so possibly out of order; match them by `id`).
+//!
+//! ```text
+//! → {"id": 1, "method": "parse", "file": "app.ncl", "source": "{ a = 1 }"}
+//! ← {"id": 1, "ok": {"a": 1}}
+//! → {"id": 2, "method": "validate", "source": "{ a = }", "priority": "background"}
+//! ← {"id": 2, "error": "Failed to parse Nickel file 'input.ncl': ..."}
+//! ```
+//!
+//! # Priorities
+//!
+//! Requests are `interactive` (the default, e.g. editor diagnostics) or
+//! `background` (e.g. batch validation). Queued interactive requests always
+//! start first, and background requests never occupy the last worker when
+//! there is more than one, so a large background check cannot make an editor
+//! wait for a free worker. Running evaluations are never interrupted.
+//!
+//! # Limits
+//!
+//! Each request may set:
+//!
+//! - `deadline_ms`: give up with an error if the request has not started
+//!   within this many milliseconds of arriving
+//! - `max_output_bytes`: fail instead of returning a larger JSON result
+//!
+//! # Status
+//!
+//! A `status` request (`{"id": 9, "method": "status"}`, no source needed) is
+//! answered as soon as it is read, without queueing, with a snapshot of the
+//! daemon:
+//!
+//! ```text
+//! {"id": 9, "ok": {"uptime_ms": 5120, "workers": 4, "answered": 31,
+//!   "queued": {"interactive": 0, "background": 2},
+//!   "running": [{"id": 30, "method": "parse", "file": "app.ncl", "elapsed_ms": 812}],
+//!   "libraries": ["helpers"], "memory_bytes": 48234496}}
+//! ```
+//!
+//! `memory_bytes` is the resident set size, or `null` where it cannot be
+//! read (outside Linux). The daemon keeps no caches or open documents
+//! between requests, so there is nothing else to report. With
+//! [`with_status_socket`](Server::with_status_socket), the same snapshot is
+//! also written to every connection on a Unix socket, for monitoring without
+//! access to the daemon's stdin.
+//!
+//! # Metrics
+//!
+//! With [`with_metrics_addr`](Server::with_metrics_addr), request counts,
+//! latencies and errors, and the status gauges, are served over HTTP for
+//! Prometheus to scrape (see [`crate::metrics`]).
+//!
+//! # Libraries
+//!
+//! Helper libraries (see [`crate::library`]) can be registered, replaced and
+//! removed while the daemon runs:
+//!
+//! ```text
+//! → {"id": 3, "method": "register", "name": "helpers", "source": "{ double = fun x => x * 2 }"}
+//! ← {"id": 3, "ok": true}
+//! → {"id": 4, "method": "parse", "source": "{ a = helpers.double 2 }"}
+//! ← {"id": 4, "ok": {"a": 4}}
+//! → {"id": 5, "method": "unregister", "name": "helpers"}
+//! ← {"id": 5, "ok": true}
+//! ```
+//!
+//! `register` and `unregister` are handled as soon as they are read, and
+//! apply to every request read after them; requests read earlier keep the
+//! libraries they arrived with even if they are still queued.
+//!
+//! # Recording
+//!
+//! With [`with_recorder`](Server::with_recorder), every request line and its
+//! response are also written to a session file that
+//! [`session::replay`](crate::session::replay) can answer again, including
+//! library changes. `status` requests are not recorded.
+//!
+//! # Shutdown
+//!
+//! Once [`Shutdown::global`] is requested (the CLI does on SIGINT and
+//! SIGTERM), queued requests, and the next one read, are answered with an
+//! `interrupted` error instead of being evaluated, and `serve` returns.
+//! Requests being evaluated finish, each [`busy`](Shutdown::busy) until
+//! its response is written.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::serve::Server;
+//! use bunsenite::NickelLoader;
+//!
+//! let input = r#"{"id": 1, "method": "parse", "source": "{ a = 1 + 1 }"}"#;
+//! let mut output = Vec::new();
+//! Server::new(NickelLoader::new()).serve(input.as_bytes(), &mut output).unwrap();
+//! assert_eq!(String::from_utf8(output).unwrap(), "{\"id\":1,\"ok\":{\"a\":2}}\n");
+//! ```
+
+use crate::error::{Error, Result};
+use crate::interrupt::Shutdown;
+use crate::library::{Bound, Libraries};
+use crate::limits;
+use crate::loader::NickelLoader;
+use crate::metrics::{self, Endpoint, Metrics};
+use crate::session::Recorder;
+use serde_json::{json, Value};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, BinaryHeap};
+use std::io::{BufRead, Write};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering as AtomicOrdering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// File name used in messages when a request does not give one
+pub const DEFAULT_FILE: &str = "input.ncl";
+
+/// Scheduling class of a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Batch work that may wait
+    Background,
+    /// Work someone is waiting on
+    Interactive,
+}
+
+/// What a request asks for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    /// Evaluate to JSON
+    Parse,
+    /// Parse without evaluating
+    ///
+    /// Types are not checked; use
+    /// [`NickelLoader::typecheck`](crate::NickelLoader::typecheck) or
+    /// `bunsenite typecheck` for that.
+    Validate,
+    /// Report the daemon's state
+    Status,
+    /// Add or replace a library
+    Register,
+    /// Remove a library
+    Unregister,
+}
+
+impl Method {
+    /// Name of the method in requests
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Method::Parse => "parse",
+            Method::Validate => "validate",
+            Method::Status => "status",
+            Method::Register => "register",
+            Method::Unregister => "unregister",
+        }
+    }
+
+    /// Whether the method changes the daemon's libraries
+    fn is_library_change(self) -> bool {
+        matches!(self, Method::Register | Method::Unregister)
+    }
+}
+
+/// Per-request limits
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// Longest a request may wait in the queue
+    pub deadline: Option<Duration>,
+    /// Largest JSON result returned
+    pub max_output_bytes: Option<usize>,
+}
+
+/// A decoded request
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    /// Client-chosen identifier, echoed in the response
+    pub id: Value,
+    /// What to do
+    pub method: Method,
+    /// Nickel source (empty for `status` and `unregister`)
+    pub source: String,
+    /// Library name, for `register` and `unregister`
+    pub name: Option<String>,
+    /// File name used in messages
+    pub file: String,
+    /// Scheduling class
+    pub priority: Priority,
+    /// Limits
+    pub limits: Limits,
+}
+
+impl Request {
+    /// Decode a request line
+    ///
+    /// # Errors
+    ///
+    /// Returns an invalid-input error for malformed requests; the error
+    /// response still carries the request's `id` when it could be read
+    pub fn parse(line: &str) -> std::result::Result<Self, (Value, Error)> {
+        let request: Value = serde_json::from_str(line).map_err(|e| {
+            (
+                Value::Null,
+                Error::invalid_input(format!("Malformed request: {}", e)),
+            )
+        })?;
+        let id = request.get("id").cloned().unwrap_or(Value::Null);
+        let invalid = |message: String| (id.clone(), Error::invalid_input(message));
+
+        let method = match request.get("method").and_then(Value::as_str) {
+            Some("parse") => Method::Parse,
+            Some("validate") => Method::Validate,
+            Some("status") => Method::Status,
+            Some("register") => Method::Register,
+            Some("unregister") => Method::Unregister,
+            Some(other) => return Err(invalid(format!("Unknown method '{}'", other))),
+            None => return Err(invalid("Request has no method".to_string())),
+        };
+        let source = match request.get("source").and_then(Value::as_str) {
+            Some(source) => source.to_string(),
+            None if matches!(method, Method::Status | Method::Unregister) => String::new(),
+            None => return Err(invalid("Request has no source".to_string())),
+        };
+        let name = match request.get("name").and_then(Value::as_str) {
+            Some(name) => Some(name.to_string()),
+            None if method.is_library_change() => {
+                return Err(invalid("Request has no library name".to_string()))
+            }
+            None => None,
+        };
+        let file = request
+            .get("file")
+            .and_then(Value::as_str)
+            .unwrap_or(DEFAULT_FILE)
+            .to_string();
+        let priority = match request.get("priority").and_then(Value::as_str) {
+            None | Some("interactive") => Priority::Interactive,
+            Some("background") => Priority::Background,
+            Some(other) => return Err(invalid(format!("Unknown priority '{}'", other))),
+        };
+        let limit = |name: &str| match request.get(name) {
+            None => Ok(None),
+            Some(value) => value
+                .as_u64()
+                .map(Some)
+                .ok_or_else(|| invalid(format!("'{}' must be a non-negative integer", name))),
+        };
+        let limits = Limits {
+            deadline: limit("deadline_ms")?.map(Duration::from_millis),
+            max_output_bytes: limit("max_output_bytes")?.map(|n| n as usize),
+        };
+
+        Ok(Self {
+            id,
+            method,
+            source,
+            name,
+            file,
+            priority,
+            limits,
+        })
+    }
+}
+
+/// A queued request
+#[derive(Debug)]
+struct Job {
+    request: Request,
+    line: String,
+    // Keeps the libraries the request was bound to until it is answered
+    _libraries: Bound,
+    received: Instant,
+    seq: u64,
+}
+
+impl Job {
+    fn key(&self) -> (Priority, Reverse<u64>) {
+        (self.request.priority, Reverse(self.seq))
+    }
+}
+
+impl PartialEq for Job {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Job {}
+
+impl PartialOrd for Job {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Job {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+/// Pending requests, highest priority first, then arrival order
+#[derive(Debug, Default)]
+struct Queue {
+    jobs: BinaryHeap<Job>,
+    seq: u64,
+    running_background: usize,
+    closed: bool,
+}
+
+impl Queue {
+    fn push(&mut self, request: Request, line: String, libraries: Bound) {
+        self.seq += 1;
+        self.jobs.push(Job {
+            request,
+            line,
+            _libraries: libraries,
+            received: Instant::now(),
+            seq: self.seq,
+        });
+    }
+
+    /// The next job a worker may start, given how many may run in the background
+    fn pop(&mut self, background_slots: usize) -> Option<Job> {
+        let next = self.jobs.peek()?;
+        if next.request.priority == Priority::Background {
+            if self.running_background >= background_slots {
+                return None;
+            }
+            self.running_background += 1;
+        }
+        self.jobs.pop()
+    }
+
+    fn queued(&self, priority: Priority) -> usize {
+        self.jobs
+            .iter()
+            .filter(|job| job.request.priority == priority)
+            .count()
+    }
+}
+
+/// A request being evaluated
+#[derive(Debug)]
+struct Running {
+    id: Value,
+    method: Method,
+    file: String,
+    started: Instant,
+}
+
+/// Counters behind `status` responses
+#[derive(Debug)]
+struct Monitor {
+    started: Instant,
+    answered: AtomicU64,
+    next: AtomicU64,
+    running: Mutex<BTreeMap<u64, Running>>,
+}
+
+impl Default for Monitor {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            answered: AtomicU64::new(0),
+            next: AtomicU64::new(0),
+            running: Mutex::new(BTreeMap::new()),
+        }
+    }
+}
+
+/// The evaluation daemon
+#[derive(Debug, Clone)]
+pub struct Server {
+    loader: NickelLoader,
+    recorder: Option<Arc<Recorder>>,
+    status_socket: Option<PathBuf>,
+    metrics_addr: Option<SocketAddr>,
+    monitor: Arc<Monitor>,
+    metrics: Arc<Metrics>,
+    libraries: Arc<Libraries>,
+}
+
+impl Server {
+    /// Create a daemon evaluating with `loader`, on its
+    /// [`threads`](NickelLoader::threads)
+    pub fn new(loader: NickelLoader) -> Self {
+        Self {
+            loader,
+            recorder: None,
+            status_socket: None,
+            metrics_addr: None,
+            monitor: Arc::default(),
+            metrics: Arc::default(),
+            libraries: Arc::default(),
+        }
+    }
+
+    /// Record every request and its response with `recorder`
+    pub fn with_recorder(mut self, recorder: Recorder) -> Self {
+        self.recorder = Some(Arc::new(recorder));
+        self
+    }
+
+    /// Also answer every connection to a Unix socket at `path` with a status
+    /// snapshot while serving
+    ///
+    /// The socket is created when serving starts and removed when it ends;
+    /// serving fails if `path` already exists. Not supported outside Unix.
+    pub fn with_status_socket(mut self, path: impl Into<PathBuf>) -> Self {
+        self.status_socket = Some(path.into());
+        self
+    }
+
+    /// Also serve Prometheus metrics over HTTP at `addr` while serving
+    ///
+    /// See [`crate::metrics`] for what is exported. Serving fails if `addr`
+    /// cannot be bound.
+    pub fn with_metrics_addr(mut self, addr: SocketAddr) -> Self {
+        self.metrics_addr = Some(addr);
+        self
+    }
+
+    /// Answer requests from `input` until it ends
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading requests or writing responses fails
+    pub fn serve<R, W>(&self, input: R, output: W) -> Result<()>
+    where
+        R: BufRead,
+        W: Write + Send,
+    {
+        let workers = self.loader.threads().max(1);
+        let background_slots = if workers > 1 { workers - 1 } else { 1 };
+        let queue = Mutex::new(Queue::default());
+        let ready = Condvar::new();
+        let output = Mutex::new(output);
+        let status = StatusSocket::bind(self.status_socket.as_deref())?;
+        let endpoint = Endpoint::bind(self.metrics_addr)?;
+
+        let served = std::thread::scope(|scope| {
+            let mut handles = Vec::with_capacity(workers);
+            for _ in 0..workers {
+                handles.push(scope.spawn(|| -> Result<()> {
+                    let shutdown = Shutdown::global();
+                    while let Some(job) = next_job(&queue, &ready, background_slots) {
+                        let _busy = shutdown.busy();
+                        let priority = job.request.priority;
+                        let response = match shutdown.check() {
+                            Ok(()) => self.answer(job.request, job.received),
+                            Err(e) => error_response(job.request.id, &e),
+                        };
+                        if priority == Priority::Background {
+                            lock(&queue).running_background -= 1;
+                            ready.notify_all();
+                        }
+                        self.respond(&output, &job.line, &response)?;
+                    }
+                    Ok(())
+                }));
+            }
+
+            let status_handle = status.as_ref().map(|status| {
+                let queue = &queue;
+                scope.spawn(move || status.answer(|| self.status(Some(queue))))
+            });
+            let metrics_handle = endpoint.as_ref().map(|endpoint| {
+                let queue = &queue;
+                scope.spawn(move || endpoint.answer(|| self.render_metrics(queue)))
+            });
+
+            let read = self.read_requests(input, &queue, &ready, &output);
+            lock(&queue).closed = true;
+            ready.notify_all();
+
+            for handle in handles {
+                handle
+                    .join()
+                    .map_err(|_| Error::internal("serve worker panicked"))??;
+            }
+            if let (Some(status), Some(handle)) = (&status, status_handle) {
+                status.stop();
+                handle
+                    .join()
+                    .map_err(|_| Error::internal("status socket panicked"))?;
+            }
+            if let (Some(endpoint), Some(handle)) = (&endpoint, metrics_handle) {
+                endpoint.stop();
+                handle
+                    .join()
+                    .map_err(|_| Error::internal("metrics endpoint panicked"))?;
+            }
+            read
+        });
+
+        drop(status);
+        served
+    }
+
+    fn read_requests<R: BufRead, W: Write>(
+        &self,
+        input: R,
+        queue: &Mutex<Queue>,
+        ready: &Condvar,
+        output: &Mutex<W>,
+    ) -> Result<()> {
+        for line in input.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match Request::parse(&line) {
+                Ok(request) if request.method == Method::Status => {
+                    let status = self.status(Some(queue));
+                    write_line(output, &json!({ "id": request.id, "ok": status }))?;
+                }
+                Ok(request) if request.method.is_library_change() => {
+                    let response = self.change_libraries(request);
+                    self.respond(output, &line, &response)?;
+                }
+                Ok(request) if Shutdown::global().requested() => {
+                    let e = Error::Interrupted("the server is shutting down".to_string());
+                    self.respond(output, &line, &error_response(request.id, &e))?;
+                    break;
+                }
+                Ok(mut request) => {
+                    // Bound now, so later library changes do not affect it
+                    let bound = self.libraries.bind(&request.source);
+                    request.source = bound.source().to_string();
+                    lock(queue).push(request, line, bound);
+                    ready.notify_one();
+                }
+                Err((id, e)) => self.respond(output, &line, &error_response(id, &e))?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Answer a single request line immediately, bypassing the queue
+    ///
+    /// Used to replay recorded sessions; the request's deadline counts from
+    /// this call.
+    pub fn answer_line(&self, line: &str) -> Value {
+        match Request::parse(line) {
+            Ok(request) if request.method.is_library_change() => self.change_libraries(request),
+            Ok(mut request) => {
+                let bound = self.libraries.bind(&request.source);
+                request.source = bound.source().to_string();
+                self.answer(request, Instant::now())
+            }
+            Err((id, e)) => error_response(id, &e),
+        }
+    }
+
+    /// Snapshot of the daemon's state, including the queue when serving
+    fn status(&self, queue: Option<&Mutex<Queue>>) -> Value {
+        let (interactive, background) = queue.map_or((0, 0), |queue| {
+            let queue = lock(queue);
+            (
+                queue.queued(Priority::Interactive),
+                queue.queued(Priority::Background),
+            )
+        });
+        let running: Vec<Value> = lock(&self.monitor.running)
+            .values()
+            .map(|running| {
+                json!({
+                    "id": running.id,
+                    "method": running.method.as_str(),
+                    "file": running.file,
+                    "elapsed_ms": running.started.elapsed().as_millis() as u64,
+                })
+            })
+            .collect();
+
+        json!({
+            "uptime_ms": self.monitor.started.elapsed().as_millis() as u64,
+            "workers": self.loader.threads().max(1),
+            "answered": self.monitor.answered.load(AtomicOrdering::Relaxed),
+            "queued": { "interactive": interactive, "background": background },
+            "running": running,
+            "libraries": self.libraries.names(),
+            "memory_bytes": limits::resident_bytes(),
+        })
+    }
+
+    /// Request metrics, plus the gauges of a status snapshot
+    fn render_metrics(&self, queue: &Mutex<Queue>) -> String {
+        let status = self.status(Some(queue));
+        let number = |value: &Value| value.as_f64().unwrap_or(0.0);
+        let mut text = self.metrics.render();
+        let gauges = [
+            (
+                "bunsenite_uptime_seconds",
+                "Time since the daemon started",
+                number(&status["uptime_ms"]) / 1000.0,
+            ),
+            (
+                "bunsenite_workers",
+                "Evaluation workers",
+                number(&status["workers"]),
+            ),
+            (
+                "bunsenite_queued_requests",
+                "Requests waiting for a worker",
+                number(&status["queued"]["interactive"]) + number(&status["queued"]["background"]),
+            ),
+            (
+                "bunsenite_running_requests",
+                "Requests being evaluated",
+                status["running"].as_array().map_or(0, Vec::len) as f64,
+            ),
+            (
+                "bunsenite_libraries",
+                "Registered helper libraries",
+                status["libraries"].as_array().map_or(0, Vec::len) as f64,
+            ),
+        ];
+        for (name, help, value) in gauges {
+            text.push_str(&metrics::gauge(name, help, value));
+        }
+        if let Some(bytes) = status["memory_bytes"].as_f64() {
+            text.push_str(&metrics::gauge(
+                "bunsenite_resident_memory_bytes",
+                "Resident set size",
+                bytes,
+            ));
+        }
+        text
+    }
+
+    /// Evaluate a request and build its response
+    fn answer(&self, request: Request, received: Instant) -> Value {
+        if request.method == Method::Status {
+            return json!({ "id": request.id, "ok": self.status(None) });
+        }
+
+        if let Some(deadline) = request.limits.deadline {
+            if received.elapsed() > deadline {
+                let error = Error::invalid_input(format!(
+                    "Request waited longer than its {} ms deadline",
+                    deadline.as_millis()
+                ));
+                self.metrics
+                    .observe(request.method.as_str(), Duration::ZERO, Some(&error));
+                return error_response(request.id, &error);
+            }
+        }
+
+        let key = self.monitor.next.fetch_add(1, AtomicOrdering::Relaxed);
+        let started = Instant::now();
+        lock(&self.monitor.running).insert(
+            key,
+            Running {
+                id: request.id.clone(),
+                method: request.method,
+                file: request.file.clone(),
+                started,
+            },
+        );
+        let result = match request.method {
+            Method::Parse => self
+                .loader
+                .parse_string(&request.source, &request.file)
+                .and_then(|value| check_size(value, request.limits.max_output_bytes)),
+            Method::Validate => self
+                .loader
+                .validate(&request.source, &request.file)
+                .map(|()| Value::Bool(true)),
+            Method::Status | Method::Register | Method::Unregister => {
+                unreachable!("answered without queueing")
+            }
+        };
+        lock(&self.monitor.running).remove(&key);
+        self.monitor.answered.fetch_add(1, AtomicOrdering::Relaxed);
+        self.metrics.observe(
+            request.method.as_str(),
+            started.elapsed(),
+            result.as_ref().err(),
+        );
+
+        match result {
+            Ok(value) => json!({ "id": request.id, "ok": value }),
+            Err(e) => error_response(request.id, &e),
+        }
+    }
+
+    /// Register or unregister a library
+    fn change_libraries(&self, request: Request) -> Value {
+        let name = request.name.as_deref().unwrap_or_default();
+        let result = match request.method {
+            Method::Register => self.libraries.register(&self.loader, name, &request.source),
+            _ => self.libraries.unregister(name),
+        };
+        match result {
+            Ok(()) => json!({ "id": request.id, "ok": true }),
+            Err(e) => error_response(request.id, &e),
+        }
+    }
+
+    fn respond<W: Write>(&self, output: &Mutex<W>, line: &str, response: &Value) -> Result<()> {
+        if let Some(recorder) = &self.recorder {
+            recorder.record(line, response)?;
+        }
+        write_line(output, response)
+    }
+}
+
+/// Block until a job may start, or return `None` once input ended and the
+/// queue drained
+fn next_job(queue: &Mutex<Queue>, ready: &Condvar, background_slots: usize) -> Option<Job> {
+    let mut queue = lock(queue);
+    loop {
+        if let Some(job) = queue.pop(background_slots) {
+            return Some(job);
+        }
+        if queue.closed && queue.jobs.is_empty() {
+            return None;
+        }
+        queue = ready.wait(queue).expect("serve queue poisoned");
+    }
+}
+
+/// Unix socket answering every connection with a status snapshot
+#[derive(Debug)]
+struct StatusSocket {
+    path: PathBuf,
+    #[cfg(unix)]
+    listener: std::os::unix::net::UnixListener,
+    stopping: AtomicBool,
+}
+
+impl StatusSocket {
+    #[cfg(unix)]
+    fn bind(path: Option<&std::path::Path>) -> Result<Option<Self>> {
+        let Some(path) = path else { return Ok(None) };
+        Ok(Some(Self {
+            path: path.to_path_buf(),
+            listener: std::os::unix::net::UnixListener::bind(path)?,
+            stopping: AtomicBool::new(false),
+        }))
+    }
+
+    #[cfg(not(unix))]
+    fn bind(path: Option<&std::path::Path>) -> Result<Option<Self>> {
+        match path {
+            Some(_) => Err(Error::invalid_input(
+                "Status sockets are only supported on Unix",
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Write `status()` to each connection until stopped
+    #[cfg(unix)]
+    fn answer<F: Fn() -> Value>(&self, status: F) {
+        for stream in self.listener.incoming() {
+            if self.stopping.load(AtomicOrdering::SeqCst) {
+                break;
+            }
+            // A monitor that disconnects early is not the daemon's problem
+            if let Ok(mut stream) = stream {
+                let line = crate::json::to_string(&status(), false);
+                let _ = writeln!(stream, "{}", line);
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn answer<F: Fn() -> Value>(&self, _status: F) {}
+
+    /// Make [`answer`](Self::answer) return, by waking it with a last
+    /// connection
+    fn stop(&self) {
+        self.stopping.store(true, AtomicOrdering::SeqCst);
+        #[cfg(unix)]
+        let _ = std::os::unix::net::UnixStream::connect(&self.path);
+    }
+}
+
+impl Drop for StatusSocket {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().expect("serve state poisoned")
+}
+
+fn check_size(value: Value, max: Option<usize>) -> Result<Value> {
+    if let Some(max) = max {
+        let size = crate::json::to_string(&value, false).len();
+        if size > max {
+            return Err(Error::invalid_input(format!(
+                "Result is {} bytes, over the request's {} byte limit",
+                size, max
+            )));
+        }
+    }
+    Ok(value)
+}
+
+fn error_response(id: Value, error: &Error) -> Value {
+    json!({ "id": id, "error": error.to_string() })
+}
+
+fn write_line<W: Write>(output: &Mutex<W>, response: &Value) -> Result<()> {
+    let line = crate::json::to_string(response, false);
+    let mut output = lock(output);
+    writeln!(output, "{}", line)?;
+    output.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(line: &str) -> Request {
+        Request::parse(line).unwrap()
+    }
+
+    fn push(queue: &mut Queue, line: &str) {
+        queue.push(request(line), line.to_string(), Bound::default());
+    }
+
+    fn responses(input: &str, threads: usize) -> Vec<Value> {
+        let mut output = Vec::new();
+        Server::new(NickelLoader::new().with_threads(threads))
+            .serve(input.as_bytes(), &mut output)
+            .unwrap();
+        let mut responses: Vec<Value> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        responses.sort_by_key(|r| r["id"].as_i64());
+        responses
+    }
+
+    #[test]
+    fn test_parse_request() {
+        let r = request(
+            r#"{"id": "a", "method": "validate", "source": "1", "priority": "background", "deadline_ms": 50}"#,
+        );
+        assert_eq!(r.method, Method::Validate);
+        assert_eq!(r.priority, Priority::Background);
+        assert_eq!(r.file, DEFAULT_FILE);
+        assert_eq!(r.limits.deadline, Some(Duration::from_millis(50)));
+
+        let (id, _) = Request::parse(r#"{"id": 7, "method": "eval", "source": "1"}"#).unwrap_err();
+        assert_eq!(id, 7);
+        assert!(Request::parse(r#"{"id": 7, "method": "parse"}"#).is_err());
+        assert!(Request::parse("not json").is_err());
+    }
+
+    #[test]
+    fn test_interactive_requests_go_first() {
+        let mut queue = Queue::default();
+        push(
+            &mut queue,
+            r#"{"id": 1, "method": "parse", "source": "1", "priority": "background"}"#,
+        );
+        push(
+            &mut queue,
+            r#"{"id": 2, "method": "parse", "source": "1", "priority": "background"}"#,
+        );
+        push(&mut queue, r#"{"id": 3, "method": "parse", "source": "1"}"#);
+
+        let order: Vec<Value> = std::iter::from_fn(|| queue.pop(usize::MAX))
+            .map(|job| job.request.id)
+            .collect();
+        assert_eq!(order, vec![json!(3), json!(1), json!(2)]);
+    }
+
+    #[test]
+    fn test_background_slots_are_limited() {
+        let mut queue = Queue::default();
+        push(
+            &mut queue,
+            r#"{"id": 1, "method": "parse", "source": "1", "priority": "background"}"#,
+        );
+        push(
+            &mut queue,
+            r#"{"id": 2, "method": "parse", "source": "1", "priority": "background"}"#,
+        );
+
+        assert!(queue.pop(1).is_some());
+        assert!(queue.pop(1).is_none());
+        push(&mut queue, r#"{"id": 3, "method": "parse", "source": "1"}"#);
+        assert_eq!(queue.pop(1).unwrap().request.id, json!(3));
+    }
+
+    #[test]
+    fn test_serve_answers_every_request() {
+        let input = [
+            r#"{"id": 1, "method": "parse", "source": "{ a = 1 }"}"#,
+            r#"{"id": 2, "method": "validate", "source": "{ a = }", "priority": "background"}"#,
+            "",
+            r#"{"id": 3, "method": "parse", "source": "{ a = [1, 2, 3] }", "max_output_bytes": 4}"#,
+            r#"{"id": 4}"#,
+        ]
+        .join("\n");
+
+        for threads in [1, 3] {
+            let responses = responses(&input, threads);
+            assert_eq!(responses.len(), 4);
+            assert_eq!(responses[0]["ok"], json!({ "a": 1 }));
+            assert!(responses[1]["error"]
+                .as_str()
+                .unwrap()
+                .contains("input.ncl"));
+            assert!(responses[2]["error"]
+                .as_str()
+                .unwrap()
+                .contains("byte limit"));
+            assert!(responses[3]["error"].is_string());
+        }
+    }
+
+    #[test]
+    fn test_status_request() {
+        let input = [
+            r#"{"id": 1, "method": "parse", "source": "{ a = 1 }"}"#,
+            r#"{"id": 2, "method": "status"}"#,
+        ]
+        .join("\n");
+
+        let responses = responses(&input, 1);
+        let status = &responses[1]["ok"];
+        assert_eq!(status["workers"], 1);
+        assert!(status["running"].is_array());
+        assert!(status["queued"]["background"].is_u64());
+        if cfg!(target_os = "linux") {
+            assert!(status["memory_bytes"].as_u64().unwrap() > 0);
+        }
+
+        let server = Server::new(NickelLoader::new().with_threads(1));
+        server.answer_line(r#"{"id": 1, "method": "parse", "source": "1"}"#);
+        let status = server.answer_line(r#"{"id": 2, "method": "status"}"#);
+        assert_eq!(status["ok"]["answered"], 1);
+        assert_eq!(status["ok"]["running"], json!([]));
+    }
+
+    #[test]
+    fn test_library_changes_apply_to_later_requests() {
+        let input = [
+            r#"{"id": 1, "method": "register", "name": "lib", "source": "{ x = 1 }"}"#,
+            r#"{"id": 2, "method": "parse", "source": "lib.x"}"#,
+            r#"{"id": 3, "method": "register", "name": "lib", "source": "{ x = 2 }"}"#,
+            r#"{"id": 4, "method": "parse", "source": "lib.x"}"#,
+            r#"{"id": 5, "method": "unregister", "name": "lib"}"#,
+            r#"{"id": 6, "method": "parse", "source": "lib.x"}"#,
+            r#"{"id": 7, "method": "unregister", "name": "lib"}"#,
+            r#"{"id": 8, "method": "register", "source": "{}"}"#,
+        ]
+        .join("\n");
+
+        let responses = responses(&input, 2);
+        assert_eq!(responses[0]["ok"], true);
+        assert_eq!(responses[1]["ok"], 1);
+        assert_eq!(responses[3]["ok"], 2);
+        assert_eq!(responses[4]["ok"], true);
+        assert!(responses[5]["error"].is_string());
+        assert!(responses[6]["error"].is_string());
+        assert!(responses[7]["error"].is_string());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_status_socket() {
+        use std::io::Read;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("status.sock");
+        let socket = StatusSocket::bind(Some(path.as_path())).unwrap().unwrap();
+
+        std::thread::scope(|scope| {
+            let handle = scope.spawn(|| socket.answer(|| json!({ "answered": 3 })));
+            let mut reply = String::new();
+            std::os::unix::net::UnixStream::connect(&path)
+                .unwrap()
+                .read_to_string(&mut reply)
+                .unwrap();
+            assert_eq!(reply, "{\"answered\":3}\n");
+            socket.stop();
+            handle.join().unwrap();
+        });
+
+        drop(socket);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,243 @@
+//! Recording and replaying daemon sessions
+//!
+//! `bunsenite serve --record session.jsonl` appends one line per answered
+//! request to the session file: the request line exactly as it was received,
+//! and the response that was sent for it.
+//!
+//! ```text
+//! {"request": "{\"id\": 1, \"method\": \"parse\", \"source\": \"{ a = 1 }\"}", "response": {"id": 1, "ok": {"a": 1}}}
+//! ```
+//!
+//! Lines are written in the order responses were sent. `bunsenite replay`
+//! answers the recorded requests again, one at a time in that order, and
+//! reports every response that differs from the recorded one. Queue timing is
+//! not reproduced, so a recorded `deadline_ms` failure replays as a normal
+//! answer and shows up as a divergence.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::serve::Server;
+//! use bunsenite::session;
+//! use bunsenite::NickelLoader;
+//!
+//! let recorded = r#"{"request": "{\"id\": 1, \"method\": \"parse\", \"source\": \"{ a = 1 + 1 }\"}", "response": {"id": 1, "ok": {"a": 3}}}"#;
+//! let exchanges = session::parse(recorded).unwrap();
+//!
+//! let divergences = session::replay(&Server::new(NickelLoader::new()), &exchanges);
+//! assert_eq!(divergences.len(), 1);
+//! assert_eq!(divergences[0].actual["ok"]["a"], 2);
+//! ```
+
+use crate::error::{Error, Result};
+use crate::serve::Server;
+use serde_json::{json, Value};
+use std::fmt;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Writes answered requests to a session file
+pub struct Recorder {
+    output: Mutex<Box<dyn Write + Send>>,
+}
+
+impl fmt::Debug for Recorder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Recorder").finish_non_exhaustive()
+    }
+}
+
+impl Recorder {
+    /// Record to `output`
+    pub fn new<W: Write + Send + 'static>(output: W) -> Self {
+        Self {
+            output: Mutex::new(Box::new(output)),
+        }
+    }
+
+    /// Record to a new file at `path`, replacing any existing one
+    ///
+    /// # Errors
+    ///
+    /// Returns an I/O error if the file cannot be created
+    pub fn create(path: &Path) -> Result<Self> {
+        Ok(Self::new(std::fs::File::create(path)?))
+    }
+
+    /// Append one exchange
+    ///
+    /// # Errors
+    ///
+    /// Returns an I/O error if writing fails
+    pub fn record(&self, request: &str, response: &Value) -> Result<()> {
+        let line =
+            crate::json::to_string(&json!({ "request": request, "response": response }), false);
+        let mut output = self.output.lock().expect("session recorder poisoned");
+        writeln!(output, "{}", line)?;
+        output.flush()?;
+        Ok(())
+    }
+}
+
+/// A recorded request and the response that was sent for it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exchange {
+    /// Request line as received
+    pub request: String,
+    /// Response as sent
+    pub response: Value,
+}
+
+/// Read a session file
+///
+/// # Errors
+///
+/// Returns an I/O error if the file cannot be read, or an invalid-input
+/// error if it is not a session recording
+pub fn load(path: &Path) -> Result<Vec<Exchange>> {
+    parse(&std::fs::read_to_string(path)?)
+}
+
+/// Parse a session recording
+///
+/// # Errors
+///
+/// Returns an invalid-input error naming the first malformed line
+pub fn parse(text: &str) -> Result<Vec<Exchange>> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            let invalid = |message: &str| {
+                Error::invalid_input(format!("Session line {}: {}", index + 1, message))
+            };
+            let entry: Value = serde_json::from_str(line).map_err(|e| invalid(&e.to_string()))?;
+            let request = entry
+                .get("request")
+                .and_then(Value::as_str)
+                .ok_or_else(|| invalid("missing request"))?
+                .to_string();
+            let response = entry
+                .get("response")
+                .cloned()
+                .ok_or_else(|| invalid("missing response"))?;
+            Ok(Exchange { request, response })
+        })
+        .collect()
+}
+
+/// A replayed request whose response changed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    /// Position of the exchange in the session, from 1
+    pub index: usize,
+    /// Request line
+    pub request: String,
+    /// Recorded response
+    pub expected: Value,
+    /// Response on replay
+    pub actual: Value,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "request {}: {}", self.index, self.request)?;
+        writeln!(
+            f,
+            "  recorded: {}",
+            crate::json::to_string(&self.expected, false)
+        )?;
+        write!(
+            f,
+            "  replayed: {}",
+            crate::json::to_string(&self.actual, false)
+        )
+    }
+}
+
+/// Answer every recorded request with `server`, in order and one at a time,
+/// and return the ones whose response changed
+pub fn replay(server: &Server, exchanges: &[Exchange]) -> Vec<Divergence> {
+    exchanges
+        .iter()
+        .enumerate()
+        .filter_map(|(index, exchange)| {
+            let actual = server.answer_line(&exchange.request);
+            (actual != exchange.response).then(|| Divergence {
+                index: index + 1,
+                request: exchange.request.clone(),
+                expected: exchange.response.clone(),
+                actual,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::NickelLoader;
+    use std::sync::Arc;
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn record(input: &str) -> Vec<Exchange> {
+        let recording = Shared::default();
+        Server::new(NickelLoader::new().with_threads(2))
+            .with_recorder(Recorder::new(recording.clone()))
+            .serve(input.as_bytes(), std::io::sink())
+            .unwrap();
+        let text = String::from_utf8(recording.0.lock().unwrap().clone()).unwrap();
+        parse(&text).unwrap()
+    }
+
+    #[test]
+    fn test_records_every_request() {
+        let input = [
+            r#"{"id": 1, "method": "parse", "source": "{ a = 1 }"}"#,
+            r#"{"id": 2, "method": "validate", "source": "{ a = }"}"#,
+            "not json",
+        ]
+        .join("\n");
+
+        let mut exchanges = record(&input);
+        assert_eq!(exchanges.len(), 3);
+        exchanges.sort_by(|a, b| a.request.cmp(&b.request));
+        assert_eq!(exchanges[0].request, "not json");
+        assert!(exchanges[0].response["error"].is_string());
+        assert_eq!(exchanges[1].response, json!({ "id": 1, "ok": { "a": 1 } }));
+    }
+
+    #[test]
+    fn test_replay_reports_changed_responses() {
+        let mut exchanges = record(r#"{"id": 1, "method": "parse", "source": "{ a = 1 }"}"#);
+        let server = Server::new(NickelLoader::new());
+        assert!(replay(&server, &exchanges).is_empty());
+
+        exchanges[0].response = json!({ "id": 1, "ok": { "a": 2 } });
+        let divergences = replay(&server, &exchanges);
+        assert_eq!(divergences.len(), 1);
+        assert_eq!(divergences[0].index, 1);
+        assert_eq!(divergences[0].actual["ok"], json!({ "a": 1 }));
+    }
+
+    #[test]
+    fn test_parse_rejects_malformed_sessions() {
+        assert!(parse("\n").unwrap().is_empty());
+        let err = parse(r#"{"request": "{}"}"#).unwrap_err();
+        assert!(err.to_string().contains("line 1"));
+        assert!(parse("{").is_err());
+    }
+}
@@ -0,0 +1,171 @@
+//! The global settings file
+//!
+//! Defaults for the command line's global options, for one user or one
+//! machine: a Nickel record in `settings.ncl` in the
+//! [config directory](crate::dirs::config_dir), or in the file
+//! [`SETTINGS_ENV`] names. Each field stands for the option of the same
+//! name, with `_` for `-`:
+//!
+//! ```nickel
+//! {
+//!   jobs = 8,
+//!   color = "always",
+//!   remote_cache = "s3://ci-cache/bunsenite",
+//!   transform = ["strip-internal"],
+//! }
+//! ```
+//!
+//! [`args`] turns it into arguments that go before the ones given, so the
+//! command line wins for options taking one value; lists are added to.
+//! `true` is a flag, `false` and `null` are left out, numbers and strings
+//! are values and arrays repeat the option. A flag set here cannot be
+//! turned off on the command line; `--no-settings` skips the file.
+
+use crate::error::{Error, Result};
+use crate::NickelLoader;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+/// Settings file to read instead of `settings.ncl` in the config directory;
+/// empty to read none
+pub const SETTINGS_ENV: &str = "BUNSENITE_SETTINGS";
+
+/// Name of the settings file in the config directory
+pub const FILE_NAME: &str = "settings.ncl";
+
+/// Where the settings file is, and whether it must exist
+///
+/// `(path, true)` for [`SETTINGS_ENV`], `(path, false)` for the default in
+/// the config directory, which is optional. `None` if settings are off
+/// (`SETTINGS_ENV` is empty) or there is no config directory.
+pub fn path() -> Option<(PathBuf, bool)> {
+    match std::env::var_os(SETTINGS_ENV) {
+        Some(path) if path.is_empty() => None,
+        Some(path) => Some((PathBuf::from(path), true)),
+        None => crate::dirs::config_dir().map(|dir| (dir.join(FILE_NAME), false)),
+    }
+}
+
+/// The settings file's options as command-line arguments, by field name
+///
+/// Empty if there is no settings file and none was asked for.
+///
+/// # Errors
+///
+/// Returns the evaluation error of the file, or an invalid-input error if
+/// it is not a record of options (see [`to_args`]) or `SETTINGS_ENV` names
+/// a file that does not exist
+pub fn args(loader: &NickelLoader) -> Result<Vec<String>> {
+    let Some((path, required)) = path() else {
+        return Ok(Vec::new());
+    };
+    if !required && !path.exists() {
+        return Ok(Vec::new());
+    }
+    load(loader, &path)
+}
+
+/// The options in settings file `path` as command-line arguments
+///
+/// # Errors
+///
+/// As for [`args`]
+pub fn load(loader: &NickelLoader, path: &Path) -> Result<Vec<String>> {
+    if !path.exists() {
+        return Err(Error::invalid_input(format!(
+            "Settings file {} does not exist",
+            path.display()
+        )));
+    }
+    let settings = loader.parse_file(path)?;
+    to_args(&settings)
+        .map_err(|e| Error::invalid_input(format!("In settings file {}: {}", path.display(), e)))
+}
+
+/// Settings record `settings` as command-line arguments
+///
+/// # Errors
+///
+/// Returns an invalid-input error if `settings` is not a record or a field
+/// is a record, or a list holding anything but numbers and strings
+pub fn to_args(settings: &Value) -> Result<Vec<String>> {
+    let Value::Object(fields) = settings else {
+        return Err(Error::invalid_input("settings must be a record"));
+    };
+    let mut args = Vec::new();
+    for (name, value) in fields {
+        let option = format!("--{}", name.replace('_', "-"));
+        match value {
+            Value::Bool(true) => args.push(option),
+            Value::Bool(false) | Value::Null => {}
+            Value::Array(items) => {
+                for item in items {
+                    args.push(format!("{}={}", option, scalar(name, item)?));
+                }
+            }
+            value => args.push(format!("{}={}", option, scalar(name, value)?)),
+        }
+    }
+    Ok(args)
+}
+
+/// A setting's value as an argument
+fn scalar(name: &str, value: &Value) -> Result<String> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Number(n) => Ok(n.to_string()),
+        _ => Err(Error::invalid_input(format!(
+            "`{}` must be a string, number, boolean or list of them",
+            name
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_to_args() {
+        let settings = json!({
+            "jobs": 8,
+            "remote_cache": "s3://ci-cache/bunsenite",
+            "transform": ["strip-internal", "inject:team=core"],
+            "verbose": true,
+            "compat": false,
+            "env_prefix": null,
+        });
+        let mut args = to_args(&settings).unwrap();
+        args.sort();
+        assert_eq!(
+            args,
+            [
+                "--jobs=8",
+                "--remote-cache=s3://ci-cache/bunsenite",
+                "--transform=inject:team=core",
+                "--transform=strip-internal",
+                "--verbose",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_to_args_errors() {
+        assert!(to_args(&json!([1])).is_err());
+        assert!(to_args(&json!({ "jobs": { "n": 1 } })).is_err());
+        assert!(to_args(&json!({ "transform": [true] })).is_err());
+    }
+
+    #[test]
+    fn test_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(FILE_NAME);
+        std::fs::write(&path, "{ jobs = 2 + 2, harden = true }").unwrap();
+        let mut args = load(&NickelLoader::new(), &path).unwrap();
+        args.sort();
+        assert_eq!(args, ["--harden", "--jobs=4"]);
+
+        assert!(load(&NickelLoader::new(), &dir.path().join("missing.ncl")).is_err());
+    }
+}
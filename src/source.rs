@@ -0,0 +1,145 @@
+//! Generating Nickel source
+//!
+//! Helpers for commands that write Nickel code, such as generated contracts
+//! or parameters injected into a template.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::source;
+//! use serde_json::json;
+//!
+//! let literal = source::value_literal(&json!({ "name": "acme", "app/id": [1, 2] }));
+//! assert_eq!(literal, r#"{ "app/id" = [1, 2], name = "acme" }"#);
+//! ```
+
+use serde_json::Value;
+
+/// Words the Nickel lexer reads as keywords rather than identifiers
+const KEYWORDS: &[&str] = &[
+    "_",
+    "Array",
+    "Bool",
+    "default",
+    "doc",
+    "Dyn",
+    "else",
+    "false",
+    "forall",
+    "force",
+    "fun",
+    "if",
+    "import",
+    "in",
+    "let",
+    "match",
+    "merge",
+    "not_exported",
+    "null",
+    "Number",
+    "optional",
+    "or",
+    "priority",
+    "rec",
+    "String",
+    "then",
+    "true",
+];
+
+/// Whether a name can be written as a bare Nickel identifier
+pub fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '\''))
+        && !KEYWORDS.contains(&name)
+}
+
+/// A record field name, quoted if it is not a plain identifier
+pub fn field_name(name: &str) -> String {
+    if is_identifier(name) {
+        name.to_string()
+    } else {
+        string_literal(name)
+    }
+}
+
+/// Quote a string as a Nickel string literal
+pub fn string_literal(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            '%' if chars.peek() == Some(&'{') => out.push_str("\\%"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// A JSON value as a Nickel expression on a single line
+pub fn value_literal(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::Number(n) => n.to_string(),
+        Value::String(s) => string_literal(s),
+        Value::Array(items) => {
+            let items: Vec<String> = items.iter().map(value_literal).collect();
+            format!("[{}]", items.join(", "))
+        }
+        Value::Object(map) if map.is_empty() => "{}".to_string(),
+        Value::Object(map) => {
+            let fields: Vec<String> = map
+                .iter()
+                .map(|(key, item)| format!("{} = {}", field_name(key), value_literal(item)))
+                .collect();
+            format!("{{ {} }}", fields.join(", "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NickelLoader;
+    use serde_json::json;
+
+    #[test]
+    fn test_string_literal_escapes() {
+        assert_eq!(string_literal("a\"b\\c\n"), r#""a\"b\\c\n""#);
+        assert_eq!(string_literal("100%{x}"), r#""100\%{x}""#);
+        assert_eq!(string_literal("50%"), r#""50%""#);
+    }
+
+    #[test]
+    fn test_field_names() {
+        assert_eq!(field_name("port"), "port");
+        assert_eq!(field_name("x-forwarded'"), "x-forwarded'");
+        assert_eq!(field_name("1st"), r#""1st""#);
+        assert_eq!(field_name("let"), r#""let""#);
+        assert_eq!(field_name(""), r#""""#);
+    }
+
+    #[test]
+    fn test_value_literal_round_trips() {
+        let value = json!({
+            "name": "acme \"corp\"",
+            "limits": { "cpu": 1.5, "mem": -2, "tags": [] },
+            "flags": [true, null, "%{x}"],
+            "my key": {},
+        });
+        let evaluated = NickelLoader::new()
+            .parse_string(&value_literal(&value), "literal.ncl")
+            .unwrap();
+        assert_eq!(evaluated, value);
+    }
+}
@@ -0,0 +1,649 @@
+//! Per-field output styles
+//!
+//! Some consumers are picky about how a value is written: a version that
+//! must stay a quoted string, a certificate that reads best as a YAML block
+//! scalar, a Kubernetes Secret whose `data` must be base64. Rather than
+//! post-processing the output, a config can say so on the field, with a
+//! contract from a record named `Output`:
+//!
+//! ```nickel
+//! let Output = {
+//!   Quoted = Dyn,
+//!   Literal = String,
+//!   Folded = String,
+//!   Base64 = String,
+//! }
+//! in
+//! {
+//!   version | Output.Quoted = 1.10,
+//!   certificate | Output.Literal = m%"
+//!     -----BEGIN CERTIFICATE-----
+//!     MIIB...
+//!     -----END CERTIFICATE-----
+//!   "%,
+//!   password | Output.Base64 = "hunter2",
+//! }
+//! ```
+//!
+//! The contracts check nothing beyond their types ([`CONTRACTS`] holds the
+//! definitions above); a [`Style`] is picked by the annotation's name, which
+//! must end in `Output.Quoted`, `Output.Literal`, `Output.Folded` or
+//! `Output.Base64`:
+//!
+//! - `Quoted` writes the value as a string, quoted in YAML even where a plain
+//!   scalar would do; numbers and booleans become their decimal or
+//!   `true`/`false` text
+//! - `Literal` and `Folded` write a string as a YAML `|` or `>` block scalar
+//!   (long lines of a folded scalar are wrapped); other formats are
+//!   unaffected
+//! - `Base64` replaces a string by the standard base64 encoding of its UTF-8
+//!   bytes, in every format
+//!
+//! Styles are read from field metadata. They apply to `bunsenite export`,
+//! which can also keep record fields in source order ([`Styles::with_order`],
+//! see [`crate::order`]).
+//!
+//! YAML output can also carry field documentation (`| doc "…"`) as
+//! `# comments` above the keys it documents ([`Styles::with_comments`],
+//! `bunsenite export --doc-comments`), so generated files stay readable for
+//! people who edit copies of them.
+//!
+//! # Examples
+//!
+//! ```
+//! # #[cfg(feature = "yaml")] {
+//! use bunsenite::export::Format;
+//! use bunsenite::style::{Style, Styles};
+//! use serde_json::json;
+//!
+//! let styles = Styles::new()
+//!     .with_style("version", Style::Quoted)
+//!     .with_style("token", Style::Base64);
+//! let value = json!({ "version": 1.5, "token": "ab" });
+//! assert_eq!(
+//!     styles.render(&value, Format::Yaml).unwrap(),
+//!     "token: YWI=\nversion: \"1.5\"\n"
+//! );
+//! # }
+//! ```
+
+use crate::error::{Error, Result};
+use crate::export::{self, Format};
+use crate::json::{self, Layout};
+use crate::order::FieldOrder;
+use serde::ser::{Serialize, SerializeMap, SerializeSeq, Serializer};
+use serde_json::Value;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Nickel definitions of the `Output` contracts
+pub const CONTRACTS: &str = r#"{
+  Quoted = Dyn,
+  Literal = String,
+  Folded = String,
+  Base64 = String,
+}
+"#;
+
+/// Record whose contracts name output styles
+const NAMESPACE: &str = "Output";
+
+/// Width folded block scalars are wrapped to
+const FOLD_WIDTH: usize = 80;
+
+/// Marks values to be restyled in serializer output
+const PLACEHOLDER: &str = "__bunsenite_style_";
+
+/// Marks keys to be commented in serializer output
+const KEY_PLACEHOLDER: &str = "__bunsenite_doc_";
+
+/// How one field is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    /// As a quoted string
+    Quoted,
+    /// As a YAML literal (`|`) block scalar
+    Literal,
+    /// As a YAML folded (`>`) block scalar
+    Folded,
+    /// Base64-encoded
+    Base64,
+}
+
+impl Style {
+    /// Every style
+    pub const ALL: [Style; 4] = [Style::Quoted, Style::Literal, Style::Folded, Style::Base64];
+
+    /// The contract's name within the `Output` record
+    pub fn name(self) -> &'static str {
+        match self {
+            Style::Quoted => "Quoted",
+            Style::Literal => "Literal",
+            Style::Folded => "Folded",
+            Style::Base64 => "Base64",
+        }
+    }
+
+    /// The style a contract annotation names, if any
+    ///
+    /// ```
+    /// use bunsenite::style::Style;
+    ///
+    /// assert_eq!(Style::from_contract("Output.Base64"), Some(Style::Base64));
+    /// assert_eq!(Style::from_contract("lib.Output.Literal"), Some(Style::Literal));
+    /// assert_eq!(Style::from_contract("Base64"), None);
+    /// ```
+    pub fn from_contract(contract: &str) -> Option<Self> {
+        let (namespace, name) = contract.trim().rsplit_once('.')?;
+        let namespace = namespace.rsplit('.').next()?;
+        if namespace != NAMESPACE {
+            return None;
+        }
+        Style::ALL.into_iter().find(|style| style.name() == name)
+    }
+
+    /// Whether the style changes only how a string is written, not the
+    /// value
+    fn is_presentation(self) -> bool {
+        matches!(self, Style::Quoted | Style::Literal | Style::Folded)
+    }
+}
+
+impl fmt::Display for Style {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", NAMESPACE, self.name())
+    }
+}
+
+/// Styles and comments of output fields, by path, the order of record
+/// fields and the layout of JSON output
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Styles {
+    by_path: BTreeMap<String, Vec<Style>>,
+    comments: BTreeMap<String, String>,
+    order: FieldOrder,
+    layout: Layout,
+}
+
+impl Styles {
+    /// No styles
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Styles named by `(path, contract)` annotations, as
+    /// [`NickelLoader::parse_with_contracts`](crate::NickelLoader::parse_with_contracts)
+    /// returns them; other contracts are ignored
+    pub fn from_contracts<'a>(contracts: impl IntoIterator<Item = &'a (String, String)>) -> Self {
+        contracts
+            .into_iter()
+            .filter_map(|(path, contract)| Some((path, Style::from_contract(contract)?)))
+            .fold(Self::new(), |styles, (path, style)| {
+                styles.with_style(path, style)
+            })
+    }
+
+    /// Add `style` to the field at `path` ([`json::key_path`] syntax)
+    pub fn with_style(mut self, path: impl Into<String>, style: Style) -> Self {
+        let styles = self.by_path.entry(path.into()).or_default();
+        if !styles.contains(&style) {
+            styles.push(style);
+        }
+        self
+    }
+
+    /// Write `(path, comment)` pairs, such as
+    /// [`NickelLoader::field_docs`](crate::NickelLoader::field_docs) returns,
+    /// as `#` comments above their keys in YAML output
+    ///
+    /// Other formats have no comments and are unaffected.
+    pub fn with_comments<'a>(
+        mut self,
+        comments: impl IntoIterator<Item = &'a (String, String)>,
+    ) -> Self {
+        for (path, comment) in comments {
+            let comment = comment.trim();
+            if !comment.is_empty() {
+                self.comments.insert(path.clone(), comment.to_string());
+            }
+        }
+        self
+    }
+
+    /// Write record fields in `order`, such as
+    /// [`NickelLoader::field_order`](crate::NickelLoader::field_order)
+    /// returns, rather than name order
+    pub fn with_order(mut self, order: FieldOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// Lay JSON output out as `layout` says
+    pub fn with_json_layout(mut self, layout: Layout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Whether no field is styled, commented or reordered, and JSON keeps
+    /// the default layout
+    pub fn is_empty(&self) -> bool {
+        self.by_path.is_empty()
+            && self.comments.is_empty()
+            && self.order.is_empty()
+            && self.layout == Layout::default()
+    }
+
+    /// The styles of the field at `path`, in annotation order
+    pub fn get(&self, path: &str) -> &[Style] {
+        self.by_path.get(path).map_or(&[], Vec::as_slice)
+    }
+
+    /// `value` in `format`, with each styled field written in its style
+    ///
+    /// Where a field has more than one of `Quoted`, `Literal` and `Folded`,
+    /// the first annotated is used.
+    ///
+    /// # Errors
+    ///
+    /// Returns a serialization error if a styled field's value does not
+    /// suit its style (`Literal`, `Folded` and `Base64` need strings;
+    /// `Quoted` needs a string, number or boolean), or as
+    /// [`export::render`]
+    pub fn render(&self, value: &Value, format: Format) -> Result<String> {
+        if self.is_empty() {
+            return export::render(value, format);
+        }
+        let mut value = value.clone();
+        let mut restyled = Vec::new();
+        let yaml = format == Format::Yaml;
+        self.apply(&mut value, String::new(), yaml, &mut restyled)?;
+        let rendered = if yaml && !self.comments.is_empty() {
+            self.commented_yaml(&value)
+        } else {
+            export::render_styled(&value, format, &self.order, &self.layout)
+        };
+        json::drop_deep(value);
+        let mut rendered = rendered?;
+
+        for (index, (text, style)) in restyled.into_iter().enumerate() {
+            let placeholder = format!("{}{}__", PLACEHOLDER, index);
+            let (start, end) = match rendered.match_indices(&placeholder).collect::<Vec<_>>()[..] {
+                [(start, _)] => (start, start + placeholder.len()),
+                _ => {
+                    return Err(Error::internal(format!(
+                        "Cannot place the {} value in the YAML output",
+                        style
+                    )))
+                }
+            };
+            let line_start = rendered[..start].rfind('\n').map_or(0, |at| at + 1);
+            let scalar = yaml_scalar(&text, style, &rendered[line_start..start]);
+            rendered.replace_range(start..end, &scalar);
+        }
+        Ok(rendered)
+    }
+
+    /// `value` as YAML with commented keys
+    fn commented_yaml(&self, value: &Value) -> Result<String> {
+        let keys = RefCell::new(Vec::new());
+        let keyed = Keyed {
+            value,
+            path: String::new(),
+            comments: &self.comments,
+            order: &self.order,
+            keys: &keys,
+        };
+        let mut rendered = export::to_yaml(&keyed).map_err(|e| {
+            Error::serialization_error(format!("Cannot write output as yaml: {}", e))
+        })?;
+
+        for (index, (key, comment)) in keys.into_inner().into_iter().enumerate() {
+            let placeholder = format!("{}{}__:", KEY_PLACEHOLDER, index);
+            let start = rendered
+                .find(&placeholder)
+                .ok_or_else(|| Error::internal("Cannot place a doc comment in the YAML output"))?;
+            let line_start = rendered[..start].rfind('\n').map_or(0, |at| at + 1);
+            let line = &rendered[line_start..start];
+            let indent = " ".repeat(line.len() - line.trim_start().len());
+            let block: String = comment
+                .lines()
+                .map(|text| match text.trim_end() {
+                    "" => format!("{}#\n", indent),
+                    text => format!("{}# {}\n", indent, text),
+                })
+                .collect();
+
+            rendered.replace_range(start..start + placeholder.len() - 1, &yaml_key(key));
+            rendered.insert_str(line_start, &block);
+        }
+        Ok(rendered)
+    }
+
+    /// Apply value styles below `path`, replacing strings to be restyled
+    /// by placeholders if `placeholders`
+    fn apply(
+        &self,
+        value: &mut Value,
+        path: String,
+        placeholders: bool,
+        restyled: &mut Vec<(String, Style)>,
+    ) -> Result<()> {
+        let styles = self.get(&path);
+        if !styles.is_empty() {
+            let unsuitable = |style: Style| {
+                Error::serialization_error(format!(
+                    "'{}' is annotated {} but is not a string",
+                    path, style
+                ))
+            };
+            for style in styles {
+                match (style, &*value) {
+                    (Style::Base64, Value::String(text)) => {
+                        *value = Value::String(base64(text.as_bytes()));
+                    }
+                    (Style::Quoted, Value::Number(n)) => *value = Value::String(n.to_string()),
+                    (Style::Quoted, Value::Bool(b)) => *value = Value::String(b.to_string()),
+                    (_, Value::String(_)) => {}
+                    (style, _) => return Err(unsuitable(*style)),
+                }
+            }
+            let presentation = styles.iter().copied().find(|s| s.is_presentation());
+            if let (Some(style), true) = (presentation, placeholders) {
+                let placeholder = format!("{}{}__", PLACEHOLDER, restyled.len());
+                if let Value::String(text) = std::mem::replace(value, Value::String(placeholder)) {
+                    restyled.push((text, style));
+                }
+            }
+        }
+
+        match value {
+            Value::Object(map) => {
+                for (key, item) in map.iter_mut() {
+                    self.apply(item, json::key_path(&path, key), placeholders, restyled)?;
+                }
+            }
+            Value::Array(items) => {
+                for (index, item) in items.iter_mut().enumerate() {
+                    self.apply(item, json::index_path(&path, index), placeholders, restyled)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// A value serialized with its commented keys replaced by placeholders,
+/// which are collected with their comments in `keys`, and its records'
+/// fields in `order`
+struct Keyed<'a, 'k> {
+    value: &'a Value,
+    path: String,
+    comments: &'a BTreeMap<String, String>,
+    order: &'a FieldOrder,
+    keys: &'k RefCell<Vec<(&'a str, &'a str)>>,
+}
+
+impl Serialize for Keyed<'_, '_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let nested = |value, path| Keyed {
+            value,
+            path,
+            comments: self.comments,
+            order: self.order,
+            keys: self.keys,
+        };
+        match self.value {
+            Value::Object(map) => {
+                let mut out = serializer.serialize_map(Some(map.len()))?;
+                for (key, value) in self.order.entries(&self.path, map) {
+                    let path = json::key_path(&self.path, key);
+                    let name = match self.comments.get(&path) {
+                        Some(comment) => {
+                            let mut keys = self.keys.borrow_mut();
+                            keys.push((key.as_str(), comment.as_str()));
+                            format!("{}{}__", KEY_PLACEHOLDER, keys.len() - 1)
+                        }
+                        None => key.clone(),
+                    };
+                    out.serialize_entry(&name, &nested(value, path))?;
+                }
+                out.end()
+            }
+            Value::Array(items) => {
+                let mut out = serializer.serialize_seq(Some(items.len()))?;
+                for (index, item) in items.iter().enumerate() {
+                    out.serialize_element(&nested(item, json::index_path(&self.path, index)))?;
+                }
+                out.end()
+            }
+            scalar => scalar.serialize(serializer),
+        }
+    }
+}
+
+/// `key` as a YAML mapping key
+fn yaml_key(key: &str) -> String {
+    if key.chars().any(char::is_control) {
+        return serde_json::to_string(key).expect("strings always serialize");
+    }
+    export::to_yaml(&key)
+        .map(|text| text.trim_end().to_string())
+        .unwrap_or_else(|_| serde_json::to_string(key).expect("strings always serialize"))
+}
+
+/// `text` as a YAML scalar in `style`, to follow `prefix` on its line
+///
+/// Strings a block scalar cannot hold exactly (control characters, a
+/// leading space) are written double-quoted instead.
+fn yaml_scalar(text: &str, style: Style, prefix: &str) -> String {
+    let quoted = || serde_json::to_string(text).expect("strings always serialize");
+    let body = text.trim_end_matches('\n');
+    let first = body.lines().find(|line| !line.is_empty());
+    let blockable = style != Style::Quoted
+        && first.is_some_and(|line| !line.starts_with(' '))
+        && !text
+            .chars()
+            .any(|c| c.is_control() && c != '\n' && c != '\t');
+    if !blockable {
+        return quoted();
+    }
+
+    // Content goes two columns right of the key or sequence entry
+    let key = prefix.trim_start_matches([' ', '-']);
+    let indent = " ".repeat(prefix.len() - key.len() + 2);
+    let chomp = match text.len() - body.len() {
+        0 => "-",
+        1 => "",
+        _ => "+",
+    };
+    // Spaced lines are never folded, so only literal keeps them exact
+    let folded = style == Style::Folded && !body.lines().any(|l| l.starts_with([' ', '\t']));
+
+    let mut lines = Vec::new();
+    if folded {
+        let mut newlines = 0;
+        for (i, line) in body.split('\n').enumerate() {
+            if i > 0 {
+                newlines += 1;
+            }
+            if line.is_empty() {
+                continue;
+            }
+            // A single line break folds into a space; n empty lines keep n
+            lines.extend(std::iter::repeat("").take(newlines));
+            lines.extend(wrap(line, FOLD_WIDTH));
+            newlines = 0;
+        }
+    } else {
+        lines.extend(body.split('\n'));
+    }
+    // Keep-chomping keeps the trailing line breaks beyond the first
+    lines.extend(std::iter::repeat("").take((text.len() - body.len()).saturating_sub(1)));
+
+    let mut scalar = format!("{}{}", if folded { '>' } else { '|' }, chomp);
+    for line in lines {
+        scalar.push('\n');
+        if !line.is_empty() {
+            scalar.push_str(&indent);
+            scalar.push_str(line);
+        }
+    }
+    scalar
+}
+
+/// Split `line` at single spaces into pieces of about `width` characters
+fn wrap(line: &str, width: usize) -> Vec<&str> {
+    let bytes = line.as_bytes();
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    let mut candidate = None;
+    for at in 0..bytes.len() {
+        let breakable = bytes[at] == b' '
+            && at > start
+            && bytes[at - 1] != b' '
+            && bytes.get(at + 1).is_some_and(|b| *b != b' ');
+        if !breakable {
+            continue;
+        }
+        if at - start > width {
+            if let Some(previous) = candidate.take() {
+                pieces.push(&line[start..previous]);
+                start = previous + 1;
+            }
+        }
+        candidate = Some(at);
+    }
+    if line.len() - start > width {
+        if let Some(previous) = candidate.filter(|at| *at > start) {
+            pieces.push(&line[start..previous]);
+            start = previous + 1;
+        }
+    }
+    pieces.push(&line[start..]);
+    pieces
+}
+
+/// Standard base64 with padding
+pub(crate) fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity((bytes.len() + 2) / 3 * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, byte)| n | (u32::from(*byte) << (16 - 8 * i)));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i)) as usize & 63] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn test_yaml_block_scalars() {
+        let styles = Styles::new()
+            .with_style("cert", Style::Literal)
+            .with_style("servers[0].motd", Style::Folded)
+            .with_style("servers[0].note", Style::Literal)
+            .with_style("lead", Style::Literal);
+        let value = json!({
+            "cert": "-----BEGIN-----\nMIIB\n-----END-----\n",
+            "lead": "  indented",
+            "servers": [{ "motd": "one\ntwo\n\nthree", "note": "a\n\nb\n\n" }],
+        });
+        let yaml = styles.render(&value, Format::Yaml).unwrap();
+        assert_eq!(
+            yaml,
+            "cert: |\n  -----BEGIN-----\n  MIIB\n  -----END-----\nlead: \"  indented\"\nservers:\n- motd: >-\n    one\n\n    two\n\n\n    three\n  note: |+\n    a\n\n    b\n\n"
+        );
+        let parsed: Value = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(parsed, value);
+    }
+
+    #[test]
+    fn test_value_styles_in_every_format() {
+        let styles = Styles::from_contracts(&[
+            ("port".to_string(), "Output.Quoted".to_string()),
+            ("secret".to_string(), "Output.Base64".to_string()),
+            ("secret".to_string(), "String".to_string()),
+            ("name".to_string(), "Output.Literal".to_string()),
+        ]);
+        assert_eq!(styles.get("secret"), [Style::Base64]);
+        let value = json!({ "port": 80, "secret": "user:pass", "name": "web" });
+
+        assert_eq!(
+            styles.render(&value, Format::Json).unwrap(),
+            "{\n  \"name\": \"web\",\n  \"port\": \"80\",\n  \"secret\": \"dXNlcjpwYXNz\"\n}\n"
+        );
+        #[cfg(feature = "yaml")]
+        assert_eq!(
+            styles.render(&value, Format::Yaml).unwrap(),
+            "name: |-\n  web\nport: \"80\"\nsecret: dXNlcjpwYXNz\n"
+        );
+        assert_eq!(base64(b"ab"), "YWI=");
+
+        let error = styles
+            .render(&json!({ "secret": 1 }), Format::Toml)
+            .unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("'secret' is annotated Output.Base64"));
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn test_doc_comments() {
+        let docs = [
+            (
+                "replicas".to_string(),
+                "How many pods\n\nAt least 2 in prod".to_string(),
+            ),
+            ("servers[0].host".to_string(), "Public name".to_string()),
+            (
+                "labels.\"app.kubernetes.io/name\"".to_string(),
+                "App".to_string(),
+            ),
+        ];
+        let styles = Styles::new()
+            .with_comments(&docs)
+            .with_style("servers[0].motd", Style::Literal);
+        let value = json!({
+            "labels": { "app.kubernetes.io/name": "web" },
+            "replicas": 3,
+            "servers": [{ "host": "a.example", "motd": "hi\nthere" }],
+        });
+        let yaml = styles.render(&value, Format::Yaml).unwrap();
+        assert_eq!(
+            yaml,
+            "labels:\n  # App\n  app.kubernetes.io/name: web\n# How many pods\n#\n# At least 2 in prod\nreplicas: 3\nservers:\n# Public name\n- host: a.example\n  motd: |-\n    hi\n    there\n"
+        );
+        let parsed: Value = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(parsed, value);
+        assert_eq!(
+            styles.render(&value, Format::Json).unwrap(),
+            export::render(&value, Format::Json).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_folded_lines_wrap() {
+        let words = vec!["word"; 40].join(" ");
+        let pieces = wrap(&words, 20);
+        assert!(pieces.len() > 1);
+        assert!(pieces.iter().all(|piece| piece.len() <= 24));
+        assert_eq!(pieces.join(" "), words);
+        assert_eq!(wrap("a  b", 1), ["a  b"]);
+    }
+}
@@ -0,0 +1,468 @@
+//! Random configs satisfying a contract
+//!
+//! Backs `bunsenite synth`: reads a record contract and generates random
+//! values that satisfy it, for load-testing consumers of a config format and
+//! fuzzing the evaluation pipeline with realistic shapes. Generation is
+//! seeded, so a seed reproduces the same configs.
+//!
+//! Contracts are read structurally, so only the subset written by
+//! `bunsenite infer-schema` (see [`crate::schema`]) is understood: records
+//! with `optional` fields, `Bool`, `Number`, `std.number.Integer`, `String`,
+//! `Dyn`, `Array T` and enums (`std.enum.TagOrString | [| 'a, 'b |]`).
+//! Anything else, such as custom contract functions, is rejected.
+//! [`check`] runs each generated value through the actual contract with
+//! Nickel.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::synth::{self, Contract};
+//!
+//! let contract = Contract::parse("{ port | std.number.Integer, env | std.enum.TagOrString | [| 'dev, 'prod |] }").unwrap();
+//! let configs = synth::generate(&contract, 3, 42);
+//! assert_eq!(configs.len(), 3);
+//! assert!(configs[0]["port"].is_i64());
+//! assert_eq!(configs, synth::generate(&contract, 3, 42));
+//! ```
+
+use crate::error::{Error, Result};
+use crate::lexer;
+use crate::loader::NickelLoader;
+use crate::source;
+use crate::threads;
+use serde_json::{Map, Number, Value};
+
+/// The structure of a contract
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Contract {
+    /// Any value
+    Dyn,
+    /// A boolean
+    Bool,
+    /// Any number
+    Number,
+    /// An integer
+    Integer,
+    /// Any string
+    String,
+    /// One of a set of strings
+    Enum(Vec<String>),
+    /// An array of elements satisfying a contract
+    Array(Box<Contract>),
+    /// A record with these fields
+    Record(Vec<Field>),
+}
+
+/// A record field in a contract
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field {
+    /// Field name
+    pub name: String,
+    /// Contract on the field's value
+    pub contract: Contract,
+    /// Whether the field may be left out
+    pub optional: bool,
+}
+
+impl Contract {
+    /// Read the structure of a contract
+    ///
+    /// # Errors
+    ///
+    /// Returns an invalid-input error for malformed contracts and for
+    /// constructs outside the supported subset
+    pub fn parse(source: &str) -> Result<Self> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            pos: 0,
+        };
+        let contract = parser.chain()?;
+        match parser.advance() {
+            None => Ok(contract),
+            Some(token) => Err(unexpected(&token)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Tag(String),
+    Punct(&'static str),
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>> {
+    let lexemes = lexer::tokens(source)?;
+    let unsupported = |lexeme: &lexer::Token| {
+        Error::invalid_input(format!(
+            "Unsupported syntax '{}' in contract",
+            lexeme.text()
+        ))
+    };
+
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while let Some(lexeme) = lexemes.get(i) {
+        i += 1;
+        let next = lexemes.get(i);
+        let token = match lexeme {
+            lexeme if lexeme.is_trivia() => continue,
+            lexer::Token::Punct('[') if matches!(next, Some(lexer::Token::Op(op)) if op == "|") => {
+                i += 1;
+                Token::Punct("[|")
+            }
+            lexer::Token::Op(op) if op == "|" && next == Some(&lexer::Token::Punct(']')) => {
+                i += 1;
+                Token::Punct("|]")
+            }
+            lexer::Token::Op(op) if op == "|" => Token::Punct("|"),
+            lexer::Token::Op(op) if op == "=" => Token::Punct("="),
+            lexer::Token::Punct('{') => Token::Punct("{"),
+            lexer::Token::Punct('}') => Token::Punct("}"),
+            lexer::Token::Punct('(') => Token::Punct("("),
+            lexer::Token::Punct(')') => Token::Punct(")"),
+            lexer::Token::Punct(',') => Token::Punct(","),
+            lexer::Token::Str(text) => {
+                Token::Str(lexer::string_value(text).ok_or_else(|| unsupported(lexeme))?)
+            }
+            lexer::Token::Word(word) if word == "'" => match next {
+                Some(quoted @ lexer::Token::Str(text)) => {
+                    i += 1;
+                    Token::Tag(lexer::string_value(text).ok_or_else(|| unsupported(quoted))?)
+                }
+                _ => return Err(unsupported(lexeme)),
+            },
+            lexer::Token::Word(word) if word.starts_with('\'') => Token::Tag(word[1..].to_string()),
+            lexer::Token::Word(word) if is_identifier(word) => Token::Ident(word.clone()),
+            lexeme => return Err(unsupported(lexeme)),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// Whether `word` is a (possibly dotted) identifier
+fn is_identifier(word: &str) -> bool {
+    word.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '\'' | '.'))
+}
+
+fn unexpected(token: &Token) -> Error {
+    let shown = match token {
+        Token::Ident(name) => name.clone(),
+        Token::Str(value) => source::string_literal(value),
+        Token::Tag(tag) => format!("'{}", tag),
+        Token::Punct(punct) => punct.to_string(),
+    };
+    Error::invalid_input(format!("Unsupported contract syntax at '{}'", shown))
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, punct: &'static str) -> bool {
+        if self.peek() == Some(&Token::Punct(punct)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, punct: &'static str) -> Result<()> {
+        if self.eat(punct) {
+            return Ok(());
+        }
+        match self.advance() {
+            Some(token) => Err(unexpected(&token)),
+            None => Err(Error::invalid_input(format!(
+                "Contract ends where '{}' was expected",
+                punct
+            ))),
+        }
+    }
+
+    /// Contracts applied together with `|`
+    fn chain(&mut self) -> Result<Contract> {
+        let mut contracts = vec![self.atom()?];
+        while self.eat("|") {
+            contracts.push(self.atom()?);
+        }
+        Ok(combine(contracts))
+    }
+
+    fn atom(&mut self) -> Result<Option<Contract>> {
+        let token = self
+            .advance()
+            .ok_or_else(|| Error::invalid_input("Contract ends where a type was expected"))?;
+        Ok(Some(match token {
+            Token::Ident(name) => match name.as_str() {
+                "Dyn" => Contract::Dyn,
+                "Bool" => Contract::Bool,
+                "Number" => Contract::Number,
+                "std.number.Integer" => Contract::Integer,
+                "String" => Contract::String,
+                // Only relaxes the enum that follows to accept strings
+                "std.enum.TagOrString" => return Ok(None),
+                "Array" => Contract::Array(Box::new(self.atom()?.unwrap_or(Contract::String))),
+                _ => return Err(unexpected(&Token::Ident(name))),
+            },
+            Token::Punct("{") => self.record()?,
+            Token::Punct("[|") => self.tags()?,
+            Token::Punct("(") => {
+                let contract = self.chain()?;
+                self.expect(")")?;
+                contract
+            }
+            token => return Err(unexpected(&token)),
+        }))
+    }
+
+    fn record(&mut self) -> Result<Contract> {
+        let mut fields = Vec::new();
+        while !self.eat("}") {
+            let name = match self.advance() {
+                Some(Token::Ident(name)) if !name.contains('.') => name,
+                Some(Token::Str(name)) => name,
+                Some(token) => return Err(unexpected(&token)),
+                None => return Err(Error::invalid_input("Unclosed record in contract")),
+            };
+            let mut contracts = Vec::new();
+            let mut optional = false;
+            while self.eat("|") {
+                match self.peek() {
+                    Some(Token::Ident(word)) if word == "optional" => {
+                        self.pos += 1;
+                        optional = true;
+                    }
+                    Some(Token::Ident(word)) if word == "doc" => {
+                        self.pos += 1;
+                        match self.advance() {
+                            Some(Token::Str(_)) => {}
+                            Some(token) => return Err(unexpected(&token)),
+                            None => {
+                                return Err(Error::invalid_input("Unclosed record in contract"))
+                            }
+                        }
+                    }
+                    _ => contracts.push(self.atom()?),
+                }
+            }
+            fields.push(Field {
+                name,
+                contract: combine(contracts),
+                optional,
+            });
+            if !self.eat(",") {
+                self.expect("}")?;
+                break;
+            }
+        }
+        Ok(Contract::Record(fields))
+    }
+
+    fn tags(&mut self) -> Result<Contract> {
+        let mut tags = Vec::new();
+        while !self.eat("|]") {
+            match self.advance() {
+                Some(Token::Tag(tag)) => tags.push(tag),
+                Some(token) => return Err(unexpected(&token)),
+                None => return Err(Error::invalid_input("Unclosed enum in contract")),
+            }
+            if !self.eat(",") {
+                self.expect("|]")?;
+                break;
+            }
+        }
+        Ok(Contract::Enum(tags))
+    }
+}
+
+/// The contract generated values must satisfy, given a field's annotations
+///
+/// The last annotation wins; an annotation-free field accepts anything.
+fn combine(contracts: Vec<Option<Contract>>) -> Contract {
+    let relaxed = contracts.iter().any(Option::is_none);
+    match contracts.into_iter().flatten().last() {
+        Some(contract) => contract,
+        None if relaxed => Contract::String,
+        None => Contract::Dyn,
+    }
+}
+
+/// A small seeded pseudo-random generator (xorshift64*)
+#[derive(Debug, Clone)]
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // Zero is a fixed point of xorshift
+        Self(seed ^ 0x9E37_79B9_7F4A_7C15)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// A number in `0..n`
+    fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n.max(1)
+    }
+}
+
+/// Longest generated arrays
+const MAX_ARRAY_LEN: u64 = 4;
+
+/// Generate `count` values satisfying `contract`, reproducibly from `seed`
+pub fn generate(contract: &Contract, count: usize, seed: u64) -> Vec<Value> {
+    let mut rng = Rng::new(seed);
+    (0..count).map(|_| value(contract, &mut rng)).collect()
+}
+
+fn value(contract: &Contract, rng: &mut Rng) -> Value {
+    match contract {
+        Contract::Dyn => match rng.below(4) {
+            0 => Value::Null,
+            1 => Value::Bool(rng.below(2) == 0),
+            2 => Value::from(rng.below(1000)),
+            _ => Value::String(word(rng)),
+        },
+        Contract::Bool => Value::Bool(rng.below(2) == 0),
+        Contract::Number => Number::from_f64(rng.below(100_000) as f64 / 100.0)
+            .map_or(Value::from(0), Value::Number),
+        Contract::Integer => Value::from(rng.below(20_000) as i64 - 10_000),
+        Contract::String => Value::String(word(rng)),
+        Contract::Enum(tags) if tags.is_empty() => Value::Null,
+        Contract::Enum(tags) => Value::String(tags[rng.below(tags.len() as u64) as usize].clone()),
+        Contract::Array(element) => Value::Array(
+            (0..rng.below(MAX_ARRAY_LEN + 1))
+                .map(|_| value(element, rng))
+                .collect(),
+        ),
+        Contract::Record(fields) => {
+            let mut map = Map::new();
+            for field in fields {
+                if field.optional && rng.below(2) == 0 {
+                    continue;
+                }
+                map.insert(field.name.clone(), value(&field.contract, rng));
+            }
+            Value::Object(map)
+        }
+    }
+}
+
+fn word(rng: &mut Rng) -> String {
+    let len = 3 + rng.below(10);
+    (0..len)
+        .map(|_| char::from(b'a' + rng.below(26) as u8))
+        .collect()
+}
+
+/// Check one value against a contract's Nickel source
+///
+/// # Errors
+///
+/// Returns the contract violation, or the error applying the contract
+pub fn satisfies(loader: &NickelLoader, value: &Value, contract: &str) -> Result<()> {
+    let program = format!("({}) | ({})", source::value_literal(value), contract);
+    loader
+        .validate(&program, "contract.ncl")
+        .and_then(|()| loader.parse_string(&program, "contract.ncl"))
+        .map(drop)
+}
+
+/// Check generated values against the contract's Nickel source
+///
+/// Values are checked in parallel on the loader's threads.
+///
+/// # Errors
+///
+/// Returns the first failure, naming the index of the rejected value
+pub fn check(loader: &NickelLoader, contract: &str, values: &[Value]) -> Result<()> {
+    let results = threads::map(
+        loader.threads(),
+        values.iter().enumerate().collect(),
+        |(index, value)| satisfies(loader, value, contract).map_err(|e| (index, e)),
+    );
+    match results.into_iter().find_map(|result| result.err()) {
+        Some((index, e)) => Err(Error::invalid_input(format!(
+            "Generated config {} does not satisfy the contract: {}",
+            index + 1,
+            e
+        ))),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::{Shape, DEFAULT_ENUM_THRESHOLD};
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_inferred_contract() {
+        let samples = [
+            json!({ "name": "api", "env": "prod", "ports": [80], "db": { "ratio": 0.5 } }),
+            json!({ "name": "web", "env": "prod", "ports": [], "db": { "ratio": 1 }, "tls": true }),
+        ];
+        let contract = Shape::infer(&samples).to_nickel(DEFAULT_ENUM_THRESHOLD);
+
+        let Contract::Record(fields) = Contract::parse(&contract).unwrap() else {
+            panic!("expected a record");
+        };
+        let names: Vec<&str> = fields.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["db", "env", "name", "ports", "tls"]);
+        assert_eq!(fields[1].contract, Contract::Enum(vec!["prod".to_string()]));
+        assert_eq!(
+            fields[3].contract,
+            Contract::Array(Box::new(Contract::Integer))
+        );
+        assert!(fields[4].optional);
+    }
+
+    #[test]
+    fn test_parse_rejects_unsupported_contracts() {
+        assert!(Contract::parse("{ port | std.number.PosNat }").is_err());
+        assert!(Contract::parse("{ port = 80 }").is_err());
+        assert!(Contract::parse("{ port | Number").is_err());
+    }
+
+    #[test]
+    fn test_generated_values_satisfy_contract() {
+        let source = r#"{
+          name | String,
+          "display name" | String | optional,
+          replicas | std.number.Integer,
+          ratio | Number,
+          env | std.enum.TagOrString | [| 'dev, 'prod |],
+          hosts | Array { addr | String, tls | Bool },
+          extra | Dyn | optional,
+        }"#;
+        let contract = Contract::parse(source).unwrap();
+        let values = generate(&contract, 8, 7);
+        assert_eq!(values, generate(&contract, 8, 7));
+        assert_ne!(values, generate(&contract, 8, 8));
+        check(&NickelLoader::new().with_threads(2), source, &values).unwrap();
+    }
+}
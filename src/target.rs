@@ -0,0 +1,210 @@
+//! Partial output targets and provenance
+//!
+//! Backs `bunsenite parse --target services.web`: when many services share
+//! one config tree, each can export just its slice as a standalone document.
+//! The loader evaluates only the selected field (Nickel is lazy, so sibling
+//! subtrees are never forced), which also means an error in another
+//! service's section does not block this one.
+//!
+//! A [`Provenance`] record describes where an exported document came from:
+//! the entrypoint, target, source files, Nickel version and a SHA-256 of the output,
+//! for `--provenance FILE` sidecars that let consumers check what they were
+//! handed.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::target::Target;
+//! use bunsenite::NickelLoader;
+//!
+//! let loader = NickelLoader::new().with_target(Target::parse("services.web").unwrap());
+//! let source = "{ services = { web = { port = 80 }, db = { port = std.fail_with \"unset\" } } }";
+//! let web = loader.parse_string(source, "services.ncl").unwrap();
+//! assert_eq!(web["port"], 80);
+//! ```
+
+use crate::error::{Error, Result};
+use crate::json;
+use crate::source;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+/// A field path selecting the subtree to export
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    path: String,
+    segments: Vec<String>,
+}
+
+impl Target {
+    /// Parse a target path in [`json::key_path`] syntax, e.g. `services.web`
+    ///
+    /// # Errors
+    ///
+    /// Returns an invalid-input error for malformed or empty paths, and for
+    /// paths with array indices or wildcards (only record fields can be
+    /// targeted)
+    pub fn parse(path: &str) -> Result<Self> {
+        let invalid =
+            |reason: &str| Error::invalid_input(format!("Invalid target '{}': {}", path, reason));
+        let segments = crate::pattern::split(path).ok_or_else(|| invalid("malformed path"))?;
+        if segments.is_empty() {
+            return Err(invalid("empty path"));
+        }
+        if segments
+            .iter()
+            .any(|s| s.starts_with('[') || s == "*" || s == "**")
+        {
+            return Err(invalid("only record fields can be targeted"));
+        }
+        Ok(Self {
+            path: json::join_path(&segments),
+            segments,
+        })
+    }
+
+    /// The target path, normalized
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Rewrite a program to evaluate to the target field only
+    ///
+    /// The program keeps its line numbers, so errors still point into it.
+    pub fn select(&self, program: &str) -> String {
+        let mut selected = format!("({}\n)", program);
+        for segment in &self.segments {
+            selected.push('.');
+            selected.push_str(&source::field_name(segment));
+        }
+        selected
+    }
+}
+
+/// Where an exported document came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Provenance {
+    /// Entrypoint that was evaluated
+    pub file: PathBuf,
+    /// Exported subtree, if not the whole config
+    pub target: Option<String>,
+    /// Files the output was built from, starting with the entrypoint
+    pub sources: Vec<PathBuf>,
+    /// SHA-256 of the output's compact JSON, hex-encoded
+    pub sha256: String,
+}
+
+impl Provenance {
+    /// Describe `output`, evaluated from `file`
+    ///
+    /// Imported files are found by scanning the sources (see
+    /// [`crate::imports`]), so nothing is evaluated again.
+    ///
+    /// # Errors
+    ///
+    /// Returns an I/O error if `file` cannot be read
+    pub fn new(
+        file: &Path,
+        target: Option<&Target>,
+        output: &Value,
+        threads: usize,
+    ) -> Result<Self> {
+        let (source, name) = crate::loader::read_source(file)?;
+        let graph = crate::imports::resolve_source(&source, &name, threads);
+        let mut sources = vec![file.to_path_buf()];
+        sources.extend(graph.files);
+
+        Ok(Self {
+            file: file.to_path_buf(),
+            target: target.map(|t| t.path().to_string()),
+            sources,
+            sha256: sha256_hex(json::to_string(output, false).as_bytes()),
+        })
+    }
+
+    /// This record with its paths relative to the project at `root`
+    ///
+    /// Records written this way are identical across checkouts of the
+    /// project; see [`paths::relative_to`](crate::paths::relative_to).
+    pub fn relative_to(mut self, root: &Path) -> Self {
+        self.file = crate::paths::relative_to(&self.file, root);
+        for source in &mut self.sources {
+            *source = crate::paths::relative_to(source, root);
+        }
+        self
+    }
+
+    /// The provenance record as JSON
+    pub fn to_json(&self) -> Value {
+        let sources: Vec<String> = self
+            .sources
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect();
+        json!({
+            "file": self.file.display().to_string(),
+            "target": self.target,
+            "sources": sources,
+            "bunsenite": crate::VERSION,
+            "nickel": crate::VERSION_INFO.nickel_language,
+            "sha256": self.sha256,
+        })
+    }
+}
+
+/// Hex-encoded SHA-256 of `bytes`
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NickelLoader;
+
+    #[test]
+    fn test_parse_target() {
+        assert_eq!(Target::parse("a.\"b c\"").unwrap().path(), "a.\"b c\"");
+        assert!(Target::parse("").is_err());
+        assert!(Target::parse("servers[0]").is_err());
+        assert!(Target::parse("services.*").is_err());
+    }
+
+    #[test]
+    fn test_select_keeps_lines() {
+        let loader = NickelLoader::new().with_target(Target::parse("a.\"b c\"").unwrap());
+        let value = loader
+            .parse_string("# comment\n{ a = { \"b c\" = [1] } } # trailing", "t.ncl")
+            .unwrap();
+        assert_eq!(value, serde_json::json!([1]));
+
+        let error = loader
+            .parse_string("{\n  a = { \"b c\" = 1 + \"x\" },\n}", "t.ncl")
+            .unwrap_err();
+        assert!(error.to_string().contains("t.ncl"));
+    }
+
+    #[test]
+    fn test_provenance() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("app.ncl");
+        std::fs::write(&file, "{ a = 1 }").unwrap();
+        let output = serde_json::json!({ "a": 1 });
+
+        let provenance = Provenance::new(&file, None, &output, 1).unwrap();
+        assert_eq!(provenance.sources, vec![file.clone()]);
+        let relative = provenance.clone().relative_to(dir.path());
+        assert_eq!(relative.to_json()["file"], "app.ncl");
+        assert_eq!(
+            relative.to_json()["sources"],
+            serde_json::json!(["app.ncl"])
+        );
+        assert_eq!(provenance.sha256, sha256_hex(b"{\"a\":1}"));
+        assert_eq!(provenance.to_json()["target"], Value::Null);
+        assert_eq!(
+            sha256_hex(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+}
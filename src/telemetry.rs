@@ -0,0 +1,212 @@
+//! Evaluation phase spans and OTLP trace export
+//!
+//! With the `otel` feature, every evaluation is wrapped in a
+//! `bunsenite.evaluate` [`tracing`] span with a child span per phase
+//! (`bunsenite.parse`, `bunsenite.typecheck`, `bunsenite.eval`,
+//! `bunsenite.serialize`). The evaluation span carries these attributes:
+//!
+//! - `file`: the name the source was evaluated under
+//! - `imports`: the number of files the source imports directly
+//! - `output_bytes`: the size of the evaluated output as compact JSON
+//!
+//! Spans are opened in the caller's current span, so an embedder that
+//! already exports its own traces through `tracing-opentelemetry` sees
+//! bunsenite's phases nested inside its request spans with no further setup.
+//! Programs without a tracing setup of their own (such as the `bunsenite`
+//! CLI, with `--otlp-endpoint`) can call [`export_otlp`] to send the spans
+//! to an OTLP/HTTP collector.
+//!
+//! Without the feature, nothing is recorded and nothing is linked in.
+
+use crate::bench::Phase;
+use serde_json::Value;
+
+/// The span of one evaluation, closed when dropped
+#[derive(Debug)]
+pub(crate) struct Evaluation {
+    #[cfg(feature = "otel")]
+    span: tracing::Span,
+}
+
+impl Evaluation {
+    /// Open the span of evaluating `source`, named `name`
+    #[cfg(feature = "otel")]
+    pub(crate) fn start(source: &str, name: &str) -> Self {
+        let span = tracing::info_span!(
+            "bunsenite.evaluate",
+            file = name,
+            imports = crate::imports::scan(source).len(),
+            output_bytes = tracing::field::Empty,
+        );
+        Self { span }
+    }
+
+    #[cfg(not(feature = "otel"))]
+    pub(crate) fn start(_source: &str, _name: &str) -> Self {
+        Self {}
+    }
+
+    /// Run `f` inside the span
+    #[cfg(feature = "otel")]
+    pub(crate) fn in_scope<T>(&self, f: impl FnOnce() -> T) -> T {
+        self.span.in_scope(f)
+    }
+
+    #[cfg(not(feature = "otel"))]
+    pub(crate) fn in_scope<T>(&self, f: impl FnOnce() -> T) -> T {
+        f()
+    }
+
+    /// Record the size of the evaluated output
+    #[cfg(feature = "otel")]
+    pub(crate) fn record_output(&self, value: &Value) {
+        if !self.span.is_disabled() {
+            let bytes = crate::json::to_string(value, false).len();
+            self.span.record("output_bytes", bytes);
+        }
+    }
+
+    #[cfg(not(feature = "otel"))]
+    pub(crate) fn record_output(&self, _value: &Value) {}
+}
+
+/// Run `f` inside the span of one pipeline phase
+pub(crate) fn phase<T>(phase: Phase, f: impl FnOnce() -> T) -> T {
+    #[cfg(feature = "otel")]
+    {
+        // Span names must be literals
+        let span = match phase {
+            Phase::Parse => tracing::info_span!("bunsenite.parse"),
+            Phase::Typecheck => tracing::info_span!("bunsenite.typecheck"),
+            Phase::Evaluate => tracing::info_span!("bunsenite.eval"),
+            Phase::Serialize => tracing::info_span!("bunsenite.serialize"),
+        };
+        span.in_scope(f)
+    }
+    #[cfg(not(feature = "otel"))]
+    {
+        let _ = phase;
+        f()
+    }
+}
+
+/// `f`, to be run on another thread inside the caller's current span
+#[cfg(feature = "otel")]
+pub(crate) fn in_current_span<T>(f: impl FnOnce() -> T + Send) -> impl FnOnce() -> T + Send {
+    let span = tracing::Span::current();
+    move || span.in_scope(f)
+}
+
+#[cfg(not(feature = "otel"))]
+pub(crate) fn in_current_span<T>(f: impl FnOnce() -> T + Send) -> impl FnOnce() -> T + Send {
+    f
+}
+
+/// Flushes exported spans when dropped
+#[cfg(feature = "otel")]
+#[derive(Debug)]
+pub struct OtlpGuard {
+    _private: (),
+}
+
+#[cfg(feature = "otel")]
+impl Drop for OtlpGuard {
+    fn drop(&mut self) {
+        opentelemetry::global::shutdown_tracer_provider();
+    }
+}
+
+/// Export bunsenite's spans to the OTLP/HTTP collector at `endpoint`
+///
+/// Installs a global `tracing` subscriber, so call it once, early, and only
+/// in programs that do not set up tracing themselves. Spans are sent as they
+/// close; keep the returned guard alive until the program ends so the last
+/// ones are flushed.
+///
+/// # Errors
+///
+/// Returns an invalid-input error if the exporter cannot be built or a
+/// global subscriber is already installed
+#[cfg(feature = "otel")]
+pub fn export_otlp(endpoint: &str) -> crate::Result<OtlpGuard> {
+    use opentelemetry_otlp::WithExportConfig;
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+
+    let failed = |e: &dyn std::fmt::Display| {
+        crate::Error::invalid_input(format!("Cannot export traces to {}: {}", endpoint, e))
+    };
+    let resource = opentelemetry_sdk::Resource::new([
+        opentelemetry::KeyValue::new("service.name", "bunsenite"),
+        opentelemetry::KeyValue::new("service.version", crate::VERSION),
+    ]);
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .http()
+                .with_endpoint(endpoint),
+        )
+        .with_trace_config(opentelemetry_sdk::trace::config().with_resource(resource))
+        .install_simple()
+        .map_err(|e| failed(&e))?;
+
+    tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(tracer))
+        .try_init()
+        .map_err(|e| failed(&e))?;
+    Ok(OtlpGuard { _private: () })
+}
+
+#[cfg(all(test, feature = "otel"))]
+mod tests {
+    use super::*;
+    use crate::NickelLoader;
+    use std::sync::{Arc, Mutex};
+    use tracing::span::{Attributes, Id};
+    use tracing::Subscriber;
+    use tracing_subscriber::layer::{Context, SubscriberExt};
+    use tracing_subscriber::Layer;
+
+    /// Records span names with their parent's name
+    #[derive(Clone, Default)]
+    struct Spans(Arc<Mutex<Vec<(String, Option<String>)>>>);
+
+    impl<S> Layer<S> for Spans
+    where
+        S: Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+    {
+        fn on_new_span(&self, _attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+            let span = ctx.span(id).unwrap();
+            let parent = span.parent().map(|parent| parent.name().to_string());
+            self.0
+                .lock()
+                .unwrap()
+                .push((span.name().to_string(), parent));
+        }
+    }
+
+    #[test]
+    fn test_phases_nest_in_evaluation() {
+        let spans = Spans::default();
+        let subscriber = tracing_subscriber::registry().with(spans.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            NickelLoader::new()
+                .with_stack_size(0)
+                .parse_string("{ a = 1 }", "t.ncl")
+                .unwrap();
+        });
+
+        let recorded = spans.0.lock().unwrap().clone();
+        let parent = Some("bunsenite.evaluate".to_string());
+        assert_eq!(
+            recorded,
+            [
+                ("bunsenite.evaluate".to_string(), None),
+                ("bunsenite.parse".to_string(), parent.clone()),
+                ("bunsenite.eval".to_string(), parent.clone()),
+                ("bunsenite.serialize".to_string(), parent),
+            ]
+        );
+    }
+}
@@ -0,0 +1,143 @@
+//! Evaluating one template for many tenants
+//!
+//! Backs `bunsenite parse --tenants`. A template config refers to a free
+//! `tenant` variable; each tenant's parameter set is bound to it and the
+//! template evaluated, all in one process. The template is read once and
+//! tenants are evaluated in parallel, instead of paying process startup
+//! and file reads once per tenant.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::tenant;
+//! use bunsenite::NickelLoader;
+//! use serde_json::json;
+//!
+//! let tenants = json!({ "acme": { "replicas": 3 }, "globex": { "replicas": 1 } });
+//! let template = "{ name = tenant.name, replicas = tenant.replicas * 2 }";
+//!
+//! let results = tenant::evaluate(&NickelLoader::new(), template, "app.ncl", &tenants).unwrap();
+//! assert_eq!(results[0].0, "acme");
+//! assert_eq!(results[0].1.as_ref().unwrap(), &json!({ "name": "acme", "replicas": 6 }));
+//! ```
+
+use crate::error::{Error, Result};
+use crate::loader::NickelLoader;
+use crate::source;
+use crate::threads;
+use serde_json::Value;
+
+/// Name the tenant's parameters are bound to in the template
+pub const BINDING: &str = "tenant";
+
+/// Bind a tenant's parameters in a template
+///
+/// The binding is prepended on the template's first line, so line numbers in
+/// error messages still match the template file. A `name` field holding the
+/// tenant name is added unless the parameters already define one.
+pub fn bind(template: &str, name: &str, params: &Value) -> String {
+    let mut params = params.clone();
+    if let Value::Object(map) = &mut params {
+        map.entry("name")
+            .or_insert_with(|| Value::String(name.to_string()));
+    }
+    format!(
+        "let {} = {} in {}",
+        BINDING,
+        source::value_literal(&params),
+        template
+    )
+}
+
+/// Check that a tenant name is safe to use as a file name
+///
+/// # Errors
+///
+/// Returns an invalid-input error for empty names, path separators and
+/// names starting with a dot
+pub fn validate_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        Err(Error::invalid_input(format!(
+            "Tenant name '{}' cannot be used as a file name",
+            name
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+/// Evaluate a template once per tenant
+///
+/// `tenants` is a record mapping tenant names to parameter sets. Results are
+/// returned in tenant name order; a failure for one tenant does not stop the
+/// others. Tenants are evaluated on the loader's
+/// [`threads`](NickelLoader::threads).
+///
+/// # Errors
+///
+/// Returns an error if `tenants` is not a record or a tenant name is invalid
+pub fn evaluate(
+    loader: &NickelLoader,
+    template: &str,
+    name: &str,
+    tenants: &Value,
+) -> Result<Vec<(String, Result<Value>)>> {
+    let tenants = tenants
+        .as_object()
+        .ok_or_else(|| Error::invalid_input("Tenants file must be a record of parameter sets"))?;
+    for tenant in tenants.keys() {
+        validate_name(tenant)?;
+    }
+
+    let results = threads::map(
+        loader.threads(),
+        tenants.iter().collect(),
+        |(tenant, params)| loader.parse_string(&bind(template, tenant, params), name),
+    );
+    Ok(tenants.keys().cloned().zip(results).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_bind_adds_name() {
+        let bound = bind("tenant.x", "acme", &json!({ "x": 1 }));
+        assert_eq!(
+            bound,
+            r#"let tenant = { name = "acme", x = 1 } in tenant.x"#
+        );
+
+        let bound = bind("tenant", "acme", &json!({ "name": "Acme Corp" }));
+        assert!(bound.contains(r#"name = "Acme Corp""#));
+    }
+
+    #[test]
+    fn test_failures_are_per_tenant() {
+        let tenants = json!({ "a": { "port": 1 }, "b": {}, "c": { "port": 3 } });
+        let results = evaluate(
+            &NickelLoader::new(),
+            "{ port = tenant.port }",
+            "t.ncl",
+            &tenants,
+        )
+        .unwrap();
+
+        let names: Vec<&str> = results.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names, vec!["a", "b", "c"]);
+        assert_eq!(results[0].1.as_ref().unwrap(), &json!({ "port": 1 }));
+        assert!(results[1].1.is_err());
+        assert_eq!(results[2].1.as_ref().unwrap(), &json!({ "port": 3 }));
+    }
+
+    #[test]
+    fn test_rejects_bad_input() {
+        let loader = NickelLoader::new();
+        assert!(evaluate(&loader, "1", "t.ncl", &json!([1])).is_err());
+        assert!(evaluate(&loader, "1", "t.ncl", &json!({ "../x": {} })).is_err());
+        assert!(validate_name("acme-eu").is_ok());
+        assert!(validate_name(".hidden").is_err());
+    }
+}
@@ -0,0 +1,332 @@
+//! Tests kept next to a config
+//!
+//! Backs `bunsenite test`: a config's `tests` field (or another, see
+//! [`run`]) is a record of tests, each one of
+//!
+//! - a `Bool`, which passes if it is `true`
+//! - a record `{ actual, expected }`, which passes if the two are equal
+//! - a record of more tests, a group, whose tests are named `group.test`
+//!
+//! ```nickel
+//! let config = { replicas = 3, image = "web:1.2" } in
+//! config & {
+//!   tests | not_exported = {
+//!     enough_replicas = config.replicas >= 2,
+//!     tagged = { actual = std.string.contains ":" config.image, expected = true },
+//!   },
+//! }
+//! ```
+//!
+//! `not_exported` keeps the tests out of the output. A config under a
+//! closed record contract cannot grow a `tests` field; put its tests in a
+//! file that imports it. Each test is evaluated on its own, so a test that
+//! fails to evaluate is reported as an error without hiding the others.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::testing;
+//! use bunsenite::NickelLoader;
+//!
+//! let dir = tempfile::tempdir().unwrap();
+//! let file = dir.path().join("app.ncl");
+//! std::fs::write(&file, "{ port = 80, tests | not_exported = { low_port = port < 1024 } }").unwrap();
+//!
+//! let report = testing::run(&NickelLoader::new(), &file, testing::DEFAULT_FIELD, None).unwrap();
+//! assert!(report.all_passed());
+//! assert_eq!(report.results[0].name, "low_port");
+//! ```
+
+use crate::error::{Error, Result};
+use crate::imports;
+use crate::json;
+use crate::loader::NickelLoader;
+use crate::source;
+use serde_json::Value;
+use std::path::Path;
+
+/// Field holding the tests unless another is given
+pub const DEFAULT_FIELD: &str = "tests";
+
+/// Outcome of one test
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// The test held
+    Pass,
+    /// The test evaluated but did not hold, for this reason
+    Fail(String),
+    /// The test did not evaluate, or is not a test, with this message
+    Error(String),
+}
+
+/// Outcome of one test, by name
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestResult {
+    /// Path of the test in the tests record, e.g. `network.ports_unique`
+    pub name: String,
+    /// What happened
+    pub outcome: Outcome,
+}
+
+/// Outcomes of a file's tests, in name order
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Report {
+    /// Every test run
+    pub results: Vec<TestResult>,
+}
+
+impl Report {
+    /// Number of tests that passed
+    pub fn passed(&self) -> usize {
+        self.results
+            .iter()
+            .filter(|result| result.outcome == Outcome::Pass)
+            .count()
+    }
+
+    /// Tests that failed or did not evaluate
+    pub fn failures(&self) -> impl Iterator<Item = &TestResult> {
+        self.results
+            .iter()
+            .filter(|result| result.outcome != Outcome::Pass)
+    }
+
+    /// Whether every test passed
+    pub fn all_passed(&self) -> bool {
+        self.failures().next().is_none()
+    }
+}
+
+/// Run the tests in field `field` of `file`
+///
+/// With a `filter`, only tests whose names contain it run.
+///
+/// # Errors
+///
+/// Returns an error if the file does not evaluate to a record with a
+/// `field` record; failing tests are in the report
+pub fn run(
+    loader: &NickelLoader,
+    file: &Path,
+    field: &str,
+    filter: Option<&str>,
+) -> Result<Report> {
+    // Tests are reached through an import of the file by absolute path,
+    // which resolves wherever the test program is
+    let absolute = std::env::current_dir()
+        .map(|cwd| imports::normalize(&cwd.join(file)))
+        .unwrap_or_else(|_| file.to_path_buf());
+    let config = format!(
+        "(import {})",
+        source::string_literal(&absolute.to_string_lossy())
+    );
+    let mut runner = Runner {
+        loader,
+        name: file.display().to_string(),
+        filter,
+        results: Vec::new(),
+    };
+
+    let has_tests = format!(
+        "std.record.has_field {} {}",
+        source::string_literal(field),
+        config
+    );
+    if runner.evaluate(&has_tests)? != Value::Bool(true) {
+        return Err(Error::invalid_input(format!(
+            "{} has no `{}` field",
+            runner.name, field
+        )));
+    }
+    let tests = format!("{}.{}", config, source::field_name(field));
+    for name in runner.field_names(&tests)? {
+        let test = format!("{}.{}", tests, source::field_name(&name));
+        runner.select(&test, &json::key_path("", &name));
+    }
+    Ok(Report {
+        results: runner.results,
+    })
+}
+
+/// The state of one [`run`]
+struct Runner<'a> {
+    loader: &'a NickelLoader,
+    name: String,
+    filter: Option<&'a str>,
+    results: Vec<TestResult>,
+}
+
+impl Runner<'_> {
+    fn evaluate(&self, program: &str) -> Result<Value> {
+        // Not under the file's own name, which Nickel would resolve the
+        // import of the file to, making the program import itself
+        self.loader
+            .parse_string(program, &format!("{} (tests)", self.name))
+    }
+
+    /// The field names of the record `program` evaluates to
+    fn field_names(&self, program: &str) -> Result<Vec<String>> {
+        let names = self.evaluate(&format!("std.record.fields ({})", program))?;
+        serde_json::from_value(names).map_err(|e| Error::internal(e.to_string()))
+    }
+
+    /// Run test or group `program`, named `path`, if the filter selects
+    /// it or tests below it
+    fn select(&mut self, program: &str, path: &str) {
+        let selected = match self.filter {
+            Some(filter) => path.contains(filter),
+            None => true,
+        };
+        if selected {
+            self.test(program, path);
+            return;
+        }
+        // Only a group can hold selected tests; the check evaluates just
+        // far enough to tell
+        let is_record = format!("std.is_record ({})", program);
+        if self.evaluate(&is_record).ok() != Some(Value::Bool(true)) {
+            return;
+        }
+        if let Ok(names) = self.field_names(program) {
+            for name in names {
+                let test = format!("{}.{}", program, source::field_name(&name));
+                self.select(&test, &json::key_path(path, &name));
+            }
+        }
+    }
+
+    /// Run test or group `program`, named `path`
+    fn test(&mut self, program: &str, path: &str) {
+        let error = match self.evaluate(program) {
+            Ok(value) => return self.judge(&value, path),
+            Err(error) => error,
+        };
+        // A group fails to evaluate if any of its tests does; run them one
+        // by one to tell which
+        match self.field_names(program) {
+            Ok(names) if !names.is_empty() && !is_comparison(&names) => {
+                for name in names {
+                    let test = format!("{}.{}", program, source::field_name(&name));
+                    self.test(&test, &json::key_path(path, &name));
+                }
+            }
+            _ => self.record(path, Outcome::Error(error.to_string())),
+        }
+    }
+
+    /// Record the outcome of test or group `value`, named `path`
+    fn judge(&mut self, value: &Value, path: &str) {
+        match value {
+            Value::Bool(true) => self.record(path, Outcome::Pass),
+            Value::Bool(false) => {
+                self.record(path, Outcome::Fail("evaluated to false".to_string()))
+            }
+            Value::Object(record) if is_comparison(record.keys()) => {
+                let (actual, expected) = (&record["actual"], &record["expected"]);
+                let outcome = if actual == expected {
+                    Outcome::Pass
+                } else {
+                    Outcome::Fail(format!(
+                        "expected {}, got {}",
+                        json::to_string(expected, false),
+                        json::to_string(actual, false)
+                    ))
+                };
+                self.record(path, outcome);
+            }
+            Value::Object(record) => {
+                for (name, value) in record {
+                    self.judge(value, &json::key_path(path, name));
+                }
+            }
+            other => self.record(
+                path,
+                Outcome::Error(format!(
+                    "not a test: {} is neither a Bool nor {{ actual, expected }}",
+                    json::to_string(other, false)
+                )),
+            ),
+        }
+    }
+
+    fn record(&mut self, name: &str, outcome: Outcome) {
+        self.results.push(TestResult {
+            name: name.to_string(),
+            outcome,
+        });
+    }
+}
+
+/// Whether field names `names` are those of an `{ actual, expected }` test
+fn is_comparison<S: AsRef<str>>(names: impl IntoIterator<Item = S>) -> bool {
+    let mut names: Vec<S> = names.into_iter().collect();
+    names.sort_by(|a, b| a.as_ref().cmp(b.as_ref()));
+    names.len() == 2 && names[0].as_ref() == "actual" && names[1].as_ref() == "expected"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outcomes(report: &Report) -> Vec<(&str, &Outcome)> {
+        report
+            .results
+            .iter()
+            .map(|result| (result.name.as_str(), &result.outcome))
+            .collect()
+    }
+
+    #[test]
+    fn test_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("app.ncl");
+        std::fs::write(
+            &file,
+            r#"{
+              port = 80,
+              tests | not_exported = {
+                low_port = port < 1024,
+                high_port = port > 1024,
+                sum = { actual = port + 1, expected = 82 },
+                net = { bound = std.is_number port, "has name" = { actual = "web", expected = "web" } },
+                broken = { fine = true, boom = 1 + "x" },
+                odd = 7,
+              },
+            }"#,
+        )
+        .unwrap();
+
+        let report = run(&NickelLoader::new(), &file, DEFAULT_FIELD, None).unwrap();
+        let results = outcomes(&report);
+        assert!(results.contains(&("low_port", &Outcome::Pass)));
+        assert!(results.contains(&(
+            "high_port",
+            &Outcome::Fail("evaluated to false".to_string())
+        )));
+        assert!(results.contains(&("sum", &Outcome::Fail("expected 82, got 81".to_string()))));
+        assert!(results.contains(&("net.bound", &Outcome::Pass)));
+        assert!(results.contains(&("net.\"has name\"", &Outcome::Pass)));
+        assert!(results.contains(&("broken.fine", &Outcome::Pass)));
+        assert!(results
+            .iter()
+            .any(|(name, outcome)| *name == "broken.boom" && matches!(outcome, Outcome::Error(_))));
+        assert!(results
+            .iter()
+            .any(|(name, outcome)| *name == "odd" && matches!(outcome, Outcome::Error(_))));
+        assert_eq!(report.passed(), 4);
+        assert!(!report.all_passed());
+
+        let report = run(&NickelLoader::new(), &file, DEFAULT_FIELD, Some("net.")).unwrap();
+        assert_eq!(report.results.len(), 2);
+        assert!(report.all_passed());
+    }
+
+    #[test]
+    fn test_missing_field() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("app.ncl");
+        std::fs::write(&file, "{ port = 80 }").unwrap();
+        assert!(run(&NickelLoader::new(), &file, DEFAULT_FIELD, None).is_err());
+        assert!(run(&NickelLoader::new(), &file, "port", None).is_err());
+    }
+}
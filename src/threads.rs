@@ -0,0 +1,99 @@
+//! Parallelism controls
+//!
+//! Batch operations (tenant and matrix evaluation, conformance checks) spread
+//! their work over [`NickelLoader::threads`](crate::NickelLoader::threads)
+//! worker threads. By default that is one per available core, capped at
+//! [`CI_THREAD_CAP`] when running under CI, where cores are shared and
+//! reported counts are often misleading.
+//!
+//! Results always come back in input order. With a single thread, work also
+//! runs in input order on the calling thread, so side effects such as trace
+//! output are deterministic too.
+
+use std::sync::Mutex;
+
+/// Most threads used by default when the `CI` environment variable is set
+pub const CI_THREAD_CAP: usize = 4;
+
+/// Default number of worker threads
+///
+/// The available parallelism, capped at [`CI_THREAD_CAP`] under CI.
+pub fn default_threads() -> usize {
+    if in_ci() {
+        available_threads().min(CI_THREAD_CAP)
+    } else {
+        available_threads()
+    }
+}
+
+/// The available parallelism, whether or not under CI
+pub fn available_threads() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// Whether the process runs under CI, following the common `CI` convention
+fn in_ci() -> bool {
+    std::env::var("CI").is_ok_and(|ci| !ci.is_empty() && ci != "0" && ci != "false")
+}
+
+/// Apply `f` to every item on up to `threads` threads, keeping input order
+pub(crate) fn map<T, R, F>(threads: usize, items: Vec<T>, f: F) -> Vec<R>
+where
+    T: Send,
+    R: Send,
+    F: Fn(T) -> R + Sync,
+{
+    let count = items.len();
+    let threads = threads.clamp(1, count.max(1));
+    if threads == 1 {
+        return items.into_iter().map(f).collect();
+    }
+
+    let queue = Mutex::new(items.into_iter().enumerate());
+    let results = Mutex::new(Vec::with_capacity(count));
+
+    std::thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| loop {
+                let next = queue.lock().expect("work queue poisoned").next();
+                let Some((index, item)) = next else { break };
+                let result = f(item);
+                results
+                    .lock()
+                    .expect("work results poisoned")
+                    .push((index, result));
+            });
+        }
+    });
+
+    let mut results = results.into_inner().expect("work results poisoned");
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_map_keeps_input_order() {
+        let items: Vec<u64> = (0..100).collect();
+        for threads in [1, 3, 64] {
+            let squares = map(threads, items.clone(), |n| n * n);
+            assert_eq!(squares, items.iter().map(|n| n * n).collect::<Vec<_>>());
+        }
+        assert!(map(4, Vec::<u8>::new(), |n| n).is_empty());
+    }
+
+    #[test]
+    fn test_single_thread_runs_in_order() {
+        let seen = Mutex::new(Vec::new());
+        map(1, vec![3, 1, 2], |n| seen.lock().unwrap().push(n));
+        assert_eq!(seen.into_inner().unwrap(), vec![3, 1, 2]);
+    }
+
+    #[test]
+    fn test_default_is_positive() {
+        assert!(default_threads() >= 1);
+    }
+}
@@ -0,0 +1,543 @@
+//! Post-processing of evaluated configs
+//!
+//! A [`Transform`] rewrites the evaluated value before it is serialized, so
+//! embedders can inject build metadata or strip internal fields without
+//! re-parsing the JSON output. Transforms are registered on a loader with
+//! [`NickelLoader::with_transform`](crate::NickelLoader::with_transform) and
+//! run in registration order.
+//!
+//! On the command line, `--transform SPEC` accepts:
+//!
+//! - `strip-internal[=PREFIX]`: drop record fields whose name starts with
+//!   `PREFIX` (default `_`), at any depth
+//! - `inject:KEY=VALUE`: set a top-level field; `VALUE` is parsed as JSON and
+//!   falls back to a plain string
+//! - `exec:COMMAND`: a plugin process that reads the value as JSON on stdin
+//!   and writes the transformed value as JSON on stdout
+//! - `mask-values`: replace every leaf value with a placeholder naming its
+//!   type (see [`MaskValues`])
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::transform::{Inject, StripInternal};
+//! use bunsenite::NickelLoader;
+//! use serde_json::json;
+//!
+//! let loader = NickelLoader::new()
+//!     .with_transform(StripInternal::default())
+//!     .with_transform(Inject::new("build", json!("abc123")));
+//!
+//! let value = loader.parse_string("{ port = 80, _draft = true }", "app.ncl").unwrap();
+//! assert_eq!(value, json!({ "port": 80, "build": "abc123" }));
+//! ```
+
+use crate::error::{Error, Result};
+use crate::json;
+use crate::mask;
+use crate::pattern::PathPattern;
+use serde_json::Value;
+use std::fmt;
+use std::process::{Command, Stdio};
+
+/// A rewrite applied to an evaluated config before serialization
+pub trait Transform: fmt::Debug + Send + Sync {
+    /// Transform an evaluated value
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value cannot be transformed; the parse that
+    /// produced it then fails with this error
+    fn apply(&self, value: Value) -> Result<Value>;
+}
+
+impl<T: Transform + ?Sized> Transform for Box<T> {
+    fn apply(&self, value: Value) -> Result<Value> {
+        (**self).apply(value)
+    }
+}
+
+/// A transform backed by a closure
+pub struct FnTransform<F>(pub F);
+
+impl<F> fmt::Debug for FnTransform<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("FnTransform")
+    }
+}
+
+impl<F> Transform for FnTransform<F>
+where
+    F: Fn(Value) -> Result<Value> + Send + Sync,
+{
+    fn apply(&self, value: Value) -> Result<Value> {
+        (self.0)(value)
+    }
+}
+
+/// Drop record fields whose name starts with a prefix, at any depth
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StripInternal {
+    prefix: String,
+}
+
+impl StripInternal {
+    /// Strip fields starting with `prefix`
+    pub fn new(prefix: impl Into<String>) -> Self {
+        Self {
+            prefix: prefix.into(),
+        }
+    }
+}
+
+impl Default for StripInternal {
+    fn default() -> Self {
+        Self::new("_")
+    }
+}
+
+impl Transform for StripInternal {
+    fn apply(&self, mut value: Value) -> Result<Value> {
+        // Explicit work stack, so deep values cannot overflow the native stack
+        let mut stack = vec![&mut value];
+        while let Some(value) = stack.pop() {
+            match value {
+                Value::Object(map) => {
+                    map.retain(|key, _| !key.starts_with(&self.prefix));
+                    stack.extend(map.values_mut());
+                }
+                Value::Array(items) => stack.extend(items.iter_mut()),
+                _ => {}
+            }
+        }
+        Ok(value)
+    }
+}
+
+/// Set a top-level field of the output record
+#[derive(Debug, Clone, PartialEq)]
+pub struct Inject {
+    key: String,
+    value: Value,
+}
+
+impl Inject {
+    /// Set `key` to `value`
+    pub fn new(key: impl Into<String>, value: Value) -> Self {
+        Self {
+            key: key.into(),
+            value,
+        }
+    }
+}
+
+impl Transform for Inject {
+    fn apply(&self, mut value: Value) -> Result<Value> {
+        let map = value.as_object_mut().ok_or_else(|| {
+            Error::invalid_input(format!(
+                "Cannot inject '{}': the config is not a record",
+                self.key
+            ))
+        })?;
+        map.insert(self.key.clone(), self.value.clone());
+        Ok(value)
+    }
+}
+
+/// A plugin process speaking JSON on stdin and stdout
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Exec {
+    program: String,
+    args: Vec<String>,
+}
+
+impl Exec {
+    /// Create a plugin from a command line (program followed by arguments)
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `command` is empty
+    pub fn new(command: Vec<String>) -> Result<Self> {
+        let mut command = command.into_iter();
+        let program = command
+            .next()
+            .ok_or_else(|| Error::invalid_input("transform command is empty"))?;
+        Ok(Self {
+            program,
+            args: command.collect(),
+        })
+    }
+}
+
+impl Transform for Exec {
+    fn apply(&self, value: Value) -> Result<Value> {
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .map_err(|e| {
+                Error::invalid_input(format!("Cannot start transform '{}': {}", self.program, e))
+            })?;
+
+        // Write from a separate thread so a plugin that streams its output
+        // cannot deadlock on a full pipe
+        let mut stdin = child.stdin.take().expect("transform stdin is piped");
+        let writer = std::thread::spawn(move || crate::json::to_writer(&mut stdin, &value, false));
+
+        let output = child.wait_with_output()?;
+        let written = writer
+            .join()
+            .map_err(|_| Error::internal("transform input writer panicked"))?;
+        if !output.status.success() {
+            return Err(Error::invalid_input(format!(
+                "Transform '{}' exited with {}",
+                self.program, output.status
+            )));
+        }
+        written?;
+
+        serde_json::from_slice(&output.stdout).map_err(|e| {
+            Error::invalid_input(format!(
+                "Transform '{}' did not output valid JSON: {}",
+                self.program, e
+            ))
+        })
+    }
+}
+
+/// Keep or drop output paths by [pattern](crate::pattern)
+///
+/// With include patterns, only the selected subtrees (and the records and
+/// arrays leading to them) are kept; exclude patterns then remove subtrees
+/// from what is left. Array elements are removed in place, so later elements
+/// shift down.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PathFilter {
+    include: Vec<PathPattern>,
+    exclude: Vec<PathPattern>,
+}
+
+/// What a filter does with the subtree at a path
+enum Keep {
+    Nothing,
+    All,
+    Some { included: bool },
+}
+
+impl PathFilter {
+    /// A filter that keeps everything
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse include and exclude patterns
+    ///
+    /// # Errors
+    ///
+    /// Returns an invalid-input error for the first malformed pattern
+    pub fn parse<I1, I2, S>(include: I1, exclude: I2) -> Result<Self>
+    where
+        I1: IntoIterator<Item = S>,
+        I2: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        fn parse<S: AsRef<str>>(patterns: impl IntoIterator<Item = S>) -> Result<Vec<PathPattern>> {
+            patterns
+                .into_iter()
+                .map(|p| PathPattern::parse(p.as_ref()))
+                .collect()
+        }
+        Ok(Self {
+            include: parse(include)?,
+            exclude: parse(exclude)?,
+        })
+    }
+
+    /// Keep only paths matching `pattern` (and any other include pattern)
+    pub fn include(mut self, pattern: PathPattern) -> Self {
+        self.include.push(pattern);
+        self
+    }
+
+    /// Drop paths matching `pattern`
+    pub fn exclude(mut self, pattern: PathPattern) -> Self {
+        self.exclude.push(pattern);
+        self
+    }
+
+    /// Whether the filter keeps everything
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    fn keep(&self, path: &str) -> Keep {
+        if self.exclude.iter().any(|p| p.matches(path)) {
+            return Keep::Nothing;
+        }
+        let included = self.include.is_empty() || self.include.iter().any(|p| p.matches(path));
+        if included {
+            if self.exclude.iter().any(|p| p.matches_below(path)) {
+                Keep::Some { included }
+            } else {
+                Keep::All
+            }
+        } else if self.include.iter().any(|p| p.matches_below(path)) {
+            Keep::Some { included }
+        } else {
+            Keep::Nothing
+        }
+    }
+
+    /// Filter a value at `path`, returning whether it is kept
+    fn filter(&self, value: &mut Value, path: &str) -> bool {
+        match self.keep(path) {
+            Keep::Nothing => false,
+            Keep::All => true,
+            Keep::Some { included } => match value {
+                Value::Object(_) | Value::Array(_) => {
+                    self.filter_children(value, path);
+                    // Containers kept only to reach included paths go if
+                    // nothing under them was included
+                    included || !is_empty_container(value)
+                }
+                _ => included,
+            },
+        }
+    }
+
+    fn filter_children(&self, value: &mut Value, path: &str) {
+        match value {
+            Value::Object(map) => {
+                map.retain(|key, item| self.filter(item, &json::key_path(path, key)));
+            }
+            Value::Array(items) => {
+                let mut index = 0;
+                items.retain_mut(|item| {
+                    let keep = self.filter(item, &json::index_path(path, index));
+                    index += 1;
+                    keep
+                });
+            }
+            _ => {}
+        }
+    }
+}
+
+fn is_empty_container(value: &Value) -> bool {
+    match value {
+        Value::Object(map) => map.is_empty(),
+        Value::Array(items) => items.is_empty(),
+        _ => false,
+    }
+}
+
+impl Transform for PathFilter {
+    fn apply(&self, mut value: Value) -> Result<Value> {
+        self.filter_children(&mut value, "");
+        Ok(value)
+    }
+}
+
+/// Replace leaf values with placeholders, keeping the structure
+///
+/// Strings become `"<string>"`, numbers `"<integer>"` or `"<number>"` and
+/// booleans `"<bool>"`; `null`, record keys and array lengths are kept. The
+/// result shows a config's shape without its values, e.g. for bug reports.
+/// Errors raised while evaluating still quote the source; mask it first with
+/// [`mask_source`](crate::mask::mask_source) to keep values out of those.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MaskValues;
+
+impl MaskValues {
+    /// The placeholder for a leaf value, or `None` for containers and `null`
+    pub fn placeholder(value: &Value) -> Option<String> {
+        match value {
+            Value::String(_) => Some(mask::STRING_PLACEHOLDER.to_string()),
+            Value::Number(n) if n.is_f64() => Some("<number>".to_string()),
+            Value::Number(_) => Some("<integer>".to_string()),
+            Value::Bool(_) => Some("<bool>".to_string()),
+            Value::Null | Value::Array(_) | Value::Object(_) => None,
+        }
+    }
+}
+
+impl Transform for MaskValues {
+    fn apply(&self, mut value: Value) -> Result<Value> {
+        let mut stack = vec![&mut value];
+        while let Some(value) = stack.pop() {
+            match value {
+                Value::Object(map) => stack.extend(map.values_mut()),
+                Value::Array(items) => stack.extend(items.iter_mut()),
+                leaf => {
+                    if let Some(placeholder) = Self::placeholder(leaf) {
+                        *leaf = Value::String(placeholder);
+                    }
+                }
+            }
+        }
+        Ok(value)
+    }
+}
+
+/// Parse a `--transform` specification
+///
+/// # Errors
+///
+/// Returns an invalid-input error for unknown or malformed specifications
+pub fn parse_spec(spec: &str) -> Result<Box<dyn Transform>> {
+    if let Some(command) = spec.strip_prefix("exec:") {
+        return Ok(Box::new(Exec::new(
+            command.split_whitespace().map(str::to_string).collect(),
+        )?));
+    }
+    if let Some(assignment) = spec.strip_prefix("inject:") {
+        let (key, value) = assignment.split_once('=').ok_or_else(|| {
+            Error::invalid_input(format!(
+                "Invalid transform '{}': expected inject:KEY=VALUE",
+                spec
+            ))
+        })?;
+        let value =
+            serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()));
+        return Ok(Box::new(Inject::new(key, value)));
+    }
+    match spec.split_once('=') {
+        None if spec == "strip-internal" => Ok(Box::new(StripInternal::default())),
+        None if spec == "mask-values" => Ok(Box::new(MaskValues)),
+        Some(("strip-internal", prefix)) if !prefix.is_empty() => {
+            Ok(Box::new(StripInternal::new(prefix)))
+        }
+        _ => Err(Error::invalid_input(format!(
+            "Unknown transform '{}' (expected strip-internal[=PREFIX], mask-values, inject:KEY=VALUE or exec:COMMAND)",
+            spec
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_strip_internal_at_any_depth() {
+        let value = json!({ "_meta": 1, "a": { "_x": 2, "y": [{ "_z": 3, "w": 4 }] } });
+        let stripped = StripInternal::default().apply(value).unwrap();
+        assert_eq!(stripped, json!({ "a": { "y": [{ "w": 4 }] } }));
+
+        let stripped = StripInternal::new("internal_")
+            .apply(json!({ "internal_id": 1, "id": 2 }))
+            .unwrap();
+        assert_eq!(stripped, json!({ "id": 2 }));
+    }
+
+    #[test]
+    fn test_path_filter_include_and_exclude() {
+        let value = json!({
+            "name": "app",
+            "db": { "host": "h", "password": "p" },
+            "servers": [{ "host": "a", "key": 1 }, { "host": "b", "key": 2 }],
+            "internal": { "x": 1 },
+        });
+
+        let public = PathFilter::parse(["name", "db", "servers[*].host"], ["db.password"])
+            .unwrap()
+            .apply(value.clone())
+            .unwrap();
+        assert_eq!(
+            public,
+            json!({
+                "name": "app",
+                "db": { "host": "h" },
+                "servers": [{ "host": "a" }, { "host": "b" }],
+            })
+        );
+
+        let full = PathFilter::parse(vec![], vec!["internal", "servers[0]"])
+            .unwrap()
+            .apply(value)
+            .unwrap();
+        assert_eq!(full["servers"], json!([{ "host": "b", "key": 2 }]));
+        assert!(full.get("internal").is_none());
+    }
+
+    #[test]
+    fn test_path_filter_drops_unused_ancestors() {
+        let filter = PathFilter::new().include("a.b.c".parse().unwrap());
+        let value = json!({ "a": { "b": 1, "d": 2 }, "e": 3 });
+        assert_eq!(filter.apply(value).unwrap(), json!({}));
+    }
+
+    #[test]
+    fn test_inject_requires_record() {
+        let inject = Inject::new("version", json!("1.2.3"));
+        assert_eq!(
+            inject.apply(json!({ "a": 1 })).unwrap(),
+            json!({ "a": 1, "version": "1.2.3" })
+        );
+        assert!(inject.apply(json!([1])).is_err());
+    }
+
+    #[test]
+    fn test_parse_spec() {
+        let t = parse_spec("inject:build={\"n\": 7}").unwrap();
+        assert_eq!(t.apply(json!({})).unwrap(), json!({ "build": { "n": 7 } }));
+        let t = parse_spec("inject:commit=abc").unwrap();
+        assert_eq!(t.apply(json!({})).unwrap(), json!({ "commit": "abc" }));
+
+        assert!(parse_spec("strip-internal").is_ok());
+        assert!(parse_spec("strip-internal=tmp_").is_ok());
+        assert!(parse_spec("strip-internal=").is_err());
+        assert!(parse_spec("mask-values").is_ok());
+        assert!(parse_spec("exec:").is_err());
+        assert!(parse_spec("reverse").is_err());
+    }
+
+    #[test]
+    fn test_mask_values_keeps_structure() {
+        let value = json!({
+            "name": "acme-prod",
+            "replicas": 3,
+            "ratio": 0.5,
+            "tls": true,
+            "backup": null,
+            "hosts": ["a.example", "b"],
+        });
+        assert_eq!(
+            MaskValues.apply(value).unwrap(),
+            json!({
+                "name": "<string>",
+                "replicas": "<integer>",
+                "ratio": "<number>",
+                "tls": "<bool>",
+                "backup": null,
+                "hosts": ["<string>", "<string>"],
+            })
+        );
+    }
+
+    #[test]
+    fn test_closures_and_loader_order() {
+        let loader = crate::NickelLoader::new()
+            .with_transform(Inject::new("_tmp", json!(1)))
+            .with_transform(StripInternal::default())
+            .with_transform(FnTransform(|v: Value| Ok(json!({ "wrapped": v }))));
+        let value = loader.parse_string("{ a = 1 }", "t.ncl").unwrap();
+        assert_eq!(value, json!({ "wrapped": { "a": 1 } }));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_exec_plugin() {
+        let plugin = Exec::new(vec!["cat".to_string()]).unwrap();
+        assert_eq!(
+            plugin.apply(json!({ "a": [1] })).unwrap(),
+            json!({ "a": [1] })
+        );
+
+        let failing = Exec::new(vec!["false".to_string()]).unwrap();
+        assert!(failing.apply(json!({})).is_err());
+    }
+}
@@ -0,0 +1,607 @@
+//! Evaluated output checked against published schemas
+//!
+//! Backs `bunsenite validate --schema`: downstream teams publish what they
+//! expect of a config in different schema languages, so an evaluated config
+//! can be checked against any of
+//!
+//! - a JSON Schema document (`.json`), using the validation keywords of
+//!   drafts 7 to 2020-12 with local `$ref`s; `pattern`,
+//!   `patternProperties` and `format` are not checked
+//! - a CUE definition (`.cue`), see [`crate::cue`]
+//! - a message in a Protobuf descriptor set (`.pb`, `.binpb`, `.desc` or
+//!   `.protoset`, as written by `protoc --descriptor_set_out`), checked
+//!   against its JSON mapping, see [`crate::protobuf`]
+//!
+//! A schema is named `FILE`, or `FILE#NAME` to pick the CUE definition or
+//! Protobuf message to check against when the file declares several.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::validation::Schema;
+//! use serde_json::json;
+//!
+//! let schema = Schema::json_schema(json!({
+//!     "type": "object",
+//!     "properties": { "port": { "type": "integer", "maximum": 65535 } },
+//!     "required": ["port", "host"],
+//! }))
+//! .unwrap();
+//! let violations: Vec<String> = schema
+//!     .check(&json!({ "port": 70000 }))
+//!     .iter()
+//!     .map(|v| v.to_string())
+//!     .collect();
+//! assert_eq!(
+//!     violations,
+//!     vec!["host: required field is missing", "port: 70000 is greater than the maximum 65535"]
+//! );
+//! ```
+
+use crate::cue;
+use crate::error::{Error, Result};
+use crate::json;
+use crate::protobuf;
+use serde_json::{Map, Value};
+use std::fmt;
+use std::path::Path;
+
+/// `$ref`s followed without descending into the value before a cycle is
+/// assumed
+const MAX_REF_HOPS: usize = 32;
+
+/// A value found not to match a schema
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    /// Path of the value, in [`json::key_path`] syntax (empty for the root)
+    pub path: String,
+    /// What is wrong with it
+    pub message: String,
+}
+
+impl Violation {
+    /// A violation at `path`
+    pub fn new(path: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = if self.path.is_empty() {
+            "."
+        } else {
+            self.path.as_str()
+        };
+        write!(f, "{}: {}", path, self.message)
+    }
+}
+
+/// A schema to check evaluated output against
+#[derive(Debug, Clone)]
+pub struct Schema {
+    kind: Kind,
+}
+
+#[derive(Debug, Clone)]
+enum Kind {
+    JsonSchema(Value),
+    Cue(cue::Definitions, String),
+    Protobuf(protobuf::DescriptorSet, String),
+}
+
+impl Schema {
+    /// A JSON Schema document
+    ///
+    /// # Errors
+    ///
+    /// Returns an invalid-input error if the schema is neither an object nor
+    /// a boolean, or refers to another document with `$ref`
+    pub fn json_schema(schema: Value) -> Result<Self> {
+        if !schema.is_object() && !schema.is_boolean() {
+            return Err(Error::invalid_input(
+                "A JSON Schema must be an object or a boolean",
+            ));
+        }
+        if let Some(reference) = remote_ref(&schema) {
+            return Err(Error::invalid_input(format!(
+                "Only local $refs are supported, not {}",
+                reference
+            )));
+        }
+        Ok(Self {
+            kind: Kind::JsonSchema(schema),
+        })
+    }
+
+    /// The CUE definition `name` (with or without its `#`) from `source`,
+    /// or its only definition if `name` is `None`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the source is malformed or outside the supported
+    /// subset, or the definition is missing or ambiguous
+    pub fn cue(source: &str, name: Option<&str>) -> Result<Self> {
+        let definitions = cue::Definitions::parse(source)?;
+        let name = definitions.resolve(name)?;
+        Ok(Self {
+            kind: Kind::Cue(definitions, name),
+        })
+    }
+
+    /// The Protobuf message `name` (fully qualified, e.g. `app.v1.Config`)
+    /// from an encoded `FileDescriptorSet`, or its only top-level message if
+    /// `name` is `None`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the descriptor set is malformed, or the message
+    /// is missing or ambiguous
+    pub fn protobuf(descriptor_set: &[u8], name: Option<&str>) -> Result<Self> {
+        let descriptors = protobuf::DescriptorSet::decode(descriptor_set)?;
+        let name = descriptors.resolve(name)?;
+        Ok(Self {
+            kind: Kind::Protobuf(descriptors, name),
+        })
+    }
+
+    /// Load the schema named by `spec`, `FILE` or `FILE#NAME`, with its
+    /// language chosen by the file extension
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read, its extension is not
+    /// recognised, or it does not hold a valid schema
+    pub fn load(spec: &str) -> Result<Self> {
+        let (file, name) = match spec.split_once('#') {
+            Some((file, name)) => (file, Some(name)),
+            None => (spec, None),
+        };
+        let path = Path::new(file);
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+
+        match extension.as_str() {
+            "json" => {
+                if name.is_some() {
+                    return Err(Error::invalid_input(format!(
+                        "{}: JSON Schemas take no #NAME; use $defs with $ref instead",
+                        file
+                    )));
+                }
+                let schema = serde_json::from_str(&std::fs::read_to_string(path)?)
+                    .map_err(|e| Error::parse_error(file, e.to_string()))?;
+                Self::json_schema(schema)
+            }
+            "cue" => Self::cue(&std::fs::read_to_string(path)?, name),
+            "pb" | "binpb" | "desc" | "protoset" => Self::protobuf(&std::fs::read(path)?, name),
+            _ => Err(Error::invalid_input(format!(
+                "{}: unknown schema language; expected .json (JSON Schema), .cue (CUE) \
+                 or .pb/.binpb/.desc/.protoset (Protobuf descriptor set)",
+                file
+            ))),
+        }
+    }
+
+    /// The ways `value` fails to match the schema, in path order
+    pub fn check(&self, value: &Value) -> Vec<Violation> {
+        let mut violations = match &self.kind {
+            Kind::JsonSchema(schema) => {
+                let mut violations = Vec::new();
+                check_json_schema(schema, schema, value, "", 0, &mut violations);
+                violations
+            }
+            Kind::Cue(definitions, name) => definitions.check(name, value),
+            Kind::Protobuf(descriptors, name) => descriptors.check(name, value),
+        };
+        violations.sort_by(|a, b| a.path.cmp(&b.path));
+        violations
+    }
+}
+
+/// The first `$ref` in `schema` that points outside it
+fn remote_ref(schema: &Value) -> Option<&str> {
+    match schema {
+        Value::Object(map) => map
+            .get("$ref")
+            .and_then(Value::as_str)
+            .filter(|reference| !reference.starts_with('#'))
+            .or_else(|| map.values().find_map(remote_ref)),
+        Value::Array(items) => items.iter().find_map(remote_ref),
+        _ => None,
+    }
+}
+
+/// Check `value` at `path` against `schema`, a subschema of `root`
+fn check_json_schema(
+    root: &Value,
+    schema: &Value,
+    value: &Value,
+    path: &str,
+    hops: usize,
+    out: &mut Vec<Violation>,
+) {
+    let map = match schema {
+        Value::Bool(true) => return,
+        Value::Bool(false) => {
+            out.push(Violation::new(path, "no value is allowed here"));
+            return;
+        }
+        Value::Object(map) => map,
+        _ => return,
+    };
+    let at = |message: String| Violation::new(path, message);
+
+    if let Some(reference) = map.get("$ref").and_then(Value::as_str) {
+        match root.pointer(&reference[1..]) {
+            _ if hops >= MAX_REF_HOPS => out.push(at(format!("$ref cycle at {}", reference))),
+            Some(target) => check_json_schema(root, target, value, path, hops + 1, out),
+            None => out.push(at(format!("$ref {} points to nothing", reference))),
+        }
+    }
+
+    if let Some(types) = map.get("type") {
+        let types: Vec<&str> = match types {
+            Value::String(name) => vec![name.as_str()],
+            Value::Array(names) => names.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        if !types.is_empty() && !types.iter().any(|name| has_type(value, name)) {
+            out.push(at(format!(
+                "expected {}, found {}",
+                types.join(" or "),
+                type_name(value)
+            )));
+            return;
+        }
+    }
+    if let Some(allowed) = map.get("enum").and_then(Value::as_array) {
+        if !allowed.iter().any(|candidate| same(candidate, value)) {
+            let allowed: Vec<String> = allowed.iter().map(|v| json::to_string(v, false)).collect();
+            out.push(at(format!(
+                "{} is not one of {}",
+                json::to_string(value, false),
+                allowed.join(", ")
+            )));
+        }
+    }
+    if let Some(expected) = map.get("const") {
+        if !same(expected, value) {
+            out.push(at(format!(
+                "expected {}, found {}",
+                json::to_string(expected, false),
+                json::to_string(value, false)
+            )));
+        }
+    }
+
+    match value {
+        Value::Number(number) => check_number(map, number, path, out),
+        Value::String(string) => {
+            let length = string.chars().count();
+            check_count(
+                map,
+                "minLength",
+                "maxLength",
+                length,
+                "character",
+                path,
+                out,
+            );
+        }
+        Value::Array(items) => check_array(root, map, items, path, out),
+        Value::Object(fields) => check_object(root, map, fields, path, out),
+        _ => {}
+    }
+
+    for key in ["allOf", "anyOf", "oneOf"] {
+        let Some(schemas) = map.get(key).and_then(Value::as_array) else {
+            continue;
+        };
+        if key == "allOf" {
+            for schema in schemas {
+                check_json_schema(root, schema, value, path, hops, out);
+            }
+            continue;
+        }
+        let matches = schemas
+            .iter()
+            .filter(|schema| matches_json_schema(root, schema, value, path, hops))
+            .count();
+        if matches == 0 {
+            out.push(at(format!("matches none of the `{}` schemas", key)));
+        } else if key == "oneOf" && matches > 1 {
+            out.push(at(format!("matches {} of the `oneOf` schemas", matches)));
+        }
+    }
+    if let Some(schema) = map.get("not") {
+        if matches_json_schema(root, schema, value, path, hops) {
+            out.push(at("must not match the `not` schema".to_string()));
+        }
+    }
+}
+
+/// Whether `value` matches `schema` without violations
+fn matches_json_schema(
+    root: &Value,
+    schema: &Value,
+    value: &Value,
+    path: &str,
+    hops: usize,
+) -> bool {
+    let mut violations = Vec::new();
+    check_json_schema(root, schema, value, path, hops, &mut violations);
+    violations.is_empty()
+}
+
+fn check_number(
+    map: &Map<String, Value>,
+    number: &serde_json::Number,
+    path: &str,
+    out: &mut Vec<Violation>,
+) {
+    let bound = |key: &str| map.get(key).and_then(Value::as_f64);
+    let mut fail = |message: String| out.push(Violation::new(path, message));
+    let shown = number.to_string();
+    let number = number.as_f64().unwrap_or_default();
+
+    if let Some(minimum) = bound("minimum").filter(|minimum| number < *minimum) {
+        fail(format!("{} is less than the minimum {}", shown, minimum));
+    }
+    if let Some(maximum) = bound("maximum").filter(|maximum| number > *maximum) {
+        fail(format!("{} is greater than the maximum {}", shown, maximum));
+    }
+    if let Some(minimum) = bound("exclusiveMinimum").filter(|minimum| number <= *minimum) {
+        fail(format!("{} must be greater than {}", shown, minimum));
+    }
+    if let Some(maximum) = bound("exclusiveMaximum").filter(|maximum| number >= *maximum) {
+        fail(format!("{} must be less than {}", shown, maximum));
+    }
+    if let Some(factor) = bound("multipleOf").filter(|factor| *factor > 0.0) {
+        let quotient = number / factor;
+        if (quotient - quotient.round()).abs() > 1e-9 {
+            fail(format!("{} is not a multiple of {}", shown, factor));
+        }
+    }
+}
+
+fn check_count(
+    map: &Map<String, Value>,
+    min_key: &str,
+    max_key: &str,
+    count: usize,
+    unit: &str,
+    path: &str,
+    out: &mut Vec<Violation>,
+) {
+    let bound = |key: &str| map.get(key).and_then(Value::as_u64);
+    if let Some(min) = bound(min_key).filter(|min| (count as u64) < *min) {
+        out.push(Violation::new(
+            path,
+            format!("has {} {}(s), fewer than {}", count, unit, min),
+        ));
+    }
+    if let Some(max) = bound(max_key).filter(|max| (count as u64) > *max) {
+        out.push(Violation::new(
+            path,
+            format!("has {} {}(s), more than {}", count, unit, max),
+        ));
+    }
+}
+
+fn check_array(
+    root: &Value,
+    map: &Map<String, Value>,
+    items: &[Value],
+    path: &str,
+    out: &mut Vec<Violation>,
+) {
+    check_count(map, "minItems", "maxItems", items.len(), "item", path, out);
+
+    // Draft 2020-12 `prefixItems` + `items`, or the older array form of
+    // `items` + `additionalItems`
+    let (prefix, rest) = match (map.get("prefixItems"), map.get("items")) {
+        (Some(Value::Array(prefix)), rest) => (prefix.as_slice(), rest),
+        (None, Some(Value::Array(prefix))) => (prefix.as_slice(), map.get("additionalItems")),
+        (_, rest) => (&[][..], rest),
+    };
+    for (index, item) in items.iter().enumerate() {
+        let schema = prefix.get(index).or(rest);
+        if let Some(schema) = schema {
+            let item_path = json::index_path(path, index);
+            check_json_schema(root, schema, item, &item_path, 0, out);
+        }
+    }
+
+    if map.get("uniqueItems") == Some(&Value::Bool(true)) {
+        for (index, item) in items.iter().enumerate() {
+            if items[..index].iter().any(|earlier| same(earlier, item)) {
+                out.push(Violation::new(
+                    json::index_path(path, index),
+                    "duplicates an earlier item",
+                ));
+            }
+        }
+    }
+}
+
+fn check_object(
+    root: &Value,
+    map: &Map<String, Value>,
+    fields: &Map<String, Value>,
+    path: &str,
+    out: &mut Vec<Violation>,
+) {
+    check_count(
+        map,
+        "minProperties",
+        "maxProperties",
+        fields.len(),
+        "field",
+        path,
+        out,
+    );
+
+    if let Some(required) = map.get("required").and_then(Value::as_array) {
+        for name in required.iter().filter_map(Value::as_str) {
+            if !fields.contains_key(name) {
+                out.push(Violation::new(
+                    json::key_path(path, name),
+                    "required field is missing",
+                ));
+            }
+        }
+    }
+
+    let properties = map.get("properties").and_then(Value::as_object);
+    let additional = map.get("additionalProperties");
+    for (name, field) in fields {
+        let field_path = json::key_path(path, name);
+        match properties
+            .and_then(|properties| properties.get(name))
+            .or(additional)
+        {
+            Some(Value::Bool(false)) if additional == Some(&Value::Bool(false)) => {
+                out.push(Violation::new(field_path, "field is not allowed"));
+            }
+            Some(schema) => check_json_schema(root, schema, field, &field_path, 0, out),
+            None => {}
+        }
+    }
+}
+
+/// Whether `value` is of the JSON Schema type `name`
+fn has_type(value: &Value, name: &str) -> bool {
+    match (name, value) {
+        ("null", Value::Null)
+        | ("boolean", Value::Bool(_))
+        | ("number", Value::Number(_))
+        | ("string", Value::String(_))
+        | ("array", Value::Array(_))
+        | ("object", Value::Object(_)) => true,
+        ("integer", Value::Number(number)) => {
+            number.is_i64() || number.is_u64() || number.as_f64().is_some_and(|n| n.fract() == 0.0)
+        }
+        _ => false,
+    }
+}
+
+/// The JSON Schema type of `value`, for messages
+pub(crate) fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(number) if number.is_i64() || number.is_u64() => "integer",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// JSON equality, with numbers compared by value (`1` equals `1.0`)
+pub(crate) fn same(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64() == b.as_f64(),
+        (Value::Array(a), Value::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| same(a, b))
+        }
+        (Value::Object(a), Value::Object(b)) => {
+            a.len() == b.len()
+                && a.iter()
+                    .all(|(key, a)| b.get(key).is_some_and(|b| same(a, b)))
+        }
+        _ => a == b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn violations(schema: Value, value: Value) -> Vec<String> {
+        Schema::json_schema(schema)
+            .unwrap()
+            .check(&value)
+            .iter()
+            .map(Violation::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_json_schema_refs_and_combinators() {
+        let schema = json!({
+            "$defs": {
+                "port": { "type": "integer", "minimum": 1, "maximum": 65535 },
+            },
+            "type": "object",
+            "properties": {
+                "ports": { "type": "array", "items": { "$ref": "#/$defs/port" }, "uniqueItems": true },
+                "mode": { "enum": ["dev", "prod"] },
+                "replicas": { "oneOf": [{ "const": 1 }, { "type": "integer", "minimum": 3 }] },
+            },
+            "additionalProperties": false,
+        });
+        assert_eq!(
+            violations(
+                schema.clone(),
+                json!({ "ports": [80, 0, 80], "mode": "qa", "replicas": 2, "extra": true }),
+            ),
+            vec![
+                "extra: field is not allowed",
+                r#"mode: "qa" is not one of "dev", "prod""#,
+                "ports[1]: 0 is less than the minimum 1",
+                "ports[2]: duplicates an earlier item",
+                "replicas: matches none of the `oneOf` schemas",
+            ]
+        );
+        assert!(violations(
+            schema,
+            json!({ "ports": [443], "mode": "prod", "replicas": 1 })
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn test_json_schema_types_and_lengths() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "name": { "type": "string", "minLength": 2 },
+                "tags": { "type": "array", "maxItems": 1 },
+                "ratio": { "type": ["number", "null"], "exclusiveMaximum": 1 },
+            },
+        });
+        assert_eq!(
+            violations(
+                schema,
+                json!({ "name": "a", "tags": ["x", "y"], "ratio": "half" })
+            ),
+            vec![
+                "name: has 1 character(s), fewer than 2",
+                r#"ratio: expected number or null, found string"#,
+                "tags: has 2 item(s), more than 1",
+            ]
+        );
+        assert_eq!(
+            violations(json!(false), json!(1)),
+            vec![".: no value is allowed here"]
+        );
+    }
+
+    #[test]
+    fn test_load_rejects_unknown_languages_and_remote_refs() {
+        let err = Schema::load("schema.xsd").unwrap_err();
+        assert!(err.to_string().contains("unknown schema language"));
+
+        let err = Schema::json_schema(json!({ "$ref": "https://example.com/s.json" })).unwrap_err();
+        assert!(err.to_string().contains("Only local $refs"));
+    }
+}
@@ -0,0 +1,338 @@
+//! Vendoring a config's imports
+//!
+//! Backs `bunsenite vendor`: every file a config transitively imports is
+//! copied into one directory, so that the copy evaluates on its own, on a
+//! machine without the original tree, or after it has changed.
+//!
+//! Files keep their places relative to each other, under the deepest
+//! directory that holds them all, so relative imports resolve in the copy
+//! as they did in the original. Absolute imports are rewritten to relative
+//! ones. The copy records where each file came from in [`MANIFEST`]:
+//!
+//! ```json
+//! {
+//!   "entry": "app/main.ncl",
+//!   "files": [
+//!     { "path": "app/main.ncl", "sha256": "…", "source": "/src/configs/app/main.ncl" },
+//!     { "path": "lib/base.ncl", "sha256": "…", "source": "/src/configs/lib/base.ncl" }
+//!   ],
+//!   "rewritten": 0
+//! }
+//! ```
+//!
+//! Imports are found as [`imports::scan`] finds them, so an import that is
+//! only reached in a branch evaluation never takes is vendored too, and
+//! every import must be readable.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::vendor;
+//! use bunsenite::NickelLoader;
+//!
+//! let dir = tempfile::tempdir().unwrap();
+//! std::fs::create_dir(dir.path().join("lib")).unwrap();
+//! std::fs::write(dir.path().join("lib/base.ncl"), "{ replicas = 3 }").unwrap();
+//! std::fs::write(dir.path().join("main.ncl"), r#"import "lib/base.ncl""#).unwrap();
+//!
+//! let out = dir.path().join("vendor");
+//! let vendored = vendor::vendor(&dir.path().join("main.ncl"), &out, 1).unwrap();
+//! assert_eq!(vendored.files.len(), 2);
+//!
+//! let config = NickelLoader::new().parse_file(&vendored.entry).unwrap();
+//! assert_eq!(config["replicas"], 3);
+//! ```
+
+use crate::error::{Error, Result};
+use crate::imports;
+use crate::json;
+use crate::output;
+use crate::paths;
+use crate::source;
+use crate::target::sha256_hex;
+use serde_json::{json, Value};
+use std::path::{Component, Path, PathBuf};
+
+/// Name of the file recording what was vendored, in the vendor directory
+pub const MANIFEST: &str = "vendor.json";
+
+/// One file copied into a vendor directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VendoredFile {
+    /// Where it is, relative to the vendor directory
+    pub path: PathBuf,
+    /// Where it was copied from
+    pub source: PathBuf,
+    /// Hex SHA-256 of the copy, after any rewriting
+    pub sha256: String,
+}
+
+/// What [`vendor`] did
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Vendored {
+    /// The copy of the config, to evaluate instead of the original
+    pub entry: PathBuf,
+    /// Every file copied, the config first
+    pub files: Vec<VendoredFile>,
+    /// Number of absolute imports rewritten to relative ones
+    pub rewritten: usize,
+}
+
+impl Vendored {
+    /// The [`MANIFEST`] contents, with `entry` relative to the vendor
+    /// directory `out`
+    pub fn to_json(&self, out: &Path) -> Value {
+        let entry = self.entry.strip_prefix(out).unwrap_or(&self.entry);
+        json!({
+            "entry": slashed(entry),
+            "files": self.files.iter().map(|file| json!({
+                "path": slashed(&file.path),
+                "source": file.source.display().to_string(),
+                "sha256": file.sha256,
+            })).collect::<Vec<_>>(),
+            "rewritten": self.rewritten,
+        })
+    }
+}
+
+/// Copy `entry` and every file it transitively imports into `out`, reading
+/// the import tree on up to `threads` threads
+///
+/// Files already in `out` are replaced.
+///
+/// # Errors
+///
+/// Returns an invalid-input error if an import cannot be read or no
+/// directory holds every file (as when they are on different Windows
+/// drives), or an I/O error
+pub fn vendor(entry: &Path, out: &Path, threads: usize) -> Result<Vendored> {
+    let absolute = imports::normalize(&std::env::current_dir()?.join(entry));
+    let graph = imports::prefetch(&absolute, threads);
+    if graph.files.is_empty() {
+        // The config itself is unreadable; report why
+        std::fs::read(entry)?;
+    }
+    if !graph.missing.is_empty() {
+        let missing: Vec<String> = graph
+            .missing
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect();
+        return Err(Error::invalid_input(format!(
+            "Cannot vendor {}: {} import(s) cannot be read: {}",
+            entry.display(),
+            missing.len(),
+            missing.join(", ")
+        )));
+    }
+
+    let root = common_root(&graph.files)?;
+    let mut vendored = Vendored {
+        entry: out.join(relative(&graph.files[0], &root)?),
+        files: Vec::with_capacity(graph.files.len()),
+        rewritten: 0,
+    };
+    for file in &graph.files {
+        let path = relative(file, &root)?;
+        let mut contents = std::fs::read(paths::to_open(file))?;
+        if imports::is_nickel(file) {
+            let (rewritten, count) =
+                rewrite_imports(&String::from_utf8_lossy(&contents), &path, &root)?;
+            if count > 0 {
+                contents = rewritten.into_bytes();
+                vendored.rewritten += count;
+            }
+        }
+
+        let dest = out.join(&path);
+        if let Some(parent) = dest.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        output::write_atomic(&dest, &contents, None)?;
+        vendored.files.push(VendoredFile {
+            path,
+            source: file.clone(),
+            sha256: sha256_hex(&contents),
+        });
+    }
+
+    let manifest = format!("{}\n", json::to_string(&vendored.to_json(out), true));
+    output::write_atomic(&out.join(MANIFEST), manifest.as_bytes(), None)?;
+    Ok(vendored)
+}
+
+/// `source`, vendored at `path`, with its absolute imports made relative;
+/// and how many there were
+///
+/// # Errors
+///
+/// As [`relative`], for an import outside `root`
+fn rewrite_imports(source: &str, path: &Path, root: &Path) -> Result<(String, usize)> {
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let mut rewritten = source.to_string();
+    let mut count = 0;
+    // From the end, so earlier ranges stay valid
+    for (range, import) in imports::scan_literals(source).into_iter().rev() {
+        let import = Path::new(&import);
+        if !import.is_absolute() {
+            continue;
+        }
+        let target = relative(&imports::normalize(import), root)?;
+        let literal = source::string_literal(&path_between(dir, &target));
+        rewritten.replace_range(range, &literal);
+        count += 1;
+    }
+    Ok((rewritten, count))
+}
+
+/// The deepest directory holding every one of `files`
+///
+/// # Errors
+///
+/// Returns an invalid-input error if no directory holds them all, as for
+/// files on different Windows drives
+fn common_root(files: &[PathBuf]) -> Result<PathBuf> {
+    let mut root = files[0].parent().unwrap_or(Path::new("")).to_path_buf();
+    for file in &files[1..] {
+        while !file.starts_with(&root) && root.pop() {}
+        if root.as_os_str().is_empty() {
+            return Err(Error::invalid_input(format!(
+                "Cannot vendor {} and {}: no directory holds both",
+                files[0].display(),
+                file.display()
+            )));
+        }
+    }
+    Ok(root)
+}
+
+/// `path` relative to `root`
+///
+/// # Errors
+///
+/// Returns an invalid-input error unless `path` is inside `root`, so that
+/// joining the result to the vendor directory stays inside it
+fn relative(path: &Path, root: &Path) -> Result<PathBuf> {
+    match path.strip_prefix(root) {
+        Ok(relative)
+            if relative
+                .components()
+                .all(|part| matches!(part, Component::Normal(_))) =>
+        {
+            Ok(relative.to_path_buf())
+        }
+        _ => Err(Error::invalid_input(format!(
+            "Cannot vendor {}: it is outside {}",
+            path.display(),
+            root.display()
+        ))),
+    }
+}
+
+/// The relative import, with `/` separators, from a file in `dir` to
+/// `target`, both relative to the same directory
+fn path_between(dir: &Path, target: &Path) -> String {
+    let from: Vec<_> = dir.components().collect();
+    let to: Vec<_> = target.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+    let mut parts = vec!["..".to_string(); from.len() - common];
+    parts.extend(
+        to[common..]
+            .iter()
+            .map(|part| part.as_os_str().to_string_lossy().into_owned()),
+    );
+    parts.join("/")
+}
+
+fn slashed(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NickelLoader;
+
+    #[test]
+    fn test_vendor_is_self_contained() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        std::fs::create_dir_all(src.join("app")).unwrap();
+        std::fs::create_dir_all(src.join("shared")).unwrap();
+        let shared = src.join("shared/ports.json");
+        std::fs::write(&shared, r#"{ "http": 80 }"#).unwrap();
+        std::fs::write(
+            src.join("shared/base.ncl"),
+            r#"{ name = "web", ports = import "ports.json" }"#,
+        )
+        .unwrap();
+        std::fs::write(
+            src.join("app/main.ncl"),
+            format!(
+                "let base = import \"../shared/base.ncl\" in\nbase & {{ again = import {} }}",
+                source::string_literal(&shared.to_string_lossy())
+            ),
+        )
+        .unwrap();
+
+        let loader = NickelLoader::new();
+        let expected = loader.parse_file(src.join("app/main.ncl")).unwrap();
+        let out = dir.path().join("vendor");
+        let vendored = vendor(&src.join("app/main.ncl"), &out, 2).unwrap();
+        assert_eq!(vendored.entry, out.join("app/main.ncl"));
+        assert_eq!(vendored.files.len(), 3);
+        assert_eq!(vendored.rewritten, 1);
+
+        std::fs::remove_dir_all(&src).unwrap();
+        assert_eq!(loader.parse_file(&vendored.entry).unwrap(), expected);
+
+        let manifest: Value =
+            serde_json::from_str(&std::fs::read_to_string(out.join(MANIFEST)).unwrap()).unwrap();
+        assert_eq!(manifest["entry"], "app/main.ncl");
+        assert_eq!(manifest["files"][0]["path"], "app/main.ncl");
+    }
+
+    #[test]
+    fn test_vendor_missing_import() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = dir.path().join("main.ncl");
+        std::fs::write(&main, r#"import "gone.ncl""#).unwrap();
+        let error = vendor(&main, &dir.path().join("vendor"), 1).unwrap_err();
+        assert!(error.to_string().contains("gone.ncl"), "{}", error);
+        assert!(!dir.path().join("vendor").exists());
+    }
+
+    #[test]
+    fn test_paths_stay_inside_the_root() {
+        // As for files on two Windows drives, which share no directory
+        let files = [PathBuf::from("c/app/main.ncl"), PathBuf::from("d/lib.ncl")];
+        let error = common_root(&files).unwrap_err();
+        assert!(
+            error.to_string().contains("no directory holds both"),
+            "{}",
+            error
+        );
+
+        let root = Path::new("/src/app");
+        assert_eq!(
+            relative(Path::new("/src/app/env/a.ncl"), root).unwrap(),
+            Path::new("env/a.ncl")
+        );
+        for outside in ["/src/lib.ncl", "/src/app/../../etc/passwd", "other/a.ncl"] {
+            assert!(relative(Path::new(outside), root).is_err(), "{}", outside);
+        }
+    }
+
+    #[test]
+    fn test_path_between() {
+        assert_eq!(
+            path_between(Path::new("app"), Path::new("app/a.ncl")),
+            "a.ncl"
+        );
+        assert_eq!(
+            path_between(Path::new("app/env"), Path::new("lib/b.ncl")),
+            "../../lib/b.ncl"
+        );
+        assert_eq!(path_between(Path::new(""), Path::new("c.ncl")), "c.ncl");
+    }
+}
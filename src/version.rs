@@ -0,0 +1,205 @@
+//! Embedded Nickel version reporting
+//!
+//! Bunsenite pins a single `nickel-lang-core` release, and with it a Nickel
+//! language version. Configs evaluated by toolchains embedding different
+//! Nickel versions can silently produce different results, so this module
+//! reports exactly what is embedded and lets callers require a minimum
+//! language version up front. [`BUILD_INFO`] adds the target and cargo
+//! profile of the build, to tell static release binaries from others.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::VERSION_INFO;
+//!
+//! assert!(VERSION_INFO.satisfies(">=1.0").unwrap());
+//! assert!(!VERSION_INFO.satisfies("<1.0").unwrap());
+//! ```
+
+use crate::error::{Error, Result};
+use semver::{Version, VersionReq};
+use serde::Serialize;
+
+/// Versions of Bunsenite and the Nickel implementation it embeds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct VersionInfo {
+    /// Bunsenite library version
+    pub bunsenite: &'static str,
+    /// Version of the embedded `nickel-lang-core` crate, pinned exactly in
+    /// `Cargo.toml`
+    pub nickel_core: &'static str,
+    /// Nickel language version implemented by `nickel_core`
+    pub nickel_language: &'static str,
+    /// Language features supported by the embedded engine
+    pub features: &'static [&'static str],
+}
+
+/// Version information for this build
+pub const VERSION_INFO: VersionInfo = VersionInfo {
+    bunsenite: crate::VERSION,
+    nickel_core: "0.9.1",
+    nickel_language: "1.8.0",
+    features: &[
+        "static-typing",
+        "contracts",
+        "custom-contracts",
+        "merge-priorities",
+        "string-interpolation",
+        "enum-variants",
+        "pattern-matching",
+        "import-json",
+        "import-yaml",
+        "import-toml",
+    ],
+};
+
+/// How this binary or library was built
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct BuildInfo {
+    /// Target triple, e.g. `x86_64-unknown-linux-musl`
+    pub target: &'static str,
+    /// Cargo profile, e.g. `release` or `static`
+    pub profile: &'static str,
+    /// Whether the C runtime is linked statically (`+crt-static`), leaving
+    /// no dynamic library dependencies
+    pub static_linking: bool,
+}
+
+/// Target and profile of this build
+pub const BUILD_INFO: BuildInfo = BuildInfo {
+    target: env!("BUNSENITE_TARGET"),
+    profile: env!("BUNSENITE_PROFILE"),
+    static_linking: cfg!(target_feature = "crt-static"),
+};
+
+impl BuildInfo {
+    /// `static` or `dynamic`, naming how the C runtime is linked
+    pub fn linkage(&self) -> &'static str {
+        if self.static_linking {
+            "static"
+        } else {
+            "dynamic"
+        }
+    }
+
+    /// Build information as a JSON value
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::to_value(self).expect("build info is always serializable")
+    }
+}
+
+impl VersionInfo {
+    /// Whether the embedded Nickel language version satisfies a requirement
+    ///
+    /// Requirements use Cargo's syntax, e.g. `">=1.5"` or `">=1.5, <2"`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the requirement cannot be parsed
+    pub fn satisfies(&self, requirement: &str) -> Result<bool> {
+        let req = VersionReq::parse(requirement.trim()).map_err(|e| {
+            Error::invalid_input(format!(
+                "Invalid Nickel version requirement '{}': {}",
+                requirement, e
+            ))
+        })?;
+        let version = Version::parse(self.nickel_language)
+            .map_err(|e| Error::internal(format!("Invalid embedded Nickel version: {}", e)))?;
+
+        Ok(req.matches(&version))
+    }
+
+    /// Fail unless the embedded Nickel language version satisfies a requirement
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::UnsupportedNickelVersion`] if the requirement is not
+    /// met, or an invalid-input error if it cannot be parsed
+    pub fn require(&self, requirement: &str) -> Result<()> {
+        if self.satisfies(requirement)? {
+            Ok(())
+        } else {
+            Err(Error::UnsupportedNickelVersion {
+                required: requirement.trim().to_string(),
+                embedded: self.nickel_language.to_string(),
+            })
+        }
+    }
+
+    /// Whether the engine supports a named language feature
+    pub fn supports(&self, feature: &str) -> bool {
+        self.features.contains(&feature)
+    }
+
+    /// Version information as a JSON value
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::to_value(self).expect("version info is always serializable")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_embedded_versions_parse() {
+        assert!(Version::parse(VERSION_INFO.nickel_core).is_ok());
+        assert!(Version::parse(VERSION_INFO.nickel_language).is_ok());
+        assert_eq!(VERSION_INFO.bunsenite, crate::VERSION);
+    }
+
+    #[test]
+    fn test_nickel_core_matches_manifest() {
+        let pin = format!("\"={}\"", VERSION_INFO.nickel_core);
+        let manifest = include_str!("../Cargo.toml");
+        assert!(
+            manifest
+                .lines()
+                .any(|line| line.starts_with("nickel-lang-core =") && line.contains(&pin)),
+            "Cargo.toml must pin nickel-lang-core to {}",
+            pin
+        );
+    }
+
+    #[test]
+    fn test_requirements() {
+        assert!(VERSION_INFO.satisfies(">=1.5").unwrap());
+        assert!(VERSION_INFO.satisfies(">=1.5, <2").unwrap());
+        assert!(!VERSION_INFO.satisfies(">=99").unwrap());
+        assert!(VERSION_INFO.satisfies("not a version").is_err());
+    }
+
+    #[test]
+    fn test_require_reports_versions() {
+        let err = VERSION_INFO.require(">=99.0").unwrap_err();
+        let msg = err.to_string();
+        assert!(msg.contains(">=99.0"));
+        assert!(msg.contains(VERSION_INFO.nickel_language));
+        assert!(VERSION_INFO.require(">=1.0").is_ok());
+    }
+
+    #[test]
+    fn test_build_info() {
+        assert!(BUILD_INFO.target.contains('-'));
+        assert!(!BUILD_INFO.profile.is_empty());
+
+        let build = BuildInfo {
+            static_linking: true,
+            ..BUILD_INFO
+        };
+        assert_eq!(build.linkage(), "static");
+        let json = build.to_json();
+        assert_eq!(json["static_linking"], true);
+        assert_eq!(json["target"], BUILD_INFO.target);
+    }
+
+    #[test]
+    fn test_features_and_json() {
+        assert!(VERSION_INFO.supports("contracts"));
+        assert!(!VERSION_INFO.supports("time-travel"));
+
+        let json = VERSION_INFO.to_json();
+        assert_eq!(json["nickel_core"], "0.9.1");
+        assert!(json["features"].as_array().unwrap().len() > 1);
+    }
+}
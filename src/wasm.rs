@@ -60,8 +60,46 @@ pub fn parse_nickel(source: &str, name: &str) -> Result<String, JsValue> {
         .parse_string(source, name)
         .map_err(|e| JsValue::from_str(&format!("{}", e)))?;
 
-    serde_json::to_string(&result)
-        .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
+    let json = crate::json::to_string(&result, false);
+    crate::json::drop_deep(result);
+    Ok(json)
+}
+
+/// An evaluated configuration for repeated lookups
+///
+/// Evaluates once and answers [`query`](NickelConfig::query) calls without
+/// marshalling the whole config across the boundary each time.
+///
+/// # Examples
+///
+/// ```javascript
+/// const config = new NickelConfig('{ db = { port = 5432 } }', 'config.ncl');
+/// console.log(JSON.parse(config.query('db.port'))); // 5432
+/// config.free();
+/// ```
+#[wasm_bindgen]
+#[derive(Debug)]
+pub struct NickelConfig {
+    handle: crate::ffi::Handle,
+}
+
+#[wasm_bindgen]
+impl NickelConfig {
+    /// Parse and evaluate a Nickel configuration string
+    #[wasm_bindgen(constructor)]
+    pub fn new(source: &str, name: &str) -> Result<NickelConfig, JsValue> {
+        let value = NickelLoader::new()
+            .parse_string(source, name)
+            .map_err(|e| JsValue::from_str(&format!("{}", e)))?;
+        Ok(Self {
+            handle: crate::ffi::Handle::from_value(value),
+        })
+    }
+
+    /// JSON of the subtree at `path` (e.g. `servers[0].port`), or `undefined`
+    pub fn query(&self, path: &str) -> Option<String> {
+        crate::ffi::query(&self.handle, path)
+    }
 }
 
 /// Validate a Nickel configuration without evaluating it
@@ -100,6 +138,19 @@ pub fn version() -> String {
     crate::VERSION.to_string()
 }
 
+/// Get embedded Nickel version information as a JSON string
+///
+/// # Examples
+///
+/// ```javascript
+/// const info = JSON.parse(version_info());
+/// console.log(info.nickel_language); // e.g. "1.8.0"
+/// ```
+#[wasm_bindgen]
+pub fn version_info() -> String {
+    crate::ffi::version_info()
+}
+
 /// Get RSR compliance tier
 #[wasm_bindgen]
 pub fn rsr_tier() -> String {
@@ -137,12 +188,24 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_wasm_config_query() {
+        let config = NickelConfig::new("{ a = { b = [1, 2] } }", "test.ncl").unwrap();
+        assert_eq!(config.query("a.b[1]").as_deref(), Some("2"));
+        assert_eq!(config.query("a.c"), None);
+    }
+
     #[test]
     fn test_wasm_version() {
         let v = version();
         assert!(!v.is_empty());
     }
 
+    #[test]
+    fn test_wasm_version_info() {
+        assert!(version_info().contains("nickel_language"));
+    }
+
     #[test]
     fn test_wasm_rsr_metadata() {
         assert_eq!(rsr_tier(), "bronze");
@@ -0,0 +1,328 @@
+//! Re-evaluation when sources change
+//!
+//! Backs `bunsenite parse --watch` and `bunsenite export --watch`, for local
+//! loops where Nickel feeds another tool. A [`Watch`] follows an entry file
+//! and everything it transitively imports (found as [`crate::imports`] finds
+//! them), and [`Watch::wait`] blocks until one of them changes. The import
+//! graph is walked again after every change, so a newly added import is
+//! watched from then on, and an import that does not exist yet is picked up
+//! when it is created.
+//!
+//! Directories are watched rather than files, so editors that save by
+//! writing a new file and renaming it over the old one are seen. Changes
+//! arriving in quick succession (a save touching several files, a
+//! `git checkout`) are reported together.
+//!
+//! Requires the `watch` feature; without it, [`Watch::new`] fails.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use bunsenite::watch::Watch;
+//! use bunsenite::NickelLoader;
+//!
+//! let loader = NickelLoader::new();
+//! let mut watch = Watch::new("config.ncl".as_ref(), 1).unwrap();
+//! loop {
+//!     match loader.parse_file("config.ncl") {
+//!         Ok(value) => println!("{}", value),
+//!         Err(e) => eprintln!("{}", e),
+//!     }
+//!     watch.wait().unwrap();
+//! }
+//! ```
+
+use crate::error::{Error, Result};
+use crate::imports;
+use crate::interrupt::Shutdown;
+use crate::paths::{self, CasePolicy};
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How long to wait for related changes before reporting
+pub const DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// How often [`Watch::wait`] looks for a shutdown request
+const SHUTDOWN_POLL: Duration = Duration::from_millis(250);
+
+/// An entry file and its imports, watched for changes
+pub struct Watch {
+    root: PathBuf,
+    threads: usize,
+    files: Vec<PathBuf>,
+    keys: HashSet<String>,
+    dirs: BTreeSet<PathBuf>,
+    #[cfg(feature = "watch")]
+    watcher: notify::RecommendedWatcher,
+    #[cfg(feature = "watch")]
+    events: std::sync::mpsc::Receiver<notify::Result<notify::Event>>,
+}
+
+impl fmt::Debug for Watch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Watch")
+            .field("root", &self.root)
+            .field("files", &self.files)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Watch {
+    /// Start watching `root` and its imports
+    ///
+    /// Imports are walked on up to `threads` threads.
+    ///
+    /// # Errors
+    ///
+    /// Returns an I/O error if a directory cannot be watched, or an
+    /// invalid-input error without the `watch` feature
+    pub fn new(root: &Path, threads: usize) -> Result<Self> {
+        let root = if root.is_absolute() {
+            root.to_path_buf()
+        } else {
+            std::env::current_dir()?.join(root)
+        };
+        let mut watch = Self::start(root, threads)?;
+        watch.rescan()?;
+        Ok(watch)
+    }
+
+    #[cfg(feature = "watch")]
+    fn start(root: PathBuf, threads: usize) -> Result<Self> {
+        let (sender, events) = std::sync::mpsc::channel();
+        let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            // The receiver only goes away with the watch
+            let _ = sender.send(event);
+        })
+        .map_err(watch_error)?;
+        Ok(Self {
+            root,
+            threads,
+            files: Vec::new(),
+            keys: HashSet::new(),
+            dirs: BTreeSet::new(),
+            watcher,
+            events,
+        })
+    }
+
+    #[cfg(not(feature = "watch"))]
+    fn start(_root: PathBuf, _threads: usize) -> Result<Self> {
+        Err(unsupported())
+    }
+
+    /// The files being watched: the entry file, then its imports in
+    /// breadth-first order, including imports that do not exist yet
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// Block until a watched file changes, returning the changed files
+    ///
+    /// # Errors
+    ///
+    /// Returns an I/O error if the watcher fails, or
+    /// [`Error::Interrupted`] once [`Shutdown::global`] is requested
+    pub fn wait(&mut self) -> Result<Vec<PathBuf>> {
+        let shutdown = Shutdown::global();
+        loop {
+            shutdown.check()?;
+            if let Some(changed) = self.wait_timeout(SHUTDOWN_POLL)? {
+                return Ok(changed);
+            }
+        }
+    }
+
+    /// As [`wait`](Self::wait), giving up with `None` after `timeout`
+    ///
+    /// # Errors
+    ///
+    /// Returns an I/O error if the watcher fails
+    #[cfg(feature = "watch")]
+    pub fn wait_timeout(&mut self, timeout: Duration) -> Result<Option<Vec<PathBuf>>> {
+        use std::sync::mpsc::RecvTimeoutError;
+
+        let deadline = std::time::Instant::now() + timeout;
+        let mut changed = BTreeSet::new();
+        loop {
+            // Once something changed, only wait out the debounce
+            let wait = if changed.is_empty() {
+                deadline.saturating_duration_since(std::time::Instant::now())
+            } else {
+                DEBOUNCE
+            };
+            let event = match self.events.recv_timeout(wait) {
+                Ok(event) => event.map_err(watch_error)?,
+                Err(RecvTimeoutError::Timeout) if changed.is_empty() => return Ok(None),
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(Error::internal("File watcher stopped"))
+                }
+            };
+            if event.kind.is_access() {
+                continue;
+            }
+            changed.extend(
+                event
+                    .paths
+                    .into_iter()
+                    .filter(|path| self.keys.contains(&key(path))),
+            );
+        }
+
+        self.rescan()?;
+        Ok(Some(changed.into_iter().collect()))
+    }
+
+    /// As [`wait`](Self::wait), giving up with `None` after `timeout`
+    ///
+    /// # Errors
+    ///
+    /// Returns an invalid-input error: this build cannot watch files
+    #[cfg(not(feature = "watch"))]
+    pub fn wait_timeout(&mut self, _timeout: Duration) -> Result<Option<Vec<PathBuf>>> {
+        Err(unsupported())
+    }
+
+    /// Walk the import graph again and watch any new directories
+    fn rescan(&mut self) -> Result<()> {
+        let graph = imports::prefetch(&self.root, self.threads);
+        let mut files = graph.files;
+        files.extend(graph.missing);
+        if files.is_empty() {
+            files.push(self.root.clone());
+        }
+
+        let dirs: BTreeSet<PathBuf> = files
+            .iter()
+            .filter_map(|file| file.parent())
+            .filter(|dir| dir.is_dir())
+            .map(Path::to_path_buf)
+            .collect();
+        let added: Vec<PathBuf> = dirs.difference(&self.dirs).cloned().collect();
+        let removed: Vec<PathBuf> = self.dirs.difference(&dirs).cloned().collect();
+        for dir in &added {
+            self.watch_dir(dir)?;
+        }
+        for dir in &removed {
+            self.unwatch_dir(dir);
+        }
+
+        self.keys = files.iter().map(|file| key(file)).collect();
+        self.files = files;
+        self.dirs = dirs;
+        Ok(())
+    }
+
+    #[cfg(feature = "watch")]
+    fn watch_dir(&mut self, dir: &Path) -> Result<()> {
+        use notify::Watcher as _;
+        self.watcher
+            .watch(dir, notify::RecursiveMode::NonRecursive)
+            .map_err(watch_error)
+    }
+
+    #[cfg(not(feature = "watch"))]
+    fn watch_dir(&mut self, _dir: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    #[cfg(feature = "watch")]
+    fn unwatch_dir(&mut self, dir: &Path) {
+        use notify::Watcher as _;
+        // The directory may be gone already
+        let _ = self.watcher.unwatch(dir);
+    }
+
+    #[cfg(not(feature = "watch"))]
+    fn unwatch_dir(&mut self, _dir: &Path) {}
+}
+
+/// Compare paths by their real directory, as watchers report them
+fn key(path: &Path) -> String {
+    let real = match (path.parent(), path.file_name()) {
+        (Some(dir), Some(name)) => dir
+            .canonicalize()
+            .map_or_else(|_| path.to_path_buf(), |dir| dir.join(name)),
+        _ => path.to_path_buf(),
+    };
+    paths::key(&real, CasePolicy::Platform)
+}
+
+#[cfg(not(feature = "watch"))]
+fn unsupported() -> Error {
+    Error::invalid_input(
+        "Cannot watch files: bunsenite was built without watch support (the `watch` feature)",
+    )
+}
+
+#[cfg(feature = "watch")]
+fn watch_error(e: notify::Error) -> Error {
+    match e.kind {
+        notify::ErrorKind::Io(io) => Error::from(io),
+        _ => Error::internal(format!("File watcher failed: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "watch")]
+    #[test]
+    fn test_changes_to_imports_are_seen() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = dir.path().join("main.ncl");
+        let lib = dir.path().join("lib").join("base.ncl");
+        std::fs::create_dir(lib.parent().unwrap()).unwrap();
+        std::fs::write(&main, r#"(import "lib/base.ncl") & { b = 2 }"#).unwrap();
+        std::fs::write(&lib, "{ a = 1 }").unwrap();
+
+        let mut watch = Watch::new(&main, 1).unwrap();
+        assert_eq!(watch.files().len(), 2);
+
+        std::fs::write(dir.path().join("unrelated.ncl"), "{}").unwrap();
+        assert_eq!(
+            watch.wait_timeout(Duration::from_millis(300)).unwrap(),
+            None
+        );
+
+        std::fs::write(&lib, "{ a = 3 }").unwrap();
+        let changed = watch.wait_timeout(Duration::from_secs(5)).unwrap().unwrap();
+        assert_eq!(changed.len(), 1);
+        assert!(changed[0].ends_with("lib/base.ncl"));
+    }
+
+    #[cfg(feature = "watch")]
+    #[test]
+    fn test_new_imports_are_watched() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = dir.path().join("main.ncl");
+        let extra = dir.path().join("extra.ncl");
+        std::fs::write(&main, "{ a = 1 }").unwrap();
+
+        let mut watch = Watch::new(&main, 1).unwrap();
+        assert_eq!(watch.files().len(), 1);
+
+        std::fs::write(&main, r#"{ a = 1 } & (import "extra.ncl")"#).unwrap();
+        assert!(watch
+            .wait_timeout(Duration::from_secs(5))
+            .unwrap()
+            .is_some());
+        assert_eq!(watch.files().len(), 2);
+
+        // The import did not exist, and its creation counts as a change
+        std::fs::write(&extra, "{ b = 2 }").unwrap();
+        let changed = watch.wait_timeout(Duration::from_secs(5)).unwrap().unwrap();
+        assert!(changed.iter().any(|path| path.ends_with("extra.ncl")));
+    }
+
+    #[cfg(not(feature = "watch"))]
+    #[test]
+    fn test_requires_feature() {
+        let error = Watch::new(Path::new("config.ncl"), 1).unwrap_err();
+        assert!(error.to_string().contains("`watch` feature"));
+    }
+}
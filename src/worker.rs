@@ -0,0 +1,675 @@
+//! Bazel persistent workers
+//!
+//! Backs `bunsenite worker`: Bazel, and Buck2 rules that speak its worker
+//! protocol, can keep one bunsenite process running and send it every
+//! action of a build, rather than starting a process per target. Bazel
+//! starts the worker with [`PERSISTENT_WORKER_FLAG`] and writes
+//! [`WorkRequest`]s to its stdin; [`Worker::serve`] answers each with a
+//! [`WorkResponse`] on stdout. Both are Protobuf messages
+//! (`worker_protocol.proto`), each preceded by its length as a varint, as
+//! `writeDelimitedTo` writes them.
+//!
+//! A request's arguments are the action's, as its rule's flagfile lists
+//! them:
+//!
+//! ```text
+//! export app.ncl --output app.yaml [--format yaml,json]
+//! validate app.ncl [more.ncl ..]
+//! ```
+//!
+//! `export` writes each format as `bunsenite export -o` does, taking the
+//! format from the output's extension if none is given. The response's exit
+//! code is 0 on success, 1 if a config fails and 2 for arguments the worker
+//! does not understand; its output holds the error, for Bazel to show.
+//!
+//! Requests are answered one at a time, in order. Relative paths resolve
+//! against the request's sandbox directory if it has one (multiplex
+//! sandboxing), else the working directory. A cancel request is ignored:
+//! the request it names has already been answered. Input digests are not
+//! used; each request evaluates its configs afresh.
+//!
+//! When workers are off, Bazel runs the same command line once, with the
+//! flagfile as an `@FILE` argument; [`expand_flagfiles`] reads it.
+//!
+//! # Examples
+//!
+//! ```
+//! use bunsenite::worker::{self, WorkRequest, WorkResponse, Worker};
+//! use bunsenite::NickelLoader;
+//!
+//! let dir = tempfile::tempdir().unwrap();
+//! std::fs::write(dir.path().join("app.ncl"), "{ port = 80 }").unwrap();
+//! let request = WorkRequest {
+//!     arguments: vec!["validate".into(), "app.ncl".into()],
+//!     request_id: 1,
+//!     sandbox_dir: Some(dir.path().display().to_string()),
+//!     ..WorkRequest::default()
+//! };
+//!
+//! let mut input = Vec::new();
+//! worker::write_delimited(&mut input, &request.encode()).unwrap();
+//! let mut output = Vec::new();
+//! Worker::new(NickelLoader::new()).serve(&input[..], &mut output).unwrap();
+//!
+//! let response = worker::read_delimited(&mut &output[..]).unwrap().unwrap();
+//! let response = WorkResponse::decode(&response).unwrap();
+//! assert_eq!((response.request_id, response.exit_code), (1, 0));
+//! ```
+
+use crate::diagnostic::Diagnostic;
+use crate::error::{Error, Result};
+use crate::export::{self, Format, Options};
+use crate::loader::{read_source, NickelLoader};
+use crate::output;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// The flag Bazel adds to a worker's command line
+pub const PERSISTENT_WORKER_FLAG: &str = "--persistent_worker";
+
+/// Largest request accepted, so a corrupt length cannot exhaust memory
+const MAX_MESSAGE: u64 = 64 * 1024 * 1024;
+
+/// Exit code of a request with arguments the worker does not understand
+const USAGE: i32 = 2;
+
+/// A file an action reads, as Bazel lists it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Input {
+    /// Path, relative to the execution root
+    pub path: String,
+    /// Bazel's digest of the file's contents
+    pub digest: Vec<u8>,
+}
+
+/// One action, as Bazel sends it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WorkRequest {
+    /// The action's arguments
+    pub arguments: Vec<String>,
+    /// The files the action reads
+    pub inputs: Vec<Input>,
+    /// Request ID, 0 for singleplex workers
+    pub request_id: i32,
+    /// Whether this cancels the request with the same ID
+    pub cancel: bool,
+    /// How much the worker may log
+    pub verbosity: i32,
+    /// Directory relative paths resolve against, under multiplex sandboxing
+    pub sandbox_dir: Option<String>,
+}
+
+/// The outcome of one action
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WorkResponse {
+    /// 0 on success
+    pub exit_code: i32,
+    /// What to show the user, such as an error
+    pub output: String,
+    /// ID of the request answered
+    pub request_id: i32,
+    /// Whether the request was cancelled rather than done
+    pub was_cancelled: bool,
+}
+
+impl WorkRequest {
+    /// Decode a request from the Protobuf wire format
+    ///
+    /// Unknown fields are skipped.
+    ///
+    /// # Errors
+    ///
+    /// Returns an invalid-input error if the message is malformed
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let mut request = Self::default();
+        decode_fields(bytes, |field, wire| {
+            match (field, wire) {
+                (1, Wire::Bytes(bytes)) => request.arguments.push(text(bytes)?),
+                (2, Wire::Bytes(bytes)) => {
+                    let mut input = Input::default();
+                    decode_fields(bytes, |field, wire| {
+                        match (field, wire) {
+                            (1, Wire::Bytes(bytes)) => input.path = text(bytes)?,
+                            (2, Wire::Bytes(bytes)) => input.digest = bytes.to_vec(),
+                            _ => {}
+                        }
+                        Ok(())
+                    })?;
+                    request.inputs.push(input);
+                }
+                (3, Wire::Varint(n)) => request.request_id = n as i32,
+                (4, Wire::Varint(n)) => request.cancel = n != 0,
+                (5, Wire::Varint(n)) => request.verbosity = n as i32,
+                (6, Wire::Bytes(bytes)) => request.sandbox_dir = Some(text(bytes)?),
+                _ => {}
+            }
+            Ok(())
+        })?;
+        Ok(request)
+    }
+
+    /// Encode the request in the Protobuf wire format, as Bazel sends it
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for argument in &self.arguments {
+            put_bytes(&mut out, 1, argument.as_bytes());
+        }
+        for input in &self.inputs {
+            let mut encoded = Vec::new();
+            put_bytes(&mut encoded, 1, input.path.as_bytes());
+            put_bytes(&mut encoded, 2, &input.digest);
+            put_bytes(&mut out, 2, &encoded);
+        }
+        put_varint_field(&mut out, 3, i64::from(self.request_id) as u64);
+        put_varint_field(&mut out, 4, u64::from(self.cancel));
+        put_varint_field(&mut out, 5, i64::from(self.verbosity) as u64);
+        if let Some(dir) = &self.sandbox_dir {
+            put_bytes(&mut out, 6, dir.as_bytes());
+        }
+        out
+    }
+}
+
+impl WorkResponse {
+    /// Decode a response from the Protobuf wire format
+    ///
+    /// # Errors
+    ///
+    /// Returns an invalid-input error if the message is malformed
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let mut response = Self::default();
+        decode_fields(bytes, |field, wire| {
+            match (field, wire) {
+                (1, Wire::Varint(n)) => response.exit_code = n as i32,
+                (2, Wire::Bytes(bytes)) => response.output = text(bytes)?,
+                (3, Wire::Varint(n)) => response.request_id = n as i32,
+                (4, Wire::Varint(n)) => response.was_cancelled = n != 0,
+                _ => {}
+            }
+            Ok(())
+        })?;
+        Ok(response)
+    }
+
+    /// Encode the response in the Protobuf wire format
+    ///
+    /// Fields with their default value are left out, as proto3 does.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        // Negative int32 values are sign-extended to 64 bits
+        put_varint_field(&mut out, 1, i64::from(self.exit_code) as u64);
+        if !self.output.is_empty() {
+            put_bytes(&mut out, 2, self.output.as_bytes());
+        }
+        put_varint_field(&mut out, 3, i64::from(self.request_id) as u64);
+        put_varint_field(&mut out, 4, u64::from(self.was_cancelled));
+        out
+    }
+}
+
+/// Read one length-prefixed message, or `None` at the end of the input
+///
+/// # Errors
+///
+/// Returns an I/O error if reading fails, or an invalid-input error if the
+/// input ends inside a message or its length is implausible
+pub fn read_delimited(reader: &mut impl Read) -> Result<Option<Vec<u8>>> {
+    let mut len = 0u64;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0u8];
+        if reader.read(&mut byte)? == 0 {
+            if shift == 0 {
+                return Ok(None);
+            }
+            return Err(malformed());
+        }
+        len |= u64::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            if len > MAX_MESSAGE {
+                return Err(Error::invalid_input(format!(
+                    "Worker message of {} bytes is larger than the limit of {}",
+                    len, MAX_MESSAGE
+                )));
+            }
+            let mut message = vec![0; len as usize];
+            reader.read_exact(&mut message).map_err(|_| malformed())?;
+            return Ok(Some(message));
+        }
+    }
+    Err(malformed())
+}
+
+/// Write one message, preceded by its length
+///
+/// # Errors
+///
+/// Returns an I/O error if writing fails
+pub fn write_delimited(writer: &mut impl Write, message: &[u8]) -> Result<()> {
+    let mut framed = Vec::with_capacity(message.len() + 10);
+    put_varint(&mut framed, message.len() as u64);
+    framed.extend_from_slice(message);
+    writer.write_all(&framed)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Arguments with each `@FILE` replaced by the lines of FILE
+///
+/// # Errors
+///
+/// Returns an I/O error if a flagfile cannot be read
+pub fn expand_flagfiles(arguments: &[String]) -> Result<Vec<String>> {
+    let mut expanded = Vec::new();
+    for argument in arguments {
+        match argument.strip_prefix('@') {
+            Some(path) => {
+                let contents = std::fs::read_to_string(path)?;
+                expanded.extend(contents.lines().map(str::to_string));
+            }
+            None => expanded.push(argument.clone()),
+        }
+    }
+    Ok(expanded)
+}
+
+/// A persistent worker, evaluating configs with one loader for every
+/// request
+#[derive(Debug)]
+pub struct Worker {
+    loader: NickelLoader,
+}
+
+impl Worker {
+    /// A worker evaluating with `loader`
+    pub fn new(loader: NickelLoader) -> Self {
+        Self { loader }
+    }
+
+    /// Answer requests from `input` on `output` until `input` ends
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a request cannot be read or decoded, or a
+    /// response cannot be written; Bazel then restarts the worker
+    pub fn serve<R: Read, W: Write>(&self, mut input: R, mut output: W) -> Result<()> {
+        while let Some(message) = read_delimited(&mut input)? {
+            let request = WorkRequest::decode(&message)?;
+            if request.cancel {
+                continue;
+            }
+            let response = self.handle(&request);
+            write_delimited(&mut output, &response.encode())?;
+        }
+        Ok(())
+    }
+
+    /// Do the action of one request
+    pub fn handle(&self, request: &WorkRequest) -> WorkResponse {
+        let dir = request.sandbox_dir.as_deref().map(Path::new);
+        let (exit_code, output) = self.run(&request.arguments, dir);
+        WorkResponse {
+            exit_code,
+            output,
+            request_id: request.request_id,
+            was_cancelled: false,
+        }
+    }
+
+    /// Do the action `arguments` ask for, resolving relative paths against
+    /// `dir`, and return the exit code and what to show the user
+    pub fn run(&self, arguments: &[String], dir: Option<&Path>) -> (i32, String) {
+        let action = match Action::parse(arguments) {
+            Ok(action) => action,
+            Err(e) => return (USAGE, format!("Error: {}\n", e)),
+        };
+        let resolve = |path: &Path| match dir {
+            Some(dir) if path.is_relative() => dir.join(path),
+            _ => path.to_path_buf(),
+        };
+        match action {
+            Action::Validate { files } => {
+                let mut failures = String::new();
+                for file in &files {
+                    let path = resolve(file);
+                    let result = read_source(&path)
+                        .and_then(|(source, name)| self.loader.validate(&source, &name));
+                    if let Err(e) = result {
+                        failures += &report(&e, &path);
+                    }
+                }
+                (i32::from(!failures.is_empty()), failures)
+            }
+            Action::Export {
+                file,
+                output,
+                formats,
+            } => {
+                let path = resolve(&file);
+                match self.export(&path, &resolve(&output), &formats) {
+                    Ok(()) => (0, String::new()),
+                    Err(e) => (1, report(&e, &path)),
+                }
+            }
+        }
+    }
+
+    fn export(&self, file: &Path, to: &Path, formats: &[Format]) -> Result<()> {
+        let rendered = export::export_file_all(&self.loader, file, formats, &Options::new())?;
+        for (contents, path) in rendered.iter().zip(export::output_paths(to, formats)) {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            output::write_atomic(&path, contents.as_bytes(), None)?;
+        }
+        Ok(())
+    }
+}
+
+/// What a request asks for
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Action {
+    Validate {
+        files: Vec<PathBuf>,
+    },
+    Export {
+        file: PathBuf,
+        output: PathBuf,
+        formats: Vec<Format>,
+    },
+}
+
+impl Action {
+    fn parse(arguments: &[String]) -> Result<Self> {
+        let Some((command, rest)) = arguments.split_first() else {
+            return Err(Error::invalid_input(
+                "Worker request has no arguments (expected export or validate)",
+            ));
+        };
+        let mut files = Vec::new();
+        let mut output = None;
+        let mut formats = None;
+        let mut rest = rest.iter();
+        while let Some(argument) = rest.next() {
+            let (flag, inline) = match argument.split_once('=') {
+                Some((flag, value)) if flag.starts_with('-') => (flag, Some(value.to_string())),
+                _ => (argument.as_str(), None),
+            };
+            let mut value = || {
+                inline
+                    .clone()
+                    .or_else(|| rest.next().cloned())
+                    .ok_or_else(|| Error::invalid_input(format!("{} needs a value", flag)))
+            };
+            match flag {
+                "-o" | "--output" if command == "export" => output = Some(PathBuf::from(value()?)),
+                "-f" | "--format" if command == "export" => {
+                    let names = value()?;
+                    let parsed: Result<Vec<Format>> =
+                        names.split(',').map(Format::from_name).collect();
+                    formats = Some(parsed?);
+                }
+                flag if flag.starts_with('-') && flag != "-" => {
+                    return Err(Error::invalid_input(format!(
+                        "Unknown worker option '{}' for {}",
+                        flag, command
+                    )))
+                }
+                _ => files.push(PathBuf::from(argument)),
+            }
+        }
+
+        match command.as_str() {
+            "validate" if !files.is_empty() => Ok(Action::Validate { files }),
+            "export" if files.len() == 1 => {
+                let output = output
+                    .ok_or_else(|| Error::invalid_input("A worker export needs --output FILE"))?;
+                let formats = match formats {
+                    Some(formats) => formats,
+                    None => {
+                        vec![Format::from_path(&output.to_string_lossy()).unwrap_or(Format::Json)]
+                    }
+                };
+                Ok(Action::Export {
+                    file: files.remove(0),
+                    output,
+                    formats,
+                })
+            }
+            "validate" | "export" => Err(Error::invalid_input(format!(
+                "Usage: {}",
+                if command == "export" {
+                    "export FILE --output FILE [--format FORMAT]"
+                } else {
+                    "validate FILE.."
+                }
+            ))),
+            other => Err(Error::invalid_input(format!(
+                "Unknown worker command '{}' (expected export or validate)",
+                other
+            ))),
+        }
+    }
+}
+
+/// An error, as the command line shows it, for a response's output
+fn report(error: &Error, file: &Path) -> String {
+    if let Ok(source) = std::fs::read_to_string(file) {
+        if error.location(&source).is_some() {
+            return Diagnostic::from_error(error).render(Some(&source), false) + "\n";
+        }
+    }
+    let mut out = format!("Error: {}\n", error);
+    if let Some(suggestion) = error.suggestion() {
+        out += &format!("\nSuggestion: {}\n", suggestion);
+    }
+    out
+}
+
+/// A field value in the Protobuf wire format
+#[derive(Debug, Clone, Copy)]
+enum Wire<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+/// Visit each field of an encoded message
+fn decode_fields<'a>(
+    mut bytes: &'a [u8],
+    mut visit: impl FnMut(u64, Wire<'a>) -> Result<()>,
+) -> Result<()> {
+    while !bytes.is_empty() {
+        let key = read_varint(&mut bytes)?;
+        let wire = match key & 7 {
+            0 => Wire::Varint(read_varint(&mut bytes)?),
+            1 => {
+                take(&mut bytes, 8)?;
+                Wire::Fixed
+            }
+            2 => {
+                let len = usize::try_from(read_varint(&mut bytes)?).map_err(|_| malformed())?;
+                Wire::Bytes(take(&mut bytes, len)?)
+            }
+            5 => {
+                take(&mut bytes, 4)?;
+                Wire::Fixed
+            }
+            _ => return Err(malformed()),
+        };
+        visit(key >> 3, wire)?;
+    }
+    Ok(())
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = bytes.split_first().ok_or_else(malformed)?;
+        *bytes = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(malformed())
+}
+
+fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if len > bytes.len() {
+        return Err(malformed());
+    }
+    let (head, rest) = bytes.split_at(len);
+    *bytes = rest;
+    Ok(head)
+}
+
+fn put_varint(out: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        out.push((n as u8 & 0x7f) | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+/// A varint field, left out if 0
+fn put_varint_field(out: &mut Vec<u8>, field: u64, value: u64) {
+    if value != 0 {
+        put_varint(out, field << 3);
+        put_varint(out, value);
+    }
+}
+
+fn put_bytes(out: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    put_varint(out, (field << 3) | 2);
+    put_varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+fn text(bytes: &[u8]) -> Result<String> {
+    String::from_utf8(bytes.to_vec())
+        .map_err(|_| Error::invalid_input("Worker message has a string that is not UTF-8"))
+}
+
+fn malformed() -> Error {
+    Error::invalid_input("Truncated or malformed worker message")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(id: i32, arguments: &[&str]) -> WorkRequest {
+        WorkRequest {
+            arguments: arguments.iter().map(|a| a.to_string()).collect(),
+            request_id: id,
+            ..WorkRequest::default()
+        }
+    }
+
+    #[test]
+    fn test_codec_round_trip() {
+        let request = WorkRequest {
+            arguments: vec!["export".into(), "app.ncl".into()],
+            inputs: vec![Input {
+                path: "app.ncl".into(),
+                digest: vec![0xab, 0xcd],
+            }],
+            request_id: 300,
+            cancel: true,
+            verbosity: 10,
+            sandbox_dir: Some("sandbox/1".into()),
+        };
+        assert_eq!(WorkRequest::decode(&request.encode()).unwrap(), request);
+
+        let response = WorkResponse {
+            exit_code: -1,
+            output: "Error: …".into(),
+            request_id: 300,
+            was_cancelled: false,
+        };
+        let encoded = response.encode();
+        assert_eq!(
+            encoded[..11],
+            [0x08, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]
+        );
+        assert_eq!(WorkResponse::decode(&encoded).unwrap(), response);
+        // Proto3 leaves defaults out
+        assert!(WorkResponse::default().encode().is_empty());
+
+        // Unknown fields are skipped, truncated messages rejected
+        let mut unknown = vec![0x3d, 1, 2, 3, 4];
+        unknown.extend(request.encode());
+        assert_eq!(WorkRequest::decode(&unknown).unwrap(), request);
+        assert!(WorkRequest::decode(&[0x0a, 5, b'a']).is_err());
+        assert!(read_delimited(&mut &[0x05, b'a'][..]).is_err());
+        assert_eq!(read_delimited(&mut &[][..]).unwrap(), None);
+    }
+
+    #[test]
+    fn test_serve() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("app.ncl"), "{ port = 80 }").unwrap();
+        std::fs::write(dir.path().join("bad.ncl"), "{ port = }").unwrap();
+
+        let mut cancel = request(2, &["validate", "app.ncl"]);
+        cancel.cancel = true;
+        let mut input = Vec::new();
+        for mut request in [
+            request(1, &["export", "app.ncl", "--output", "out/app.json"]),
+            request(2, &["validate", "app.ncl", "bad.ncl"]),
+            cancel,
+            request(3, &["build", "app.ncl"]),
+        ] {
+            request.sandbox_dir = Some(dir.path().display().to_string());
+            write_delimited(&mut input, &request.encode()).unwrap();
+        }
+        let mut output = Vec::new();
+        Worker::new(NickelLoader::new())
+            .serve(&input[..], &mut output)
+            .unwrap();
+
+        let mut output = &output[..];
+        let mut responses = Vec::new();
+        while let Some(message) = read_delimited(&mut output).unwrap() {
+            responses.push(WorkResponse::decode(&message).unwrap());
+        }
+        let codes: Vec<(i32, i32)> = responses
+            .iter()
+            .map(|r| (r.request_id, r.exit_code))
+            .collect();
+        assert_eq!(codes, [(1, 0), (2, 1), (3, USAGE)]);
+        assert!(responses[1].output.contains("bad.ncl"));
+        assert!(responses[2]
+            .output
+            .contains("Unknown worker command 'build'"));
+        let written = std::fs::read_to_string(dir.path().join("out/app.json")).unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&written).unwrap(),
+            serde_json::json!({ "port": 80 })
+        );
+    }
+
+    #[test]
+    fn test_arguments() {
+        let args =
+            |args: &[&str]| Action::parse(&args.iter().map(|a| a.to_string()).collect::<Vec<_>>());
+        assert_eq!(
+            args(&["export", "app.ncl", "-o=out", "--format", "json,toml"]).unwrap(),
+            Action::Export {
+                file: PathBuf::from("app.ncl"),
+                output: PathBuf::from("out"),
+                formats: vec![Format::Json, Format::Toml],
+            }
+        );
+        assert!(args(&["export", "app.ncl"]).is_err());
+        assert!(args(&["validate"]).is_err());
+        assert!(args(&["validate", "app.ncl", "--output", "x"]).is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        let flagfile = dir.path().join("args");
+        std::fs::write(&flagfile, "validate\napp.ncl\n").unwrap();
+        let expanded =
+            expand_flagfiles(&["--x".to_string(), format!("@{}", flagfile.display())]).unwrap();
+        assert_eq!(expanded, ["--x", "validate", "app.ncl"]);
+    }
+}